@@ -0,0 +1,84 @@
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use rand::{prelude::StdRng, SeedableRng};
+
+use progressive_mcts::klucb::klucb_bernoulli;
+use selfdriving::{choose_policy, Env, Parameters, Road};
+
+// The truth road of the example scenario after a few seconds of driving, with its belief,
+// filled in with random cars up to n_cars, and planning with mcts
+fn recorded_road(n_cars: usize) -> Road {
+    let mut params = Parameters::new().unwrap();
+    params.scenario_file = "scenarios/example.yaml".to_owned();
+    params.n_cars = n_cars;
+    let mut env = Env::new(params).unwrap();
+    for _ in 0..10 {
+        env.step(0).unwrap();
+    }
+    let mut road = env.road().clone();
+    let mut params = (*road.params).clone();
+    params.method = "mcts".to_owned();
+    road.params = params.into();
+    road
+}
+
+pub fn road_update_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("road_update");
+    for n_cars in [10, 20, 40] {
+        // a forward sim's road, as the planners step it
+        let road = recorded_road(n_cars).sample_belief(&mut StdRng::seed_from_u64(0));
+        let dt = road.params.physics_dt;
+        group.bench_function(n_cars.to_string(), |b| {
+            b.iter_batched_ref(|| road.clone(), |r| r.update(dt), BatchSize::SmallInput)
+        });
+    }
+    group.finish();
+}
+
+pub fn mcts_planning_benchmark(c: &mut Criterion) {
+    let road = recorded_road(20);
+    let mut group = c.benchmark_group("mcts_planning");
+    group.sample_size(10);
+    group.bench_function("cycle", |b| {
+        b.iter(|| {
+            let mut rng = StdRng::seed_from_u64(0);
+            choose_policy(&road.params, &road, &mut rng).unwrap()
+        })
+    });
+    group.finish();
+}
+
+pub fn belief_update_benchmark(c: &mut Criterion) {
+    let road = recorded_road(20);
+    let belief = (**road.belief.as_ref().unwrap()).clone();
+    c.bench_function("belief_update", |b| {
+        b.iter_batched_ref(
+            || belief.clone(),
+            |belief| belief.update(&road, None),
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+pub fn belief_sampling_benchmark(c: &mut Criterion) {
+    let road = recorded_road(20);
+    let mut rng = StdRng::seed_from_u64(0);
+    c.bench_function("sample_beliefs/64", |b| {
+        b.iter(|| road.sample_beliefs(&mut rng, 64))
+    });
+}
+
+pub fn klucb_benchmark(c: &mut Criterion) {
+    c.bench_function("klucb_bernoulli", |b| {
+        b.iter(|| klucb_bernoulli(black_box(0.3), black_box(0.05)))
+    });
+}
+
+criterion_group!(
+    benches,
+    road_update_benchmark,
+    mcts_planning_benchmark,
+    belief_update_benchmark,
+    belief_sampling_benchmark,
+    klucb_benchmark
+);
+criterion_main!(benches);
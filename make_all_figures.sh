@@ -12,14 +12,14 @@ cd ../
 # We originally split all the work evenly in two to run on two machines
 # by using the 0:2:16383 (all evens) and 1:2:16383 (all odds) syntax
 
-time cargo run --release rng_seed 0:2:16383 :: method mpdm :: use_cfb false :: mpdm.samples_n 2 4 8 16 32 64
-time cargo run --release rng_seed 0:2:16383 :: method eudm :: use_cfb false true :: eudm.samples_n 1 2 4 8 16 32
-time cargo run --release rng_seed 0:2:16383 :: method mcts :: use_cfb false :: mcts.bound_mode classic :: mcts.samples_n 8 16 32 64 128 256 :: mcts.repeat_const 0
-time cargo run --release rng_seed 0:2:16383 :: method mcts :: use_cfb false :: mcts.bound_mode marginal :: mcts.samples_n 8 16 32 64 128 256 :: mcts.repeat_const 0 32768
+time cargo run --release rng_seed 0:2:16383 :: preset mpdm_paper
+time cargo run --release rng_seed 0:2:16383 :: preset eudm_paper
+time cargo run --release rng_seed 0:2:16383 :: preset mcts_classic
+time cargo run --release rng_seed 0:2:16383 :: preset mcptdm
 
-time cargo run --release rng_seed 1:2:16383 :: method mpdm :: use_cfb false :: mpdm.samples_n 2 4 8 16 32 64
-time cargo run --release rng_seed 1:2:16383 :: method eudm :: use_cfb false true :: eudm.samples_n 1 2 4 8 16 32
-time cargo run --release rng_seed 1:2:16383 :: method mcts :: use_cfb false :: mcts.bound_mode classic :: mcts.samples_n 8 16 32 64 128 256 :: mcts.repeat_const 0
-time cargo run --release rng_seed 1:2:16383 :: method mcts :: use_cfb false :: mcts.bound_mode marginal :: mcts.samples_n 8 16 32 64 128 256 :: mcts.repeat_const 0 32768
+time cargo run --release rng_seed 1:2:16383 :: preset mpdm_paper
+time cargo run --release rng_seed 1:2:16383 :: preset eudm_paper
+time cargo run --release rng_seed 1:2:16383 :: preset mcts_classic
+time cargo run --release rng_seed 1:2:16383 :: preset mcptdm
 
 ./plot.py final
@@ -56,13 +56,59 @@ with open(cache_file, "r") as f:
             entry["997_ts"] = float(parts[11])
             entry["max_ts"] = float(parts[12])
             entry["stddev_ts"] = float(parts[13])
+            # older results lines end here, with only the run's seconds following
+            if len(parts) > 18:
+                entry["crash_count"] = float(parts[14])
+                entry["min_ttc"] = float(parts[15])
+                entry["policy_switches"] = float(parts[16])
+                entry["rollouts"] = float(parts[17])
+            if len(parts) > 19:
+                entry["red_light_violations"] = float(parts[18])
+            if len(parts) > 20:
+                entry["emergency_brakes"] = float(parts[19])
+            if len(parts) > 21:
+                entry["rss_violation_t"] = float(parts[20])
+            if len(parts) > 26:
+                entry["mean_ttc"] = float(parts[21])
+                entry["min_headway"] = float(parts[22])
+                entry["mean_headway"] = float(parts[23])
+                entry["min_pet"] = float(parts[24])
+                entry["mean_pet"] = float(parts[25])
+            # the comfort costs come just before the seconds, after however many reward columns,
+            # and the lane-keeping cost after them since it was added
+            if len(parts) > 38:
+                entry["cost.jerk"] = float(parts[-4])
+                entry["cost.lat_accel"] = float(parts[-3])
+                entry["cost.lane_offset"] = float(parts[-2])
+            elif len(parts) > 28:
+                entry["cost.jerk"] = float(parts[-3])
+                entry["cost.lat_accel"] = float(parts[-2])
+            if len(parts) > 29:
+                entry["impact_speed"] = float(parts[26])
+            if len(parts) > 30:
+                entry["planning_allocations"] = float(parts[27])
+            # seconds of planning by subsystem, which are 0 without profile_subsystems
+            if len(parts) > 35:
+                entry["forward_sims_time"] = float(parts[28])
+                entry["collision_checks_time"] = float(parts[29])
+                entry["policy_evaluation_time"] = float(parts[30])
+                entry["belief_sampling_time"] = float(parts[31])
+                entry["tree_bookkeeping_time"] = float(parts[32])
+            # seconds of planning per second driven
+            if len(parts) > 36:
+                entry["planning_load"] = float(parts[33])
+            # the RMS of the ego's offset from its lane's center
+            if len(parts) > 38:
+                entry["lane_offset_rms"] = float(parts[34])
 
             entry["cost.efficiency"] = float(parts[1])
             entry["cost.safety"] = float(parts[2])
             entry["cost.accel"] = float(parts[3])
             entry["cost.steer"] = float(parts[4])
             entry["cost"] = entry["cost.efficiency"] + entry["cost.safety"] + \
-                entry["cost.accel"] + entry["cost.steer"]
+                entry["cost.accel"] + entry["cost.steer"] + \
+                entry.get("cost.jerk", 0.0) + entry.get("cost.lat_accel", 0.0) + \
+                entry.get("cost.lane_offset", 0.0)
 
             results.append(entry)
         else:
@@ -1,8 +1,8 @@
 pub mod cost_set;
 pub mod klucb;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, Hash)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, Serialize, Hash)]
 #[serde(rename_all = "snake_case")]
 pub enum CostBoundMode {
     Classic,
@@ -39,7 +39,7 @@ impl std::str::FromStr for CostBoundMode {
     }
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, Hash)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, Serialize, Hash)]
 #[serde(rename_all = "lowercase")]
 pub enum ChildSelectionMode {
     UCB,
@@ -0,0 +1,269 @@
+use std::collections::BTreeMap;
+
+use itertools::Itertools;
+use rand::{prelude::StdRng, Rng, SeedableRng};
+
+use crate::{
+    arg_parameters::{parse_name_value_pairs, parse_scenario_filters},
+    parameters_sql::column_types,
+};
+
+// Per-seed values of the metric for the results matching the filters.
+// Identical reruns of a scenario are deduplicated by specifiers_hash,
+// but two different configurations on the same seed mean the filters are too loose.
+fn seed_values(
+    conn: &rusqlite::Connection,
+    filters: &[(String, Vec<String>)],
+    metric: &str,
+) -> BTreeMap<i64, f64> {
+    let mut sql = format!("SELECT rng_seed, specifiers_hash, {} FROM results", metric);
+    let mut filter_values = Vec::new();
+    for (i, (key, values)) in filters.iter().enumerate() {
+        sql.push_str(if i == 0 { " WHERE " } else { " AND " });
+        sql.push_str(&format!(
+            "{} IN ({})",
+            key,
+            values.iter().map(|_| "?").join(", ")
+        ));
+        filter_values.extend(values.iter().cloned());
+    }
+
+    let mut statement = conn.prepare(&sql).expect("prepare select");
+    let rows = statement
+        .query_map(rusqlite::params_from_iter(filter_values.iter()), |r| {
+            Ok((
+                r.get::<_, i64>(0)?,
+                r.get::<_, i64>(1)?,
+                r.get::<_, Option<f64>>(2)?,
+            ))
+        })
+        .expect("select")
+        .filter_map(|a| a.ok());
+
+    let mut values: BTreeMap<i64, (i64, f64)> = BTreeMap::new();
+    for (seed, hash, value) in rows {
+        let value = match value {
+            Some(value) => value,
+            None => continue,
+        };
+        if let Some((other_hash, _)) = values.insert(seed, (hash, value)) {
+            if other_hash != hash {
+                panic!(
+                    "Several configurations match {:?} for rng_seed {}; add filters to pick one",
+                    filters, seed
+                );
+            }
+        }
+    }
+    values
+        .into_iter()
+        .map(|(seed, (_, value))| (seed, value))
+        .collect()
+}
+
+fn mean(vals: &[f64]) -> f64 {
+    vals.iter().sum::<f64>() / vals.len() as f64
+}
+
+// Percentile bootstrap confidence interval for the mean
+fn bootstrap_mean_ci(
+    vals: &[f64],
+    n_resamples: usize,
+    confidence: f64,
+    rng: &mut StdRng,
+) -> (f64, f64) {
+    let mut means = (0..n_resamples)
+        .map(|_| {
+            (0..vals.len())
+                .map(|_| vals[rng.gen_range(0..vals.len())])
+                .sum::<f64>()
+                / vals.len() as f64
+        })
+        .collect_vec();
+    means.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let alpha = (1.0 - confidence) / 2.0;
+    let index = |q: f64| ((q * n_resamples as f64) as usize).min(n_resamples - 1);
+    (means[index(alpha)], means[index(1.0 - alpha)])
+}
+
+// Complementary error function, Numerical Recipes' erfc approximation (fractional error < 1.2e-7)
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let r = t
+        * (-z * z - 1.26551223
+            + t * (1.00002368
+                + t * (0.37409196
+                    + t * (0.09678418
+                        + t * (-0.18628806
+                            + t * (0.27886807
+                                + t * (-1.13520398
+                                    + t * (1.48851587 + t * (-0.82215223 + t * 0.17087277)))))))))
+            .exp();
+    if x >= 0.0 {
+        r
+    } else {
+        2.0 - r
+    }
+}
+
+struct Wilcoxon {
+    n: usize,
+    w_plus: f64,
+    z: f64,
+    p: f64,
+}
+
+// Two-sided Wilcoxon signed-rank test on paired differences,
+// with zeros dropped, average ranks for ties,
+// and the normal approximation (with tie and continuity corrections) for the p-value.
+fn wilcoxon_signed_rank(diffs: &[f64]) -> Wilcoxon {
+    let mut nonzero = diffs.iter().copied().filter(|d| *d != 0.0).collect_vec();
+    nonzero.sort_by(|a, b| a.abs().partial_cmp(&b.abs()).unwrap());
+    let n = nonzero.len();
+
+    let mut w_plus = 0.0;
+    let mut tie_correction = 0.0;
+    let mut i = 0;
+    while i < n {
+        let mut j = i;
+        while j + 1 < n && nonzero[j + 1].abs() == nonzero[i].abs() {
+            j += 1;
+        }
+        // ranks i+1..=j+1 share their average
+        let rank = (i + j + 2) as f64 / 2.0;
+        w_plus += rank * nonzero[i..=j].iter().filter(|d| **d > 0.0).count() as f64;
+        let t = (j - i + 1) as f64;
+        tie_correction += t * t * t - t;
+        i = j + 1;
+    }
+
+    if n == 0 {
+        return Wilcoxon {
+            n,
+            w_plus,
+            z: 0.0,
+            p: 1.0,
+        };
+    }
+
+    let n_f = n as f64;
+    let expected = n_f * (n_f + 1.0) / 4.0;
+    let variance = n_f * (n_f + 1.0) * (2.0 * n_f + 1.0) / 24.0 - tie_correction / 48.0;
+    let deviation = (w_plus - expected).abs() - 0.5;
+    let z = if variance > 0.0 {
+        deviation.max(0.0) / variance.sqrt() * (w_plus - expected).signum()
+    } else {
+        0.0
+    };
+    let p = erfc(z.abs() / std::f64::consts::SQRT_2).min(1.0);
+    Wilcoxon { n, w_plus, z, p }
+}
+
+// analyze :: a key=value* :: b key=value* :: [metric regret ::] [db_path results.db ::]
+//         [bootstrap 10000 ::] [seed 0 ::]
+pub fn run_analyze(args: &[String]) {
+    let mut a_filters = None;
+    let mut b_filters = None;
+    let mut metric = "regret".to_owned();
+    let mut db_path = "results.db".to_owned();
+    let mut n_resamples = 10000;
+    let mut seed = 0;
+    for (name, vals) in parse_name_value_pairs(args.iter().cloned()) {
+        match name.as_str() {
+            "a" => a_filters = Some(parse_scenario_filters(&vals)),
+            "b" => b_filters = Some(parse_scenario_filters(&vals)),
+            "metric" => metric = vals[0].clone(),
+            "db_path" => db_path = vals[0].clone(),
+            "bootstrap" => n_resamples = vals[0].parse().unwrap(),
+            "seed" => seed = vals[0].parse().unwrap(),
+            _ => panic!(
+                "{} is not a valid analyze option (a, b, metric, db_path, bootstrap, seed)",
+                name
+            ),
+        }
+    }
+    let a_filters = a_filters.expect("analyze needs the first configuration: a key=value ::");
+    let b_filters = b_filters.expect("analyze needs the second configuration: b key=value ::");
+    if !column_types().iter().any(|(name, _)| *name == metric) {
+        panic!("{} is not a column of the results table!", metric);
+    }
+
+    let conn = rusqlite::Connection::open(&db_path).unwrap();
+    let a_values = seed_values(&conn, &a_filters, &metric);
+    let b_values = seed_values(&conn, &b_filters, &metric);
+
+    let paired = a_values
+        .iter()
+        .filter_map(|(seed, a)| b_values.get(seed).map(|b| (*a, *b)))
+        .collect_vec();
+    let describe = |filters: &[(String, Vec<String>)]| {
+        filters
+            .iter()
+            .map(|(key, values)| format!("{}={}", key, values.join("|")))
+            .join(" ")
+    };
+    println!("a: {}", describe(&a_filters));
+    println!("b: {}", describe(&b_filters));
+    println!(
+        "{} paired seeds ({} only in a, {} only in b)",
+        paired.len(),
+        a_values.len() - paired.len(),
+        b_values.len() - paired.len()
+    );
+    if paired.is_empty() {
+        return;
+    }
+
+    let a_paired = paired.iter().map(|(a, _)| *a).collect_vec();
+    let b_paired = paired.iter().map(|(_, b)| *b).collect_vec();
+    let diffs = paired.iter().map(|(a, b)| a - b).collect_vec();
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    let (ci_low, ci_high) = bootstrap_mean_ci(&diffs, n_resamples, 0.95, &mut rng);
+    let wilcoxon = wilcoxon_signed_rank(&diffs);
+
+    println!(
+        "mean {}: a {:.4}, b {:.4}",
+        metric,
+        mean(&a_paired),
+        mean(&b_paired)
+    );
+    println!(
+        "mean difference (a - b): {:.4}, 95% bootstrap CI [{:.4}, {:.4}]",
+        mean(&diffs),
+        ci_low,
+        ci_high
+    );
+    println!(
+        "Wilcoxon signed-rank: n = {} nonzero, W+ = {}, z = {:.3}, p = {:.4e}",
+        wilcoxon.n, wilcoxon.w_plus, wilcoxon.z, wilcoxon.p
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+
+    #[test]
+    fn test_wilcoxon_signed_rank() {
+        // ranks 1..=5 with ties: |d| = 1, 2, 2, 3, 4 -> ranks 1, 2.5, 2.5, 4, 5
+        let diffs = [1.0, -2.0, 2.0, 3.0, 4.0, 0.0];
+        let w = wilcoxon_signed_rank(&diffs);
+        assert_eq!(w.n, 5);
+        assert_abs_diff_eq!(w.w_plus, 1.0 + 2.5 + 4.0 + 5.0);
+
+        // clearly shifted differences are significant, symmetric ones are not
+        let shifted = (1..=30).map(|i| i as f64).collect_vec();
+        assert!(wilcoxon_signed_rank(&shifted).p < 1e-5);
+        let symmetric = (1..=30)
+            .map(|i| if i % 2 == 0 { i as f64 } else { -i as f64 })
+            .collect_vec();
+        assert!(wilcoxon_signed_rank(&symmetric).p > 0.5);
+
+        assert_abs_diff_eq!(erfc(0.0), 1.0, epsilon = 1e-7);
+        assert_abs_diff_eq!(erfc(1.0), 0.157299207, epsilon = 1e-7);
+        assert_abs_diff_eq!(erfc(-1.0), 1.842700793, epsilon = 1e-7);
+    }
+}
@@ -1,23 +1,32 @@
 use std::{
     collections::BTreeSet,
     sync::{
-        atomic::{AtomicBool, AtomicUsize, Ordering},
+        atomic::{AtomicBool, Ordering},
         mpsc::{sync_channel, RecvTimeoutError},
-        Arc, Mutex,
+        Arc,
     },
-    time::Duration,
+    time::{Duration, Instant},
 };
 
+use crate::checkpoint::{install_interrupt_handler, Checkpoint, DEFAULT_CHECKPOINT_PATH};
+use crate::dashboard::Dashboard;
+use crate::optimizer::run_optimization;
 use crate::parameters_sql::{
-    create_table_sql, insert_sql, make_insert_specifiers, parse_parameters, specifier_params,
-    specifiers_hash,
+    make_select_specifiers, parse_parameters, specifiers_hash, try_parse_parameters,
 };
+use crate::progress::Progress;
+use crate::results_store::open_results_store;
+use crate::scheduling::{estimate_durations, run_longest_first};
+use crate::sweep_config::SweepConfig;
 #[allow(unused)]
 use fstrings::{format_args_f, format_f, println_f};
 use itertools::Itertools;
-use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use rand::{
+    prelude::{SliceRandom, StdRng},
+    Rng, SeedableRng,
+};
 
-use crate::{run_with_parameters, ChildSelectionMode, CostBoundMode};
+use crate::{run_with_parameters, ChildSelectionMode, CostBoundMode, RunResults};
 
 #[derive(Clone, Debug)]
 pub struct Parameters {
@@ -35,8 +44,12 @@ pub struct Parameters {
     pub selection_mode: ChildSelectionMode,
     pub repeat_const: f64,
     pub most_visited_best_cost_consistency: bool,
+    pub early_stop_z: f64,
 
     pub thread_limit: usize,
+    pub db_path: String,
+    // raw root child cost samples are written here when not empty
+    pub samples_dir: String,
     pub specifiers_hash: Option<i64>,
 
     pub print_report: bool,
@@ -45,7 +58,7 @@ pub struct Parameters {
 }
 
 impl Parameters {
-    fn new() -> Self {
+    pub fn new() -> Self {
         Self {
             search_depth: 4,
             n_actions: 5,
@@ -60,8 +73,11 @@ impl Parameters {
             selection_mode: ChildSelectionMode::KLUCB,
             repeat_const: -1.0,
             most_visited_best_cost_consistency: true,
+            early_stop_z: 0.0, // disabled when <= 0
 
             thread_limit: 0,
+            db_path: "results.db".to_owned(),
+            samples_dir: "".to_owned(),
             specifiers_hash: None,
 
             print_report: false,
@@ -71,7 +87,235 @@ impl Parameters {
     }
 }
 
-fn create_scenarios(
+// Every parameter that can be given as "name value ::", for the help text
+const PARAMETER_DOCS: &[(&str, &str)] = &[
+    ("rng_seed", "seed of the problem scenario and its samples"),
+    ("search_depth", "depth of the problem's action tree"),
+    ("n_actions", "actions to choose between at each node"),
+    ("samples_n", "trials to run before choosing"),
+    (
+        "most_visited_best_cost_consistency",
+        "run up to 20% more trials while the most visited and lowest cost actions differ",
+    ),
+    (
+        "bound_mode",
+        "how a node's expected cost is estimated: classic, expectimax, lower_bound, marginal",
+    ),
+    (
+        "final_choice_mode",
+        "bound_mode for the final choice, or same to use bound_mode",
+    ),
+    (
+        "selection_mode",
+        "child selection: ucb, ucbv, ucbd, klucb, klucb+, uniform",
+    ),
+    ("ucb_const", "exploration constant of every selection_mode but uniform"),
+    ("ucbv_const", "variance term constant of ucbv"),
+    ("ucbd_const", "constant of ucbd"),
+    ("klucb_max_cost", "cost scale of klucb and klucb+"),
+    (
+        "repeat_const",
+        "particles replayed in other branches, per sample (disabled when <= 0)",
+    ),
+    (
+        "early_stop_z",
+        "stop once the best action's bounds at this z separate it from the others (disabled when <= 0)",
+    ),
+    ("thread_limit", "worker threads, or 0 for one per core"),
+    (
+        "db_path",
+        "SQLite file or postgres:// connection string for the results",
+    ),
+    (
+        "samples_dir",
+        "when set, write each scenario's raw root child costs here",
+    ),
+    ("print_report", "print the search tree after a single run"),
+    (
+        "stats_analysis",
+        "print the sample counts and a profile of each result: seconds of tree bookkeeping and of the steps at each depth",
+    ),
+];
+
+// "name (default): description" for every parameter
+pub fn parameters_help() -> String {
+    let p = Parameters::new();
+    let specifiers = make_select_specifiers(&p);
+    PARAMETER_DOCS
+        .iter()
+        .map(|(name, doc)| {
+            let default = match *name {
+                "thread_limit" => p.thread_limit.to_string(),
+                "db_path" => p.db_path.clone(),
+                "samples_dir" => p.samples_dir.clone(),
+                "print_report" => p.print_report.to_string(),
+                "stats_analysis" => p.stats_analysis.to_string(),
+                _ => specifiers
+                    .iter()
+                    .find(|(spec, _)| &spec[1..] == *name)
+                    .unwrap()
+                    .1
+                    .clone(),
+            };
+            format!("  {} ({}): {}", name, default, doc)
+        })
+        .join("\n")
+}
+
+// rounds away floating point noise like 0.30000000000000004 before printing
+fn clean_float_string(v: f64) -> String {
+    format!("{:.9e}", v).parse::<f64>().unwrap().to_string()
+}
+
+// Expands the range syntaxes into their individual values:
+// integer ranges "2-8", float ranges with a step "0.1:0.9:0.1",
+// and logarithmic ranges "1e-3..1e3 log 7" (7 values, evenly spaced in log-space).
+// Anything else is passed through unchanged.
+pub fn expand_value(value: &str) -> Vec<String> {
+    // Do we have a numeric range? special-case handle that!
+    let range_parts = value.split('-').collect_vec();
+    if range_parts.len() == 2 {
+        let low: Option<usize> = range_parts[0].parse().ok();
+        let high: Option<usize> = range_parts[1].parse().ok();
+        if let (Some(low), Some(high)) = (low, high) {
+            if low < high {
+                return (low..=high).map(|v| v.to_string()).collect();
+            }
+        }
+    }
+
+    // or a float range with a step size?
+    let step_range_parts = value.split(':').collect_vec();
+    if step_range_parts.len() == 3 {
+        let low: Option<f64> = step_range_parts[0].parse().ok();
+        let high: Option<f64> = step_range_parts[1].parse().ok();
+        let step: Option<f64> = step_range_parts[2].parse().ok();
+        if let (Some(low), Some(high), Some(step)) = (low, high, step) {
+            if low < high && step > 0.0 {
+                let n_steps = ((high - low) / step + 1e-9).floor() as usize;
+                return (0..=n_steps)
+                    .map(|i| clean_float_string(low + i as f64 * step))
+                    .collect();
+            }
+        }
+    }
+
+    // or a logarithmic range?
+    let log_parts = value.split_ascii_whitespace().collect_vec();
+    if log_parts.len() == 3 && log_parts[1] == "log" {
+        let bounds = log_parts[0].split("..").collect_vec();
+        let n: Option<usize> = log_parts[2].parse().ok();
+        if let (2, Some(n)) = (bounds.len(), n) {
+            let low: Option<f64> = bounds[0].parse().ok();
+            let high: Option<f64> = bounds[1].parse().ok();
+            if let (Some(low), Some(high)) = (low, high) {
+                // both bounds negative is fine too (like ucb_const), we just space the magnitudes
+                let sign = low.signum();
+                if n >= 2 && low * high > 0.0 {
+                    let ln_low = (low * sign).ln();
+                    let ln_high = (high * sign).ln();
+                    return (0..n)
+                        .map(|i| {
+                            let ln_v = ln_low + (ln_high - ln_low) * i as f64 / (n - 1) as f64;
+                            clean_float_string(sign * ln_v.exp())
+                        })
+                        .collect();
+                }
+            }
+        }
+    }
+
+    vec![value.to_owned()]
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SamplingMode {
+    Random,
+    LatinHypercube,
+}
+
+// Instead of the full Cartesian product, draw n combinations of the declared values,
+// each returned as its own list of single-valued name/value pairs.
+// Latin hypercube sampling splits each parameter's values into n even strata
+// so that every value is represented as evenly as n allows.
+fn sample_name_value_pairs(
+    name_value_pairs: &[(String, Vec<String>)],
+    n: usize,
+    mode: SamplingMode,
+    rng: &mut StdRng,
+) -> Vec<Vec<(String, Vec<String>)>> {
+    let expanded = name_value_pairs
+        .iter()
+        .map(|(name, values)| {
+            let values = values.iter().flat_map(|v| expand_value(v)).collect_vec();
+            assert!(!values.is_empty(), "Parameter {} has no values", name);
+            (name, values)
+        })
+        .collect_vec();
+
+    let value_indices = expanded
+        .iter()
+        .map(|(_, values)| match mode {
+            SamplingMode::Random => (0..n).map(|_| rng.gen_range(0..values.len())).collect_vec(),
+            SamplingMode::LatinHypercube => {
+                let mut strata = (0..n).collect_vec();
+                strata.shuffle(rng);
+                strata
+                    .into_iter()
+                    .map(|stratum| {
+                        let u: f64 = rng.gen_range(0.0..1.0);
+                        let index =
+                            ((stratum as f64 + u) / n as f64 * values.len() as f64) as usize;
+                        index.min(values.len() - 1)
+                    })
+                    .collect_vec()
+            }
+        })
+        .collect_vec();
+
+    (0..n)
+        .map(|sample_i| {
+            expanded
+                .iter()
+                .zip(value_indices.iter())
+                .map(|((name, values), indices)| {
+                    (name.to_string(), vec![values[indices[sample_i]].clone()])
+                })
+                .collect_vec()
+        })
+        .collect_vec()
+}
+
+// Whether parameters prefixed with "prefix." apply to the scenario,
+// or None when the prefix isn't a bound or selection mode
+fn prefix_applies(prefix: &str, p: &Parameters) -> Option<bool> {
+    Some(match prefix {
+        "classic" => p.bound_mode == CostBoundMode::Classic,
+        "expectimax" => p.bound_mode == CostBoundMode::Expectimax,
+        "lower_bound" => p.bound_mode == CostBoundMode::LowerBound,
+        "marginal" => p.bound_mode == CostBoundMode::Marginal,
+        "ucb" => p.selection_mode == ChildSelectionMode::UCB,
+        "ucbv" => p.selection_mode == ChildSelectionMode::UCBV,
+        "ucbd" => p.selection_mode == ChildSelectionMode::UCBd,
+        "klucb" => p.selection_mode == ChildSelectionMode::KLUCB,
+        "klucb+" => p.selection_mode == ChildSelectionMode::KLUCBP,
+        _ => return None,
+    })
+}
+
+// The selection modes that read each selection-specific parameter
+fn selection_modes_using(param: &str) -> Option<&'static [ChildSelectionMode]> {
+    use ChildSelectionMode::*;
+    match param {
+        "ucb_const" => Some(&[UCB, UCBV, UCBd, KLUCB, KLUCBP]),
+        "ucbv_const" => Some(&[UCBV]),
+        "ucbd_const" => Some(&[UCBd]),
+        "klucb_max_cost" => Some(&[KLUCB, KLUCBP]),
+        _ => None,
+    }
+}
+
+pub fn create_scenarios(
     base_p: &Parameters,
     name_value_pairs: &[(String, Vec<String>)],
 ) -> Vec<Parameters> {
@@ -82,42 +326,14 @@ fn create_scenarios(
     let mut scenarios = Vec::new();
     let (name, values) = &name_value_pairs[0];
 
-    if name.starts_with("classic.") && base_p.bound_mode != CostBoundMode::Classic
-        || name.starts_with("expectimax.") && base_p.bound_mode != CostBoundMode::Expectimax
-        || name.starts_with("lower_bound.") && base_p.bound_mode != CostBoundMode::LowerBound
-        || name.starts_with("marginal.") && base_p.bound_mode != CostBoundMode::Marginal
-    {
-        return create_scenarios(&base_p, &name_value_pairs[1..]);
-    }
-
-    if name.starts_with("ucb.") && base_p.selection_mode != ChildSelectionMode::UCB
-        || name.starts_with("ucbv.") && base_p.selection_mode != ChildSelectionMode::UCBV
-        || name.starts_with("ucbd.") && base_p.selection_mode != ChildSelectionMode::UCBd
-        || name.starts_with("klucb.") && base_p.selection_mode != ChildSelectionMode::KLUCB
-        || name.starts_with("klucb+.") && base_p.selection_mode != ChildSelectionMode::KLUCBP
-    {
-        return create_scenarios(&base_p, &name_value_pairs[1..]);
+    if let Some((prefix, _)) = name.split_once('.') {
+        if prefix_applies(prefix, base_p) == Some(false) {
+            return create_scenarios(base_p, &name_value_pairs[1..]);
+        }
     }
 
     for value in values.iter() {
-        let mut value_set = vec![value.to_owned()];
-
-        // Do we have a numeric range? special-case handle that!
-        let range_parts = value.split("-").collect_vec();
-        if range_parts.len() == 2 {
-            let low: Option<usize> = range_parts[0].parse().ok();
-            let high: Option<usize> = range_parts[1].parse().ok();
-            if let (Some(low), Some(high)) = (low, high) {
-                if low < high {
-                    value_set.clear();
-                    for v in low..=high {
-                        value_set.push(v.to_string());
-                    }
-                }
-            }
-        }
-
-        for val in value_set {
+        for val in expand_value(value) {
             let mut params = base_p.clone();
             parse_parameters(&mut params, name, &val);
             if name_value_pairs.len() > 1 {
@@ -131,30 +347,217 @@ fn create_scenarios(
     scenarios
 }
 
-pub fn run_parallel_scenarios() {
-    let parameters_default = Parameters::new();
+// Every problem with the given names and (expanded) values, without stopping at the first
+fn validate_name_value_pairs(name_value_pairs: &[(String, Vec<String>)]) -> Vec<String> {
+    let mut errors = Vec::new();
+    for (name, values) in name_value_pairs.iter() {
+        // the known flags have all been taken out by now
+        if name.starts_with("--") {
+            errors.push(format!("{} is not a valid flag, see sweep --help", name));
+            continue;
+        }
+        if values.is_empty() {
+            errors.push(format!("{} has no values", name));
+        }
+        if let Some((prefix, _)) = name.split_once('.') {
+            if prefix_applies(prefix, &Parameters::new()).is_none() {
+                errors.push(format!(
+                    "{} has the prefix {}, which is not a bound_mode or selection_mode",
+                    name, prefix
+                ));
+            }
+        }
+        for val in values.iter().flat_map(|v| expand_value(v)) {
+            let mut params = Parameters::new();
+            if let Err(e) = try_parse_parameters(&mut params, name, &val) {
+                errors.push(e);
+            }
+        }
+    }
+    errors
+}
+
+// Problems with the expanded scenarios of one set of name/values pairs:
+// values that are out of range and parameters that the scenario would silently ignore
+fn validate_scenarios(
+    name_value_pairs: &[(String, Vec<String>)],
+    scenarios: &[Parameters],
+) -> Vec<String> {
+    let mut errors = Vec::new();
+    for s in scenarios.iter() {
+        for (name, value) in [
+            ("samples_n", s.samples_n),
+            ("search_depth", s.search_depth as usize),
+            ("n_actions", s.n_actions as usize),
+        ] {
+            if value == 0 {
+                errors.push(format!("{} must be at least 1", name));
+            }
+        }
+        if s.bound_mode == CostBoundMode::Same {
+            errors.push(format!(
+                "bound_mode=same is only valid for final_choice_mode (final_choice_mode={} then uses bound_mode); \
+                 use classic, expectimax, lower_bound, or marginal",
+                s.final_choice_mode
+            ));
+        }
+
+        for (name, _) in name_value_pairs.iter() {
+            let (prefix, param) = match name.rsplit_once('.') {
+                Some((prefix, param)) => (Some(prefix), param),
+                None => (None, name.as_str()),
+            };
+            if prefix.and_then(|prefix| prefix_applies(prefix, s)) == Some(false) {
+                continue;
+            }
+            if let Some(modes) = selection_modes_using(param) {
+                if !modes.contains(&s.selection_mode) {
+                    errors.push(format!(
+                        "{} is not used by selection_mode={} (only by {}); \
+                         prefix it with the mode it is meant for, e.g. {}.{}",
+                        name,
+                        s.selection_mode,
+                        modes.iter().join(", "),
+                        modes[0],
+                        param
+                    ));
+                }
+            }
+        }
+    }
+
+    errors
+}
 
-    // let args = std::env::args().collect_vec();
+// Prefixed parameters that no scenario at all applies, which would otherwise be skipped silently
+fn unused_prefixed_parameters(
+    name_value_pair_sets: &[Vec<(String, Vec<String>)>],
+    scenarios: &[Parameters],
+) -> Vec<String> {
+    name_value_pair_sets
+        .iter()
+        .flatten()
+        .map(|(name, _)| name)
+        .unique()
+        .filter_map(|name| {
+            let (prefix, _) = name.split_once('.')?;
+            if scenarios
+                .iter()
+                .any(|s| prefix_applies(prefix, s) != Some(false))
+            {
+                return None;
+            }
+            Some(format!(
+                "{} only applies when bound_mode or selection_mode is {}, which no scenario has",
+                name, prefix
+            ))
+        })
+        .collect()
+}
+
+// Parses "--filter key=value ..." into key/values, where values of the same key are alternatives.
+// Values are normalized through the parameter parser, so "KLUCB+" matches "klucb+" and "0.10" matches "0.1".
+pub fn parse_scenario_filters(filters: &[String]) -> Vec<(String, Vec<String>)> {
+    let mut keyed: Vec<(String, Vec<String>)> = Vec::new();
+    for filter in filters.iter() {
+        let (key, value) = filter
+            .split_once('=')
+            .unwrap_or_else(|| panic!("--filter takes key=value pairs, not {}", filter));
+        let mut params = Parameters::new();
+        if let Err(e) = try_parse_parameters(&mut params, key, value) {
+            panic!("--filter {}: {}", filter, e);
+        }
+        let value = make_select_specifiers(&params)
+            .into_iter()
+            .find(|(name, _)| &name[1..] == key)
+            .unwrap_or_else(|| panic!("--filter {}: {} is not a scenario parameter", filter, key))
+            .1;
+        match keyed.iter_mut().find(|(k, _)| k == key) {
+            Some((_, values)) => values.push(value),
+            None => keyed.push((key.to_owned(), vec![value])),
+        }
+    }
+    keyed
+}
+
+fn scenario_matches_filters(scenario: &Parameters, filters: &[(String, Vec<String>)]) -> bool {
+    let specifiers = make_select_specifiers(scenario);
+    filters.iter().all(|(key, values)| {
+        specifiers
+            .iter()
+            .any(|(name, val)| &name[1..] == key && values.contains(val))
+    })
+}
+
+// Runs exactly one scenario, printing the search's progress along with its results
+pub fn run_single_scenario(args: &[String]) {
+    let name_value_pairs = parse_name_value_pairs(args.iter().cloned());
+    let mut errors = validate_name_value_pairs(&name_value_pairs);
+    let scenarios = if errors.is_empty() {
+        create_scenarios(&Parameters::new(), &name_value_pairs)
+    } else {
+        Vec::new()
+    };
+    errors.append(&mut validate_scenarios(&name_value_pairs, &scenarios));
+    errors.append(&mut unused_prefixed_parameters(
+        &[name_value_pairs],
+        &scenarios,
+    ));
+    if errors.is_empty() && scenarios.len() != 1 {
+        errors.push(format!(
+            "run takes a single scenario, but these parameters make {} (use sweep for more)",
+            scenarios.len()
+        ));
+    }
+    if !errors.is_empty() {
+        for error in errors.iter().unique() {
+            eprintln!("{}", error);
+        }
+        std::process::exit(1);
+    }
+
+    let mut scenario = scenarios[0].clone();
+    scenario.is_single_run = true;
+    let res = run_with_parameters(scenario);
+    println_f!("{res}");
+}
+
+// Runs a stored scenario again, found by its specifiers_hash (a column of the exports),
+// with the search tree report, and compares its regret to the stored one
+pub fn replay_scenario(specifiers_hash: i64, db_path: &str) {
+    let mut store = open_results_store(db_path);
+    let mut scenario = store.stored_parameters(specifiers_hash).unwrap_or_else(|| {
+        eprintln!(
+            "No results with specifiers_hash {} in {}",
+            specifiers_hash, db_path
+        );
+        std::process::exit(1);
+    });
+    scenario.db_path = db_path.to_owned();
+    scenario.is_single_run = true;
+    scenario.print_report = true;
+
+    let specifiers = make_select_specifiers(&scenario)
+        .into_iter()
+        .map(|(name, val)| format!("{}={}", &name[1..], val))
+        .join(" ");
+    println!("{}", specifiers);
+    let res = run_with_parameters(scenario);
+    println_f!("{res}");
+    if let Some(stored_regret) = store.cached_regret(specifiers_hash) {
+        println!(
+            "stored regret {:.3}, replayed regret {:.3}",
+            stored_regret, res.regret
+        );
+    }
+}
+
+// Groups the command line "name val val :: name val" into its name/values pairs
+pub fn parse_name_value_pairs(args: impl Iterator<Item = String>) -> Vec<(String, Vec<String>)> {
     let mut name_value_pairs = Vec::<(String, Vec<String>)>::new();
-    // let mut arg_i = 0;
     let mut name: Option<String> = None;
     let mut vals: Option<Vec<String>> = None;
-    for arg in std::env::args()
-        .skip(1)
-        .chain(std::iter::once("::".to_owned()))
-    {
-        if arg == "--help" || arg == "help" {
-            eprintln!("Usage: (<param name> [param value]* ::)*");
-            eprintln!("For example: limit 8 12 16 24 32 :: steps 1000 :: rng_seed 0 1 2 3 4");
-            eprintln!("Valid parameters and their default values:");
-            let params_str = format!("{:?}", parameters_default)
-                .replace(", file_name: None", "")
-                .replace(", ", "\n\t")
-                .replace("Parameters { ", "\t")
-                .replace(" }", "");
-            eprintln!("{}", params_str);
-            std::process::exit(0);
-        }
+    for arg in args.chain(std::iter::once("::".to_owned())) {
         if name.is_some() {
             if arg == "::" {
                 let name = name.take().unwrap();
@@ -170,23 +573,282 @@ pub fn run_parallel_scenarios() {
             vals = Some(Vec::new());
         }
     }
+    name_value_pairs
+}
+
+// Runs a sweep given as "name value* ::" arguments. Its flags are given the same way
+// (like "--quiet ::"), which is also how checkpoints store them.
+pub fn run_parallel_scenarios(args: Vec<String>) {
+    let parameters_default = Parameters::new();
+
+    let mut name_value_pairs = parse_name_value_pairs(args.iter().cloned());
+
+    // a resumed sweep gets its arguments from the checkpoint, plus any others given now,
+    // where flags given now replace the saved ones
+    let (sweep_args, resume) = match name_value_pairs
+        .iter()
+        .position(|(name, _)| name == "--resume")
+    {
+        Some(i) => {
+            let (_, vals) = name_value_pairs.remove(i);
+            let path = vals
+                .first()
+                .map_or(DEFAULT_CHECKPOINT_PATH.to_owned(), |p| p.to_owned());
+            let checkpoint = Checkpoint::load(&path);
+            for pair in parse_name_value_pairs(checkpoint.args.iter().cloned()) {
+                if name_value_pairs.iter().any(|(name, _)| *name == pair.0) {
+                    if pair.0.starts_with("--") {
+                        continue;
+                    }
+                    panic!(
+                        "Parameter {} is already set by the checkpoint {}",
+                        pair.0, path
+                    );
+                }
+                name_value_pairs.push(pair);
+            }
+            (checkpoint.args, Some((path, checkpoint.remaining)))
+        }
+        None => (args, None),
+    };
+    let checkpoint_path = name_value_pairs
+        .iter()
+        .position(|(name, _)| name == "--checkpoint")
+        .map_or(DEFAULT_CHECKPOINT_PATH.to_owned(), |i| {
+            let (_, vals) = name_value_pairs.remove(i);
+            vals.first()
+                .expect("--checkpoint takes the path to write the checkpoint to if interrupted")
+                .to_owned()
+        });
 
     // for (name, vals) in name_value_pairs.iter() {
     //     eprintln!("{}: {:?}", name, vals);
     // }
 
+    let mut sampling = None;
+    for (flag, mode) in [
+        ("--sample-random", SamplingMode::Random),
+        ("--sample-lhs", SamplingMode::LatinHypercube),
+    ] {
+        if let Some(i) = name_value_pairs.iter().position(|(name, _)| name == flag) {
+            let (_, vals) = name_value_pairs.remove(i);
+            let n: usize = vals
+                .first()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or_else(|| {
+                    panic!(
+                        "{} takes the number of samples, then an optional seed",
+                        flag
+                    )
+                });
+            let seed: u64 = vals.get(1).map_or(0, |v| v.parse().unwrap());
+            assert!(
+                sampling.is_none(),
+                "Only one sampling mode may be used at a time"
+            );
+            sampling = Some((n, mode, seed));
+        }
+    }
+
+    let optimize = name_value_pairs
+        .iter()
+        .position(|(name, _)| name == "--optimize")
+        .map(|i| {
+            let (_, vals) = name_value_pairs.remove(i);
+            let n: usize = vals
+                .first()
+                .and_then(|v| v.parse().ok())
+                .expect("--optimize takes the number of iterations, then an optional seed");
+            let seed: u64 = vals.get(1).map_or(0, |v| v.parse().unwrap());
+            (n, seed)
+        });
+
+    let quiet = name_value_pairs
+        .iter()
+        .position(|(name, _)| name == "--quiet")
+        .map(|i| name_value_pairs.remove(i))
+        .is_some();
+
+    let dry_run = name_value_pairs
+        .iter()
+        .position(|(name, _)| name == "--dry-run")
+        .map(|i| name_value_pairs.remove(i))
+        .is_some();
+
+    let filters = name_value_pairs
+        .iter()
+        .position(|(name, _)| name == "--filter")
+        .map_or(Vec::new(), |i| {
+            parse_scenario_filters(&name_value_pairs.remove(i).1)
+        });
+    let force_recompute = name_value_pairs
+        .iter()
+        .position(|(name, _)| name == "--force-recompute")
+        .map(|i| name_value_pairs.remove(i))
+        .is_some();
+
+    let long_threads = name_value_pairs
+        .iter()
+        .position(|(name, _)| name == "--long-threads")
+        .map_or(0, |i| {
+            let (_, vals) = name_value_pairs.remove(i);
+            vals.first()
+                .and_then(|v| v.parse().ok())
+                .expect("--long-threads takes the number of threads for the longest scenarios")
+        });
+
+    let dashboard_addr = name_value_pairs
+        .iter()
+        .position(|(name, _)| name == "--dashboard")
+        .map(|i| {
+            let (_, vals) = name_value_pairs.remove(i);
+            vals.first()
+                .map_or("127.0.0.1:8088".to_owned(), |addr| addr.to_owned())
+        });
+
+    let sweep_file = name_value_pairs
+        .iter()
+        .position(|(name, _)| name == "sweep_file")
+        .map(|i| name_value_pairs.remove(i));
+    let name_value_pair_sets = if let Some((_, vals)) = sweep_file {
+        assert_eq!(vals.len(), 1, "sweep_file takes exactly one file path");
+        let mut config = SweepConfig::load(&vals[0]);
+        config.apply_overrides(&name_value_pairs);
+        config.name_value_pair_sets()
+    } else {
+        vec![name_value_pairs]
+    };
+
+    let name_value_pair_sets = if let Some((n, mode, seed)) = sampling {
+        let mut rng = StdRng::seed_from_u64(seed);
+        name_value_pair_sets
+            .iter()
+            .flat_map(|pairs| sample_name_value_pairs(pairs, n, mode, &mut rng))
+            .collect_vec()
+    } else {
+        name_value_pair_sets
+    };
+
+    let errors = name_value_pair_sets
+        .iter()
+        .flat_map(|pairs| validate_name_value_pairs(pairs))
+        .unique()
+        .collect_vec();
+    if !errors.is_empty() {
+        for error in errors.iter() {
+            eprintln!("{}", error);
+        }
+        std::process::exit(1);
+    }
+
+    if let Some((n_iterations, seed)) = optimize {
+        assert!(
+            sampling.is_none(),
+            "--optimize cannot be combined with a sampling mode"
+        );
+        let db_path = name_value_pair_sets[0]
+            .iter()
+            .find(|(name, _)| name == "db_path")
+            .map_or(parameters_default.db_path.clone(), |(_, vals)| {
+                vals[0].clone()
+            });
+        let thread_limit = name_value_pair_sets[0]
+            .iter()
+            .find(|(name, _)| name == "thread_limit")
+            .map_or(parameters_default.thread_limit, |(_, vals)| {
+                vals[0].parse().unwrap()
+            });
+        if thread_limit > 0 {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(thread_limit)
+                .build_global()
+                .unwrap();
+        }
+        let mut store = open_results_store(&db_path);
+        run_optimization(
+            store.as_mut(),
+            &parameters_default,
+            &name_value_pair_sets,
+            n_iterations,
+            seed,
+        );
+        return;
+    }
+
     let base_scenario = parameters_default;
-    let scenarios = create_scenarios(&base_scenario, &name_value_pairs);
+    let mut errors = Vec::new();
+    let mut scenarios = Vec::new();
+    for pairs in name_value_pair_sets.iter() {
+        let mut set_scenarios = create_scenarios(&base_scenario, pairs);
+        errors.append(&mut validate_scenarios(pairs, &set_scenarios));
+        scenarios.append(&mut set_scenarios);
+    }
+    errors.append(&mut unused_prefixed_parameters(
+        &name_value_pair_sets,
+        &scenarios,
+    ));
+    if !errors.is_empty() {
+        for error in errors.iter().unique() {
+            eprintln!("{}", error);
+        }
+        std::process::exit(1);
+    }
+
+    let scenarios = scenarios
+        .into_iter()
+        .filter(|scenario| scenario_matches_filters(scenario, &filters))
+        .filter(|scenario| match &resume {
+            Some((_, remaining)) => remaining.contains(&specifiers_hash(scenario)),
+            None => true,
+        })
+        .collect_vec();
     // for (i, scenario) in scenarios.iter().enumerate() {
     //     eprintln!("{}: {:?}", i, scenario.file_name);
     // }
 
     let n_scenarios = scenarios.len();
-    eprintln!("Starting to run {} scenarios", n_scenarios);
+    if !dry_run {
+        eprintln!("Starting to run {} scenarios", n_scenarios);
+    }
     if n_scenarios == 0 {
         return;
     }
 
+    if dry_run {
+        const MAX_LISTED: usize = 20;
+        for scenario in scenarios.iter().take(MAX_LISTED) {
+            let specifiers = make_select_specifiers(scenario)
+                .into_iter()
+                .map(|(name, val)| format!("{}={}", &name[1..], val))
+                .join(" ");
+            println!("{}", specifiers);
+        }
+        if n_scenarios > MAX_LISTED {
+            println!("... and {} more", n_scenarios - MAX_LISTED);
+        }
+
+        let completed_result_set = open_results_store(&scenarios[0].db_path).completed_hashes();
+        let n_cached = scenarios
+            .iter()
+            .filter(|scenario| completed_result_set.contains(&specifiers_hash(scenario)))
+            .count();
+        if force_recompute {
+            println!(
+                "{} scenarios, {} already in {} would be replaced",
+                n_scenarios, n_cached, scenarios[0].db_path
+            );
+        } else {
+            println!(
+                "{} scenarios, {} already in {}, {} to run",
+                n_scenarios,
+                n_cached,
+                scenarios[0].db_path,
+                n_scenarios - n_cached
+            );
+        }
+        return;
+    }
+
     let thread_limit = scenarios[0].thread_limit;
     if thread_limit > 0 {
         rayon::ThreadPoolBuilder::new()
@@ -195,22 +857,16 @@ pub fn run_parallel_scenarios() {
             .unwrap();
     }
 
-    let n_scenarios_completed = AtomicUsize::new(0);
-
-    let cache_filename = "results.db";
-    let mut conn = rusqlite::Connection::open(cache_filename).unwrap();
-    // create if doesn't exist (lazy way, ignoring an error)
-    let _ = conn.execute(&create_table_sql(), []);
-
-    let mut specifiers_hash_statement = conn
-        .prepare("SELECT specifiers_hash FROM results;")
-        .expect("prepare select specifiers_hash");
-    let specifiers_hashs = specifiers_hash_statement
-        .query_map([], |r| r.get::<_, i64>(0))
-        .unwrap();
-    let completed_result_set: BTreeSet<i64> = specifiers_hashs.filter_map(|a| a.ok()).collect();
-    let completed_result_set = Mutex::new(completed_result_set);
-    drop(specifiers_hash_statement);
+    let mut store = open_results_store(&scenarios[0].db_path);
+    let completed_result_set = if force_recompute {
+        // drop the old results first, so the recomputed ones don't sit next to them
+        let hashes = scenarios.iter().map(specifiers_hash).collect_vec();
+        store.delete_results(&hashes);
+        BTreeSet::new()
+    } else {
+        store.completed_hashes()
+    };
+    let past_run_times = store.mean_run_times();
 
     let many_scenarios = n_scenarios > 30000;
     if n_scenarios == 1 {
@@ -219,100 +875,241 @@ pub fn run_parallel_scenarios() {
         let res = run_with_parameters(single_scenario);
         println_f!("{res}");
     } else {
-        let (tx, rx) = sync_channel(2048);
+        let (stats_analysis, db_path) = (scenarios[0].stats_analysis, scenarios[0].db_path.clone());
+        // already-completed scenarios are skipped up front so they don't distort the ETA
+        let scenarios = scenarios
+            .into_iter()
+            .map(|mut scenario| {
+                scenario.specifiers_hash = Some(specifiers_hash(&scenario));
+                scenario
+            })
+            .filter(|scenario| !completed_result_set.contains(&scenario.specifiers_hash.unwrap()))
+            .collect_vec();
+        if scenarios.len() < n_scenarios {
+            eprintln!(
+                "Skipping {} scenarios already in the database",
+                n_scenarios - scenarios.len()
+            );
+        }
+        let dashboard = dashboard_addr.map(|addr| Dashboard::start(&addr, &scenarios));
+
+        // each scenario's results, or its panic message
+        let (tx, rx) = sync_channel::<(Parameters, Result<RunResults, String>)>(2048);
         let is_done = Arc::new(AtomicBool::new(false));
 
         let is_done_job = is_done.clone();
-        let recv_thread = std::thread::spawn(move || loop {
-            let transaction = conn.transaction().expect("transaction");
-            let mut insert_statement = transaction.prepare(&insert_sql()).expect("prepare insert");
-
-            let mut received_any = false;
+        let recv_thread = std::thread::spawn(move || {
+            let mut n_failed = 0;
+            // seconds spent storing the results
+            let mut write_seconds = 0.0;
+            let mut handle_received = |received: Vec<(Parameters, Result<RunResults, String>)>| {
+                if let Some(dashboard) = &dashboard {
+                    dashboard.record(&received);
+                }
+                let completed = received
+                    .into_iter()
+                    .filter_map(|(scenario, res)| match res {
+                        Ok(res) => Some((scenario, res)),
+                        Err(_) => {
+                            n_failed += 1;
+                            None
+                        }
+                    })
+                    .collect_vec();
+                let start = Instant::now();
+                store.insert_results(&completed);
+                write_seconds += start.elapsed().as_secs_f64();
+            };
 
             loop {
-                match rx.recv_timeout(Duration::from_millis(1)) {
-                    Ok((scenario, res)) => {
-                        received_any = true;
-                        let insert_specifiers = make_insert_specifiers(&scenario, &res);
-                        insert_statement
-                            .insert(specifier_params(&insert_specifiers).as_slice())
-                            .expect("insert");
+                let mut received = Vec::new();
+                let mut disconnected = false;
+
+                loop {
+                    match rx.recv_timeout(Duration::from_millis(1)) {
+                        Ok(scenario_res) => received.push(scenario_res),
+                        Err(RecvTimeoutError::Timeout) => break,
+                        Err(RecvTimeoutError::Disconnected) => {
+                            disconnected = true;
+                            break;
+                        }
                     }
-                    Err(RecvTimeoutError::Timeout) => break,
-                    Err(RecvTimeoutError::Disconnected) => return,
                 }
-            }
 
-            drop(insert_statement);
-            transaction.commit().expect("commit");
+                let received_any = !received.is_empty();
+                if received_any || disconnected {
+                    handle_received(received);
+                }
 
-            if !received_any && is_done_job.load(Ordering::Relaxed) {
-                break;
+                if disconnected || !received_any && is_done_job.load(Ordering::Relaxed) {
+                    break;
+                }
             }
+            (n_failed, write_seconds)
         });
 
-        scenarios.par_iter().for_each(|scenario| {
-            let result = std::panic::catch_unwind(|| {
-            {
-                let mut scenario = scenario.clone();
-                scenario.specifiers_hash = Some(specifiers_hash(&scenario));
+        let progress = Progress::new(&scenarios, quiet);
+        let durations = estimate_durations(&scenarios, &past_run_times);
+        let stop = install_interrupt_handler();
 
-                if completed_result_set.lock().unwrap().contains(&scenario.specifiers_hash.unwrap()) {
-                    n_scenarios_completed.fetch_add(1, Ordering::Relaxed);
-                    return;
-                }
+        let not_started = run_longest_first(
+            scenarios,
+            &durations,
+            long_threads,
+            &stop,
+            |scenario| {
+                let result = std::panic::catch_unwind(|| {
+                    {
+                        let scenario = scenario.clone();
+                        let res = run_with_parameters(scenario.clone());
 
-                let res = run_with_parameters(scenario.clone());
-
-                n_scenarios_completed.fetch_add(1, Ordering::Relaxed);
-                if many_scenarios {
-                    let completed = n_scenarios_completed.load(Ordering::Relaxed);
-                    if completed % 500 == 0 {
-                        println!(
-                            "{}/{}: ",
-                            n_scenarios_completed.load(Ordering::Relaxed),
-                            n_scenarios
-                        );
-                    }
-                } else {
-                    print!(
-                        "{}/{}: ",
-                        n_scenarios_completed.load(Ordering::Relaxed),
-                        n_scenarios
-                    );
-                    if scenario.stats_analysis {
-                        println_f!(
-                            "{res} {scenario.search_depth} {scenario.n_actions} {scenario.samples_n}"
-                        );
-                    } else {
-                        println_f!("{res}");
+                        progress.complete(&scenario);
+                        if !many_scenarios && !progress.is_quiet() {
+                            if scenario.stats_analysis {
+                                progress.println(&format_f!(
+                            "{res} {scenario.search_depth} {scenario.n_actions} {scenario.samples_n} {res.samples_used} {res.tree_seconds:.6} {}",
+                            res.step_seconds.iter().map(|s| format!("{:.6}", s)).join(" ")
+                        ));
+                            } else {
+                                progress.println(&format_f!("{res}"));
+                            }
+                        }
+
+                        // writeln_f!(file.lock().unwrap(), "{scenario_name} {res}").unwrap();
+                        // {
+                        //     let insert_specifiers = make_insert_specifiers(scenario, &res);
+                        //     let conn_guard = conn.lock().unwrap();
+                        //     let mut insert_statement =
+                        //         conn_guard.prepare(&insert_sql()).expect("prepare insert");
+                        //     insert_statement
+                        //         .insert(specifier_params(&insert_specifiers).as_slice())
+                        //         .expect("insert");
+                        // }
+                        tx.send((scenario, Ok(res))).expect("tx send");
                     }
+                });
+                // a failed scenario isn't stored, so running the sweep again retries it
+                if let Err(payload) = result {
+                    let message = payload
+                        .downcast_ref::<&str>()
+                        .map(|m| m.to_string())
+                        .or_else(|| payload.downcast_ref::<String>().cloned())
+                        .unwrap_or_else(|| "unknown panic".to_owned());
+                    progress.complete(scenario);
+                    tx.send((scenario.clone(), Err(message))).expect("tx send");
                 }
-
-                // writeln_f!(file.lock().unwrap(), "{scenario_name} {res}").unwrap();
-                // {
-                //     let insert_specifiers = make_insert_specifiers(scenario, &res);
-                //     let conn_guard = conn.lock().unwrap();
-                //     let mut insert_statement =
-                //         conn_guard.prepare(&insert_sql()).expect("prepare insert");
-                //     insert_statement
-                //         .insert(specifier_params(&insert_specifiers).as_slice())
-                //         .expect("insert");
-                // }
-                tx.send((scenario, res)).expect("tx send");
-            }
-            });
-            if result.is_err() {
-                // eprintln!(
-                //     "PANIC for scenario: {:?}",
-                //     scenario.sc.as_ref().unwrap()
-                // );
-                // panic!();
-                std::process::abort();
-            }
-        });
+            },
+        );
 
         is_done.store(true, Ordering::Relaxed);
-        recv_thread.join().unwrap();
+        let (n_failed, write_seconds) = recv_thread.join().unwrap();
+        progress.finish();
+        if stats_analysis {
+            eprintln!(
+                "{:.3} seconds writing results to {}",
+                write_seconds, db_path
+            );
+        }
+
+        if !not_started.is_empty() {
+            let checkpoint = Checkpoint {
+                args: sweep_args,
+                remaining: not_started
+                    .iter()
+                    .map(|scenario| scenario.specifiers_hash.unwrap())
+                    .collect(),
+            };
+            checkpoint.save(&checkpoint_path);
+            eprintln!(
+                "{} scenarios were not started; continue with: sweep --resume={}",
+                not_started.len(),
+                checkpoint_path
+            );
+        } else if let Some((path, _)) = resume {
+            std::fs::remove_file(&path)
+                .unwrap_or_else(|e| panic!("Could not remove finished checkpoint {}: {}", path, e));
+        }
+
+        if n_failed > 0 {
+            eprintln!(
+                "{} scenarios panicked and were not stored; running the sweep again retries them",
+                n_failed
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_value() {
+        assert_eq!(expand_value("klucb"), vec!["klucb"]);
+        assert_eq!(expand_value("-0.1"), vec!["-0.1"]);
+        assert_eq!(expand_value("2-5"), vec!["2", "3", "4", "5"]);
+        assert_eq!(
+            expand_value("0.1:0.5:0.1"),
+            vec!["0.1", "0.2", "0.3", "0.4", "0.5"]
+        );
+        assert_eq!(expand_value("8:32:8"), vec!["8", "16", "24", "32"]);
+        assert_eq!(
+            expand_value("1e-3..1e3 log 7"),
+            vec!["0.001", "0.01", "0.1", "1", "10", "100", "1000"]
+        );
+        assert_eq!(expand_value("-1..-100 log 3"), vec!["-1", "-10", "-100"]);
+    }
+
+    #[test]
+    fn test_latin_hypercube_covers_values() {
+        let pairs = vec![
+            (
+                "samples_n".to_owned(),
+                vec!["8".to_owned(), "16".to_owned()],
+            ),
+            ("rng_seed".to_owned(), vec!["0-3".to_owned()]),
+        ];
+        let mut rng = StdRng::seed_from_u64(0);
+        let samples = sample_name_value_pairs(&pairs, 4, SamplingMode::LatinHypercube, &mut rng);
+        assert_eq!(samples.len(), 4);
+
+        let seeds = samples
+            .iter()
+            .map(|s| s[1].1[0].clone())
+            .sorted()
+            .collect_vec();
+        assert_eq!(seeds, vec!["0", "1", "2", "3"]);
+
+        let n_eights = samples.iter().filter(|s| s[0].1[0] == "8").count();
+        assert_eq!(n_eights, 2);
+    }
+
+    #[test]
+    fn test_validate_scenarios() {
+        let errors_for = |args: &str| {
+            let pairs = parse_name_value_pairs(args.split_whitespace().map(|a| a.to_owned()));
+            let scenarios = create_scenarios(&Parameters::new(), &pairs);
+            let mut errors = validate_scenarios(&pairs, &scenarios);
+            errors.append(&mut unused_prefixed_parameters(&[pairs], &scenarios));
+            errors.into_iter().unique().collect_vec()
+        };
+
+        // mode-prefixed parameters only apply to their own scenarios
+        assert!(errors_for(
+            "selection_mode ucb ucbv :: ucbv.ucbv_const 0.1 :: ucb_const -10 :: samples_n 8"
+        )
+        .is_empty());
+
+        assert_eq!(errors_for("selection_mode ucb :: ucbv_const 0.1").len(), 1);
+        assert_eq!(
+            errors_for("selection_mode ucb :: ucbv.ucbv_const 0.1").len(),
+            1
+        );
+        assert_eq!(errors_for("samples_n 0 8").len(), 1);
+        assert_eq!(
+            errors_for("bound_mode same :: final_choice_mode same").len(),
+            1
+        );
     }
 }
@@ -0,0 +1,97 @@
+use std::{
+    collections::BTreeSet,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use toml::Value;
+
+pub const DEFAULT_CHECKPOINT_PATH: &str = "sweep.checkpoint";
+
+// What's left of an interrupted sweep: its command line and the specifiers_hash of every scenario
+// that hadn't started yet. Resuming recreates the scenarios from the same arguments
+// and runs only the remaining ones, even with --force-recompute.
+//
+// args = ["rng_seed", "0-511", "::", "samples_n", "8", "16"]
+// remaining = [-4325876543, 12349876]
+pub struct Checkpoint {
+    pub args: Vec<String>,
+    pub remaining: BTreeSet<i64>,
+}
+
+impl Checkpoint {
+    pub fn load(path: &str) -> Self {
+        let contents = std::fs::read_to_string(path)
+            .unwrap_or_else(|e| panic!("Could not read checkpoint {}: {}", path, e));
+        let root: toml::value::Table = toml::from_str(&contents)
+            .unwrap_or_else(|e| panic!("Could not parse checkpoint {}: {}", path, e));
+        let array = |name: &str| {
+            root.get(name)
+                .and_then(|v| v.as_array())
+                .unwrap_or_else(|| panic!("Checkpoint {} has no {} array", path, name))
+        };
+
+        Self {
+            args: array("args")
+                .iter()
+                .map(|v| v.as_str().expect("checkpoint args are strings").to_owned())
+                .collect(),
+            remaining: array("remaining")
+                .iter()
+                .map(|v| v.as_integer().expect("checkpoint hashes are integers"))
+                .collect(),
+        }
+    }
+
+    pub fn save(&self, path: &str) {
+        let mut root = toml::value::Table::new();
+        root.insert(
+            "args".to_owned(),
+            Value::Array(self.args.iter().cloned().map(Value::String).collect()),
+        );
+        root.insert(
+            "remaining".to_owned(),
+            Value::Array(self.remaining.iter().copied().map(Value::Integer).collect()),
+        );
+        std::fs::write(path, toml::to_string(&root).unwrap())
+            .unwrap_or_else(|e| panic!("Could not write checkpoint {}: {}", path, e));
+    }
+}
+
+// The first Ctrl-C sets the returned flag, so no new scenarios are started
+// while the running ones finish and get stored. A second Ctrl-C exits immediately.
+pub fn install_interrupt_handler() -> Arc<AtomicBool> {
+    let stop = Arc::new(AtomicBool::new(false));
+    let stop_handler = stop.clone();
+    ctrlc::set_handler(move || {
+        if stop_handler.swap(true, Ordering::SeqCst) {
+            eprintln!("Interrupted again, exiting without waiting for running scenarios");
+            std::process::exit(130);
+        }
+        eprintln!("Interrupted, finishing the running scenarios (Ctrl-C again to exit now)");
+    })
+    .expect("set Ctrl-C handler");
+    stop
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checkpoint_round_trip() {
+        let path = std::env::temp_dir().join(format!("checkpoint_test_{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let checkpoint = Checkpoint {
+            args: vec!["rng_seed".to_owned(), "0-3".to_owned(), "::".to_owned()],
+            remaining: vec![-5, 7, i64::MAX].into_iter().collect(),
+        };
+        checkpoint.save(path);
+        let loaded = Checkpoint::load(path);
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(loaded.args, checkpoint.args);
+        assert_eq!(loaded.remaining, checkpoint.remaining);
+    }
+}
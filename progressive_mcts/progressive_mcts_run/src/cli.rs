@@ -0,0 +1,288 @@
+use clap::{Arg, ArgAction, ArgMatches, Command};
+
+use crate::arg_parameters::{
+    parameters_help, replay_scenario, run_parallel_scenarios, run_single_scenario,
+};
+use crate::{analyze, export, plot};
+
+const SUBCOMMANDS: &[&str] = &[
+    "sweep", "run", "replay", "export", "analyze", "plot", "help",
+];
+
+const PARAMETERS_USAGE: &str = "\
+Parameters are given as <name> [value]* :: where each value may also be a range:
+2-8 (integers), 0.1:0.9:0.1 (with step), or \"1e-3..1e3 log 7\".
+Prefixing a name with a bound_mode or selection_mode (klucb.ucb_const) only applies it there.";
+
+// The "name value* ::" arguments, which take everything after the flags
+fn parameters_arg() -> Arg {
+    Arg::new("parameters")
+        .value_name("PARAMETERS")
+        .num_args(0..)
+        .trailing_var_arg(true)
+        .allow_hyphen_values(true)
+        .help("<name> [value]* :: for each parameter")
+}
+
+fn flag(name: &'static str, help: &'static str) -> Arg {
+    Arg::new(name)
+        .long(name)
+        .action(ArgAction::SetTrue)
+        .help(help)
+}
+
+fn option(name: &'static str, value_name: &'static str, help: &'static str) -> Arg {
+    Arg::new(name).long(name).value_name(value_name).help(help)
+}
+
+fn command() -> Command {
+    let parameters_help = format!(
+        "{}\n\nParameters (default): description\n{}",
+        PARAMETERS_USAGE,
+        parameters_help()
+    );
+
+    Command::new("progressive_mcts_run")
+        .about("Runs and analyzes MCTS experiments on randomly generated problem trees")
+        .after_help("Without a subcommand, all of the arguments are run as a sweep's parameters")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("sweep")
+                .about("Run every combination of the parameters' values, storing the results")
+                .after_help(format!(
+                    "{}\n\nOr load a sweep definition from a TOML (or .yaml) file: sweep_file sweep.toml :: rng_seed 0-7\n\
+                     Scenarios already in db_path are skipped, and scenarios run longest first.\n\
+                     Ctrl-C stops starting new scenarios and writes a checkpoint to continue from with --resume.",
+                    parameters_help
+                ))
+                .arg(flag("quiet", "Don't draw the progress bar or print each result"))
+                .arg(flag(
+                    "dry-run",
+                    "List the scenarios and how many are already stored, without running them",
+                ))
+                .arg(flag(
+                    "force-recompute",
+                    "Replace the stored results of these scenarios",
+                ))
+                .arg(
+                    option(
+                        "filter",
+                        "KEY=VALUE",
+                        "Only run the scenarios with this parameter value (repeatable, OR within a key)",
+                    )
+                    .action(ArgAction::Append),
+                )
+                .arg(option(
+                    "sample-random",
+                    "N",
+                    "Run N random combinations instead of the full grid",
+                ))
+                .arg(option(
+                    "sample-lhs",
+                    "N",
+                    "Run N latin hypercube combinations instead of the full grid",
+                ))
+                .arg(option(
+                    "optimize",
+                    "N",
+                    "Tune the multi-valued parameters for the lowest mean regret over N iterations",
+                ))
+                .arg(option(
+                    "seed",
+                    "SEED",
+                    "Seed of --sample-random, --sample-lhs, and --optimize [default: 0]",
+                ))
+                .arg(option(
+                    "long-threads",
+                    "N",
+                    "Keep only N threads on the longest scenarios and the rest on the shortest",
+                ))
+                .arg(option(
+                    "checkpoint",
+                    "PATH",
+                    "Where to write the checkpoint if interrupted [default: sweep.checkpoint]",
+                ))
+                .arg(
+                    option(
+                        "resume",
+                        "PATH",
+                        "Continue an interrupted sweep from its checkpoint [default: sweep.checkpoint]",
+                    )
+                    .num_args(0..=1)
+                    .require_equals(true)
+                    .default_missing_value(""),
+                )
+                .arg(
+                    option(
+                        "dashboard",
+                        "ADDR",
+                        "Serve a live status page (needs --features dashboard) [default: 127.0.0.1:8088]",
+                    )
+                    .num_args(0..=1)
+                    .require_equals(true)
+                    .default_missing_value(""),
+                )
+                .arg(parameters_arg()),
+        )
+        .subcommand(
+            Command::new("run")
+                .about("Run a single scenario, printing the search's progress")
+                .after_help(parameters_help)
+                .arg(parameters_arg()),
+        )
+        .subcommand(
+            Command::new("replay")
+                .about("Run a stored scenario again, with the search tree report")
+                .arg(
+                    Arg::new("specifiers_hash")
+                        .required(true)
+                        .allow_negative_numbers(true)
+                        .value_parser(clap::value_parser!(i64))
+                        .help("The scenario's specifiers_hash, as in the exports"),
+                )
+                .arg(option("db-path", "PATH", "Results to find the scenario in").default_value("results.db")),
+        )
+        .subcommand(
+            Command::new("export")
+                .about("Export the stored results as CSV or Parquet")
+                .after_help(
+                    "export <file.csv|file.parquet> [db_path results.db ::] [<column> [value]* ::]*",
+                )
+                .arg(parameters_arg()),
+        )
+        .subcommand(
+            Command::new("analyze")
+                .about("Compare two configurations on their shared seeds")
+                .after_help(
+                    "analyze :: a key=value* :: b key=value* :: [metric regret ::] [db_path results.db ::]",
+                )
+                .arg(parameters_arg()),
+        )
+        .subcommand(
+            Command::new("plot")
+                .about("Draw the cost, regret, and trade-off figures")
+                .after_help(
+                    "plot [out_dir plots ::] [format svg|png ::] [where key=value* ::] [db_path results.db ::]",
+                )
+                .arg(parameters_arg()),
+        )
+}
+
+fn parameters(matches: &ArgMatches) -> Vec<String> {
+    matches
+        .get_many::<String>("parameters")
+        .map_or(Vec::new(), |vals| vals.cloned().collect())
+}
+
+// The sweep's flags in the "--flag value* ::" form that run_parallel_scenarios
+// (and the checkpoints) use, followed by its parameters
+fn sweep_args(matches: &ArgMatches) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut push = |name: &str, vals: Vec<String>| {
+        args.push(format!("--{}", name));
+        args.extend(vals.into_iter().filter(|v| !v.is_empty()));
+        args.push("::".to_owned());
+    };
+
+    for name in ["quiet", "dry-run", "force-recompute"].iter() {
+        if matches.get_flag(name) {
+            push(name, Vec::new());
+        }
+    }
+    if let Some(filters) = matches.get_many::<String>("filter") {
+        push("filter", filters.cloned().collect());
+    }
+    let seed = matches.get_one::<String>("seed");
+    for name in ["sample-random", "sample-lhs", "optimize"].iter() {
+        if let Some(n) = matches.get_one::<String>(name) {
+            push(name, std::iter::once(n).chain(seed).cloned().collect());
+        }
+    }
+    for name in ["long-threads", "checkpoint", "resume", "dashboard"].iter() {
+        if let Some(val) = matches.get_one::<String>(name) {
+            push(name, vec![val.clone()]);
+        }
+    }
+
+    args.extend(parameters(matches));
+    args
+}
+
+pub fn run_cli() {
+    let mut args = std::env::args().collect::<Vec<_>>();
+    // sweeps used to be given without a subcommand, with their flags as "--flag value* ::",
+    // which still works by passing all of the arguments through as parameters
+    let has_subcommand = match args.get(1) {
+        Some(a) => SUBCOMMANDS.contains(&a.as_str()) || ["-h", "--help"].contains(&a.as_str()),
+        None => false,
+    };
+    if !has_subcommand {
+        args.splice(1..1, vec!["sweep".to_owned(), "--".to_owned()]);
+    }
+
+    let matches = command().get_matches_from(args);
+    match matches.subcommand() {
+        Some(("sweep", m)) => run_parallel_scenarios(sweep_args(m)),
+        Some(("run", m)) => run_single_scenario(&parameters(m)),
+        Some(("replay", m)) => replay_scenario(
+            *m.get_one::<i64>("specifiers_hash").unwrap(),
+            m.get_one::<String>("db-path").unwrap(),
+        ),
+        Some(("export", m)) => export::run_export(&parameters(m)),
+        Some(("analyze", m)) => analyze::run_analyze(&parameters(m)),
+        Some(("plot", m)) => plot::run_plot(&parameters(m)),
+        _ => unreachable!("a subcommand is required"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sweep_args() {
+        let matches = command().get_matches_from(vec![
+            "progressive_mcts_run",
+            "sweep",
+            "--quiet",
+            "--filter",
+            "samples_n=8",
+            "--filter",
+            "samples_n=16",
+            "--sample-lhs",
+            "20",
+            "--seed",
+            "3",
+            "--resume",
+            "rng_seed",
+            "0-3",
+            "::",
+            "--dry-run",
+            "::",
+        ]);
+        let (_, m) = matches.subcommand().unwrap();
+        assert_eq!(
+            sweep_args(m),
+            vec![
+                "--quiet",
+                "::",
+                "--filter",
+                "samples_n=8",
+                "samples_n=16",
+                "::",
+                "--sample-lhs",
+                "20",
+                "3",
+                "::",
+                "--resume",
+                "::",
+                "rng_seed",
+                "0-3",
+                "::",
+                // flags after the parameters are still passed through the old way
+                "--dry-run",
+                "::",
+            ]
+        );
+    }
+}
@@ -0,0 +1,221 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use itertools::Itertools;
+
+use crate::{
+    arg_parameters::Parameters, parameters_sql::make_select_specifiers, progress::method_name,
+    RunResults,
+};
+
+const MAX_RECENT_FAILURES: usize = 20;
+
+#[derive(Default)]
+struct MethodStatus {
+    completed: usize,
+    total: usize,
+    regret_sum: f64,
+    chosen_true_cost_sum: f64,
+}
+
+struct DashboardState {
+    started: Instant,
+    completed: usize,
+    failed: usize,
+    methods: BTreeMap<String, MethodStatus>,
+    // (scenario specifiers, panic message), newest last
+    recent_failures: VecDeque<(String, String)>,
+}
+
+// Sweep progress, running means per method, and the most recent failures,
+// served as a small web page (and as JSON at /status.json) for checking on long sweeps.
+// It sees the same results the receiving thread stores in the database.
+// The server itself needs the dashboard feature: cargo build --features dashboard
+pub struct Dashboard {
+    state: Arc<Mutex<DashboardState>>,
+}
+
+impl Dashboard {
+    pub fn start(addr: &str, scenarios: &[Parameters]) -> Self {
+        let mut methods = BTreeMap::<String, MethodStatus>::new();
+        for scenario in scenarios.iter() {
+            methods.entry(method_name(scenario)).or_default().total += 1;
+        }
+        let state = Arc::new(Mutex::new(DashboardState {
+            started: Instant::now(),
+            completed: 0,
+            failed: 0,
+            methods,
+            recent_failures: VecDeque::new(),
+        }));
+        serve(addr, state.clone());
+        eprintln!("Serving the sweep dashboard at http://{}/", addr);
+        Self { state }
+    }
+
+    pub fn record(&self, received: &[(Parameters, Result<RunResults, String>)]) {
+        let mut state = self.state.lock().unwrap();
+        for (scenario, res) in received.iter() {
+            match res {
+                Ok(res) => {
+                    state.completed += 1;
+                    let method = state.methods.entry(method_name(scenario)).or_default();
+                    method.completed += 1;
+                    method.regret_sum += res.regret;
+                    method.chosen_true_cost_sum += res.chosen_true_cost;
+                }
+                Err(message) => {
+                    state.failed += 1;
+                    let specifiers = make_select_specifiers(scenario)
+                        .into_iter()
+                        .map(|(name, val)| format!("{}={}", &name[1..], val))
+                        .join(" ");
+                    state
+                        .recent_failures
+                        .push_back((specifiers, message.clone()));
+                    if state.recent_failures.len() > MAX_RECENT_FAILURES {
+                        state.recent_failures.pop_front();
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg_attr(not(feature = "dashboard"), allow(dead_code))]
+impl DashboardState {
+    fn total(&self) -> usize {
+        self.methods.values().map(|m| m.total).sum()
+    }
+
+    fn to_json(&self) -> String {
+        let methods = self
+            .methods
+            .iter()
+            .map(|(name, m)| {
+                let n = m.completed.max(1) as f64;
+                format!(
+                    "{{\"method\":{:?},\"completed\":{},\"total\":{},\"mean_regret\":{},\"mean_chosen_true_cost\":{}}}",
+                    name,
+                    m.completed,
+                    m.total,
+                    m.regret_sum / n,
+                    m.chosen_true_cost_sum / n
+                )
+            })
+            .join(",");
+        let failures = self
+            .recent_failures
+            .iter()
+            .map(|(specifiers, message)| {
+                format!(
+                    "{{\"scenario\":{:?},\"message\":{:?}}}",
+                    specifiers, message
+                )
+            })
+            .join(",");
+        format!(
+            "{{\"elapsed_s\":{:.1},\"completed\":{},\"failed\":{},\"total\":{},\"methods\":[{}],\"recent_failures\":[{}]}}",
+            self.started.elapsed().as_secs_f64(),
+            self.completed,
+            self.failed,
+            self.total(),
+            methods,
+            failures
+        )
+    }
+
+    fn to_html(&self) -> String {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let done = self.completed + self.failed;
+        let eta = if done > 0 {
+            format!(
+                "{:.0}s",
+                elapsed / done as f64 * (self.total() - done) as f64
+            )
+        } else {
+            "-".to_owned()
+        };
+
+        let mut html = String::from(
+            "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><meta http-equiv=\"refresh\" content=\"5\">\
+             <title>progressive_mcts sweep</title>\
+             <style>body{font-family:sans-serif} td,th{padding:2px 10px;text-align:right}</style>\
+             </head><body>",
+        );
+        html += &format!(
+            "<h2>{}/{} scenarios, {} failed</h2><p>{:.0}s elapsed, ETA {}</p>",
+            self.completed,
+            self.total(),
+            self.failed,
+            elapsed,
+            eta
+        );
+        html += "<table><tr><th>method</th><th>completed</th><th>mean regret</th><th>mean chosen true cost</th></tr>";
+        for (name, m) in self.methods.iter() {
+            let n = m.completed.max(1) as f64;
+            html += &format!(
+                "<tr><td>{}</td><td>{}/{}</td><td>{:.3}</td><td>{:.3}</td></tr>",
+                escape_html(name),
+                m.completed,
+                m.total,
+                m.regret_sum / n,
+                m.chosen_true_cost_sum / n
+            );
+        }
+        html += "</table>";
+        if !self.recent_failures.is_empty() {
+            html += "<h3>Recent failures</h3><ul>";
+            for (specifiers, message) in self.recent_failures.iter().rev() {
+                html += &format!(
+                    "<li><code>{}</code>: {}</li>",
+                    escape_html(specifiers),
+                    escape_html(message)
+                );
+            }
+            html += "</ul>";
+        }
+        html += "</body></html>";
+        html
+    }
+}
+
+#[cfg_attr(not(feature = "dashboard"), allow(dead_code))]
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(feature = "dashboard")]
+fn serve(addr: &str, state: Arc<Mutex<DashboardState>>) {
+    let server = tiny_http::Server::http(addr)
+        .unwrap_or_else(|e| panic!("Could not serve the dashboard at {}: {}", addr, e));
+    std::thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let (body, content_type) = {
+                let state = state.lock().unwrap();
+                if request.url() == "/status.json" {
+                    (state.to_json(), "application/json")
+                } else {
+                    (state.to_html(), "text/html; charset=utf-8")
+                }
+            };
+            let header =
+                tiny_http::Header::from_bytes(&b"Content-Type"[..], content_type.as_bytes())
+                    .unwrap();
+            // a client that went away isn't our problem
+            let _ = request.respond(tiny_http::Response::from_string(body).with_header(header));
+        }
+    });
+}
+
+#[cfg(not(feature = "dashboard"))]
+fn serve(_addr: &str, _state: Arc<Mutex<DashboardState>>) {
+    panic!(
+        "--dashboard needs a build with the dashboard feature: cargo build --features dashboard"
+    );
+}
@@ -0,0 +1,269 @@
+use std::{fs::File, io::Write, sync::Arc};
+
+use itertools::Itertools;
+use parquet::{
+    basic::Compression,
+    data_type::{ByteArray, ByteArrayType, DoubleType, Int64Type},
+    file::{properties::WriterProperties, writer::SerializedFileWriter},
+    schema::parser::parse_message_type,
+};
+use rusqlite::types::ValueRef;
+
+use crate::{
+    arg_parameters::{expand_value, parse_name_value_pairs},
+    parameters_sql::column_types,
+};
+
+enum Column {
+    Integer(Vec<Option<i64>>),
+    Real(Vec<Option<f64>>),
+    Text(Vec<Option<String>>),
+}
+
+// booleans like most_visited_best_cost_consistency are stored as "true"/"false"
+fn value_to_integer(value: ValueRef) -> Option<i64> {
+    match value {
+        ValueRef::Null => None,
+        ValueRef::Integer(i) => Some(i),
+        ValueRef::Real(f) => Some(f as i64),
+        ValueRef::Text(t) | ValueRef::Blob(t) => match std::str::from_utf8(t).unwrap() {
+            "true" => Some(1),
+            "false" => Some(0),
+            t => t.parse().ok(),
+        },
+    }
+}
+
+fn value_to_real(value: ValueRef) -> Option<f64> {
+    match value {
+        ValueRef::Null => None,
+        ValueRef::Integer(i) => Some(i as f64),
+        ValueRef::Real(f) => Some(f),
+        ValueRef::Text(t) | ValueRef::Blob(t) => std::str::from_utf8(t).unwrap().parse().ok(),
+    }
+}
+
+fn value_to_text(value: ValueRef) -> Option<String> {
+    match value {
+        ValueRef::Null => None,
+        ValueRef::Integer(i) => Some(i.to_string()),
+        ValueRef::Real(f) => Some(f.to_string()),
+        ValueRef::Text(t) | ValueRef::Blob(t) => Some(String::from_utf8_lossy(t).into_owned()),
+    }
+}
+
+// Reads the whole results table, keeping only the rows matching every filter
+// (a row matches a filter when its column equals any of the filter's values).
+// Columns get their types from parameters_sql, so older databases with fewer columns still work.
+fn read_columns(
+    conn: &rusqlite::Connection,
+    filters: &[(String, Vec<String>)],
+) -> Vec<(String, Column)> {
+    let types = column_types();
+
+    let mut sql = "SELECT * FROM results".to_owned();
+    let mut filter_values = Vec::new();
+    for (i, (name, values)) in filters.iter().enumerate() {
+        if !types.iter().any(|(column, _)| column == name) {
+            panic!("{} is not a column of the results table!", name);
+        }
+        let values = values.iter().flat_map(|v| expand_value(v)).collect_vec();
+        sql.push_str(if i == 0 { " WHERE " } else { " AND " });
+        sql.push_str(&format!(
+            "{} IN ({})",
+            name,
+            values.iter().map(|_| "?").join(", ")
+        ));
+        filter_values.extend(values);
+    }
+
+    let mut statement = conn.prepare(&sql).expect("prepare select");
+    let mut columns = statement
+        .column_names()
+        .into_iter()
+        .map(|name| {
+            let sql_type = types
+                .iter()
+                .find(|(column, _)| *column == name)
+                .map_or("TEXT", |(_, sql_type)| sql_type);
+            let column = match sql_type {
+                "INTEGER" => Column::Integer(Vec::new()),
+                "REAL" => Column::Real(Vec::new()),
+                _ => Column::Text(Vec::new()),
+            };
+            (name.to_owned(), column)
+        })
+        .collect_vec();
+
+    let mut rows = statement
+        .query(rusqlite::params_from_iter(filter_values.iter()))
+        .expect("select");
+    while let Some(row) = rows.next().expect("row") {
+        for (i, (_, column)) in columns.iter_mut().enumerate() {
+            let value = row.get_ref(i).unwrap();
+            match column {
+                Column::Integer(vals) => vals.push(value_to_integer(value)),
+                Column::Real(vals) => vals.push(value_to_real(value)),
+                Column::Text(vals) => vals.push(value_to_text(value)),
+            }
+        }
+    }
+
+    columns
+}
+
+fn csv_field(field: String) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field
+    }
+}
+
+fn write_csv(columns: &[(String, Column)], out: &mut impl Write) -> std::io::Result<()> {
+    writeln!(out, "{}", columns.iter().map(|(name, _)| name).join(","))?;
+    let n_rows = columns.first().map_or(0, |(_, column)| match column {
+        Column::Integer(vals) => vals.len(),
+        Column::Real(vals) => vals.len(),
+        Column::Text(vals) => vals.len(),
+    });
+    for row_i in 0..n_rows {
+        let fields = columns.iter().map(|(_, column)| match column {
+            Column::Integer(vals) => vals[row_i].map(|v| v.to_string()),
+            Column::Real(vals) => vals[row_i].map(|v| v.to_string()),
+            Column::Text(vals) => vals[row_i].clone().map(csv_field),
+        });
+        writeln!(out, "{}", fields.map(|f| f.unwrap_or_default()).join(","))?;
+    }
+    Ok(())
+}
+
+// definition levels are 1 for present values and 0 for NULLs, which are left out of the values
+fn split_nulls<T: Clone>(vals: &[Option<T>]) -> (Vec<T>, Vec<i16>) {
+    let present = vals.iter().filter_map(|v| v.clone()).collect_vec();
+    let def_levels = vals.iter().map(|v| v.is_some() as i16).collect_vec();
+    (present, def_levels)
+}
+
+fn write_parquet(columns: &[(String, Column)], file: File) -> parquet::errors::Result<()> {
+    let fields = columns
+        .iter()
+        .map(|(name, column)| match column {
+            Column::Integer(_) => format!("OPTIONAL INT64 {};", name),
+            Column::Real(_) => format!("OPTIONAL DOUBLE {};", name),
+            Column::Text(_) => format!("OPTIONAL BYTE_ARRAY {} (UTF8);", name),
+        })
+        .join(" ");
+    let schema = Arc::new(parse_message_type(&format!(
+        "message results {{ {} }}",
+        fields
+    ))?);
+    let props = Arc::new(
+        WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build(),
+    );
+
+    let mut writer = SerializedFileWriter::new(file, schema, props)?;
+    let mut row_group = writer.next_row_group()?;
+    for (_, column) in columns.iter() {
+        let mut column_writer = row_group.next_column()?.expect("column writer");
+        match column {
+            Column::Integer(vals) => {
+                let (vals, def_levels) = split_nulls(vals);
+                column_writer
+                    .typed::<Int64Type>()
+                    .write_batch(&vals, Some(&def_levels), None)?;
+            }
+            Column::Real(vals) => {
+                let (vals, def_levels) = split_nulls(vals);
+                column_writer
+                    .typed::<DoubleType>()
+                    .write_batch(&vals, Some(&def_levels), None)?;
+            }
+            Column::Text(vals) => {
+                let (vals, def_levels) = split_nulls(vals);
+                let vals = vals
+                    .iter()
+                    .map(|v| ByteArray::from(v.as_str()))
+                    .collect_vec();
+                column_writer.typed::<ByteArrayType>().write_batch(
+                    &vals,
+                    Some(&def_levels),
+                    None,
+                )?;
+            }
+        }
+        column_writer.close()?;
+    }
+    row_group.close()?;
+    writer.close()?;
+    Ok(())
+}
+
+// export <file.csv|file.parquet> [db_path results.db ::] [<column> [value]* ::]*
+pub fn run_export(args: &[String]) {
+    let out_path = args
+        .first()
+        .expect("export takes the output file (.csv or .parquet), then optional filters");
+    let mut filters = parse_name_value_pairs(args[1..].iter().cloned());
+    let db_path = filters
+        .iter()
+        .position(|(name, _)| name == "db_path")
+        .map_or("results.db".to_owned(), |i| {
+            let (_, vals) = filters.remove(i);
+            vals[0].clone()
+        });
+
+    let conn = rusqlite::Connection::open(&db_path).unwrap();
+    let columns = read_columns(&conn, &filters);
+
+    let file =
+        File::create(out_path).unwrap_or_else(|e| panic!("Could not create {}: {}", out_path, e));
+    if out_path.ends_with(".parquet") {
+        write_parquet(&columns, file).expect("write parquet");
+    } else if out_path.ends_with(".csv") {
+        write_csv(&columns, &mut std::io::BufWriter::new(file)).expect("write csv");
+    } else {
+        panic!(
+            "Unknown export format for {}, use .csv or .parquet",
+            out_path
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parameters_sql::create_table_sql;
+
+    #[test]
+    fn test_csv_export_filters_and_types() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        conn.execute(&create_table_sql(), []).unwrap();
+        conn.execute_batch(
+            "INSERT INTO results (rng_seed, bound_mode, most_visited_best_cost_consistency, regret)
+                VALUES (3, 'marginal', 'true', 1.5);
+             INSERT INTO results (rng_seed, bound_mode, most_visited_best_cost_consistency, regret)
+                VALUES (4, 'classic', 'false', 2.5);",
+        )
+        .unwrap();
+
+        let filters = vec![("rng_seed".to_owned(), vec!["1-3".to_owned()])];
+        let columns = read_columns(&conn, &filters);
+        let mut out = Vec::new();
+        write_csv(&columns, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        let lines = out.lines().collect_vec();
+        assert_eq!(lines.len(), 2);
+        let header = lines[0].split(',').collect_vec();
+        let row = lines[1].split(',').collect_vec();
+        let field = |name: &str| row[header.iter().position(|h| *h == name).unwrap()];
+        assert_eq!(field("rng_seed"), "3");
+        assert_eq!(field("bound_mode"), "marginal");
+        assert_eq!(field("most_visited_best_cost_consistency"), "1");
+        assert_eq!(field("regret"), "1.5");
+        assert_eq!(field("ucb_const"), "");
+    }
+}
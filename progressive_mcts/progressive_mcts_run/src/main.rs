@@ -1,8 +1,20 @@
+mod analyze;
 mod arg_parameters;
+mod checkpoint;
+mod cli;
+mod dashboard;
+mod export;
+mod optimizer;
 mod parameters_sql;
+mod plot;
 mod problem_scenario;
+mod progress;
+mod raw_samples;
+mod results_store;
+mod scheduling;
+mod sweep_config;
 
-use arg_parameters::{run_parallel_scenarios, Parameters};
+use arg_parameters::Parameters;
 #[allow(unused)]
 use fstrings::{eprintln_f, format_args_f, println_f, write_f};
 use itertools::Itertools;
@@ -14,8 +26,9 @@ use rand::{
     prelude::{SliceRandom, StdRng},
     SeedableRng,
 };
+use std::time::Instant;
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug)]
 pub struct RunResults {
     steps_taken: usize,
     chosen_cost: f64,
@@ -24,6 +37,13 @@ pub struct RunResults {
     regret: f64,
     cost_estimation_error: f64,
     sum_repeated: usize,
+    samples_used: usize,
+    // wall-clock seconds, for estimating how long similar scenarios will take
+    run_time: f64,
+    // with stats_analysis, wall-clock seconds simulating the steps at each depth, from 1,
+    // and the rest of the trials' time, spent choosing paths and updating the tree's costs
+    step_seconds: Vec<f64>,
+    tree_seconds: f64,
 }
 
 impl std::fmt::Display for RunResults {
@@ -90,6 +110,13 @@ fn compute_selection_index(
     Some(index)
 }
 
+// the simulator steps run by a search
+struct Steps {
+    taken: usize,
+    // seconds simulating steps by depth, when profiling with stats_analysis
+    seconds: Option<Vec<f64>>,
+}
+
 struct MctsNode<'a> {
     params: &'a Parameters,
     policy_choices: &'a [u32],
@@ -284,6 +311,39 @@ impl<'a> MctsNode<'a> {
         chosen_policy
     }
 
+    // true when the best child's upper bound is below every other child's lower bound,
+    // with bounds taken as z standard deviations (of the mean) around the expected cost
+    fn best_child_is_separated(&self, z: f64) -> bool {
+        let sub_nodes = match self.sub_nodes.as_ref() {
+            Some(sub_nodes) => sub_nodes,
+            None => return false,
+        };
+        if sub_nodes.len() < 2 || sub_nodes.iter().any(|n| n.n_trials < 2) {
+            return false;
+        }
+
+        let bounds = sub_nodes
+            .iter()
+            .map(|n| {
+                let cost = n.expected_cost.unwrap();
+                let std_dev = n.expected_cost_std_dev.unwrap();
+                (cost - z * std_dev, cost + z * std_dev)
+            })
+            .collect_vec();
+
+        let (best_i, best_upper) = bounds
+            .iter()
+            .enumerate()
+            .map(|(i, (_, upper))| (i, *upper))
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+            .unwrap();
+
+        bounds
+            .iter()
+            .enumerate()
+            .all(|(i, (lower, _))| i == best_i || best_upper < *lower)
+    }
+
     fn get_best_policy_by_visits(&self) -> u32 {
         let chosen_policy = self
             .sub_nodes
@@ -414,7 +474,7 @@ fn find_and_run_trial<'a>(
     node: &mut MctsNode<'a>,
     sim: &mut Simulator<'a>,
     rng: &mut StdRng,
-    steps_taken: &mut usize,
+    steps: &mut Steps,
     n_completed: usize,
 ) -> f64 {
     let path = find_trial_path(node, rng, Vec::new());
@@ -423,7 +483,7 @@ fn find_and_run_trial<'a>(
 
         assert_eq!(sim.depth + 1, depth);
 
-        let score = run_trial(node, sim, rng, steps_taken, &path, depth as i32);
+        let score = run_trial(node, sim, rng, steps, &path, depth as i32);
 
         for_node_in_path(node, &path[0..depth as usize - 1], |_| ())
             .sub_node_repeated_particles
@@ -447,7 +507,7 @@ fn find_and_run_trial<'a>(
         return score;
     }
 
-    let score = run_trial(node, sim, rng, steps_taken, &path, 0);
+    let score = run_trial(node, sim, rng, steps, &path, 0);
 
     if node.params.is_single_run {
         let mut depth1_action = None;
@@ -488,15 +548,19 @@ fn run_step<'a>(
     node: &mut MctsNode<'a>,
     sim: &mut Simulator<'a>,
     rng: &mut StdRng,
-    steps_taken: &mut usize,
+    steps: &mut Steps,
 ) -> Option<f64> {
     if let Some(ref policy) = node.policy {
         let prev_cost = sim.cost;
+        let start = steps.seconds.as_ref().map(|_| Instant::now());
         sim.take_step(*policy, rng);
+        if let (Some(seconds), Some(start)) = (steps.seconds.as_mut(), start) {
+            seconds[sim.depth as usize - 1] += start.elapsed().as_secs_f64();
+        }
         node.intermediate_costs.push((sim.cost, ()));
         node.marginal_costs.push((sim.cost - prev_cost, ()));
 
-        *steps_taken += 1;
+        steps.taken += 1;
 
         return Some(sim.cost);
     }
@@ -507,7 +571,7 @@ fn run_trial<'a>(
     node: &mut MctsNode<'a>,
     sim: &mut Simulator<'a>,
     rng: &mut StdRng,
-    steps_taken: &mut usize,
+    steps: &mut Steps,
     path: &[usize],
     skip_depth: i32,
 ) -> f64 {
@@ -516,7 +580,7 @@ fn run_trial<'a>(
     // skip over when we are repeating a particle and it has already been evaluated at this level
     let skip_over = skip_depth > 0;
     if !skip_over {
-        run_step(node, sim, rng, steps_taken);
+        run_step(node, sim, rng, steps);
     }
 
     let orig_sim = sim.clone();
@@ -529,7 +593,7 @@ fn run_trial<'a>(
             &mut node.sub_nodes.as_mut().unwrap()[path[0]],
             sim,
             rng,
-            steps_taken,
+            steps,
             &path[1..],
             skip_depth - 1,
         )
@@ -572,7 +636,9 @@ fn print_report(
 
         let _costs_only = node.costs.iter().map(|(c, _)| *c).collect_vec();
 
-        let index = node.compute_expected_cost_index(parent_n_trials, parent_n_trials.ln()).unwrap_or(99999.0);
+        let index = node
+            .compute_expected_cost_index(parent_n_trials, parent_n_trials.ln())
+            .unwrap_or(99999.0);
 
         //  interm = {_intermediate_cost:6.1?}, \
         //  {node.intermediate_costs=:.2?}, \
@@ -583,9 +649,8 @@ fn print_report(
              true = {additional_true_cost:6.1} ({true_intermediate_cost:6.1}), \
              marginal_costs = {:.2?}, \
              ",
-            &node.marginal_costs.iter().map(|a| a.0).collect_vec()
-            //  {_costs_only=:.2?}, \
-            //  {node.costs=:.2?}" //,
+            &node.marginal_costs.iter().map(|a| a.0).collect_vec() //  {_costs_only=:.2?}, \
+                                                                   //  {node.costs=:.2?}" //,
         );
     }
     if let Some(sub_nodes) = &node.sub_nodes {
@@ -656,6 +721,7 @@ fn set_final_choice_expected_values(params: &Parameters, node: &mut MctsNode) {
 }
 
 fn run_with_parameters(params: Parameters) -> RunResults {
+    let start_time = Instant::now();
     let policies = (0..params.n_actions).collect_vec();
 
     let mut node = MctsNode {
@@ -683,7 +749,15 @@ fn run_with_parameters(params: Parameters) -> RunResults {
 
     let scenario = ProblemScenario::new(params.search_depth, params.n_actions, &mut rng);
 
-    let mut steps_taken = 0;
+    let mut steps = Steps {
+        taken: 0,
+        seconds: if params.stats_analysis {
+            Some(vec![0.0; params.search_depth as usize])
+        } else {
+            None
+        },
+    };
+    let trials_start_time = Instant::now();
 
     // Expand first level so marginal_cost_confidence_interval has enough to go on
     node.get_or_expand_sub_nodes();
@@ -694,11 +768,21 @@ fn run_with_parameters(params: Parameters) -> RunResults {
             &mut node,
             &mut Simulator::sample(&scenario, i, &mut rng),
             &mut rng,
-            &mut steps_taken,
+            &mut steps,
             i,
         );
         i += 1;
 
+        if params.early_stop_z > 0.0
+            && i < params.samples_n
+            && node.best_child_is_separated(params.early_stop_z)
+        {
+            if params.is_single_run {
+                eprintln_f!("Stopping early after {i} samples");
+            }
+            break;
+        }
+
         if i >= params.samples_n {
             if params.most_visited_best_cost_consistency && i <= params.samples_n * 12 / 10 {
                 // if we have this best policy inconsistency, do more trials to try to resolve it!
@@ -715,10 +799,29 @@ fn run_with_parameters(params: Parameters) -> RunResults {
         }
     }
 
+    let trials_seconds = trials_start_time.elapsed().as_secs_f64();
+
     if params.print_report {
         print_report(&scenario, &node, node.n_trials as f64, 0.0);
     }
 
+    if !params.samples_dir.is_empty() {
+        let children = node
+            .sub_nodes
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|n| (n.policy.unwrap(), n.costs.iter().map(|(c, _)| *c).collect()))
+            .collect_vec();
+        let hash = params
+            .specifiers_hash
+            .unwrap_or_else(|| parameters_sql::specifiers_hash(&params));
+        raw_samples::write_root_samples(
+            &raw_samples::samples_path(&params.samples_dir, hash),
+            &children,
+        );
+    }
+
     set_final_choice_expected_values(&params, &mut node);
     let chosen_policy = node.get_best_policy_by_cost();
 
@@ -738,23 +841,34 @@ fn run_with_parameters(params: Parameters) -> RunResults {
         sum_repeated += sub_node.n_particles_repeated;
     }
     if params.is_single_run {
-        println_f!("steps taken: {steps_taken}");
+        println_f!("steps taken: {steps.taken}");
         println_f!("total repeated: {sum_repeated}");
     }
 
     let chosen_cost = node.expected_cost.unwrap_or(99999.0);
 
+    let step_seconds = steps.seconds.unwrap_or_default();
+    let tree_seconds = if params.stats_analysis {
+        (trials_seconds - step_seconds.iter().sum::<f64>()).max(0.0)
+    } else {
+        0.0
+    };
+
     RunResults {
-        steps_taken,
+        steps_taken: steps.taken,
         chosen_cost,
         chosen_true_cost,
         true_best_cost,
         regret: chosen_true_cost - true_best_cost,
         cost_estimation_error: (chosen_cost - chosen_true_cost).abs(),
         sum_repeated,
+        samples_used: i,
+        run_time: start_time.elapsed().as_secs_f64(),
+        step_seconds,
+        tree_seconds,
     }
 }
 
 fn main() {
-    run_parallel_scenarios();
+    cli::run_cli();
 }
@@ -0,0 +1,262 @@
+use std::collections::BTreeMap;
+
+#[allow(unused)]
+use fstrings::{eprintln_f, format_args_f, println_f};
+use itertools::Itertools;
+use rand::{
+    distributions::WeightedIndex,
+    prelude::{Distribution, StdRng},
+    Rng, SeedableRng,
+};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use crate::{
+    arg_parameters::{create_scenarios, expand_value, Parameters},
+    parameters_sql::specifiers_hash,
+    results_store::ResultsStore,
+    run_with_parameters, RunResults,
+};
+
+// random configurations tried before the TPE model takes over
+const N_STARTUP: usize = 5;
+// fraction of the evaluated configurations considered "good" by the TPE model
+const GOOD_FRACTION: f64 = 0.25;
+// candidates drawn from the good model each iteration, of which the most promising is run
+const N_CANDIDATES: usize = 24;
+
+type Config = Vec<usize>;
+
+struct SearchSpace {
+    // parameters with several values to choose between
+    names: Vec<String>,
+    values: Vec<Vec<String>>,
+    // everything else, including the rng_seed set each configuration is evaluated on
+    fixed: Vec<(String, Vec<String>)>,
+    // where each chosen parameter, then each fixed one, appeared in the original pairs
+    order: Vec<usize>,
+}
+
+impl SearchSpace {
+    fn new(name_value_pairs: &[(String, Vec<String>)]) -> Self {
+        let mut space = Self {
+            names: Vec::new(),
+            values: Vec::new(),
+            fixed: Vec::new(),
+            order: Vec::new(),
+        };
+        let mut fixed_order = Vec::new();
+        for (i, (name, values)) in name_value_pairs.iter().enumerate() {
+            let values = values.iter().flat_map(|v| expand_value(v)).collect_vec();
+            if name == "rng_seed" || values.len() <= 1 {
+                space.fixed.push((name.to_owned(), values));
+                fixed_order.push(i);
+            } else {
+                space.names.push(name.to_owned());
+                space.values.push(values);
+                space.order.push(i);
+            }
+        }
+        space.order.extend(fixed_order);
+        space
+    }
+
+    fn name_value_pairs(&self, config: &[usize]) -> Vec<(String, Vec<String>)> {
+        // interleave the chosen and fixed values back in their original order,
+        // so that prefixed names are still filtered against the modes set before them
+        self.names
+            .iter()
+            .zip(self.values.iter())
+            .zip(config.iter())
+            .map(|((name, values), &i)| (name.to_owned(), vec![values[i].clone()]))
+            .chain(self.fixed.iter().cloned())
+            .zip(self.order.iter())
+            .sorted_by_key(|(_, &i)| i)
+            .map(|(pair, _)| pair)
+            .collect_vec()
+    }
+
+    fn describe(&self, config: &[usize]) -> String {
+        self.names
+            .iter()
+            .zip(self.values.iter())
+            .zip(config.iter())
+            .map(|((name, values), &i)| format!("{}={}", name, values[i]))
+            .join(", ")
+    }
+
+    fn random_config(&self, rng: &mut StdRng) -> Config {
+        self.values
+            .iter()
+            .map(|values| rng.gen_range(0..values.len()))
+            .collect()
+    }
+
+    // Tree-structured Parzen estimator over the categorical values:
+    // per-parameter value frequencies among the good and bad configurations (plus a prior of one)
+    // give densities l(x) and g(x), and we pick the candidate from l(x) maximizing l(x) / g(x).
+    fn propose_tpe(&self, history: &[(Config, f64)], rng: &mut StdRng) -> Config {
+        let mut sorted = history.iter().collect_vec();
+        sorted.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+        let n_good = ((history.len() as f64 * GOOD_FRACTION).ceil() as usize).max(1);
+        let (good, bad) = sorted.split_at(n_good);
+
+        let densities = |configs: &[&(Config, f64)]| {
+            self.values
+                .iter()
+                .enumerate()
+                .map(|(param_i, values)| {
+                    let mut counts = vec![1.0; values.len()];
+                    for (config, _) in configs.iter() {
+                        counts[config[param_i]] += 1.0;
+                    }
+                    let total: f64 = counts.iter().sum();
+                    counts.into_iter().map(|c| c / total).collect_vec()
+                })
+                .collect_vec()
+        };
+        let good_densities = densities(good);
+        let bad_densities = densities(bad);
+
+        (0..N_CANDIDATES)
+            .map(|_| {
+                let candidate = good_densities
+                    .iter()
+                    .map(|d| WeightedIndex::new(d).unwrap().sample(rng))
+                    .collect_vec();
+                let score: f64 = candidate
+                    .iter()
+                    .enumerate()
+                    .map(|(param_i, &value_i)| {
+                        (good_densities[param_i][value_i] / bad_densities[param_i][value_i]).ln()
+                    })
+                    .sum();
+                (candidate, score)
+            })
+            .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+            .unwrap()
+            .0
+    }
+}
+
+// Runs (or loads from the database) every scenario of the configuration and returns the mean regret
+fn evaluate(
+    store: &mut dyn ResultsStore,
+    base_p: &Parameters,
+    name_value_pairs: &[(String, Vec<String>)],
+) -> f64 {
+    let mut scenarios = create_scenarios(base_p, name_value_pairs);
+    for scenario in scenarios.iter_mut() {
+        scenario.specifiers_hash = Some(specifiers_hash(scenario));
+    }
+
+    let mut regrets = Vec::new();
+    let mut to_run = Vec::new();
+    for scenario in scenarios.into_iter() {
+        match store.cached_regret(scenario.specifiers_hash.unwrap()) {
+            Some(regret) => regrets.push(regret),
+            None => to_run.push(scenario),
+        }
+    }
+
+    let new_results: Vec<(Parameters, RunResults)> = to_run
+        .into_par_iter()
+        .map(|scenario| {
+            let res = run_with_parameters(scenario.clone());
+            (scenario, res)
+        })
+        .collect();
+
+    store.insert_results(&new_results);
+    regrets.extend(new_results.iter().map(|(_, res)| res.regret));
+
+    assert!(!regrets.is_empty(), "No scenarios to evaluate");
+    regrets.iter().sum::<f64>() / regrets.len() as f64
+}
+
+// Tunes each method's (or sweep file preset's) multi-valued parameters for the lowest mean regret,
+// evaluating each proposed configuration on the full rng_seed set.
+pub fn run_optimization(
+    store: &mut dyn ResultsStore,
+    base_p: &Parameters,
+    name_value_pair_sets: &[Vec<(String, Vec<String>)>],
+    n_iterations: usize,
+    seed: u64,
+) {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut best_per_method = Vec::new();
+
+    for name_value_pairs in name_value_pair_sets.iter() {
+        let space = SearchSpace::new(name_value_pairs);
+        let method = space
+            .fixed
+            .iter()
+            .filter(|(name, _)| name.ends_with("_mode"))
+            .map(|(name, values)| format!("{}={}", name, values.join("|")))
+            .join(", ");
+
+        let mut history: Vec<(Config, f64)> = Vec::new();
+        let mut evaluated = BTreeMap::new();
+        for iteration in 0..n_iterations {
+            let config = if history.len() < N_STARTUP {
+                space.random_config(&mut rng)
+            } else {
+                space.propose_tpe(&history, &mut rng)
+            };
+
+            let regret = match evaluated.get(&config) {
+                Some(&regret) => regret,
+                None => {
+                    let regret = evaluate(store, base_p, &space.name_value_pairs(&config));
+                    evaluated.insert(config.clone(), regret);
+                    regret
+                }
+            };
+            eprintln_f!("{iteration}: {regret:8.3} for {}", space.describe(&config));
+            history.push((config, regret));
+        }
+
+        if let Some((config, regret)) = history.iter().min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+        {
+            best_per_method.push((method, space.describe(config), *regret));
+        }
+    }
+
+    println!("Best configurations by mean regret:");
+    for (method, config, regret) in best_per_method.iter() {
+        println_f!("{regret:8.3} [{method}] {config}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prefixed_values_apply_under_fixed_mode() {
+        let pairs = [
+            ("selection_mode", vec!["ucbv"]),
+            ("ucbv.ucbv_const", vec!["0.1", "0.5", "2"]),
+            ("samples_n", vec!["8"]),
+        ]
+        .iter()
+        .map(|(name, values)| {
+            (
+                name.to_string(),
+                values.iter().map(|v| v.to_string()).collect(),
+            )
+        })
+        .collect_vec();
+        let space = SearchSpace::new(&pairs);
+        assert_eq!(space.names, vec!["ucbv.ucbv_const"]);
+
+        let base_p = Parameters::new();
+        let consts = (0..3)
+            .map(|i| {
+                let scenarios = create_scenarios(&base_p, &space.name_value_pairs(&[i]));
+                assert_eq!(scenarios.len(), 1);
+                scenarios[0].ucbv_const
+            })
+            .collect_vec();
+        assert_eq!(consts, vec![0.1, 0.5, 2.0]);
+    }
+}
@@ -12,12 +12,16 @@ macro_rules! define_params {
             // const $defining_type: &[&'static str] = &[$([<$param:upper>]),*];
             const [<$defining_type _PARAMS>]: &[&'static str] = &[$(stringify!($param)),*];
 
-            fn [<parse_ $defining_type:lower _params>](params: &mut Parameters, name: &str, val: &str) -> bool {
+            fn [<parse_ $defining_type:lower _params>](params: &mut Parameters, name: &str, val: &str) -> Result<bool, String> {
                 match name {
-                    $(stringify!($param) => params.$param = val.parse().unwrap(),)*
-                    _ => return false
+                    $(stringify!($param) => {
+                        params.$param = val
+                            .parse()
+                            .map_err(|e| format!("Invalid value '{}' for {}: {}", val, name, e))?
+                    })*
+                    _ => return Ok(false)
                 }
-                true
+                Ok(true)
             }
 
             fn [<hash_ $defining_type:lower _specifiers>](params: &Parameters, hasher: &mut DefaultHasher) {
@@ -48,19 +52,30 @@ macro_rules! define_params {
 }
 
 pub fn parse_parameters(params: &mut Parameters, name: &str, val: &str) {
+    if let Err(e) = try_parse_parameters(params, name, val) {
+        panic!("{}", e);
+    }
+}
+
+pub fn try_parse_parameters(params: &mut Parameters, name: &str, val: &str) -> Result<(), String> {
     let name = name.split('.').last().unwrap();
-    if parse_integer_params(params, name, val)
-        || parse_text_params(params, name, val)
-        || parse_real_params(params, name, val)
+    if parse_integer_params(params, name, val)?
+        || parse_text_params(params, name, val)?
+        || parse_real_params(params, name, val)?
     {
-        return;
+        return Ok(());
     }
+    let invalid =
+        |e: &dyn std::fmt::Display| format!("Invalid value '{}' for {}: {}", val, name, e);
     match name {
-        "thread_limit" => params.thread_limit = val.parse().unwrap(),
-        "print_report" => params.print_report = val.parse().unwrap(),
-        "stats_analysis" => params.stats_analysis = val.parse().unwrap(),
-        _ => panic!("{} is not a valid parameter!", name),
+        "thread_limit" => params.thread_limit = val.parse().map_err(|e| invalid(&e))?,
+        "db_path" => params.db_path = val.to_owned(),
+        "samples_dir" => params.samples_dir = val.to_owned(),
+        "print_report" => params.print_report = val.parse().map_err(|e| invalid(&e))?,
+        "stats_analysis" => params.stats_analysis = val.parse().map_err(|e| invalid(&e))?,
+        _ => return Err(format!("{} is not a valid parameter!", name)),
     }
+    Ok(())
 }
 
 define_params!(
@@ -80,7 +95,8 @@ define_params!(
     ucbv_const,
     ucbd_const,
     klucb_max_cost,
-    repeat_const
+    repeat_const,
+    early_stop_z
 );
 
 macro_rules! define_result_values {
@@ -102,7 +118,9 @@ define_result_values!(
     true_best_cost,
     regret,
     cost_estimation_error,
-    sum_repeated
+    sum_repeated,
+    samples_used,
+    run_time
 );
 
 pub fn create_table_sql() -> String {
@@ -115,6 +133,16 @@ pub fn create_table_sql() -> String {
     )
 }
 
+// SQL type of every column of the results table
+pub fn column_types() -> Vec<(&'static str, &'static str)> {
+    let mut columns = vec![("id", "INTEGER"), ("specifiers_hash", "INTEGER")];
+    columns.extend(INTEGER_PARAMS.iter().map(|p| (*p, "INTEGER")));
+    columns.extend(TEXT_PARAMS.iter().map(|p| (*p, "TEXT")));
+    columns.extend(REAL_PARAMS.iter().map(|p| (*p, "REAL")));
+    columns.extend(RESULT_VALUES.iter().map(|p| (*p, "REAL")));
+    columns
+}
+
 // pub fn select_where_sql() -> String {
 //     let mut sql = "SELECT id FROM results WHERE ".to_owned();
 //     let mut added_any = false;
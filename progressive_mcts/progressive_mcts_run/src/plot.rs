@@ -0,0 +1,285 @@
+use std::{collections::BTreeMap, error::Error, path::Path};
+
+use itertools::Itertools;
+use plotters::{coord::Shift, prelude::*};
+
+use crate::arg_parameters::{parse_name_value_pairs, parse_scenario_filters};
+
+// Mean and standard error of one configuration's runs
+#[derive(Clone, Copy, Debug)]
+struct Summary {
+    mean: f64,
+    std_err: f64,
+}
+
+impl Summary {
+    fn of(vals: &[f64]) -> Self {
+        let n = vals.len() as f64;
+        let mean = vals.iter().sum::<f64>() / n;
+        let variance = if vals.len() > 1 {
+            vals.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.0)
+        } else {
+            0.0
+        };
+        Self {
+            mean,
+            std_err: (variance / n).sqrt(),
+        }
+    }
+}
+
+struct ConfigResults {
+    samples_n: f64,
+    cost: Summary,
+    regret: Summary,
+    samples_used: Summary,
+}
+
+// (chosen_true_cost, regret, samples_used) of a single run
+type RunValues = (f64, f64, f64);
+
+// Results grouped by method (bound_mode-selection_mode) and then samples_n
+fn read_results(
+    conn: &rusqlite::Connection,
+    filters: &[(String, Vec<String>)],
+) -> BTreeMap<String, Vec<ConfigResults>> {
+    let mut sql = "SELECT bound_mode, selection_mode, samples_n, chosen_true_cost, regret,
+        samples_used FROM results"
+        .to_owned();
+    let mut filter_values = Vec::new();
+    for (i, (key, values)) in filters.iter().enumerate() {
+        sql.push_str(if i == 0 { " WHERE " } else { " AND " });
+        sql.push_str(&format!(
+            "{} IN ({})",
+            key,
+            values.iter().map(|_| "?").join(", ")
+        ));
+        filter_values.extend(values.iter().cloned());
+    }
+
+    let mut statement = conn.prepare(&sql).expect("prepare select");
+    let rows = statement
+        .query_map(rusqlite::params_from_iter(filter_values.iter()), |r| {
+            let samples_n: i64 = r.get(2)?;
+            Ok((
+                format!("{}-{}", r.get::<_, String>(0)?, r.get::<_, String>(1)?),
+                samples_n,
+                r.get::<_, f64>(3)?,
+                r.get::<_, f64>(4)?,
+                // older databases don't have samples_used, where all samples were always used
+                r.get::<_, Option<f64>>(5)?.unwrap_or(samples_n as f64),
+            ))
+        })
+        .expect("select")
+        .filter_map(|a| a.ok());
+
+    let mut grouped: BTreeMap<String, BTreeMap<i64, Vec<RunValues>>> = BTreeMap::new();
+    for (method, samples_n, cost, regret, samples_used) in rows {
+        grouped
+            .entry(method)
+            .or_default()
+            .entry(samples_n)
+            .or_default()
+            .push((cost, regret, samples_used));
+    }
+
+    grouped
+        .into_iter()
+        .map(|(method, by_samples_n)| {
+            let configs = by_samples_n
+                .into_iter()
+                .map(|(samples_n, runs)| ConfigResults {
+                    samples_n: samples_n as f64,
+                    cost: Summary::of(&runs.iter().map(|r| r.0).collect_vec()),
+                    regret: Summary::of(&runs.iter().map(|r| r.1).collect_vec()),
+                    samples_used: Summary::of(&runs.iter().map(|r| r.2).collect_vec()),
+                })
+                .collect_vec();
+            (method, configs)
+        })
+        .collect()
+}
+
+fn padded_range(vals: impl Iterator<Item = f64> + Clone) -> std::ops::Range<f64> {
+    let min = vals.clone().fold(f64::INFINITY, f64::min);
+    let max = vals.fold(f64::NEG_INFINITY, f64::max);
+    let pad = ((max - min) * 0.05).max(1e-9);
+    (min - pad)..(max + pad)
+}
+
+// A line per method of the metric's mean (with standard error bars) against samples_n
+fn draw_metric_vs_samples<DB: DrawingBackend>(
+    root: DrawingArea<DB, Shift>,
+    results: &BTreeMap<String, Vec<ConfigResults>>,
+    title: &str,
+    y_label: &str,
+    metric: fn(&ConfigResults) -> Summary,
+) -> Result<(), Box<dyn Error>>
+where
+    DB::ErrorType: 'static,
+{
+    root.fill(&WHITE)?;
+    let all = results.values().flatten();
+    let x_range = padded_range(all.clone().map(|c| c.samples_n));
+    let x_range = x_range.start.max(1.0)..x_range.end;
+    let y_range = padded_range(all.flat_map(|c| {
+        let m = metric(c);
+        [m.mean - m.std_err, m.mean + m.std_err]
+    }));
+
+    let mut chart = ChartBuilder::on(&root)
+        .caption(title, ("sans-serif", 24))
+        .margin(10)
+        .x_label_area_size(40)
+        .y_label_area_size(60)
+        .build_cartesian_2d(x_range.log_scale(), y_range)?;
+    chart
+        .configure_mesh()
+        .x_desc("samples_n")
+        .y_desc(y_label)
+        .draw()?;
+
+    for (i, (method, configs)) in results.iter().enumerate() {
+        let color = Palette99::pick(i).to_rgba();
+        chart
+            .draw_series(LineSeries::new(
+                configs.iter().map(|c| (c.samples_n, metric(c).mean)),
+                color.stroke_width(2),
+            ))?
+            .label(method.as_str())
+            .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], color));
+        chart.draw_series(configs.iter().map(|c| {
+            let m = metric(c);
+            ErrorBar::new_vertical(
+                c.samples_n,
+                m.mean - m.std_err,
+                m.mean,
+                m.mean + m.std_err,
+                color.filled(),
+                6,
+            )
+        }))?;
+    }
+
+    chart
+        .configure_series_labels()
+        .background_style(WHITE.mix(0.8))
+        .border_style(BLACK)
+        .draw()?;
+    root.present()?;
+    Ok(())
+}
+
+// Solution quality against computation: each point is one method at one samples_n
+fn draw_tradeoff<DB: DrawingBackend>(
+    root: DrawingArea<DB, Shift>,
+    results: &BTreeMap<String, Vec<ConfigResults>>,
+) -> Result<(), Box<dyn Error>>
+where
+    DB::ErrorType: 'static,
+{
+    root.fill(&WHITE)?;
+    let all = results.values().flatten();
+    let x_range = padded_range(all.clone().map(|c| c.samples_used.mean));
+    let x_range = x_range.start.max(1.0)..x_range.end;
+    let y_range = padded_range(all.map(|c| c.regret.mean));
+
+    let mut chart = ChartBuilder::on(&root)
+        .caption("Regret vs. samples used", ("sans-serif", 24))
+        .margin(10)
+        .x_label_area_size(40)
+        .y_label_area_size(60)
+        .build_cartesian_2d(x_range.log_scale(), y_range)?;
+    chart
+        .configure_mesh()
+        .x_desc("mean samples used")
+        .y_desc("mean regret")
+        .draw()?;
+
+    for (i, (method, configs)) in results.iter().enumerate() {
+        let color = Palette99::pick(i).to_rgba();
+        chart
+            .draw_series(
+                configs
+                    .iter()
+                    .map(|c| Circle::new((c.samples_used.mean, c.regret.mean), 4, color.filled())),
+            )?
+            .label(method.as_str())
+            .legend(move |(x, y)| Circle::new((x + 10, y), 4, color.filled()));
+    }
+
+    chart
+        .configure_series_labels()
+        .background_style(WHITE.mix(0.8))
+        .border_style(BLACK)
+        .draw()?;
+    root.present()?;
+    Ok(())
+}
+
+// Draws into an SVG or PNG file depending on the path's extension
+macro_rules! draw_figure {
+    ($path:expr, |$root:ident| $draw:expr) => {{
+        const SIZE: (u32, u32) = (900, 600);
+        let path: &Path = &$path;
+        let result = match path.extension().and_then(|e| e.to_str()) {
+            Some("svg") => {
+                let $root = SVGBackend::new(path, SIZE).into_drawing_area();
+                $draw
+            }
+            Some("png") => {
+                let $root = BitMapBackend::new(path, SIZE).into_drawing_area();
+                $draw
+            }
+            _ => panic!("Unknown plot format for {}, use svg or png", path.display()),
+        };
+        result.unwrap_or_else(|e| panic!("Could not draw {}: {}", path.display(), e));
+        println!("Wrote {}", path.display());
+    }};
+}
+
+// plot [db_path results.db ::] [out_dir plots ::] [format svg|png ::] [where key=value* ::]
+pub fn run_plot(args: &[String]) {
+    let mut db_path = "results.db".to_owned();
+    let mut out_dir = "plots".to_owned();
+    let mut format = "svg".to_owned();
+    let mut filters = Vec::new();
+    for (name, vals) in parse_name_value_pairs(args.iter().cloned()) {
+        match name.as_str() {
+            "db_path" => db_path = vals[0].clone(),
+            "out_dir" => out_dir = vals[0].clone(),
+            "format" => format = vals[0].clone(),
+            "where" => filters = parse_scenario_filters(&vals),
+            _ => panic!(
+                "{} is not a valid plot option (db_path, out_dir, format, where)",
+                name
+            ),
+        }
+    }
+
+    let conn = rusqlite::Connection::open(&db_path).unwrap();
+    let results = read_results(&conn, &filters);
+    if results.is_empty() {
+        eprintln!("No results to plot in {}", db_path);
+        return;
+    }
+
+    std::fs::create_dir_all(&out_dir).unwrap();
+    let out_dir = Path::new(&out_dir);
+
+    draw_figure!(out_dir.join(format!("cost.{}", format)), |root| {
+        draw_metric_vs_samples(
+            root,
+            &results,
+            "True cost of the chosen action",
+            "cost",
+            |c| c.cost,
+        )
+    });
+    draw_figure!(out_dir.join(format!("regret.{}", format)), |root| {
+        draw_metric_vs_samples(root, &results, "Regret", "regret", |c| c.regret)
+    });
+    draw_figure!(out_dir.join(format!("tradeoff.{}", format)), |root| {
+        draw_tradeoff(root, &results)
+    });
+}
@@ -0,0 +1,93 @@
+use std::{collections::BTreeMap, sync::Mutex, time::Duration};
+
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use itertools::Itertools;
+
+use crate::arg_parameters::Parameters;
+
+pub fn method_name(params: &Parameters) -> String {
+    format!("{}-{}", params.bound_mode, params.selection_mode)
+}
+
+// Progress bar (on stderr) with the ETA, scenarios/second, and completion counts per method.
+// When quiet, nothing is drawn and only the final summary line is printed.
+pub struct Progress {
+    bar: ProgressBar,
+    quiet: bool,
+    // method name -> (completed, total)
+    methods: Mutex<BTreeMap<String, (usize, usize)>>,
+}
+
+impl Progress {
+    pub fn new(scenarios: &[Parameters], quiet: bool) -> Self {
+        let mut methods = BTreeMap::new();
+        for scenario in scenarios.iter() {
+            methods.entry(method_name(scenario)).or_insert((0, 0)).1 += 1;
+        }
+
+        let bar = ProgressBar::with_draw_target(
+            Some(scenarios.len() as u64),
+            if quiet {
+                ProgressDrawTarget::hidden()
+            } else {
+                ProgressDrawTarget::stderr()
+            },
+        );
+        bar.set_style(
+            ProgressStyle::with_template(
+                "{elapsed_precise} [{wide_bar}] {pos}/{len} {per_sec} ETA {eta_precise}\n{msg}",
+            )
+            .unwrap()
+            .progress_chars("=> "),
+        );
+        bar.enable_steady_tick(Duration::from_millis(500));
+
+        let progress = Self {
+            bar,
+            quiet,
+            methods: Mutex::new(methods),
+        };
+        progress.update_message(&progress.methods.lock().unwrap());
+        progress
+    }
+
+    fn update_message(&self, methods: &BTreeMap<String, (usize, usize)>) {
+        let message = methods
+            .iter()
+            .map(|(name, (completed, total))| format!("{} {}/{}", name, completed, total))
+            .join(", ");
+        self.bar.set_message(message);
+    }
+
+    pub fn is_quiet(&self) -> bool {
+        self.quiet
+    }
+
+    // Prints a line to stdout without tearing the progress bar
+    pub fn println(&self, line: &str) {
+        if !self.quiet {
+            self.bar.suspend(|| println!("{}", line));
+        }
+    }
+
+    pub fn complete(&self, scenario: &Parameters) {
+        let mut methods = self.methods.lock().unwrap();
+        if let Some(counts) = methods.get_mut(&method_name(scenario)) {
+            counts.0 += 1;
+        }
+        self.update_message(&methods);
+        self.bar.inc(1);
+    }
+
+    pub fn finish(&self) {
+        let n = self.bar.position();
+        self.bar.finish_and_clear();
+        let elapsed = self.bar.elapsed().as_secs_f64();
+        eprintln!(
+            "Completed {} scenarios in {:.1}s ({:.2} scenarios/s)",
+            n,
+            elapsed,
+            n as f64 / elapsed.max(1e-9)
+        );
+    }
+}
@@ -0,0 +1,93 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+const MAGIC: &[u8; 4] = b"PMRS";
+const VERSION: u8 = 1;
+
+// The final cost of every trial through each root child, for looking at the shape of the
+// cost distributions (and their heavy tails) rather than only their means.
+// One little-endian file per scenario, named by its specifiers_hash:
+// "PMRS", version u8, n_children u32, then per child: policy u32, n u32, n costs as f32.
+pub fn samples_path(samples_dir: &str, specifiers_hash: i64) -> PathBuf {
+    Path::new(samples_dir).join(format!("{:016x}.samples", specifiers_hash))
+}
+
+// Written to a temporary file that is then renamed into place, so an interrupted sweep
+// never leaves a truncated file behind, and resuming it writes the remaining scenarios'.
+pub fn write_root_samples(path: &Path, children: &[(u32, Vec<f64>)]) {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(MAGIC);
+    bytes.push(VERSION);
+    bytes.extend_from_slice(&(children.len() as u32).to_le_bytes());
+    for (policy, costs) in children.iter() {
+        bytes.extend_from_slice(&policy.to_le_bytes());
+        bytes.extend_from_slice(&(costs.len() as u32).to_le_bytes());
+        for cost in costs.iter() {
+            bytes.extend_from_slice(&(*cost as f32).to_le_bytes());
+        }
+    }
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .unwrap_or_else(|e| panic!("Could not create {}: {}", dir.display(), e));
+    }
+    let tmp_path = path.with_extension("samples.tmp");
+    let mut file = std::fs::File::create(&tmp_path)
+        .unwrap_or_else(|e| panic!("Could not create {}: {}", tmp_path.display(), e));
+    file.write_all(&bytes)
+        .unwrap_or_else(|e| panic!("Could not write {}: {}", tmp_path.display(), e));
+    std::fs::rename(&tmp_path, path)
+        .unwrap_or_else(|e| panic!("Could not rename to {}: {}", path.display(), e));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+
+    use super::*;
+
+    // the files are for analysis outside of this crate, so reading them is only for the round trip
+    fn read_root_samples(path: &Path) -> Vec<(u32, Vec<f32>)> {
+        let bytes = std::fs::read(path)
+            .unwrap_or_else(|e| panic!("Could not read {}: {}", path.display(), e));
+        assert!(
+            bytes.len() >= 9 && &bytes[0..4] == MAGIC && bytes[4] == VERSION,
+            "{} is not a version {} samples file",
+            path.display(),
+            VERSION
+        );
+
+        let mut pos = 5;
+        let mut next_u32 = |bytes: &[u8]| {
+            let v = u32::from_le_bytes(bytes[pos..pos + 4].try_into().unwrap());
+            pos += 4;
+            v
+        };
+        let n_children = next_u32(&bytes);
+        (0..n_children)
+            .map(|_| {
+                let policy = next_u32(&bytes);
+                let n = next_u32(&bytes);
+                let costs = (0..n).map(|_| f32::from_bits(next_u32(&bytes))).collect();
+                (policy, costs)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_root_samples_round_trip() {
+        let dir = std::env::temp_dir().join(format!("raw_samples_test_{}", std::process::id()));
+        let path = samples_path(dir.to_str().unwrap(), -42);
+        let children = vec![(0, vec![1.5, 250.0, 0.25]), (1, vec![]), (2, vec![7.0])];
+        write_root_samples(&path, &children);
+        let read = read_root_samples(&path);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            read,
+            vec![(0, vec![1.5, 250.0, 0.25]), (1, vec![]), (2, vec![7.0])]
+        );
+    }
+}
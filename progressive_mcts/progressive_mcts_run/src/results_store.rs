@@ -0,0 +1,659 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    time::Duration,
+};
+
+use itertools::Itertools;
+use rusqlite::types::ValueRef;
+
+use crate::{
+    arg_parameters::Parameters,
+    parameters_sql::{
+        column_types, create_table_sql, insert_sql, make_insert_specifiers, make_select_specifiers,
+        parse_parameters, specifier_params, specifiers_hash,
+    },
+    RunResults,
+};
+
+const MEAN_RUN_TIMES_SQL: &str = "SELECT samples_n, search_depth, n_actions, AVG(run_time)
+    FROM results WHERE run_time IS NOT NULL GROUP BY samples_n, search_depth, n_actions";
+
+// Bump when existing columns change meaning (or specifiers_hash changes),
+// which can't be handled by simply adding the new columns.
+const SCHEMA_VERSION: i64 = 1;
+
+// Where the results of each scenario run are kept.
+// Chosen by db_path: a postgres:// (or postgresql://) connection string uses Postgres,
+// so several sweep processes can write at once, and anything else is a SQLite file.
+pub trait ResultsStore: Send {
+    fn completed_hashes(&mut self) -> BTreeSet<i64>;
+    fn cached_regret(&mut self, specifiers_hash: i64) -> Option<f64>;
+    // the parameters of a stored scenario, for running it again
+    fn stored_parameters(&mut self, specifiers_hash: i64) -> Option<Parameters>;
+    // mean run_time by (samples_n, search_depth, n_actions), over the results that recorded it
+    fn mean_run_times(&mut self) -> BTreeMap<(i64, i64, i64), f64>;
+    // inserts all of the results in one transaction
+    fn insert_results(&mut self, results: &[(Parameters, RunResults)]);
+    fn delete_results(&mut self, specifiers_hashes: &[i64]);
+}
+
+pub fn open_results_store(db_path: &str) -> Box<dyn ResultsStore> {
+    if db_path.starts_with("postgres://") || db_path.starts_with("postgresql://") {
+        Box::new(PostgresStore::open(db_path))
+    } else {
+        Box::new(SqliteStore::open(db_path))
+    }
+}
+
+fn check_schema_version(db_path: &str, version: Option<i64>) {
+    if let Some(version) = version {
+        if version > SCHEMA_VERSION {
+            panic!(
+                "{} has schema version {}, but this build only understands up to version {}",
+                db_path, version, SCHEMA_VERSION
+            );
+        }
+    }
+}
+
+// Compares an existing results table to the columns this build writes, returning those to add.
+// Columns that changed type or no longer exist are a hard error,
+// since their rows can't be matched against the current parameters.
+fn missing_columns(
+    db_path: &str,
+    existing: &[(String, String)],
+    db_type: fn(&str) -> &'static str,
+) -> Vec<(&'static str, &'static str)> {
+    let expected = column_types()
+        .into_iter()
+        .filter(|(column, _)| *column != "id")
+        .collect_vec();
+    for (name, existing_type) in existing.iter() {
+        match expected.iter().find(|(column, _)| column == name) {
+            Some((_, sql_type)) if !db_type(sql_type).eq_ignore_ascii_case(existing_type) => {
+                panic!(
+                    "{}: column {} has type {} but {} is expected; use a new db_path",
+                    db_path,
+                    name,
+                    existing_type,
+                    db_type(sql_type)
+                )
+            }
+            Some(_) => (),
+            None => panic!(
+                "{}: column {} is no longer a parameter or result; use a new db_path",
+                db_path, name
+            ),
+        }
+    }
+    expected
+        .into_iter()
+        .filter(|(column, _)| !existing.iter().any(|(name, _)| name == column))
+        .collect()
+}
+
+// New parameter columns get their default value, which is what the older runs implicitly used.
+// New result columns are left NULL.
+fn default_literal(name: &str, sql_type: &str) -> String {
+    let defaults = make_select_specifiers(&Parameters::new());
+    match defaults.iter().find(|(spec, _)| &spec[1..] == name) {
+        Some((_, val)) if sql_type == "TEXT" => format!("'{}'", val),
+        Some((_, val)) if sql_type == "INTEGER" && val == "true" => "1".to_owned(),
+        Some((_, val)) if sql_type == "INTEGER" && val == "false" => "0".to_owned(),
+        Some((_, val)) => val.to_owned(),
+        None => "NULL".to_owned(),
+    }
+}
+
+// Adding a parameter changes every specifiers_hash, so recompute them from the stored parameters
+// to keep the old results matching their scenarios.
+fn rehash(param_values: &[(&'static str, String)]) -> i64 {
+    specifiers_hash(&params_from_values(param_values))
+}
+
+// The parameters with the stored values of the param_columns(), as read back as text
+fn params_from_values(param_values: &[(&'static str, String)]) -> Parameters {
+    let mut params = Parameters::new();
+    let defaults = make_select_specifiers(&params);
+    for (name, val) in param_values.iter().filter(|(_, val)| !val.is_empty()) {
+        let is_bool = defaults
+            .iter()
+            .any(|(spec, v)| &spec[1..] == *name && (v == "true" || v == "false"));
+        let val = match val.as_str() {
+            "1" if is_bool => "true",
+            "0" if is_bool => "false",
+            val => val,
+        };
+        parse_parameters(&mut params, name, val);
+    }
+    params
+}
+
+fn param_columns() -> Vec<&'static str> {
+    make_select_specifiers(&Parameters::new())
+        .into_iter()
+        .map(|(spec, _)| &spec[1..])
+        .collect()
+}
+
+pub struct SqliteStore {
+    conn: rusqlite::Connection,
+}
+
+impl SqliteStore {
+    pub fn open(path: &str) -> Self {
+        let mut conn = rusqlite::Connection::open(path).unwrap();
+        // wait on other processes' transactions instead of failing immediately
+        conn.busy_timeout(Duration::from_secs(60)).unwrap();
+        // create if doesn't exist (lazy way, ignoring an error)
+        let _ = conn.execute(&create_table_sql(), []);
+        Self::migrate(&mut conn, path);
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS results_specifiers_hash ON results (specifiers_hash)",
+            [],
+        )
+        .expect("create index");
+        Self { conn }
+    }
+
+    fn migrate(conn: &mut rusqlite::Connection, path: &str) {
+        let transaction = conn
+            .transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)
+            .expect("transaction");
+        transaction
+            .execute(
+                "CREATE TABLE IF NOT EXISTS schema_version (version INTEGER)",
+                [],
+            )
+            .expect("create schema_version");
+        let version: Option<i64> = transaction
+            .query_row("SELECT version FROM schema_version", [], |r| r.get(0))
+            .ok();
+        check_schema_version(path, version);
+
+        let existing = transaction
+            .prepare("PRAGMA table_info(results)")
+            .expect("prepare table_info")
+            .query_map([], |r| Ok((r.get::<_, String>(1)?, r.get::<_, String>(2)?)))
+            .unwrap()
+            .filter_map(|a| a.ok())
+            .filter(|(name, _)| name != "id")
+            .collect_vec();
+        let missing = missing_columns(path, &existing, |sql_type| match sql_type {
+            "INTEGER" => "INTEGER",
+            "REAL" => "REAL",
+            _ => "TEXT",
+        });
+
+        if !missing.is_empty() {
+            for (name, sql_type) in missing.iter() {
+                transaction
+                    .execute(
+                        &format!(
+                            "ALTER TABLE results ADD COLUMN {} {} DEFAULT {}",
+                            name,
+                            sql_type,
+                            default_literal(name, sql_type)
+                        ),
+                        [],
+                    )
+                    .expect("add column");
+            }
+
+            let params = param_columns();
+            let new_hashes = transaction
+                .prepare(&format!("SELECT id, {} FROM results", params.join(", ")))
+                .expect("prepare select params")
+                .query_map([], |r| {
+                    let id: i64 = r.get(0)?;
+                    let values = params
+                        .iter()
+                        .enumerate()
+                        .map(|(i, name)| {
+                            let val = match r.get_ref(i + 1)? {
+                                ValueRef::Integer(v) => v.to_string(),
+                                ValueRef::Real(v) => v.to_string(),
+                                ValueRef::Text(v) => String::from_utf8_lossy(v).into_owned(),
+                                _ => String::new(),
+                            };
+                            Ok((*name, val))
+                        })
+                        .collect::<rusqlite::Result<Vec<_>>>()?;
+                    Ok((id, rehash(&values)))
+                })
+                .unwrap()
+                .collect::<rusqlite::Result<Vec<_>>>()
+                .expect("read params");
+            for (id, hash) in new_hashes.iter() {
+                transaction
+                    .execute(
+                        "UPDATE results SET specifiers_hash = ?1 WHERE id = ?2",
+                        [hash, id],
+                    )
+                    .expect("update specifiers_hash");
+            }
+
+            eprintln!(
+                "Migrated {}: added columns {}",
+                path,
+                missing.iter().map(|(name, _)| name).join(", ")
+            );
+        }
+
+        if version.is_none() {
+            transaction
+                .execute(
+                    "INSERT INTO schema_version (version) VALUES (?1)",
+                    [SCHEMA_VERSION],
+                )
+                .expect("insert schema_version");
+        } else {
+            transaction
+                .execute("UPDATE schema_version SET version = ?1", [SCHEMA_VERSION])
+                .expect("update schema_version");
+        }
+        transaction.commit().expect("commit");
+    }
+}
+
+impl ResultsStore for SqliteStore {
+    fn completed_hashes(&mut self) -> BTreeSet<i64> {
+        self.conn
+            .prepare("SELECT specifiers_hash FROM results;")
+            .expect("prepare select specifiers_hash")
+            .query_map([], |r| r.get::<_, i64>(0))
+            .unwrap()
+            .filter_map(|a| a.ok())
+            .collect()
+    }
+
+    fn cached_regret(&mut self, specifiers_hash: i64) -> Option<f64> {
+        self.conn
+            .query_row(
+                "SELECT regret FROM results WHERE specifiers_hash = ?1 LIMIT 1",
+                [specifiers_hash],
+                |r| r.get(0),
+            )
+            .ok()
+    }
+
+    fn stored_parameters(&mut self, specifiers_hash: i64) -> Option<Parameters> {
+        let params = param_columns();
+        self.conn
+            .query_row(
+                &format!(
+                    "SELECT {} FROM results WHERE specifiers_hash = ?1 LIMIT 1",
+                    params.join(", ")
+                ),
+                [specifiers_hash],
+                |r| {
+                    params
+                        .iter()
+                        .enumerate()
+                        .map(|(i, name)| {
+                            let val = match r.get_ref(i)? {
+                                ValueRef::Integer(v) => v.to_string(),
+                                ValueRef::Real(v) => v.to_string(),
+                                ValueRef::Text(v) => String::from_utf8_lossy(v).into_owned(),
+                                _ => String::new(),
+                            };
+                            Ok((*name, val))
+                        })
+                        .collect::<rusqlite::Result<Vec<_>>>()
+                },
+            )
+            .ok()
+            .map(|values| params_from_values(&values))
+    }
+
+    fn mean_run_times(&mut self) -> BTreeMap<(i64, i64, i64), f64> {
+        let mut statement = self
+            .conn
+            .prepare(MEAN_RUN_TIMES_SQL)
+            .expect("prepare select run_time");
+        let run_times = statement
+            .query_map([], |r| Ok(((r.get(0)?, r.get(1)?, r.get(2)?), r.get(3)?)))
+            .unwrap()
+            .filter_map(|a| a.ok())
+            .collect();
+        run_times
+    }
+
+    fn insert_results(&mut self, results: &[(Parameters, RunResults)]) {
+        let transaction = self.conn.transaction().expect("transaction");
+        {
+            let mut insert_statement = transaction.prepare(&insert_sql()).expect("prepare insert");
+            for (scenario, res) in results.iter() {
+                let insert_specifiers = make_insert_specifiers(scenario, res);
+                insert_statement
+                    .insert(specifier_params(&insert_specifiers).as_slice())
+                    .expect("insert");
+            }
+        }
+        transaction.commit().expect("commit");
+    }
+
+    fn delete_results(&mut self, specifiers_hashes: &[i64]) {
+        let transaction = self.conn.transaction().expect("transaction");
+        {
+            let mut delete_statement = transaction
+                .prepare("DELETE FROM results WHERE specifiers_hash = ?1")
+                .expect("prepare delete");
+            for hash in specifiers_hashes.iter() {
+                delete_statement.execute([hash]).expect("delete");
+            }
+        }
+        transaction.commit().expect("commit");
+    }
+}
+
+pub struct PostgresStore {
+    client: postgres::Client,
+    // every column but id, in the order of the insert statement's $n placeholders
+    columns: Vec<(&'static str, &'static str)>,
+    insert_sql: String,
+}
+
+impl PostgresStore {
+    pub fn open(url: &str) -> Self {
+        let mut client = postgres::Client::connect(url, postgres::NoTls)
+            .unwrap_or_else(|e| panic!("Could not connect to {}: {}", url, e));
+
+        let columns = column_types()
+            .into_iter()
+            .filter(|(name, _)| *name != "id")
+            .collect_vec();
+        let column_defs = columns
+            .iter()
+            .map(|(name, sql_type)| format!("{} {}", name, postgres_type(sql_type)))
+            .join(", ");
+        client
+            .batch_execute(&format!(
+                "CREATE TABLE IF NOT EXISTS results (id BIGSERIAL PRIMARY KEY, {});
+                 CREATE INDEX IF NOT EXISTS results_specifiers_hash ON results (specifiers_hash);",
+                column_defs
+            ))
+            .expect("create table");
+
+        Self::migrate(&mut client, url);
+
+        let insert_sql = format!(
+            "INSERT INTO results ({}) VALUES ({})",
+            columns.iter().map(|(name, _)| name).join(", "),
+            (1..=columns.len()).map(|i| format!("${}", i)).join(", ")
+        );
+
+        Self {
+            client,
+            columns,
+            insert_sql,
+        }
+    }
+}
+
+impl PostgresStore {
+    fn migrate(client: &mut postgres::Client, url: &str) {
+        let mut transaction = client.transaction().expect("transaction");
+        // serialize concurrent migrations from several sweep processes
+        transaction
+            .batch_execute(
+                "CREATE TABLE IF NOT EXISTS schema_version (version BIGINT);
+                 LOCK TABLE schema_version IN EXCLUSIVE MODE;",
+            )
+            .expect("create schema_version");
+        let version: Option<i64> = transaction
+            .query_opt("SELECT version FROM schema_version", &[])
+            .expect("select version")
+            .map(|row| row.get(0));
+        check_schema_version(url, version);
+
+        let existing = transaction
+            .query(
+                "SELECT column_name::TEXT, data_type::TEXT FROM information_schema.columns
+                 WHERE table_name = 'results' AND table_schema = current_schema()",
+                &[],
+            )
+            .expect("select columns")
+            .iter()
+            .map(|row| (row.get::<_, String>(0), row.get::<_, String>(1)))
+            .filter(|(name, _)| name != "id")
+            .collect_vec();
+        let missing = missing_columns(url, &existing, postgres_type);
+
+        if !missing.is_empty() {
+            for (name, sql_type) in missing.iter() {
+                transaction
+                    .batch_execute(&format!(
+                        "ALTER TABLE results ADD COLUMN {} {} DEFAULT {}",
+                        name,
+                        postgres_type(sql_type),
+                        default_literal(name, sql_type)
+                    ))
+                    .expect("add column");
+            }
+
+            let params = param_columns();
+            let select_sql = format!(
+                "SELECT id, {} FROM results",
+                params
+                    .iter()
+                    .map(|name| format!("{}::TEXT", name))
+                    .join(", ")
+            );
+            let new_hashes = transaction
+                .query(select_sql.as_str(), &[])
+                .expect("select params")
+                .iter()
+                .map(|row| {
+                    let values = params
+                        .iter()
+                        .enumerate()
+                        .map(|(i, name)| {
+                            (
+                                *name,
+                                row.get::<_, Option<String>>(i + 1).unwrap_or_default(),
+                            )
+                        })
+                        .collect_vec();
+                    (row.get::<_, i64>(0), rehash(&values))
+                })
+                .collect_vec();
+            for (id, hash) in new_hashes.iter() {
+                transaction
+                    .execute(
+                        "UPDATE results SET specifiers_hash = $1 WHERE id = $2",
+                        &[hash, id],
+                    )
+                    .expect("update specifiers_hash");
+            }
+
+            eprintln!(
+                "Migrated results: added columns {}",
+                missing.iter().map(|(name, _)| name).join(", ")
+            );
+        }
+
+        if version.is_none() {
+            transaction
+                .execute(
+                    "INSERT INTO schema_version (version) VALUES ($1)",
+                    &[&SCHEMA_VERSION],
+                )
+                .expect("insert schema_version");
+        } else {
+            transaction
+                .execute("UPDATE schema_version SET version = $1", &[&SCHEMA_VERSION])
+                .expect("update schema_version");
+        }
+        transaction.commit().expect("commit");
+    }
+}
+
+fn postgres_type(sql_type: &str) -> &'static str {
+    match sql_type {
+        "INTEGER" => "BIGINT",
+        "REAL" => "DOUBLE PRECISION",
+        _ => "TEXT",
+    }
+}
+
+// Postgres won't coerce text into numeric columns the way SQLite does,
+// so convert the specifier strings to the column types first (booleans become 0/1).
+fn postgres_value(sql_type: &str, val: &str) -> Box<dyn postgres::types::ToSql + Sync> {
+    match sql_type {
+        "INTEGER" => Box::new(match val {
+            "true" => 1,
+            "false" => 0,
+            _ => val.parse::<i64>().unwrap(),
+        }),
+        "REAL" => Box::new(val.parse::<f64>().unwrap()),
+        _ => Box::new(val.to_owned()),
+    }
+}
+
+impl ResultsStore for PostgresStore {
+    fn completed_hashes(&mut self) -> BTreeSet<i64> {
+        self.client
+            .query("SELECT specifiers_hash FROM results", &[])
+            .expect("select specifiers_hash")
+            .iter()
+            .filter_map(|row| row.get::<_, Option<i64>>(0))
+            .collect()
+    }
+
+    fn cached_regret(&mut self, specifiers_hash: i64) -> Option<f64> {
+        self.client
+            .query_opt(
+                "SELECT regret FROM results WHERE specifiers_hash = $1 LIMIT 1",
+                &[&specifiers_hash],
+            )
+            .expect("select regret")
+            .and_then(|row| row.get(0))
+    }
+
+    fn stored_parameters(&mut self, specifiers_hash: i64) -> Option<Parameters> {
+        let params = param_columns();
+        let select_sql = format!(
+            "SELECT {} FROM results WHERE specifiers_hash = $1 LIMIT 1",
+            params
+                .iter()
+                .map(|name| format!("{}::TEXT", name))
+                .join(", ")
+        );
+        self.client
+            .query_opt(select_sql.as_str(), &[&specifiers_hash])
+            .expect("select params")
+            .map(|row| {
+                let values = params
+                    .iter()
+                    .enumerate()
+                    .map(|(i, name)| (*name, row.get::<_, Option<String>>(i).unwrap_or_default()))
+                    .collect_vec();
+                params_from_values(&values)
+            })
+    }
+
+    fn mean_run_times(&mut self) -> BTreeMap<(i64, i64, i64), f64> {
+        self.client
+            .query(MEAN_RUN_TIMES_SQL, &[])
+            .expect("select run_time")
+            .iter()
+            .map(|row| ((row.get(0), row.get(1), row.get(2)), row.get(3)))
+            .collect()
+    }
+
+    fn insert_results(&mut self, results: &[(Parameters, RunResults)]) {
+        let mut transaction = self.client.transaction().expect("transaction");
+        let insert_statement = transaction
+            .prepare(&self.insert_sql)
+            .expect("prepare insert");
+        for (scenario, res) in results.iter() {
+            let insert_specifiers = make_insert_specifiers(scenario, res);
+            let values = self
+                .columns
+                .iter()
+                .map(|(name, sql_type)| {
+                    let (_, val) = insert_specifiers
+                        .iter()
+                        .find(|(spec, _)| &spec[1..] == *name)
+                        .unwrap_or_else(|| panic!("No value for column {}", name));
+                    postgres_value(sql_type, val)
+                })
+                .collect_vec();
+            let params = values
+                .iter()
+                .map(|v| v.as_ref() as &(dyn postgres::types::ToSql + Sync))
+                .collect_vec();
+            transaction
+                .execute(&insert_statement, &params)
+                .expect("insert");
+        }
+        transaction.commit().expect("commit");
+    }
+
+    fn delete_results(&mut self, specifiers_hashes: &[i64]) {
+        self.client
+            .execute(
+                "DELETE FROM results WHERE specifiers_hash = ANY($1)",
+                &[&specifiers_hashes],
+            )
+            .expect("delete");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sqlite_migration_adds_columns_and_rehashes() {
+        let path = std::env::temp_dir().join(format!("migration_test_{}.db", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+
+        // a results table from before early_stop_z and samples_used existed
+        {
+            let conn = rusqlite::Connection::open(path).unwrap();
+            conn.execute_batch(
+                "CREATE TABLE results (id INTEGER PRIMARY KEY, specifiers_hash INTEGER,
+                    rng_seed INTEGER, search_depth INTEGER, n_actions INTEGER, samples_n INTEGER,
+                    most_visited_best_cost_consistency INTEGER,
+                    bound_mode TEXT, final_choice_mode TEXT, selection_mode TEXT,
+                    ucb_const REAL, ucbv_const REAL, ucbd_const REAL, klucb_max_cost REAL,
+                    repeat_const REAL,
+                    steps_taken REAL, chosen_cost REAL, chosen_true_cost REAL, true_best_cost REAL,
+                    regret REAL, cost_estimation_error REAL, sum_repeated REAL);
+                 INSERT INTO results (specifiers_hash, rng_seed, search_depth, n_actions, samples_n,
+                    most_visited_best_cost_consistency, bound_mode, final_choice_mode,
+                    selection_mode, ucb_const, ucbv_const, ucbd_const, klucb_max_cost,
+                    repeat_const, regret)
+                 VALUES (123, 7, 4, 5, 64, 'true', 'marginal', 'same', 'klucb',
+                    -0.1, 0.001, 0.1, 4700, -1, 2.5);",
+            )
+            .unwrap();
+        }
+
+        let mut store = SqliteStore::open(path);
+        let mut params = Parameters::new();
+        params.rng_seed = 7;
+        let hash = specifiers_hash(&params);
+        assert!(store.completed_hashes().contains(&hash));
+        assert_eq!(store.cached_regret(hash), Some(2.5));
+        let stored = store.stored_parameters(hash).unwrap();
+        assert_eq!(specifiers_hash(&stored), hash);
+
+        let early_stop_z: f64 = store
+            .conn
+            .query_row("SELECT early_stop_z FROM results", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(early_stop_z, 0.0);
+        let version: i64 = store
+            .conn
+            .query_row("SELECT version FROM schema_version", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(version, SCHEMA_VERSION);
+
+        drop(store);
+        std::fs::remove_file(path).unwrap();
+    }
+}
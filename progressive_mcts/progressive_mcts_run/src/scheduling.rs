@@ -0,0 +1,129 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+use itertools::Itertools;
+
+use crate::arg_parameters::Parameters;
+
+fn run_time_key(scenario: &Parameters) -> (i64, i64, i64) {
+    (
+        scenario.samples_n as i64,
+        scenario.search_depth as i64,
+        scenario.n_actions as i64,
+    )
+}
+
+// Rollout steps in one run, which is what the run time mostly scales with
+fn work(scenario: &Parameters) -> f64 {
+    scenario.samples_n as f64 * scenario.search_depth as f64
+}
+
+// Estimated seconds for each scenario: the mean run_time of past runs with the same
+// samples_n, search_depth, and n_actions, or otherwise its work at the past runs' seconds per step.
+// Without any past runs, only the relative order of the estimates is meaningful.
+pub fn estimate_durations(
+    scenarios: &[Parameters],
+    past_run_times: &BTreeMap<(i64, i64, i64), f64>,
+) -> Vec<f64> {
+    let secs_per_step = if past_run_times.is_empty() {
+        1.0
+    } else {
+        past_run_times
+            .iter()
+            .map(|((samples_n, search_depth, _), run_time)| {
+                run_time / (*samples_n as f64 * *search_depth as f64).max(1.0)
+            })
+            .sum::<f64>()
+            / past_run_times.len() as f64
+    };
+
+    scenarios
+        .iter()
+        .map(|scenario| {
+            past_run_times
+                .get(&run_time_key(scenario))
+                .copied()
+                .unwrap_or_else(|| work(scenario) * secs_per_step)
+        })
+        .collect()
+}
+
+// Runs every scenario on the rayon thread pool, longest (estimated) first,
+// so the long runs don't end up as stragglers after everything else has finished.
+// With long_threads > 0, only that many threads take the longest remaining scenarios
+// and the others take the shortest, which keeps results of the quick scenarios coming in early.
+// Once stop is set, no more scenarios are started, and those never started are returned.
+pub fn run_longest_first<F>(
+    scenarios: Vec<Parameters>,
+    durations: &[f64],
+    long_threads: usize,
+    stop: &AtomicBool,
+    run: F,
+) -> Vec<Parameters>
+where
+    F: Fn(&Parameters) + Sync,
+{
+    let queue = Mutex::new(
+        scenarios
+            .into_iter()
+            .zip(durations.iter())
+            .sorted_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap())
+            .map(|(scenario, _)| scenario)
+            .collect::<VecDeque<_>>(),
+    );
+
+    let n_workers = rayon::current_num_threads();
+    rayon::scope(|s| {
+        for worker in 0..n_workers {
+            let queue = &queue;
+            let run = &run;
+            s.spawn(move |_| loop {
+                if stop.load(Ordering::SeqCst) {
+                    break;
+                }
+                let takes_longest = long_threads == 0 || worker < long_threads;
+                let next = if takes_longest {
+                    queue.lock().unwrap().pop_front()
+                } else {
+                    queue.lock().unwrap().pop_back()
+                };
+                match next {
+                    Some(scenario) => run(&scenario),
+                    None => break,
+                }
+            });
+        }
+    });
+
+    queue.into_inner().unwrap().into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate_durations() {
+        let scenario = |samples_n| {
+            let mut p = Parameters::new();
+            p.samples_n = samples_n;
+            p
+        };
+        let scenarios = vec![scenario(8), scenario(64), scenario(1024)];
+
+        let estimates = estimate_durations(&scenarios, &BTreeMap::new());
+        assert!(estimates[0] < estimates[1] && estimates[1] < estimates[2]);
+
+        // a measured configuration is used as-is, and sets the scale for the others
+        let past = vec![((64, 4, 5), 2.0)].into_iter().collect();
+        let estimates = estimate_durations(&scenarios, &past);
+        assert_eq!(estimates[1], 2.0);
+        assert_eq!(estimates[0], 0.25);
+        assert_eq!(estimates[2], 32.0);
+    }
+}
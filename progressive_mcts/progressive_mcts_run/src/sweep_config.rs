@@ -0,0 +1,220 @@
+use std::path::Path;
+
+use toml::Value;
+
+// A sweep file holds the same information as the `name val val :: name val` command line:
+//
+// thread_limit = 8
+// db_path = "results.db"
+//
+// [parameters]
+// rng_seed = "0-511"
+// samples_n = [8, 16, 32, 64]
+//
+// [methods.marginal_klucb]
+// bound_mode = "marginal"
+// selection_mode = "klucb"
+//
+// Each method preset is expanded separately against the shared parameter grid,
+// and its own values are applied first so that prefixed names like "klucb.ucb_const"
+// see the right selection_mode.
+//
+// A .yaml or .yml file is read as YAML with the same layout, and anything else as TOML.
+pub struct SweepConfig {
+    pub settings: Pairs,
+    pub parameters: Pairs,
+    pub methods: Vec<(String, Pairs)>,
+}
+
+// names with their values, as on the command line
+type Pairs = Vec<(String, Vec<String>)>;
+
+fn value_to_strings(name: &str, value: &Value) -> Vec<String> {
+    match value {
+        Value::String(s) => vec![s.to_owned()],
+        Value::Integer(i) => vec![i.to_string()],
+        Value::Float(f) => vec![f.to_string()],
+        Value::Boolean(b) => vec![b.to_string()],
+        Value::Array(values) => values
+            .iter()
+            .flat_map(|v| value_to_strings(name, v))
+            .collect(),
+        _ => panic!("Sweep file value for {} must be a scalar or an array", name),
+    }
+}
+
+fn table_to_pairs(table: &toml::value::Table) -> Pairs {
+    table
+        .iter()
+        .map(|(name, value)| (name.to_owned(), value_to_strings(name, value)))
+        .collect()
+}
+
+impl SweepConfig {
+    pub fn load(path: &str) -> Self {
+        let contents = std::fs::read_to_string(path)
+            .unwrap_or_else(|e| panic!("Could not read sweep file {}: {}", path, e));
+        let extension = Path::new(path).extension().and_then(|e| e.to_str());
+        let root: toml::value::Table = match extension {
+            Some("yaml") | Some("yml") => serde_yaml::from_str(&contents)
+                .unwrap_or_else(|e| panic!("Could not parse sweep file {}: {}", path, e)),
+            _ => toml::from_str(&contents)
+                .unwrap_or_else(|e| panic!("Could not parse sweep file {}: {}", path, e)),
+        };
+
+        let mut config = Self {
+            settings: Vec::new(),
+            parameters: Vec::new(),
+            methods: Vec::new(),
+        };
+
+        for (name, value) in root.iter() {
+            match (name.as_str(), value) {
+                ("parameters", Value::Table(table)) => config.parameters = table_to_pairs(table),
+                ("methods", Value::Table(methods)) => {
+                    for (method_name, method) in methods.iter() {
+                        let method = method.as_table().unwrap_or_else(|| {
+                            panic!("Method preset {} must be a table", method_name)
+                        });
+                        config
+                            .methods
+                            .push((method_name.to_owned(), table_to_pairs(method)));
+                    }
+                }
+                _ => config
+                    .settings
+                    .push((name.to_owned(), value_to_strings(name, value))),
+            }
+        }
+
+        config
+    }
+
+    // Command-line values replace those of the same name from the file (keeping their position),
+    // while new names are added to the end of the shared parameters.
+    pub fn apply_overrides(&mut self, overrides: &[(String, Vec<String>)]) {
+        for (name, vals) in overrides.iter() {
+            let mut found = false;
+            for pairs in std::iter::once(&mut self.settings)
+                .chain(std::iter::once(&mut self.parameters))
+                .chain(self.methods.iter_mut().map(|(_, pairs)| pairs))
+            {
+                for pair in pairs.iter_mut().filter(|pair| &pair.0 == name) {
+                    pair.1 = vals.clone();
+                    found = true;
+                }
+            }
+            if !found {
+                self.parameters.push((name.to_owned(), vals.clone()));
+            }
+        }
+    }
+
+    // One list of name/values pairs per method preset (or just one when there are no presets)
+    pub fn name_value_pair_sets(&self) -> Vec<Pairs> {
+        let shared = self
+            .settings
+            .iter()
+            .chain(self.parameters.iter())
+            .cloned()
+            .collect::<Vec<_>>();
+
+        if self.methods.is_empty() {
+            return vec![shared];
+        }
+
+        self.methods
+            .iter()
+            .map(|(_, method_pairs)| {
+                let mut pairs = method_pairs.clone();
+                pairs.extend(
+                    shared
+                        .iter()
+                        .filter(|(name, _)| !method_pairs.iter().any(|(n, _)| n == name))
+                        .cloned(),
+                );
+                pairs
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load_str(file_name: &str, contents: &str) -> SweepConfig {
+        let dir = std::env::temp_dir().join(format!("sweep_config_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(file_name);
+        std::fs::write(&path, contents).unwrap();
+        let config = SweepConfig::load(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        config
+    }
+
+    fn check(config: &SweepConfig) {
+        let pairs = |pairs: &[(&str, &[&str])]| {
+            pairs
+                .iter()
+                .map(|(name, vals)| {
+                    (
+                        name.to_string(),
+                        vals.iter().map(|v| v.to_string()).collect::<Vec<_>>(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            config.settings,
+            pairs(&[("thread_limit", &["8"]), ("db_path", &["results.db"])])
+        );
+        assert_eq!(
+            config.parameters,
+            pairs(&[("rng_seed", &["0-511"]), ("samples_n", &["8", "16", "32"])])
+        );
+        assert_eq!(config.methods.len(), 1);
+        assert_eq!(config.methods[0].0, "marginal_klucb");
+        assert_eq!(
+            config.methods[0].1,
+            pairs(&[("bound_mode", &["marginal"]), ("klucb.ucb_const", &["0.5"])])
+        );
+    }
+
+    #[test]
+    fn test_load_toml() {
+        check(&load_str(
+            "sweep.toml",
+            r#"
+thread_limit = 8
+db_path = "results.db"
+
+[parameters]
+rng_seed = "0-511"
+samples_n = [8, 16, 32]
+
+[methods.marginal_klucb]
+bound_mode = "marginal"
+"klucb.ucb_const" = 0.5
+"#,
+        ));
+    }
+
+    #[test]
+    fn test_load_yaml() {
+        check(&load_str(
+            "sweep.yaml",
+            r#"
+thread_limit: 8
+db_path: results.db
+parameters:
+  rng_seed: "0-511"
+  samples_n: [8, 16, 32]
+methods:
+  marginal_klucb:
+    bound_mode: marginal
+    klucb.ucb_const: 0.5
+"#,
+        ));
+    }
+}
@@ -0,0 +1,9 @@
+"""The driving simulator from Rust, for training learned baselines against it.
+
+Env is the simulator itself, and SelfDrivingEnv wraps it as a Gym environment.
+"""
+
+from ._selfdriving import Env
+from .gym_env import SelfDrivingEnv
+
+__all__ = ["Env", "SelfDrivingEnv"]
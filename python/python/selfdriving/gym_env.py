@@ -0,0 +1,44 @@
+import gymnasium as gym
+import numpy as np
+from gymnasium import spaces
+
+from ._selfdriving import Env
+
+
+class SelfDrivingEnv(gym.Env):
+    """The simulator as a Gym environment.
+
+    Each action is one of the ego policies the planners choose between (the lane to be in
+    and whether to maintain speed or accelerate, or else to slow down), which the ego
+    follows for replan_dt. The reward is the negative of the ego's cost over that time,
+    and info has the cost by component.
+
+    params override parameters.toml by name, as on the command line, like
+    {"n_cars": 8, "discount_factor": 0.9}. A crash ends an episode, and reaching
+    max_steps truncates it. Resetting with the same seed gives the same traffic as a run
+    with that rng_seed.
+    """
+
+    metadata = {"render_modes": []}
+
+    def __init__(self, params=None):
+        params = {name: str(value).lower() if isinstance(value, bool) else str(value)
+                  for name, value in (params or {}).items()}
+        self.sim = Env(params)
+        self.action_space = spaces.Discrete(self.sim.n_actions)
+        self.observation_space = spaces.Box(
+            -np.inf, np.inf, shape=(self.sim.observation_len,), dtype=np.float64)
+
+    def reset(self, *, seed=None, options=None):
+        super().reset(seed=seed)
+        if seed is None:
+            seed = int(self.np_random.integers(2**63))
+        observation = self.sim.reset(seed)
+        return np.array(observation), {}
+
+    def step(self, action):
+        observation, cost, done = self.sim.step(int(action))
+        terminated = self.sim.crashed
+        truncated = done and not terminated
+        info = {"cost": cost, "t": self.sim.t}
+        return np.array(observation), -cost["total"], terminated, truncated, info
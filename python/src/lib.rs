@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+
+use pyo3::{
+    exceptions::{PyRuntimeError, PyValueError},
+    prelude::*,
+};
+use selfdriving::{load_cost_spec, set_parameter, Parameters, SimError};
+
+// the simulator's errors, like a scenario it can't set up, as Python RuntimeErrors
+fn runtime_error(e: SimError) -> PyErr {
+    PyRuntimeError::new_err(e.to_string())
+}
+
+// The simulator as the selfdriving._selfdriving.Env class, which selfdriving.SelfDrivingEnv
+// wraps for Gym. Parameters come from parameters.toml in the working directory,
+// with overrides by name like on the command line.
+#[pyclass(unsendable)]
+struct Env {
+    env: selfdriving::Env,
+}
+
+#[pymethods]
+impl Env {
+    #[new]
+    #[pyo3(signature = (params=None))]
+    fn new(params: Option<HashMap<String, String>>) -> PyResult<Self> {
+        let mut parameters = Parameters::new()
+            .map_err(|e| PyValueError::new_err(format!("Could not load parameters: {}", e)))?;
+        for (name, val) in params.unwrap_or_default() {
+            set_parameter(&mut parameters, &name, &val);
+        }
+        load_cost_spec(&mut parameters);
+        Ok(Self {
+            env: selfdriving::Env::new(parameters).map_err(runtime_error)?,
+        })
+    }
+
+    #[getter]
+    fn n_actions(&self) -> usize {
+        self.env.n_actions()
+    }
+
+    #[getter]
+    fn observation_len(&self) -> usize {
+        self.env.observation_len()
+    }
+
+    #[getter]
+    fn crashed(&self) -> bool {
+        self.env.road().cars[0].crashed
+    }
+
+    #[getter]
+    fn t(&self) -> f64 {
+        self.env.road().t
+    }
+
+    fn reset(&mut self, seed: u64) -> PyResult<Vec<f64>> {
+        self.env.reset(seed).map_err(runtime_error)
+    }
+
+    // the observation, the ego's cost over the step by component and in total, and whether it's done
+    fn step(&mut self, action: usize) -> PyResult<(Vec<f64>, HashMap<&'static str, f64>, bool)> {
+        if action >= self.env.n_actions() {
+            return Err(PyValueError::new_err(format!(
+                "action {} is not below {}",
+                action,
+                self.env.n_actions()
+            )));
+        }
+        let step = self.env.step(action).map_err(runtime_error)?;
+        let c = step.cost;
+        let cost = [
+            ("efficiency", c.efficiency),
+            ("safety", c.safety),
+            ("accel", c.accel),
+            ("steer", c.steer),
+            ("jerk", c.jerk),
+            ("lat_accel", c.lat_accel),
+            ("lane_offset", c.lane_offset),
+            ("total", c.total()),
+        ];
+        Ok((step.observation, cost.iter().copied().collect(), step.done))
+    }
+}
+
+#[pymodule]
+fn _selfdriving(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Env>()?;
+    Ok(())
+}
@@ -0,0 +1,53 @@
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+};
+
+// The system allocator, counting the allocations made on each thread, so a run can report how
+// much its planning allocates. The binary installs it as its #[global_allocator], and without
+// it the counts just stay at 0.
+pub struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<u64> = const { Cell::new(0) };
+}
+
+fn count_allocation() {
+    // not while the thread is tearing down its thread locals
+    let _ = ALLOCATIONS.try_with(|c| c.set(c.get() + 1));
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count_allocation();
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        count_allocation();
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count_allocation();
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+// Allocations (and reallocations) on this thread since the last call
+pub fn take_allocation_count() -> u64 {
+    ALLOCATIONS.with(|c| c.replace(0))
+}
+
+// for the allocations that other threads made for this one
+pub fn add_allocation_count(allocations: u64) {
+    ALLOCATIONS.with(|c| c.set(c.get() + allocations));
+}
+
+#[cfg(test)]
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
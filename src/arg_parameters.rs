@@ -1,32 +1,29 @@
-use std::{
-    collections::BTreeMap,
-    fs::{File, OpenOptions},
-    io::{BufRead, BufReader, Write},
-    sync::{
-        atomic::{self, AtomicUsize},
-        Mutex,
-    },
-    time::Instant,
-};
-
-use atomic::Ordering;
 use itertools::Itertools;
-use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-use crate::run_with_parameters;
+use crate::{commonroad, cost_spec::CostSpec, planner, policy_registry};
 use progressive_mcts::{ChildSelectionMode, CostBoundMode};
 
-#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct EudmParameters {
     pub dt: f64,
     pub layer_t: f64,
     pub search_depth: u32,
     pub samples_n: usize,
     pub allow_different_root_policy: bool,
+    pub prediction: String,
+    // With contingency, the tree branches after the first layer on what happens ahead of
+    // the ego (see contingency_policy::Outcome), with the best sub-policy for each
+    pub contingency: bool,
+    pub contingency_brake_accel: f64,
+    // With belief prediction and cfb_k > 0, the rollouts start from and branch at each layer
+    // boundary into the cfb_k most probable policy assignments of the critical cars, chosen
+    // by cfb_criticality: risk (as for use_cfb) or distance
+    pub cfb_k: usize,
+    pub cfb_criticality: String,
 }
 
-#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct MctsParameters {
     pub dt: f64,
     pub layer_t: f64,
@@ -40,16 +37,28 @@ pub struct MctsParameters {
     pub klucb_max_cost: f64,
     pub repeat_const: f64,
     pub most_visited_best_cost_consistency: bool,
+    pub prediction: String,
+    // draw the planning tree in place of the rollout traces, toggled with t while running
+    pub tree_overlay: bool,
+    // with parallel_rollouts, the samples are split over this many independent search trees,
+    // each from its own RNG, and their root choices averaged
+    pub parallel_trees: usize,
 }
 
-#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct MpdmParameters {
     pub dt: f64,
     pub forward_t: f64,
     pub samples_n: usize,
+    // how the obstacle cars act in the rollouts: belief (their sampled policies),
+    // interactive (IDM and MOBIL, reacting to the ego), or open_loop (constant velocity)
+    pub prediction: String,
+    // with belief prediction, up to this many rounds of re-electing the obstacle cars'
+    // policies given the ego's choice and then the ego's given theirs, 0 for one-shot MPDM
+    pub election_rounds: u32,
 }
 
-#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct CostParameters {
     pub efficiency_speed_cost: f64,
     pub efficiency_weight: f64,
@@ -62,11 +71,16 @@ pub struct CostParameters {
 
     pub accel_weight: f64,
     pub steer_weight: f64,
+    // on the squares of the ego's longitudinal jerk and lateral acceleration
+    pub jerk_weight: f64,
+    pub lat_accel_weight: f64,
+    // on the square of the ego's offset from its lane's center, while it keeps its lane
+    pub lane_offset_weight: f64,
 
     pub discount_factor: f64,
 }
 
-#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct CfbParameters {
     pub key_vehicle_base_dist: f64,
     pub key_vehicle_dist_time: f64,
@@ -76,26 +90,379 @@ pub struct CfbParameters {
     pub horizon_t: f64,
 }
 
-#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct BeliefParameters {
     pub different_lane_prob: f64,
     pub different_longitudinal_prob: f64,
     pub decelerate_prior_prob: f64,
+    pub mobil_prior_prob: f64,
     pub accelerate_delta_vel_thresh: f64,
     pub accelerate_ahead_dist_thresh: f64,
     pub decelerate_vel_thresh: f64,
     pub finished_waiting_dy: f64,
     pub skips_waiting_prob: f64,
+    // per second, for the cars the ego's sensor doesn't see
+    pub unseen_reversion_rate: f64,
+    // With dirichlet, the belief is the mean of a Dirichlet distribution: dirichlet_prior
+    // pseudo-counts for every policy, plus the evidence from each step's prediction at
+    // evidence_rate counts per second, which is forgotten at forgetting_rate per second
+    pub dirichlet: bool,
+    pub dirichlet_prior: f64,
+    pub evidence_rate: f64,
+    pub forgetting_rate: f64,
 }
 
-#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct SpawnParameters {
     pub remove_ahead_beyond: f64,
     pub remove_behind_beyond: f64,
     pub place_ahead_beyond: f64,
+    // instead of respawning the same cars, they leave beyond the remove_* horizons
+    // and new ones arrive at the horizons, with flow cars per second in each lane
+    // at speeds uniform from speed_low to speed_high
+    pub open_boundary: bool,
+    pub flow: f64,
+    pub speed_low: f64,
+    pub speed_high: f64,
 }
 
-#[derive(Clone, Debug, Deserialize, PartialEq)]
+// An on-ramp: ramp_lane only exists from start to end (repeating every period, if it is positive),
+// so the cars on it have to merge out before its end. A negative ramp_lane has no ramp.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct MergeParameters {
+    pub ramp_lane: i32,
+    pub start: f64,
+    pub end: f64,
+    pub period: f64,
+}
+
+// A lane closure (a construction zone): lane is coned off from start to end
+// (repeating every period, if it is positive), so the cars in it have to merge out before it.
+// A negative lane has no closure.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct LaneClosureParameters {
+    pub lane: i32,
+    pub start: f64,
+    pub end: f64,
+    pub period: f64,
+}
+
+// For the idm forward control: the standstill gap s0 (m), time headway T (s),
+// max accel a and comfortable decel b (m/s^2), and the free-road accel exponent delta
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct IdmParameters {
+    pub desired_gap: f64,
+    pub time_headway: f64,
+    pub max_accel: f64,
+    pub comfortable_decel: f64,
+    pub accel_exponent: f64,
+}
+
+// For the pure_pursuit side control: it steers toward the point on the trajectory
+// ahead_time * vel away (s), within limits
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct PurePursuitParameters {
+    pub ahead_time: f64,
+}
+
+// For the stanley side control: steer = heading error + atan(gain * cross-track error / (soft_vel + vel)),
+// with soft_vel (m/s) keeping it from steering hard at low speeds
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct StanleyParameters {
+    pub gain: f64,
+    pub soft_vel: f64,
+}
+
+// With dynamic, the ego moves by a dynamic bicycle model with tire slip once it's going
+// at least min_vel (m/s), with its mass (kg), yaw inertia (kg m^2),
+// and front and rear axle cornering stiffnesses (N/rad), instead of the kinematic model
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct DynamicsParameters {
+    pub dynamic: bool,
+    pub min_vel: f64,
+    pub mass: f64,
+    pub yaw_inertia: f64,
+    pub front_stiffness: f64,
+    pub rear_stiffness: f64,
+}
+
+// First-order lag time constants (s) between the controllers' accel and steer commands
+// and the cars' actual accel and steer, and a limit on how fast the steering turns (rad/s).
+// Zeros respond right away, with no limit.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ActuatorParameters {
+    pub accel_time_constant: f64,
+    pub steer_time_constant: f64,
+    pub max_steer_rate: f64,
+}
+
+// With sample, each random obstacle car gets latent driver traits, uniform from low to high:
+// the standstill gap it keeps (m), its MOBIL politeness, and an offset to its preferred speed (m/s)
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct DriverTraitParameters {
+    pub sample: bool,
+    pub desired_gap_low: f64,
+    pub desired_gap_high: f64,
+    pub politeness_low: f64,
+    pub politeness_high: f64,
+    pub speed_offset_low: f64,
+    pub speed_offset_high: f64,
+}
+
+// Gaussian noise on every car's accel (m/s^2) and steering (rad) each step, as Car::update
+// moves it, in the true simulation with truth and in the planners' forward sims with rollouts.
+// Apart, they model a world that's noisier than the planners think, or the other way around.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ProcessNoiseParameters {
+    pub accel_std: f64,
+    pub steer_std: f64,
+    pub truth: bool,
+    pub rollouts: bool,
+}
+
+// With enabled, the random obstacle cars drive by a different distribution than the planners
+// assume, to stress-test them (see mismatch.rs): their lane changes take lane_change_time_scale
+// times as long, they follow follow_time_offset (s) further behind and accelerate accel_scale
+// times as hard, and novel_fraction of their policy draws are from the novel policies.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct MismatchParameters {
+    pub enabled: bool,
+    pub lane_change_time_scale: f64,
+    pub follow_time_offset: f64,
+    pub accel_scale: f64,
+    pub novel_fraction: f64,
+}
+
+// The ego's sensor, for the belief update: it sees the other cars within range (m, 0 for unlimited),
+// with gaussian noise of position_std (m) on their positions and vel_std (m/s) on their velocities.
+// All zeros gives the belief the truth, like before there was a sensor.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct SensorParameters {
+    pub range: f64,
+    pub position_std: f64,
+    pub vel_std: f64,
+}
+
+// With enabled, the ego doesn't see cars fully hidden behind others, for the belief update.
+// truck_fraction of the random obstacle cars are trucks, which hide more.
+// The planner adds up to phantoms cars (0 for none) in the hidden spots within phantom_range (m).
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct OcclusionParameters {
+    pub enabled: bool,
+    pub truck_fraction: f64,
+    pub phantoms: usize,
+    pub phantom_range: f64,
+}
+
+// With enabled, the belief also tracks whether each car means to stay in its lane or change
+// left or right, from its lateral velocity (with lat_vel_std, m/s) and its blinker, which shows
+// the lane it's headed for and is believed right blinker_reliability of the time.
+// change_prior_prob is the prior for each of changing left and right, and the estimate moves
+// toward what it sees at rate (per s). The policies in the belief are weighted by their intents.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct IntentParameters {
+    pub enabled: bool,
+    pub change_prior_prob: f64,
+    pub lat_vel_std: f64,
+    pub blinker_reliability: f64,
+    pub rate: f64,
+}
+
+// How the planners (MPDM, EUDM, and MCTS at its nodes) combine the costs of the sampled roads:
+// mean, worst, percentile (the cost at that quantile), cvar (the mean of the worst
+// 1 - cvar_alpha of them), or mean_std (the mean plus std_k standard deviations)
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct RiskParameters {
+    pub aggregation: String,
+    pub percentile: f64,
+    pub cvar_alpha: f64,
+    pub std_k: f64,
+}
+
+// A runtime safety filter: with enabled, the ego brakes as hard as it can whenever its
+// time-to-collision with the car ahead is under ttc_threshold (s), whatever its policy.
+// Each second of that costs activation_weight, as safety cost.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct SafetyFilterParameters {
+    pub enabled: bool,
+    pub ttc_threshold: f64,
+    pub activation_weight: f64,
+}
+
+// A Responsibility-Sensitive Safety monitor: with enabled, each step checks whether the ego
+// is closer to any car than the RSS safe distances both along and across the road.
+// The response_time (s), max_accel, min_brake and max_brake (m/s^2) give the longitudinal one,
+// and lat_max_accel, lat_min_brake (m/s^2) and lat_margin (m) the lateral one.
+// Each second in violation costs weight, as safety cost, which may be 0 to only measure it.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct RssParameters {
+    pub enabled: bool,
+    pub response_time: f64,
+    pub max_accel: f64,
+    pub min_brake: f64,
+    pub max_brake: f64,
+    pub lat_max_accel: f64,
+    pub lat_min_brake: f64,
+    pub lat_margin: f64,
+    pub weight: f64,
+}
+
+// A goal for the ego: to pass station x (m) in lane by time (s). With enabled, the end of each
+// rollout (and of the run) adds a terminal efficiency cost of lane_weight per lane off from it,
+// and late_weight per second past time that it arrived, or will at its current speed.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct GoalParameters {
+    pub enabled: bool,
+    pub x: f64,
+    pub lane: i32,
+    pub time: f64,
+    pub lane_weight: f64,
+    pub late_weight: f64,
+}
+
+// With severity, an ego crash costs base_weight plus speed_weight per m/s of impact speed
+// (the cars' closing speed along their contact normal) once, as safety cost,
+// in place of the running costs of sitting crashed for the rest of the rollout.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct CrashParameters {
+    pub severity: bool,
+    pub base_weight: f64,
+    pub speed_weight: f64,
+}
+
+// SVG snapshots of the road, traces and belief overlay written to dir (when set) at the given
+// timesteps, every so many (0 for not), and when the ego crashes, even in runs with no viewer
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct SvgParameters {
+    pub dir: String,
+    pub steps: Vec<usize>,
+    pub every: usize,
+    pub on_crash: bool,
+}
+
+// Every car's trajectory, written to dir (when set) for each run as csv or jsonl
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct TrajectoriesParameters {
+    pub dir: String,
+    pub format: String,
+}
+
+// CommonRoad scenarios, from a scenario_file ending in .xml (see commonroad.rs).
+// With curvilinear, the road follows the curvature of the scenario's lanes instead of
+// straightening them, and solution is where to write the ego's trajectory as a CommonRoad solution
+// for solution_model, the vehicle model, vehicle type and cost function of its benchmark id.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct CommonRoadParameters {
+    pub curvilinear: bool,
+    pub solution: String,
+    pub solution_model: String,
+}
+
+// A real road section from an OpenDRIVE (.xodr) map file (see opendrive.rs), taking the place of
+// road_geometry, n_lanes and speed_limit. road is the id of its road to drive, or the longest road.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct OpenDriveParameters {
+    pub file: String,
+    pub road: String,
+}
+
+// Background traffic from SUMO over TraCI (see sumo.rs), when port isn't 0, with SUMO started
+// like: sumo -c <config> --remote-port <port> --step-length <sync_dt>. The ego is ego_id in SUMO,
+// added on ego_route unless the config already has it, and our road's origin is at
+// (origin_x, origin_y) in SUMO's network, which keeps the cars within range of the ego.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct SumoParameters {
+    pub host: String,
+    pub port: u16,
+    pub ego_id: String,
+    pub ego_route: String,
+    pub sync_dt: f64,
+    pub origin_x: f64,
+    pub origin_y: f64,
+    pub range: f64,
+}
+
+// One declaration of the policies the cars choose between (see policy_registry.rs):
+// a lane_change into each lane or staying in the current one, with a longitudinal policy of
+// maintain, accelerate or decelerate, a nudge over within the current lane, a gap_alignment
+// with a gap in each lane before changing into it, or mobil, and whether it's a policy for the ego,
+// for the obstacle cars, and in the belief over the obstacle cars, or novel, for the obstacle
+// cars only under mismatch
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct PolicyParameters {
+    pub name: String,
+    pub kind: String,
+    #[serde(default)]
+    pub lanes: String,
+    #[serde(default)]
+    pub longitudinal: String,
+    #[serde(default)]
+    pub wait_for_clear: bool,
+    // for a lane_change to go back to its lane if the target lane closes up before it's across
+    #[serde(default)]
+    pub abort: bool,
+    // or else the lane_change_time parameter
+    pub lane_change_time: Option<f64>,
+    // m from the lane's center a nudge drives at, positive toward the higher lanes
+    #[serde(default)]
+    pub offset: f64,
+    // which gap a gap_alignment lines up with: 0 beside the car, 1 the next ahead, -1 behind
+    #[serde(default)]
+    pub gap: i32,
+    #[serde(default)]
+    pub ego: bool,
+    #[serde(default)]
+    pub obstacle: bool,
+    #[serde(default)]
+    pub belief: bool,
+    #[serde(default)]
+    pub novel: bool,
+}
+
+// MOBIL lane changes for fraction of the obstacle cars, with politeness factor p,
+// advantage threshold a_thr, and the safe braking limit b_safe (m/s^2) for their new followers.
+// With a fraction above 0, MOBIL is also one of the policies in the belief over the obstacle cars.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct MobilParameters {
+    pub fraction: f64,
+    pub politeness: f64,
+    pub advantage_threshold: f64,
+    pub safe_decel: f64,
+}
+
+// Pedestrians at crosswalks first_crosswalk + k * crosswalk_spacing, per_crosswalk at each one
+// near the ego. They wait on a curb for wait_time, then cross at walk_speed once no car would
+// reach the crosswalk within gap_time, and then wait on the other side to cross back.
+// A crosswalk_spacing of 0 has no pedestrians.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct PedestrianParameters {
+    pub crosswalk_spacing: f64,
+    pub first_crosswalk: f64,
+    pub per_crosswalk: usize,
+    pub walk_speed: f64,
+    pub wait_time: f64,
+    pub gap_time: f64,
+    // the ego's safety cost for getting within safety_margin of a crossing pedestrian
+    pub safety_weight: f64,
+    pub safety_margin: f64,
+}
+
+// Signalized intersections with their stop lines at first + k * spacing, cycling through
+// green_time, yellow_time, then red_time, each phase_offset later than the one before.
+// A spacing of 0 has no intersections.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct SignalParameters {
+    pub spacing: f64,
+    pub first: f64,
+    pub green_time: f64,
+    pub yellow_time: f64,
+    pub red_time: f64,
+    pub phase_offset: f64,
+    // the ego's cost for each time it crosses a stop line at a red light
+    pub violation_weight: f64,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct Parameters {
     pub max_steps: u32,
     pub n_cars: usize,
@@ -104,17 +471,55 @@ pub struct Parameters {
 
     pub physics_dt: f64,
     pub replan_dt: f64,
+    // physics steps from one planner invocation to the next, the ego's chosen policy driving
+    // in between, or 0 for every replan_dt
+    pub replan_interval: u32,
     pub nonego_policy_change_prob: f64,
     pub nonego_policy_change_dt: f64,
     pub lane_change_time: f64,
+    pub n_lanes: i32,
+    // intelligent_driver or idm, for the ego and for the obstacle cars
+    pub ego_forward_control: String,
+    pub obstacle_forward_control: String,
+    // pure_pursuit or stanley, for the ego and for the obstacle cars
+    pub ego_side_control: String,
+    pub obstacle_side_control: String,
+    pub road_geometry: String,
+    // the most any car prefers to drive, in m/s, or 0 for no limit
+    pub speed_limit: f64,
+    // what the planners minimize and the runs report: discounted (by cost.discount_factor in
+    // rollouts, with the runs themselves undiscounted), total (undiscounted everywhere),
+    // or average (undiscounted cost per second)
+    pub objective_mode: String,
 
     pub thread_limit: usize,
+    pub memory_budget_mb: usize,
     pub rng_seed: u64,
+    // each car, timestep and rollout draws from its own stream forked from rng_seed's
+    pub rng_streams: bool,
+    pub seed_reps: u64,
     pub run_fast: bool,
     pub load_and_record_results: bool,
     pub is_single_run: bool,
+    pub runs_dir: String,
+    pub replays_dir: String,
+    pub record: String,
+    // overrides for a second planner on the same traffic, like method=eudm,eudm.samples_n=32
+    pub compare: String,
+    pub log_filter: String,
+    pub log_json_path: String,
+    pub scenario_file: String,
+    pub cost_file: String,
+    // loaded from cost_file when the scenarios are made
+    #[serde(default)]
+    pub cost_spec: Option<CostSpec>,
+    pub named_scenario: String,
     pub graphics_speedup: f64,
     pub graphics_for_paper: bool,
+    pub belief_overlay: bool,
+    // with a default for replays recorded before it
+    #[serde(default)]
+    pub candidates_overlay: bool,
     pub debug_car_i: Option<usize>,
     pub debug_steps_before: usize,
     pub super_debug: bool,
@@ -129,11 +534,47 @@ pub struct Parameters {
     pub ego_traces_debug: bool,
 
     pub only_ego_crashes_in_forward_sims: bool,
+    // the forward sims' kinematic updates and collision checks computed in f32 arithmetic.
+    // The cars' state is still stored in f64, so this trades precision for speed, not memory.
+    pub f32_rollout_math: bool,
+    // a planning call's forward sims for different policies (for mcts, its parallel_trees)
+    // on all the cores, for single runs
+    pub parallel_rollouts: bool,
+    // times the planners' forward sims, collision checks, policy evaluation and belief sampling
+    pub profile_subsystems: bool,
     pub only_crashes_with_ego: bool,
     pub obstacles_only_for_ego: bool,
     pub true_belief_sample_only: bool,
 
     pub spawn: SpawnParameters,
+    pub merge: MergeParameters,
+    pub closure: LaneClosureParameters,
+    pub pedestrians: PedestrianParameters,
+    pub signals: SignalParameters,
+    pub idm: IdmParameters,
+    pub mobil: MobilParameters,
+    #[serde(default = "policy_registry::default_policies")]
+    pub policies: Vec<PolicyParameters>,
+    pub pure_pursuit: PurePursuitParameters,
+    pub stanley: StanleyParameters,
+    pub ego_dynamics: DynamicsParameters,
+    pub actuators: ActuatorParameters,
+    pub driver_traits: DriverTraitParameters,
+    pub process_noise: ProcessNoiseParameters,
+    pub mismatch: MismatchParameters,
+    pub sensor: SensorParameters,
+    pub occlusion: OcclusionParameters,
+    pub intent: IntentParameters,
+    pub risk: RiskParameters,
+    pub safety_filter: SafetyFilterParameters,
+    pub rss: RssParameters,
+    pub goal: GoalParameters,
+    pub crash: CrashParameters,
+    pub svg: SvgParameters,
+    pub trajectories: TrajectoriesParameters,
+    pub commonroad: CommonRoadParameters,
+    pub opendrive: OpenDriveParameters,
+    pub sumo: SumoParameters,
     pub belief: BeliefParameters,
     pub cost: CostParameters,
     pub cfb: CfbParameters,
@@ -145,19 +586,274 @@ pub struct Parameters {
 }
 
 impl Parameters {
-    fn new() -> Result<Self, config::ConfigError> {
+    pub fn new() -> Result<Self, config::ConfigError> {
         let mut s = config::Config::new();
         s.merge(config::File::with_name("parameters"))?;
         s.try_into()
     }
+
+    pub fn replan_steps(&self) -> u32 {
+        if self.replan_interval > 0 {
+            self.replan_interval
+        } else {
+            ((self.replan_dt / self.physics_dt).round() as u32).max(1)
+        }
+    }
+}
+
+// Repeats a scenario over the contiguous block of seeds rng_seed..rng_seed + seed_reps
+fn seed_block(params: Parameters) -> Vec<Parameters> {
+    (0..params.seed_reps.max(1))
+        .map(|rep| {
+            let mut p = params.clone();
+            p.rng_seed += rep;
+            p
+        })
+        .collect()
+}
+
+// Loads cost_spec from cost_file, if there is one
+pub fn load_cost_spec(params: &mut Parameters) {
+    if params.cost_file.is_empty() {
+        return;
+    }
+    let spec = CostSpec::load(&params.cost_file);
+    // the cost file's discount_factor wins over the parameter's
+    if let Some(discount_factor) = spec.discount_factor {
+        params.cost.discount_factor = discount_factor;
+    }
+    params.cost_spec = Some(spec);
+}
+
+// Sets the parameter by its name, like on the command line
+pub fn set_parameter(params: &mut Parameters, name: &str, val: &str) {
+    match name {
+        "method" => params.method = val.parse().unwrap(),
+        "use_cfb" => params.use_cfb = val.parse().unwrap(),
+        "max_steps" => params.max_steps = val.parse().unwrap(),
+        "n_cars" => params.n_cars = val.parse().unwrap(),
+        "n_lanes" => params.n_lanes = val.parse().unwrap(),
+        "road_geometry" => params.road_geometry = val.to_owned(),
+        "speed_limit" => params.speed_limit = val.parse().unwrap(),
+        "objective_mode" => params.objective_mode = val.to_owned(),
+        "spawn.open_boundary" => params.spawn.open_boundary = val.parse().unwrap(),
+        "spawn.flow" => params.spawn.flow = val.parse().unwrap(),
+        "merge.ramp_lane" => params.merge.ramp_lane = val.parse().unwrap(),
+        "merge.start" => params.merge.start = val.parse().unwrap(),
+        "merge.end" => params.merge.end = val.parse().unwrap(),
+        "merge.period" => params.merge.period = val.parse().unwrap(),
+        "ego_forward_control" => params.ego_forward_control = val.to_owned(),
+        "obstacle_forward_control" => params.obstacle_forward_control = val.to_owned(),
+        "idm.desired_gap" => params.idm.desired_gap = val.parse().unwrap(),
+        "idm.time_headway" => params.idm.time_headway = val.parse().unwrap(),
+        "idm.max_accel" => params.idm.max_accel = val.parse().unwrap(),
+        "idm.comfortable_decel" => params.idm.comfortable_decel = val.parse().unwrap(),
+        "ego_side_control" => params.ego_side_control = val.to_owned(),
+        "obstacle_side_control" => params.obstacle_side_control = val.to_owned(),
+        "pure_pursuit.ahead_time" => params.pure_pursuit.ahead_time = val.parse().unwrap(),
+        "stanley.gain" => params.stanley.gain = val.parse().unwrap(),
+        "stanley.soft_vel" => params.stanley.soft_vel = val.parse().unwrap(),
+        "ego_dynamics.dynamic" => params.ego_dynamics.dynamic = val.parse().unwrap(),
+        "ego_dynamics.min_vel" => params.ego_dynamics.min_vel = val.parse().unwrap(),
+        "ego_dynamics.front_stiffness" => {
+            params.ego_dynamics.front_stiffness = val.parse().unwrap()
+        }
+        "ego_dynamics.rear_stiffness" => params.ego_dynamics.rear_stiffness = val.parse().unwrap(),
+        "actuators.accel_time_constant" => {
+            params.actuators.accel_time_constant = val.parse().unwrap()
+        }
+        "actuators.steer_time_constant" => {
+            params.actuators.steer_time_constant = val.parse().unwrap()
+        }
+        "actuators.max_steer_rate" => params.actuators.max_steer_rate = val.parse().unwrap(),
+        "driver_traits.sample" => params.driver_traits.sample = val.parse().unwrap(),
+        "driver_traits.desired_gap_low" => {
+            params.driver_traits.desired_gap_low = val.parse().unwrap()
+        }
+        "driver_traits.desired_gap_high" => {
+            params.driver_traits.desired_gap_high = val.parse().unwrap()
+        }
+        "driver_traits.politeness_low" => {
+            params.driver_traits.politeness_low = val.parse().unwrap()
+        }
+        "driver_traits.politeness_high" => {
+            params.driver_traits.politeness_high = val.parse().unwrap()
+        }
+        "driver_traits.speed_offset_low" => {
+            params.driver_traits.speed_offset_low = val.parse().unwrap()
+        }
+        "driver_traits.speed_offset_high" => {
+            params.driver_traits.speed_offset_high = val.parse().unwrap()
+        }
+        "process_noise.accel_std" => params.process_noise.accel_std = val.parse().unwrap(),
+        "process_noise.steer_std" => params.process_noise.steer_std = val.parse().unwrap(),
+        "process_noise.truth" => params.process_noise.truth = val.parse().unwrap(),
+        "process_noise.rollouts" => params.process_noise.rollouts = val.parse().unwrap(),
+        "mismatch.enabled" => params.mismatch.enabled = val.parse().unwrap(),
+        "mismatch.lane_change_time_scale" => {
+            params.mismatch.lane_change_time_scale = val.parse().unwrap()
+        }
+        "mismatch.follow_time_offset" => params.mismatch.follow_time_offset = val.parse().unwrap(),
+        "mismatch.accel_scale" => params.mismatch.accel_scale = val.parse().unwrap(),
+        "mismatch.novel_fraction" => params.mismatch.novel_fraction = val.parse().unwrap(),
+        "sensor.range" => params.sensor.range = val.parse().unwrap(),
+        "sensor.position_std" => params.sensor.position_std = val.parse().unwrap(),
+        "sensor.vel_std" => params.sensor.vel_std = val.parse().unwrap(),
+        "occlusion.enabled" => params.occlusion.enabled = val.parse().unwrap(),
+        "occlusion.truck_fraction" => params.occlusion.truck_fraction = val.parse().unwrap(),
+        "occlusion.phantoms" => params.occlusion.phantoms = val.parse().unwrap(),
+        "occlusion.phantom_range" => params.occlusion.phantom_range = val.parse().unwrap(),
+        "belief.dirichlet" => params.belief.dirichlet = val.parse().unwrap(),
+        "belief.dirichlet_prior" => params.belief.dirichlet_prior = val.parse().unwrap(),
+        "belief.evidence_rate" => params.belief.evidence_rate = val.parse().unwrap(),
+        "belief.forgetting_rate" => params.belief.forgetting_rate = val.parse().unwrap(),
+        "intent.enabled" => params.intent.enabled = val.parse().unwrap(),
+        "intent.change_prior_prob" => params.intent.change_prior_prob = val.parse().unwrap(),
+        "intent.lat_vel_std" => params.intent.lat_vel_std = val.parse().unwrap(),
+        "intent.blinker_reliability" => params.intent.blinker_reliability = val.parse().unwrap(),
+        "intent.rate" => params.intent.rate = val.parse().unwrap(),
+        "risk.aggregation" => params.risk.aggregation = val.to_owned(),
+        "risk.percentile" => params.risk.percentile = val.parse().unwrap(),
+        "risk.cvar_alpha" => params.risk.cvar_alpha = val.parse().unwrap(),
+        "risk.std_k" => params.risk.std_k = val.parse().unwrap(),
+        "safety_filter.enabled" => params.safety_filter.enabled = val.parse().unwrap(),
+        "safety_filter.ttc_threshold" => params.safety_filter.ttc_threshold = val.parse().unwrap(),
+        "f32_rollout_math" => params.f32_rollout_math = val.parse().unwrap(),
+        "parallel_rollouts" => params.parallel_rollouts = val.parse().unwrap(),
+        "profile_subsystems" => params.profile_subsystems = val.parse().unwrap(),
+        "rss.enabled" => params.rss.enabled = val.parse().unwrap(),
+        "rss.response_time" => params.rss.response_time = val.parse().unwrap(),
+        "rss.max_accel" => params.rss.max_accel = val.parse().unwrap(),
+        "rss.min_brake" => params.rss.min_brake = val.parse().unwrap(),
+        "rss.max_brake" => params.rss.max_brake = val.parse().unwrap(),
+        "rss.lat_max_accel" => params.rss.lat_max_accel = val.parse().unwrap(),
+        "rss.lat_min_brake" => params.rss.lat_min_brake = val.parse().unwrap(),
+        "rss.lat_margin" => params.rss.lat_margin = val.parse().unwrap(),
+        "rss.weight" => params.rss.weight = val.parse().unwrap(),
+        "goal.enabled" => params.goal.enabled = val.parse().unwrap(),
+        "goal.x" => params.goal.x = val.parse().unwrap(),
+        "goal.lane" => params.goal.lane = val.parse().unwrap(),
+        "goal.time" => params.goal.time = val.parse().unwrap(),
+        "goal.lane_weight" => params.goal.lane_weight = val.parse().unwrap(),
+        "goal.late_weight" => params.goal.late_weight = val.parse().unwrap(),
+        "crash.severity" => params.crash.severity = val.parse().unwrap(),
+        "crash.base_weight" => params.crash.base_weight = val.parse().unwrap(),
+        "crash.speed_weight" => params.crash.speed_weight = val.parse().unwrap(),
+        "svg.dir" => params.svg.dir = val.to_owned(),
+        "svg.steps" => params.svg.steps = val.split(',').map(|s| s.parse().unwrap()).collect(),
+        "svg.every" => params.svg.every = val.parse().unwrap(),
+        "svg.on_crash" => params.svg.on_crash = val.parse().unwrap(),
+        "trajectories.dir" => params.trajectories.dir = val.to_owned(),
+        "trajectories.format" => params.trajectories.format = val.to_owned(),
+        "commonroad.curvilinear" => params.commonroad.curvilinear = val.parse().unwrap(),
+        "commonroad.solution" => params.commonroad.solution = val.to_owned(),
+        "commonroad.solution_model" => params.commonroad.solution_model = val.to_owned(),
+        "opendrive.file" => params.opendrive.file = val.to_owned(),
+        "opendrive.road" => params.opendrive.road = val.to_owned(),
+        "sumo.host" => params.sumo.host = val.to_owned(),
+        "sumo.port" => params.sumo.port = val.parse().unwrap(),
+        "sumo.ego_id" => params.sumo.ego_id = val.to_owned(),
+        "sumo.ego_route" => params.sumo.ego_route = val.to_owned(),
+        "sumo.sync_dt" => params.sumo.sync_dt = val.parse().unwrap(),
+        "sumo.origin_x" => params.sumo.origin_x = val.parse().unwrap(),
+        "sumo.origin_y" => params.sumo.origin_y = val.parse().unwrap(),
+        "sumo.range" => params.sumo.range = val.parse().unwrap(),
+        "safety_filter.activation_weight" => {
+            params.safety_filter.activation_weight = val.parse().unwrap()
+        }
+        "mobil.fraction" => params.mobil.fraction = val.parse().unwrap(),
+        "mobil.politeness" => params.mobil.politeness = val.parse().unwrap(),
+        "mobil.advantage_threshold" => params.mobil.advantage_threshold = val.parse().unwrap(),
+        "mobil.safe_decel" => params.mobil.safe_decel = val.parse().unwrap(),
+        "closure.lane" => params.closure.lane = val.parse().unwrap(),
+        "closure.start" => params.closure.start = val.parse().unwrap(),
+        "closure.end" => params.closure.end = val.parse().unwrap(),
+        "closure.period" => params.closure.period = val.parse().unwrap(),
+        "pedestrians.crosswalk_spacing" => {
+            params.pedestrians.crosswalk_spacing = val.parse().unwrap()
+        }
+        "pedestrians.per_crosswalk" => params.pedestrians.per_crosswalk = val.parse().unwrap(),
+        "pedestrians.gap_time" => params.pedestrians.gap_time = val.parse().unwrap(),
+        "pedestrians.safety_weight" => params.pedestrians.safety_weight = val.parse().unwrap(),
+        "signals.spacing" => params.signals.spacing = val.parse().unwrap(),
+        "signals.phase_offset" => params.signals.phase_offset = val.parse().unwrap(),
+        "signals.violation_weight" => params.signals.violation_weight = val.parse().unwrap(),
+        "discount_factor" => params.cost.discount_factor = val.parse().unwrap(),
+        "replan_dt" => params.replan_dt = val.parse().unwrap(),
+        "replan_interval" => params.replan_interval = val.parse().unwrap(),
+        "rng_seed" => params.rng_seed = val.parse().unwrap(),
+        "rng_streams" => params.rng_streams = val.parse().unwrap(),
+        "seed_reps" => params.seed_reps = val.parse().unwrap(),
+        "run_fast" => params.run_fast = val.parse().unwrap(),
+        "load_and_record_results" => params.load_and_record_results = val.parse().unwrap(),
+        "thread_limit" => params.thread_limit = val.parse().unwrap(),
+        "memory_budget_mb" => params.memory_budget_mb = val.parse().unwrap(),
+        "log_filter" => params.log_filter = val.to_owned(),
+        "log_json_path" => params.log_json_path = val.to_owned(),
+        "replays_dir" => params.replays_dir = val.to_owned(),
+        "record" => params.record = val.to_owned(),
+        "compare" => params.compare = val.to_owned(),
+        "belief_overlay" => params.belief_overlay = val.parse().unwrap(),
+        "candidates_overlay" => params.candidates_overlay = val.parse().unwrap(),
+        "scenario_file" | "--scenario" => params.scenario_file = val.to_owned(),
+        "cost_file" => params.cost_file = val.to_owned(),
+        "named_scenario" => params.named_scenario = val.to_owned(),
+        "mpdm.samples_n" => params.mpdm.samples_n = val.parse().unwrap(),
+        "mpdm.prediction" => params.mpdm.prediction = val.to_owned(),
+        "mpdm.election_rounds" => params.mpdm.election_rounds = val.parse().unwrap(),
+        "eudm.prediction" => params.eudm.prediction = val.to_owned(),
+        "mcts.prediction" => params.mcts.prediction = val.to_owned(),
+        "eudm.samples_n" => params.eudm.samples_n = val.parse().unwrap(),
+        "mcts.samples_n" => params.mcts.samples_n = val.parse().unwrap(),
+        "mpdm.forward_t" => params.mpdm.forward_t = val.parse().unwrap(),
+        "eudm.search_depth" => params.eudm.search_depth = val.parse().unwrap(),
+        "mcts.search_depth" => params.mcts.search_depth = val.parse().unwrap(),
+        "eudm.layer_t" => params.eudm.layer_t = val.parse().unwrap(),
+        "mcts.layer_t" => params.mcts.layer_t = val.parse().unwrap(),
+        "mcts.total_forward_t" => params.mcts.total_forward_t = Some(val.parse().unwrap()),
+        "safety" => params.cost.safety_weight = val.parse().unwrap(),
+        "safety_margin_low" => params.cost.safety_margin_low = val.parse().unwrap(),
+        "safety_margin_high" => params.cost.safety_margin_high = val.parse().unwrap(),
+        "accel" => params.cost.accel_weight = val.parse().unwrap(),
+        "steer" => params.cost.steer_weight = val.parse().unwrap(),
+        "jerk" => params.cost.jerk_weight = val.parse().unwrap(),
+        "lat_accel" => params.cost.lat_accel_weight = val.parse().unwrap(),
+        "lane_offset" => params.cost.lane_offset_weight = val.parse().unwrap(),
+        "mcts.bound_mode" => params.mcts.bound_mode = val.parse().unwrap(),
+        "mcts.selection_mode" => params.mcts.selection_mode = val.parse().unwrap(),
+        "mcts.ucb_const" => params.mcts.ucb_const = val.parse().unwrap(),
+        "mcts.klucb_max_cost" => params.mcts.klucb_max_cost = val.parse().unwrap(),
+        "mcts.repeat_const" => params.mcts.repeat_const = val.parse().unwrap(),
+        "mcts.most_visited_best_cost_consistency" => {
+            params.mcts.most_visited_best_cost_consistency = val.parse().unwrap()
+        }
+        "mcts.tree_overlay" => params.mcts.tree_overlay = val.parse().unwrap(),
+        "mcts.parallel_trees" => params.mcts.parallel_trees = val.parse().unwrap(),
+        "eudm.allow_different_root_policy" => {
+            params.eudm.allow_different_root_policy = val.parse().unwrap()
+        }
+        "eudm.contingency" => params.eudm.contingency = val.parse().unwrap(),
+        "eudm.cfb_k" => params.eudm.cfb_k = val.parse().unwrap(),
+        "eudm.cfb_criticality" => params.eudm.cfb_criticality = val.to_owned(),
+        "eudm.contingency_brake_accel" => {
+            params.eudm.contingency_brake_accel = val.parse().unwrap()
+        }
+        _ if name.starts_with("policies.") => {
+            policy_registry::set_policy_parameter(params, name, val)
+        }
+        _ => panic!("{} is not a valid parameter!", name),
+    }
 }
 
-fn create_scenarios(
+// The parameter sets of a sweep from base_params: one for each combination of the values of
+// name_value_pairs, in order, skipping the parameters of the other methods, and each over
+// its block of seeds. Each gets its scenario_name from the parameters that tell it apart.
+pub fn create_scenarios(
     base_params: &Parameters,
     name_value_pairs: &[(String, Vec<String>)],
 ) -> Vec<Parameters> {
     if name_value_pairs.is_empty() {
-        return vec![base_params.clone()];
+        return seed_block(base_params.clone());
     }
 
     let mut scenarios = Vec::new();
@@ -206,48 +902,11 @@ fn create_scenarios(
 
         for val in value_set {
             let mut params = base_params.clone();
-            match name.as_str() {
-                "method" => params.method = val.parse().unwrap(),
-                "use_cfb" => params.use_cfb = val.parse().unwrap(),
-                "max_steps" => params.max_steps = val.parse().unwrap(),
-                "n_cars" => params.n_cars = val.parse().unwrap(),
-                "discount_factor" => params.cost.discount_factor = val.parse().unwrap(),
-                "replan_dt" => params.replan_dt = val.parse().unwrap(),
-                "rng_seed" => params.rng_seed = val.parse().unwrap(),
-                "run_fast" => params.run_fast = val.parse().unwrap(),
-                "load_and_record_results" => params.load_and_record_results = val.parse().unwrap(),
-                "thread_limit" => params.thread_limit = val.parse().unwrap(),
-                "mpdm.samples_n" => params.mpdm.samples_n = val.parse().unwrap(),
-                "eudm.samples_n" => params.eudm.samples_n = val.parse().unwrap(),
-                "mcts.samples_n" => params.mcts.samples_n = val.parse().unwrap(),
-                "mpdm.forward_t" => params.mpdm.forward_t = val.parse().unwrap(),
-                "eudm.search_depth" => params.eudm.search_depth = val.parse().unwrap(),
-                "mcts.search_depth" => params.mcts.search_depth = val.parse().unwrap(),
-                "eudm.layer_t" => params.eudm.layer_t = val.parse().unwrap(),
-                "mcts.layer_t" => params.mcts.layer_t = val.parse().unwrap(),
-                "mcts.total_forward_t" => params.mcts.total_forward_t = Some(val.parse().unwrap()),
-                "safety" => params.cost.safety_weight = val.parse().unwrap(),
-                "safety_margin_low" => params.cost.safety_margin_low = val.parse().unwrap(),
-                "safety_margin_high" => params.cost.safety_margin_high = val.parse().unwrap(),
-                "accel" => params.cost.accel_weight = val.parse().unwrap(),
-                "steer" => params.cost.steer_weight = val.parse().unwrap(),
-                "mcts.bound_mode" => params.mcts.bound_mode = val.parse().unwrap(),
-                "mcts.selection_mode" => params.mcts.selection_mode = val.parse().unwrap(),
-                "mcts.ucb_const" => params.mcts.ucb_const = val.parse().unwrap(),
-                "mcts.klucb_max_cost" => params.mcts.klucb_max_cost = val.parse().unwrap(),
-                "mcts.repeat_const" => params.mcts.repeat_const = val.parse().unwrap(),
-                "mcts.most_visited_best_cost_consistency" => {
-                    params.mcts.most_visited_best_cost_consistency = val.parse().unwrap()
-                }
-                "eudm.allow_different_root_policy" => {
-                    params.eudm.allow_different_root_policy = val.parse().unwrap()
-                }
-                _ => panic!("{} is not a valid parameter!", name),
-            }
+            set_parameter(&mut params, name, &val);
             if name_value_pairs.len() > 1 {
                 scenarios.append(&mut create_scenarios(&params, &name_value_pairs[1..]));
             } else {
-                scenarios.push(params);
+                scenarios.append(&mut seed_block(params));
             }
         }
     }
@@ -260,12 +919,18 @@ fn create_scenarios(
     }
 
     for s in scenarios.iter_mut() {
+        load_cost_spec(s);
+        assert!(planner::is_method(&s.method), "Unknown method {}", s.method);
+
         let samples_n = match s.method.as_str() {
             "fixed" => "".to_string(),
+            "mpdm" if s.mpdm.election_rounds > 0 => {
+                format_f!(",samples_n={s.mpdm.samples_n},election_rounds={s.mpdm.election_rounds}")
+            }
             "mpdm" => format_f!(",samples_n={s.mpdm.samples_n}"),
             "eudm" => format_f!(",samples_n={s.eudm.samples_n}"),
             "mcts" => format_f!(",samples_n={s.mcts.samples_n}"),
-            _ => panic!("Unknown method {}", s.method),
+            _ => "".to_string(),
         };
 
         let search_depth = match s.method.as_str() {
@@ -273,7 +938,7 @@ fn create_scenarios(
             "mpdm" => "".to_string(),
             "eudm" => format_f!(",search_depth={s.eudm.search_depth}"),
             "mcts" => format_f!(",search_depth={s.mcts.search_depth}"),
-            _ => panic!("Unknown method {}", s.method),
+            _ => "".to_string(),
         };
 
         let forward_t = match s.method.as_str() {
@@ -287,7 +952,29 @@ fn create_scenarios(
                     format_f!(",layer_t={s.mcts.layer_t}")
                 }
             }
-            _ => panic!("Unknown method {}", s.method),
+            _ => "".to_string(),
+        };
+
+        let prediction = match s.method.as_str() {
+            "mpdm" => s.mpdm.prediction.as_str(),
+            "eudm" => s.eudm.prediction.as_str(),
+            "mcts" => s.mcts.prediction.as_str(),
+            _ => "belief",
+        };
+        let prediction = if prediction != "belief" {
+            format_f!(",prediction={prediction}")
+        } else {
+            "".to_string()
+        };
+
+        let r = &s.risk;
+        let risk = match r.aggregation.as_str() {
+            "mean" => "".to_string(),
+            "worst" => ",risk=worst".to_string(),
+            "percentile" => format_f!(",risk=percentile:{r.percentile}"),
+            "cvar" => format_f!(",risk=cvar:{r.cvar_alpha}"),
+            "mean_std" => format_f!(",risk=mean_std:{r.std_k}"),
+            _ => panic!("Unknown risk aggregation {}", r.aggregation),
         };
 
         let selection_mode = match s.method.as_str() {
@@ -335,28 +1022,295 @@ fn create_scenarios(
             _ => "".to_string(),
         };
 
+        let contingency = if s.method == "eudm" && s.eudm.contingency {
+            format_f!(",contingency={s.eudm.contingency_brake_accel}")
+        } else {
+            "".to_string()
+        };
+        let cfb_k = if s.method == "eudm" && s.eudm.cfb_k > 0 {
+            format_f!(",cfb_k={s.eudm.cfb_k}:{s.eudm.cfb_criticality}")
+        } else {
+            "".to_string()
+        };
+
+        let scenario_file = if s.scenario_file.is_empty() {
+            "".to_string()
+        } else {
+            let stem = std::path::Path::new(&s.scenario_file).file_stem().unwrap();
+            let curvilinear =
+                commonroad::is_commonroad(&s.scenario_file) && s.commonroad.curvilinear;
+            let curvilinear = if curvilinear { ",curvilinear" } else { "" };
+            format!(",scenario={}{}", stem.to_string_lossy(), curvilinear)
+        };
+
+        let cost_file = if s.cost_file.is_empty() {
+            "".to_string()
+        } else {
+            let stem = std::path::Path::new(&s.cost_file).file_stem().unwrap();
+            format!(",cost={}", stem.to_string_lossy())
+        };
+
+        // left out for the original two lanes, to keep matching the existing results
+        let n_lanes = if s.n_lanes == 2 {
+            "".to_string()
+        } else {
+            format_f!(",n_lanes={s.n_lanes}")
+        };
+
+        let objective_mode = match s.objective_mode.as_str() {
+            "discounted" => "".to_string(),
+            "total" | "average" => format_f!(",objective={s.objective_mode}"),
+            _ => panic!("Unknown objective_mode {}", s.objective_mode),
+        };
+
+        let road_geometry = if s.road_geometry.is_empty() {
+            "".to_string()
+        } else {
+            // its segments are comma-separated too
+            format!(",road_geometry={}", s.road_geometry.replace(',', ";"))
+        };
+
+        let speed_limit = if s.speed_limit > 0.0 {
+            format_f!(",speed_limit={s.speed_limit}")
+        } else {
+            "".to_string()
+        };
+
+        // which sets up the road, lanes and speed limit when the run starts
+        let opendrive = if s.opendrive.file.is_empty() {
+            "".to_string()
+        } else {
+            let stem = std::path::Path::new(&s.opendrive.file).file_stem().unwrap();
+            let road = if s.opendrive.road.is_empty() {
+                "".to_string()
+            } else {
+                format!(":{}", s.opendrive.road)
+            };
+            format!(",opendrive={}{}", stem.to_string_lossy(), road)
+        };
+
+        let policies = policy_registry::policies_name(s);
+
+        let sumo = if s.sumo.port == 0 {
+            "".to_string()
+        } else {
+            format_f!(",sumo={s.sumo.sync_dt}")
+        };
+
+        let open_boundary = if s.spawn.open_boundary {
+            format_f!(",flow={s.spawn.flow}")
+        } else {
+            "".to_string()
+        };
+
+        let merge = if s.merge.ramp_lane < 0 {
+            "".to_string()
+        } else {
+            format_f!(",ramp_lane={s.merge.ramp_lane},ramp={s.merge.start}:{s.merge.end}:{s.merge.period}")
+        };
+
+        // left out for the original controller, to keep matching the existing results
+        let forward_control = if s.ego_forward_control == "intelligent_driver"
+            && s.obstacle_forward_control == "intelligent_driver"
+        {
+            "".to_string()
+        } else {
+            let i = &s.idm;
+            format_f!(",ego_control={s.ego_forward_control},obstacle_control={s.obstacle_forward_control},idm={i.desired_gap}:{i.time_headway}:{i.max_accel}:{i.comfortable_decel}:{i.accel_exponent}")
+        };
+
+        // left out for the original pure pursuit, to keep matching the existing results
+        let side_control = if s.ego_side_control == "pure_pursuit"
+            && s.obstacle_side_control == "pure_pursuit"
+            && s.pure_pursuit.ahead_time == 0.6
+        {
+            "".to_string()
+        } else {
+            let st = &s.stanley;
+            format_f!(",ego_steer={s.ego_side_control},obstacle_steer={s.obstacle_side_control},pp_ahead={s.pure_pursuit.ahead_time},stanley={st.gain}:{st.soft_vel}")
+        };
+
+        let dynamics = if s.ego_dynamics.dynamic {
+            let d = &s.ego_dynamics;
+            format_f!(",ego_dynamics={d.min_vel}:{d.mass}:{d.yaw_inertia}:{d.front_stiffness}:{d.rear_stiffness}")
+        } else {
+            "".to_string()
+        };
+
+        let actuators = &s.actuators;
+        let actuators = if actuators.accel_time_constant > 0.0
+            || actuators.steer_time_constant > 0.0
+            || actuators.max_steer_rate > 0.0
+        {
+            format_f!(",actuator_lag={actuators.accel_time_constant}:{actuators.steer_time_constant},max_steer_rate={actuators.max_steer_rate}")
+        } else {
+            "".to_string()
+        };
+
+        let driver_traits = if s.driver_traits.sample {
+            let d = &s.driver_traits;
+            format_f!(",traits=gap:{d.desired_gap_low}:{d.desired_gap_high}:polite:{d.politeness_low}:{d.politeness_high}:speed:{d.speed_offset_low}:{d.speed_offset_high}")
+        } else {
+            "".to_string()
+        };
+
+        let pn = &s.process_noise;
+        let process_noise =
+            if (pn.truth || pn.rollouts) && (pn.accel_std > 0.0 || pn.steer_std > 0.0) {
+                format_f!(",process_noise={pn.accel_std}:{pn.steer_std}:{pn.truth}:{pn.rollouts}")
+            } else {
+                "".to_string()
+            };
+
+        let mismatch = if s.mismatch.enabled {
+            let m = &s.mismatch;
+            format_f!(",mismatch={m.lane_change_time_scale}:{m.follow_time_offset}:{m.accel_scale}:{m.novel_fraction}")
+        } else {
+            "".to_string()
+        };
+
+        let sensor = &s.sensor;
+        let sensor = if sensor.range > 0.0 || sensor.position_std > 0.0 || sensor.vel_std > 0.0 {
+            format_f!(",sensor={sensor.range}:{sensor.position_std}:{sensor.vel_std}")
+        } else {
+            "".to_string()
+        };
+
+        let o = &s.occlusion;
+        let occlusion = if o.enabled || o.truck_fraction > 0.0 || o.phantoms > 0 {
+            format_f!(",occlusion={o.enabled},trucks={o.truck_fraction},phantoms={o.phantoms}:{o.phantom_range}")
+        } else {
+            "".to_string()
+        };
+
+        let dirichlet = if s.belief.dirichlet {
+            let b = &s.belief;
+            format_f!(",dirichlet={b.dirichlet_prior}:{b.evidence_rate}:{b.forgetting_rate}")
+        } else {
+            "".to_string()
+        };
+
+        let intent = if s.intent.enabled {
+            let i = &s.intent;
+            format_f!(
+                ",intent={i.change_prior_prob}:{i.lat_vel_std}:{i.blinker_reliability}:{i.rate}"
+            )
+        } else {
+            "".to_string()
+        };
+
+        let safety_filter = if s.safety_filter.enabled {
+            let f = &s.safety_filter;
+            format_f!(",safety_filter={f.ttc_threshold}:{f.activation_weight}")
+        } else {
+            "".to_string()
+        };
+
+        let goal = if s.goal.enabled {
+            let g = &s.goal;
+            format_f!(",goal={g.x}:{g.lane}:{g.time}:{g.lane_weight}:{g.late_weight}")
+        } else {
+            "".to_string()
+        };
+
+        let crash = if s.crash.severity {
+            format_f!(",crash={s.crash.base_weight}:{s.crash.speed_weight}")
+        } else {
+            "".to_string()
+        };
+
+        // only the weight changes how the ego drives, the rest only what gets measured
+        let rss = if s.rss.enabled && s.rss.weight > 0.0 {
+            let r = &s.rss;
+            format_f!(",rss={r.response_time}:{r.weight}")
+        } else {
+            "".to_string()
+        };
+
+        let mobil = if s.mobil.fraction > 0.0 {
+            let m = &s.mobil;
+            format_f!(",mobil={m.fraction}:{m.politeness}:{m.advantage_threshold}:{m.safe_decel}")
+        } else {
+            "".to_string()
+        };
+
+        let closure = if s.closure.lane < 0 {
+            "".to_string()
+        } else {
+            format_f!(",closed_lane={s.closure.lane},closure={s.closure.start}:{s.closure.end}:{s.closure.period}")
+        };
+
+        let pedestrians = if s.pedestrians.crosswalk_spacing > 0.0 {
+            let p = &s.pedestrians;
+            format_f!(",crosswalks={p.first_crosswalk}:{p.crosswalk_spacing},pedestrians={p.per_crosswalk},gap_time={p.gap_time},pedestrian_safety={p.safety_weight}")
+        } else {
+            "".to_string()
+        };
+
+        let signals = if s.signals.spacing > 0.0 {
+            let g = &s.signals;
+            format_f!(",signals={g.first}:{g.spacing}:{g.green_time}:{g.yellow_time}:{g.red_time}:{g.phase_offset},red_light={g.violation_weight}")
+        } else {
+            "".to_string()
+        };
+
+        let named_scenario = if s.named_scenario.is_empty() {
+            "".to_string()
+        } else {
+            format_f!(",named_scenario={s.named_scenario}")
+        };
+
         // "smoothness" => params.cost.smoothness_weight = val.parse().unwrap(),
         // "safety" => params.cost.safety_weight = val.parse().unwrap(),
         // "ud" => params.cost.uncomfortable_dec_weight = val.parse().unwrap(),
         // "cc" => params.cost.curvature_change_weight = val.parse().unwrap(),
         // "safety_margin" => params.cost.safety_margin = val.parse().unwrap(),
 
+        let rng_streams = if s.rng_streams {
+            ",rng_streams=true".to_string()
+        } else {
+            "".to_string()
+        };
+
+        let replan = if s.replan_interval > 0 {
+            format_f!(",replan_interval={s.replan_interval}")
+        } else {
+            format_f!(",replan_dt={s.replan_dt}")
+        };
+
+        let comfort = if s.cost.jerk_weight != 0.0 || s.cost.lat_accel_weight != 0.0 {
+            format_f!(",jerk={s.cost.jerk_weight},lat_accel={s.cost.lat_accel_weight}")
+        } else {
+            "".to_string()
+        };
+
+        let lane_keeping = if s.cost.lane_offset_weight != 0.0 {
+            format_f!(",lane_offset={s.cost.lane_offset_weight}")
+        } else {
+            "".to_string()
+        };
+
         s.scenario_name = Some(format_f!(
             ",method={s.method}\
              ,use_cfb={s.use_cfb}\
-             {samples_n}{search_depth}{forward_t}\
+             {samples_n}{search_depth}{forward_t}{prediction}{risk}\
              {selection_mode}{bound_mode}{ucb_const}{kluct_max_cost}{repeat_const}\
              {most_visited_best_cost_consistency}\
-             {allow_different_root_policy}\
+             {allow_different_root_policy}{contingency}{cfb_k}\
              ,max_steps={s.max_steps}\
              ,n_cars={s.n_cars}\
+             {n_lanes}{forward_control}{side_control}{dynamics}{actuators}{driver_traits}{process_noise}{mismatch}{sensor}{occlusion}{dirichlet}{intent}{safety_filter}{rss}{goal}{crash}{mobil}{policies}{road_geometry}{speed_limit}{opendrive}{sumo}{merge}{closure}{open_boundary}{pedestrians}{signals}\
+             {scenario_file}{named_scenario}{cost_file}\
              ,safety={s.cost.safety_weight}\
              ,safety_margin_low={s.cost.safety_margin_low}\
              ,safety_margin_high={s.cost.safety_margin_high}\
              ,accel={s.cost.accel_weight}\
              ,steer={s.cost.steer_weight}\
-             ,replan_dt={s.replan_dt}\
+             {comfort}{lane_keeping}\
+             {replan}\
              ,discount_factor={s.cost.discount_factor}\
+             {objective_mode}\
+             {rng_streams}\
              ,rng_seed={s.rng_seed}\
              ,"
         ));
@@ -364,155 +1318,3 @@ fn create_scenarios(
 
     scenarios
 }
-
-pub fn run_parallel_scenarios() {
-    let parameters_default = Parameters::new().unwrap();
-
-    // let args = std::env::args().collect_vec();
-    let mut name_value_pairs = Vec::<(String, Vec<String>)>::new();
-    // let mut arg_i = 0;
-    let mut name: Option<String> = None;
-    let mut vals: Option<Vec<String>> = None;
-    for arg in std::env::args()
-        .skip(1)
-        .chain(std::iter::once("::".to_owned()))
-    {
-        if arg == "--help" || arg == "help" {
-            eprintln!("Usage: (<param name> [param value]* ::)*");
-            eprintln!("For example: limit 8 12 16 24 32 :: steps 1000 :: rng_seed 0 1 2 3 4");
-            eprintln!("Valid parameters and their default values:");
-            let params_str = format!("{:?}", parameters_default)
-                .replace(", file_name: None", "")
-                .replace(", ", "\n\t")
-                .replace("Parameters { ", "\t")
-                .replace(" }", "");
-            eprintln!("{}", params_str);
-            std::process::exit(0);
-        }
-        if name.is_some() {
-            if arg == "::" {
-                let name = name.take().unwrap();
-                if name_value_pairs.iter().any(|pair| pair.0 == name) {
-                    panic!("Parameter {} has already been specified!", name);
-                }
-                name_value_pairs.push((name, vals.take().unwrap()));
-            } else {
-                vals.as_mut().unwrap().push(arg);
-            }
-        } else if arg != "::" {
-            name = Some(arg);
-            vals = Some(Vec::new());
-        }
-    }
-
-    // for (name, vals) in name_value_pairs.iter() {
-    //     eprintln!("{}: {:?}", name, vals);
-    // }
-
-    let mut base_scenario = parameters_default;
-    base_scenario.scenario_name = Some("".to_owned());
-
-    let scenarios = create_scenarios(&base_scenario, &name_value_pairs);
-    // for (i, scenario) in scenarios.iter().enumerate() {
-    //     eprintln!("{}: {:?}", i, scenario.file_name);
-    // }
-
-    let n_scenarios = scenarios.len();
-    eprintln!("Starting to run {} scenarios", n_scenarios);
-    if n_scenarios == 0 {
-        return;
-    }
-
-    let thread_limit = scenarios[0].thread_limit;
-    if thread_limit > 0 {
-        rayon::ThreadPoolBuilder::new()
-            .num_threads(thread_limit as usize)
-            .build_global()
-            .unwrap();
-    }
-
-    let load_and_record_results = scenarios[0].load_and_record_results;
-
-    let n_scenarios_completed = AtomicUsize::new(0);
-    let cumulative_results = Mutex::new(BTreeMap::new());
-
-    let cache_filename = "results.cache";
-    // read the existing cache file
-    if load_and_record_results {
-        let mut cumulative_results = cumulative_results.lock().unwrap();
-        if let Ok(file) = File::open(cache_filename) {
-            let file = BufReader::new(file);
-            for line in file.lines() {
-                let line = line.unwrap();
-                let parts = line.split_ascii_whitespace().collect_vec();
-                let scenario_name = parts[0].to_owned();
-                cumulative_results.insert(scenario_name, ());
-            }
-        }
-    }
-
-    let file = if load_and_record_results {
-        Some(Mutex::new(
-            OpenOptions::new()
-                .append(true)
-                .create(true)
-                .open(cache_filename)
-                .unwrap(),
-        ))
-    } else {
-        None
-    };
-
-    if n_scenarios == 1 {
-        let mut scenario = scenarios[0].clone();
-        scenario.is_single_run = true;
-
-        let scenario_name = scenario.scenario_name.clone().unwrap();
-        let (cost, reward) = run_with_parameters(scenario);
-        println_f!("{scenario_name}");
-        println_f!("{cost:?}, {reward:?}");
-    } else {
-        scenarios.par_iter().for_each(|scenario| {
-            let result = std::panic::catch_unwind(|| {
-                let scenario_name = scenario.scenario_name.clone().unwrap();
-
-                if cumulative_results
-                    .lock()
-                    .unwrap()
-                    .contains_key(&scenario_name)
-                {
-                    n_scenarios_completed.fetch_add(1, Ordering::Relaxed);
-                    return;
-                }
-
-                let start_time = Instant::now();
-                let (cost, reward) = run_with_parameters(scenario.clone());
-                let seconds = start_time.elapsed().as_secs_f64();
-
-                n_scenarios_completed.fetch_add(1, Ordering::Relaxed);
-                print!(
-                    "{}/{} ({}): ",
-                    n_scenarios_completed.load(Ordering::Relaxed),
-                    n_scenarios,
-                    scenario.rng_seed,
-                );
-                println_f!("{cost} {reward} {seconds:6.2}");
-                if let Some(ref file) = file {
-                    writeln_f!(
-                        file.lock().unwrap(),
-                        "{scenario_name} {cost} {reward} {seconds:6.2}"
-                    )
-                    .unwrap();
-                }
-
-                cumulative_results.lock().unwrap().insert(scenario_name, ());
-            });
-            if result.is_err() {
-                eprintln!(
-                    "PANIC for scenario: {:?}",
-                    scenario.scenario_name.as_ref().unwrap()
-                );
-            }
-        });
-    }
-}
@@ -1,16 +1,96 @@
+use std::f64::consts::PI;
+
 use itertools::Itertools;
+use parry2d_f64::na::Point2;
 use rand::{
     distributions::WeightedIndex,
     prelude::{Distribution, StdRng},
 };
+use rvx::{Rvx, RvxColor};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    car::Car,
+    lane_change_policy::LongitudinalPolicy,
+    mpdm::make_obstacle_vehicle_policy_belief_states,
+    render::{self, Painter},
+    road::{Road, LANE_WIDTH},
+    side_policies::SidePolicy,
+};
+
+// how tall the belief overlay's bar for a certain policy is, in m
+const OVERLAY_BAR_HEIGHT: f64 = 3.0;
+
+// What a car means to do over the next lane change time, indexing the belief's intent estimate.
+// Left is toward the higher lanes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Intent {
+    Stay = 0,
+    ChangeLeft = 1,
+    ChangeRight = 2,
+}
+
+impl Intent {
+    pub const ALL: [Intent; 3] = [Intent::Stay, Intent::ChangeLeft, Intent::ChangeRight];
+
+    pub fn from_lanes(current_lane_i: i32, target_lane_i: i32) -> Self {
+        if target_lane_i > current_lane_i {
+            Intent::ChangeLeft
+        } else if target_lane_i < current_lane_i {
+            Intent::ChangeRight
+        } else {
+            Intent::Stay
+        }
+    }
+
+    // the lateral velocity a car with this intent would have
+    fn lat_vel(self, lane_change_time: f64) -> f64 {
+        match self {
+            Intent::Stay => 0.0,
+            Intent::ChangeLeft => LANE_WIDTH / lane_change_time,
+            Intent::ChangeRight => -LANE_WIDTH / lane_change_time,
+        }
+    }
+}
 
-use crate::{lane_change_policy::LongitudinalPolicy, road::Road};
+fn intent_prior(road: &Road) -> [f64; 3] {
+    let change = road.params.intent.change_prior_prob;
+    [1.0 - 2.0 * change, change, change]
+}
+
+// the intent estimate from what the car is doing right now:
+// its lateral velocity and where its blinker points
+fn observe_intent(road: &Road, car_i: usize) -> [f64; 3] {
+    let iparams = &road.params.intent;
+    let car = &road.cars[car_i];
+    let lat_vel = car.vel * car.theta().sin();
+    let blinker = Intent::from_lanes(car.current_lane(), car.target_lane_i);
+
+    let mut probs = intent_prior(road);
+    for intent in Intent::ALL {
+        let z = (lat_vel - intent.lat_vel(road.params.lane_change_time)) / iparams.lat_vel_std;
+        probs[intent as usize] *= (-0.5 * z * z).exp();
+        probs[intent as usize] *= if intent == blinker {
+            iparams.blinker_reliability
+        } else {
+            (1.0 - iparams.blinker_reliability) / 2.0
+        };
+    }
+    // in case the lateral velocity is far off from them all
+    if probs.iter().sum::<f64>() < 1e-100 {
+        return intent_prior(road);
+    }
+    normalize(&mut probs);
+    probs
+}
 
 fn predict_lane(road: &Road, car_i: usize) -> i32 {
     let car = &road.cars[car_i];
     let predicted_y =
         car.y() + car.vel * (car.theta() + car.steer).sin() * road.params.lane_change_time;
-    Road::get_lane_i(predicted_y).min(1).max(0)
+    Road::get_lane_i(predicted_y)
+        .min(road.params.n_lanes - 1)
+        .max(0)
 }
 
 fn predict_long(road: &Road, car_i: usize) -> LongitudinalPolicy {
@@ -49,14 +129,28 @@ fn normalize(belief: &mut [f64]) {
     }
 }
 
-#[derive(Clone)]
+// the mean of the Dirichlet distribution with prior pseudo-counts for each policy plus counts
+fn dirichlet_mean(belief: &mut [f64], counts: &[f64], prior: f64) {
+    for (prob, count) in belief.iter_mut().zip(counts) {
+        *prob = prior + count;
+    }
+    normalize(belief);
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Belief {
     belief: Vec<Vec<f64>>,
+    // by car_i, the evidence for each policy, with belief.dirichlet
+    counts: Vec<Vec<f64>>,
+    // by car_i, the probabilities of each Intent, with intent.enabled
+    intent: Vec<[f64; 3]>,
 }
 impl Belief {
     pub fn uniform(n_cars: usize, n_policies: usize) -> Self {
         Self {
             belief: vec![vec![1.0 / n_policies as f64; n_policies]; n_cars],
+            counts: vec![vec![0.0; n_policies]; n_cars],
+            intent: vec![[1.0 / 3.0; 3]; n_cars],
         }
     }
 
@@ -66,13 +160,50 @@ impl Belief {
         normalize(&mut single_belief);
 
         Self {
+            counts: vec![vec![0.0; single_belief.len()]; n_cars],
             belief: vec![single_belief; n_cars],
+            intent: vec![[1.0 / 3.0; 3]; n_cars],
         }
     }
 
-    pub fn update(&mut self, road: &Road) {
+    // with visible by car_i, the beliefs about the cars that weren't seen
+    // go back toward the uniform prior at unseen_reversion_rate
+    pub fn update(&mut self, road: &Road, visible: Option<&[bool]>) {
         let bparams = &road.params.belief;
+        let iparams = &road.params.intent;
+        let reversion = 1.0 - (-bparams.unseen_reversion_rate * road.params.physics_dt).exp();
+        let intent_step = 1.0 - (-iparams.rate * road.params.physics_dt).exp();
+        let kept = (-bparams.forgetting_rate * road.params.physics_dt).exp();
+        // the belief is over these, in order
+        let policies = make_obstacle_vehicle_policy_belief_states(&road.params);
         for (car_i, belief) in self.belief.iter_mut().enumerate().skip(1) {
+            let intent = &mut self.intent[car_i];
+            let counts = &mut self.counts[car_i];
+            if visible.map_or(false, |visible| !visible[car_i]) {
+                if bparams.dirichlet {
+                    // without new evidence, forgetting takes it back to the prior
+                    for count in counts.iter_mut() {
+                        *count *= kept;
+                    }
+                    dirichlet_mean(belief, counts, bparams.dirichlet_prior);
+                } else {
+                    let prior = 1.0 / belief.len() as f64;
+                    for prob in belief.iter_mut() {
+                        *prob += (prior - *prob) * reversion;
+                    }
+                }
+                if iparams.enabled {
+                    for (prob, prior) in intent.iter_mut().zip(intent_prior(road)) {
+                        *prob += (prior - *prob) * reversion;
+                    }
+                }
+                continue;
+            }
+            if iparams.enabled {
+                for (prob, observed) in intent.iter_mut().zip(observe_intent(road, car_i)) {
+                    *prob += (observed - *prob) * intent_step;
+                }
+            }
             let pred_lane = predict_lane(road, car_i);
             let pred_long = predict_long(road, car_i);
             let pred_finished_waiting = predict_finished_waiting(road, car_i);
@@ -81,71 +212,121 @@ impl Belief {
                 && road.params.belief_debug
                 && road.params.debug_car_i == Some(car_i)
             {
-                eprintln_f!("{pred_lane=} {pred_long=:?} {pred_finished_waiting=}");
+                trace_f!("{pred_lane=} {pred_long=:?} {pred_finished_waiting=}");
             }
 
+            // decelerating keeps to the lane, and MOBIL could go either way
+            let (stay_prob, any_prob) = if iparams.enabled {
+                (intent[Intent::Stay as usize], 1.0 / 3.0)
+            } else {
+                (1.0, 1.0)
+            };
             belief.clear();
-            for &lane_i in &[0, 1] {
-                for long_policy in [LongitudinalPolicy::Maintain, LongitudinalPolicy::Accelerate] {
-                    for wait_for_clear in [false, true] {
-                        let mut prob = 1.0;
-                        if lane_i != pred_lane {
-                            prob *= bparams.different_lane_prob;
-                        }
+            for policy in policies.iter() {
+                let policy = match policy {
+                    SidePolicy::LaneChangePolicy(policy) => policy,
+                    SidePolicy::MobilPolicy(_) => {
+                        belief.push(bparams.mobil_prior_prob * any_prob);
+                        continue;
+                    }
+                    _ => panic!("No belief model for {:?}", policy),
+                };
+                let long_policy = policy.long_policy();
+                let wait_for_clear = policy.wait_for_clear();
+                let lane_i = match policy.target_lane_i() {
+                    Some(lane_i) => lane_i,
+                    // staying in the current lane
+                    None => {
+                        let mut prob = if long_policy == LongitudinalPolicy::Decelerate {
+                            bparams.decelerate_prior_prob
+                        } else {
+                            1.0
+                        };
                         if long_policy != pred_long {
                             prob *= bparams.different_longitudinal_prob;
                         }
-                        // wait_for_clear && pred_finished_waiting: already making lane change
-                        // !wait_for_clear && pred_finished_waiting: already making lane change
-                        // wait_for_clear && !pred_finished_waiting: still need to wait
-                        // !wait_for_clear && !pred_finished_waiting: will start lane change
-                        let would_lane_change = pred_finished_waiting || !wait_for_clear;
-                        let current_lane_i = road.cars[car_i].current_lane();
-                        let wants_lane_change = lane_i != current_lane_i;
-                        let will_lane_change = would_lane_change && wants_lane_change;
-                        // either we can make the lane change, and might as well use wait_for_clear=false
-                        // or we still need to wait and so use wait_for_clear=true
-                        // the other scenarios are superfluous, or inaccurate
-                        if will_lane_change && wait_for_clear {
-                            prob = 0.0;
-                        }
-                        // waiting... to _not_ change lanes is also pointless
-                        if !wants_lane_change && wait_for_clear {
-                            prob = 0.0;
-                        }
-                        // the chance that the vehicle effectively skips checking for it to be clear before turning
-                        // in practice, this would more mean that noise prevented us from telling that they already started turning(?)
-                        if wants_lane_change && !pred_finished_waiting && !wait_for_clear {
-                            prob *= bparams.skips_waiting_prob;
-                        }
-                        belief.push(prob);
-
-                        if road.super_debug()
-                            && road.params.belief_debug
-                            && road.params.debug_car_i == Some(car_i)
-                        {
-                            eprintln_f!("{road.timesteps}: {car_i=} {lane_i=} {long_policy=:?} {wait_for_clear=}: {prob=:.2}, would: {would_lane_change}, wants: {wants_lane_change}, will: {will_lane_change}");
-                        }
+                        belief.push(prob * stay_prob);
+                        continue;
                     }
+                };
+
+                let mut prob = 1.0;
+                if lane_i != pred_lane {
+                    prob *= bparams.different_lane_prob;
+                }
+                if long_policy != pred_long {
+                    prob *= bparams.different_longitudinal_prob;
+                }
+                // wait_for_clear && pred_finished_waiting: already making lane change
+                // !wait_for_clear && pred_finished_waiting: already making lane change
+                // wait_for_clear && !pred_finished_waiting: still need to wait
+                // !wait_for_clear && !pred_finished_waiting: will start lane change
+                let would_lane_change = pred_finished_waiting || !wait_for_clear;
+                let current_lane_i = road.cars[car_i].current_lane();
+                let wants_lane_change = lane_i != current_lane_i;
+                let will_lane_change = would_lane_change && wants_lane_change;
+                // either we can make the lane change, and might as well use wait_for_clear=false
+                // or we still need to wait and so use wait_for_clear=true
+                // the other scenarios are superfluous, or inaccurate
+                if will_lane_change && wait_for_clear {
+                    prob = 0.0;
+                }
+                // waiting... to _not_ change lanes is also pointless
+                if !wants_lane_change && wait_for_clear {
+                    prob = 0.0;
+                }
+                // the chance that the vehicle effectively skips checking for it to be clear before turning
+                // in practice, this would more mean that noise prevented us from telling that they already started turning(?)
+                if wants_lane_change && !pred_finished_waiting && !wait_for_clear {
+                    prob *= bparams.skips_waiting_prob;
+                }
+                if iparams.enabled {
+                    prob *= intent[Intent::from_lanes(current_lane_i, lane_i) as usize];
+                }
+                belief.push(prob);
+
+                if road.super_debug()
+                    && road.params.belief_debug
+                    && road.params.debug_car_i == Some(car_i)
+                {
+                    trace_f!("{road.timesteps}: {car_i=} {lane_i=} {long_policy=:?} {wait_for_clear=}: {prob=:.2}, would: {would_lane_change}, wants: {wants_lane_change}, will: {will_lane_change}");
                 }
-            }
-            if LongitudinalPolicy::Decelerate == pred_long {
-                belief.push(bparams.decelerate_prior_prob);
-            } else {
-                belief.push(bparams.decelerate_prior_prob * bparams.different_longitudinal_prob);
             }
 
             normalize(belief);
 
+            if bparams.dirichlet {
+                let evidence = bparams.evidence_rate * road.params.physics_dt;
+                for (count, prob) in counts.iter_mut().zip(belief.iter()) {
+                    *count = *count * kept + evidence * prob;
+                }
+                dirichlet_mean(belief, counts, bparams.dirichlet_prior);
+            }
+
             if road.params.belief_debug
                 && road.super_debug()
                 && road.params.debug_car_i == Some(car_i)
             {
-                eprintln_f!("{road.timesteps}: Belief about {car_i}: {belief:.2?}");
+                debug_f!("{road.timesteps}: Belief about {car_i}: {belief:.2?}");
             }
         }
     }
 
+    // a new car with a uniform belief
+    pub fn add_car(&mut self) {
+        let n_policies = self.belief[0].len();
+        self.belief.push(vec![1.0 / n_policies as f64; n_policies]);
+        self.counts.push(vec![0.0; n_policies]);
+        self.intent.push([1.0 / 3.0; 3]);
+    }
+
+    // same as the road's cars.swap_remove(car_i)
+    pub fn swap_remove_car(&mut self, car_i: usize) {
+        self.belief.swap_remove(car_i);
+        self.counts.swap_remove(car_i);
+        self.intent.swap_remove(car_i);
+    }
+
     pub fn sample(&self, rng: &mut StdRng) -> Vec<usize> {
         self.belief
             .iter()
@@ -153,6 +334,25 @@ impl Belief {
             .collect_vec()
     }
 
+    pub fn sample_car(&self, car_i: usize, rng: &mut StdRng) -> usize {
+        WeightedIndex::new(&self.belief[car_i]).unwrap().sample(rng)
+    }
+
+    // for drawing many samples, drawing the same ones from the same rng as sample_car
+    pub fn sampler(&self) -> BeliefSampler {
+        BeliefSampler {
+            cars: self
+                .belief
+                .iter()
+                .map(|weights| WeightedIndex::new(weights).unwrap())
+                .collect(),
+        }
+    }
+
+    pub fn n_cars(&self) -> usize {
+        self.belief.len()
+    }
+
     pub fn get(&self, car_i: usize, policy_id: usize) -> f64 {
         assert_ne!(car_i, 0);
         self.belief[car_i][policy_id]
@@ -162,6 +362,12 @@ impl Belief {
         &self.belief[car_i]
     }
 
+    // the probabilities of each Intent for car_i, with intent.enabled
+    pub fn get_intent(&self, car_i: usize) -> [f64; 3] {
+        assert_ne!(car_i, 0);
+        self.intent[car_i]
+    }
+
     pub fn get_most_likely(&self, car_i: usize) -> usize {
         assert_ne!(car_i, 0);
         self.belief[car_i]
@@ -172,6 +378,137 @@ impl Belief {
             .0
     }
 
+    // Of the obstacle cars whose most likely policy has a lane (not MOBIL), how many it has
+    // headed for another lane than they really are, and how many there are, for how well
+    // the policies explain the traffic
+    pub fn lane_errors(&self, road: &Road) -> (u32, u32) {
+        let policies = make_obstacle_vehicle_policy_belief_states(&road.params);
+        let (mut errors, mut checks) = (0, 0);
+        for (car_i, car) in road.cars.iter().enumerate().skip(1) {
+            if car.crashed || car_i >= self.belief.len() {
+                continue;
+            }
+            let lane_i = match &policies[self.get_most_likely(car_i)] {
+                SidePolicy::LaneChangePolicy(p) => {
+                    p.target_lane_i().unwrap_or_else(|| car.current_lane())
+                }
+                _ => continue,
+            };
+            checks += 1;
+            if lane_i != car.target_lane_i {
+                errors += 1;
+            }
+        }
+        (errors, checks)
+    }
+
+    // the entropy of the belief about car_i, in bits
+    pub fn entropy(&self, car_i: usize) -> f64 {
+        assert_ne!(car_i, 0);
+        -self.belief[car_i]
+            .iter()
+            .filter(|&&prob| prob > 0.0)
+            .map(|prob| prob * prob.log2())
+            .sum::<f64>()
+    }
+
+    // averaged over the obstacle cars, or 0 without any
+    pub fn mean_entropy(&self) -> f64 {
+        let n_cars = self.belief.len() - 1;
+        if n_cars == 0 {
+            return 0.0;
+        }
+        (1..=n_cars).map(|car_i| self.entropy(car_i)).sum::<f64>() / n_cars as f64
+    }
+
+    // Next to each obstacle car, a bar for each policy as tall as its probability,
+    // with the most likely one highlighted, and the entropy of the belief about it
+    pub fn draw(&self, road: &Road, r: &mut Rvx, world_car: impl Fn(&Car) -> Car) {
+        for (car_i, car) in road.cars.iter().enumerate().skip(1) {
+            if car_i >= self.belief.len() {
+                break;
+            }
+            let car = world_car(car);
+            let belief = &self.belief[car_i];
+            let most_likely = self.get_most_likely(car_i);
+            let bar_width = car.length / belief.len() as f64;
+            // from the car's back to its front, rising from its left side
+            for (policy_i, &prob) in belief.iter().enumerate() {
+                let height = (prob * OVERLAY_BAR_HEIGHT).max(0.05);
+                let center = car.pose()
+                    * Point2::new(
+                        -car.length / 2.0 + (policy_i as f64 + 0.5) * bar_width,
+                        car.width / 2.0 + 0.2 + height / 2.0,
+                    );
+                let color = if policy_i == most_likely {
+                    RvxColor::YELLOW
+                } else {
+                    RvxColor::WHITE
+                };
+                r.draw(
+                    Rvx::square()
+                        .scale_xy(&[bar_width * 0.8, height])
+                        .rot(car.theta())
+                        .translate(&[center.x, center.y])
+                        .color(color.set_a(0.8)),
+                );
+            }
+            let label = car.pose()
+                * Point2::new(car.length / 2.0, car.width / 2.0 + OVERLAY_BAR_HEIGHT + 0.5);
+            r.draw(
+                Rvx::text(&format!("H: {:.2}", self.entropy(car_i)), "Arial", 40.0)
+                    .rot(-PI / 2.0)
+                    .translate(&[label.x, label.y])
+                    .color(RvxColor::WHITE),
+            );
+        }
+    }
+
+    // the same bars and entropies for drawing outside of rvx
+    pub fn paint(&self, road: &Road, painter: &mut impl Painter) {
+        for (car_i, car) in road.cars.iter().enumerate().skip(1) {
+            if car_i >= self.belief.len() {
+                break;
+            }
+            let car = road.world_car(car);
+            let belief = &self.belief[car_i];
+            let most_likely = self.get_most_likely(car_i);
+            let bar_width = car.length / belief.len() as f64;
+            for (policy_i, &prob) in belief.iter().enumerate() {
+                let height = (prob * OVERLAY_BAR_HEIGHT).max(0.05);
+                let center = car.pose()
+                    * Point2::new(
+                        -car.length / 2.0 + (policy_i as f64 + 0.5) * bar_width,
+                        car.width / 2.0 + 0.2 + height / 2.0,
+                    );
+                let color = if policy_i == most_likely {
+                    render::YELLOW
+                } else {
+                    render::WHITE
+                };
+                painter.fill_rect(
+                    (center.x, center.y),
+                    bar_width * 0.8,
+                    height,
+                    car.theta(),
+                    color,
+                    0.8,
+                );
+            }
+            let label = car.pose()
+                * Point2::new(
+                    -car.length / 2.0,
+                    car.width / 2.0 + OVERLAY_BAR_HEIGHT + 0.5,
+                );
+            painter.text(
+                (label.x, label.y),
+                &format!("H: {:.2}", self.entropy(car_i)),
+                0.8,
+                render::WHITE,
+            );
+        }
+    }
+
     pub fn is_uncertain(&self, car_i: usize, threshold: f64) -> bool {
         assert_ne!(car_i, 0);
         if self.belief[car_i].len() <= 1 {
@@ -186,3 +523,103 @@ impl Belief {
         (values[0] - values[1]) < threshold
     }
 }
+
+// The belief's distribution over each car's policies, built once
+pub struct BeliefSampler {
+    cars: Vec<WeightedIndex<f64>>,
+}
+
+impl BeliefSampler {
+    pub fn n_cars(&self) -> usize {
+        self.cars.len()
+    }
+
+    pub fn sample_car(&self, car_i: usize, rng: &mut StdRng) -> usize {
+        self.cars[car_i].sample(rng)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        arg_parameters::Parameters, car::Car, mpdm::make_obstacle_vehicle_policy_belief_states,
+    };
+
+    #[test]
+    fn test_intent() {
+        let mut params = Parameters::new().unwrap();
+        params.intent.enabled = true;
+        let n_policies = make_obstacle_vehicle_policy_belief_states(&params).len();
+        let mut road = Road::new(Arc::new(params));
+        road.cars[0].set_x(-50.0);
+        let mut car = Car::new(&road.params, 1, 0);
+        car.vel = 10.0;
+        road.cars.push(car);
+        road.update_cars_spatial();
+
+        // keeping to its lane
+        let mut belief = Belief::uniform(road.cars.len(), n_policies);
+        for _ in 0..100 {
+            belief.update(&road, None);
+        }
+        let intent = belief.get_intent(1);
+        assert!(intent[Intent::Stay as usize] > 0.9, "{:?}", intent);
+
+        // heading over to the left with its blinker on
+        road.cars[1].set_theta(0.15);
+        road.cars[1].target_lane_i = 1;
+        for _ in 0..100 {
+            belief.update(&road, None);
+        }
+        let intent = belief.get_intent(1);
+        assert!(intent[Intent::ChangeLeft as usize] > 0.9, "{:?}", intent);
+
+        // and the policies staying in lane 0 become unlikely
+        let lane_0_prob: f64 = belief.get_all(1)[..4].iter().sum();
+        assert!(lane_0_prob < 0.1, "{}", lane_0_prob);
+    }
+
+    #[test]
+    fn test_entropy() {
+        let belief = Belief::uniform(3, 8);
+        assert!((belief.entropy(1) - 3.0).abs() < 1e-9);
+
+        let belief = Belief::for_all_cars(3, &[1.0, 0.0, 0.0]);
+        assert_eq!(belief.entropy(2), 0.0);
+        assert_eq!(belief.mean_entropy(), 0.0);
+    }
+
+    #[test]
+    fn test_dirichlet_readapts() {
+        let mut params = Parameters::new().unwrap();
+        params.belief.dirichlet = true;
+        let n_policies = make_obstacle_vehicle_policy_belief_states(&params).len();
+        let steps_per_s = (1.0 / params.physics_dt) as usize;
+        let mut road = Road::new(Arc::new(params));
+        road.cars[0].set_x(-50.0);
+        let mut car = Car::new(&road.params, 1, 0);
+        car.vel = 10.0;
+        road.cars.push(car);
+        road.update_cars_spatial();
+        let lane_1_prob = |belief: &Belief| belief.get_all(1)[4..8].iter().sum::<f64>();
+
+        // confident it keeps to lane 0 after a while
+        let mut belief = Belief::uniform(road.cars.len(), n_policies);
+        for _ in 0..10 * steps_per_s {
+            belief.update(&road, None);
+        }
+        assert!(lane_1_prob(&belief) < 0.3, "{:?}", belief.get_all(1));
+
+        // when it starts heading for lane 1, that doesn't take over right away, but it does soon
+        road.cars[1].set_theta(0.15);
+        belief.update(&road, None);
+        assert!(lane_1_prob(&belief) < 0.3, "{:?}", belief.get_all(1));
+        for _ in 0..3 * steps_per_s {
+            belief.update(&road, None);
+        }
+        assert!(lane_1_prob(&belief) > 0.5, "{:?}", belief.get_all(1));
+    }
+}
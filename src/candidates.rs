@@ -0,0 +1,177 @@
+use std::{cell::Cell, cell::RefCell, f64::consts::PI};
+
+use rvx::{Rvx, RvxColor};
+use serde::Serialize;
+
+use crate::{
+    mpdm::make_policy_choices,
+    road::{Road, LANE_WIDTH},
+};
+
+// how many of the candidates the overlay lists, best first
+const OVERLAY_ROWS: usize = 5;
+// m along the road between the overlay's rows
+const OVERLAY_ROW_SPACING: f64 = 3.0;
+
+// A policy the ego's planner weighed at a replan: its id among make_policy_choices(), the
+// estimated cost of switching to it (the least over the ways the planner tried it), and
+// whether it's the one the planner chose
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct Candidate {
+    pub policy_id: u32,
+    pub cost: f64,
+    pub chosen: bool,
+}
+
+thread_local! {
+    // the candidates of the latest planning on this thread, kept so recording doesn't allocate
+    static CANDIDATES: RefCell<Vec<Candidate>> = RefCell::new(Vec::new());
+    // whether the planning traces get the candidates overlay, see candidates_overlay()
+    static OVERLAY: Cell<bool> = Cell::new(false);
+}
+
+// before each planning, so a planner that doesn't record any leaves none
+pub fn clear_candidates() {
+    CANDIDATES.with(|c| c.borrow_mut().clear());
+}
+
+// For the planners: a cost for policy_id, which keeps the lowest of the costs it gets
+pub fn record_candidate(policy_id: u32, cost: f64) {
+    CANDIDATES.with(|c| {
+        let mut candidates = c.borrow_mut();
+        match candidates.iter_mut().find(|c| c.policy_id == policy_id) {
+            Some(candidate) => candidate.cost = candidate.cost.min(cost),
+            None => candidates.push(Candidate {
+                policy_id,
+                cost,
+                chosen: false,
+            }),
+        }
+    })
+}
+
+// and which of them it chose
+pub fn choose_candidate(policy_id: u32) {
+    CANDIDATES.with(|c| {
+        for candidate in c.borrow_mut().iter_mut() {
+            candidate.chosen = candidate.policy_id == policy_id;
+        }
+    })
+}
+
+// The candidates of the latest planning on this thread, into candidates
+pub fn copy_candidates(candidates: &mut Vec<Candidate>) {
+    CANDIDATES.with(|c| candidates.clone_from(&c.borrow()));
+}
+
+pub fn take_candidates() -> Vec<Candidate> {
+    CANDIDATES.with(|c| c.take())
+}
+
+// for the candidates that other threads recorded for this one
+pub fn add_candidates(candidates: &[Candidate]) {
+    for candidate in candidates {
+        record_candidate(candidate.policy_id, candidate.cost);
+    }
+}
+
+pub fn set_candidates_overlay(show: bool) {
+    OVERLAY.with(|o| o.set(show));
+}
+
+pub fn candidates_overlay_shown() -> bool {
+    OVERLAY.with(|o| o.get())
+}
+
+// The candidates from best to worst, as text lines like "> 3 lane Some(1), Maintain: 12.40"
+// for the chosen one and "  0 lane None, Accelerate: +0.85" for a runner-up, by how much
+// more than the chosen one it cost
+pub fn describe(road: &Road, candidates: &[Candidate]) -> Vec<String> {
+    let policies = make_policy_choices(&road.params);
+    let chosen_cost = candidates.iter().find(|c| c.chosen).map(|c| c.cost);
+    let mut sorted = candidates.to_vec();
+    sorted.sort_by(|a, b| a.cost.partial_cmp(&b.cost).unwrap());
+    sorted
+        .iter()
+        .map(|c| {
+            let policy = policies
+                .get(c.policy_id as usize)
+                .map_or("?".to_owned(), |p| format!("{:?}", p));
+            match chosen_cost {
+                Some(chosen_cost) if !c.chosen => format!(
+                    "  {} {}: {:+.2}",
+                    c.policy_id,
+                    policy.trim(),
+                    c.cost - chosen_cost
+                ),
+                _ => format!(
+                    "{} {} {}: {:.2}",
+                    if c.chosen { ">" } else { " " },
+                    c.policy_id,
+                    policy.trim(),
+                    c.cost
+                ),
+            }
+        })
+        .collect()
+}
+
+// The best few candidates as text beside the road ahead of the ego, the chosen one in white
+// and the runner-ups in yellow with how much more they cost
+pub fn candidates_overlay(road: &Road, candidates: &[Candidate]) -> Vec<rvx::Shape> {
+    let high_y = (road.params.n_lanes - 1) as f64 * LANE_WIDTH;
+    let mut sorted = candidates.to_vec();
+    sorted.sort_by(|a, b| a.cost.partial_cmp(&b.cost).unwrap());
+    let lines = describe(road, candidates);
+    let ego_x = road.cars[0].x();
+    sorted
+        .iter()
+        .zip(lines)
+        .take(OVERLAY_ROWS)
+        .enumerate()
+        .map(|(row, (candidate, line))| {
+            let s = ego_x + 20.0 - row as f64 * OVERLAY_ROW_SPACING;
+            let (x, y) = road.geometry.to_cartesian(s, high_y + 2.0 * LANE_WIDTH);
+            let color = if candidate.chosen {
+                RvxColor::WHITE
+            } else {
+                RvxColor::YELLOW
+            };
+            Rvx::text(&line, "Arial", 40.0)
+                .rot(-PI / 2.0)
+                .translate(&[x, y])
+                .color(color)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::arg_parameters::Parameters;
+
+    #[test]
+    fn test_record_candidates() {
+        clear_candidates();
+        record_candidate(2, 5.0);
+        record_candidate(0, 3.0);
+        record_candidate(2, 4.0);
+        record_candidate(2, 6.0);
+        choose_candidate(0);
+        let mut candidates = Vec::new();
+        copy_candidates(&mut candidates);
+        assert_eq!(candidates.len(), 2);
+        assert_eq!(candidates[0].cost, 4.0);
+        assert!(candidates[1].chosen && !candidates[0].chosen);
+
+        let road = Road::new(Arc::new(Parameters::new().unwrap()));
+        let lines = describe(&road, &candidates);
+        assert!(lines[0].starts_with("> 0 "), "{:?}", lines);
+        assert!(lines[1].ends_with(": +1.00"), "{:?}", lines);
+        clear_candidates();
+        copy_candidates(&mut candidates);
+        assert!(candidates.is_empty());
+    }
+}
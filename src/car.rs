@@ -3,27 +3,30 @@ use std::f64::consts::PI;
 use nalgebra::vector;
 use parry2d_f64::{
     bounding_volume::AABB,
-    na::Isometry2,
+    na::{Isometry2, RealField, Translation2, UnitComplex, Vector2},
     shape::{Cuboid, Shape},
 };
 use rand::prelude::{Rng, StdRng};
 use rvx::{Rvx, RvxColor};
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    arg_parameters::Parameters,
-    forward_control::ForwardControl,
-    intelligent_driver::IntelligentDriverPolicy,
+    arg_parameters::{DynamicsParameters, Parameters},
+    forward_control::{make_forward_control, ForwardControl},
+    lane_change_policy::LongitudinalPolicy,
+    mismatch,
     mpdm::make_obstacle_vehicle_policy_choices,
     open_loop_policy::{OpenLoopForwardControl, OpenLoopPolicy, OpenLoopSideControl},
-    pure_pursuit::PurePursuitPolicy,
+    policy_registry::{find_lane_change, find_mobil},
     road::{Road, ROAD_LENGTH},
-    side_control::{SideControl, SideControlTrait},
+    side_control::{make_side_control, SideControl, SideControlTrait},
     side_policies::{SidePolicy, SidePolicyTrait},
-    AHEAD_TIME_DEFAULT,
 };
 
 pub const PRIUS_WIDTH: f64 = 1.76;
 pub const PRIUS_LENGTH: f64 = 4.57;
+pub const TRUCK_WIDTH: f64 = 2.6;
+pub const TRUCK_LENGTH: f64 = 12.0;
 pub const PRIUS_MAX_STEER: f64 = 1.11; // from minimum turning radius of 4.34 meters and PRIUS_LENGTH
 pub const MPH_TO_MPS: f64 = 0.44704;
 pub const MPS_TO_MPH: f64 = 2.23694;
@@ -42,7 +45,36 @@ pub const PREFERRED_ACCEL_HIGH: f64 = 2.0; // 11.2; // model s, 2.4s zero to six
 pub const PREFERRED_ACCEL_DEFAULT: f64 = 2.0; // 16s zero to sixty, just under max accel for a prius (13s)
 pub const BREAKING_ACCEL: f64 = 6.0;
 
-#[derive(Clone, Debug)]
+// An obstacle car driver's latent traits, sampled at spawn from params.driver_traits
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DriverTraits {
+    // the standstill gap (m) it keeps, in place of FOLLOW_DIST_BASE or idm.desired_gap
+    pub desired_gap: f64,
+    // in place of mobil.politeness
+    pub politeness: f64,
+    // added to its preferred speed (m/s)
+    pub speed_offset: f64,
+}
+
+impl DriverTraits {
+    pub fn sample(params: &Parameters, rng: &mut StdRng) -> Self {
+        let dt = &params.driver_traits;
+        Self {
+            desired_gap: rng.gen_range(dt.desired_gap_low..=dt.desired_gap_high),
+            politeness: rng.gen_range(dt.politeness_low..=dt.politeness_high),
+            speed_offset: rng.gen_range(dt.speed_offset_low..=dt.speed_offset_high),
+        }
+    }
+}
+
+// Perturbations to a car's accel (m/s^2) and steering angle (rad) over one update
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ProcessNoise {
+    pub accel: f64,
+    pub steer: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Car {
     pub car_i: usize,
     pub crashed: bool,
@@ -53,6 +85,13 @@ pub struct Car {
     theta: f64,
     pub vel: f64,
     pub steer: f64,
+    // the acceleration the car is actually making, which lags the forward control's
+    pub accel: f64,
+    // dynamic bicycle model states, for the ego with params.ego_dynamics:
+    // lateral velocity at the center, yaw rate, and the lateral acceleration they give
+    pub lat_vel: f64,
+    pub yaw_rate: f64,
+    pub lat_accel: f64,
 
     pub width: f64,
     pub length: f64,
@@ -61,15 +100,17 @@ pub struct Car {
     pub preferred_vel: f64,
     pub preferred_accel: f64,
     pub preferred_follow_time: f64,
+    // only with driver_traits.sample, otherwise everyone drives by the parameters
+    pub traits: Option<DriverTraits>,
 
     // current properties/goals
     pub target_follow_time: f64,
     pub target_vel: f64,
     pub target_lane_i: i32,
 
-    pub forward_control: Option<ForwardControl>,
-    pub side_control: Option<SideControl>,
-    pub side_policy: Option<SidePolicy>,
+    pub forward_control: ForwardControl,
+    pub side_control: SideControl,
+    pub side_policy: SidePolicy,
 
     // cached
     shape: Cuboid,
@@ -78,12 +119,82 @@ pub struct Car {
     // norotation_aabb: AABB,
 }
 
+// clone_from clones the policy and controls into the existing ones,
+// so recycled rollout roads keep their cars' composite policies' allocations
+impl Clone for Car {
+    fn clone(&self) -> Self {
+        Self {
+            car_i: self.car_i,
+            crashed: self.crashed,
+            x: self.x,
+            y: self.y,
+            theta: self.theta,
+            vel: self.vel,
+            steer: self.steer,
+            accel: self.accel,
+            lat_vel: self.lat_vel,
+            yaw_rate: self.yaw_rate,
+            lat_accel: self.lat_accel,
+            width: self.width,
+            length: self.length,
+            preferred_vel: self.preferred_vel,
+            preferred_accel: self.preferred_accel,
+            preferred_follow_time: self.preferred_follow_time,
+            traits: self.traits,
+            target_follow_time: self.target_follow_time,
+            target_vel: self.target_vel,
+            target_lane_i: self.target_lane_i,
+            forward_control: self.forward_control.clone(),
+            side_control: self.side_control.clone(),
+            side_policy: self.side_policy.clone(),
+            shape: self.shape,
+            pose: self.pose,
+            aabb: self.aabb,
+        }
+    }
+
+    fn clone_from(&mut self, source: &Self) {
+        self.car_i = source.car_i;
+        self.crashed = source.crashed;
+        self.x = source.x;
+        self.y = source.y;
+        self.theta = source.theta;
+        self.vel = source.vel;
+        self.steer = source.steer;
+        self.accel = source.accel;
+        self.lat_vel = source.lat_vel;
+        self.yaw_rate = source.yaw_rate;
+        self.lat_accel = source.lat_accel;
+        self.width = source.width;
+        self.length = source.length;
+        self.preferred_vel = source.preferred_vel;
+        self.preferred_accel = source.preferred_accel;
+        self.preferred_follow_time = source.preferred_follow_time;
+        self.traits = source.traits;
+        self.target_follow_time = source.target_follow_time;
+        self.target_vel = source.target_vel;
+        self.target_lane_i = source.target_lane_i;
+        self.forward_control.clone_from(&source.forward_control);
+        self.side_control.clone_from(&source.side_control);
+        self.side_policy.clone_from(&source.side_policy);
+        self.shape = source.shape;
+        self.pose = source.pose;
+        self.aabb = source.aabb;
+    }
+}
+
 impl Car {
     pub fn new(params: &Parameters, car_i: usize, lane_i: i32) -> Self {
         let lane_y = Road::get_lane_y(lane_i);
         let policies = make_obstacle_vehicle_policy_choices(params);
         let width = PRIUS_WIDTH;
         let length = PRIUS_LENGTH;
+        // no faster than the road's speed limit
+        let speed = if params.speed_limit > 0.0 {
+            SPEED_DEFAULT.min(params.speed_limit)
+        } else {
+            SPEED_DEFAULT
+        };
         let mut car = Self {
             car_i,
             crashed: false,
@@ -93,30 +204,41 @@ impl Car {
             theta: 0.0,
             vel: 0.0,
             steer: 0.0,
+            accel: 0.0,
+            lat_vel: 0.0,
+            yaw_rate: 0.0,
+            lat_accel: 0.0,
 
             width,
             length,
 
-            preferred_vel: SPEED_DEFAULT,
+            preferred_vel: speed,
             preferred_accel: PREFERRED_ACCEL_DEFAULT,
             preferred_follow_time: FOLLOW_TIME_DEFAULT,
+            traits: None,
 
             target_follow_time: FOLLOW_TIME_DEFAULT,
-            target_vel: SPEED_DEFAULT,
+            target_vel: speed,
             target_lane_i: lane_i,
 
             // policy: Some(Policy::AdapativeCruisePolicy(AdapativeCruisePolicy::new())),
-            forward_control: Some(ForwardControl::IntelligentDriverPolicy(
-                IntelligentDriverPolicy::new(),
-            )),
-            side_control: Some(SideControl::PurePursuitPolicy(PurePursuitPolicy::new(
-                AHEAD_TIME_DEFAULT,
-            ))),
-            side_policy: Some(if lane_i == 0 {
-                policies[1].clone()
+            forward_control: make_forward_control(if car_i == 0 {
+                &params.ego_forward_control
             } else {
-                policies[3].clone()
+                &params.obstacle_forward_control
             }),
+            side_control: make_side_control(
+                params,
+                if car_i == 0 {
+                    &params.ego_side_control
+                } else {
+                    &params.obstacle_side_control
+                },
+            ),
+            // accelerate in its own lane
+            side_policy: find_lane_change(&policies, Some(lane_i), LongitudinalPolicy::Accelerate)
+                .unwrap_or(&policies[0])
+                .clone(),
 
             shape: Cuboid::new(vector!(length / 2.0, width / 2.0)),
             pose: Isometry2::identity(),
@@ -128,53 +250,97 @@ impl Car {
     }
 
     pub fn random_new(params: &Parameters, car_i: usize, rng: &mut StdRng) -> Self {
-        let lane_i = rng.gen_range(0..=1);
+        let lane_i = rng.gen_range(0..params.n_lanes);
+        Self::random_in_lane(params, car_i, lane_i, rng)
+    }
+
+    pub fn random_in_lane(
+        params: &Parameters,
+        car_i: usize,
+        lane_i: i32,
+        rng: &mut StdRng,
+    ) -> Self {
         let mut car = Self::new(params, car_i, lane_i);
         car.preferred_vel = rng.gen_range(SPEED_LOW..SPEED_HIGH);
         car.vel = car.preferred_vel;
         car.set_x(rng.gen_range(0.0..ROAD_LENGTH) - ROAD_LENGTH / 2.0);
         car.preferred_accel = rng.gen_range(PREFERRED_ACCEL_LOW..PREFERRED_ACCEL_HIGH);
         car.preferred_follow_time = rng.gen_range(FOLLOW_TIME_LOW..FOLLOW_TIME_HIGH);
+        if params.occlusion.truck_fraction > 0.0 && rng.gen_bool(params.occlusion.truck_fraction) {
+            car.make_truck();
+        }
+        if params.driver_traits.sample {
+            let traits = DriverTraits::sample(params, rng);
+            car.preferred_vel = (car.preferred_vel + traits.speed_offset).max(0.0);
+            car.vel = car.preferred_vel;
+            car.traits = Some(traits);
+        }
+        if params.speed_limit > 0.0 && car.preferred_vel > params.speed_limit {
+            car.preferred_vel = params.speed_limit;
+            car.vel = car.preferred_vel;
+        }
+        if params.mobil.fraction > 0.0 && rng.gen_bool(params.mobil.fraction) {
+            let policies = make_obstacle_vehicle_policy_choices(params);
+            if let Some(mobil) = find_mobil(&policies) {
+                car.side_policy = mobil.clone();
+            }
+        }
+        mismatch::mismatch_car(params, &mut car, rng);
 
         car
     }
 
     pub fn sim_estimate(&self) -> Self {
         let mut sim_car = self.clone();
-
-        sim_car.preferred_vel = self.vel.max(SPEED_LOW);
-        sim_car.preferred_accel = PREFERRED_ACCEL_DEFAULT;
-        sim_car.preferred_follow_time = FOLLOW_TIME_DEFAULT;
-
-        sim_car.target_lane_i = sim_car.current_lane();
-        sim_car.target_vel = sim_car.vel;
-        sim_car.target_follow_time = sim_car.preferred_follow_time;
-
+        sim_car.make_sim_estimate();
         sim_car
     }
 
+    // sim_estimate in place, for a road that's already a copy
+    pub fn make_sim_estimate(&mut self) {
+        self.preferred_vel = self.vel.max(SPEED_LOW);
+        self.preferred_accel = PREFERRED_ACCEL_DEFAULT;
+        self.preferred_follow_time = FOLLOW_TIME_DEFAULT;
+        // the traits are latent, so the estimate drives by the parameters
+        self.traits = None;
+
+        self.target_lane_i = self.current_lane();
+        self.target_vel = self.vel;
+        self.target_follow_time = self.preferred_follow_time;
+        // nor can the planners know what a scripted event has the car doing
+        if let ForwardControl::ScriptedBraking(braking) = &self.forward_control {
+            self.forward_control = braking.control().clone();
+        }
+    }
+
     pub fn open_loop_estimate(&self) -> Self {
         let mut car = self.sim_estimate();
 
-        car.side_policy = Some(SidePolicy::OpenLoopPolicy(OpenLoopPolicy));
-        car.side_control = Some(SideControl::OpenLoopSideControl(OpenLoopSideControl));
-        car.forward_control = Some(ForwardControl::OpenLoopForwardControl(
-            OpenLoopForwardControl,
-        ));
+        car.side_policy = SidePolicy::OpenLoopPolicy(OpenLoopPolicy);
+        car.side_control = SideControl::OpenLoopSideControl(OpenLoopSideControl);
+        car.forward_control = ForwardControl::OpenLoopForwardControl(OpenLoopForwardControl);
 
         car
     }
 
     pub fn operating_policy_id(&self) -> u32 {
-        self.side_policy
-            .as_ref()
-            .unwrap()
-            .operating_policy()
-            .policy_id()
+        self.side_policy.operating_policy().policy_id()
     }
 
     pub fn full_policy_id(&self) -> u32 {
-        self.side_policy.as_ref().unwrap().policy_id()
+        self.side_policy.policy_id()
+    }
+
+    // a box truck, which hides more of the road behind it
+    pub fn make_truck(&mut self) {
+        self.set_size(TRUCK_LENGTH, TRUCK_WIDTH);
+    }
+
+    pub fn set_size(&mut self, length: f64, width: f64) {
+        self.length = length;
+        self.width = width;
+        self.shape = Cuboid::new(vector!(self.length / 2.0, self.width / 2.0));
+        self.update_geometry_cache();
     }
 
     pub fn is_ego(&self) -> bool {
@@ -182,26 +348,142 @@ impl Car {
     }
 
     pub fn follow_dist(&self) -> f64 {
-        FOLLOW_DIST_BASE + self.target_follow_time * self.vel
+        self.traits.map_or(FOLLOW_DIST_BASE, |t| t.desired_gap) + self.target_follow_time * self.vel
     }
 
+    // The pose and AABB that every query uses, kept whenever x, y, theta or the size change,
+    // with the trig done just once
     fn update_geometry_cache(&mut self) {
-        let center_x = self.x - self.length / 2.0 * self.theta.cos();
-        let center_y = self.y - self.length / 2.0 * self.theta.sin();
-        self.pose = Isometry2::new(vector!(center_x, center_y), self.theta);
+        let rotation = UnitComplex::new(self.theta);
+        let center_x = self.x - self.length / 2.0 * rotation.re;
+        let center_y = self.y - self.length / 2.0 * rotation.im;
+        self.pose = Isometry2::from_parts(Translation2::new(center_x, center_y), rotation);
+
+        self.aabb = self.shape.compute_aabb(&self.pose);
+    }
+
+    // update_geometry_cache with the trig in f32
+    fn update_geometry_cache_f32(&mut self) {
+        let (sin, cos) = (self.theta as f32).sin_cos();
+        let rotation = UnitComplex::from_cos_sin_unchecked(cos as f64, sin as f64);
+        let center_x = self.x - self.length / 2.0 * rotation.re;
+        let center_y = self.y - self.length / 2.0 * rotation.im;
+        self.pose = Isometry2::from_parts(Translation2::new(center_x, center_y), rotation);
 
-        self.aabb = self.shape().compute_aabb(&self.pose());
+        self.aabb = self.shape.compute_aabb(&self.pose);
     }
 
-    pub fn update(&mut self, dt: f64) {
+    pub fn uses_dynamic_model(&self, dynamics: &DynamicsParameters) -> bool {
+        self.is_ego() && dynamics.dynamic && self.vel >= dynamics.min_vel
+    }
+
+    // curvature is the road's at the car, as the car moves in the road's frame,
+    // and noise perturbs its accel and steering over just this step
+    pub fn update(
+        &mut self,
+        dt: f64,
+        curvature: f64,
+        dynamics: &DynamicsParameters,
+        noise: ProcessNoise,
+    ) {
         if !self.crashed {
-            let theta = self.theta + self.steer;
+            self.vel = (self.vel + noise.accel * dt).max(0.0);
+            let steer = self.steer;
+            self.steer += noise.steer;
+            self.update_model(dt, curvature, dynamics);
+            self.steer = steer;
+        }
+    }
+
+    fn update_model(&mut self, dt: f64, curvature: f64, dynamics: &DynamicsParameters) {
+        if self.uses_dynamic_model(dynamics) {
+            self.update_dynamic(dt, curvature, dynamics);
+            self.update_geometry_cache();
+            return;
+        }
+
+        let theta = self.theta + self.steer;
+        if curvature == 0.0 {
             self.x += theta.cos() * self.vel * dt;
             self.y += theta.sin() * self.vel * dt;
             self.theta += self.vel * self.steer.sin() / self.length * dt;
+        } else {
+            let ds = theta.cos() * self.vel * dt / (1.0 - curvature * self.y);
+            self.x += ds;
+            self.y += theta.sin() * self.vel * dt;
+            self.theta += self.vel * self.steer.sin() / self.length * dt - curvature * ds;
+        }
 
-            self.update_geometry_cache();
+        // the dynamic states that match, for when the ego speeds up into the dynamic model
+        if self.is_ego() && dynamics.dynamic {
+            self.yaw_rate = self.vel * self.steer.sin() / self.length;
+            self.lat_vel = self.vel * self.steer.sin() - self.length / 2.0 * self.yaw_rate;
+            self.lat_accel = self.vel * self.yaw_rate;
+        }
+
+        self.update_geometry_cache();
+    }
+
+    // update's kinematic model computed in f32, for the forward sims with
+    // params.f32_rollout_math. The state stays in f64 between steps, so only the math is f32.
+    // An ego with the dynamic model stays in f64, since its slip angles are small differences.
+    pub fn update_f32(
+        &mut self,
+        dt: f64,
+        curvature: f64,
+        dynamics: &DynamicsParameters,
+        noise: ProcessNoise,
+    ) {
+        if self.crashed || self.is_ego() && dynamics.dynamic {
+            self.update(dt, curvature, dynamics, noise);
+            return;
         }
+        self.vel = (self.vel + noise.accel * dt).max(0.0);
+
+        let (dt, curvature) = (dt as f32, curvature as f32);
+        let (vel, steer, length) = (self.vel as f32, self.steer as f32, self.length as f32);
+        let steer = steer + noise.steer as f32;
+        let (sin, cos) = (self.theta as f32 + steer).sin_cos();
+        let dy = sin * vel * dt;
+        let (dx, dtheta) = if curvature == 0.0 {
+            (cos * vel * dt, vel * steer.sin() / length * dt)
+        } else {
+            let ds = cos * vel * dt / (1.0 - curvature * self.y as f32);
+            (ds, vel * steer.sin() / length * dt - curvature * ds)
+        };
+        self.x += dx as f64;
+        self.y += dy as f64;
+        self.theta += dtheta as f64;
+
+        self.update_geometry_cache_f32();
+    }
+
+    // Dynamic bicycle model with linear tire forces, about the car's center, so the tires slip
+    // and it can't turn as sharply as the kinematic model lets it at speed. vel is the
+    // longitudinal speed, and x, y is still the front of the car (where the front axle is).
+    // With the center of mass in the middle, a front_stiffness under rear_stiffness
+    // makes it understeer like a front-heavy car.
+    fn update_dynamic(&mut self, dt: f64, curvature: f64, dynamics: &DynamicsParameters) {
+        let half_length = self.length / 2.0;
+        let vx = self.vel;
+        let front_slip = self.steer - (self.lat_vel + half_length * self.yaw_rate).atan2(vx);
+        let rear_slip = -(self.lat_vel - half_length * self.yaw_rate).atan2(vx);
+        let front_force = dynamics.front_stiffness * front_slip * self.steer.cos();
+        let rear_force = dynamics.rear_stiffness * rear_slip;
+
+        self.lat_accel = (front_force + rear_force) / dynamics.mass;
+        let yaw_accel = half_length * (front_force - rear_force) / dynamics.yaw_inertia;
+        self.lat_vel += (self.lat_accel - vx * self.yaw_rate) * dt;
+        self.yaw_rate += yaw_accel * dt;
+
+        // the front of the car moves with the center, plus its swing from the yaw rate
+        let front_lat_vel = self.lat_vel + half_length * self.yaw_rate;
+        let (sin, cos) = self.theta.sin_cos();
+        let dx = (vx * cos - front_lat_vel * sin) * dt;
+        let ds = dx / (1.0 - curvature * self.y);
+        self.x += ds;
+        self.y += (vx * sin + front_lat_vel * cos) * dt;
+        self.theta += self.yaw_rate * dt - curvature * ds;
     }
 
     pub fn draw(&self, params: &Parameters, r: &mut Rvx, color: RvxColor) {
@@ -295,7 +577,7 @@ impl Car {
                         self.target_follow_time,
                         self.x,
                         if self.is_ego() || params.debug_car_i == Some(self.car_i) {
-                            format!("{:?}", self.side_policy.as_ref().unwrap())
+                            format!("{:?}", self.side_policy)
                         } else {
                             "".to_owned()
                         },
@@ -309,7 +591,7 @@ impl Car {
         }
 
         if self.is_ego() && !params.graphics_for_paper {
-            self.side_control.iter().for_each(|a| a.draw(r));
+            self.side_control.draw(r);
         }
     }
 
@@ -330,6 +612,32 @@ impl Car {
         self.pose
     }
 
+    // Whether the two cars' rectangles overlap, the same as parry's intersection_test but
+    // by separating axes, since crash checks run for every pair of nearby cars in every rollout
+    pub fn intersects(&self, other: &Car) -> bool {
+        let half_a = vector!(self.length / 2.0, self.width / 2.0);
+        let half_b = vector!(other.length / 2.0, other.width / 2.0);
+        rects_intersect(&self.pose, half_a, &other.pose, half_b)
+    }
+
+    // intersects, with the separating axes test computed in f32 from the f64 poses
+    pub fn intersects_f32(&self, other: &Car) -> bool {
+        let half_a = vector!(self.length as f32 / 2.0, self.width as f32 / 2.0);
+        let half_b = vector!(other.length as f32 / 2.0, other.width as f32 / 2.0);
+        // relative to self, so the positions keep their precision far down the road
+        let offset = self.pose.translation.vector;
+        let pose_a = Isometry2::from_parts(
+            Translation2::new(0.0, 0.0),
+            self.pose.rotation.cast::<f32>(),
+        );
+        let d = other.pose.translation.vector - offset;
+        let pose_b = Isometry2::from_parts(
+            Translation2::new(d.x as f32, d.y as f32),
+            other.pose.rotation.cast::<f32>(),
+        );
+        rects_intersect(&pose_a, half_a, &pose_b, half_b)
+    }
+
     pub fn aabb(&self) -> AABB {
         // let aabb = self.shape().compute_aabb(&self.pose());
         // assert_eq!(aabb, self.aabb);
@@ -354,13 +662,11 @@ impl Car {
         self.update_geometry_cache();
     }
 
-    #[allow(unused)]
     pub fn set_y(&mut self, y: f64) {
         self.y = y;
         self.update_geometry_cache();
     }
 
-    #[allow(unused)]
     pub fn set_theta(&mut self, theta: f64) {
         self.theta = theta;
         self.update_geometry_cache();
@@ -375,7 +681,39 @@ impl Car {
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+// Separating axis test for two rectangles, by their center poses and half extents.
+// The bounding circles and the inscribed circles settle most pairs before any axis.
+pub fn rects_intersect<T: RealField + Copy>(
+    pose_a: &Isometry2<T>,
+    half_a: Vector2<T>,
+    pose_b: &Isometry2<T>,
+    half_b: Vector2<T>,
+) -> bool {
+    let d = pose_b.translation.vector - pose_a.translation.vector;
+    let dist_sq = d.norm_squared();
+    if dist_sq > (half_a.norm() + half_b.norm()).powi(2) {
+        return false;
+    }
+    if dist_sq < (half_a.min() + half_b.min()).powi(2) {
+        return true;
+    }
+
+    let (cos_a, sin_a) = (pose_a.rotation.re, pose_a.rotation.im);
+    let (cos_b, sin_b) = (pose_b.rotation.re, pose_b.rotation.im);
+    let axes = [
+        vector!(cos_a, sin_a),
+        vector!(-sin_a, cos_a),
+        vector!(cos_b, sin_b),
+        vector!(-sin_b, cos_b),
+    ];
+    axes.iter().all(|axis| {
+        let reach_a = half_a.x * axes[0].dot(axis).abs() + half_a.y * axes[1].dot(axis).abs();
+        let reach_b = half_b.x * axes[2].dot(axis).abs() + half_b.y * axes[3].dot(axis).abs();
+        d.dot(axis).abs() <= reach_a + reach_b
+    })
+}
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub struct SpatialCar {
     pub x: i32,
     pub car_i: u32,
@@ -389,3 +727,148 @@ impl From<&Car> for SpatialCar {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dynamic_model_understeers() {
+        let mut params = Parameters::new().unwrap();
+        params.ego_dynamics.dynamic = true;
+        let turn = |params: &Parameters, vel: f64| {
+            let mut car = Car::new(params, 0, 0);
+            car.vel = vel;
+            car.steer = 0.05;
+            for _ in 0..200 {
+                car.update(0.01, 0.0, &params.ego_dynamics, ProcessNoise::default());
+            }
+            car
+        };
+
+        // at speed it turns less than the kinematic model
+        let kinematic = turn(&Parameters::new().unwrap(), 25.0);
+        let dynamic = turn(&params, 25.0);
+        assert!(dynamic.theta() < 0.9 * kinematic.theta());
+        assert!(dynamic.theta() > 0.0);
+        assert!(dynamic.lat_accel > 0.0);
+
+        // but is still kinematic when slow
+        let kinematic = turn(&Parameters::new().unwrap(), 3.0);
+        let dynamic = turn(&params, 3.0);
+        assert_eq!(dynamic.theta(), kinematic.theta());
+    }
+
+    #[test]
+    fn test_geometry_cache() {
+        let params = Parameters::new().unwrap();
+        let mut car = Car::new(&params, 1, 1);
+        car.vel = 10.0;
+        car.steer = 0.1;
+        let dynamics = params.ego_dynamics.clone();
+        for _ in 0..20 {
+            car.update(params.physics_dt, 0.01, &dynamics, ProcessNoise::default());
+        }
+        car.make_truck();
+        let pose = Isometry2::new(
+            vector!(
+                car.x() - car.length / 2.0 * car.theta().cos(),
+                car.y() - car.length / 2.0 * car.theta().sin()
+            ),
+            car.theta(),
+        );
+        assert_eq!(car.pose(), pose);
+        assert_eq!(car.aabb(), car.shape().compute_aabb(&pose));
+    }
+
+    #[test]
+    fn test_rects_intersect() {
+        use parry2d_f64::query;
+        use rand::SeedableRng;
+
+        // the same as parry, for rectangles all around and at every angle
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut n_intersecting = 0;
+        for _ in 0..10000 {
+            let mut rect = || {
+                let pose = Isometry2::new(
+                    vector!(rng.gen_range(-8.0..8.0), rng.gen_range(-4.0..4.0)),
+                    rng.gen_range(-PI..PI),
+                );
+                let half = vector!(rng.gen_range(0.5..6.0), rng.gen_range(0.5..1.5));
+                (pose, half)
+            };
+            let (pose_a, half_a) = rect();
+            let (pose_b, half_b) = rect();
+            let expected = query::intersection_test(
+                &pose_a,
+                &Cuboid::new(half_a),
+                &pose_b,
+                &Cuboid::new(half_b),
+            )
+            .unwrap();
+            assert_eq!(rects_intersect(&pose_a, half_a, &pose_b, half_b), expected);
+            n_intersecting += expected as u32;
+        }
+        assert!(n_intersecting > 1000 && n_intersecting < 9000);
+    }
+
+    #[test]
+    fn test_f32_update() {
+        // a forward sim's worth of steps stays within centimeters of the f64 model
+        let params = Parameters::new().unwrap();
+        let mut car = Car::new(&params, 1, 1);
+        car.set_x(500.0);
+        car.vel = 25.0;
+        car.steer = 0.02;
+        let mut car_f32 = car.clone();
+        for _ in 0..1000 {
+            car.update(
+                params.physics_dt,
+                0.002,
+                &params.ego_dynamics,
+                ProcessNoise::default(),
+            );
+            car_f32.update_f32(
+                params.physics_dt,
+                0.002,
+                &params.ego_dynamics,
+                ProcessNoise::default(),
+            );
+        }
+        approx::assert_abs_diff_eq!(car.x(), car_f32.x(), epsilon = 0.02);
+        approx::assert_abs_diff_eq!(car.y(), car_f32.y(), epsilon = 0.02);
+        approx::assert_abs_diff_eq!(car.theta(), car_f32.theta(), epsilon = 1e-4);
+
+        // and bumpers a centimeter apart far down the road still don't touch
+        let mut other = Car::new(&params, 2, 1);
+        car_f32 = Car::new(&params, 1, 1);
+        car_f32.set_x(2000.0);
+        other.set_x(car_f32.x() + other.length - 0.01);
+        assert!(car_f32.intersects_f32(&other));
+        other.set_x(car_f32.x() + other.length + 0.01);
+        assert!(!car_f32.intersects_f32(&other));
+    }
+
+    #[test]
+    fn test_driver_traits() {
+        use rand::SeedableRng;
+
+        let mut params = Parameters::new().unwrap();
+        let mut rng = StdRng::seed_from_u64(0);
+        assert_eq!(Car::random_new(&params, 1, &mut rng).traits, None);
+
+        params.driver_traits.sample = true;
+        params.driver_traits.desired_gap_low = 3.0;
+        params.driver_traits.desired_gap_high = 3.0;
+        for _ in 0..20 {
+            let car = Car::random_new(&params, 1, &mut rng);
+            let traits = car.traits.unwrap();
+            assert_eq!(car.follow_dist(), 3.0 + car.target_follow_time * car.vel);
+            assert!((0.0..=0.6).contains(&traits.politeness));
+            assert!(car.preferred_vel >= SPEED_LOW - 2.0 && car.preferred_vel <= SPEED_HIGH + 2.0);
+            // which the planner doesn't get to know
+            assert_eq!(car.sim_estimate().traits, None);
+        }
+    }
+}
@@ -4,8 +4,14 @@ use itertools::Itertools;
 use ordered_float::NotNan;
 
 use crate::{
-    arg_parameters::Parameters, belief::Belief, car::SPEED_LOW,
-    mpdm::make_obstacle_vehicle_policy_belief_states, road::Road, road_set::RoadSet,
+    arg_parameters::Parameters,
+    belief::Belief,
+    car::SPEED_LOW,
+    mpdm::make_obstacle_vehicle_policy_belief_states,
+    road::Road,
+    road_arena,
+    road_set::RoadSet,
+    side_policies::{SidePolicy, SidePolicyTrait},
 };
 
 fn key_vehicles(params: &Parameters, road: &Road) -> Vec<(usize, f64)> {
@@ -79,24 +85,40 @@ fn most_probable_cartesian_product_scenarios(
     top_n_scenarios
 }
 
-pub fn conditional_focused_branching(
-    params: &Parameters,
-    road: &Road,
-    n: usize,
-) -> (RoadSet, Vec<usize>) {
+// The uncertain key vehicles that matter most to the ego, up to max_n_for_cartesian_product of
+// them. By "risk", the ones whose policies spread the ego's open-loop costs the most, the
+// nearest first among equals, and by "distance", just the nearest.
+pub fn critical_cars(params: &Parameters, road: &Road, criticality: &str) -> Vec<usize> {
     let belief = road.belief.as_ref().unwrap();
     let debug = params.cfb_debug && road.super_debug();
 
     let key_car_ids = key_vehicles(params, road);
     if debug {
-        eprintln_f!("{key_car_ids=:?}");
+        debug_f!("{key_car_ids=:?}");
     }
+    // phantom cars have no belief to branch on
     let uncertain_car_ids = key_car_ids
         .into_iter()
-        .filter(|&(car_i, _dx)| belief.is_uncertain(car_i, params.cfb.uncertainty_threshold))
+        .filter(|&(car_i, _dx)| {
+            car_i < belief.n_cars() && belief.is_uncertain(car_i, params.cfb.uncertainty_threshold)
+        })
         .collect_vec();
     if debug {
-        eprintln_f!("{uncertain_car_ids=:?}");
+        debug_f!("{uncertain_car_ids=:?}");
+    }
+
+    match criticality {
+        "risk" => (),
+        "distance" => {
+            let mut nearest = uncertain_car_ids;
+            nearest.sort_by(|(_, dx_a), (_, dx_b)| dx_a.partial_cmp(dx_b).unwrap());
+            nearest.truncate(params.cfb.max_n_for_cartesian_product);
+            return nearest.into_iter().map(|(car_i, _)| car_i).collect();
+        }
+        _ => panic!(
+            "Unknown criticality '{}', expected risk or distance",
+            criticality
+        ),
     }
 
     // For each car, perform an open-loop simulation with only that car, using each real policy.
@@ -117,7 +139,7 @@ pub fn conditional_focused_branching(
                 .iter()
                 .map(|policy| {
                     let mut road = road.clone();
-                    road.cars[car_i].side_policy = Some(policy.clone());
+                    road.cars[car_i].side_policy.clone_from(policy);
                     road.car_traces = None;
                     road.take_update_steps(params.cfb.horizon_t, params.cfb.dt);
                     // eprintln_f!("{car_i=} {road.cost:.2?} {policy:?}");
@@ -140,10 +162,7 @@ pub fn conditional_focused_branching(
         .collect_vec();
 
     if debug {
-        eprintln!("Open loop sim results:");
-        for open_loop_sim in open_loop_sims.iter() {
-            eprintln_f!("{open_loop_sim:.2?}");
-        }
+        tracing::debug!("Open loop sim results: {:.2?}", open_loop_sims);
     }
 
     let mut sorted_open_sims = open_loop_sims;
@@ -156,52 +175,70 @@ pub fn conditional_focused_branching(
     });
 
     if debug {
-        eprintln!("Potentially dangerous sims:");
-        for sim in sorted_open_sims.iter() {
-            eprintln_f!("{sim:.2?}");
-        }
+        tracing::debug!("Potentially dangerous sims: {:.2?}", sorted_open_sims);
     }
 
     sorted_open_sims.truncate(params.cfb.max_n_for_cartesian_product);
 
-    let selected_important_car_ids = sorted_open_sims.iter().map(|a| a.0).collect_vec();
-
     if debug {
-        eprintln!("Choosing to consider all permutations of:");
-        for sim in sorted_open_sims.iter() {
-            eprintln_f!("{sim:.2?}");
-        }
+        tracing::debug!(
+            "Choosing to consider all permutations of: {:.2?}",
+            sorted_open_sims
+        );
     }
 
+    sorted_open_sims.iter().map(|a| a.0).collect()
+}
+
+// The n most probable assignments of the critical cars' policies, each on its own copy of the
+// road, weighted by its probability
+fn branch_road(
+    road: &Road,
+    belief: &Belief,
+    policies: &[SidePolicy],
+    critical_car_ids: &[usize],
+    n: usize,
+) -> Vec<Road> {
+    most_probable_cartesian_product_scenarios(critical_car_ids, belief, policies.len(), n)
+        .into_iter()
+        .map(|(prob, scenario)| {
+            let mut road = road_arena::clone_road(road);
+            for (car_i, policy_i) in scenario.iter() {
+                // a car keeps its policy's state when it's already on that one
+                if road.cars[*car_i].side_policy.policy_id() != policies[*policy_i].policy_id() {
+                    road.cars[*car_i]
+                        .side_policy
+                        .clone_from(&policies[*policy_i]);
+                }
+            }
+
+            road.cost.weight = prob;
+            road
+        })
+        .collect()
+}
+
+pub fn conditional_focused_branching(
+    params: &Parameters,
+    road: &Road,
+    n: usize,
+    criticality: &str,
+) -> (RoadSet, Vec<usize>) {
+    let belief = road.belief.as_ref().unwrap();
+    let policies = make_obstacle_vehicle_policy_belief_states(params);
+    let selected_important_car_ids = critical_cars(params, road, criticality);
+
     let mut sim_road = road.sim_estimate();
     // Each car (besides ego) defaults to the policy that is most likely for it
     for c in sim_road.cars[1..].iter_mut() {
         let policy_i = belief.get_most_likely(c.car_i);
-        c.side_policy = Some(policies[policy_i].clone());
+        c.side_policy.clone_from(&policies[policy_i]);
     }
 
-    let top_n_scenarios = most_probable_cartesian_product_scenarios(
-        &selected_important_car_ids,
-        belief,
-        policies.len(),
-        n,
-    );
-
     // sort descending and choose just the most probable
     // ranked_scenarios.sort_unstable_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
     // ranked_scenarios.truncate(n);
-    let mut roads = top_n_scenarios
-        .into_iter()
-        .map(|(prob, scenario)| {
-            let mut sim_road = sim_road.clone();
-            for (car_i, policy_i) in scenario.iter() {
-                sim_road.cars[*car_i].side_policy = Some(policies[*policy_i].clone());
-            }
-
-            sim_road.cost.weight = prob;
-            sim_road
-        })
-        .collect_vec();
+    let mut roads = branch_road(&sim_road, belief, &policies, &selected_important_car_ids, n);
 
     if roads.is_empty() {
         roads.push(sim_road);
@@ -210,6 +247,39 @@ pub fn conditional_focused_branching(
     (RoadSet::new(roads), selected_important_car_ids)
 }
 
+// Conditional focused branching again partway through the rollouts, at a layer boundary: each
+// road splits into the k most probable assignments of its own critical cars' policies, and the
+// k most probable of all of those carry on. Their weights are renormalized to average 1.
+pub fn branch_at_layer(
+    params: &Parameters,
+    roads: RoadSet,
+    k: usize,
+    criticality: &str,
+) -> RoadSet {
+    let policies = make_obstacle_vehicle_policy_belief_states(params);
+    let mut branched = Vec::new();
+    for road in roads.roads() {
+        let belief = road.belief.as_ref().unwrap();
+        let critical_car_ids = critical_cars(params, road, criticality);
+        for mut branch in branch_road(road, belief, &policies, &critical_car_ids, k) {
+            branch.cost.weight *= road.cost.weight;
+            branched.push(branch);
+        }
+    }
+    roads.recycle();
+
+    branched.sort_by(|a, b| b.cost.weight.partial_cmp(&a.cost.weight).unwrap());
+    for road in branched.drain(k.min(branched.len())..) {
+        road_arena::recycle_road(road);
+    }
+    let total_weight = branched.iter().map(|r| r.cost.weight).sum::<f64>();
+    let n = branched.len() as f64;
+    for road in branched.iter_mut() {
+        road.cost.weight *= n / total_weight;
+    }
+    RoadSet::new(branched)
+}
+
 #[cfg(test)]
 mod tests {
     use crate::belief::Belief;
@@ -273,6 +343,43 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_branch_at_layer() {
+        use rand::{prelude::StdRng, SeedableRng};
+        use std::sync::Arc;
+
+        let mut params = Parameters::new().unwrap();
+        params.n_cars = 20;
+        let params = Arc::new(params);
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut road = Road::new(params.clone());
+        for _ in 0..params.n_cars {
+            road.add_random_car(&mut rng).unwrap();
+        }
+        road.init_belief();
+
+        let k = 4;
+        for &criticality in ["risk", "distance"].iter() {
+            let (mut roads, critical_car_ids) =
+                conditional_focused_branching(&params, &road, k, criticality);
+            assert!(!critical_car_ids.is_empty());
+            roads.take_update_steps(2.0, 0.2);
+
+            let roads = branch_at_layer(&params, roads, k, criticality);
+            assert_eq!(roads.roads().len(), k);
+            let total_weight = roads.roads().iter().map(|r| r.cost.weight).sum::<f64>();
+            assert!((total_weight - k as f64).abs() < 1e-9);
+            // most probable first, with its costs so far
+            for (a, b) in roads.roads().iter().tuple_windows() {
+                assert!(a.cost.weight >= b.cost.weight);
+            }
+            assert!(roads
+                .roads()
+                .iter()
+                .all(|r| (r.t - 2.0).abs() < 1e-6 && r.cost.total() > 0.0));
+        }
+    }
+
     fn most_probable_cartesian_product_reference(
         car_is: &[usize],
         belief: &Belief,
@@ -0,0 +1,74 @@
+use std::fmt::Write;
+
+use selfdriving::{evaluate_all, init_logging, Evaluation, Parameters};
+
+use super::sweep::scenarios_from_args;
+
+// A row for each evaluation, with the main metrics as mean ± the CI's half width
+pub fn evaluation_table(evaluations: &[Evaluation]) -> String {
+    let mut table = format!("{:>5}", "runs");
+    if let Some(evaluation) = evaluations.first() {
+        for (name, _) in evaluation.columns().iter() {
+            write!(table, " {:>18}", name).unwrap();
+        }
+    }
+    table += " scenario\n";
+    for evaluation in evaluations {
+        write!(table, "{:5}", evaluation.n_episodes).unwrap();
+        for (_, metric) in evaluation.columns().iter() {
+            let half_width = (metric.ci_high - metric.ci_low) / 2.0;
+            write!(table, " {:9.3} ± {:6.3}", metric.mean, half_width).unwrap();
+        }
+        writeln!(table, " {}", evaluation.scenario_name).unwrap();
+        for (seed, e) in evaluation.errors.iter() {
+            writeln!(table, "      rng_seed {}: error: {}", seed, e).unwrap();
+        }
+    }
+    table
+}
+
+// The n_episodes, whether to print json, and the parameters' configurations of
+// <command> <n_episodes> [--json] <params> (but only the first of each one's rng_seeds,
+// which the episodes start from), or None without any
+pub fn evaluate_args(args: Vec<String>, command: &str) -> Option<(usize, bool, Vec<Parameters>)> {
+    let n_episodes = match args.first().and_then(|n| n.parse::<usize>().ok()) {
+        Some(n) if n > 0 => n,
+        _ => {
+            eprintln!(
+                "Usage: {} <n_episodes> [--json] (<param name> [param value]* ::)*",
+                command
+            );
+            std::process::exit(1);
+        }
+    };
+    let json = args.get(1).map(String::as_str) == Some("--json");
+    let params_args = args.into_iter().skip(if json { 2 } else { 1 });
+    let mut configs = scenarios_from_args(params_args);
+    if configs.is_empty() {
+        return None;
+    }
+    // one configuration for each block of seeds
+    let first_seed = configs[0].rng_seed;
+    configs.retain(|c| c.rng_seed == first_seed);
+    for config in configs.iter_mut() {
+        let name = config.scenario_name.as_ref().unwrap();
+        config.scenario_name = Some(name.replace(&format!(",rng_seed={},", first_seed), ","));
+    }
+    init_logging(&configs[0]);
+    Some((n_episodes, json, configs))
+}
+
+// The evaluate subcommand: evaluate <n_episodes> [--json] <params>, with a row for each of the
+// parameters' configurations
+pub fn run_evaluate(args: Vec<String>) {
+    let (n_episodes, json, configs) = match evaluate_args(args, "evaluate") {
+        Some(args) => args,
+        None => return,
+    };
+    let evaluations = evaluate_all(&configs, n_episodes);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&evaluations).unwrap());
+    } else {
+        print!("{}", evaluation_table(&evaluations));
+    }
+}
@@ -0,0 +1,56 @@
+use std::path::PathBuf;
+
+use selfdriving::{init_logging, minimize, Parameters};
+
+// The minimize subcommand, for the one run of the arguments:
+// writes the minimized scenario file and its start's snapshot, and how to run it
+pub fn run_minimize(scenarios: Vec<Parameters>) {
+    if scenarios.len() != 1 {
+        eprintln!(
+            "minimize takes the parameters of one run, not {}",
+            scenarios.len()
+        );
+        std::process::exit(1);
+    }
+    let params = scenarios.into_iter().next().unwrap();
+    init_logging(&params);
+    let scratch_path = std::env::temp_dir().join(format!("minimize_{}.yaml", std::process::id()));
+    let minimized = match minimize(params, &scratch_path) {
+        Ok(minimized) => minimized,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let scenario_path = PathBuf::from("minimized.yaml");
+    let snapshot_path = PathBuf::from("minimized_snapshot.json");
+    let written = minimized
+        .scenario
+        .save(&scenario_path)
+        .and_then(|()| minimized.snapshot.save_snapshot(&snapshot_path));
+    if let Err(e) = written {
+        eprintln!("Could not write the minimized scenario: {}", e);
+        std::process::exit(1);
+    }
+    eprintln!(
+        "The ego crashed at step {}. From step {}, with {} of the {} cars, it crashes after {} steps.",
+        minimized.crash_step,
+        minimized.start_step,
+        minimized.n_cars,
+        minimized.snapshot.cars.len() - 1,
+        minimized.max_steps
+    );
+    eprintln!(
+        "Wrote {} and the road at step {} to {}",
+        scenario_path.display(),
+        minimized.start_step,
+        snapshot_path.display()
+    );
+    eprintln!(
+        "Run it with the same parameters but: scenario_file {} :: n_cars {} :: max_steps {}",
+        scenario_path.display(),
+        minimized.n_cars,
+        minimized.max_steps
+    );
+}
@@ -0,0 +1,39 @@
+use std::fmt::Write;
+
+use selfdriving::{stress_test_all, StressTest};
+
+use super::evaluate::{evaluate_args, evaluation_table};
+
+// Each configuration's two rows from evaluation_table, and then the degradation between them
+pub fn stress_table(tests: &[StressTest]) -> String {
+    let mut table = String::new();
+    for (test_i, test) in tests.iter().enumerate() {
+        let rows = evaluation_table(&[test.matched.clone(), test.mismatched.clone()]);
+        // with the header just once
+        let skip = if test_i == 0 { 0 } else { 1 };
+        for line in rows.lines().skip(skip) {
+            writeln!(table, "{}", line).unwrap();
+        }
+        write!(table, "{:>5}", "Δ").unwrap();
+        for (_, delta) in test.degradation.iter() {
+            write!(table, " {:9.3}         ", delta).unwrap();
+        }
+        table += " degradation\n";
+    }
+    table
+}
+
+// The stress subcommand: stress <n_episodes> [--json] <params>, with the mismatch from the
+// parameters' mismatch.* (which needn't set mismatch.enabled), for each of their configurations
+pub fn run_stress(args: Vec<String>) {
+    let (n_episodes, json, configs) = match evaluate_args(args, "stress") {
+        Some(args) => args,
+        None => return,
+    };
+    let tests = stress_test_all(&configs, n_episodes);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&tests).unwrap());
+    } else {
+        print!("{}", stress_table(&tests));
+    }
+}
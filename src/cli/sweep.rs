@@ -0,0 +1,487 @@
+// Sweeps over the parameter sets of the command line, each run once and kept in a results cache
+
+use std::{
+    collections::BTreeMap,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    sync::{
+        atomic::{self, AtomicUsize},
+        Mutex,
+    },
+    time::Instant,
+};
+
+use atomic::Ordering;
+use itertools::Itertools;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use selfdriving::{
+    create_scenarios, init_logging, run_with_parameters, ComfortDisplay, Parameters,
+};
+
+// Named groups of parameters for reproducing the paper's experiments
+// (the same sweeps as make_all_figures.sh, except for rng_seed)
+const PRESETS: &[(&str, &[(&str, &[&str])])] = &[
+    (
+        "mpdm_paper",
+        &[
+            ("method", &["mpdm"]),
+            ("use_cfb", &["false"]),
+            ("mpdm.samples_n", &["2", "4", "8", "16", "32", "64"]),
+        ],
+    ),
+    (
+        "eudm_paper",
+        &[
+            ("method", &["eudm"]),
+            ("use_cfb", &["false", "true"]),
+            ("eudm.samples_n", &["1", "2", "4", "8", "16", "32"]),
+        ],
+    ),
+    (
+        "mcts_classic",
+        &[
+            ("method", &["mcts"]),
+            ("use_cfb", &["false"]),
+            ("mcts.bound_mode", &["classic"]),
+            ("mcts.samples_n", &["8", "16", "32", "64", "128", "256"]),
+            ("mcts.repeat_const", &["0"]),
+        ],
+    ),
+    (
+        "mcptdm",
+        &[
+            ("method", &["mcts"]),
+            ("use_cfb", &["false"]),
+            ("mcts.bound_mode", &["marginal"]),
+            ("mcts.samples_n", &["8", "16", "32", "64", "128", "256"]),
+            ("mcts.repeat_const", &["0", "32768"]),
+        ],
+    ),
+];
+
+// Replaces each "preset" pair with the preset's parameters, in place so that "method" still comes
+// before the method-specific parameters. Parameters given explicitly take precedence over presets.
+fn expand_presets(name_value_pairs: Vec<(String, Vec<String>)>) -> Vec<(String, Vec<String>)> {
+    let explicit_names = name_value_pairs
+        .iter()
+        .map(|(name, _)| name.clone())
+        .collect_vec();
+
+    let mut expanded = Vec::<(String, Vec<String>)>::new();
+    for (name, vals) in name_value_pairs {
+        if name != "preset" {
+            expanded.push((name, vals));
+            continue;
+        }
+        for preset_name in vals.iter() {
+            let preset = PRESETS
+                .iter()
+                .find(|(n, _)| n == preset_name)
+                .unwrap_or_else(|| {
+                    panic!(
+                        "Unknown preset {}, valid presets are: {}",
+                        preset_name,
+                        PRESETS.iter().map(|(n, _)| n).join(", ")
+                    )
+                });
+            for (param_name, param_vals) in preset.1.iter() {
+                if explicit_names.iter().any(|n| n == param_name) {
+                    continue;
+                }
+                if expanded.iter().any(|pair| pair.0 == *param_name) {
+                    panic!(
+                        "Parameter {} is set by more than one preset in {:?}",
+                        param_name, vals
+                    );
+                }
+                expanded.push((
+                    param_name.to_string(),
+                    param_vals.iter().map(|v| v.to_string()).collect(),
+                ));
+            }
+        }
+    }
+    expanded
+}
+
+// MCPTDM_<NAME>=<values> overrides (or adds) the parameter <name>, with "__" standing for "."
+// and values separated by whitespace, e.g. MCPTDM_MCTS__SAMPLES_N="64 128" or MCPTDM_PRESET=mcptdm
+fn apply_env_overrides(
+    name_value_pairs: &mut Vec<(String, Vec<String>)>,
+    vars: impl Iterator<Item = (String, String)>,
+) {
+    for (key, value) in vars {
+        let name = match key.strip_prefix("MCPTDM_") {
+            Some(name) => name.to_lowercase().replace("__", "."),
+            None => continue,
+        };
+        let vals = value.split_whitespace().map(|v| v.to_owned()).collect_vec();
+        match name_value_pairs.iter_mut().find(|pair| pair.0 == name) {
+            Some(pair) => pair.1 = vals,
+            None => name_value_pairs.push((name, vals)),
+        }
+    }
+}
+
+// Mean and sample standard deviation of the main results over each scenario's block of seeds.
+// results holds the numeric columns of each results.cache line: the cost components,
+// then the reward fields, then the seconds taken.
+fn seed_block_summary(scenarios: &[Parameters], results: &BTreeMap<String, Vec<f64>>) -> String {
+    let mut blocks = BTreeMap::<String, Vec<&Vec<f64>>>::new();
+    for s in scenarios.iter() {
+        let scenario_name = s.scenario_name.as_ref().unwrap();
+        if let Some(values) = results.get(scenario_name) {
+            let block_name = scenario_name.replace(&format!(",rng_seed={},", s.rng_seed), ",");
+            blocks.entry(block_name).or_default().push(values);
+        }
+    }
+
+    let columns: [(&str, fn(&[f64]) -> f64); 5] = [
+        // with the comfort columns just before the seconds, when the line has them,
+        // and the lane-keeping one after those on the lines since it
+        ("cost", |v| {
+            let n = v.len();
+            v[0..4].iter().sum::<f64>()
+                + if n > 37 {
+                    v[n - 4] + v[n - 3] + v[n - 2]
+                } else if n > 27 {
+                    v[n - 3] + v[n - 2]
+                } else {
+                    0.0
+                }
+        }),
+        ("safety", |v| v[1]),
+        ("crashed", |v| v[4]),
+        ("avg_vel", |v| v[7]),
+        ("mean_ts", |v| v[8]),
+    ];
+
+    let mut table = format!("{:>5}", "seeds");
+    for (name, _) in columns.iter() {
+        table += &format!(" {:>9} {:>8}", name, "std");
+    }
+    table += " scenario\n";
+    for (block_name, values) in blocks {
+        let n = values.len() as f64;
+        table += &format!("{:5}", values.len());
+        for (_, column) in columns.iter() {
+            let mean = values.iter().map(|v| column(v)).sum::<f64>() / n;
+            let std_dev = if values.len() > 1 {
+                (values
+                    .iter()
+                    .map(|v| (column(v) - mean).powi(2))
+                    .sum::<f64>()
+                    / (n - 1.0))
+                    .sqrt()
+            } else {
+                0.0
+            };
+            table += &format!(" {:9.3} {:8.3}", mean, std_dev);
+        }
+        table += &format!(" {}\n", block_name);
+    }
+    table
+}
+
+// The parameter sets of the command line's args, after the program (and subcommand)
+pub fn scenarios_from_args(args: impl Iterator<Item = String>) -> Vec<Parameters> {
+    let parameters_default = Parameters::new().unwrap();
+
+    // let args = std::env::args().collect_vec();
+    let mut name_value_pairs = Vec::<(String, Vec<String>)>::new();
+    // let mut arg_i = 0;
+    let mut name: Option<String> = None;
+    let mut vals: Option<Vec<String>> = None;
+    for arg in args.chain(std::iter::once("::".to_owned())) {
+        if arg == "--help" || arg == "help" {
+            eprintln!("Usage: (<param name> [param value]* ::)*");
+            eprintln!("For example: limit 8 12 16 24 32 :: steps 1000 :: rng_seed 0 1 2 3 4");
+            eprintln!("Or play a run recorded with replays_dir: replay <file.replay>");
+            eprintln!(
+                "Or shrink a run where the ego crashes to a scenario file: minimize <params>"
+            );
+            eprintln!(
+                "Or compare configurations over seeds: evaluate <n_episodes> [--json] <params>"
+            );
+            eprintln!(
+                "Or see how they hold up against obstacle cars unlike the planners' hypotheses: \
+                 stress <n_episodes> [--json] <params>"
+            );
+            eprintln!(
+                "Presets (preset <name> ::): {}",
+                PRESETS.iter().map(|(n, _)| n).join(", ")
+            );
+            eprintln!("Environment variables MCPTDM_<NAME> (with __ for .) override parameters");
+            eprintln!("Valid parameters and their default values:");
+            let params_str = format!("{:?}", parameters_default)
+                .replace(", file_name: None", "")
+                .replace(", ", "\n\t")
+                .replace("Parameters { ", "\t")
+                .replace(" }", "");
+            eprintln!("{}", params_str);
+            std::process::exit(0);
+        }
+        if name.is_some() {
+            if arg == "::" {
+                let name = name.take().unwrap();
+                if name_value_pairs.iter().any(|pair| pair.0 == name) {
+                    panic!("Parameter {} has already been specified!", name);
+                }
+                name_value_pairs.push((name, vals.take().unwrap()));
+            } else {
+                vals.as_mut().unwrap().push(arg);
+            }
+        } else if arg != "::" {
+            name = Some(arg);
+            vals = Some(Vec::new());
+        }
+    }
+
+    apply_env_overrides(&mut name_value_pairs, std::env::vars());
+    let name_value_pairs = expand_presets(name_value_pairs);
+
+    // for (name, vals) in name_value_pairs.iter() {
+    //     eprintln!("{}: {:?}", name, vals);
+    // }
+
+    let mut base_scenario = parameters_default;
+    base_scenario.scenario_name = Some("".to_owned());
+
+    create_scenarios(&base_scenario, &name_value_pairs)
+    // for (i, scenario) in scenarios.iter().enumerate() {
+    //     eprintln!("{}: {:?}", i, scenario.file_name);
+    // }
+}
+
+pub fn run_parallel_scenarios() {
+    let scenarios = scenarios_from_args(std::env::args().skip(1));
+
+    let n_scenarios = scenarios.len();
+    eprintln!("Starting to run {} scenarios", n_scenarios);
+    if n_scenarios == 0 {
+        return;
+    }
+
+    init_logging(&scenarios[0]);
+
+    let thread_limit = scenarios[0].thread_limit;
+    if thread_limit > 0 {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(thread_limit as usize)
+            .build_global()
+            .unwrap();
+    }
+
+    let load_and_record_results = scenarios[0].load_and_record_results;
+
+    let n_scenarios_completed = AtomicUsize::new(0);
+    let cumulative_results = Mutex::new(BTreeMap::new());
+
+    let cache_filename = "results.cache";
+    // read the existing cache file
+    if load_and_record_results {
+        let mut cumulative_results = cumulative_results.lock().unwrap();
+        if let Ok(file) = File::open(cache_filename) {
+            let file = BufReader::new(file);
+            for line in file.lines() {
+                let line = line.unwrap();
+                let parts = line.split_ascii_whitespace().collect_vec();
+                let scenario_name = parts[0].to_owned();
+                let values = parts[1..].iter().map(|v| v.parse().unwrap()).collect();
+                cumulative_results.insert(scenario_name, values);
+            }
+        }
+    }
+
+    let open_append = |filename: &str| {
+        if load_and_record_results {
+            Some(Mutex::new(
+                OpenOptions::new()
+                    .append(true)
+                    .create(true)
+                    .open(filename)
+                    .unwrap(),
+            ))
+        } else {
+            None
+        }
+    };
+    let file = open_append(cache_filename);
+    // the scenarios that couldn't be run, which aren't cached so they run again next time
+    let errors_file = open_append("results.errors");
+    let errors = Mutex::new(Vec::new());
+
+    if n_scenarios == 1 {
+        let mut scenario = scenarios[0].clone();
+        scenario.is_single_run = true;
+
+        let scenario_name = scenario.scenario_name.clone().unwrap();
+        let (cost, reward) = match run_with_parameters(scenario) {
+            Ok(result) => result,
+            Err(e) => {
+                eprintln!("{}: {}", scenario_name, e);
+                std::process::exit(1);
+            }
+        };
+        println_f!("{scenario_name}");
+        println_f!("{cost:?}, {reward:?}");
+    } else {
+        scenarios.par_iter().for_each(|scenario| {
+            let result = std::panic::catch_unwind(|| {
+                let scenario_name = scenario.scenario_name.clone().unwrap();
+
+                if cumulative_results
+                    .lock()
+                    .unwrap()
+                    .contains_key(&scenario_name)
+                {
+                    n_scenarios_completed.fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
+
+                let start_time = Instant::now();
+                let result = run_with_parameters(scenario.clone());
+                let seconds = start_time.elapsed().as_secs_f64();
+
+                n_scenarios_completed.fetch_add(1, Ordering::Relaxed);
+                let (cost, reward) = match result {
+                    Ok(result) => result,
+                    Err(e) => {
+                        println!(
+                            "{}/{} ({}): error: {}",
+                            n_scenarios_completed.load(Ordering::Relaxed),
+                            n_scenarios,
+                            scenario.rng_seed,
+                            e
+                        );
+                        tracing::error!("ERROR for scenario: {:?}: {}", scenario_name, e);
+                        if let Some(ref file) = errors_file {
+                            writeln_f!(file.lock().unwrap(), "{scenario_name} {e}").unwrap();
+                        }
+                        errors.lock().unwrap().push((scenario_name, e));
+                        return;
+                    }
+                };
+                print!(
+                    "{}/{} ({}): ",
+                    n_scenarios_completed.load(Ordering::Relaxed),
+                    n_scenarios,
+                    scenario.rng_seed,
+                );
+                let comfort = ComfortDisplay(cost);
+                let results_line = format_f!("{cost} {reward} {comfort} {seconds:6.2}");
+                println!("{}", results_line);
+                if let Some(ref file) = file {
+                    writeln_f!(file.lock().unwrap(), "{scenario_name} {results_line}").unwrap();
+                }
+
+                let values = results_line
+                    .split_ascii_whitespace()
+                    .map(|v| v.parse().unwrap())
+                    .collect();
+                cumulative_results
+                    .lock()
+                    .unwrap()
+                    .insert(scenario_name, values);
+            });
+            if result.is_err() {
+                tracing::error!(
+                    "PANIC for scenario: {:?}",
+                    scenario.scenario_name.as_ref().unwrap()
+                );
+            }
+        });
+
+        let errors = errors.into_inner().unwrap();
+        if !errors.is_empty() {
+            eprintln!("{} of {} scenarios failed:", errors.len(), n_scenarios);
+            for (scenario_name, e) in errors.iter() {
+                eprintln!("{}: {}", scenario_name, e);
+            }
+        }
+
+        if scenarios.iter().any(|s| s.seed_reps > 1) {
+            print!(
+                "{}",
+                seed_block_summary(&scenarios, &cumulative_results.lock().unwrap())
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pairs(pairs: &[(&str, &[&str])]) -> Vec<(String, Vec<String>)> {
+        pairs
+            .iter()
+            .map(|(n, vals)| (n.to_string(), vals.iter().map(|v| v.to_string()).collect()))
+            .collect()
+    }
+
+    #[test]
+    fn test_presets_and_env_overrides() {
+        let mut name_value_pairs = pairs(&[
+            ("rng_seed", &["0", "1"]),
+            ("preset", &["eudm_paper"]),
+            ("eudm.samples_n", &["8"]),
+        ]);
+        apply_env_overrides(
+            &mut name_value_pairs,
+            vec![
+                ("MCPTDM_RNG_SEED".to_owned(), "0:2:10".to_owned()),
+                ("MCPTDM_EUDM__SEARCH_DEPTH".to_owned(), "3 5".to_owned()),
+                ("PATH".to_owned(), "/bin".to_owned()),
+            ]
+            .into_iter(),
+        );
+
+        assert_eq!(
+            expand_presets(name_value_pairs),
+            pairs(&[
+                ("rng_seed", &["0:2:10"]),
+                ("method", &["eudm"]),
+                ("use_cfb", &["false", "true"]),
+                ("eudm.samples_n", &["8"]),
+                ("eudm.search_depth", &["3", "5"]),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_seed_reps() {
+        let mut base_scenario = Parameters::new().unwrap();
+        base_scenario.scenario_name = Some("".to_owned());
+        let scenarios = create_scenarios(
+            &base_scenario,
+            &pairs(&[
+                ("method", &["fixed"]),
+                ("rng_seed", &["0", "10"]),
+                ("seed_reps", &["3"]),
+            ]),
+        );
+        assert_eq!(
+            scenarios.iter().map(|s| s.rng_seed).collect_vec(),
+            vec![0, 1, 2, 10, 11, 12]
+        );
+
+        // each block's mean and standard deviation of the total cost
+        let results = scenarios
+            .iter()
+            .map(|s| {
+                let cost = (s.rng_seed % 10) as f64;
+                let values = vec![cost, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0];
+                (s.scenario_name.clone().unwrap(), values)
+            })
+            .collect();
+        let summary = seed_block_summary(&scenarios[0..3], &results);
+        let row = summary
+            .lines()
+            .nth(1)
+            .unwrap()
+            .split_whitespace()
+            .collect_vec();
+        assert_eq!(&row[0..3], &["3", "1.000", "1.000"]);
+    }
+}
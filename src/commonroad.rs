@@ -0,0 +1,687 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    fmt::Write as _,
+    hash::{Hash, Hasher},
+    io,
+    path::{Path, PathBuf},
+};
+
+use roxmltree::{Document, Node};
+
+use crate::{
+    arg_parameters::Parameters,
+    car::Car,
+    road::{Road, LANE_WIDTH},
+    scenario_file::{CarSpec, ObstacleSpec, PolicySpec, ScenarioFile},
+    sim_error::SimError,
+};
+
+// A CommonRoad (commonroad.in.tum.de) scenario, loaded with a scenario_file ending in .xml.
+// The road follows the lanes of the planning problem's initial lanelet: the rightmost lanelet
+// beside it going the same way, with its predecessors and successors, makes the reference line,
+// where x is the distance along it and lane 0 is its lane. Cars start at the centers of their
+// lanes, lined up with them, and keep their speed. Static obstacles are stopped cars.
+// Obstacles off these lanes, like on crossing roads or going the other way, are left out.
+//
+// The road is straightened out, or with commonroad.curvilinear, follows the reference line's
+// curvature (as road_geometry).
+pub struct CommonRoadScenario {
+    pub benchmark_id: String,
+    pub version: String,
+    // seconds between the scenario's time steps
+    pub dt: f64,
+    pub planning_problem_id: String,
+    pub reference: ReferenceLine,
+    pub n_lanes: i32,
+    lane_width: f64,
+    ego: InitialState,
+    obstacles: Vec<Obstacle>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct InitialState {
+    x: f64,
+    y: f64,
+    vel: f64,
+}
+
+struct Obstacle {
+    state: InitialState,
+    length: f64,
+    is_static: bool,
+}
+
+struct Lanelet {
+    center: Vec<(f64, f64)>,
+    width: f64,
+    left: Option<u64>,
+    right: Option<u64>,
+    predecessor: Option<u64>,
+    successor: Option<u64>,
+}
+
+pub fn is_commonroad(path: &str) -> bool {
+    path.ends_with(".xml")
+}
+
+fn child<'a, 'i>(node: Node<'a, 'i>, name: &str) -> Option<Node<'a, 'i>> {
+    node.children().find(|n| n.has_tag_name(name))
+}
+
+fn number(node: Node) -> Option<f64> {
+    node.text().and_then(|t| t.trim().parse().ok())
+}
+
+// an exact value, or the middle of an interval
+fn value(node: Node, name: &str) -> Option<f64> {
+    let node = child(node, name)?;
+    if let Some(exact) = child(node, "exact") {
+        return number(exact);
+    }
+    match (child(node, "intervalStart"), child(node, "intervalEnd")) {
+        (Some(start), Some(end)) => Some((number(start)? + number(end)?) / 2.0),
+        _ => number(node),
+    }
+}
+
+fn points(bound: Node) -> Vec<(f64, f64)> {
+    bound
+        .children()
+        .filter(|n| n.has_tag_name("point"))
+        .filter_map(|p| Some((value(p, "x")?, value(p, "y")?)))
+        .collect()
+}
+
+// the lanelet referred to, if it goes the same way
+fn same_way(lanelet: Node, name: &str) -> Option<u64> {
+    let node = child(lanelet, name)?;
+    if node.attribute("drivingDir") == Some("opposite") {
+        return None;
+    }
+    node.attribute("ref")?.parse().ok()
+}
+
+fn initial_state(node: Node) -> Option<InitialState> {
+    let state = child(node, "initialState")?;
+    let point = child(child(state, "position")?, "point")?;
+    Some(InitialState {
+        x: value(point, "x")?,
+        y: value(point, "y")?,
+        vel: value(state, "velocity").unwrap_or(0.0),
+    })
+}
+
+fn parse_lanelet(node: Node) -> Lanelet {
+    let left = points(child(node, "leftBound").expect("lanelet without a leftBound"));
+    let right = points(child(node, "rightBound").expect("lanelet without a rightBound"));
+    assert!(
+        left.len() >= 2 && left.len() == right.len(),
+        "lanelet {} needs bounds of the same number of points",
+        node.attribute("id").unwrap_or("?")
+    );
+    let width = left
+        .iter()
+        .zip(right.iter())
+        .map(|(l, r)| (l.0 - r.0).hypot(l.1 - r.1))
+        .sum::<f64>()
+        / left.len() as f64;
+    let center = left
+        .iter()
+        .zip(right.iter())
+        .map(|(l, r)| ((l.0 + r.0) / 2.0, (l.1 + r.1) / 2.0))
+        .collect();
+    let first_ref = |name: &str| {
+        child(node, name)
+            .and_then(|n| n.attribute("ref"))
+            .and_then(|r| r.parse().ok())
+    };
+    Lanelet {
+        center,
+        width,
+        left: same_way(node, "adjacentLeft"),
+        right: same_way(node, "adjacentRight"),
+        predecessor: first_ref("predecessor"),
+        successor: first_ref("successor"),
+    }
+}
+
+impl CommonRoadScenario {
+    pub fn load(path: &str) -> Result<Self, SimError> {
+        let load_error = |message: String| SimError::Load {
+            path: path.to_owned(),
+            message,
+        };
+        let contents = std::fs::read_to_string(path).map_err(|e| load_error(e.to_string()))?;
+        Self::parse(&contents).map_err(load_error)
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, String> {
+        let doc = Document::parse(contents).map_err(|e| e.to_string())?;
+        let root = doc.root_element();
+        let attribute = |name: &str| root.attribute(name).unwrap_or("").to_owned();
+
+        let lanelets = root
+            .children()
+            .filter(|n| n.has_tag_name("lanelet"))
+            .map(|n| {
+                let id = n.attribute("id").and_then(|id| id.parse().ok());
+                (id.expect("lanelet without an id"), parse_lanelet(n))
+            })
+            .collect::<HashMap<u64, Lanelet>>();
+
+        let problem = root
+            .descendants()
+            .find(|n| n.has_tag_name("planningProblem"))
+            .ok_or("no planningProblem for the ego")?;
+        let ego = initial_state(problem).ok_or("no initialState point for the planning problem")?;
+
+        let mut obstacles = Vec::new();
+        for node in root.children() {
+            let is_static = node.has_tag_name("staticObstacle");
+            if !is_static && !node.has_tag_name("dynamicObstacle") {
+                continue;
+            }
+            let kind = child(node, "type").and_then(|t| t.text()).unwrap_or("");
+            if kind == "pedestrian" {
+                continue;
+            }
+            let length = child(node, "shape")
+                .and_then(|s| child(s, "rectangle"))
+                .and_then(|r| value(r, "length"))
+                .unwrap_or(0.0);
+            if let Some(state) = initial_state(node) {
+                obstacles.push(Obstacle {
+                    state,
+                    length,
+                    is_static,
+                });
+            }
+        }
+
+        // the lanelet the ego starts in, then the rightmost one beside it going the same way
+        let ego_lanelet = lanelets
+            .iter()
+            .min_by(|a, b| {
+                let dist = |l: &Lanelet| ReferenceLine::new(&l.center).closest(ego.x, ego.y).0;
+                dist(a.1).partial_cmp(&dist(b.1)).unwrap()
+            })
+            .map(|(&id, _)| id)
+            .ok_or("no lanelets")?;
+        let mut rightmost = ego_lanelet;
+        let mut n_lanes = 1;
+        // (bounded, in case the lanelets are each other's neighbors on both sides)
+        for _ in 0..lanelets.len() {
+            match lanelets[&rightmost]
+                .right
+                .filter(|r| lanelets.contains_key(r))
+            {
+                Some(right) => rightmost = right,
+                None => break,
+            }
+        }
+        let mut lane = rightmost;
+        while let Some(left) = lanelets[&lane].left.filter(|l| lanelets.contains_key(l)) {
+            if n_lanes as usize >= lanelets.len() {
+                break;
+            }
+            lane = left;
+            n_lanes += 1;
+        }
+
+        // back through its predecessors, and then forward through the successors
+        let mut first = rightmost;
+        let mut seen = vec![first];
+        while let Some(prev) = lanelets[&first]
+            .predecessor
+            .filter(|p| lanelets.contains_key(p))
+        {
+            if seen.contains(&prev) {
+                break;
+            }
+            seen.push(prev);
+            first = prev;
+        }
+        let mut center = lanelets[&first].center.clone();
+        let mut chain = vec![first];
+        let mut lanelet = first;
+        while let Some(next) = lanelets[&lanelet]
+            .successor
+            .filter(|n| lanelets.contains_key(n))
+        {
+            if chain.contains(&next) {
+                break;
+            }
+            // the lanelets share their end and start points
+            center.extend_from_slice(&lanelets[&next].center[1..]);
+            chain.push(next);
+            lanelet = next;
+        }
+        let lane_width =
+            chain.iter().map(|id| lanelets[id].width).sum::<f64>() / chain.len() as f64;
+
+        let dt = root
+            .attribute("timeStepSize")
+            .and_then(|t| t.parse().ok())
+            .unwrap_or(0.1);
+        Ok(Self {
+            benchmark_id: attribute("benchmarkID"),
+            version: attribute("commonRoadVersion"),
+            dt,
+            planning_problem_id: problem.attribute("id").unwrap_or("0").to_owned(),
+            reference: ReferenceLine::new(&center),
+            n_lanes,
+            lane_width,
+            ego,
+            obstacles,
+        })
+    }
+
+    // the lane and station of a point, if it's on one of the road's lanes
+    fn place(&self, state: &InitialState) -> Option<(i32, f64)> {
+        let (s, d) = self.reference.to_frenet(state.x, state.y);
+        let lane = (d / self.lane_width).round() as i32;
+        let on_road = (0..self.n_lanes).contains(&lane)
+            && (0.0..=self.reference.length()).contains(&s)
+            && (d - lane as f64 * self.lane_width).abs() <= self.lane_width / 2.0;
+        on_road.then_some((lane, s))
+    }
+
+    // the cars to start with, on the road of the parameters
+    pub fn to_scenario_file(&self, params: &Parameters) -> ScenarioFile {
+        assert_eq!(
+            self.n_lanes, params.n_lanes,
+            "The CommonRoad scenario has {} lanes, so run it with n_lanes {}",
+            self.n_lanes, self.n_lanes
+        );
+        let (ego_lane, ego_s) = self
+            .place(&self.ego)
+            .expect("The CommonRoad planning problem's ego isn't on its own lanes");
+        // their reference point is the center, and ours the front
+        let ego_x = ego_s + Car::new(params, 0, ego_lane).length / 2.0;
+
+        let mut cars = Vec::new();
+        let mut obstacles = Vec::new();
+        let mut n_left_out = 0;
+        for obstacle in self.obstacles.iter() {
+            let (lane, x) = match self.place(&obstacle.state) {
+                Some(place) => place,
+                None => {
+                    n_left_out += 1;
+                    continue;
+                }
+            };
+            let x = x + obstacle.length / 2.0;
+            let truck = obstacle.length > 7.0;
+            if obstacle.is_static || obstacle.state.vel == 0.0 {
+                obstacles.push(ObstacleSpec { lane, x, truck });
+            } else {
+                cars.push(CarSpec {
+                    lane,
+                    x,
+                    vel: Some(obstacle.state.vel),
+                    preferred_vel: Some(obstacle.state.vel),
+                    preferred_accel: None,
+                    follow_time: None,
+                    policy: PolicySpec::Maintain,
+                    target_lane: None,
+                    truck,
+                });
+            }
+        }
+        if n_left_out > 0 {
+            eprintln!(
+                "Left out {} CommonRoad obstacles that aren't on the ego's road",
+                n_left_out
+            );
+        }
+
+        ScenarioFile {
+            spawn_seed: None,
+            fill_random_cars: false,
+            ego: Some(CarSpec {
+                lane: ego_lane,
+                x: ego_x,
+                vel: Some(self.ego.vel),
+                preferred_vel: None,
+                preferred_accel: None,
+                follow_time: None,
+                policy: PolicySpec::Maintain,
+                target_lane: None,
+                truck: false,
+            }),
+            cars,
+            obstacles,
+            events: Vec::new(),
+        }
+    }
+
+    // where a car on the road is in the scenario's frame, with its heading there
+    fn scenario_pose(&self, x: f64, y: f64, theta: f64) -> (f64, f64, f64) {
+        let d = (y - Road::get_lane_y(0)) / LANE_WIDTH * self.lane_width;
+        let (px, py) = self.reference.to_cartesian(x, d);
+        (px, py, self.reference.heading(x) + theta)
+    }
+}
+
+// A polyline, with its points' stations along it
+pub struct ReferenceLine {
+    points: Vec<(f64, f64)>,
+    stations: Vec<f64>,
+}
+
+impl ReferenceLine {
+    fn new(points: &[(f64, f64)]) -> Self {
+        let mut stations = vec![0.0];
+        for (a, b) in points.iter().zip(points.iter().skip(1)) {
+            stations.push(stations.last().unwrap() + (b.0 - a.0).hypot(b.1 - a.1));
+        }
+        Self {
+            points: points.to_vec(),
+            stations,
+        }
+    }
+
+    pub fn length(&self) -> f64 {
+        *self.stations.last().unwrap()
+    }
+
+    fn segment_heading(&self, i: usize) -> f64 {
+        let (a, b) = (self.points[i], self.points[i + 1]);
+        (b.1 - a.1).atan2(b.0 - a.0)
+    }
+
+    // the segment with station s, continuing the first and last ones past the ends
+    fn segment_at(&self, s: f64) -> usize {
+        let i = self.stations.partition_point(|&station| station <= s);
+        i.saturating_sub(1).min(self.points.len() - 2)
+    }
+
+    fn heading(&self, s: f64) -> f64 {
+        self.segment_heading(self.segment_at(s))
+    }
+
+    // distance to the closest point on the line, and its station and the offset to the left
+    fn closest(&self, x: f64, y: f64) -> (f64, f64, f64) {
+        let mut best = (f64::MAX, 0.0, 0.0);
+        for i in 0..self.points.len() - 1 {
+            let a = self.points[i];
+            let length = self.stations[i + 1] - self.stations[i];
+            let (sin, cos) = self.segment_heading(i).sin_cos();
+            let (dx, dy) = (x - a.0, y - a.1);
+            let along = (cos * dx + sin * dy).max(0.0).min(length);
+            let d = -sin * dx + cos * dy;
+            let dist = (dx - along * cos).hypot(dy - along * sin);
+            if dist < best.0 {
+                best = (dist, self.stations[i] + along, d);
+            }
+        }
+        best
+    }
+
+    fn to_frenet(&self, x: f64, y: f64) -> (f64, f64) {
+        let (_, s, d) = self.closest(x, y);
+        (s, d)
+    }
+
+    fn to_cartesian(&self, s: f64, d: f64) -> (f64, f64) {
+        let i = self.segment_at(s);
+        let (sin, cos) = self.segment_heading(i).sin_cos();
+        let along = s - self.stations[i];
+        let a = self.points[i];
+        (a.0 + along * cos - d * sin, a.1 + along * sin + d * cos)
+    }
+
+    // For road_geometry: the turn at each point is an arc as long as the shorter of the
+    // segments beside it, centered on the point, and the segments are straight between,
+    // and then it goes straight on
+    pub fn road_geometry(&self) -> String {
+        let n = self.points.len() - 1;
+        let lengths = (0..n)
+            .map(|i| self.stations[i + 1] - self.stations[i])
+            .collect::<Vec<_>>();
+        // the length and curvature of the arc at each point
+        let arcs = (0..=n)
+            .map(|i| {
+                if i == 0 || i == n || lengths[i - 1] <= 0.0 || lengths[i] <= 0.0 {
+                    return (0.0, 0.0);
+                }
+                let turn = self.segment_heading(i) - self.segment_heading(i - 1);
+                // wrapped to -PI..PI
+                let turn = turn.sin().atan2(turn.cos());
+                let length = lengths[i - 1].min(lengths[i]);
+                (length, turn / length)
+            })
+            .collect::<Vec<_>>();
+
+        let mut segments = Vec::new();
+        for i in 0..n {
+            let (start_length, start_curvature) = arcs[i];
+            let (end_length, end_curvature) = arcs[i + 1];
+            let straight = lengths[i] - start_length / 2.0 - end_length / 2.0;
+            for (length, curvature) in [
+                (start_length / 2.0, start_curvature),
+                (straight, 0.0),
+                (end_length / 2.0, end_curvature),
+            ] {
+                if length <= 1e-9 {
+                    continue;
+                }
+                segments.push(if curvature == 0.0 {
+                    format!("straight:{}", length)
+                } else {
+                    format!("arc:{}:{}", length, curvature)
+                });
+            }
+        }
+        // since the segments repeat, a long way before the road comes around again
+        segments.push("straight:1000".to_owned());
+        segments.join(",")
+    }
+}
+
+// where a run's solution goes: the commonroad.solution path for a single run,
+// and otherwise that with the method and seed in it, like videos
+pub fn solution_path(params: &Parameters) -> PathBuf {
+    let path = Path::new(&params.commonroad.solution);
+    if params.is_single_run {
+        return path.to_owned();
+    }
+    let mut hasher = DefaultHasher::new();
+    params.scenario_name.hash(&mut hasher);
+    let stem = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("solution");
+    path.with_file_name(format!(
+        "{}_{}_seed{}_{:016x}.xml",
+        stem,
+        params.method,
+        params.rng_seed,
+        hasher.finish()
+    ))
+}
+
+// The ego's trajectory as a CommonRoad solution, with a kinematic single-track (KS) state
+// at each of the scenario's time steps
+pub struct SolutionRecorder {
+    scenario: CommonRoadScenario,
+    step_interval: usize,
+    // x, y, steering angle, velocity, orientation, at the center of the ego
+    states: Vec<[f64; 5]>,
+}
+
+impl SolutionRecorder {
+    pub fn new(scenario: CommonRoadScenario, params: &Parameters) -> Self {
+        let step_interval = ((scenario.dt / params.physics_dt).round() as usize).max(1);
+        Self {
+            scenario,
+            step_interval,
+            states: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, road: &Road) {
+        if road.timesteps % self.step_interval != 0 {
+            return;
+        }
+        let ego = &road.cars[0];
+        let (sin, cos) = ego.theta().sin_cos();
+        let (x, y, heading) = self.scenario.scenario_pose(
+            ego.x() - ego.length / 2.0 * cos,
+            ego.y() - ego.length / 2.0 * sin,
+            ego.theta(),
+        );
+        self.states.push([x, y, ego.steer, ego.vel, heading]);
+    }
+
+    pub fn to_xml(&self, params: &Parameters, computation_time: f64) -> String {
+        let scenario = &self.scenario;
+        let mut xml = String::new();
+        writeln!(xml, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>").unwrap();
+        writeln!(
+            xml,
+            "<CommonRoadSolution benchmark_id=\"{}:{}:{}\" computation_time=\"{:.3}\" processor_name=\"selfdriving {}\">",
+            params.commonroad.solution_model,
+            scenario.benchmark_id,
+            scenario.version,
+            computation_time,
+            params.method
+        )
+        .unwrap();
+        writeln!(
+            xml,
+            "  <ksTrajectory planningProblem=\"{}\">",
+            scenario.planning_problem_id
+        )
+        .unwrap();
+        for (time, [x, y, steer, vel, heading]) in self.states.iter().enumerate() {
+            writeln!(
+                xml,
+                "    <ksState><x>{:.4}</x><y>{:.4}</y><steeringAngle>{:.4}</steeringAngle>\
+                 <velocity>{:.4}</velocity><orientation>{:.4}</orientation><time>{}</time></ksState>",
+                x, y, steer, vel, heading, time
+            )
+            .unwrap();
+        }
+        writeln!(xml, "  </ksTrajectory>\n</CommonRoadSolution>").unwrap();
+        xml
+    }
+
+    pub fn write(&self, params: &Parameters, computation_time: f64) -> io::Result<PathBuf> {
+        let path = solution_path(params);
+        std::fs::write(&path, self.to_xml(params, computation_time))?;
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::road_geometry::RoadGeometry;
+
+    // two lanes along a road that turns left, with a car ahead of the ego, a parked car,
+    // and a car on a road going the other way
+    const SCENARIO: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<commonRoad timeStepSize="0.1" commonRoadVersion="2020a" benchmarkID="TEST_1_T-1">
+  <lanelet id="1">
+    <leftBound><point><x>0</x><y>0</y></point><point><x>100</x><y>0</y></point></leftBound>
+    <rightBound><point><x>0</x><y>-3.5</y></point><point><x>100</x><y>-3.5</y></point></rightBound>
+    <successor ref="3"/>
+    <adjacentLeft ref="2" drivingDir="same"/>
+  </lanelet>
+  <lanelet id="2">
+    <leftBound><point><x>0</x><y>3.5</y></point><point><x>100</x><y>3.5</y></point></leftBound>
+    <rightBound><point><x>0</x><y>0</y></point><point><x>100</x><y>0</y></point></rightBound>
+    <adjacentRight ref="1" drivingDir="same"/>
+  </lanelet>
+  <lanelet id="3">
+    <leftBound><point><x>100</x><y>0</y></point><point><x>150</x><y>10</y></point></leftBound>
+    <rightBound><point><x>100</x><y>-3.5</y></point><point><x>150</x><y>6.5</y></point></rightBound>
+    <predecessor ref="1"/>
+  </lanelet>
+  <lanelet id="4">
+    <leftBound><point><x>100</x><y>20</y></point><point><x>0</x><y>20</y></point></leftBound>
+    <rightBound><point><x>100</x><y>23.5</y></point><point><x>0</x><y>23.5</y></point></rightBound>
+  </lanelet>
+  <dynamicObstacle id="10">
+    <type>car</type>
+    <shape><rectangle><length>4.5</length><width>1.8</width></rectangle></shape>
+    <initialState>
+      <position><point><x>40</x><y>1.75</y></point></position>
+      <velocity><exact>12</exact></velocity>
+    </initialState>
+  </dynamicObstacle>
+  <staticObstacle id="11">
+    <type>parkedVehicle</type>
+    <shape><rectangle><length>4.5</length><width>1.8</width></rectangle></shape>
+    <initialState><position><point><x>80</x><y>-1.75</y></point></position></initialState>
+  </staticObstacle>
+  <dynamicObstacle id="12">
+    <type>car</type>
+    <shape><rectangle><length>4.5</length><width>1.8</width></rectangle></shape>
+    <initialState>
+      <position><point><x>50</x><y>21.75</y></point></position>
+      <velocity><exact>10</exact></velocity>
+    </initialState>
+  </dynamicObstacle>
+  <planningProblem id="100">
+    <initialState>
+      <position><point><x>10</x><y>-1.7</y></point></position>
+      <velocity><exact>11</exact></velocity>
+    </initialState>
+  </planningProblem>
+</commonRoad>
+"#;
+
+    #[test]
+    fn test_commonroad_scenario() {
+        let scenario = CommonRoadScenario::parse(SCENARIO).unwrap();
+        assert_eq!(scenario.n_lanes, 2);
+        assert_eq!(scenario.planning_problem_id, "100");
+        assert!((scenario.reference.length() - (100.0 + 50.0_f64.hypot(10.0))).abs() < 1e-9);
+
+        let mut params = Parameters::new().unwrap();
+        params.n_lanes = 2;
+        let file = scenario.to_scenario_file(&params);
+        let ego = file.ego.as_ref().unwrap();
+        let ego_length = Car::new(&params, 0, 0).length;
+        assert_eq!((ego.lane, ego.vel), (0, Some(11.0)));
+        assert!((ego.x - (10.0 + ego_length / 2.0)).abs() < 1e-9);
+        assert_eq!(file.cars.len(), 1);
+        assert_eq!(file.cars[0].lane, 1);
+        assert!((file.cars[0].x - 42.25).abs() < 1e-9);
+        assert_eq!(file.obstacles.len(), 1);
+        assert_eq!(file.obstacles[0].lane, 0);
+
+        // a turn to the left at the end of the first lanelet
+        let geometry = scenario.reference.road_geometry();
+        let segments = geometry.split(',').collect::<Vec<_>>();
+        assert_eq!(segments.len(), 5);
+        assert_eq!(segments[4], "straight:1000");
+        let road_geometry = RoadGeometry::parse(&geometry);
+        let length = scenario.reference.length();
+        assert_eq!(road_geometry.heading(50.0, 0.0), 0.0);
+        assert!((road_geometry.heading(length, 0.0) - 0.2_f64.atan()).abs() < 1e-9);
+        // and ends up about where the lanes do, from where they start
+        let (x, y) = road_geometry.to_cartesian(length, 0.0);
+        assert!((x - 150.0).abs() < 0.5 && (y - 10.0).abs() < 0.5);
+
+        // and back to where the ego started in the solution
+        let mut road = Road::new(Arc::new(params.clone()));
+        let mut recorder = SolutionRecorder::new(scenario, &params);
+        let mut ego = road.cars[0].clone();
+        ego.set_x(10.0 + ego_length / 2.0);
+        ego.set_y(Road::get_lane_y(0));
+        road.cars[0] = ego;
+        recorder.record(&road);
+        let [x, y, _, _, heading] = recorder.states[0];
+        assert!((x - 10.0).abs() < 1e-9 && (y + 1.75).abs() < 1e-9);
+        assert!((heading - road.cars[0].theta()).abs() < 1e-9);
+        let xml = recorder.to_xml(&params, 1.0);
+        assert!(xml.contains("benchmark_id=\"KS2:SM1:TEST_1_T-1:2020a\""));
+        assert!(xml.contains("<ksTrajectory planningProblem=\"100\">"));
+        assert!(Document::parse(&xml).is_ok());
+    }
+}
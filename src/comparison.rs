@@ -0,0 +1,174 @@
+use rvx::{Rvx, RvxColor};
+
+use crate::{
+    arg_parameters::{self, Parameters},
+    car::Car,
+    cost::Cost,
+    road::Road,
+};
+
+// how far apart the egos are before they count as having diverged, in m
+const DIVERGED_DIST: f64 = 0.1;
+// a new point on the ego paths only after moving this far, in m
+const PATH_POINT_DIST: f64 = 0.5;
+
+// The parameters of the other planner, from the compare overrides like
+// "method=eudm,eudm.samples_n=32" on top of the run's own parameters.
+// Only the run being compared against records replays, videos, svgs and trajectories.
+pub fn compare_params(params: &Parameters) -> Parameters {
+    let mut other = params.clone();
+    for setting in params.compare.split(',').filter(|s| !s.trim().is_empty()) {
+        let (name, val) = setting
+            .split_once('=')
+            .unwrap_or_else(|| panic!("compare setting '{}' should be like name=value", setting));
+        arg_parameters::set_parameter(&mut other, name.trim(), val.trim());
+    }
+    if other.cost_file != params.cost_file {
+        arg_parameters::load_cost_spec(&mut other);
+    }
+    other.compare = String::new();
+    other.replays_dir = String::new();
+    other.record = String::new();
+    other.svg.dir = String::new();
+    other.trajectories.dir = String::new();
+    other.commonroad.solution = String::new();
+    other
+}
+
+// Two planners driving the identical traffic, side by side in the viewer:
+// the compared planner's ego drawn over the road of the first, with both egos' paths
+// and a marker where they first went separate ways
+#[derive(Default)]
+pub struct Comparison {
+    ego_paths: [Vec<f64>; 2],
+    other_ego: Option<Car>,
+    // the timestep and world position of the first ego when they diverged
+    diverged_at: Option<(usize, (f64, f64))>,
+}
+
+impl Comparison {
+    // after each step, with the first planner's road and then the other's
+    pub fn track(&mut self, road: &Road, other_road: &Road) {
+        let egos = [
+            road.world_car(&road.cars[0]),
+            other_road.world_car(&other_road.cars[0]),
+        ];
+        for (path, ego) in self.ego_paths.iter_mut().zip(egos.iter()) {
+            let moved = match path[..] {
+                [.., x, y] => (ego.x() - x).hypot(ego.y() - y) > PATH_POINT_DIST,
+                _ => true,
+            };
+            if moved {
+                path.extend_from_slice(&[ego.x(), ego.y()]);
+            }
+        }
+
+        let dist = (egos[0].x() - egos[1].x()).hypot(egos[0].y() - egos[1].y());
+        if self.diverged_at.is_none() && dist > DIVERGED_DIST {
+            self.diverged_at = Some((road.timesteps, (egos[0].x(), egos[0].y())));
+        }
+        let [_, other_ego] = egos;
+        self.other_ego = Some(other_ego);
+    }
+
+    pub fn diverged_step(&self) -> Option<usize> {
+        self.diverged_at.map(|(step, _)| step)
+    }
+
+    pub fn shapes(&self, params: &Parameters) -> Vec<rvx::Shape> {
+        let colors = [RvxColor::GREEN.set_a(0.5), other_color().set_a(0.5)];
+        let mut shapes = self
+            .ego_paths
+            .iter()
+            .zip(colors.iter())
+            .filter(|(path, _)| path.len() >= 4)
+            .map(|(path, &color)| Rvx::lines(path, 4.0).color(color))
+            .collect::<Vec<_>>();
+        if let Some((_, (x, y))) = self.diverged_at {
+            shapes.push(
+                Rvx::circle()
+                    .scale(1.5)
+                    .translate(&[x, y])
+                    .color(RvxColor::YELLOW.set_a(0.8)),
+            );
+        }
+        if let Some(ego) = self.other_ego.as_ref() {
+            let (sin, cos) = ego.theta().sin_cos();
+            shapes.push(
+                Rvx::square()
+                    .scale_xy(&[ego.length, ego.width])
+                    .rot(ego.theta())
+                    .translate(&[
+                        ego.x() - ego.length / 2.0 * cos,
+                        ego.y() - ego.length / 2.0 * sin,
+                    ])
+                    .color(other_color().set_a(if params.graphics_for_paper { 1.0 } else { 0.6 })),
+            );
+        }
+        shapes
+    }
+
+    pub fn summary(
+        &self,
+        params: &Parameters,
+        other_params: &Parameters,
+        other_cost: &Cost,
+    ) -> String {
+        let diverged = match self.diverged_step() {
+            Some(step) => format!("diverged at step {}", step),
+            None => "never diverged".to_owned(),
+        };
+        format!(
+            "{} vs {} ({}): {}, compared cost {:.2}",
+            params.method,
+            other_params.method,
+            params.compare,
+            diverged,
+            other_cost.total()
+        )
+    }
+}
+
+// the compared planner's ego
+fn other_color() -> RvxColor {
+    RvxColor::rgb(0.0, 0.8, 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[test]
+    fn test_compare_params() {
+        let mut params = Parameters::new().unwrap();
+        params.compare = "method=eudm, eudm.samples_n=3".to_owned();
+        params.record = "run.mp4".to_owned();
+        let other = compare_params(&params);
+        assert_eq!(other.method, "eudm");
+        assert_eq!(other.eudm.samples_n, 3);
+        assert!(other.compare.is_empty() && other.record.is_empty());
+        assert_eq!(other.rng_seed, params.rng_seed);
+    }
+
+    #[test]
+    fn test_divergence() {
+        let params = Arc::new(Parameters::new().unwrap());
+        let road = Road::new(params.clone());
+        let mut other_road = road.clone();
+        let mut comparison = Comparison::default();
+        comparison.track(&road, &other_road);
+        assert_eq!(comparison.diverged_step(), None);
+
+        other_road.cars[0].set_y(road.cars[0].y() + 1.0);
+        other_road.timesteps = 7;
+        let mut road = road;
+        road.timesteps = 7;
+        comparison.track(&road, &other_road);
+        assert_eq!(comparison.diverged_step(), Some(7));
+        assert_eq!(comparison.ego_paths[1].len(), 4);
+        // the other ego's path, the marker and the other ego, but no path yet for the first
+        assert_eq!(comparison.shapes(&params).len(), 3);
+    }
+}
@@ -0,0 +1,267 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    road::Road,
+    side_policies::{SidePolicy, SidePolicyTrait, Trajectory},
+};
+
+// What the ego sees happen ahead of it by the time a contingency plan branches,
+// relative to the car that was its lead when the plan was made
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd, Eq, Hash, Serialize, Deserialize)]
+pub enum Outcome {
+    Nominal,
+    // the same lead car, braking harder than eudm.contingency_brake_accel
+    LeadBrakes,
+    // a different car is now the closest ahead in the ego's lane, like one that cut in
+    NewLead,
+}
+
+pub fn lead_car(road: &Road) -> Option<usize> {
+    road.dist_clear_ahead_in_lane(0, road.cars[0].current_lane())
+        .map(|(_, car_i)| car_i)
+}
+
+pub fn classify_outcome(road: &Road, planned_lead_car_i: Option<usize>) -> Outcome {
+    match lead_car(road) {
+        Some(car_i) if Some(car_i) != planned_lead_car_i => Outcome::NewLead,
+        Some(car_i) if road.cars[car_i].accel < -road.params.eudm.contingency_brake_accel => {
+            Outcome::LeadBrakes
+        }
+        _ => Outcome::Nominal,
+    }
+}
+
+// Follows policy_a until delay_time, and then on each step the branch policy for
+// whatever outcome it sees, or the nominal branch's for an outcome it didn't plan for
+#[derive(PartialEq, PartialOrd, Serialize, Deserialize)]
+pub struct ContingencyPolicy {
+    policy_a: Box<SidePolicy>,
+    branches: Vec<(Outcome, SidePolicy)>,
+    lead_car_i: Option<usize>,
+    delay_time: f64,
+    start_time: Option<f64>,
+    active_branch: Option<usize>,
+}
+
+// clone_from copies into the existing box and branches, like DelayedPolicy's
+impl Clone for ContingencyPolicy {
+    fn clone(&self) -> Self {
+        Self {
+            policy_a: self.policy_a.clone(),
+            branches: self.branches.clone(),
+            lead_car_i: self.lead_car_i,
+            delay_time: self.delay_time,
+            start_time: self.start_time,
+            active_branch: self.active_branch,
+        }
+    }
+
+    fn clone_from(&mut self, source: &Self) {
+        self.policy_a.clone_from(&source.policy_a);
+        if self.branches.len() == source.branches.len() {
+            for ((outcome, policy), (source_outcome, source_policy)) in
+                self.branches.iter_mut().zip(source.branches.iter())
+            {
+                *outcome = *source_outcome;
+                policy.clone_from(source_policy);
+            }
+        } else {
+            self.branches.clone_from(&source.branches);
+        }
+        self.lead_car_i = source.lead_car_i;
+        self.delay_time = source.delay_time;
+        self.start_time = source.start_time;
+        self.active_branch = source.active_branch;
+    }
+}
+
+impl std::fmt::Debug for ContingencyPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f)?;
+        writeln!(f, "policy_id: {:?}", self.policy_id())?;
+        writeln!(f, "policy_a: {:?}", self.policy_a)?;
+        for (outcome, policy) in self.branches.iter() {
+            writeln!(f, "if {:?}: {:?}", outcome, policy)?;
+        }
+        writeln!(f, "lead_car_i: {:?}", self.lead_car_i)?;
+        writeln!(f, "delay_time: {:.2?}", self.delay_time)?;
+        writeln!(f, "start_time: {:.2?}", self.start_time)?;
+        writeln!(f, "active_branch: {:?}", self.active_branch)
+    }
+}
+
+impl ContingencyPolicy {
+    pub fn new(
+        policy_a: SidePolicy,
+        branches: Vec<(Outcome, SidePolicy)>,
+        lead_car_i: Option<usize>,
+        delay_time: f64,
+    ) -> Self {
+        assert!(!branches.is_empty());
+        Self {
+            policy_a: Box::new(policy_a),
+            branches,
+            lead_car_i,
+            delay_time,
+            start_time: None,
+            active_branch: None,
+        }
+    }
+
+    fn check_for_switch(&mut self, road: &Road, dt: f64) {
+        let start_time = *self.start_time.get_or_insert(road.t);
+        if start_time + self.delay_time - road.t >= dt {
+            return;
+        }
+        let outcome = classify_outcome(road, self.lead_car_i);
+        let branch_i = self
+            .branches
+            .iter()
+            .position(|(o, _)| *o == outcome)
+            .or_else(|| {
+                self.branches
+                    .iter()
+                    .position(|(o, _)| *o == Outcome::Nominal)
+            })
+            .unwrap_or(0);
+        self.active_branch = Some(branch_i);
+    }
+
+    fn active_policy(&mut self) -> &mut SidePolicy {
+        match self.active_branch {
+            Some(branch_i) => &mut self.branches[branch_i].1,
+            None => &mut self.policy_a,
+        }
+    }
+}
+
+impl SidePolicyTrait for ContingencyPolicy {
+    fn precheck(&mut self, road: &Road, dt: f64) {
+        self.check_for_switch(road, dt);
+    }
+
+    fn choose_target_lane(&mut self, road: &Road, car_i: usize) -> i32 {
+        self.active_policy().choose_target_lane(road, car_i)
+    }
+
+    fn choose_trajectory(&mut self, road: &Road, car_i: usize, traj: &mut Trajectory) {
+        self.active_policy().choose_trajectory(road, car_i, traj)
+    }
+
+    fn choose_follow_time(&mut self, road: &crate::Road, car_i: usize) -> f64 {
+        self.active_policy().choose_follow_time(road, car_i)
+    }
+
+    fn choose_vel(&mut self, road: &Road, car_i: usize) -> f64 {
+        self.active_policy().choose_vel(road, car_i)
+    }
+
+    fn policy_id(&self) -> u32 {
+        let active_id = match self.active_branch {
+            Some(branch_i) => self.branches[branch_i].1.policy_id(),
+            None => self.policy_a.policy_id(),
+        };
+        200 + self.delay_time as u32 * 1000 + 10 * active_id
+    }
+
+    fn operating_policy(&self) -> SidePolicy {
+        match self.active_branch {
+            Some(branch_i) => self.branches[branch_i].1.operating_policy(),
+            None => self.policy_a.operating_policy(),
+        }
+    }
+
+    // the active branch follows the lead car's outcome on every step
+    fn is_settled(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{arg_parameters::Parameters, car::Car, mpdm::make_policy_choices};
+
+    #[test]
+    fn test_contingency_branches() {
+        let params = Parameters::new().unwrap();
+        let choices = make_policy_choices(&params);
+        let mut road = Road::new(Arc::new(params.clone()));
+        let mut lead = Car::new(&params, 1, 0);
+        lead.set_x(30.0);
+        road.cars.push(lead);
+        road.update_cars_spatial();
+        assert_eq!(lead_car(&road), Some(1));
+
+        let decelerate = choices.last().unwrap().clone();
+        let new_policy = || {
+            ContingencyPolicy::new(
+                choices[0].clone(),
+                vec![
+                    (Outcome::Nominal, choices[1].clone()),
+                    (Outcome::LeadBrakes, decelerate.clone()),
+                ],
+                Some(1),
+                1.0,
+            )
+        };
+        let dt = params.physics_dt;
+
+        // the first policy until the branch
+        let mut policy = new_policy();
+        policy.precheck(&road, dt);
+        assert_eq!(policy.policy_id(), 200 + 1000);
+
+        // then whichever the lead car calls for, on each step
+        road.t = 1.0;
+        policy.precheck(&road, dt);
+        assert_eq!(policy.operating_policy(), choices[1]);
+        road.cars[1].accel = -5.0;
+        policy.precheck(&road, dt);
+        assert_eq!(policy.operating_policy(), decelerate);
+
+        // and the nominal branch for the outcome it didn't plan for
+        road.cars[1].set_x(60.0);
+        let mut cut_in = Car::new(&params, 2, 0);
+        cut_in.set_x(20.0);
+        road.cars.push(cut_in);
+        road.update_cars_spatial();
+        assert_eq!(classify_outcome(&road, Some(1)), Outcome::NewLead);
+        policy.precheck(&road, dt);
+        assert_eq!(policy.operating_policy(), choices[1]);
+    }
+
+    #[test]
+    fn test_clone_from_reuses_boxes() {
+        let params = Parameters::new().unwrap();
+        let choices = make_policy_choices(&params);
+        let policy = ContingencyPolicy::new(
+            choices[0].clone(),
+            vec![(Outcome::Nominal, choices[1].clone())],
+            Some(1),
+            1.0,
+        );
+        let mut source = Car::new(&params, 0, 0);
+        source.side_policy = SidePolicy::ContingencyPolicy(policy.clone());
+        let mut car = source.clone();
+
+        // a later state of the source policy is copied into the car's existing box
+        let road = Road::new(Arc::new(params.clone()));
+        let mut advanced = policy;
+        advanced.start_time = Some(-2.0);
+        advanced.precheck(&road, params.physics_dt);
+        source.side_policy = SidePolicy::ContingencyPolicy(advanced);
+
+        let box_ptr = |car: &Car| match &car.side_policy {
+            SidePolicy::ContingencyPolicy(p) => &*p.policy_a as *const SidePolicy,
+            _ => unreachable!(),
+        };
+        let before = box_ptr(&car);
+        car.clone_from(&source);
+        assert_eq!(box_ptr(&car), before);
+        assert_eq!(car.side_policy, source.side_policy);
+        assert_eq!(car.operating_policy_id(), choices[1].policy_id());
+    }
+}
@@ -1,9 +1,19 @@
-#[derive(Clone, Copy, PartialEq)]
+use serde::{Deserialize, Serialize};
+
+use crate::arg_parameters::RiskParameters;
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct Cost {
     pub efficiency: f64,
     pub safety: f64,
     pub accel: f64,
     pub steer: f64,
+    // comfort, from the ego's longitudinal jerk and lateral acceleration
+    pub jerk: f64,
+    pub lat_accel: f64,
+    // lane keeping, from the ego's offset from its lane's center
+    #[serde(default)]
+    pub lane_offset: f64,
 
     pub discount: f64,
     pub discount_factor: f64,
@@ -21,12 +31,23 @@ impl std::fmt::Display for Cost {
     }
 }
 
+// The comfort components for results lines, which follow the reward's columns
+// so that the older lines still parse the same, and then the lane-keeping one
+pub struct ComfortDisplay(pub Cost);
+
+impl std::fmt::Display for ComfortDisplay {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = self.0.normalize();
+        write_f!(f, "{s.jerk:8.2} {s.lat_accel:8.2} {s.lane_offset:8.2}")
+    }
+}
+
 impl std::fmt::Debug for Cost {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let s = self;
         write_f!(
             f,
-            "eff: {s.efficiency:.2}, safe: {s.safety:.2}, accel: {s.accel:.2}, steer: {s.steer:.2}"
+            "eff: {s.efficiency:.2}, safe: {s.safety:.2}, accel: {s.accel:.2}, steer: {s.steer:.2}, jerk: {s.jerk:.2}, lat accel: {s.lat_accel:.2}, lane offset: {s.lane_offset:.2}"
         )
     }
 }
@@ -40,6 +61,9 @@ impl Cost {
             safety: 0.0,
             accel: 0.0,
             steer: 0.0,
+            jerk: 0.0,
+            lat_accel: 0.0,
+            lane_offset: 0.0,
             discount: 1.0,
             discount_factor,
             weight,
@@ -52,6 +76,9 @@ impl Cost {
             safety: 0.0,
             accel: 0.0,
             steer: 0.0,
+            jerk: 0.0,
+            lat_accel: 0.0,
+            lane_offset: 0.0,
             discount: 1.0,
             discount_factor: 1.0,
             weight: 1.0,
@@ -64,6 +91,9 @@ impl Cost {
             safety: self.safety * self.weight,
             accel: self.accel * self.weight,
             steer: self.steer * self.weight,
+            jerk: self.jerk * self.weight,
+            lat_accel: self.lat_accel * self.weight,
+            lane_offset: self.lane_offset * self.weight,
             discount: 1.0,
             discount_factor: 1.0,
             weight: 1.0,
@@ -71,7 +101,13 @@ impl Cost {
     }
 
     fn unweighted_total(&self) -> f64 {
-        self.efficiency + self.safety + self.accel + self.steer
+        self.efficiency
+            + self.safety
+            + self.accel
+            + self.steer
+            + self.jerk
+            + self.lat_accel
+            + self.lane_offset
     }
 
     pub fn total(&self) -> f64 {
@@ -89,6 +125,72 @@ impl Cost {
             *other
         }
     }
+
+    // Combines the costs of sampled roads by risk.aggregation. The mean is the usual
+    // sum over the count, but the others treat the costs' weights as their probabilities.
+    pub fn aggregate(costs: impl Iterator<Item = Cost>, risk: &RiskParameters) -> Self {
+        if risk.aggregation == "mean" {
+            let mut n = 0;
+            let sum = costs.inspect(|_| n += 1).sum::<Cost>();
+            return sum / n as f64;
+        }
+
+        // by descending total, with the weights as probabilities
+        let mut costs = costs
+            .map(|c| {
+                let weight = c.weight;
+                (Cost { weight: 1.0, ..c }, weight)
+            })
+            .collect::<Vec<_>>();
+        let total_weight = costs.iter().map(|(_, w)| w).sum::<f64>();
+        costs.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+        let mean = costs
+            .iter()
+            .map(|&(c, w)| c * (w / total_weight))
+            .sum::<Cost>();
+
+        match risk.aggregation.as_str() {
+            "worst" => costs[0].0,
+            "percentile" => {
+                // the lowest cost with at least percentile of the mass at or below it
+                let mut mass = 0.0;
+                for &(c, w) in costs.iter().rev() {
+                    mass += w / total_weight;
+                    if mass >= risk.percentile - 1e-9 {
+                        return c;
+                    }
+                }
+                costs[0].0
+            }
+            "cvar" => {
+                let tail_mass = (1.0 - risk.cvar_alpha).max(1e-9);
+                let mut mass = 0.0;
+                let mut sum = Cost::ZERO;
+                for &(c, w) in costs.iter() {
+                    let p = (w / total_weight).min(tail_mass - mass);
+                    if p <= 0.0 {
+                        break;
+                    }
+                    sum += c * p;
+                    mass += p;
+                }
+                sum / mass
+            }
+            "mean_std" => {
+                let mean_total = mean.total();
+                let variance = costs
+                    .iter()
+                    .map(|(c, w)| w / total_weight * (c.total() - mean_total).powi(2))
+                    .sum::<f64>();
+                if mean_total <= 0.0 {
+                    return mean;
+                }
+                // scaled up so its total is the mean's plus std_k standard deviations
+                mean * ((mean_total + risk.std_k * variance.sqrt()) / mean_total)
+            }
+            _ => panic!("Unknown risk aggregation '{}'", risk.aggregation),
+        }
+    }
 }
 
 impl Default for Cost {
@@ -122,6 +224,9 @@ impl std::ops::Mul<f64> for Cost {
             safety: self.safety * rhs,
             accel: self.accel * rhs,
             steer: self.steer * rhs,
+            jerk: self.jerk * rhs,
+            lat_accel: self.lat_accel * rhs,
+            lane_offset: self.lane_offset * rhs,
             discount: self.discount,
             discount_factor: self.discount_factor,
             weight: self.weight,
@@ -138,6 +243,9 @@ impl std::ops::Div<f64> for Cost {
             safety: self.safety / rhs,
             accel: self.accel / rhs,
             steer: self.steer / rhs,
+            jerk: self.jerk / rhs,
+            lat_accel: self.lat_accel / rhs,
+            lane_offset: self.lane_offset / rhs,
             discount: self.discount,
             discount_factor: self.discount_factor,
             weight: self.weight,
@@ -151,6 +259,9 @@ impl std::ops::DivAssign<f64> for Cost {
         self.safety /= rhs;
         self.accel /= rhs;
         self.steer /= rhs;
+        self.jerk /= rhs;
+        self.lat_accel /= rhs;
+        self.lane_offset /= rhs;
     }
 }
 
@@ -165,6 +276,9 @@ impl std::ops::Add for Cost {
             safety: a.safety + b.safety,
             accel: a.accel + b.accel,
             steer: a.steer + b.steer,
+            jerk: a.jerk + b.jerk,
+            lat_accel: a.lat_accel + b.lat_accel,
+            lane_offset: a.lane_offset + b.lane_offset,
             discount: self.discount,
             discount_factor: self.discount_factor,
             weight: 1.0,
@@ -183,6 +297,9 @@ impl std::ops::Sub for Cost {
             safety: a.safety - b.safety,
             accel: a.accel - b.accel,
             steer: a.steer - b.steer,
+            jerk: a.jerk - b.jerk,
+            lat_accel: a.lat_accel - b.lat_accel,
+            lane_offset: a.lane_offset - b.lane_offset,
             discount: self.discount,
             discount_factor: self.discount_factor,
             weight: 1.0,
@@ -195,3 +312,32 @@ impl std::ops::AddAssign for Cost {
         *self = *self + rhs;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arg_parameters::Parameters;
+
+    #[test]
+    fn test_aggregate() {
+        let mut risk = Parameters::new().unwrap().risk;
+        let costs = (1..=10)
+            .map(|i| Cost {
+                safety: i as f64,
+                ..Cost::ZERO
+            })
+            .collect::<Vec<_>>();
+        let mut aggregate = |aggregation: &str| {
+            risk.aggregation = aggregation.to_string();
+            Cost::aggregate(costs.iter().copied(), &risk).total()
+        };
+
+        assert_eq!(aggregate("mean"), 5.5);
+        assert_eq!(aggregate("worst"), 10.0);
+        // the percentile 0.9 and the mean of the worst 10%
+        assert_eq!(aggregate("percentile"), 9.0);
+        assert!((aggregate("cvar") - 10.0).abs() < 1e-9);
+        // mean plus one (population) standard deviation
+        assert!((aggregate("mean_std") - (5.5 + 8.25f64.sqrt())).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,227 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cost::Cost,
+    road::{change_range, logistic, Road},
+};
+
+// A whole cost function, loaded with cost_file file.yaml in place of the built-in one
+// from the cost parameters (see costs/default.yaml for that one written out this way).
+// Each step, every term adds weight * shape(measure) to its component of the ego's cost,
+// times dt when per_second, and discounted by discount_factor per second.
+// Measures that don't apply on a step, like the distance with no car close by, add nothing.
+//
+// discount_factor: 0.8
+// terms:
+//   - { measure: speed_deviation, component: efficiency, weight: 1.0 }
+//   - { measure: accel, component: accel, weight: 0.1, shape: square }
+//   - { measure: headway, component: safety, weight: 50.0, shape: { below: 1.0 } }
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CostSpec {
+    // defaults to the cost parameters' discount_factor
+    pub discount_factor: Option<f64>,
+    pub terms: Vec<CostTerm>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CostTerm {
+    pub measure: CostMeasure,
+    pub component: CostComponent,
+    pub weight: f64,
+    #[serde(default)]
+    pub shape: CostShape,
+    #[serde(default = "default_true")]
+    pub per_second: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CostMeasure {
+    // m/s from the ego's preferred velocity
+    SpeedDeviation,
+    // m to the closest car, within twice its length and the cost safety_margin_high
+    CarDistance,
+    // m to the closest crossing pedestrian, within the pedestrians' safety_margin
+    PedestrianDistance,
+    // 1 on the step the ego runs a red light
+    RedLight,
+    // m/s^2, m/s^3, rad/s and m/s^2, see Road::ego_accel() and the rest
+    Accel,
+    Jerk,
+    CourseRate,
+    LatAccel,
+    // m off the center of the ego's lane, while it keeps its lane, see Road::ego_lane_offset()
+    LaneOffset,
+    // 1 while the safety filter brakes, or the ego violates RSS (with rss.enabled)
+    EmergencyBraking,
+    RssViolation,
+    // s of time headway to the car ahead in the ego's lane, and time to collision with it
+    Headway,
+    Ttc,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CostComponent {
+    Efficiency,
+    Safety,
+    Accel,
+    Steer,
+    Jerk,
+    LatAccel,
+    LaneOffset,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CostShape {
+    Abs,
+    Square,
+    // how far the measure is below or above the threshold, and otherwise 0
+    Below(f64),
+    Above(f64),
+    // like the built-in safety cost, the logistic of the measure mapped from low..high
+    // onto map_low..map_high
+    Logistic {
+        low: f64,
+        high: f64,
+        map_low: f64,
+        map_high: f64,
+    },
+}
+
+impl Default for CostShape {
+    fn default() -> Self {
+        Self::Abs
+    }
+}
+
+impl CostShape {
+    fn apply(&self, x: f64) -> f64 {
+        match *self {
+            Self::Abs => x.abs(),
+            Self::Square => x.powi(2),
+            Self::Below(threshold) => (threshold - x).max(0.0),
+            Self::Above(threshold) => (x - threshold).max(0.0),
+            Self::Logistic {
+                low,
+                high,
+                map_low,
+                map_high,
+            } => logistic(change_range(x, low, high, map_low, map_high)),
+        }
+    }
+}
+
+impl CostMeasure {
+    fn measure(&self, road: &Road, dt: f64) -> Option<f64> {
+        let car = &road.cars[0];
+        let flag = |b: bool| if b { 1.0 } else { 0.0 };
+        match self {
+            Self::SpeedDeviation => Some(car.preferred_vel - car.vel),
+            Self::CarDistance => road.min_unsafe_dist(0),
+            Self::PedestrianDistance => road.min_pedestrian_dist(0),
+            Self::RedLight => Some(flag(road.ego_ran_red_light())),
+            Self::Accel => Some(road.ego_accel(dt)),
+            Self::Jerk => Some(road.ego_jerk(dt)),
+            Self::CourseRate => Some(road.ego_course_rate(dt)),
+            Self::LatAccel => Some(road.ego_lat_accel(dt)),
+            Self::LaneOffset => road.ego_lane_offset(),
+            Self::EmergencyBraking => Some(flag(road.emergency_braking)),
+            Self::RssViolation => Some(flag(road.rss_violation)),
+            Self::Headway => road
+                .dist_clear_ahead_in_lane(0, car.current_lane())
+                .filter(|_| car.vel > 0.0)
+                .map(|(dist, _)| dist.max(0.0) / car.vel),
+            Self::Ttc => road.ego_time_to_collision(),
+        }
+    }
+}
+
+impl Cost {
+    pub fn component_mut(&mut self, component: CostComponent) -> &mut f64 {
+        match component {
+            CostComponent::Efficiency => &mut self.efficiency,
+            CostComponent::Safety => &mut self.safety,
+            CostComponent::Accel => &mut self.accel,
+            CostComponent::Steer => &mut self.steer,
+            CostComponent::Jerk => &mut self.jerk,
+            CostComponent::LatAccel => &mut self.lat_accel,
+            CostComponent::LaneOffset => &mut self.lane_offset,
+        }
+    }
+}
+
+impl CostSpec {
+    pub fn load(path: &str) -> Self {
+        let contents = std::fs::read_to_string(path)
+            .unwrap_or_else(|e| panic!("Could not read cost file {}: {}", path, e));
+        serde_yaml::from_str(&contents)
+            .unwrap_or_else(|e| panic!("Could not parse cost file {}: {}", path, e))
+    }
+
+    pub fn uses(&self, measure: CostMeasure) -> bool {
+        self.terms.iter().any(|t| t.measure == measure)
+    }
+
+    pub fn add_costs(&self, road: &mut Road, dt: f64) {
+        for term in self.terms.iter() {
+            if let Some(x) = term.measure.measure(road, dt) {
+                let mut cost = term.weight * term.shape.apply(x) * road.cost.discount;
+                if term.per_second {
+                    cost *= dt;
+                }
+                *road.cost.component_mut(term.component) += cost;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rand::{prelude::StdRng, SeedableRng};
+
+    use super::*;
+    use crate::arg_parameters::Parameters;
+
+    #[test]
+    fn test_default_cost_spec() {
+        // the built-in cost function, written out, costs the same
+        let params = Parameters::new().unwrap();
+        let mut spec_params = params.clone();
+        spec_params.cost_spec = Some(CostSpec::load("costs/default.yaml"));
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut road = Road::new(Arc::new(params.clone()));
+        for _ in 0..params.n_cars {
+            road.add_random_car(&mut rng).unwrap();
+        }
+        road.init_belief();
+        road.update_cars_spatial();
+        let mut spec_road = road.clone();
+        spec_road.params = Arc::new(spec_params);
+
+        for _ in 0..200 {
+            road.update(params.physics_dt);
+            spec_road.update(params.physics_dt);
+        }
+        let (a, b) = (road.cost, spec_road.cost);
+        assert!(a.total() > 0.0);
+        for (x, y) in [
+            (a.efficiency, b.efficiency),
+            (a.safety, b.safety),
+            (a.accel, b.accel),
+            (a.steer, b.steer),
+        ] {
+            assert!((x - y).abs() < 1e-9, "{:?} vs {:?}", a, b);
+        }
+    }
+}
@@ -1,11 +1,11 @@
-use parry2d_f64::na::Point2;
+use serde::{Deserialize, Serialize};
 
 use crate::{
     road::Road,
-    side_policies::{SidePolicy, SidePolicyTrait},
+    side_policies::{SidePolicy, SidePolicyTrait, Trajectory},
 };
 
-#[derive(Clone, PartialEq, PartialOrd)]
+#[derive(PartialEq, PartialOrd, Serialize, Deserialize)]
 pub struct DelayedPolicy {
     policy_a: Box<SidePolicy>,
     policy_b: Box<SidePolicy>,
@@ -15,6 +15,29 @@ pub struct DelayedPolicy {
     has_switched: bool,
 }
 
+// clone_from copies into the existing boxes, for rollout roads recycled with the same policies
+impl Clone for DelayedPolicy {
+    fn clone(&self) -> Self {
+        Self {
+            policy_a: self.policy_a.clone(),
+            policy_b: self.policy_b.clone(),
+            delay_time: self.delay_time,
+            start_time: self.start_time,
+            time_until_switch: self.time_until_switch,
+            has_switched: self.has_switched,
+        }
+    }
+
+    fn clone_from(&mut self, source: &Self) {
+        self.policy_a.clone_from(&source.policy_a);
+        self.policy_b.clone_from(&source.policy_b);
+        self.delay_time = source.delay_time;
+        self.start_time = source.start_time;
+        self.time_until_switch = source.time_until_switch;
+        self.has_switched = source.has_switched;
+    }
+}
+
 impl std::fmt::Debug for DelayedPolicy {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         writeln!(f)?;
@@ -62,7 +85,7 @@ impl SidePolicyTrait for DelayedPolicy {
         }
     }
 
-    fn choose_trajectory(&mut self, road: &Road, car_i: usize, traj: &mut Vec<Point2<f64>>) {
+    fn choose_trajectory(&mut self, road: &Road, car_i: usize, traj: &mut Trajectory) {
         if self.has_switched {
             self.policy_b.choose_trajectory(road, car_i, traj)
         } else {
@@ -115,4 +138,48 @@ impl SidePolicyTrait for DelayedPolicy {
             self.policy_a.operating_policy()
         }
     }
+
+    fn is_settled(&self) -> bool {
+        self.has_switched && self.policy_b.is_settled()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{prelude::StdRng, SeedableRng};
+
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{arg_parameters::Parameters, mpdm::make_policy_choices};
+
+    #[test]
+    fn test_settled_acts_as_operating_policy() {
+        let mut params = Parameters::new().unwrap();
+        params.n_cars = 10;
+        let choices = make_policy_choices(&params);
+        let mut road = Road::new(Arc::new(params.clone()));
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..params.n_cars {
+            road.add_random_car(&mut rng).unwrap();
+        }
+        let mut road = road.sim_estimate();
+        road.set_ego_policy(&SidePolicy::DelayedPolicy(DelayedPolicy::new(
+            choices[0].clone(),
+            choices[2].clone(),
+            1.0,
+        )));
+
+        assert!(!road.ego_policy().is_settled());
+        road.take_update_steps(1.5, params.physics_dt);
+        assert!(road.ego_policy().is_settled());
+
+        // the rest of the rollout is the same either way
+        let mut operating_road = road.clone();
+        operating_road.set_ego_policy(&road.ego_policy().operating_policy());
+        road.take_update_steps(4.0, params.physics_dt);
+        operating_road.take_update_steps(4.0, params.physics_dt);
+        assert_eq!(road.cost, operating_road.cost);
+        assert_eq!(road.cars[0].x(), operating_road.cars[0].x());
+    }
 }
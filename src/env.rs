@@ -0,0 +1,182 @@
+use std::{rc::Rc, sync::Arc};
+
+use crate::{
+    arg_parameters::Parameters, cost::Cost, headless_params, mpdm::make_policy_choices, new_state,
+    road::Road, side_policies::SidePolicy, sim_error::SimError, State,
+};
+
+// numbers in the observation for the ego, and then for each other car
+pub const EGO_FEATURES: usize = 4;
+pub const CAR_FEATURES: usize = 5;
+
+// What one step of the environment gives back
+pub struct Step {
+    pub observation: Vec<f64>,
+    // the ego's cost over just this step, by component
+    pub cost: Cost,
+    pub done: bool,
+}
+
+// A Gym-style environment over the simulator, for training learned baselines against it.
+// Each step drives the ego with one of the policies the planners choose between
+// (see mpdm::make_policy_choices) until the next replan, just as a planner's choice would.
+pub struct Env {
+    params: Parameters,
+    state: State,
+    policy_choices: Rc<[SidePolicy]>,
+}
+
+impl Env {
+    // params are as for a run, except that the actions take the place of the method
+    pub fn new(mut params: Parameters) -> Result<Self, SimError> {
+        params.method = "fixed".to_owned();
+        let params = headless_params(params);
+        let policy_choices = make_policy_choices(&params);
+        let state = new_state(Arc::new(params.clone()))?;
+        Ok(Self {
+            params,
+            state,
+            policy_choices,
+        })
+    }
+
+    pub fn n_actions(&self) -> usize {
+        self.policy_choices.len()
+    }
+
+    pub fn observation_len(&self) -> usize {
+        EGO_FEATURES + self.params.n_cars * CAR_FEATURES
+    }
+
+    pub fn params(&self) -> &Parameters {
+        &self.params
+    }
+
+    // starts over on the traffic of the seed, the same as a run with that rng_seed
+    pub fn reset(&mut self, rng_seed: u64) -> Result<Vec<f64>, SimError> {
+        self.params.rng_seed = rng_seed;
+        self.state = new_state(Arc::new(self.params.clone()))?;
+        Ok(self.observation())
+    }
+
+    pub fn step(&mut self, action: usize) -> Result<Step, SimError> {
+        let policy = self
+            .policy_choices
+            .get(action)
+            .unwrap_or_else(|| panic!("action {} is not below {}", action, self.n_actions()));
+        self.state.road.set_ego_policy(policy);
+
+        let cost_before = self.state.road.cost;
+        let physics_dt = self.params.physics_dt;
+        for _ in 0..self.params.replan_steps() {
+            if self.done() {
+                break;
+            }
+            self.state.update(physics_dt)?;
+        }
+        Ok(Step {
+            observation: self.observation(),
+            cost: self.state.road.cost - cost_before,
+            done: self.done(),
+        })
+    }
+
+    pub fn done(&self) -> bool {
+        self.state.road.cars[0].crashed || self.state.timesteps >= self.params.max_steps
+    }
+
+    pub fn road(&self) -> &Road {
+        &self.state.road
+    }
+
+    // The ego's velocity, y, heading and lane, and then for the n_cars closest other cars
+    // (by distance along the road, nearest first): 1.0 for being there, distance ahead, y,
+    // velocity and heading. It's all zeros for missing cars.
+    pub fn observation(&self) -> Vec<f64> {
+        let road = &self.state.road;
+        let ego = &road.cars[0];
+        let mut observation = vec![ego.vel, ego.y(), ego.theta(), ego.current_lane() as f64];
+
+        let mut others = road.cars[1..].iter().collect::<Vec<_>>();
+        others.sort_by(|a, b| {
+            let a_dist = (a.x() - ego.x()).abs();
+            let b_dist = (b.x() - ego.x()).abs();
+            a_dist.partial_cmp(&b_dist).unwrap()
+        });
+        for car in others.iter().take(self.params.n_cars) {
+            observation.extend_from_slice(&[1.0, car.x() - ego.x(), car.y(), car.vel, car.theta()]);
+        }
+        observation.resize(self.observation_len(), 0.0);
+        observation
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_env_steps() {
+        let mut params = Parameters::new().unwrap();
+        params.max_steps = 50;
+        let mut env = Env::new(params).unwrap();
+        let observation = env.reset(3).unwrap();
+        assert_eq!(observation.len(), env.observation_len());
+
+        let mut n_steps = 0;
+        let mut total = 0.0;
+        loop {
+            let step = env.step(env.n_actions() - 1).unwrap();
+            assert_eq!(step.observation.len(), env.observation_len());
+            total += step.cost.total();
+            n_steps += 1;
+            if step.done {
+                break;
+            }
+        }
+        // replan_dt of 0.25 s is 25 physics steps
+        assert_eq!(env.road().timesteps, 50);
+        assert_eq!(n_steps, 2);
+        assert!((total - env.road().cost.total()).abs() < 1e-9);
+
+        // the same seed gives the same traffic again
+        assert_eq!(env.reset(3).unwrap(), observation);
+    }
+
+    #[test]
+    fn test_replan_interval() {
+        let mut params = Parameters::new().unwrap();
+        params.max_steps = 50;
+        params.replan_interval = 10;
+        let mut env = Env::new(params.clone()).unwrap();
+        env.reset(3).unwrap();
+        env.step(0).unwrap();
+        assert_eq!(env.road().timesteps, 10);
+
+        // the planner runs every replan_interval steps, whatever replan_dt says
+        let mut state = new_state(Arc::new(headless_params(params.clone()))).unwrap();
+        for _ in 0..params.max_steps {
+            state.update(params.physics_dt).unwrap();
+        }
+        assert_eq!(state.reward.planning_times.len(), 5);
+        state.reward.end_t = state.road.t;
+        state.reward.calculate_timestep_metrics();
+        let total = state.reward.planning_times.iter().sum::<f64>();
+        assert_eq!(state.reward.planning_load, Some(total / state.road.t));
+    }
+
+    #[test]
+    fn test_env_setup_errors() {
+        let mut params = Parameters::new().unwrap();
+        params.scenario_file = "scenarios/missing.yaml".to_owned();
+        assert!(matches!(Env::new(params), Err(SimError::Load { .. })));
+
+        // far more cars than fit on the road
+        let mut params = Parameters::new().unwrap();
+        params.n_cars = 10000;
+        assert!(matches!(
+            Env::new(params),
+            Err(SimError::CarPlacement { tries: 100, .. })
+        ));
+    }
+}
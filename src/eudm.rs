@@ -2,15 +2,42 @@ use rand::prelude::StdRng;
 
 use crate::{
     arg_parameters::Parameters,
+    candidates::{choose_candidate, record_candidate},
+    cfb::{branch_at_layer, conditional_focused_branching},
+    contingency_policy::{classify_outcome, lead_car, ContingencyPolicy, Outcome},
     cost::Cost,
     delayed_policy::DelayedPolicy,
     mpdm::make_policy_choices,
+    planner::EgoPlanner,
     road::Road,
+    road_arena::map_rollouts,
     road_set::RoadSet,
     road_set_for_scenario,
     side_policies::{SidePolicy, SidePolicyTrait},
 };
 
+// With eudm.cfb_k, the rollouts branch on the critical cars' most probable policies, from the
+// root and at each layer boundary, instead of following sampled roads
+fn cfb_branching(params: &Parameters) -> bool {
+    params.eudm.cfb_k > 0 && params.eudm.prediction == "belief"
+}
+
+// One layer of the rollouts, adding its traces, and then branching for the layers after it
+fn take_layer(
+    params: &Parameters,
+    roads: &mut RoadSet,
+    depth_level: u32,
+    traces: &mut Vec<rvx::Shape>,
+) {
+    let eudm = &params.eudm;
+    roads.take_update_steps(eudm.layer_t, eudm.dt);
+    traces.append(&mut roads.make_traces(depth_level, false));
+    if cfb_branching(params) && depth_level + 1 < eudm.search_depth {
+        let layer_roads = std::mem::replace(roads, RoadSet::new(Vec::new()));
+        *roads = branch_at_layer(params, layer_roads, eudm.cfb_k, &eudm.cfb_criticality);
+    }
+}
+
 fn dcp_tree_search(
     params: &Parameters,
     policy_choices: &[SidePolicy],
@@ -24,7 +51,7 @@ fn dcp_tree_search(
     let eudm = &params.eudm;
 
     if debug {
-        eprintln!(
+        tracing::debug!(
             "{}: EUDM DCP-Tree search policies and costs, starting with policy {}",
             roads.timesteps(),
             unchanged_policy.policy_id(),
@@ -37,36 +64,42 @@ fn dcp_tree_search(
     let mut best_switch_depth = 0;
     let mut best_cost = Cost::max_value();
 
+    // Once the ongoing policy has settled into its operating policy, keeping it is the same
+    // rollout as never switching from the operating policy below, so that one stands in for it
+    let ongoing_is_operating = unchanged_policy.is_settled();
+
     // Let's first consider the ongoing policy, which may be mid-way through a transition
     // unlike everything else we will consider, which won't transition policies for at least some period
-    {
-        let mut ongoing_roads = roads.clone();
+    if !ongoing_is_operating {
+        let mut ongoing_roads = roads.arena_clone();
         for depth_level in 0..eudm.search_depth {
             if depth_level < max_car_traces_depth {
                 ongoing_roads.reset_car_traces();
             } else {
                 ongoing_roads.disable_car_traces();
             }
-            ongoing_roads.take_update_steps(eudm.layer_t, eudm.dt);
-            traces.append(&mut ongoing_roads.make_traces(depth_level, false));
+            take_layer(params, &mut ongoing_roads, depth_level, &mut traces);
         }
         let cost = ongoing_roads.cost();
         if debug {
             let unchanged_policy_id = unchanged_policy.policy_id();
-            eprintln_f!(
+            debug_f!(
                 "Unchanged: {unchanged_policy_id}: {cost:7.2?} = {:7.2}, {unchanged_policy:?}",
                 cost.total()
             );
         }
+        // as a candidate, keeping the policy is its operating policy
+        record_candidate(operating_policy.policy_id(), cost.total());
         if cost < best_cost {
             best_cost = cost;
             best_sub_policy = None;
         }
+        ongoing_roads.recycle();
     }
 
     // this copy of the roads will be advanced by layer_t each time through the loop
     // to avoid doing duplicate work.
-    let mut init_policy_roads = roads.clone();
+    let mut init_policy_roads = roads.arena_clone();
     init_policy_roads.set_ego_policy(&operating_policy);
 
     let start_depth = if eudm.allow_different_root_policy {
@@ -83,13 +116,17 @@ fn dcp_tree_search(
         }
 
         if switch_depth > 0 {
-            init_policy_roads.take_update_steps(eudm.layer_t, eudm.dt);
-            traces.append(&mut init_policy_roads.make_traces(switch_depth - 1, false));
+            take_layer(
+                params,
+                &mut init_policy_roads,
+                switch_depth - 1,
+                &mut traces,
+            );
         }
 
         if switch_depth == eudm.search_depth {
             if debug {
-                eprintln_f!(
+                debug_f!(
                     "switch time: {}, {operating_policy:?}: {:7.2?} = {:7.2}",
                     switch_depth as f64 * eudm.layer_t,
                     init_policy_roads.cost(),
@@ -98,39 +135,56 @@ fn dcp_tree_search(
             }
 
             let cost = init_policy_roads.cost();
-            if cost < best_cost {
+            record_candidate(operating_policy.policy_id(), cost.total());
+            if ongoing_is_operating {
+                // as the first one considered, it would have won the ties
+                if cost <= best_cost {
+                    best_cost = cost;
+                    best_sub_policy = None;
+                }
+            } else if cost < best_cost {
                 best_cost = cost;
                 best_switch_depth = switch_depth;
                 best_sub_policy = Some(&operating_policy);
             }
         } else {
-            for (i, sub_policy) in policy_choices.iter().enumerate() {
-                let mut roads = init_policy_roads.clone();
-                if sub_policy.policy_id() == operating_policy.policy_id() {
-                    continue;
-                }
+            let sub_policies = policy_choices
+                .iter()
+                .enumerate()
+                .filter(|(_, p)| p.policy_id() != operating_policy.policy_id())
+                .collect::<Vec<_>>();
+            let evaluations = map_rollouts(params, &sub_policies, |(_, sub_policy)| {
+                let mut roads = init_policy_roads.arena_clone();
                 roads.set_ego_policy_not_switched(sub_policy);
 
+                let mut traces = Vec::new();
                 for depth_level in switch_depth..eudm.search_depth {
                     if depth_level < max_car_traces_depth {
                         roads.reset_car_traces();
                     } else {
                         roads.disable_car_traces();
                     }
-                    roads.take_update_steps(eudm.layer_t, eudm.dt);
-                    traces.append(&mut roads.make_traces(depth_level, false));
+                    take_layer(params, &mut roads, depth_level, &mut traces);
                 }
+                let cost = roads.cost();
+                roads.recycle();
+                (cost, traces)
+            });
 
+            for ((i, sub_policy), (cost, mut new_traces)) in
+                sub_policies.into_iter().zip(evaluations)
+            {
+                traces.append(&mut new_traces);
                 if debug {
-                    eprintln_f!(
+                    debug_f!(
                         "switch time: {}, to {i}: {sub_policy:?}: {:7.2?} = {:7.2}",
                         switch_depth as f64 * eudm.layer_t,
-                        roads.cost(),
-                        roads.cost().total()
+                        cost,
+                        cost.total()
                     );
                 }
+                record_candidate(sub_policy.policy_id(), cost.total());
 
-                let cost = roads.cost();
                 if cost < best_cost {
                     best_cost = cost;
                     best_switch_depth = switch_depth;
@@ -140,10 +194,13 @@ fn dcp_tree_search(
         }
     }
 
+    init_policy_roads.recycle();
+    choose_candidate(best_sub_policy.unwrap_or(&operating_policy).policy_id());
+
     // will be Some if we should switch policies after one layer, and None to stay the same
     if let Some(best_sub_policy) = best_sub_policy {
         if debug {
-            eprintln_f!(
+            debug_f!(
                 "Choose policy with best_cost {:.2}, {best_switch_depth=}, and {best_sub_policy:?}",
                 best_cost.total()
             );
@@ -158,21 +215,168 @@ fn dcp_tree_search(
         )
     } else {
         if debug {
-            eprintln_f!("Choose to keep unchanged policy with {best_cost=:.2}");
+            debug_f!("Choose to keep unchanged policy with {best_cost=:.2}");
         }
         (None, traces)
     }
 }
 
+// Contingency planning: each policy for the first layer is followed by the best sub-policy for
+// each outcome the sampled roads have come to by then, like keeping up speed if the lead car
+// keeps going but braking if it brakes. The plan's cost is over all the roads together, each
+// with its own outcome's sub-policy, so a first layer that leaves good options for each wins.
+// The contingency plan that starts with policy_a: the best sub-policy for each outcome,
+// and the cost over all the roads, along with their traces
+fn contingency_plan(
+    params: &Parameters,
+    policy_choices: &[SidePolicy],
+    roads: &RoadSet,
+    policy_a: &SidePolicy,
+    lead_car_i: Option<usize>,
+    debug: bool,
+) -> (Cost, Vec<(Outcome, SidePolicy)>, Vec<rvx::Shape>) {
+    let mut traces = Vec::new();
+    let eudm = &params.eudm;
+    let max_car_traces_depth = 3;
+
+    let mut first_roads = roads.arena_clone();
+    first_roads.set_ego_policy(policy_a);
+    first_roads.reset_car_traces();
+    take_layer(params, &mut first_roads, 0, &mut traces);
+
+    let mut branches = Vec::new();
+    let mut branch_roads = Vec::new();
+    for (outcome, outcome_roads) in first_roads.partition(|road| classify_outcome(road, lead_car_i))
+    {
+        let mut best_branch: Option<(Cost, &SidePolicy, RoadSet)> = None;
+        for sub_policy in policy_choices.iter() {
+            let mut roads = outcome_roads.arena_clone();
+            if sub_policy.policy_id() != policy_a.policy_id() {
+                roads.set_ego_policy_not_switched(sub_policy);
+            }
+            for depth_level in 1..eudm.search_depth {
+                if depth_level < max_car_traces_depth {
+                    roads.reset_car_traces();
+                } else {
+                    roads.disable_car_traces();
+                }
+                take_layer(params, &mut roads, depth_level, &mut traces);
+            }
+
+            let cost = roads.cost();
+            if debug {
+                debug_f!(
+                    "{policy_a:?}, if {outcome:?}, then {sub_policy:?}: {cost:7.2?} = {:7.2}",
+                    cost.total()
+                );
+            }
+            if best_branch.as_ref().map_or(true, |(c, _, _)| cost < *c) {
+                if let Some((_, _, roads)) = best_branch.take() {
+                    roads.recycle();
+                }
+                best_branch = Some((cost, sub_policy, roads));
+            } else {
+                roads.recycle();
+            }
+        }
+        outcome_roads.recycle();
+
+        let (_, sub_policy, roads) = best_branch.unwrap();
+        branches.push((outcome, sub_policy.clone()));
+        branch_roads.push(roads);
+    }
+
+    let all_roads = RoadSet::merge(branch_roads);
+    let cost = all_roads.cost();
+    all_roads.recycle();
+    (cost, branches, traces)
+}
+
+fn contingency_tree_search(
+    params: &Parameters,
+    policy_choices: &[SidePolicy],
+    roads: RoadSet,
+    lead_car_i: Option<usize>,
+    debug: bool,
+) -> (Option<SidePolicy>, Vec<rvx::Shape>) {
+    let mut traces = Vec::new();
+    let eudm = &params.eudm;
+
+    if debug {
+        tracing::debug!(
+            "{}: EUDM contingency search policies and costs, with lead car {:?}",
+            roads.timesteps(),
+            lead_car_i,
+        );
+    }
+
+    let mut best_cost = Cost::max_value();
+    let mut best_policy = None;
+    let mut best_policy_id = 0;
+
+    let plans = map_rollouts(params, policy_choices, |policy_a| {
+        contingency_plan(params, policy_choices, &roads, policy_a, lead_car_i, debug)
+    });
+    for (policy_a, (cost, branches, mut new_traces)) in policy_choices.iter().zip(plans) {
+        traces.append(&mut new_traces);
+        if debug {
+            debug_f!(
+                "first {policy_a:?} with {branches:?}: {cost:7.2?} = {:7.2}",
+                cost.total()
+            );
+        }
+        record_candidate(policy_a.policy_id(), cost.total());
+        if cost < best_cost {
+            best_cost = cost;
+            best_policy_id = policy_a.policy_id();
+            best_policy = Some(ContingencyPolicy::new(
+                policy_a.clone(),
+                branches,
+                lead_car_i,
+                eudm.layer_t,
+            ));
+        }
+    }
+    if best_policy.is_some() {
+        choose_candidate(best_policy_id);
+    }
+
+    if debug {
+        debug_f!(
+            "Choose contingency plan with best_cost {:.2}: {best_policy:?}",
+            best_cost.total()
+        );
+    }
+    (best_policy.map(SidePolicy::ContingencyPolicy), traces)
+}
+
 pub fn dcp_tree_choose_policy(
     params: &Parameters,
     true_road: &Road,
     rng: &mut StdRng,
 ) -> (Option<SidePolicy>, Vec<rvx::Shape>) {
-    let roads = road_set_for_scenario(params, true_road, rng, params.eudm.samples_n);
+    let eudm = &params.eudm;
+    let roads = if cfb_branching(params) {
+        conditional_focused_branching(params, true_road, eudm.cfb_k, &eudm.cfb_criticality).0
+    } else {
+        road_set_for_scenario(params, true_road, rng, eudm.samples_n, &eudm.prediction)
+    };
     let debug = params.policy_report_debug
         && true_road.debug
         && true_road.timesteps + params.debug_steps_before >= params.max_steps as usize;
     let policy_choices = make_policy_choices(params);
-    dcp_tree_search(params, &policy_choices, roads, debug)
+    if params.eudm.contingency {
+        contingency_tree_search(params, &policy_choices, roads, lead_car(true_road), debug)
+    } else {
+        dcp_tree_search(params, &policy_choices, roads, debug)
+    }
+}
+
+// EUDM, as the method eudm
+pub struct EudmPlanner;
+
+impl EgoPlanner for EudmPlanner {
+    fn plan(&mut self, road: &Road, rng: &mut StdRng) -> (Option<SidePolicy>, Vec<rvx::Shape>) {
+        dcp_tree_choose_policy(&road.params, road, rng)
+    }
 }
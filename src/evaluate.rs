@@ -0,0 +1,208 @@
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use serde::Serialize;
+
+use crate::{
+    arg_parameters::Parameters, cost::Cost, headless_params, reward::Reward, run_with_parameters,
+    sim_error::SimError,
+};
+
+// two-sided 95% quantiles of Student's t, by degrees of freedom from 1,
+// after which the normal's is close enough
+const T95: [f64; 30] = [
+    12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228, 2.201, 2.179, 2.160,
+    2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086, 2.080, 2.074, 2.069, 2.064, 2.060, 2.056,
+    2.052, 2.048, 2.045, 2.042,
+];
+
+// A metric over the episodes: its mean, sample standard deviation,
+// and the 95% confidence interval of the mean (unbounded with a single episode)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct MetricSummary {
+    pub mean: f64,
+    pub std_dev: f64,
+    pub ci_low: f64,
+    pub ci_high: f64,
+}
+
+impl MetricSummary {
+    pub fn new(values: &[f64]) -> Self {
+        let n = values.len();
+        if n == 0 {
+            return Self::default();
+        }
+        let mean = values.iter().sum::<f64>() / n as f64;
+        if n == 1 {
+            return Self {
+                mean,
+                std_dev: 0.0,
+                ci_low: f64::NEG_INFINITY,
+                ci_high: f64::INFINITY,
+            };
+        }
+        let std_dev =
+            (values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1) as f64).sqrt();
+        let t = T95.get(n - 2).copied().unwrap_or(1.96);
+        let half_width = t * std_dev / (n as f64).sqrt();
+        Self {
+            mean,
+            std_dev,
+            ci_low: mean - half_width,
+            ci_high: mean + half_width,
+        }
+    }
+}
+
+// The results of a planner configuration over its episodes, one for each seed from
+// params.rng_seed on. The metrics are over the episodes that ran, and those that couldn't
+// be set up are in errors instead.
+#[derive(Clone, Debug, Serialize)]
+pub struct Evaluation {
+    pub scenario_name: String,
+    pub n_episodes: usize,
+    // the ego's final cost, as a whole and by component
+    pub cost: MetricSummary,
+    pub efficiency: MetricSummary,
+    pub safety: MetricSummary,
+    pub accel: MetricSummary,
+    pub steer: MetricSummary,
+    // the fraction of episodes where the ego crashed
+    pub crashed: MetricSummary,
+    pub avg_vel: MetricSummary,
+    pub dist_travelled: MetricSummary,
+    pub mean_planning_time: MetricSummary,
+    pub policy_switches: MetricSummary,
+    pub rollouts: MetricSummary,
+    // the fraction of the obstacle cars' steps the belief had them in the wrong lane
+    pub belief_lane_error: MetricSummary,
+    // the RMS of the ego's offset from its lane's center while it kept its lane
+    pub lane_offset_rms: MetricSummary,
+    pub errors: Vec<(u64, String)>,
+}
+
+// an episode's seed and how its run went
+type EpisodeResult = (u64, Result<(Cost, Reward), SimError>);
+
+impl Evaluation {
+    fn new(params: &Parameters, results: Vec<EpisodeResult>) -> Self {
+        let mut errors = Vec::new();
+        let mut episodes = Vec::new();
+        for (seed, result) in results {
+            match result {
+                Ok(episode) => episodes.push(episode),
+                Err(e) => errors.push((seed, e.to_string())),
+            }
+        }
+        let summary = |metric: fn(&Cost, &Reward) -> f64| {
+            let values = episodes
+                .iter()
+                .map(|(cost, reward)| metric(&cost.normalize(), reward))
+                .collect::<Vec<_>>();
+            MetricSummary::new(&values)
+        };
+        Self {
+            scenario_name: params.scenario_name.clone().unwrap_or_default(),
+            n_episodes: episodes.len(),
+            cost: summary(|c, _| c.total()),
+            efficiency: summary(|c, _| c.efficiency),
+            safety: summary(|c, _| c.safety),
+            accel: summary(|c, _| c.accel),
+            steer: summary(|c, _| c.steer),
+            crashed: summary(|_, r| r.crashed as u8 as f64),
+            avg_vel: summary(|_, r| r.avg_vel),
+            dist_travelled: summary(|_, r| r.dist_travelled),
+            mean_planning_time: summary(|_, r| r.mean_planning_time.unwrap_or(0.0)),
+            policy_switches: summary(|_, r| r.policy_switches as f64),
+            rollouts: summary(|_, r| r.rollouts as f64),
+            belief_lane_error: summary(|_, r| r.belief_lane_error()),
+            lane_offset_rms: summary(|_, r| r.lane_offset_rms()),
+            errors,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    pub fn columns(&self) -> [(&'static str, &MetricSummary); 8] {
+        [
+            ("cost", &self.cost),
+            ("safety", &self.safety),
+            ("crashed", &self.crashed),
+            ("avg_vel", &self.avg_vel),
+            ("plan_t", &self.mean_planning_time),
+            ("switches", &self.policy_switches),
+            ("lane_err", &self.belief_lane_error),
+            ("lane_rms", &self.lane_offset_rms),
+        ]
+    }
+}
+
+// Runs each of the configurations over n_episodes seeds, all in parallel, without the sweep's
+// results cache, for quick comparisons
+pub fn evaluate_all(configs: &[Parameters], n_episodes: usize) -> Vec<Evaluation> {
+    let runs = configs
+        .iter()
+        .enumerate()
+        .flat_map(|(config_i, params)| {
+            (0..n_episodes as u64).map(move |i| {
+                let mut episode = headless_params(params.clone());
+                episode.rng_seed = params.rng_seed + i;
+                (config_i, episode)
+            })
+        })
+        .collect::<Vec<_>>();
+    let results = runs
+        .into_par_iter()
+        .map(|(config_i, episode)| (config_i, episode.rng_seed, run_with_parameters(episode)))
+        .collect::<Vec<_>>();
+
+    let mut by_config = configs.iter().map(|_| Vec::new()).collect::<Vec<_>>();
+    for (config_i, seed, result) in results {
+        by_config[config_i].push((seed, result));
+    }
+    configs
+        .iter()
+        .zip(by_config)
+        .map(|(params, results)| Evaluation::new(params, results))
+        .collect()
+}
+
+/// Runs the planner configuration of `params` over `n_episodes` episodes in parallel,
+/// seeded from `params.rng_seed` on, and summarizes each metric with its mean, standard
+/// deviation and 95% confidence interval
+pub fn evaluate(params: &Parameters, n_episodes: usize) -> Evaluation {
+    evaluate_all(std::slice::from_ref(params), n_episodes)
+        .pop()
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metric_summary() {
+        let summary = MetricSummary::new(&[1.0, 2.0, 3.0, 4.0]);
+        assert_eq!(summary.mean, 2.5);
+        approx::assert_abs_diff_eq!(summary.std_dev, 1.291, epsilon = 1e-3);
+        // t with 3 degrees of freedom
+        approx::assert_abs_diff_eq!(summary.ci_high - 2.5, 3.182 * 1.291 / 2.0, epsilon = 1e-3);
+        approx::assert_abs_diff_eq!(summary.ci_high - 2.5, 2.5 - summary.ci_low, epsilon = 1e-9);
+        assert!(MetricSummary::new(&[1.0]).ci_high.is_infinite());
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let mut params = Parameters::new().unwrap();
+        params.method = "fixed".to_owned();
+        params.max_steps = 20;
+        params.scenario_name = Some("test".to_owned());
+        let evaluation = evaluate(&params, 3);
+        assert_eq!(evaluation.n_episodes, 3);
+        assert!(evaluation.errors.is_empty());
+        assert!(evaluation.avg_vel.mean > 0.0);
+        assert!(evaluation.avg_vel.ci_low <= evaluation.avg_vel.mean);
+        let json: serde_json::Value = serde_json::from_str(&evaluation.to_json()).unwrap();
+        assert_eq!(json["n_episodes"], 3);
+    }
+}
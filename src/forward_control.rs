@@ -1,12 +1,39 @@
+use serde::{Deserialize, Serialize};
+
+use crate::idm_control::IdmControl;
 use crate::intelligent_driver::IntelligentDriverPolicy;
 use crate::open_loop_policy::OpenLoopForwardControl;
+use crate::scripted_events::ScriptedBraking;
 use crate::Road;
 
 #[enum_dispatch]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ForwardControl {
     IntelligentDriverPolicy,
+    IdmControl,
     OpenLoopForwardControl,
+    ScriptedBraking,
+}
+
+// by the names used for ego_forward_control and obstacle_forward_control
+pub fn make_forward_control(name: &str) -> ForwardControl {
+    match name {
+        "intelligent_driver" => {
+            ForwardControl::IntelligentDriverPolicy(IntelligentDriverPolicy::new())
+        }
+        "idm" => ForwardControl::IdmControl(IdmControl::new()),
+        _ => panic!(
+            "Unknown forward control '{}', expected intelligent_driver or idm",
+            name
+        ),
+    }
+}
+
+// what a car holds while its own control is out choosing its accel
+impl Default for ForwardControl {
+    fn default() -> Self {
+        Self::OpenLoopForwardControl(OpenLoopForwardControl)
+    }
 }
 
 #[enum_dispatch(ForwardControl)]
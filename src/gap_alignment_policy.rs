@@ -0,0 +1,271 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    lane_change_policy::{LaneChangePolicy, LongitudinalPolicy},
+    road::Road,
+    side_policies::{SidePolicy, SidePolicyTrait, Trajectory},
+};
+
+// how much faster than the gap the car drives per m it is behind the gap's center, in 1/s
+const ALIGN_GAIN: f64 = 0.5;
+// how close to the gap's center the car has to be before it changes lanes
+const ALIGN_DX: f64 = 2.0;
+// room, in car lengths, to leave from the one car bounding a gap that's open on the other side
+const OPEN_GAP_MARGIN: f64 = 2.0;
+
+// Lines up with a gap between the cars in the target lane before changing into it:
+// the car keeps to its lane at the speed that closes on the gap's center,
+// and then changes lanes once it's beside the gap and the target lane is clear there.
+// Gap 0 is the one beside the car when it starts, 1 the next one ahead of that, -1 the next
+// one behind.
+// In the target lane already, it just maintains.
+#[derive(Clone, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub struct GapAlignmentPolicy {
+    target_lane_i: i32,
+    gap: i32,
+    lane_change: LaneChangePolicy,
+    // the cars behind and ahead of the gap, once chosen, so it stays the same gap as the car moves
+    gap_cars: Option<(Option<usize>, Option<usize>)>,
+    committed: bool,
+}
+
+impl std::fmt::Debug for GapAlignmentPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "gap {:+} in lane {}, committed {}",
+            self.gap, self.target_lane_i, self.committed
+        )
+    }
+}
+
+// the closest car to from_i's x in lane_i, ahead of it or behind it, besides car_i
+fn next_in_lane(
+    road: &Road,
+    car_i: usize,
+    from_i: usize,
+    lane_i: i32,
+    ahead: bool,
+) -> Option<usize> {
+    let x = road.cars[from_i].x();
+    let mut closest: Option<(f64, usize)> = None;
+    for (i, c) in road.cars.iter().enumerate() {
+        if i == car_i || i == from_i || c.current_lane() != lane_i {
+            continue;
+        }
+        let dist = if ahead { c.x() - x } else { x - c.x() };
+        // a car right alongside counts as ahead
+        if dist < 0.0 || (dist == 0.0 && !ahead) {
+            continue;
+        }
+        if closest.map_or(true, |(d, _)| dist < d) {
+            closest = Some((dist, i));
+        }
+    }
+    closest.map(|(_, i)| i)
+}
+
+impl GapAlignmentPolicy {
+    pub fn new(policy_id: u32, target_lane_i: i32, gap: i32, transition_time: f64) -> Self {
+        Self {
+            target_lane_i,
+            gap,
+            lane_change: LaneChangePolicy::new(
+                policy_id,
+                Some(target_lane_i),
+                transition_time,
+                true,
+                LongitudinalPolicy::Maintain,
+            ),
+            gap_cars: None,
+            committed: false,
+        }
+    }
+
+    pub fn target_lane_i(&self) -> i32 {
+        self.target_lane_i
+    }
+
+    pub fn gap(&self) -> i32 {
+        self.gap
+    }
+
+    pub fn committed(&self) -> bool {
+        self.committed
+    }
+
+    // the cars behind and ahead of the chosen gap, stepping out gap by gap from the car,
+    // and stopping early at the last one that's open
+    fn find_gap_cars(
+        &self,
+        road: &Road,
+        car_i: usize,
+        lane_i: i32,
+    ) -> (Option<usize>, Option<usize>) {
+        let mut behind = next_in_lane(road, car_i, car_i, lane_i, false);
+        let mut ahead = next_in_lane(road, car_i, car_i, lane_i, true);
+        for _ in 0..self.gap {
+            if let Some(a) = ahead {
+                behind = Some(a);
+                ahead = next_in_lane(road, car_i, a, lane_i, true);
+            }
+        }
+        for _ in self.gap..0 {
+            if let Some(b) = behind {
+                ahead = Some(b);
+                behind = next_in_lane(road, car_i, b, lane_i, false);
+            }
+        }
+        (behind, ahead)
+    }
+
+    // where the gap's center is, and how fast it moves
+    fn gap_target(&mut self, road: &Road, car_i: usize, lane_i: i32) -> (f64, f64) {
+        let gap_cars = match self.gap_cars {
+            Some(gap_cars) => gap_cars,
+            None => *self
+                .gap_cars
+                .insert(self.find_gap_cars(road, car_i, lane_i)),
+        };
+        let car = &road.cars[car_i];
+        let margin = OPEN_GAP_MARGIN * car.length + 0.5 * car.length;
+        match gap_cars {
+            (Some(b), Some(a)) => {
+                let (b, a) = (&road.cars[b], &road.cars[a]);
+                let low = b.x() + 0.5 * b.length;
+                let high = a.x() - 0.5 * a.length;
+                (0.5 * (low + high), 0.5 * (b.vel + a.vel))
+            }
+            (Some(b), None) => {
+                let b = &road.cars[b];
+                (car.x().max(b.x() + 0.5 * b.length + margin), b.vel)
+            }
+            (None, Some(a)) => {
+                let a = &road.cars[a];
+                (car.x().min(a.x() - 0.5 * a.length - margin), a.vel)
+            }
+            (None, None) => (car.x(), car.vel),
+        }
+    }
+
+    fn update_committed(&mut self, road: &Road, car_i: usize) {
+        if self.committed {
+            return;
+        }
+        let car = &road.cars[car_i];
+        let lane_i = road.usable_lane(car_i, self.target_lane_i);
+        // already on the way over, as when replanning partway through the lane change
+        if car.current_lane() == lane_i || car.target_lane_i == lane_i {
+            self.committed = true;
+            return;
+        }
+        let (center_x, _) = self.gap_target(road, car_i, lane_i);
+        self.committed = (center_x - car.x()).abs() < ALIGN_DX;
+    }
+}
+
+impl SidePolicyTrait for GapAlignmentPolicy {
+    fn choose_target_lane(&mut self, road: &Road, car_i: usize) -> i32 {
+        self.update_committed(road, car_i);
+        if self.committed {
+            self.lane_change.choose_target_lane(road, car_i)
+        } else {
+            road.cars[car_i].current_lane()
+        }
+    }
+
+    fn choose_follow_time(&mut self, road: &Road, car_i: usize) -> f64 {
+        self.lane_change.choose_follow_time(road, car_i)
+    }
+
+    fn choose_vel(&mut self, road: &Road, car_i: usize) -> f64 {
+        self.update_committed(road, car_i);
+        if self.committed {
+            return self.lane_change.choose_vel(road, car_i);
+        }
+        let car = &road.cars[car_i];
+        let lane_i = road.usable_lane(car_i, self.target_lane_i);
+        let (center_x, gap_vel) = self.gap_target(road, car_i, lane_i);
+        (gap_vel + ALIGN_GAIN * (center_x - car.x())).max(0.0)
+    }
+
+    fn choose_trajectory(&mut self, road: &Road, car_i: usize, traj: &mut Trajectory) {
+        self.update_committed(road, car_i);
+        if self.committed {
+            // which still waits for the lane to be clear
+            self.lane_change.choose_trajectory(road, car_i, traj)
+        } else {
+            self.lane_change
+                .lane_keep_trajectory(road, car_i, 0.0, traj)
+        }
+    }
+
+    fn policy_id(&self) -> u32 {
+        self.lane_change.policy_id()
+    }
+
+    fn operating_policy(&self) -> SidePolicy {
+        SidePolicy::GapAlignmentPolicy(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        arg_parameters::Parameters, car::Car, mpdm::make_obstacle_vehicle_policy_choices,
+        policy_registry::find_lane_change,
+    };
+
+    #[test]
+    fn test_gap_alignment() {
+        let params = Arc::new(Parameters::new().unwrap());
+        let mut road = Road::new(params.clone());
+        road.cars[0].set_x(100.0);
+        road.cars[0].vel = 10.0;
+        road.cars[0].preferred_vel = 20.0;
+        // a car alongside in lane 1, and one 30 m further ahead, both keeping their speed
+        let maintain = find_lane_change(
+            &make_obstacle_vehicle_policy_choices(&params),
+            Some(1),
+            LongitudinalPolicy::Maintain,
+        )
+        .unwrap()
+        .clone();
+        for (car_i, x) in [(1, 100.0), (2, 130.0)] {
+            let mut car = Car::new(&params, car_i, 1);
+            car.set_x(x);
+            car.vel = 10.0;
+            car.side_policy = maintain.clone();
+            road.cars.push(car);
+        }
+        road.update_cars_spatial();
+        let mut road = road.sim_estimate();
+
+        let mut ahead = GapAlignmentPolicy::new(0, 1, 1, params.lane_change_time);
+        let mut behind = GapAlignmentPolicy::new(0, 1, -1, params.lane_change_time);
+        let (center_x, gap_vel) = ahead.gap_target(&road, 0, 1);
+        assert!((center_x - 115.0).abs() < 1e-9);
+        assert_eq!(gap_vel, 10.0);
+        // speeding up toward the gap ahead, or slowing down for the one behind, in the same lane
+        assert!(ahead.choose_vel(&road, 0) > 10.0);
+        assert!(behind.choose_vel(&road, 0) < 10.0);
+        assert_eq!(ahead.choose_target_lane(&road, 0), 0);
+        assert!(!ahead.committed());
+
+        // and changing lanes into the gap once beside it
+        road.set_ego_policy(&SidePolicy::GapAlignmentPolicy(ahead));
+        for _ in 0..2000 {
+            road.update(params.physics_dt);
+            if road.cars[0].current_lane() == 1 {
+                break;
+            }
+        }
+        assert!(!road.cars[0].crashed);
+        assert_eq!(road.cars[0].current_lane(), 1);
+        let ego_x = road.cars[0].x();
+        assert!(road.cars[1].x() < ego_x && ego_x < road.cars[2].x());
+    }
+}
@@ -0,0 +1,108 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    arg_parameters::IdmParameters, car::BREAKING_ACCEL, forward_control::ForwardControlTrait,
+    intelligent_driver::obstacle_ahead, Road,
+};
+
+// The textbook Intelligent Driver Model, with the desired gap, time headway, max accel,
+// and comfortable decel from params.idm shared by every car using it, rather than
+// IntelligentDriverPolicy's per-car preferred accel and the policies' follow times.
+// https://en.wikipedia.org/wiki/Intelligent_driver_model
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdmControl;
+
+impl IdmControl {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+// IDM acceleration at vel toward target_vel, with ahead as (gap, velocity) of what's ahead
+pub fn idm_accel(idm: &IdmParameters, vel: f64, target_vel: f64, ahead: Option<(f64, f64)>) -> f64 {
+    let accel_free_road = if target_vel == 0.0 {
+        if vel > 0.0 {
+            -BREAKING_ACCEL
+        } else {
+            0.0
+        }
+    } else {
+        idm.max_accel * (1.0 - (vel / target_vel).powf(idm.accel_exponent))
+    };
+
+    match ahead {
+        Some((gap, ahead_vel)) => {
+            let approaching_rate = vel - ahead_vel;
+            let desired_gap = idm.desired_gap
+                + (vel * idm.time_headway
+                    + vel * approaching_rate
+                        / (2.0 * (idm.max_accel * idm.comfortable_decel).sqrt()))
+                .max(0.0);
+            accel_free_road - idm.max_accel * (desired_gap / gap.max(0.01)).powi(2)
+        }
+        None => accel_free_road,
+    }
+}
+
+// idm_accel for car_i toward its target velocity, with its own desired gap if it has one
+pub fn car_idm_accel(road: &Road, car_i: usize, ahead: Option<(f64, f64)>) -> f64 {
+    let car = &road.cars[car_i];
+    match car.traits {
+        Some(traits) => {
+            let idm = IdmParameters {
+                desired_gap: traits.desired_gap,
+                ..road.params.idm.clone()
+            };
+            idm_accel(&idm, car.vel, car.target_vel, ahead)
+        }
+        None => idm_accel(&road.params.idm, car.vel, car.target_vel, ahead),
+    }
+}
+
+impl ForwardControlTrait for IdmControl {
+    fn choose_accel(&mut self, road: &Road, car_i: usize) -> f64 {
+        let ahead = obstacle_ahead(road, car_i).map(|(gap, ahead_vel, _)| (gap, ahead_vel));
+        car_idm_accel(road, car_i, ahead)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        arg_parameters::Parameters,
+        car::{Car, SpatialCar},
+    };
+
+    #[test]
+    fn test_idm_following() {
+        let params = Parameters::new().unwrap();
+        let idm = params.idm.clone();
+        let mut road = Road::new(Arc::new(params.clone()));
+        road.cars[0].vel = 10.0;
+        road.cars[0].target_vel = 10.0;
+
+        // free road at the target speed
+        let mut control = IdmControl::new();
+        assert_eq!(control.choose_accel(&road, 0), 0.0);
+
+        // at the equilibrium gap behind a car going the same speed
+        let mut lead = Car::new(&params, 1, 0);
+        lead.vel = 10.0;
+        let gap = (idm.desired_gap + 10.0 * idm.time_headway)
+            / (1.0 - 0.9f64.powf(idm.accel_exponent)).sqrt();
+        lead.set_x(gap + lead.length);
+        road.cars_spatial.push(SpatialCar::from(&lead));
+        road.cars.push(lead);
+        road.cars[0].target_vel = 10.0 / 0.9;
+        let accel = control.choose_accel(&road, 0);
+        assert!(accel.abs() < 1e-3, "{}", accel);
+
+        // and braking for it once it's closer
+        let length = road.cars[1].length;
+        road.cars[1].set_x(gap / 2.0 + length);
+        assert!(control.choose_accel(&road, 0) < -idm.max_accel);
+    }
+}
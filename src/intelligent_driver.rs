@@ -1,6 +1,11 @@
-use crate::{car::BREAKING_ACCEL, forward_control::ForwardControlTrait, Road};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone)]
+use crate::{
+    car::BREAKING_ACCEL, forward_control::ForwardControlTrait, pedestrian::pedestrian_ahead,
+    traffic_light::stop_line_ahead, Road,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct IntelligentDriverPolicy;
 
 impl IntelligentDriverPolicy {
@@ -9,6 +14,29 @@ impl IntelligentDriverPolicy {
     }
 }
 
+// What car_i has to keep its distance from: (forward distance, its velocity, the car, if a car).
+// The end of the lane it's in, a crossing pedestrian, and a stop line at a red light
+// are all like stopped cars.
+pub fn obstacle_ahead(road: &Road, car_i: usize) -> Option<(f64, f64, Option<usize>)> {
+    let car = &road.cars[car_i];
+    let mut ahead = road
+        .dist_clear_ahead_in_lane(car_i, car.target_lane_i)
+        .map(|(forward_dist, c_i)| (forward_dist, road.cars[c_i].vel, Some(c_i)));
+    for stop_dist in [
+        road.lane_end_ahead(car.current_lane(), car.x()),
+        pedestrian_ahead(road, car_i),
+        stop_line_ahead(road, car_i),
+    ]
+    .iter()
+    .flatten()
+    {
+        if ahead.map_or(true, |(forward_dist, _, _)| *stop_dist < forward_dist) {
+            ahead = Some((*stop_dist, 0.0, None));
+        }
+    }
+    ahead
+}
+
 // https://en.wikipedia.org/wiki/Intelligent_driver_model
 impl ForwardControlTrait for IntelligentDriverPolicy {
     fn choose_accel(&mut self, road: &Road, car_i: usize) -> f64 {
@@ -25,7 +53,7 @@ impl ForwardControlTrait for IntelligentDriverPolicy {
         };
 
         // if road.params.intelligent_driver_debug && road.super_debug() && car.is_ego() {
-        //     eprintln_f!(
+        //     trace_f!(
         //         "{road.timesteps}: {car.vel=:.4} {car.preferred_accel=:.4}, {car.target_vel=:.4}"
         //     );
         // }
@@ -38,9 +66,11 @@ impl ForwardControlTrait for IntelligentDriverPolicy {
             car.target_vel,
         );
 
+        let ahead = obstacle_ahead(road, car_i);
+
         let accel;
-        if let Some((forward_dist, c_i)) = road.dist_clear_ahead_in_lane(car_i, car.target_lane_i) {
-            let approaching_rate = car.vel - road.cars[c_i].vel;
+        if let Some((forward_dist, ahead_vel, c_i)) = ahead {
+            let approaching_rate = car.vel - ahead_vel;
 
             let follow_dist = car.follow_dist();
             let spacing_term = follow_dist
@@ -52,16 +82,19 @@ impl ForwardControlTrait for IntelligentDriverPolicy {
 
             if road.params.intelligent_driver_debug {
                 if road.super_debug() && car.is_ego() {
-                    eprintln_f!("{road.timesteps}: {car_i=}, {c_i=}, lane_i = {car.target_lane_i}, {forward_dist=:.10}, {follow_dist=:.10}, vel = {car.vel:.10}, {approaching_rate=:.10}, {spacing_term=:.10}, {accel_free_road=:.10}, {accel_interaction=:.10}");
-                } else if road.super_debug() && c_i == 0 && road.params.debug_car_i == Some(car_i) {
-                    eprintln_f!("{road.timesteps}: {car_i=}, {c_i=}, lane_i = {car.target_lane_i}, {forward_dist=:.10}, {follow_dist=:.10}, vel = {car.vel:.10}, {approaching_rate=:.10}, {spacing_term=:.10}, {accel_free_road=:.10}, {accel_interaction=:.10}");
+                    trace_f!("{road.timesteps}: {car_i=}, {c_i=:?}, lane_i = {car.target_lane_i}, {forward_dist=:.10}, {follow_dist=:.10}, vel = {car.vel:.10}, {approaching_rate=:.10}, {spacing_term=:.10}, {accel_free_road=:.10}, {accel_interaction=:.10}");
+                } else if road.super_debug()
+                    && c_i == Some(0)
+                    && road.params.debug_car_i == Some(car_i)
+                {
+                    trace_f!("{road.timesteps}: {car_i=}, {c_i=:?}, lane_i = {car.target_lane_i}, {forward_dist=:.10}, {follow_dist=:.10}, vel = {car.vel:.10}, {approaching_rate=:.10}, {spacing_term=:.10}, {accel_free_road=:.10}, {accel_interaction=:.10}");
                 }
             }
         } else {
             accel = accel_free_road;
 
             if road.params.intelligent_driver_debug && road.super_debug() && car.is_ego() {
-                eprintln_f!(
+                trace_f!(
                     "{road.timesteps}: {car_i=}, lane_i = {car.target_lane_i}, vel = {car.vel:.10}, {accel_free_road=:6.10}, {car.target_vel=:.10}"
                 );
             }
@@ -1,24 +1,27 @@
 use nalgebra::point;
-use parry2d_f64::na::Point2;
+use serde::{Deserialize, Serialize};
 
 use crate::{
     car::{PREFERRED_VEL_ESTIMATE_MIN, PRIUS_LENGTH},
     road::LANE_WIDTH,
-    side_policies::{SidePolicy, SidePolicyTrait},
+    side_policies::{SidePolicy, SidePolicyTrait, Trajectory},
     Road,
 };
 
 const TRANSITION_DIST_MIN: f64 = 1.0 * PRIUS_LENGTH;
 const TRANSITION_DIST_MAX: f64 = 100.0 * PRIUS_LENGTH;
+// an aborting lane change gives up when a car in the target lane would close the gap
+// beside the car within this many seconds
+const ABORT_CLOSING_TIME: f64 = 1.5;
 
-#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub enum LongitudinalPolicy {
     Maintain,
     Accelerate,
     Decelerate,
 }
 
-#[derive(Clone, PartialEq, PartialOrd)]
+#[derive(Clone, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub struct LaneChangePolicy {
     policy_id: u32,
     target_lane_i: Option<i32>,
@@ -27,13 +30,21 @@ pub struct LaneChangePolicy {
     long_policy: LongitudinalPolicy,
     start_vel: Option<f64>,
     waiting_done: bool,
+    abort_if_closed: bool,
+    // the lane the car started from, for going back to if it aborts
+    from_lane_i: Option<i32>,
+    aborted: bool,
 }
 
 impl std::fmt::Debug for LaneChangePolicy {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let s = self;
         let policy_str = format_f!("{s.long_policy:?}");
-        write_f!(f, "lane {s.target_lane_i:?}, {policy_str:10}")
+        write_f!(f, "lane {s.target_lane_i:?}, {policy_str:10}")?;
+        if s.aborted {
+            write!(f, " aborted")?;
+        }
+        Ok(())
     }
 }
 
@@ -53,17 +64,99 @@ impl LaneChangePolicy {
             long_policy,
             start_vel: None,
             waiting_done: false,
+            abort_if_closed: false,
+            from_lane_i: None,
+            aborted: false,
+        }
+    }
+
+    // goes back to the lane it started from if the target lane closes up before it gets there
+    pub fn with_abort(mut self, abort_if_closed: bool) -> Self {
+        self.abort_if_closed = abort_if_closed;
+        self
+    }
+
+    pub fn abort_if_closed(&self) -> bool {
+        self.abort_if_closed
+    }
+
+    pub fn aborted(&self) -> bool {
+        self.aborted
+    }
+
+    pub fn target_lane_i(&self) -> Option<i32> {
+        self.target_lane_i
+    }
+
+    pub fn long_policy(&self) -> LongitudinalPolicy {
+        self.long_policy
+    }
+
+    pub fn wait_for_clear(&self) -> bool {
+        self.wait_for_clear
+    }
+
+    pub fn transition_time(&self) -> f64 {
+        self.transition_time
+    }
+
+    // where the car is headed, which is back where it came from after an abort
+    fn heading_lane(&self, road: &Road, car_i: usize) -> i32 {
+        let lane_i = if self.aborted {
+            self.from_lane_i
+        } else {
+            self.target_lane_i
+        };
+        lane_i.unwrap_or_else(|| road.cars[car_i].current_lane())
+    }
+
+    // Whether the target lane is closing up beside the car, with it still in its own lane:
+    // either not clear right there, or with a car coming up from behind or slowing ahead
+    // that closes the gap within ABORT_CLOSING_TIME.
+    fn target_lane_closing(road: &Road, car_i: usize, lane_i: i32) -> bool {
+        let car = &road.cars[car_i];
+        let (back, front) = (car.x() - 0.5 * car.length, car.x() + 0.5 * car.length);
+        if !road.lane_definitely_clear_between(car_i, lane_i, back - car.length, front) {
+            return true;
+        }
+        road.cars.iter().enumerate().any(|(i, c)| {
+            if i == car_i || c.crashed || c.current_lane() != lane_i {
+                return false;
+            }
+            if c.x() < car.x() {
+                let gap = back - (c.x() + 0.5 * c.length);
+                gap < (c.vel - car.vel) * ABORT_CLOSING_TIME
+            } else {
+                let gap = (c.x() - 0.5 * c.length) - front;
+                gap < (car.vel - c.vel) * ABORT_CLOSING_TIME
+            }
+        })
+    }
+
+    fn check_abort(&mut self, road: &Road, car_i: usize) {
+        if !self.abort_if_closed || self.aborted {
+            return;
+        }
+        let car = &road.cars[car_i];
+        let from_lane_i = *self.from_lane_i.get_or_insert(car.current_lane());
+        let target_lane_i = road.usable_lane(car_i, self.heading_lane(road, car_i));
+        // only while it's changing lanes and hasn't crossed over yet
+        let changing = !self.wait_for_clear || self.waiting_done;
+        if !changing || target_lane_i == from_lane_i || car.current_lane() != from_lane_i {
+            return;
         }
+        self.aborted = Self::target_lane_closing(road, car_i, target_lane_i);
     }
 
-    fn lane_change_trajectory(&mut self, road: &Road, car_i: usize, traj: &mut Vec<Point2<f64>>) {
+    fn lane_change_trajectory(&mut self, road: &Road, car_i: usize, traj: &mut Trajectory) {
         let car = &road.cars[car_i];
 
         let total_transition_dist = (self.transition_time * car.vel)
             .max(TRANSITION_DIST_MIN)
             .min(TRANSITION_DIST_MAX);
 
-        let target_y = Road::get_lane_y(self.target_lane_i.unwrap_or_else(|| car.current_lane()));
+        let target_lane_i = self.heading_lane(road, car_i);
+        let target_y = Road::get_lane_y(road.usable_lane(car_i, target_lane_i));
 
         let transition_left = (car.y() - target_y).abs() / LANE_WIDTH;
         let transition_dist = total_transition_dist * transition_left;
@@ -71,27 +164,32 @@ impl LaneChangePolicy {
         let target_x = car.x() + transition_dist;
         // let progress = (road.t - start_time) / self.transition_time;
 
-        traj.clear();
-        traj.extend_from_slice(&[
+        traj.set(&[
             point!(car.x(), car.y()),
             point!(target_x, target_y),
             point!(target_x + 100.0, target_y), // then continue straight
         ]);
     }
 
-    fn lane_keep_trajectory(&mut self, road: &Road, car_i: usize, traj: &mut Vec<Point2<f64>>) {
+    // staying in the current lane, offset m from its center toward the higher lanes
+    pub fn lane_keep_trajectory(
+        &mut self,
+        road: &Road,
+        car_i: usize,
+        offset: f64,
+        traj: &mut Trajectory,
+    ) {
         let car = &road.cars[car_i];
-        let lane_i = car.current_lane();
+        let target_y = Road::get_lane_y(car.current_lane()) + offset;
 
         let transition_dist = (self.transition_time * car.vel)
             .max(TRANSITION_DIST_MIN)
             .min(TRANSITION_DIST_MAX);
 
-        traj.clear();
-        traj.extend_from_slice(&[
+        traj.set(&[
             point!(car.x(), car.y()),
-            point!(car.x() + transition_dist, Road::get_lane_y(lane_i)),
-            point!(car.x() + 100.0, Road::get_lane_y(lane_i)),
+            point!(car.x() + transition_dist, target_y),
+            point!(car.x() + 100.0, target_y),
         ]);
     }
 }
@@ -101,8 +199,8 @@ impl SidePolicyTrait for LaneChangePolicy {
         if self.wait_for_clear && !self.waiting_done {
             return road.cars[car_i].current_lane();
         }
-        self.target_lane_i
-            .unwrap_or_else(|| road.cars[car_i].current_lane())
+        self.check_abort(road, car_i);
+        road.usable_lane(car_i, self.heading_lane(road, car_i))
     }
 
     fn choose_follow_time(&mut self, _road: &Road, _car_i: usize) -> f64 {
@@ -127,12 +225,13 @@ impl SidePolicyTrait for LaneChangePolicy {
         target_vel
     }
 
-    fn choose_trajectory(&mut self, road: &Road, car_i: usize, traj: &mut Vec<Point2<f64>>) {
+    fn choose_trajectory(&mut self, road: &Road, car_i: usize, traj: &mut Trajectory) {
         if self.wait_for_clear && !self.waiting_done {
             let car = &road.cars[car_i];
+            let target_lane_i = self.heading_lane(road, car_i);
             self.waiting_done = road.lane_definitely_clear_between(
                 car_i,
-                self.target_lane_i.unwrap_or_else(|| car.current_lane()),
+                road.usable_lane(car_i, target_lane_i),
                 car.x() - 0.5 * car.length - car.length,
                 car.x() + 0.5 * car.length,
             );
@@ -140,7 +239,7 @@ impl SidePolicyTrait for LaneChangePolicy {
         if self.waiting_done || !self.wait_for_clear {
             self.lane_change_trajectory(road, car_i, traj)
         } else {
-            self.lane_keep_trajectory(road, car_i, traj)
+            self.lane_keep_trajectory(road, car_i, 0.0, traj)
         }
     }
 
@@ -160,3 +259,63 @@ impl SidePolicyTrait for LaneChangePolicy {
         SidePolicy::LaneChangePolicy(self.clone())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{arg_parameters::Parameters, car::Car};
+
+    #[test]
+    fn test_abort_lane_change() {
+        let params = Arc::new(Parameters::new().unwrap());
+        let mut road = Road::new(params.clone());
+        road.cars[0].set_x(100.0);
+        road.cars[0].vel = 10.0;
+        // coming up fast in the target lane
+        let mut car = Car::new(&params, 1, 1);
+        car.set_x(70.0);
+        car.vel = 25.0;
+        car.preferred_vel = 25.0;
+        car.side_policy = SidePolicy::LaneChangePolicy(LaneChangePolicy::new(
+            1,
+            Some(1),
+            2.0,
+            false,
+            LongitudinalPolicy::Maintain,
+        ));
+        road.cars.push(car);
+        road.update_cars_spatial();
+        let road = road.sim_estimate();
+
+        let run = |policy: LaneChangePolicy| {
+            let mut road = road.clone();
+            road.set_ego_policy(&SidePolicy::LaneChangePolicy(policy));
+            road.take_update_steps(6.0, params.physics_dt);
+            road
+        };
+        let lane_change =
+            LaneChangePolicy::new(0, Some(1), 2.0, false, LongitudinalPolicy::Maintain);
+        let keep = run(LaneChangePolicy::new(
+            0,
+            None,
+            2.0,
+            false,
+            LongitudinalPolicy::Maintain,
+        ));
+        let through = run(lane_change.clone());
+        let aborted = run(lane_change.with_abort(true));
+        assert_eq!(through.cars[0].current_lane(), 1);
+        let ego = &aborted.cars[0];
+        assert!(!ego.crashed);
+        assert_eq!(ego.current_lane(), 0);
+        assert!(matches!(aborted.ego_policy(), SidePolicy::LaneChangePolicy(p) if p.aborted()));
+
+        // going partway over and back costs some smoothness, but far less than cutting in
+        assert_eq!(keep.cost.steer, 0.0);
+        assert!(aborted.cost.steer > 0.0 && aborted.cost.steer < through.cost.steer);
+        assert!(aborted.cost.safety < 0.1 * through.cost.safety);
+        assert!(aborted.cost.total() < through.cost.total());
+    }
+}
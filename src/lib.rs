@@ -0,0 +1,792 @@
+//! A highway driving simulator and the planners that drive its ego car through traffic.
+//!
+//! The simulator is a [`Road`] of [`Car`]s: the ego, as `cars[0]`, and the obstacle cars,
+//! each driven by a side policy ([`SidePolicy`]) over forward and side controllers.
+//! [`Road::update`] steps it by a physics timestep and accrues the ego's [`Cost`], and
+//! the road keeps a [`Belief`] over the obstacle cars' policies for the planners.
+//! Everything is configured by [`Parameters`], loaded from parameters.toml and set by name with
+//! [`set_parameter`].
+//!
+//! The planners choose the ego's next policy from the road by forward simulation:
+//! mpdm (multipolicy decision making), eudm (a domain-specific decision tree)
+//! and mcts (Monte Carlo tree search), all behind [`choose_policy`] by the method parameter,
+//! which gives back a [`Plan`]. Each is an [`EgoPlanner`], and planners from other crates join
+//! them as methods with [`register_planner`].
+//!
+//! ```
+//! use std::sync::Arc;
+//!
+//! use rand::{prelude::StdRng, SeedableRng};
+//! use selfdriving::{choose_policy, Parameters, Road};
+//!
+//! let mut params = Parameters::new().unwrap();
+//! params.method = "mpdm".to_owned();
+//! params.run_fast = true;
+//! let params = Arc::new(params);
+//!
+//! let mut road = Road::new(params.clone());
+//! let mut rng = StdRng::seed_from_u64(0);
+//! road.init_belief();
+//! let plan = choose_policy(&params, &road, &mut rng).unwrap();
+//! road.set_ego_policy(&plan.policy.unwrap());
+//! for _ in 0..25 {
+//!     road.update(params.physics_dt);
+//! }
+//! assert!(road.cost.total() >= 0.0);
+//! ```
+//!
+//! Whole runs, with respawning traffic, recording and the viewer, are [`run_with_parameters`],
+//! and [`create_scenarios`] expands a sweep over parameter values into the runs' parameters,
+//! which the binary runs from the command line. [`Env`] wraps the simulator as a Gym-style
+//! environment, and [`play_replay`] plays back recorded runs. [`minimize`] shrinks a run where
+//! the ego crashes to a small scenario file that still crashes. For quick comparisons without
+//! a sweep, [`evaluate`] runs a configuration over seeded episodes in parallel and gives each
+//! metric's mean, standard deviation and confidence interval, and [`stress_test_all`] compares
+//! that with the obstacle cars driving unlike the planners' hypotheses.
+//!
+//! Setting up a run, and planning, give back a [`SimError`] instead of panicking when they can't
+//! go on, like for a missing scenario file or a road with no room for its cars.
+
+use std::{
+    f64::consts::PI,
+    rc::Rc,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+pub use alloc_counter::CountingAllocator;
+pub use arg_parameters::{create_scenarios, load_cost_spec, set_parameter, Parameters};
+pub use belief::Belief;
+pub use candidates::Candidate;
+pub use car::Car;
+pub use cost::{ComfortDisplay, Cost};
+pub use env::{Env, Step};
+pub use evaluate::{evaluate, evaluate_all, Evaluation, MetricSummary};
+pub use logging::init_logging;
+pub use minimize::{minimize, Minimized};
+pub use mismatch::{stress_test_all, StressTest};
+pub use planner::{register_planner, EgoPlanner, PlannerFactory};
+pub use replay::play_replay;
+pub use reward::Reward;
+pub use road::Road;
+pub use scenario_file::ScenarioFile;
+pub use side_policies::SidePolicy;
+pub use sim_error::SimError;
+
+use cfb::conditional_focused_branching;
+use commonroad::{CommonRoadScenario, SolutionRecorder};
+use comparison::Comparison;
+use opendrive::OpenDriveRoad;
+use playback::{Playback, PlaybackAction};
+
+use rand::{prelude::StdRng, Rng, SeedableRng};
+use replay::ReplayRecorder;
+use rng_streams::Stream;
+use road::TraceLine;
+use road_set::RoadSet;
+use rvx::{Rvx, RvxColor};
+use scenario_library::named_scenario;
+use scripted_events::ScriptedEvents;
+use side_policies::SidePolicyTrait;
+use sumo::SumoTraffic;
+use svg::SvgExporter;
+use trajectories::TrajectoryRecorder;
+use video::VideoRecorder;
+
+#[macro_use]
+extern crate fstrings;
+
+// Like eprintln_f!, but as tracing events so they get levels, per-module filters, and run context
+macro_rules! trace_f {
+    ($($args:tt)*) => {
+        tracing::trace!("{}", format_f!($($args)*))
+    };
+}
+
+macro_rules! debug_f {
+    ($($args:tt)*) => {
+        tracing::debug!("{}", format_f!($($args)*))
+    };
+}
+
+macro_rules! error_f {
+    ($($args:tt)*) => {
+        tracing::error!("{}", format_f!($($args)*))
+    };
+}
+
+mod alloc_counter;
+mod arg_parameters;
+mod belief;
+mod candidates;
+mod car;
+mod cfb;
+mod commonroad;
+mod comparison;
+mod contingency_policy;
+mod cost;
+mod cost_spec;
+mod delayed_policy;
+mod env;
+mod eudm;
+mod evaluate;
+mod forward_control;
+mod gap_alignment_policy;
+mod idm_control;
+mod intelligent_driver;
+mod lane_change_policy;
+mod logging;
+mod mcts;
+mod minimize;
+mod mismatch;
+mod mobil_policy;
+mod mpdm;
+mod nudge_policy;
+mod occlusion;
+mod open_loop_policy;
+mod opendrive;
+mod pedestrian;
+mod planner;
+mod playback;
+mod policy_registry;
+mod profiling;
+mod pure_pursuit;
+mod rate_timer;
+mod render;
+mod replay;
+mod reward;
+mod rng_streams;
+mod road;
+mod road_arena;
+mod road_geometry;
+mod road_set;
+mod rss;
+mod run_artifacts;
+mod safety_metrics;
+mod scenario_file;
+mod scenario_library;
+mod scripted_events;
+mod sensor;
+mod side_control;
+mod side_policies;
+mod sim_error;
+mod stanley;
+mod sumo;
+mod svg;
+mod traffic_light;
+mod trajectories;
+mod video;
+mod x_prefilter;
+
+#[macro_use]
+extern crate enum_dispatch;
+
+struct State {
+    scenario_rng: StdRng,
+    respawn_rng: StdRng,
+    policy_rng: StdRng,
+    sensor_rng: StdRng,
+    params: Arc<Parameters>,
+    road: Road,
+    planner: Box<dyn EgoPlanner>,
+    traces: Rc<Vec<rvx::Shape>>,
+    r: Option<Rvx>,
+    timesteps: u32,
+    reward: Reward,
+    paper_graphics_sets: Vec<Vec<rvx::Shape>>,
+    // cumulative ego cost after each timestep, only kept for single runs
+    cost_history: Vec<Cost>,
+    // the mean entropy of the belief over the obstacle cars, for each step
+    belief_entropy_history: Vec<f64>,
+    // the ego planner's candidate policies at the latest replan
+    candidates: Vec<Candidate>,
+    // and at each replan, with its timestep, only kept for single runs
+    candidate_history: Vec<(u32, Candidate)>,
+    replay: Option<ReplayRecorder>,
+    video: Option<VideoRecorder>,
+    svg: Option<SvgExporter>,
+    // the ego's traces from the latest planning, only collected for svg
+    trace_lines: Vec<TraceLine>,
+    solution: Option<SolutionRecorder>,
+    sumo: Option<SumoTraffic>,
+    trajectories: Option<TrajectoryRecorder>,
+    scripted_events: ScriptedEvents,
+}
+
+impl State {
+    // debugging another car partway through a run, as if it had been the parameter
+    fn set_debug_car_i(&mut self, debug_car_i: Option<usize>) {
+        let mut params = (*self.params).clone();
+        params.debug_car_i = debug_car_i;
+        self.params = Arc::new(params);
+        self.road.params = self.params.clone();
+    }
+
+    fn update_graphics(&mut self, traces: &Rc<Vec<rvx::Shape>>) {
+        if let Some(r) = self.r.as_mut() {
+            r.clear();
+
+            self.road.draw(r);
+            r.draw_all(traces.iter().cloned());
+
+            if self.params.graphics_for_paper && self.timesteps >= 1100 && self.timesteps % 50 == 25
+            {
+                self.paper_graphics_sets.push(r.shapes().to_vec());
+            }
+
+            r.set_global_rot(-PI / 2.0);
+            r.commit_changes();
+        }
+    }
+
+    fn update(&mut self, dt: f64) -> Result<(), SimError> {
+        let replan_interval = self.params.replan_steps();
+        let ego_policy_id = self.road.cars[0].operating_policy_id();
+
+        // method chooses the ego policy
+        let mut plan_rng;
+        let policy_rng = if self.params.rng_streams {
+            let keys = [self.timesteps as u64];
+            plan_rng = rng_streams::fork(&self.policy_rng, Stream::Planning, &keys);
+            &mut plan_rng
+        } else {
+            &mut self.policy_rng
+        };
+        let replanned = self.timesteps % replan_interval == 0 && !self.road.cars[0].crashed;
+        if replanned {
+            let replan_real_time_start = Instant::now();
+            road_arena::begin_planning();
+            alloc_counter::take_allocation_count();
+            profiling::take_profile();
+            candidates::clear_candidates();
+            let (policy, mut traces) = self.planner.plan(&self.road, policy_rng);
+
+            let planning_time = replan_real_time_start.elapsed().as_secs_f64();
+            self.reward.planning_times.push(planning_time);
+            self.reward.planning_allocations += alloc_counter::take_allocation_count();
+            if self.params.profile_subsystems {
+                self.reward
+                    .profile
+                    .add_planning_call(planning_time, &profiling::take_profile());
+            }
+            self.reward.rollouts += road::take_rollout_count();
+
+            candidates::copy_candidates(&mut self.candidates);
+            if self.params.is_single_run {
+                let step = self.timesteps;
+                self.candidate_history
+                    .extend(self.candidates.iter().map(|c| (step, *c)));
+            }
+            if candidates::candidates_overlay_shown() {
+                traces.extend(candidates::candidates_overlay(&self.road, &self.candidates));
+            }
+            self.traces = Rc::new(traces);
+            // taken either way, so a compared planner's don't pile up for the next
+            let trace_lines = road::take_trace_lines();
+            if self.svg.is_some() {
+                self.trace_lines = trace_lines;
+            }
+
+            if let Some(policy) = policy {
+                self.road.set_ego_policy(&policy);
+            }
+        }
+
+        let timesteps = self.timesteps;
+        for event in self.scripted_events.update(&mut self.road) {
+            if self.road.debug && self.params.obstacle_car_debug {
+                debug_f!("{timesteps}: scripted event {event:?}");
+            }
+        }
+
+        // random policy changes for the obstacle vehicles, but not for ones an event has taken over
+        let policy_change_interval =
+            (self.params.nonego_policy_change_dt / self.params.physics_dt).round() as u32;
+        if self.timesteps % policy_change_interval == 0 {
+            for c in self.road.cars[1..].iter_mut() {
+                if self.scripted_events.is_scripted(c.car_i) {
+                    continue;
+                }
+                let mut car_rng;
+                let rng = if self.params.rng_streams {
+                    let keys = [self.timesteps as u64, c.car_i as u64];
+                    car_rng = rng_streams::fork(&self.scenario_rng, Stream::PolicyChange, &keys);
+                    &mut car_rng
+                } else {
+                    &mut self.scenario_rng
+                };
+                if rng.gen_bool(
+                    self.params.nonego_policy_change_prob * self.params.nonego_policy_change_dt,
+                ) {
+                    let new_policy = mismatch::draw_obstacle_policy(&self.params, rng);
+
+                    if self.road.debug && self.params.obstacle_car_debug {
+                        let new_policy_i = new_policy.policy_id();
+                        debug_f!("{timesteps}: obstacle car {c.car_i} switching to policy {new_policy_i}: {new_policy:?}");
+                    }
+
+                    c.side_policy = new_policy;
+                }
+            }
+        }
+
+        // actual simulation
+        let n_crashed = self.road.cars.iter().filter(|c| c.crashed).count();
+        let ego_x = self.road.cars[0].x();
+        let was_emergency_braking = self.road.emergency_braking;
+        self.road.update_belief(&mut self.sensor_rng)?;
+        self.road.update(dt);
+
+        // final reporting reward (separate from cost function, though similar)
+        self.reward.crash_count +=
+            (self.road.cars.iter().filter(|c| c.crashed).count() - n_crashed) as u32;
+        if self.road.cars[0].operating_policy_id() != ego_policy_id {
+            self.reward.policy_switches += 1;
+        }
+        if traffic_light::ran_red_light(&self.params, ego_x, self.road.cars[0].x(), self.road.t) {
+            self.reward.red_light_violations += 1;
+        }
+        if self.road.emergency_braking && !was_emergency_braking {
+            self.reward.emergency_brakes += 1;
+        }
+        if let Some(belief) = self.road.belief.as_ref() {
+            let (errors, checks) = belief.lane_errors(&self.road);
+            self.reward.belief_lane_errors += errors;
+            self.reward.belief_lane_checks += checks;
+        }
+        if let Some(offset) = self.road.ego_lane_offset() {
+            self.reward.lane_offset_sq_t += offset.powi(2) * dt;
+            self.reward.lane_keeping_t += dt;
+        }
+        if self.road.rss_violation {
+            self.reward.rss_violation_t += dt;
+        }
+        self.reward.safety.update(&self.road);
+
+        if let Some(sumo) = self.sumo.as_mut() {
+            let sync_interval = (self.params.sumo.sync_dt / self.params.physics_dt).round() as u32;
+            if (self.timesteps + 1) % sync_interval.max(1) == 0 {
+                sumo.sync(&mut self.road);
+            }
+        } else if self.params.spawn.open_boundary {
+            self.road.open_boundary_traffic(&mut self.respawn_rng, dt);
+        } else {
+            self.road.respawn_obstacle_cars(&mut self.respawn_rng);
+        }
+        self.road.respawn_pedestrians(&mut self.respawn_rng);
+
+        self.reward.dist_travelled += self.road.cars[0].vel * dt;
+        if self.road.cars[0].crashed {
+            self.reward.crashed = true;
+            self.reward.impact_speed = self.road.ego_impact_speed;
+        }
+        if self.params.is_single_run {
+            self.cost_history.push(self.road.cost);
+            let entropy = self.road.belief.as_ref().map_or(0.0, |b| b.mean_entropy());
+            self.belief_entropy_history.push(entropy);
+        }
+        if let Some(replay) = self.replay.as_mut() {
+            replay.record_frame(&self.road, replanned, &self.candidates);
+        }
+        if let Some(video) = self.video.as_mut() {
+            video.record_frame(&self.road);
+        }
+        if let Some(trajectories) = self.trajectories.as_mut() {
+            trajectories.record_frame(&self.road);
+        }
+        if let Some(solution) = self.solution.as_mut() {
+            solution.record(&self.road);
+        }
+        if let Some(svg) = self.svg.as_mut() {
+            match svg.after_step(&self.road, &self.trace_lines) {
+                Ok(Some(path)) if self.params.is_single_run => {
+                    eprintln!("Wrote {}", path.display())
+                }
+                Ok(_) => (),
+                Err(e) => eprintln!("Could not write svg: {}", e),
+            }
+        }
+
+        self.timesteps += 1;
+        Ok(())
+    }
+}
+
+/// What [`choose_policy`] planned for the ego, and what went into it
+pub struct Plan {
+    /// the policy to switch to, or None to keep the current one
+    pub policy: Option<SidePolicy>,
+    /// the shapes of what the planner considered, for the viewer
+    pub traces: Vec<rvx::Shape>,
+    /// the policies the planner weighed, with their estimated costs and the one it chose
+    pub candidates: Vec<Candidate>,
+    /// the forward simulations it ran
+    pub rollouts: u64,
+}
+
+/// The ego's next policy from a new planner of `params.method` (mpdm, eudm, mcts, fixed for
+/// keeping its current one, or a registered one), planning with the road's parameters.
+/// The road needs its belief, from [`Road::init_belief`].
+pub fn choose_policy(params: &Parameters, road: &Road, rng: &mut StdRng) -> Result<Plan, SimError> {
+    let mut planner = planner::make_planner(params)?;
+    if road.belief.is_none() {
+        return Err(SimError::NoBelief);
+    }
+    road_arena::begin_planning();
+    candidates::clear_candidates();
+    road::take_rollout_count();
+    let (policy, traces) = planner.plan(road, rng);
+    Ok(Plan {
+        policy,
+        traces,
+        candidates: candidates::take_candidates(),
+        rollouts: road::take_rollout_count(),
+    })
+}
+
+// the road and everything a run steps, before its first timestep
+fn new_state(params: Arc<Parameters>) -> Result<State, SimError> {
+    let mut full_seed = [0; 32];
+    full_seed[0..8].copy_from_slice(&params.rng_seed.to_le_bytes());
+
+    let mut road = Road::new(params.clone());
+    // road.add_obstacle(100.0, 0);
+    let mut scenario_rng = StdRng::from_seed(full_seed);
+    let mut commonroad_scenario = None;
+    let scenario = if commonroad::is_commonroad(&params.scenario_file) {
+        let commonroad = CommonRoadScenario::load(&params.scenario_file)?;
+        let scenario = commonroad.to_scenario_file(&params);
+        commonroad_scenario = Some(commonroad);
+        Some(scenario)
+    } else if !params.scenario_file.is_empty() {
+        Some(ScenarioFile::load(&params.scenario_file)?)
+    } else if !params.named_scenario.is_empty() {
+        Some(named_scenario(&params.named_scenario, &mut scenario_rng))
+    } else {
+        None
+    };
+    let events = scenario
+        .as_ref()
+        .map_or_else(Vec::new, |s| s.events.clone());
+    // its own stream, so sensor noise doesn't change the traffic
+    let mut sensor_seed = full_seed;
+    sensor_seed[8] = 1;
+    let mut spawn_seed = full_seed;
+    match scenario {
+        // SUMO's vehicles arrive when it first syncs
+        None if params.sumo.port != 0 => (),
+        None if params.spawn.open_boundary => road.populate_open_boundary(&mut scenario_rng),
+        None => {
+            while road.cars.len() < params.n_cars + 1 {
+                road.add_random_car(&mut scenario_rng)?;
+            }
+        }
+        Some(scenario) => {
+            // the file's spawn_seed fixes the random and respawned cars across rng_seeds
+            if let Some(seed) = scenario.spawn_seed {
+                spawn_seed[0..8].copy_from_slice(&seed.to_le_bytes());
+                scenario_rng = StdRng::from_seed(spawn_seed);
+            }
+            scenario.populate_road(&mut road, &mut scenario_rng)?;
+        }
+    }
+    road.respawn_pedestrians(&mut scenario_rng);
+    let scripted_events = ScriptedEvents::new(events, &road);
+    road.init_belief();
+    let sumo = if params.sumo.port != 0 {
+        let mut sumo = SumoTraffic::connect(&params)?;
+        sumo.sync(&mut road);
+        Some(sumo)
+    } else {
+        None
+    };
+
+    let mut state = State {
+        scenario_rng,
+        respawn_rng: StdRng::from_seed(spawn_seed),
+        policy_rng: StdRng::from_seed(full_seed),
+        sensor_rng: StdRng::from_seed(sensor_seed),
+        road,
+        planner: planner::make_planner(&params)?,
+        r: None,
+        timesteps: 0,
+        params,
+        traces: Rc::new(Vec::new()),
+        reward: Default::default(),
+        paper_graphics_sets: Vec::new(),
+        cost_history: Vec::new(),
+        belief_entropy_history: Vec::new(),
+        candidates: Vec::new(),
+        candidate_history: Vec::new(),
+        replay: None,
+        video: None,
+        svg: None,
+        trace_lines: Vec::new(),
+        solution: None,
+        sumo,
+        trajectories: None,
+        scripted_events,
+    };
+    if !state.params.replays_dir.is_empty() {
+        let mut replay = ReplayRecorder::new(&state.params);
+        replay.record_frame(&state.road, false, &[]);
+        state.replay = Some(replay);
+    }
+    if !state.params.record.is_empty() {
+        let mut video = VideoRecorder::new(&state.params);
+        video.record_frame(&state.road);
+        state.video = Some(video);
+    }
+    if !state.params.svg.dir.is_empty() {
+        state.svg = Some(SvgExporter::default());
+    }
+    if !state.params.trajectories.dir.is_empty() {
+        let mut trajectories = TrajectoryRecorder::new(&state.params);
+        trajectories.record_frame(&state.road);
+        state.trajectories = Some(trajectories);
+    }
+    if !state.params.commonroad.solution.is_empty() {
+        let scenario = commonroad_scenario.ok_or_else(|| {
+            SimError::InvalidParameters(
+                "commonroad.solution needs a CommonRoad scenario_file (.xml)".to_owned(),
+            )
+        })?;
+        let mut solution = SolutionRecorder::new(scenario, &state.params);
+        solution.record(&state.road);
+        state.solution = Some(solution);
+    }
+    Ok(state)
+}
+
+// Without the viewer or anything that the run would record or report,
+// for runs that are only a step toward something else
+fn headless_params(mut params: Parameters) -> Parameters {
+    params.run_fast = true;
+    params.is_single_run = false;
+    params.compare = String::new();
+    params.replays_dir = String::new();
+    params.record = String::new();
+    params.svg.dir = String::new();
+    params.trajectories.dir = String::new();
+    params.commonroad.solution = String::new();
+    params
+}
+
+// The road that the scenario's files give, before its state is made
+fn load_road_geometry(params: &mut Parameters) -> Result<(), SimError> {
+    // the curves of a CommonRoad scenario's lanes, which its name already covers
+    if params.commonroad.curvilinear && commonroad::is_commonroad(&params.scenario_file) {
+        let scenario = CommonRoadScenario::load(&params.scenario_file)?;
+        params.road_geometry = scenario.reference.road_geometry();
+    }
+    // the road of an OpenDRIVE map, likewise
+    if !params.opendrive.file.is_empty() {
+        let road = OpenDriveRoad::load(&params.opendrive.file, &params.opendrive.road)?;
+        road.apply(params);
+    }
+    Ok(())
+}
+
+/// One whole run of `params`, with its traffic, its planner and whatever it records,
+/// giving back the ego's final cost and the reported metrics, or why it couldn't be run
+pub fn run_with_parameters(mut params: Parameters) -> Result<(Cost, Reward), SimError> {
+    let _span = tracing::info_span!(
+        "run",
+        rng_seed = params.rng_seed,
+        method = %params.method,
+        use_cfb = params.use_cfb
+    )
+    .entered();
+    load_road_geometry(&mut params)?;
+    if params.sumo.port != 0 && !params.compare.is_empty() {
+        return Err(SimError::InvalidParameters(
+            "compare needs its own traffic, but SUMO's only has one ego".to_owned(),
+        ));
+    }
+    let params = Arc::new(params);
+    let mut state = new_state(params.clone())?;
+    // the other planner, on the same traffic from the same seeds
+    let mut compare_state = if params.compare.is_empty() {
+        None
+    } else {
+        Some(new_state(Arc::new(comparison::compare_params(&params)))?)
+    };
+    let mut comparison = Comparison::default();
+    road::collect_trace_lines(!params.svg.dir.is_empty());
+
+    let use_graphics = !state.params.run_fast;
+    mcts::set_tree_overlay(use_graphics && state.params.mcts.tree_overlay);
+    candidates::set_candidates_overlay(use_graphics && state.params.candidates_overlay);
+
+    let mut playback = None;
+    if use_graphics {
+        eprintln!("{}", playback::HELP);
+        let mut r = Rvx::new("Self-Driving!", [0, 0, 0, 0], 8000);
+        // r.set_user_zoom(Some(0.4)); // 0.22
+        std::thread::sleep(Duration::from_millis(500));
+        r.set_user_zoom(None);
+        state.r = Some(r);
+
+        let mut p = Playback::new(&state.params);
+        p.record(&state.road, &state.traces);
+        playback = Some(p);
+    }
+
+    while state.timesteps < state.params.max_steps {
+        if let (Some(p), Some(r)) = (playback.as_mut(), state.r.as_mut()) {
+            let action = p.next_action(r);
+            if let Some(debug_car_i) = p.take_debug_car_i() {
+                state.set_debug_car_i(debug_car_i);
+            }
+            match action {
+                PlaybackAction::Step => (),
+                PlaybackAction::Wait => continue,
+                PlaybackAction::Quit => break,
+            }
+        }
+
+        state.update(state.params.physics_dt)?;
+
+        // with the other planner's ego and both paths over the first's traces
+        let mut traces = state.traces.clone();
+        if let Some(other) = compare_state.as_mut() {
+            other.update(other.params.physics_dt)?;
+            comparison.track(&state.road, &other.road);
+            if playback.is_some() {
+                let mut shapes = (*traces).clone();
+                shapes.extend(comparison.shapes(&state.params));
+                traces = Rc::new(shapes);
+            }
+        }
+
+        if let Some(p) = playback.as_mut() {
+            p.record(&state.road, &traces);
+            if p.draws_step() {
+                state.update_graphics(&traces);
+                p.wait_until_ready();
+            }
+        }
+
+        // if i == 1000 {
+        //     for side_policy in state.road.cars[0].side_policy.iter_mut() {
+        //         *side_policy = side_policies::SidePolicy::LaneChangePolicy(
+        //             lane_change_policy::LaneChangePolicy::new(1, LANE_CHANGE_TIME, None),
+        //         );
+        //     }
+        // }
+    }
+
+    if state.params.graphics_for_paper {
+        if let Some(r) = state.r.as_mut() {
+            r.clear();
+            r.draw(Rvx::square().scale(1000.0).color(RvxColor::LIGHT_GRAY));
+
+            let x = 0.0;
+            let mut y = 0.0;
+
+            for shape_set in state.paper_graphics_sets.iter() {
+                r.draw_all(shape_set.iter().cloned());
+                r.set_translate_modifier(x, y);
+                y -= 9.0;
+            }
+            r.commit_changes();
+        }
+    }
+
+    if use_graphics {
+        std::thread::sleep(Duration::from_millis(1000));
+    }
+
+    state.road.cost = state.road.final_cost();
+    state.reward.end_t = state.road.t;
+    state.reward.avg_vel = state.reward.dist_travelled / state.road.t;
+    state.reward.calculate_timestep_metrics();
+
+    if let Some(other) = compare_state.as_mut() {
+        other.road.cost = other.road.final_cost();
+        if state.params.is_single_run {
+            eprintln!(
+                "{}",
+                comparison.summary(&state.params, &other.params, &other.road.cost)
+            );
+        }
+    }
+
+    if state.params.is_single_run {
+        match run_artifacts::write_run_artifacts(
+            &state.params,
+            &state.cost_history,
+            &state.belief_entropy_history,
+            &state.candidate_history,
+            &state.road.cost,
+            &state.reward,
+        ) {
+            Ok(dir) => eprintln!("Wrote run artifacts to {}", dir.display()),
+            Err(e) => eprintln!("Could not write run artifacts: {}", e),
+        }
+    }
+
+    if let Some(replay) = state.replay.as_ref() {
+        let path = replay::replay_path(&state.params);
+        match replay.write(&path) {
+            Ok(()) if state.params.is_single_run => eprintln!("Wrote replay to {}", path.display()),
+            Ok(()) => (),
+            Err(e) => eprintln!("Could not write replay {}: {}", path.display(), e),
+        }
+    }
+
+    if let Some(trajectories) = state.trajectories.as_ref() {
+        let path = replay::run_file_path(
+            &state.params.trajectories.dir,
+            &state.params,
+            trajectories.extension(),
+        );
+        match trajectories.write(&path) {
+            Ok(()) if state.params.is_single_run => {
+                eprintln!("Wrote trajectories to {}", path.display())
+            }
+            Ok(()) => (),
+            Err(e) => eprintln!("Could not write trajectories {}: {}", path.display(), e),
+        }
+    }
+
+    if let Some(video) = state.video.take() {
+        match video.finish() {
+            Ok(path) if state.params.is_single_run => {
+                eprintln!("Wrote video to {}", path.display())
+            }
+            Ok(_) => (),
+            Err(e) => eprintln!("Could not write video: {}", e),
+        }
+    }
+
+    if let Some(solution) = state.solution.as_ref() {
+        let computation_time = state.reward.planning_times.iter().sum();
+        match solution.write(&state.params, computation_time) {
+            Ok(path) if state.params.is_single_run => {
+                eprintln!("Wrote CommonRoad solution to {}", path.display())
+            }
+            Ok(_) => (),
+            Err(e) => eprintln!("Could not write CommonRoad solution: {}", e),
+        }
+    }
+
+    Ok((state.road.cost, state.reward))
+}
+
+fn road_set_for_scenario(
+    params: &Parameters,
+    true_road: &Road,
+    rng: &mut StdRng,
+    n: usize,
+    prediction: &str,
+) -> RoadSet {
+    let mut roads = if params.use_cfb {
+        let (base_set, _selected_ids) = conditional_focused_branching(params, true_road, n, "risk");
+        base_set
+    } else {
+        RoadSet::new_samples(true_road, rng, n)
+    };
+    roads.set_prediction(prediction);
+    roads
+}
@@ -0,0 +1,28 @@
+use std::{fs::File, sync::Mutex};
+
+use tracing_subscriber::{fmt, prelude::*, EnvFilter};
+
+use crate::arg_parameters::Parameters;
+
+// Debug output goes through tracing: human-readable on stderr, filtered by RUST_LOG if set
+// and otherwise by params.log_filter (e.g. "info", "selfdriving::cfb=debug,selfdriving::road=trace").
+// With params.log_json_path, every event is also written there as a JSON line,
+// tagged with the rng_seed and method of the run it came from.
+pub fn init_logging(params: &Parameters) {
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(&params.log_filter));
+
+    let json_layer = if params.log_json_path.is_empty() {
+        None
+    } else {
+        let file = File::create(&params.log_json_path)
+            .unwrap_or_else(|e| panic!("Could not create {}: {}", params.log_json_path, e));
+        Some(fmt::layer().json().with_writer(Mutex::new(file)))
+    };
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer().with_writer(std::io::stderr).without_time())
+        .with(json_layer)
+        .init();
+}
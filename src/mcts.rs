@@ -1,18 +1,39 @@
+use std::cell::Cell;
+
 use itertools::Itertools;
 use progressive_mcts::{
     cost_set::CostSet, klucb::klucb_bernoulli, ChildSelectionMode, CostBoundMode,
 };
-use rand::prelude::{SliceRandom, StdRng};
+use rand::{
+    prelude::{SliceRandom, StdRng},
+    Rng, SeedableRng,
+};
+use rvx::{Rvx, RvxColor};
 
 use crate::{
     arg_parameters::{MctsParameters, Parameters},
+    candidates::{choose_candidate, record_candidate},
     cost::Cost,
     mpdm::make_policy_choices,
+    planner::EgoPlanner,
     road::{Particle, Road},
-    road_set_for_scenario,
+    road_arena, road_set_for_scenario,
     side_policies::{SidePolicy, SidePolicyTrait},
 };
 
+thread_local! {
+    // whether the planning tree takes the place of the rollout traces, see tree_overlay()
+    static TREE_OVERLAY: Cell<bool> = Cell::new(false);
+}
+
+pub fn set_tree_overlay(show: bool) {
+    TREE_OVERLAY.with(|t| t.set(show));
+}
+
+pub fn tree_overlay_shown() -> bool {
+    TREE_OVERLAY.with(|t| t.get())
+}
+
 fn compute_selection_index(
     mctsp: &MctsParameters,
     total_n: f64,
@@ -54,6 +75,8 @@ struct MctsNode<'a> {
     policy_choices: &'a [SidePolicy],
     policy: Option<SidePolicy>,
     traces: Vec<rvx::Shape>,
+    // where the ego went in the node's first trial, for the tree overlay
+    tree_points: Vec<f64>,
 
     depth: u32,
     n_trials: usize,
@@ -96,6 +119,7 @@ impl<'a> MctsNode<'a> {
             policy_choices,
             policy,
             traces: Vec::new(),
+            tree_points: Vec::new(),
             depth,
             n_trials: 0,
             expected_cost: None,
@@ -117,15 +141,14 @@ impl<'a> MctsNode<'a> {
     }
 
     fn mean_cost(&self) -> Cost {
-        self.costs.iter().map(|(c, _)| *c).sum::<Cost>() / self.costs.len() as f64
+        Cost::aggregate(self.costs.iter().map(|(c, _)| *c), &self.params.risk)
     }
 
     fn intermediate_cost(&self) -> Cost {
         if self.intermediate_costs.is_empty() {
             Cost::ZERO
         } else {
-            self.intermediate_costs.iter().copied().sum::<Cost>()
-                / self.intermediate_costs.len() as f64
+            Cost::aggregate(self.intermediate_costs.iter().copied(), &self.params.risk)
         }
     }
 
@@ -133,8 +156,10 @@ impl<'a> MctsNode<'a> {
         if self.marginal_costs.is_empty() {
             Cost::ZERO
         } else {
-            self.marginal_costs.iter().map(|(_, c)| *c).sum::<Cost>()
-                / self.marginal_costs.len() as f64
+            Cost::aggregate(
+                self.marginal_costs.iter().map(|(_, c)| *c),
+                &self.params.risk,
+            )
         }
     }
 
@@ -249,7 +274,7 @@ fn possibly_modify_particle(costs: &mut [(Cost, Particle)], node: &mut MctsNode,
     for (_c, particle) in costs.iter() {
         if particle.id >= node_seen_particles.len() || !node_seen_particles[particle.id] {
             for (car, policy) in road.cars.iter_mut().zip(&particle.policies).skip(1) {
-                car.side_policy = Some(policy.clone());
+                car.side_policy.clone_from(policy);
             }
             road.sample_id = Some(particle.id);
             road.save_particle();
@@ -263,7 +288,7 @@ fn run_step<'a>(node: &mut MctsNode<'a>, road: &mut Road) -> Option<Cost> {
     let mcts = &node.params.mcts;
 
     if let Some(ref policy) = node.policy {
-        road.set_ego_policy(policy.clone());
+        road.set_ego_policy(policy);
         // road.reset_car_traces();
         if node.depth < 4 {
             road.reset_car_traces();
@@ -278,6 +303,9 @@ fn run_step<'a>(node: &mut MctsNode<'a>, road: &mut Road) -> Option<Cost> {
             .push((marginal_cost.total(), marginal_cost));
         node.traces
             .append(&mut road.make_traces(node.depth - 1, false));
+        if node.tree_points.is_empty() && tree_overlay_shown() {
+            node.tree_points = road.ego_trace_points();
+        }
 
         return Some(road.cost);
     }
@@ -292,7 +320,7 @@ fn find_and_run_trial(node: &mut MctsNode, road: &mut Road, rng: &mut StdRng) ->
 
     let mut trial_final_cost = None;
     if node.depth + 1 > mcts.search_depth {
-        trial_final_cost = Some(road.cost);
+        trial_final_cost = Some(road.final_cost());
     } else {
         node.get_or_expand_sub_nodes();
         let sub_nodes = node.sub_nodes.as_mut().unwrap();
@@ -374,16 +402,50 @@ fn collect_traces(node: &mut MctsNode, traces: &mut Vec<rvx::Shape>) {
     }
 }
 
+fn collect_tree_nodes<'a, 'b>(node: &'b MctsNode<'a>, nodes: &mut Vec<&'b MctsNode<'a>>) {
+    if node.n_trials > 0 && node.tree_points.len() >= 4 {
+        nodes.push(node);
+    }
+    if let Some(sub_nodes) = node.sub_nodes.as_ref() {
+        for sub_node in sub_nodes.iter() {
+            collect_tree_nodes(sub_node, nodes);
+        }
+    }
+}
+
+// The planning tree, drawn along where each node's first trial took the ego:
+// from green for the lowest expected cost to red for the highest, and wider the more visited
+fn tree_overlay(root: &MctsNode) -> Vec<rvx::Shape> {
+    let mut nodes = Vec::new();
+    collect_tree_nodes(root, &mut nodes);
+    let costs = nodes
+        .iter()
+        .map(|n| n.expected_cost.map_or(0.0, |c| c.total()))
+        .collect_vec();
+    let min_cost = costs.iter().copied().fold(f64::MAX, f64::min);
+    let max_cost = costs.iter().copied().fold(f64::MIN, f64::max);
+    let cost_range = (max_cost - min_cost).max(1e-9);
+
+    nodes
+        .iter()
+        .zip(costs)
+        .map(|(node, cost)| {
+            let frac = ((cost - min_cost) / cost_range) as f32;
+            let color = RvxColor::rgba(frac, 1.0 - frac, 0.0, 0.7);
+            let width = 1.0 + 12.0 * node.n_trials as f64 / root.n_trials.max(1) as f64;
+            Rvx::lines(&node.tree_points, width).color(color)
+        })
+        .collect()
+}
+
 fn print_report(node: &MctsNode) {
     if node.n_trials > 0 {
-        for _ in 0..node.depth {
-            eprint!("    ");
-        }
+        let indent = "    ".repeat(node.depth as usize);
         let policy_id = node.policy.as_ref().map(|p| p.policy_id());
         let expected_score = node.expected_cost.unwrap();
         let score = expected_score.total();
-        eprintln_f!(
-            "n_trials: {node.n_trials}, policy: {policy_id:?}, score: {score:.2}, cost: {expected_score=:.2?}"
+        debug_f!(
+            "{indent}n_trials: {node.n_trials}, policy: {policy_id:?}, score: {score:.2}, cost: {expected_score=:.2?}"
         );
     }
 
@@ -394,28 +456,22 @@ fn print_report(node: &MctsNode) {
     }
 }
 
-pub fn mcts_choose_policy(
-    params: &Parameters,
-    true_road: &Road,
-    rng: &mut StdRng,
-) -> (Option<SidePolicy>, Vec<rvx::Shape>) {
-    let mut params = params.clone();
-    if let Some(total_forward_t) = params.mcts.total_forward_t {
-        params.mcts.layer_t = total_forward_t / params.mcts.search_depth as f64;
-    }
-    let params = &params;
+// The root's choices after a search: each policy with its number of trials and expected cost
+struct RootSearch {
+    choices: Vec<(SidePolicy, usize, Option<Cost>)>,
+    traces: Vec<rvx::Shape>,
+}
 
+fn search_tree(params: &Parameters, true_road: &Road, rng: &mut StdRng, debug: bool) -> RootSearch {
+    let policy_choices = make_policy_choices(params);
     let mut roads = road_set_for_scenario(
         params,
         true_road,
         rng,
         (params.mcts.samples_n as f64 * 1.2).ceil() as usize,
+        &params.mcts.prediction,
     );
 
-    let policy_choices = make_policy_choices(params);
-    let debug = true_road.debug
-        && true_road.timesteps + params.debug_steps_before >= params.max_steps as usize;
-
     let mut node = MctsNode::new(params, &policy_choices, None, 0);
     node.get_or_expand_sub_nodes();
 
@@ -425,6 +481,7 @@ pub fn mcts_choose_policy(
         road.sample_id = Some(i);
         road.save_particle();
         find_and_run_trial(&mut node, &mut road, rng);
+        road_arena::recycle_road(road);
 
         i += 1;
         if i >= params.mcts.samples_n {
@@ -445,14 +502,158 @@ pub fn mcts_choose_policy(
         }
     }
 
-    let best_policy = node.get_best_policy_by_cost().cloned();
+    roads.recycle();
+
+    let choices = node
+        .sub_nodes
+        .as_ref()
+        .unwrap()
+        .iter()
+        .map(|n| (n.policy.clone().unwrap(), n.n_trials, n.expected_cost))
+        .collect();
 
     let mut traces = Vec::new();
-    collect_traces(&mut node, &mut traces);
+    if tree_overlay_shown() {
+        traces = tree_overlay(&node);
+    } else {
+        collect_traces(&mut node, &mut traces);
+    }
 
     if debug && params.policy_report_debug {
         print_report(&node);
     }
 
-    (best_policy, traces)
+    RootSearch { choices, traces }
+}
+
+// Root parallelization: each choice's expected cost averaged over the trees by their trials
+fn merge_searches(searches: Vec<RootSearch>) -> RootSearch {
+    let mut searches = searches.into_iter();
+    let mut merged = searches.next().unwrap();
+    for search in searches {
+        for ((_, n, cost), (_, other_n, other_cost)) in
+            merged.choices.iter_mut().zip(search.choices)
+        {
+            *cost = match (*cost, other_cost) {
+                (Some(cost), Some(other_cost)) => {
+                    let total_n = (*n + other_n) as f64;
+                    Some(cost * (*n as f64 / total_n) + other_cost * (other_n as f64 / total_n))
+                }
+                (cost, other_cost) => cost.or(other_cost),
+            };
+            *n += other_n;
+        }
+        merged.traces.extend(search.traces);
+    }
+    merged
+}
+
+pub fn mcts_choose_policy(
+    params: &Parameters,
+    true_road: &Road,
+    rng: &mut StdRng,
+) -> (Option<SidePolicy>, Vec<rvx::Shape>) {
+    let mut params = params.clone();
+    if let Some(total_forward_t) = params.mcts.total_forward_t {
+        params.mcts.layer_t = total_forward_t / params.mcts.search_depth as f64;
+    }
+    let params = &params;
+
+    let debug = true_road.debug
+        && true_road.timesteps + params.debug_steps_before >= params.max_steps as usize;
+
+    let n_trees = if params.parallel_rollouts {
+        params.mcts.parallel_trees.max(1)
+    } else {
+        1
+    };
+    let search = if n_trees == 1 {
+        search_tree(params, true_road, rng, debug)
+    } else {
+        // each tree splits off its own RNG and share of the samples
+        let mut tree_params = params.clone();
+        tree_params.mcts.samples_n = params.mcts.samples_n.div_ceil(n_trees);
+        let seeds = (0..n_trees).map(|_| rng.gen::<u64>()).collect_vec();
+        let overlay = tree_overlay_shown();
+        let searches = road_arena::map_rollouts(params, &seeds, |&seed| {
+            set_tree_overlay(overlay);
+            let mut rng = StdRng::seed_from_u64(seed);
+            search_tree(&tree_params, true_road, &mut rng, debug)
+        });
+        merge_searches(searches)
+    };
+
+    let best_policy = search
+        .choices
+        .iter()
+        .min_by(|(_, _, a), (_, _, b)| {
+            let cost_a = a.map_or(f64::MAX, |c| c.total());
+            let cost_b = b.map_or(f64::MAX, |c| c.total());
+            cost_a.partial_cmp(&cost_b).unwrap()
+        })
+        .map(|(policy, _, _)| policy.clone());
+    // the root's choices, by the ones the trials got to
+    for (policy, _, cost) in search.choices.iter() {
+        if let Some(cost) = cost {
+            record_candidate(policy.policy_id(), cost.total());
+        }
+    }
+    if let Some(policy) = &best_policy {
+        choose_candidate(policy.policy_id());
+    }
+
+    (best_policy, search.traces)
+}
+
+// MCPTDM, as the method mcts
+pub struct MctsPlanner;
+
+impl EgoPlanner for MctsPlanner {
+    fn plan(&mut self, road: &Road, rng: &mut StdRng) -> (Option<SidePolicy>, Vec<rvx::Shape>) {
+        mcts_choose_policy(&road.params, road, rng)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::new_state;
+
+    #[test]
+    fn test_parallel_trees_choose_the_same_anywhere() {
+        // the trees draw from their own RNGs, so they search alike whichever threads they run on
+        let mut params = Parameters::new().unwrap();
+        params.method = "mcts".to_owned();
+        params.n_cars = 10;
+        params.mcts.samples_n = 16;
+        params.mcts.parallel_trees = 2;
+        params.parallel_rollouts = true;
+        let mut state = new_state(Arc::new(params.clone())).unwrap();
+        for _ in 0..100 {
+            state.update(params.physics_dt).unwrap();
+        }
+        let road = &state.road;
+
+        let choose = || {
+            let mut rng = StdRng::seed_from_u64(0);
+            let plan = crate::choose_policy(&road.params, road, &mut rng).unwrap();
+            (
+                plan.policy,
+                plan.traces.len(),
+                plan.rollouts,
+                plan.candidates,
+            )
+        };
+        let chosen = choose();
+        assert!(chosen.0.is_some());
+        assert!(!chosen.3.is_empty());
+        // where map_rollouts runs the trees in turn
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .unwrap();
+        assert_eq!(pool.install(choose), chosen);
+    }
 }
@@ -0,0 +1,191 @@
+use std::{path::Path, sync::Arc};
+
+use crate::{
+    arg_parameters::Parameters, headless_params, load_road_geometry, new_state, road::Road,
+    scenario_file::ScenarioFile, sim_error::SimError,
+};
+
+// A crash a replan away is usually already unavoidable, so a minimized run starts at least
+// this long before it, to show what the ego chose leading up to it
+const MIN_LEAD_TIME: f64 = 1.0;
+// and runs this much past it, since a scenario file's cars don't carry over exactly
+const CRASH_MARGIN_TIME: f64 = 2.0;
+
+// A smaller run that crashes like the one it came from
+pub struct Minimized {
+    pub scenario: ScenarioFile,
+    // the original run's timesteps where the minimized run starts, and where it crashed
+    pub start_step: u32,
+    pub crash_step: u32,
+    // for running the scenario file, up to its crash
+    pub n_cars: usize,
+    pub max_steps: u32,
+    // the original run's road at start_step, with all of its cars
+    pub snapshot: Road,
+}
+
+// Runs params until the ego crashes, or up to max_steps, giving the timesteps when it crashed
+// and the road at each of snapshot_steps that came before
+fn run_until_crash(
+    params: &Parameters,
+    snapshot_steps: &[u32],
+) -> Result<(Option<u32>, Vec<Road>), SimError> {
+    let mut state = new_state(Arc::new(params.clone()))?;
+    let mut snapshots = Vec::new();
+    while state.timesteps < params.max_steps {
+        if snapshot_steps.contains(&state.timesteps) {
+            // with a belief of its own, which the run goes on updating
+            let mut snapshot = state.road.clone();
+            snapshot.belief = snapshot.belief.as_deref().cloned().map(Arc::new);
+            snapshots.push(snapshot);
+        }
+        state.update(params.physics_dt)?;
+        if state.road.cars[0].crashed {
+            return Ok((Some(state.timesteps), snapshots));
+        }
+    }
+    Ok((None, snapshots))
+}
+
+fn scenario_params(params: &Parameters, scenario: &ScenarioFile, path: &Path) -> Parameters {
+    let mut params = params.clone();
+    params.scenario_file = path.to_string_lossy().into_owned();
+    params.named_scenario = String::new();
+    params.spawn.open_boundary = false;
+    params.n_cars = scenario.n_cars();
+    params
+}
+
+// the steps to the ego's crash from the start of scenario, if it does within max_steps
+fn scenario_crash(
+    params: &Parameters,
+    scenario: &ScenarioFile,
+    max_steps: u32,
+    path: &Path,
+) -> Result<Option<u32>, SimError> {
+    scenario.save(path).map_err(|e| SimError::Load {
+        path: path.display().to_string(),
+        message: e.to_string(),
+    })?;
+    let mut params = scenario_params(params, scenario, path);
+    params.max_steps = max_steps;
+    Ok(run_until_crash(&params, &[])?.0)
+}
+
+// Searches for a smaller run where the ego still crashes: from the latest start that still
+// does (its cars from a snapshot of the run as a scenario file), with as few of those cars
+// as still crash, and only up to the crash. Every candidate is checked by running it, with
+// the scenario files written to scratch_path.
+pub fn minimize(params: Parameters, scratch_path: &Path) -> Result<Minimized, SimError> {
+    let mut params = headless_params(params);
+    load_road_geometry(&mut params)?;
+    let crash_step = run_until_crash(&params, &[])?.0.ok_or(SimError::NoCrash {
+        max_steps: params.max_steps,
+    })?;
+
+    // the latest start first, then twice the lead time each time, back to the first step
+    let steps_per_second = (1.0 / params.physics_dt).round() as u32;
+    let mut lead_steps = (MIN_LEAD_TIME * steps_per_second as f64) as u32;
+    let mut start_steps = Vec::new();
+    loop {
+        let start_step = crash_step.saturating_sub(lead_steps);
+        start_steps.push(start_step);
+        if start_step == 0 {
+            break;
+        }
+        lead_steps *= 2;
+    }
+    let (_, snapshots) = run_until_crash(&params, &start_steps)?;
+    let margin_steps = (CRASH_MARGIN_TIME * steps_per_second as f64) as u32;
+
+    let mut found = None;
+    for (&start_step, snapshot) in start_steps.iter().zip(snapshots) {
+        let scenario = ScenarioFile::from_road(&snapshot);
+        let max_steps = crash_step - start_step + margin_steps;
+        if let Some(steps) = scenario_crash(&params, &scenario, max_steps, scratch_path)? {
+            found = Some((start_step, snapshot, scenario, max_steps, steps));
+            break;
+        }
+    }
+    let (start_step, snapshot, mut scenario, max_steps, mut steps) =
+        found.ok_or(SimError::NotReproduced { crash_step })?;
+
+    // then without each car in turn, the farthest from the ego first
+    let ego_x = snapshot.cars[0].x();
+    let by_distance = |x: f64| -((x - ego_x).abs() * 1000.0) as i64;
+    scenario.cars.sort_by_key(|c| by_distance(c.x));
+    scenario.obstacles.sort_by_key(|o| by_distance(o.x));
+    let mut car_i = 0;
+    while car_i < scenario.cars.len() {
+        let car = scenario.cars.remove(car_i);
+        match scenario_crash(&params, &scenario, max_steps, scratch_path)? {
+            Some(s) => steps = s,
+            None => {
+                scenario.cars.insert(car_i, car);
+                car_i += 1;
+            }
+        }
+    }
+    let mut obstacle_i = 0;
+    while obstacle_i < scenario.obstacles.len() {
+        let obstacle = scenario.obstacles.remove(obstacle_i);
+        match scenario_crash(&params, &scenario, max_steps, scratch_path)? {
+            Some(s) => steps = s,
+            None => {
+                scenario.obstacles.insert(obstacle_i, obstacle);
+                obstacle_i += 1;
+            }
+        }
+    }
+    let _ = std::fs::remove_file(scratch_path);
+
+    Ok(Minimized {
+        n_cars: scenario.n_cars(),
+        scenario,
+        start_step,
+        crash_step,
+        max_steps: steps,
+        snapshot,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_minimize() {
+        // the ego speeding into a stopped car, with other cars around that don't matter
+        let dir = std::env::temp_dir();
+        let path = dir.join("test_minimize_scenario.yaml");
+        std::fs::write(
+            &path,
+            "ego: { lane: 0, x: 0.0, vel: 30.0 }
+obstacles:
+  - { lane: 0, x: 10.0 }
+",
+        )
+        .unwrap();
+        let mut params = Parameters::new().unwrap();
+        params.method = "fixed".to_owned();
+        params.n_cars = 4;
+        params.max_steps = 200;
+        params.scenario_file = path.to_string_lossy().into_owned();
+
+        let scratch_path = dir.join("test_minimize_scratch.yaml");
+        let minimized = minimize(params.clone(), &scratch_path).unwrap();
+        assert_eq!(minimized.start_step, 0);
+        assert_eq!(minimized.snapshot.cars.len(), 5);
+        assert_eq!(minimized.n_cars, 1);
+        assert_eq!(minimized.scenario.obstacles.len(), 1);
+        assert!(minimized.max_steps <= minimized.crash_step);
+
+        // and a run that doesn't crash has nothing to minimize
+        params.scenario_file = String::new();
+        params.max_steps = 5;
+        assert_eq!(
+            minimize(params, &scratch_path).err(),
+            Some(SimError::NoCrash { max_steps: 5 })
+        );
+    }
+}
@@ -0,0 +1,181 @@
+use rand::{prelude::StdRng, Rng};
+use serde::Serialize;
+
+use crate::{
+    arg_parameters::Parameters,
+    car::Car,
+    evaluate::{evaluate_all, Evaluation},
+    mpdm::make_obstacle_vehicle_policy_choices,
+    policy_registry::{shared_policies, PolicyRole},
+    side_policies::{SidePolicy, SidePolicyTrait},
+};
+
+// A random obstacle car as it really drives under mismatch, once it has spawned: with its
+// preferences shifted, its policy's lane changes stretched, or now and then a novel policy
+pub fn mismatch_car(params: &Parameters, car: &mut Car, rng: &mut StdRng) {
+    let mismatch = &params.mismatch;
+    if !mismatch.enabled {
+        return;
+    }
+    car.preferred_accel *= mismatch.accel_scale;
+    car.preferred_follow_time = (car.preferred_follow_time + mismatch.follow_time_offset).max(0.0);
+    car.side_policy = if draws_novel(params, rng) {
+        draw(params, PolicyRole::Novel, rng)
+    } else {
+        // the same policy, which has the same id among the mismatched ones
+        let policies = shared_policies(params, PolicyRole::Mismatched);
+        policies[car.side_policy.policy_id() as usize].clone()
+    };
+}
+
+fn draws_novel(params: &Parameters, rng: &mut StdRng) -> bool {
+    let fraction = params.mismatch.novel_fraction;
+    fraction > 0.0 && rng.gen_bool(fraction.min(1.0))
+}
+
+fn draw(params: &Parameters, role: PolicyRole, rng: &mut StdRng) -> SidePolicy {
+    let policies = shared_policies(params, role);
+    policies[rng.gen_range(0..policies.len())].clone()
+}
+
+// An obstacle car's new policy when it randomly changes: any of the obstacle cars' policies,
+// or under mismatch, the mismatched ones or novel_fraction of the time the novel ones
+pub fn draw_obstacle_policy(params: &Parameters, rng: &mut StdRng) -> SidePolicy {
+    if !params.mismatch.enabled {
+        let policies = make_obstacle_vehicle_policy_choices(params);
+        return policies[rng.gen_range(0..policies.len())].clone();
+    }
+    if draws_novel(params, rng) {
+        draw(params, PolicyRole::Novel, rng)
+    } else {
+        draw(params, PolicyRole::Mismatched, rng)
+    }
+}
+
+// A planner configuration over the same seeds with the obstacle cars as the planners
+// assume them and with the mismatch, and how much worse it did with the mismatch
+#[derive(Clone, Debug, Serialize)]
+pub struct StressTest {
+    pub matched: Evaluation,
+    pub mismatched: Evaluation,
+    // the mismatched mean minus the matched one, for each of the table's metrics
+    pub degradation: Vec<(&'static str, f64)>,
+}
+
+impl StressTest {
+    fn new(matched: Evaluation, mismatched: Evaluation) -> Self {
+        let degradation = matched
+            .columns()
+            .iter()
+            .zip(mismatched.columns().iter())
+            .map(|((name, matched), (_, mismatched))| (*name, mismatched.mean - matched.mean))
+            .collect();
+        Self {
+            matched,
+            mismatched,
+            degradation,
+        }
+    }
+}
+
+// Evaluates each of the configurations both ways, all in parallel
+pub fn stress_test_all(configs: &[Parameters], n_episodes: usize) -> Vec<StressTest> {
+    let mut runs = Vec::new();
+    for params in configs {
+        let name = params.scenario_name.clone().unwrap_or_default();
+        for enabled in [false, true] {
+            let mut run = params.clone();
+            run.mismatch.enabled = enabled;
+            let label = if enabled { "mismatched" } else { "matched" };
+            run.scenario_name = Some(format!("{}{}", label, name));
+            runs.push(run);
+        }
+    }
+    let mut evaluations = evaluate_all(&runs, n_episodes).into_iter();
+    let mut tests = Vec::new();
+    while let (Some(matched), Some(mismatched)) = (evaluations.next(), evaluations.next()) {
+        tests.push(StressTest::new(matched, mismatched));
+    }
+    tests
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::road::Road;
+
+    #[test]
+    fn test_mismatch_car() {
+        let mut params = Parameters::new().unwrap();
+        let mut rng = StdRng::seed_from_u64(0);
+        let matched = Car::random_new(&params, 1, &mut StdRng::seed_from_u64(0));
+
+        params.mismatch.enabled = true;
+        params.mismatch.accel_scale = 2.0;
+        params.mismatch.follow_time_offset = 1.0;
+        params.mismatch.lane_change_time_scale = 3.0;
+        let car = Car::random_new(&params, 1, &mut rng);
+        assert_eq!(car.preferred_accel, 2.0 * matched.preferred_accel);
+        assert_eq!(
+            car.preferred_follow_time,
+            matched.preferred_follow_time + 1.0
+        );
+        assert_eq!(car.side_policy.policy_id(), matched.side_policy.policy_id());
+        let transition_time = |p: &SidePolicy| match p {
+            SidePolicy::LaneChangePolicy(p) => p.transition_time(),
+            p => panic!("{:?} should be a lane change", p),
+        };
+        assert_eq!(
+            transition_time(&car.side_policy),
+            3.0 * transition_time(&matched.side_policy)
+        );
+
+        // and only novel policies, all of which the belief lacks
+        params.mismatch.novel_fraction = 1.0;
+        let belief = shared_policies(&params, PolicyRole::Belief);
+        for _ in 0..20 {
+            let policy = draw_obstacle_policy(&params, &mut rng);
+            assert!(!belief.iter().any(|p| p == &policy));
+            if let SidePolicy::LaneChangePolicy(p) = &policy {
+                assert!(p.abort_if_closed());
+            }
+        }
+    }
+
+    #[test]
+    fn test_novel_policies_drive() {
+        let mut params = Parameters::new().unwrap();
+        params.mismatch.enabled = true;
+        params.mismatch.novel_fraction = 1.0;
+        let params = Arc::new(params);
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut road = Road::new(params.clone());
+        for _ in 0..8 {
+            road.add_random_car(&mut rng).unwrap();
+        }
+        road.init_belief();
+        let (mut errors, mut checks) = (0, 0);
+        for step in 0..400 {
+            if step % 100 == 0 {
+                for car in road.cars[1..].iter_mut() {
+                    car.side_policy = draw_obstacle_policy(&params, &mut rng);
+                }
+            }
+            road.update_belief(&mut rng).unwrap();
+            road.update(params.physics_dt);
+            let (e, c) = road.belief.as_ref().unwrap().lane_errors(&road);
+            errors += e;
+            checks += c;
+        }
+        // the belief still has a lane for the cars, even if not their real one
+        assert!(checks > 0 && errors <= checks);
+        assert!(road.cars[1..].iter().all(|c| matches!(
+            c.side_policy,
+            SidePolicy::NudgePolicy(_) | SidePolicy::LaneChangePolicy(_)
+        )));
+    }
+}
@@ -0,0 +1,222 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    idm_control::car_idm_accel,
+    lane_change_policy::{LaneChangePolicy, LongitudinalPolicy},
+    road::Road,
+    side_policies::{SidePolicy, SidePolicyTrait, Trajectory},
+};
+
+// how often a MOBIL car reconsiders its lane
+const DECISION_DT: f64 = 0.5;
+// how close to its target lane's center a car has to be before it decides again
+const IN_LANE_DY: f64 = 0.5;
+
+// MOBIL (Kesting, Treiber, and Helbing 2007) lane changes for the obstacle cars: a car changes
+// into a neighboring lane when its own gain in acceleration, plus politeness times the gains of
+// its followers in the old and new lanes, is over the advantage threshold, and as long as
+// the new follower wouldn't have to brake harder than safe_decel.
+// The accelerations are the IDM's, with params.idm (and the cars' own desired gaps),
+// whatever the cars' forward control.
+#[derive(Clone, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub struct MobilPolicy {
+    policy_id: u32,
+    target_lane_i: Option<i32>,
+    lane_change: Option<LaneChangePolicy>,
+    next_decision_t: f64,
+}
+
+impl std::fmt::Debug for MobilPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "MOBIL, lane {:?}", self.target_lane_i)
+    }
+}
+
+// a neighboring car as (gap, car_i)
+type Neighbor = Option<(f64, usize)>;
+
+// the closest cars ahead of and behind car_i in lane_i,
+// where a car alongside counts as ahead with no gap
+fn neighbors_in_lane(road: &Road, car_i: usize, lane_i: i32) -> (Neighbor, Neighbor) {
+    let car = &road.cars[car_i];
+    let front = car.x();
+    let back = car.x() - car.length;
+    let mut ahead: Neighbor = None;
+    let mut behind: Neighbor = None;
+    for (i, c) in road.cars.iter().enumerate() {
+        if i == car_i || c.current_lane() != lane_i {
+            continue;
+        }
+        let gap = if c.x() <= back {
+            let gap = back - c.x();
+            if behind.map_or(true, |(g, _)| gap < g) {
+                behind = Some((gap, i));
+            }
+            continue;
+        } else {
+            (c.x() - c.length - front).max(0.0)
+        };
+        if ahead.map_or(true, |(g, _)| gap < g) {
+            ahead = Some((gap, i));
+        }
+    }
+    (ahead, behind)
+}
+
+impl MobilPolicy {
+    pub fn new(policy_id: u32) -> Self {
+        Self {
+            policy_id,
+            target_lane_i: None,
+            lane_change: None,
+            next_decision_t: 0.0,
+        }
+    }
+
+    fn accel(road: &Road, car_i: usize, ahead: Neighbor) -> f64 {
+        car_idm_accel(
+            road,
+            car_i,
+            ahead.map(|(gap, ahead_i)| (gap, road.cars[ahead_i].vel)),
+        )
+    }
+
+    // MOBIL's incentive for car_i to change into lane_i, or None if it wouldn't be safe
+    fn incentive(road: &Road, car_i: usize, lane_i: i32) -> Option<f64> {
+        let mparams = &road.params.mobil;
+        let length = road.cars[car_i].length;
+        let (old_ahead, old_behind) =
+            neighbors_in_lane(road, car_i, road.cars[car_i].current_lane());
+        let (new_ahead, new_behind) = neighbors_in_lane(road, car_i, lane_i);
+        if new_ahead.map_or(false, |(gap, _)| gap <= 0.0) {
+            return None;
+        }
+
+        let own_gain = Self::accel(road, car_i, new_ahead) - Self::accel(road, car_i, old_ahead);
+
+        let new_follower_gain = match new_behind {
+            Some((gap, follower_i)) => {
+                let accel_after = Self::accel(road, follower_i, Some((gap, car_i)));
+                if accel_after < -mparams.safe_decel {
+                    return None;
+                }
+                let ahead_before =
+                    new_ahead.map(|(ahead_gap, ahead_i)| (gap + length + ahead_gap, ahead_i));
+                accel_after - Self::accel(road, follower_i, ahead_before)
+            }
+            None => 0.0,
+        };
+
+        let old_follower_gain = match old_behind {
+            Some((gap, follower_i)) => {
+                let ahead_after =
+                    old_ahead.map(|(ahead_gap, ahead_i)| (gap + length + ahead_gap, ahead_i));
+                Self::accel(road, follower_i, ahead_after)
+                    - Self::accel(road, follower_i, Some((gap, car_i)))
+            }
+            None => 0.0,
+        };
+
+        let politeness = road.cars[car_i]
+            .traits
+            .map_or(mparams.politeness, |t| t.politeness);
+        Some(own_gain + politeness * (new_follower_gain + old_follower_gain))
+    }
+
+    fn decide(&mut self, road: &Road, car_i: usize) {
+        let car = &road.cars[car_i];
+        let current_lane_i = car.current_lane();
+        if let Some(target_lane_i) = self.target_lane_i {
+            if road.t < self.next_decision_t
+                || (car.y() - Road::get_lane_y(target_lane_i)).abs() > IN_LANE_DY
+            {
+                return;
+            }
+        }
+        self.next_decision_t = road.t + DECISION_DT;
+
+        let mut best = (current_lane_i, road.params.mobil.advantage_threshold);
+        for &lane_i in [current_lane_i - 1, current_lane_i + 1].iter() {
+            if !road.lane_exists(lane_i, car.x()) || road.usable_lane(car_i, lane_i) != lane_i {
+                continue;
+            }
+            if let Some(incentive) = Self::incentive(road, car_i, lane_i) {
+                if incentive > best.1 {
+                    best = (lane_i, incentive);
+                }
+            }
+        }
+
+        if self.target_lane_i != Some(best.0) {
+            self.target_lane_i = Some(best.0);
+            self.lane_change = Some(LaneChangePolicy::new(
+                self.policy_id,
+                Some(best.0),
+                road.params.lane_change_time,
+                false,
+                LongitudinalPolicy::Maintain,
+            ));
+        }
+    }
+}
+
+impl SidePolicyTrait for MobilPolicy {
+    fn choose_target_lane(&mut self, road: &Road, car_i: usize) -> i32 {
+        self.decide(road, car_i);
+        road.usable_lane(car_i, self.target_lane_i.unwrap())
+    }
+
+    fn choose_trajectory(&mut self, road: &Road, car_i: usize, traj: &mut Trajectory) {
+        self.decide(road, car_i);
+        self.lane_change
+            .as_mut()
+            .unwrap()
+            .choose_trajectory(road, car_i, traj);
+    }
+
+    fn policy_id(&self) -> u32 {
+        self.policy_id
+    }
+
+    fn operating_policy(&self) -> SidePolicy {
+        SidePolicy::MobilPolicy(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{arg_parameters::Parameters, car::Car};
+
+    #[test]
+    fn test_mobil_passes_slow_car() {
+        let params = Parameters::new().unwrap();
+        let mut road = Road::new(Arc::new(params.clone()));
+        road.cars[0].set_x(-100.0);
+
+        // stuck behind a slow car, with the other lane empty
+        let mut car = Car::new(&params, 1, 0);
+        car.vel = 12.0;
+        car.target_vel = 15.0;
+        road.cars.push(car);
+        let mut slow = Car::new(&params, 2, 0);
+        slow.set_x(20.0);
+        slow.vel = 5.0;
+        slow.target_vel = 5.0;
+        road.cars.push(slow);
+
+        let mut policy = MobilPolicy::new(0);
+        assert_eq!(policy.choose_target_lane(&road, 1), 1);
+
+        // but not with a fast car coming up right behind in the other lane
+        let mut fast = Car::new(&params, 3, 1);
+        fast.set_x(-6.0);
+        fast.vel = 15.0;
+        fast.target_vel = 15.0;
+        road.cars.push(fast);
+        let mut policy = MobilPolicy::new(0);
+        assert_eq!(policy.choose_target_lane(&road, 1), 0);
+    }
+}
@@ -1,110 +1,142 @@
+use std::{rc::Rc, sync::Arc};
+
 use rand::prelude::StdRng;
 
 use crate::{
     arg_parameters::Parameters,
+    belief::Belief,
+    candidates::{choose_candidate, clear_candidates, record_candidate},
     cost::Cost,
-    lane_change_policy::{LaneChangePolicy, LongitudinalPolicy},
+    planner::EgoPlanner,
+    policy_registry::{shared_policies, PolicyRole},
     road::Road,
+    road_arena::map_rollouts,
     road_set::RoadSet,
     road_set_for_scenario,
     side_policies::{SidePolicy, SidePolicyTrait},
 };
 
-pub fn make_obstacle_vehicle_policy_choices(params: &Parameters) -> Vec<SidePolicy> {
-    let mut policy_choices = Vec::new();
-
-    for lane_i in [0, 1] {
-        for long_policy in [LongitudinalPolicy::Maintain, LongitudinalPolicy::Accelerate] {
-            policy_choices.push(SidePolicy::LaneChangePolicy(LaneChangePolicy::new(
-                policy_choices.len() as u32,
-                Some(lane_i),
-                params.lane_change_time,
-                true,
-                long_policy,
-            )));
-        }
-    }
+// the policies each role chooses between, see policy_registry.rs
+pub fn make_obstacle_vehicle_policy_choices(params: &Parameters) -> Rc<[SidePolicy]> {
+    shared_policies(params, PolicyRole::Obstacle)
+}
 
-    policy_choices.push(SidePolicy::LaneChangePolicy(LaneChangePolicy::new(
-        policy_choices.len() as u32,
-        None,
-        params.lane_change_time,
-        true,
-        LongitudinalPolicy::Decelerate,
-    )));
+pub fn make_obstacle_vehicle_policy_belief_states(params: &Parameters) -> Rc<[SidePolicy]> {
+    shared_policies(params, PolicyRole::Belief)
+}
 
-    policy_choices
+pub fn make_policy_choices(params: &Parameters) -> Rc<[SidePolicy]> {
+    shared_policies(params, PolicyRole::Ego)
 }
 
-pub fn make_obstacle_vehicle_policy_belief_states(params: &Parameters) -> Vec<SidePolicy> {
-    let mut policy_choices = Vec::new();
-
-    for lane_i in [0, 1] {
-        for long_policy in [LongitudinalPolicy::Maintain, LongitudinalPolicy::Accelerate] {
-            for wait_for_clear in [false, true] {
-                policy_choices.push(SidePolicy::LaneChangePolicy(LaneChangePolicy::new(
-                    policy_choices.len() as u32,
-                    Some(lane_i),
-                    params.lane_change_time,
-                    wait_for_clear,
-                    long_policy,
-                )));
-            }
-        }
-    }
+fn evaluate_policy(
+    params: &Parameters,
+    roads: &RoadSet,
+    policy: &SidePolicy,
+) -> (Cost, Vec<rvx::Shape>) {
+    let mut roads = roads.arena_clone();
+    roads.set_ego_policy(policy);
 
-    policy_choices.push(SidePolicy::LaneChangePolicy(LaneChangePolicy::new(
-        policy_choices.len() as u32,
-        None,
-        params.lane_change_time,
-        true,
-        LongitudinalPolicy::Decelerate,
-    )));
+    let mpdm = &params.mpdm;
+    roads.reset_car_traces();
+    roads.take_update_steps(mpdm.forward_t, mpdm.dt);
 
-    policy_choices
+    let result = (roads.cost(), roads.make_traces(0, false));
+    roads.recycle();
+    result
 }
 
-pub fn make_policy_choices(params: &Parameters) -> Vec<SidePolicy> {
-    let mut policy_choices = Vec::new();
+// The ego's policy with the lowest cost over the samples, and all of their traces
+fn elect_ego_policy(
+    params: &Parameters,
+    roads: &RoadSet,
+    debug: bool,
+) -> (Option<SidePolicy>, Vec<rvx::Shape>) {
+    let mut traces = Vec::new();
+    let policy_choices = make_policy_choices(params);
+    let mut best_cost = Cost::max_value();
+    let mut best_policy = None;
+    let mut best_i = 0;
+    // just this election's, when there are rounds of them
+    clear_candidates();
 
-    let long_policies = vec![LongitudinalPolicy::Maintain, LongitudinalPolicy::Accelerate];
+    let evaluations = map_rollouts(params, &policy_choices, |policy| {
+        evaluate_policy(params, roads, policy)
+    });
+    for (i, (policy, (cost, mut new_traces))) in policy_choices.iter().zip(evaluations).enumerate()
+    {
+        traces.append(&mut new_traces);
+        if debug {
+            debug_f!("{i}: {policy:?}: {:7.2?} = {:7.2}", cost, cost.total());
+        }
+        record_candidate(i as u32, cost.total());
 
-    for &lane_i in &[0, 1] {
-        for &long_policy in long_policies.iter() {
-            policy_choices.push(SidePolicy::LaneChangePolicy(LaneChangePolicy::new(
-                policy_choices.len() as u32,
-                Some(lane_i),
-                params.lane_change_time,
-                false,
-                long_policy,
-            )));
+        if cost < best_cost {
+            best_cost = cost;
+            best_policy = Some(policy.clone());
+            best_i = i;
         }
     }
+    if best_policy.is_some() {
+        choose_candidate(best_i as u32);
+    }
 
-    policy_choices.push(SidePolicy::LaneChangePolicy(LaneChangePolicy::new(
-        policy_choices.len() as u32,
-        None,
-        params.lane_change_time,
-        false,
-        LongitudinalPolicy::Decelerate,
-    )));
+    (best_policy, traces)
+}
 
-    policy_choices
+// A believed car's own cost over the sample's rollout with the given policy
+fn car_cost(params: &Parameters, road: &Road, car_i: usize, policy: &SidePolicy) -> f64 {
+    let mut road = road.clone();
+    road.car_traces = None;
+    road.cars[car_i].side_policy.clone_from(policy);
+    road.take_update_steps_car_cost(params.mpdm.forward_t, params.mpdm.dt, car_i)
 }
 
-fn evaluate_policy(
-    params: &Parameters,
-    roads: &RoadSet,
-    policy: &SidePolicy,
-) -> (Cost, Vec<rvx::Shape>) {
-    let mut roads = roads.clone();
-    roads.set_ego_policy(policy);
+// Given the ego's policy, each believed car in turn re-elects the policy with the lowest cost
+// to itself, among those its belief gives any likelihood (the most likely on ties), and given
+// the cars before it. Returns the (car_i, policy_i) that changed.
+fn elect_obstacle_policies(
+    params: &Arc<Parameters>,
+    belief: &Belief,
+    road: &Road,
+    ego_policy: &SidePolicy,
+) -> Vec<(usize, usize)> {
+    let policies = make_obstacle_vehicle_policy_belief_states(params);
+    // phantom cars have no belief to elect from
+    let n_believed = belief.n_cars().min(road.cars.len());
 
-    let mpdm = &params.mpdm;
-    roads.reset_car_traces();
-    roads.take_update_steps(mpdm.forward_t, mpdm.dt);
+    let mut road = road.clone();
+    road.params = params.clone();
+    road.set_ego_policy(ego_policy);
 
-    (roads.cost(), roads.make_traces(0, false))
+    let mut elected = Vec::new();
+    for car_i in 1..n_believed {
+        if road.cars[car_i].crashed {
+            continue;
+        }
+        let current_policy = road.cars[car_i].side_policy.clone();
+        let current_cost = car_cost(params, &road, car_i, &current_policy);
+        let best = (0..policies.len())
+            .filter(|&i| {
+                policies[i].policy_id() != current_policy.policy_id() && belief.get(car_i, i) > 0.0
+            })
+            .map(|i| (car_cost(params, &road, car_i, &policies[i]), i))
+            .min_by(|(cost_a, a), (cost_b, b)| {
+                cost_a.partial_cmp(cost_b).unwrap().then(
+                    belief
+                        .get(car_i, *b)
+                        .partial_cmp(&belief.get(car_i, *a))
+                        .unwrap(),
+                )
+            });
+        if let Some((cost, policy_i)) = best {
+            if cost < current_cost {
+                road.cars[car_i].side_policy.clone_from(&policies[policy_i]);
+                elected.push((car_i, policy_i));
+            }
+        }
+    }
+    elected
 }
 
 pub fn mpdm_choose_policy(
@@ -112,50 +144,229 @@ pub fn mpdm_choose_policy(
     true_road: &Road,
     rng: &mut StdRng,
 ) -> (Option<SidePolicy>, Vec<rvx::Shape>) {
-    let mut traces = Vec::new();
-    let roads = road_set_for_scenario(params, true_road, rng, params.mpdm.samples_n);
+    let mut roads = road_set_for_scenario(
+        params,
+        true_road,
+        rng,
+        params.mpdm.samples_n,
+        &params.mpdm.prediction,
+    );
     let debug = params.policy_report_debug
         && true_road.debug
         && true_road.timesteps + params.debug_steps_before >= params.max_steps as usize;
     if debug {
-        eprintln!(
+        tracing::debug!(
             "{}: MPDM search policies and costs, starting with policy {}",
             roads.timesteps(),
             roads.ego_policy().policy_id(),
         );
-        eprintln!(
+        tracing::debug!(
             "Starting from base costs: {:7.2?} = {:7.2}",
             roads.cost(),
             roads.cost().total()
         );
     }
 
-    let policy_choices = make_policy_choices(params);
-    let mut best_cost = Cost::max_value();
-    let mut best_policy = None;
+    let (mut best_policy, mut traces) = elect_ego_policy(params, &roads, debug);
 
-    for (i, policy) in policy_choices.into_iter().enumerate() {
-        // if roads.timesteps() >= 2200 && i != 3 {
-        //     continue;
-        // }
-        // if i == 0 || i == 3 {
-        //     continue;
-        // }
+    // Iterative election: the obstacle cars re-elect their policies given the ego's choice,
+    // and the ego re-elects given theirs, until neither changes. Only the belief samples
+    // have policies to elect from.
+    if params.mpdm.election_rounds > 0 && params.mpdm.prediction == "belief" {
+        // where the obstacle cars crash too, for their own costs
+        let mut election_params = params.clone();
+        election_params.only_ego_crashes_in_forward_sims = false;
+        let election_params = Arc::new(election_params);
+        let belief = true_road.belief.as_ref().unwrap();
 
-        let (cost, mut new_traces) = evaluate_policy(params, &roads, &policy);
-        traces.append(&mut new_traces);
-        // eprint!("{:.2} ", cost);
-        // eprintln!("{:?}: {:.2} ", policy, cost);
-        if debug {
-            eprintln_f!("{i}: {policy:?}: {:7.2?} = {:7.2}", cost, cost.total());
-        }
+        for round in 1..=params.mpdm.election_rounds {
+            let ego_policy = match best_policy.as_ref() {
+                Some(policy) => policy,
+                None => break,
+            };
+            let elections = map_rollouts(params, roads.roads(), |road| {
+                elect_obstacle_policies(&election_params, belief, road, ego_policy)
+            });
+            let n_elected: usize = elections.iter().map(|e| e.len()).sum();
+            if debug {
+                debug_f!("Election round {round}: {n_elected} obstacle car policies changed");
+            }
+            if n_elected == 0 {
+                break;
+            }
 
-        if cost < best_cost {
-            best_cost = cost;
-            best_policy = Some(policy);
+            let policies = make_obstacle_vehicle_policy_belief_states(params);
+            for (road, elected) in roads.iter_mut().zip(elections) {
+                for (car_i, policy_i) in elected {
+                    road.cars[car_i].side_policy.clone_from(&policies[policy_i]);
+                }
+            }
+            let (policy, new_traces) = elect_ego_policy(params, &roads, debug);
+            best_policy = policy;
+            traces = new_traces;
         }
     }
-    // eprintln!();
 
     (best_policy, traces)
 }
+
+// MPDM, as the method mpdm
+pub struct MpdmPlanner;
+
+impl EgoPlanner for MpdmPlanner {
+    fn plan(&mut self, road: &Road, rng: &mut StdRng) -> (Option<SidePolicy>, Vec<rvx::Shape>) {
+        mpdm_choose_policy(&road.params, road, rng)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::new_state;
+
+    // Rolls out the sample with the ego's policy, and returns which of the believed cars crash
+    fn crashed_cars(params: &Parameters, road: &Road, n_believed: usize) -> Vec<usize> {
+        let mut road = road.clone();
+        road.car_traces = None;
+        road.take_update_steps(params.mpdm.forward_t, params.mpdm.dt);
+        (1..n_believed).filter(|&i| road.cars[i].crashed).collect()
+    }
+
+    #[test]
+    fn test_f32_rollout_math_choose_alike() {
+        // from the same states and samples, f32 rollout math chooses nearly always the same
+        let mut params = Parameters::new().unwrap();
+        params.method = "mpdm".to_owned();
+        params.n_cars = 10;
+        let mut f32_params = params.clone();
+        f32_params.f32_rollout_math = true;
+        let f32_params = Arc::new(f32_params);
+
+        let mut state = new_state(Arc::new(params.clone())).unwrap();
+        let (mut n_same, mut n_chosen) = (0, 0);
+        while state.timesteps < 400 {
+            state.update(params.physics_dt).unwrap();
+            if state.timesteps % 20 != 0 {
+                continue;
+            }
+            let mut f32_road = state.road.clone();
+            f32_road.params = f32_params.clone();
+            let choose = |road: &Road| {
+                let mut rng = StdRng::seed_from_u64(state.timesteps as u64);
+                let (policy, _) = mpdm_choose_policy(&road.params, road, &mut rng);
+                policy.map(|p| p.policy_id())
+            };
+            n_same += (choose(&state.road) == choose(&f32_road)) as u32;
+            n_chosen += 1;
+        }
+        assert!(n_same * 10 >= n_chosen * 9, "{} of {}", n_same, n_chosen);
+    }
+
+    #[test]
+    fn test_parallel_rollouts_choose_the_same() {
+        let mut params = Parameters::new().unwrap();
+        params.n_cars = 10;
+        let mut state = new_state(Arc::new(params.clone())).unwrap();
+        for _ in 0..100 {
+            state.update(params.physics_dt).unwrap();
+        }
+        let state_road = &state.road;
+
+        for (method, contingency) in [("mpdm", false), ("eudm", false), ("eudm", true)] {
+            let choose = |parallel_rollouts: bool| {
+                let mut params = params.clone();
+                params.method = method.to_owned();
+                params.eudm.contingency = contingency;
+                params.parallel_rollouts = parallel_rollouts;
+                let mut road = state_road.clone();
+                road.params = Arc::new(params);
+                let mut rng = StdRng::seed_from_u64(0);
+                crate::road::collect_trace_lines(true);
+                let plan = crate::choose_policy(&road.params, &road, &mut rng).unwrap();
+                (
+                    plan.policy,
+                    plan.traces.len(),
+                    plan.rollouts,
+                    crate::road::take_trace_lines().len(),
+                    plan.candidates,
+                )
+            };
+            let chosen = choose(true);
+            assert_eq!(chosen, choose(false), "{}", method);
+            assert!(!chosen.4.is_empty());
+            // and from inside a rayon worker, as in a sweep
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(2)
+                .build()
+                .unwrap();
+            assert_eq!(pool.install(|| choose(true)), chosen, "{}", method);
+        }
+        crate::road::collect_trace_lines(false);
+    }
+
+    #[test]
+    fn test_obstacle_election() {
+        use crate::{
+            car::Car, lane_change_policy::LongitudinalPolicy, policy_registry::find_lane_change,
+        };
+
+        let params = Arc::new(Parameters::new().unwrap());
+        let mut road = Road::new(params.clone());
+        road.cars[0].vel = 10.0;
+        let ego_lane = road.cars[0].current_lane();
+        // right beside the ego, and sampled to change into its lane
+        let mut car = Car::new(&params, 1, ego_lane + 1);
+        car.vel = 10.0;
+        let belief_policies = make_obstacle_vehicle_policy_belief_states(&params);
+        car.side_policy = find_lane_change(
+            &belief_policies,
+            Some(ego_lane),
+            LongitudinalPolicy::Maintain,
+        )
+        .unwrap()
+        .clone();
+        road.cars.push(car);
+        road.update_cars_spatial();
+        road.init_belief();
+        let road = road.sim_estimate();
+
+        let ego_policy = find_lane_change(
+            &make_policy_choices(&params),
+            Some(ego_lane),
+            LongitudinalPolicy::Maintain,
+        )
+        .unwrap()
+        .clone();
+        let mut election_params = (*params).clone();
+        election_params.only_ego_crashes_in_forward_sims = false;
+        let election_params = Arc::new(election_params);
+        let belief = road.belief.as_ref().unwrap();
+
+        let elected = elect_obstacle_policies(&election_params, belief, &road, &ego_policy);
+        assert_eq!(elected.len(), 1);
+        let (car_i, policy_i) = elected[0];
+        assert_eq!(car_i, 1);
+
+        // the car's elected policy costs it less and doesn't crash it, given the ego's,
+        // as its sampled one did
+        let mut road = road.clone();
+        road.params = election_params.clone();
+        road.set_ego_policy(&ego_policy);
+        assert_eq!(crashed_cars(&election_params, &road, 2), vec![1]);
+        let sampled_policy = road.cars[1].side_policy.clone();
+        assert!(
+            car_cost(&election_params, &road, 1, &belief_policies[policy_i])
+                < car_cost(&election_params, &road, 1, &sampled_policy)
+        );
+        road.cars[1]
+            .side_policy
+            .clone_from(&belief_policies[policy_i]);
+        assert!(crashed_cars(&election_params, &road, 2).is_empty());
+        // and it's stable, with nothing more to elect
+        assert!(elect_obstacle_policies(&election_params, belief, &road, &ego_policy).is_empty());
+    }
+}
@@ -0,0 +1,96 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    lane_change_policy::{LaneChangePolicy, LongitudinalPolicy},
+    road::Road,
+    side_policies::{SidePolicy, SidePolicyTrait, Trajectory},
+};
+
+// Stays in the current lane but drives offset m from its center (positive toward the higher
+// lanes, to the left), like edging over for room from a car alongside or to see past the car ahead
+#[derive(Clone, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub struct NudgePolicy {
+    offset: f64,
+    lane_keep: LaneChangePolicy,
+}
+
+impl std::fmt::Debug for NudgePolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "nudge {:+.1}, {:?}", self.offset, self.lane_keep)
+    }
+}
+
+impl NudgePolicy {
+    pub fn new(
+        policy_id: u32,
+        offset: f64,
+        transition_time: f64,
+        long_policy: LongitudinalPolicy,
+    ) -> Self {
+        Self {
+            offset,
+            lane_keep: LaneChangePolicy::new(policy_id, None, transition_time, false, long_policy),
+        }
+    }
+
+    pub fn offset(&self) -> f64 {
+        self.offset
+    }
+}
+
+impl SidePolicyTrait for NudgePolicy {
+    fn choose_target_lane(&mut self, road: &Road, car_i: usize) -> i32 {
+        self.lane_keep.choose_target_lane(road, car_i)
+    }
+
+    fn choose_follow_time(&mut self, road: &Road, car_i: usize) -> f64 {
+        self.lane_keep.choose_follow_time(road, car_i)
+    }
+
+    fn choose_vel(&mut self, road: &Road, car_i: usize) -> f64 {
+        self.lane_keep.choose_vel(road, car_i)
+    }
+
+    fn choose_trajectory(&mut self, road: &Road, car_i: usize, traj: &mut Trajectory) {
+        self.lane_keep
+            .lane_keep_trajectory(road, car_i, self.offset, traj)
+    }
+
+    fn policy_id(&self) -> u32 {
+        self.lane_keep.policy_id()
+    }
+
+    fn operating_policy(&self) -> SidePolicy {
+        SidePolicy::NudgePolicy(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::arg_parameters::Parameters;
+
+    #[test]
+    fn test_nudge_stays_in_lane() {
+        let params = Arc::new(Parameters::new().unwrap());
+        let mut road = Road::new(params.clone());
+        road.cars[0].vel = 20.0;
+        let lane_i = road.cars[0].current_lane();
+        let nudge = NudgePolicy::new(
+            0,
+            0.5,
+            params.lane_change_time,
+            LongitudinalPolicy::Maintain,
+        );
+        let mut road = road.sim_estimate();
+        road.set_ego_policy(&SidePolicy::NudgePolicy(nudge));
+        road.take_update_steps(5.0, params.physics_dt);
+
+        let ego = &road.cars[0];
+        assert_eq!(ego.current_lane(), lane_i);
+        assert_eq!(ego.target_lane_i, lane_i);
+        assert!((ego.y() - Road::get_lane_y(lane_i) - 0.5).abs() < 0.1);
+    }
+}
@@ -0,0 +1,163 @@
+use parry2d_f64::{
+    na::Point2,
+    query::{Ray, RayCast},
+};
+use rvx::{Rvx, RvxColor};
+
+use crate::{
+    car::Car, lane_change_policy::LongitudinalPolicy, mpdm::make_obstacle_vehicle_policy_choices,
+    policy_registry::find_lane_change, road::Road,
+};
+
+// how far apart along each lane the planner tries placing phantom cars
+const PHANTOM_SPACING: f64 = 8.0;
+
+fn corners(car: &Car) -> [Point2<f64>; 4] {
+    let pose = car.pose();
+    let (half_length, half_width) = (car.length / 2.0, car.width / 2.0);
+    [
+        pose * Point2::new(half_length, half_width),
+        pose * Point2::new(half_length, -half_width),
+        pose * Point2::new(-half_length, half_width),
+        pose * Point2::new(-half_length, -half_width),
+    ]
+}
+
+// whether any car other than the ego and car_i is in the way from the ego to pt
+fn sight_blocked(road: &Road, from: Point2<f64>, pt: Point2<f64>, car_i: Option<usize>) -> bool {
+    let (low_x, high_x) = (from.x.min(pt.x), from.x.max(pt.x));
+    let ray = Ray::new(from, pt - from);
+    road.cars.iter().enumerate().skip(1).any(|(i, c)| {
+        Some(i) != car_i
+            && c.aabb().maxs.x >= low_x
+            && c.aabb().mins.x <= high_x
+            && c.shape().intersects_ray(&c.pose(), &ray, 1.0)
+    })
+}
+
+// Whether car (car_i on the road, or a car that isn't there yet) is hidden from the ego:
+// every sight line from the ego's center to its corners passes through another car.
+// The sight lines are straight in the road's frame, so this is rough on curves.
+fn hidden(road: &Road, car: &Car, car_i: Option<usize>) -> bool {
+    let from = Point2::from(road.cars[0].pose().translation.vector);
+    corners(car)
+        .iter()
+        .all(|&pt| sight_blocked(road, from, pt, car_i))
+}
+
+// by car_i, whether the ego can't see the car for the others in the way
+pub fn occluded_cars(road: &Road) -> Vec<bool> {
+    road.cars
+        .iter()
+        .enumerate()
+        .map(|(car_i, car)| car_i != 0 && hidden(road, car, Some(car_i)))
+        .collect()
+}
+
+// Phantom cars in the spots near the ego it can't see, up to occlusion.phantoms of the closest,
+// so the planner allows for someone being there. They keep to their lanes at the ego's speed.
+pub fn add_phantom_cars(road: &mut Road) {
+    let params = road.params.clone();
+    let occlusion = &params.occlusion;
+    let policies = make_obstacle_vehicle_policy_choices(&params);
+    let ego = &road.cars[0];
+    let (ego_x, ego_vel) = (ego.x(), ego.vel);
+
+    let mut spots = Vec::new();
+    let n_spots = (occlusion.phantom_range / PHANTOM_SPACING) as i32;
+    for lane_i in 0..params.n_lanes {
+        for k in -n_spots..=n_spots {
+            let x = ego_x + k as f64 * PHANTOM_SPACING;
+            if road.lane_exists(lane_i, x) {
+                spots.push((lane_i, x));
+            }
+        }
+    }
+    spots.sort_by(|a, b| {
+        (a.1 - ego_x)
+            .abs()
+            .partial_cmp(&(b.1 - ego_x).abs())
+            .unwrap()
+    });
+
+    let mut n_phantoms = 0;
+    for (lane_i, x) in spots {
+        if n_phantoms >= occlusion.phantoms {
+            break;
+        }
+        let mut car = Car::new(&params, road.cars.len(), lane_i);
+        car.set_x(x);
+        if road.collides_any_car(&car) || !hidden(road, &car, None) {
+            continue;
+        }
+        car.vel = ego_vel;
+        car.preferred_vel = ego_vel;
+        car.side_policy = find_lane_change(&policies, Some(lane_i), LongitudinalPolicy::Maintain)
+            .unwrap_or(&policies[0])
+            .clone();
+        road.cars.push(car);
+        n_phantoms += 1;
+    }
+    road.update_cars_spatial();
+}
+
+// dims the cars the ego can't see
+pub fn draw_occluded(road: &Road, r: &mut Rvx, world_car: impl Fn(&Car) -> Car) {
+    for (car, _) in road
+        .cars
+        .iter()
+        .zip(occluded_cars(road))
+        .filter(|(_, hidden)| *hidden)
+    {
+        let car = world_car(car);
+        r.draw(
+            Rvx::square()
+                .scale_xy(&[car.length, car.width])
+                .rot(car.theta())
+                .translate(&[
+                    car.pose().translation.vector.x,
+                    car.pose().translation.vector.y,
+                ])
+                .color(RvxColor::BLACK.set_a(0.6)),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::arg_parameters::Parameters;
+
+    #[test]
+    fn test_occlusion() {
+        let mut params = Parameters::new().unwrap();
+        params.occlusion.enabled = true;
+        params.occlusion.phantoms = 2;
+        let mut road = Road::new(Arc::new(params));
+
+        // a truck right ahead of the ego hides the car ahead of it, but not one alongside it
+        let mut truck = Car::new(&road.params, 1, 0);
+        truck.make_truck();
+        truck.set_x(20.0);
+        road.cars.push(truck);
+        let mut hidden_car = Car::new(&road.params, 2, 0);
+        hidden_car.set_x(40.0);
+        road.cars.push(hidden_car);
+        let mut seen_car = Car::new(&road.params, 3, 1);
+        seen_car.set_x(10.0);
+        road.cars.push(seen_car);
+        road.update_cars_spatial();
+        assert_eq!(occluded_cars(&road), vec![false, false, true, false]);
+
+        // and the phantoms go in the truck's shadow
+        add_phantom_cars(&mut road);
+        assert_eq!(road.cars.len(), 6);
+        let occluded = occluded_cars(&road);
+        for (phantom_i, phantom) in road.cars.iter().enumerate().skip(4) {
+            assert!(occluded[phantom_i]);
+            assert!(phantom.x() > 20.0);
+        }
+    }
+}
@@ -0,0 +1,308 @@
+use std::collections::HashMap;
+
+use roxmltree::{Document, Node};
+
+use crate::{arg_parameters::Parameters, road::LANE_WIDTH, sim_error::SimError};
+
+// A road of an OpenDRIVE (.xodr) map, loaded with opendrive.file, for running on real road
+// sections. Its reference line becomes road_geometry: lines, arcs and spirals as the same
+// segments, and poly3 and paramPoly3 curves as arcs with the same change in heading.
+// It continues through the road's successors that are roads, but not through junctions.
+// The driving lanes to the right of the first road's reference line, which the traffic
+// drives on, become the road's lanes, though they keep the usual LANE_WIDTH.
+// The speed limit of the road type, or else of its lanes, caps the cars' preferred speeds.
+#[derive(Debug)]
+pub struct OpenDriveRoad {
+    // as for road_geometry
+    pub geometry: String,
+    pub n_lanes: i32,
+    // the mean of the driving lanes' widths where the road starts
+    pub lane_width: f64,
+    // in m/s
+    pub speed_limit: Option<f64>,
+    pub length: f64,
+}
+
+fn child<'a, 'i>(node: Node<'a, 'i>, name: &str) -> Option<Node<'a, 'i>> {
+    node.children().find(|n| n.has_tag_name(name))
+}
+
+fn number(node: Node, name: &str) -> f64 {
+    node.attribute(name)
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(0.0)
+}
+
+// in m/s, from the max and unit of a speed record
+fn speed(node: Node) -> Option<f64> {
+    let max = node.attribute("max")?.trim().parse::<f64>().ok()?;
+    let to_mps = match node.attribute("unit").unwrap_or("m/s") {
+        "km/h" => 1.0 / 3.6,
+        "mph" => 0.44704,
+        _ => 1.0,
+    };
+    Some(max * to_mps)
+}
+
+// road_geometry segments for the geometry records of a planView
+fn segments(plan_view: Node) -> Vec<String> {
+    let mut segments = Vec::new();
+    for geometry in plan_view.children().filter(|n| n.has_tag_name("geometry")) {
+        let length = number(geometry, "length");
+        if length <= 0.0 {
+            continue;
+        }
+        let shape = match geometry.children().find(|n| n.is_element()) {
+            Some(shape) => shape,
+            None => continue,
+        };
+        let segment = match shape.tag_name().name() {
+            "line" => format!("straight:{}", length),
+            "arc" => format!("arc:{}:{}", length, number(shape, "curvature")),
+            "spiral" => format!(
+                "clothoid:{}:{}:{}",
+                length,
+                number(shape, "curvStart"),
+                number(shape, "curvEnd")
+            ),
+            "poly3" => {
+                // v(u) = a + b u + c u^2 + d u^3, with u about along the road
+                let (b, c, d) = (number(shape, "b"), number(shape, "c"), number(shape, "d"));
+                let slope = |u: f64| b + 2.0 * c * u + 3.0 * d * u * u;
+                let turn = slope(length).atan() - slope(0.0).atan();
+                format!("arc:{}:{}", length, turn / length)
+            }
+            "paramPoly3" => {
+                let p_end = match shape.attribute("pRange") {
+                    Some("arcLength") => length,
+                    _ => 1.0,
+                };
+                let tangent = |p: f64| {
+                    let du = number(shape, "bU")
+                        + 2.0 * number(shape, "cU") * p
+                        + 3.0 * number(shape, "dU") * p * p;
+                    let dv = number(shape, "bV")
+                        + 2.0 * number(shape, "cV") * p
+                        + 3.0 * number(shape, "dV") * p * p;
+                    dv.atan2(du)
+                };
+                let turn = tangent(p_end) - tangent(0.0);
+                let turn = turn.sin().atan2(turn.cos());
+                format!("arc:{}:{}", length, turn / length)
+            }
+            other => panic!("Unknown OpenDRIVE geometry {}", other),
+        };
+        segments.push(segment);
+    }
+    segments
+}
+
+impl OpenDriveRoad {
+    pub fn load(path: &str, road_id: &str) -> Result<Self, SimError> {
+        let load_error = |message: String| SimError::Load {
+            path: path.to_owned(),
+            message,
+        };
+        let contents = std::fs::read_to_string(path).map_err(|e| load_error(e.to_string()))?;
+        Self::parse(&contents, road_id).map_err(load_error)
+    }
+
+    // the road with road_id, or the longest road when it's empty
+    pub fn parse(contents: &str, road_id: &str) -> Result<Self, String> {
+        let doc = Document::parse(contents).map_err(|e| e.to_string())?;
+        let roads = doc
+            .root_element()
+            .children()
+            .filter(|n| n.has_tag_name("road"))
+            .map(|n| (n.attribute("id").unwrap_or("").to_owned(), n))
+            .collect::<HashMap<_, _>>();
+        let first = if road_id.is_empty() {
+            roads
+                .values()
+                .max_by(|a, b| {
+                    number(**a, "length")
+                        .partial_cmp(&number(**b, "length"))
+                        .unwrap()
+                })
+                .copied()
+                .ok_or("no roads")?
+        } else {
+            *roads
+                .get(road_id)
+                .ok_or_else(|| format!("no road with id {}", road_id))?
+        };
+
+        // then on through its successors, while they start where the road before ends
+        let mut chain = vec![first];
+        let mut road = first;
+        while let Some(successor) = child(road, "link").and_then(|l| child(l, "successor")) {
+            if successor.attribute("elementType") != Some("road")
+                || successor.attribute("contactPoint") == Some("end")
+            {
+                break;
+            }
+            let next = match successor
+                .attribute("elementId")
+                .and_then(|id| roads.get(id))
+            {
+                Some(next) => *next,
+                None => break,
+            };
+            if chain
+                .iter()
+                .any(|r| r.attribute("id") == next.attribute("id"))
+            {
+                break;
+            }
+            chain.push(next);
+            road = next;
+        }
+
+        let mut geometry = Vec::new();
+        let mut length = 0.0;
+        for road in chain.iter() {
+            let plan_view = child(*road, "planView").ok_or("road without a planView")?;
+            geometry.extend(segments(plan_view));
+            length += number(*road, "length");
+        }
+        if geometry.is_empty() {
+            return Err("the road has no geometry".to_owned());
+        }
+
+        let section = child(first, "lanes")
+            .and_then(|l| child(l, "laneSection"))
+            .ok_or("road without a laneSection")?;
+        let driving_lanes = child(section, "right")
+            .map(|right| {
+                right
+                    .children()
+                    .filter(|n| n.has_tag_name("lane") && n.attribute("type") == Some("driving"))
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        if driving_lanes.is_empty() {
+            return Err("the road has no driving lanes on its right".to_owned());
+        }
+        let lane_width = driving_lanes
+            .iter()
+            .map(|lane| child(*lane, "width").map_or(LANE_WIDTH, |w| number(w, "a")))
+            .sum::<f64>()
+            / driving_lanes.len() as f64;
+
+        let road_speed = first
+            .children()
+            .filter(|n| n.has_tag_name("type"))
+            .find_map(|t| child(t, "speed").and_then(speed));
+        let lane_speed = driving_lanes
+            .iter()
+            .find_map(|lane| child(*lane, "speed").and_then(speed));
+
+        Ok(Self {
+            geometry: geometry.join(","),
+            n_lanes: driving_lanes.len() as i32,
+            lane_width,
+            speed_limit: road_speed.or(lane_speed),
+            length,
+        })
+    }
+
+    // sets up the run for the road
+    pub fn apply(&self, params: &mut Parameters) {
+        params.road_geometry = self.geometry.clone();
+        params.n_lanes = self.n_lanes;
+        if let Some(speed_limit) = self.speed_limit {
+            params.speed_limit = speed_limit;
+        }
+        if params.is_single_run {
+            eprintln!(
+                "OpenDRIVE road of {:.0} m with {} lanes of {:.2} m (run with {} m lanes){}",
+                self.length,
+                self.n_lanes,
+                self.lane_width,
+                LANE_WIDTH,
+                self.speed_limit
+                    .map_or(String::new(), |v| format!(", speed limit {:.1} m/s", v))
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::road_geometry::RoadGeometry;
+
+    // a highway of two roads: a line, a spiral into an arc and out again,
+    // then a poly3 that bends back, with three lanes going each way
+    const MAP: &str = r#"<?xml version="1.0" standalone="yes"?>
+<OpenDRIVE>
+  <header revMajor="1" revMinor="6" name="test"/>
+  <road name="ramp" length="50" id="7" junction="-1">
+    <planView><geometry s="0" x="0" y="0" hdg="0" length="50"><line/></geometry></planView>
+    <lanes><laneSection s="0"><right>
+      <lane id="-1" type="driving" level="false"><width sOffset="0" a="3.5" b="0" c="0" d="0"/></lane>
+    </right></laneSection></lanes>
+  </road>
+  <road name="highway" length="400" id="1" junction="-1">
+    <link><successor elementType="road" elementId="2" contactPoint="start"/></link>
+    <type s="0" type="motorway"><speed max="100" unit="km/h"/></type>
+    <planView>
+      <geometry s="0" x="0" y="0" hdg="0" length="100"><line/></geometry>
+      <geometry s="100" x="100" y="0" hdg="0" length="50"><spiral curvStart="0" curvEnd="0.004"/></geometry>
+      <geometry s="150" x="150" y="0.2" hdg="0.1" length="200"><arc curvature="0.004"/></geometry>
+      <geometry s="350" x="340" y="60" hdg="0.9" length="50"><spiral curvStart="0.004" curvEnd="0"/></geometry>
+    </planView>
+    <lanes><laneSection s="0">
+      <left>
+        <lane id="1" type="driving" level="false"><width sOffset="0" a="3.5" b="0" c="0" d="0"/></lane>
+      </left>
+      <center><lane id="0" type="none" level="false"/></center>
+      <right>
+        <lane id="-1" type="driving" level="false"><width sOffset="0" a="3.5" b="0" c="0" d="0"/></lane>
+        <lane id="-2" type="driving" level="false"><width sOffset="0" a="3.75" b="0" c="0" d="0"/></lane>
+        <lane id="-3" type="shoulder" level="false"><width sOffset="0" a="2.5" b="0" c="0" d="0"/></lane>
+      </right>
+    </laneSection></lanes>
+  </road>
+  <road name="highway" length="100" id="2" junction="-1">
+    <link><successor elementType="junction" elementId="5"/></link>
+    <planView>
+      <geometry s="0" x="360" y="95" hdg="1.1" length="100"><poly3 a="0" b="0" c="-0.0005" d="0"/></geometry>
+    </planView>
+  </road>
+</OpenDRIVE>
+"#;
+
+    #[test]
+    fn test_opendrive_road() {
+        let road = OpenDriveRoad::parse(MAP, "").unwrap();
+        assert_eq!(road.n_lanes, 2);
+        assert!((road.lane_width - 3.625).abs() < 1e-9);
+        assert!((road.speed_limit.unwrap() - 100.0 / 3.6).abs() < 1e-9);
+        assert_eq!(road.length, 500.0);
+        let segments = road.geometry.split(',').collect::<Vec<_>>();
+        assert_eq!(
+            segments[..4],
+            [
+                "straight:100",
+                "clothoid:50:0:0.004",
+                "arc:200:0.004",
+                "clothoid:50:0.004:0"
+            ]
+        );
+        // the poly3 turns right by the slope at its end
+        assert_eq!(
+            segments[4],
+            format!("arc:100:{}", (-0.1_f64).atan() / 100.0)
+        );
+
+        let geometry = RoadGeometry::parse(&road.geometry);
+        let turn = 0.004 * 50.0 / 2.0 * 2.0 + 0.004 * 200.0;
+        assert!((geometry.heading(400.0, 0.0) - turn).abs() < 1e-9);
+
+        let ramp = OpenDriveRoad::parse(MAP, "7").unwrap();
+        assert_eq!((ramp.geometry.as_str(), ramp.n_lanes), ("straight:50", 1));
+        assert_eq!(ramp.speed_limit, None);
+        assert!(OpenDriveRoad::parse(MAP, "3").is_err());
+    }
+}
@@ -0,0 +1,177 @@
+use parry2d_f64::{na::Isometry2, shape::Ball};
+use rvx::{Rvx, RvxColor};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    arg_parameters::Parameters,
+    car::Car,
+    road::{Road, LANE_WIDTH},
+    road_geometry::RoadGeometry,
+};
+
+pub const PEDESTRIAN_RADIUS: f64 = 0.35;
+pub const CROSSWALK_WIDTH: f64 = 3.0;
+// how far outside the road's edges the curbs they wait on are
+const CURB_OFFSET: f64 = 1.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum PedestrianState {
+    // on a curb, looking for a gap in traffic once it's after until
+    Waiting { until: f64 },
+    // walking across the road, toward positive y for dir 1.0 and negative y for dir -1.0
+    Crossing { dir: f64 },
+}
+
+// Someone crossing the road at the crosswalk at station x, in the road's frame like the cars.
+// They don't stop once they've started crossing, so it's up to the cars to yield.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Pedestrian {
+    pub x: f64,
+    pub y: f64,
+    pub state: PedestrianState,
+    pub hit: bool,
+}
+
+// y of the curbs on either side of the road
+pub fn curbs_y(params: &Parameters) -> (f64, f64) {
+    (
+        -LANE_WIDTH - CURB_OFFSET,
+        (params.n_lanes - 1) as f64 * LANE_WIDTH + CURB_OFFSET,
+    )
+}
+
+impl Pedestrian {
+    pub fn new(params: &Parameters, x: f64, on_low_curb: bool, until: f64) -> Self {
+        let (low_y, high_y) = curbs_y(params);
+        Self {
+            x,
+            y: if on_low_curb { low_y } else { high_y },
+            state: PedestrianState::Waiting { until },
+            hit: false,
+        }
+    }
+
+    pub fn is_crossing(&self) -> bool {
+        matches!(self.state, PedestrianState::Crossing { .. })
+    }
+
+    pub fn shape(&self) -> Ball {
+        Ball::new(PEDESTRIAN_RADIUS)
+    }
+
+    pub fn pose(&self) -> Isometry2<f64> {
+        Isometry2::translation(self.x, self.y)
+    }
+
+    // no car is on the crosswalk or would reach it within gap_time
+    fn gap_is_clear(&self, params: &Parameters, cars: &[Car]) -> bool {
+        let near_edge = self.x - PEDESTRIAN_RADIUS;
+        let far_edge = self.x + PEDESTRIAN_RADIUS;
+        cars.iter().all(|car| {
+            if car.x() - car.length > far_edge {
+                // already past
+                true
+            } else if car.x() >= near_edge {
+                false
+            } else {
+                near_edge - car.x() > car.vel * params.pedestrians.gap_time
+            }
+        })
+    }
+
+    pub fn update(&mut self, params: &Parameters, cars: &[Car], t: f64, dt: f64) {
+        if self.hit {
+            return;
+        }
+        let (low_y, high_y) = curbs_y(params);
+        match self.state {
+            PedestrianState::Waiting { until } => {
+                if t >= until && self.gap_is_clear(params, cars) {
+                    let dir = if self.y < (low_y + high_y) / 2.0 {
+                        1.0
+                    } else {
+                        -1.0
+                    };
+                    self.state = PedestrianState::Crossing { dir };
+                }
+            }
+            PedestrianState::Crossing { dir } => {
+                self.y += dir * params.pedestrians.walk_speed * dt;
+                if self.y <= low_y || self.y >= high_y {
+                    self.y = self.y.max(low_y).min(high_y);
+                    self.state = PedestrianState::Waiting {
+                        until: t + params.pedestrians.wait_time,
+                    };
+                }
+            }
+        }
+    }
+
+    pub fn draw(&self, geometry: &RoadGeometry, r: &mut Rvx) {
+        let (x, y) = geometry.to_cartesian(self.x, self.y);
+        let color = if self.hit {
+            RvxColor::RED
+        } else {
+            RvxColor::PINK
+        };
+        r.draw(
+            Rvx::circle()
+                .scale(PEDESTRIAN_RADIUS)
+                .translate(&[x, y])
+                .color(color),
+        );
+    }
+}
+
+// the car's distance ahead to a crossing pedestrian that hasn't got out of its way yet
+pub fn pedestrian_ahead(road: &Road, car_i: usize) -> Option<f64> {
+    let car = &road.cars[car_i];
+    let clear_side = car.width / 2.0 + PEDESTRIAN_RADIUS + 1.0;
+    road.pedestrians
+        .iter()
+        .filter_map(|p| match p.state {
+            PedestrianState::Crossing { dir } if (p.y - car.y()) * dir < clear_side => {
+                Some(p.x - PEDESTRIAN_RADIUS - car.x())
+            }
+            _ => None,
+        })
+        .filter(|&dist| dist > 0.0)
+        .min_by(|a, b| a.partial_cmp(b).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pedestrian_crossing() {
+        let mut params = Parameters::new().unwrap();
+        params.pedestrians.wait_time = 1.0;
+        params.pedestrians.gap_time = 4.0;
+        let (low_y, high_y) = curbs_y(&params);
+        let mut pedestrian = Pedestrian::new(&params, 50.0, true, 1.0);
+
+        // waits out its time, and then for a car 20 m away at 10 m/s to pass
+        let mut car = Car::new(&params, 0, 0);
+        car.set_x(30.0);
+        car.vel = 10.0;
+        let dt = 0.1;
+        pedestrian.update(&params, &[car.clone()], 0.5, dt);
+        assert!(!pedestrian.is_crossing());
+        pedestrian.update(&params, &[car.clone()], 1.5, dt);
+        assert!(!pedestrian.is_crossing());
+        car.set_x(50.0 + car.length + 1.0);
+        pedestrian.update(&params, &[car.clone()], 1.6, dt);
+        assert_eq!(pedestrian.state, PedestrianState::Crossing { dir: 1.0 });
+
+        // then crosses all the way over without stopping
+        let mut t = 1.6;
+        while pedestrian.is_crossing() {
+            t += dt;
+            pedestrian.update(&params, &[], t, dt);
+        }
+        assert_eq!(pedestrian.y, high_y);
+        let crossing_time = (high_y - low_y) / params.pedestrians.walk_speed;
+        assert!((t - 1.6 - crossing_time).abs() <= dt + 1e-9);
+    }
+}
@@ -0,0 +1,113 @@
+use std::sync::Mutex;
+
+use rand::prelude::StdRng;
+
+use crate::{
+    arg_parameters::Parameters, eudm::EudmPlanner, mcts::MctsPlanner, mpdm::MpdmPlanner,
+    road::Road, side_policies::SidePolicy, sim_error::SimError,
+};
+
+// What chooses the ego's policy every replan_dt, by the method parameter.
+// It plans from the road it's given, with that road's parameters, and gives back the
+// policy to switch to, or None to keep the current one, with shapes for the viewer.
+pub trait EgoPlanner {
+    fn plan(&mut self, road: &Road, rng: &mut StdRng) -> (Option<SidePolicy>, Vec<rvx::Shape>);
+}
+
+// The ego keeps whatever policy it starts with
+pub struct FixedPlanner;
+
+impl EgoPlanner for FixedPlanner {
+    fn plan(&mut self, _road: &Road, _rng: &mut StdRng) -> (Option<SidePolicy>, Vec<rvx::Shape>) {
+        (None, Vec::new())
+    }
+}
+
+// A new planner for a run with the given parameters
+pub type PlannerFactory = fn(&Parameters) -> Box<dyn EgoPlanner>;
+
+// the methods from outside the crate, across all the runs' threads
+static REGISTERED: Mutex<Vec<(String, PlannerFactory)>> = Mutex::new(Vec::new());
+
+const BUILT_IN: [&str; 4] = ["fixed", "mpdm", "eudm", "mcts"];
+
+// Makes method a valid method parameter, for planners from other crates, like:
+// register_planner("mine", |params| Box::new(MyPlanner::new(params)));
+// Registering a method again replaces its planner.
+pub fn register_planner(method: &str, factory: PlannerFactory) {
+    assert!(
+        !BUILT_IN.contains(&method),
+        "method {} is built in and can't be registered",
+        method
+    );
+    let mut registered = REGISTERED.lock().unwrap();
+    registered.retain(|(m, _)| m != method);
+    registered.push((method.to_owned(), factory));
+}
+
+fn registered_factory(method: &str) -> Option<PlannerFactory> {
+    let registered = REGISTERED.lock().unwrap();
+    registered
+        .iter()
+        .find(|(m, _)| m == method)
+        .map(|(_, factory)| *factory)
+}
+
+pub fn is_method(method: &str) -> bool {
+    BUILT_IN.contains(&method) || registered_factory(method).is_some()
+}
+
+pub fn make_planner(params: &Parameters) -> Result<Box<dyn EgoPlanner>, SimError> {
+    let planner: Box<dyn EgoPlanner> = match params.method.as_str() {
+        "fixed" => Box::new(FixedPlanner),
+        "mpdm" => Box::new(MpdmPlanner),
+        "eudm" => Box::new(EudmPlanner),
+        "mcts" => Box::new(MctsPlanner),
+        method => match registered_factory(method) {
+            Some(factory) => factory(params),
+            None => return Err(SimError::UnknownMethod(method.to_owned())),
+        },
+    };
+    Ok(planner)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::mpdm::make_policy_choices;
+
+    // always the last of the policy choices
+    struct LastChoicePlanner;
+
+    impl EgoPlanner for LastChoicePlanner {
+        fn plan(
+            &mut self,
+            road: &Road,
+            _rng: &mut StdRng,
+        ) -> (Option<SidePolicy>, Vec<rvx::Shape>) {
+            (
+                make_policy_choices(&road.params).last().cloned(),
+                Vec::new(),
+            )
+        }
+    }
+
+    #[test]
+    fn test_registered_planner() {
+        assert!(is_method("eudm"));
+        assert!(!is_method("last_choice"));
+        register_planner("last_choice", |_| Box::new(LastChoicePlanner));
+        assert!(is_method("last_choice"));
+
+        let mut params = Parameters::new().unwrap();
+        params.method = "last_choice".to_owned();
+        let road = Road::new(Arc::new(params.clone()));
+        let mut planner = make_planner(&params).unwrap();
+        let (policy, _) = planner.plan(&road, &mut StdRng::seed_from_u64(0));
+        assert_eq!(policy, make_policy_choices(&params).last().cloned());
+    }
+}
@@ -0,0 +1,203 @@
+use std::{f64::consts::PI, rc::Rc, sync::mpsc::Receiver, time::Duration};
+
+use rvx::Rvx;
+
+use crate::{
+    arg_parameters::Parameters, candidates, mcts, rate_timer::RateTimer, replay, road::Road,
+};
+
+pub const HELP: &str = "Enter: pause/resume, n: step, b: step back, g <step>: go to step, \
+                        x <speed>: run at speed (like x 0.25 or x 4), i <car_i> or i <m ahead of the ego> <lane>: \
+                        inspect a car and debug it, i: stop debugging, t: MCTS tree overlay, \
+                        c: candidate policies overlay, q: quit";
+
+pub enum PlaybackAction {
+    // run the next timestep
+    Step,
+    // paused, so check back for commands
+    Wait,
+    Quit,
+}
+
+// Controls for watching a live run, typed into the terminal like for replays (see HELP).
+// Stepping back and going to a past step show the road as it was then, and resuming goes live again.
+// Going to a later step runs ahead to it without drawing, and pauses there.
+pub struct Playback {
+    commands: Receiver<String>,
+    interval: Duration,
+    rate: RateTimer,
+    paused: bool,
+    step_once: bool,
+    quit: bool,
+    run_to: Option<usize>,
+    // the road and traces after each timestep so far, to go back through
+    history: Vec<(Road, Rc<Vec<rvx::Shape>>)>,
+    // the step being shown from the history, or None when live
+    viewing: Option<usize>,
+    // a new debug_car_i from inspecting a car, for the run to take
+    debug_car_i: Option<Option<usize>>,
+}
+
+impl Playback {
+    pub fn new(params: &Parameters) -> Self {
+        let interval = Duration::from_secs_f64(params.physics_dt / params.graphics_speedup);
+        Self {
+            commands: replay::spawn_stdin_commands(),
+            interval,
+            rate: RateTimer::new(interval),
+            paused: false,
+            step_once: false,
+            quit: false,
+            run_to: None,
+            history: Vec::new(),
+            viewing: None,
+            debug_car_i: None,
+        }
+    }
+
+    pub fn record(&mut self, road: &Road, traces: &Rc<Vec<rvx::Shape>>) {
+        self.history.push((road.clone(), traces.clone()));
+    }
+
+    fn live_step(&self) -> usize {
+        self.history.len().saturating_sub(1)
+    }
+
+    fn show(&mut self, r: &mut Rvx, step: usize) {
+        self.paused = true;
+        self.viewing = if step < self.live_step() {
+            Some(step)
+        } else {
+            None
+        };
+        let (road, traces) = &self.history[step.min(self.live_step())];
+        r.clear();
+        road.draw(r);
+        r.draw_all(traces.iter().cloned());
+        r.set_global_rot(-PI / 2.0);
+        r.commit_changes();
+        eprintln!("step {}, t = {:.2}", road.timesteps, road.t);
+    }
+
+    fn command(&mut self, r: &mut Rvx, command: &str) {
+        let mut words = command.split_whitespace();
+        match words.next() {
+            None if self.paused => {
+                if self.viewing.is_some() {
+                    self.show(r, self.live_step());
+                }
+                self.paused = false;
+            }
+            None => {
+                self.paused = true;
+                eprintln!("Paused at step {}", self.live_step());
+            }
+            Some("n") => match self.viewing {
+                Some(step) => self.show(r, step + 1),
+                None => {
+                    self.paused = true;
+                    self.step_once = true;
+                }
+            },
+            Some("b") => {
+                let step = self.viewing.unwrap_or_else(|| self.live_step());
+                self.show(r, step.saturating_sub(1));
+            }
+            Some("g") => match words.next().and_then(|s| s.parse::<usize>().ok()) {
+                Some(step) if step <= self.live_step() => self.show(r, step),
+                Some(step) => {
+                    self.viewing = None;
+                    self.run_to = Some(step);
+                    eprintln!("Running ahead to step {}", step);
+                }
+                None => eprintln!("Usage: g <step>"),
+            },
+            Some("x") => match words.next().and_then(|s| s.parse::<f64>().ok()) {
+                Some(speed) if speed > 0.0 => {
+                    self.rate = RateTimer::new(self.interval.div_f64(speed));
+                    eprintln!("Running at {}x", speed);
+                }
+                _ => eprintln!("Usage: x <speed>"),
+            },
+            Some("i") => {
+                let words = words.collect::<Vec<_>>();
+                let step = self.viewing.unwrap_or_else(|| self.live_step());
+                let (road, _) = &self.history[step];
+                let car_i = match words[..] {
+                    [] => {
+                        self.debug_car_i = Some(None);
+                        eprintln!("No longer debugging a car");
+                        return;
+                    }
+                    [car_i] => car_i.parse::<usize>().ok().filter(|&i| i < road.cars.len()),
+                    [ahead, lane_i] => match (ahead.parse::<f64>(), lane_i.parse::<i32>()) {
+                        (Ok(ahead), Ok(lane_i)) => {
+                            road.pick_car(road.cars[0].x() + ahead, Road::get_lane_y(lane_i))
+                        }
+                        _ => None,
+                    },
+                    _ => None,
+                };
+                match car_i {
+                    Some(car_i) => {
+                        eprintln!("{}", road.describe_car(car_i));
+                        self.debug_car_i = Some(Some(car_i));
+                    }
+                    None => eprintln!("No such car. {}", HELP),
+                }
+            }
+            Some("t") => mcts::set_tree_overlay(!mcts::tree_overlay_shown()),
+            Some("c") => {
+                candidates::set_candidates_overlay(!candidates::candidates_overlay_shown())
+            }
+            Some("q") => self.quit = true,
+            Some(other) => eprintln!("Unknown command {}. {}", other, HELP),
+        }
+    }
+
+    // before each timestep, following any commands typed in
+    pub fn next_action(&mut self, r: &mut Rvx) -> PlaybackAction {
+        if let Ok(command) = self.commands.try_recv() {
+            self.command(r, &command);
+        }
+        if self.quit {
+            PlaybackAction::Quit
+        } else if self.run_to.is_some() {
+            PlaybackAction::Step
+        } else if self.paused {
+            if self.step_once && self.viewing.is_none() {
+                self.step_once = false;
+                return PlaybackAction::Step;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+            PlaybackAction::Wait
+        } else {
+            PlaybackAction::Step
+        }
+    }
+
+    // after each timestep, whether to draw it (and then wait_until_ready)
+    pub fn draws_step(&mut self) -> bool {
+        if let Some(run_to) = self.run_to {
+            if self.live_step() < run_to {
+                return false;
+            }
+            self.run_to = None;
+            self.paused = true;
+        }
+        if self.paused {
+            eprintln!("Paused at step {}", self.live_step());
+        }
+        true
+    }
+
+    pub fn take_debug_car_i(&mut self) -> Option<Option<usize>> {
+        self.debug_car_i.take()
+    }
+
+    pub fn wait_until_ready(&mut self) {
+        if !self.paused {
+            self.rate.wait_until_ready();
+        }
+    }
+}
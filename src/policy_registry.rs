@@ -0,0 +1,452 @@
+use std::{
+    cell::RefCell,
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    rc::Rc,
+};
+
+use crate::{
+    arg_parameters::{Parameters, PolicyParameters},
+    gap_alignment_policy::GapAlignmentPolicy,
+    lane_change_policy::{LaneChangePolicy, LongitudinalPolicy},
+    mobil_policy::MobilPolicy,
+    nudge_policy::NudgePolicy,
+    side_policies::SidePolicy,
+};
+
+// Who chooses between a set of policies
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PolicyRole {
+    // the ego's planners
+    Ego,
+    // the obstacle cars, for what they really do
+    Obstacle,
+    // the planners, for what they believe the obstacle cars might be doing
+    Belief,
+    // the random obstacle cars under mismatch, the same policies as Obstacle but with their
+    // lane changes stretched by mismatch.lane_change_time_scale
+    Mismatched,
+    // and the ones they draw novel_fraction of the time, which the belief doesn't have
+    Novel,
+}
+
+fn declare(
+    name: &str,
+    kind: &str,
+    lanes: &str,
+    longitudinal: &str,
+    wait_for_clear: bool,
+    [ego, obstacle, belief]: [bool; 3],
+) -> PolicyParameters {
+    PolicyParameters {
+        name: name.to_owned(),
+        kind: kind.to_owned(),
+        lanes: lanes.to_owned(),
+        longitudinal: longitudinal.to_owned(),
+        wait_for_clear,
+        abort: false,
+        lane_change_time: None,
+        offset: 0.0,
+        gap: 0,
+        ego,
+        obstacle,
+        belief,
+        novel: false,
+    }
+}
+
+// The policies when the parameters file doesn't declare its own [[policies]]:
+// the ego changes lanes right away, the obstacle cars wait for a clear lane,
+// and the belief allows for either. The aborting lane changes, nudges and gap alignments
+// are there for the ego to turn on, like policies.nudge_left.ego true, and the aborting lane
+// changes and nudges are the obstacle cars' novel policies under mismatch.
+pub fn default_policies() -> Vec<PolicyParameters> {
+    vec![
+        declare(
+            "maintain",
+            "lane_change",
+            "each",
+            "maintain",
+            false,
+            [true, false, true],
+        ),
+        declare(
+            "maintain_waiting",
+            "lane_change",
+            "each",
+            "maintain",
+            true,
+            [false, true, true],
+        ),
+        declare(
+            "accelerate",
+            "lane_change",
+            "each",
+            "accelerate",
+            false,
+            [true, false, true],
+        ),
+        declare(
+            "accelerate_waiting",
+            "lane_change",
+            "each",
+            "accelerate",
+            true,
+            [false, true, true],
+        ),
+        declare(
+            "decelerate",
+            "lane_change",
+            "current",
+            "decelerate",
+            false,
+            [true, false, false],
+        ),
+        declare(
+            "decelerate_waiting",
+            "lane_change",
+            "current",
+            "decelerate",
+            true,
+            [false, true, true],
+        ),
+        declare("mobil", "mobil", "", "", false, [false, true, true]),
+        PolicyParameters {
+            abort: true,
+            novel: true,
+            ..declare(
+                "maintain_aborting",
+                "lane_change",
+                "each",
+                "maintain",
+                false,
+                [false; 3],
+            )
+        },
+        PolicyParameters {
+            abort: true,
+            novel: true,
+            ..declare(
+                "accelerate_aborting",
+                "lane_change",
+                "each",
+                "accelerate",
+                false,
+                [false; 3],
+            )
+        },
+        PolicyParameters {
+            offset: 0.5,
+            novel: true,
+            ..declare(
+                "nudge_left",
+                "nudge",
+                "current",
+                "maintain",
+                false,
+                [false; 3],
+            )
+        },
+        PolicyParameters {
+            offset: -0.5,
+            novel: true,
+            ..declare(
+                "nudge_right",
+                "nudge",
+                "current",
+                "maintain",
+                false,
+                [false; 3],
+            )
+        },
+        PolicyParameters {
+            gap: 1,
+            ..declare("align_ahead", "gap_alignment", "each", "", true, [false; 3])
+        },
+        PolicyParameters {
+            gap: -1,
+            ..declare(
+                "align_behind",
+                "gap_alignment",
+                "each",
+                "",
+                true,
+                [false; 3],
+            )
+        },
+    ]
+}
+
+fn longitudinal(decl: &PolicyParameters) -> LongitudinalPolicy {
+    match decl.longitudinal.as_str() {
+        "maintain" => LongitudinalPolicy::Maintain,
+        "accelerate" => LongitudinalPolicy::Accelerate,
+        "decelerate" => LongitudinalPolicy::Decelerate,
+        _ => panic!(
+            "Unknown longitudinal policy {} for policy {}",
+            decl.longitudinal, decl.name
+        ),
+    }
+}
+
+fn has_role(decl: &PolicyParameters, role: PolicyRole) -> bool {
+    match role {
+        PolicyRole::Ego => decl.ego,
+        PolicyRole::Obstacle => decl.obstacle,
+        PolicyRole::Belief => decl.belief,
+        PolicyRole::Mismatched => decl.obstacle,
+        PolicyRole::Novel => decl.novel,
+    }
+}
+
+// The policies of role, with their ids in order. The declarations for each lane come first,
+// lane by lane, and then the rest as declared. MOBIL is only there with mobil.fraction above 0.
+pub fn make_policies(params: &Parameters, role: PolicyRole) -> Vec<SidePolicy> {
+    let decls = params
+        .policies
+        .iter()
+        .filter(|d| has_role(d, role))
+        .filter(|d| d.kind != "mobil" || params.mobil.fraction > 0.0)
+        .collect::<Vec<_>>();
+    let time_scale = match role {
+        PolicyRole::Mismatched | PolicyRole::Novel => params.mismatch.lane_change_time_scale,
+        _ => 1.0,
+    };
+    // into lane_i, or in the current lane for None
+    let make = |decl: &PolicyParameters, id: usize, lane_i: Option<i32>| {
+        let id = id as u32;
+        let transition_time = decl.lane_change_time.unwrap_or(params.lane_change_time) * time_scale;
+        match (decl.kind.as_str(), lane_i) {
+            ("lane_change", _) => SidePolicy::LaneChangePolicy(
+                LaneChangePolicy::new(
+                    id,
+                    lane_i,
+                    transition_time,
+                    decl.wait_for_clear,
+                    longitudinal(decl),
+                )
+                .with_abort(decl.abort),
+            ),
+            ("nudge", None) => SidePolicy::NudgePolicy(NudgePolicy::new(
+                id,
+                decl.offset,
+                transition_time,
+                longitudinal(decl),
+            )),
+            ("gap_alignment", Some(lane_i)) => SidePolicy::GapAlignmentPolicy(
+                GapAlignmentPolicy::new(id, lane_i, decl.gap, transition_time),
+            ),
+            ("mobil", None) => SidePolicy::MobilPolicy(MobilPolicy::new(id)),
+            ("nudge", Some(_)) | ("gap_alignment", None) | ("mobil", Some(_)) => panic!(
+                "Policy {} of kind {} can't have lanes {}",
+                decl.name, decl.kind, decl.lanes
+            ),
+            (kind, _) => panic!("Unknown kind {} for policy {}", kind, decl.name),
+        }
+    };
+
+    let mut policies = Vec::new();
+    for lane_i in 0..params.n_lanes {
+        for decl in decls.iter().filter(|d| d.lanes == "each") {
+            policies.push(make(decl, policies.len(), Some(lane_i)));
+        }
+    }
+    for decl in decls.iter().filter(|d| d.lanes != "each") {
+        if decl.kind != "mobil" && decl.lanes != "current" {
+            panic!(
+                "Policy {} has lanes {}, not each or current",
+                decl.name, decl.lanes
+            );
+        }
+        policies.push(make(decl, policies.len(), None));
+    }
+    assert!(!policies.is_empty(), "No policies for the {:?} role", role);
+    policies
+}
+
+// The first lane change policy toward lane_i (or staying, for None) with the longitudinal
+// policy, for where a car needs one in particular
+pub fn find_lane_change(
+    policies: &[SidePolicy],
+    lane_i: Option<i32>,
+    long_policy: LongitudinalPolicy,
+) -> Option<&SidePolicy> {
+    policies.iter().find(|p| match p {
+        SidePolicy::LaneChangePolicy(p) => {
+            p.target_lane_i() == lane_i && p.long_policy() == long_policy
+        }
+        _ => false,
+    })
+}
+
+pub fn find_mobil(policies: &[SidePolicy]) -> Option<&SidePolicy> {
+    policies
+        .iter()
+        .find(|p| matches!(p, SidePolicy::MobilPolicy(_)))
+}
+
+// The parameters that make_policies builds from, to tell when a built set is still current
+struct PolicyKey {
+    n_lanes: i32,
+    lane_change_time: f64,
+    lane_change_time_scale: f64,
+    mobil: bool,
+    policies: Vec<PolicyParameters>,
+}
+
+impl PolicyKey {
+    fn new(params: &Parameters) -> Self {
+        Self {
+            n_lanes: params.n_lanes,
+            lane_change_time: params.lane_change_time,
+            lane_change_time_scale: params.mismatch.lane_change_time_scale,
+            mobil: params.mobil.fraction > 0.0,
+            policies: params.policies.clone(),
+        }
+    }
+
+    fn matches(&self, params: &Parameters) -> bool {
+        self.n_lanes == params.n_lanes
+            && self.lane_change_time == params.lane_change_time
+            && self.lane_change_time_scale == params.mismatch.lane_change_time_scale
+            && self.mobil == (params.mobil.fraction > 0.0)
+            && self.policies == params.policies
+    }
+}
+
+type PolicySet = Option<(PolicyKey, Rc<[SidePolicy]>)>;
+
+thread_local! {
+    // the last set of policies built for each role, in PolicyRole's order
+    static POLICY_SETS: RefCell<[PolicySet; 5]> =
+        RefCell::new([None, None, None, None, None]);
+}
+
+// make_policies, but built only when the parameters it's from change, since the planners and
+// the belief want them on every call. Each rayon worker keeps its own.
+pub fn shared_policies(params: &Parameters, role: PolicyRole) -> Rc<[SidePolicy]> {
+    POLICY_SETS.with(|sets| {
+        let set = &mut sets.borrow_mut()[role as usize];
+        match set {
+            Some((key, policies)) if key.matches(params) => policies.clone(),
+            _ => {
+                let policies = Rc::<[SidePolicy]>::from(make_policies(params, role));
+                *set = Some((PolicyKey::new(params), policies.clone()));
+                policies
+            }
+        }
+    })
+}
+
+// For the scenario name: nothing for the default policies, and otherwise a hash of them
+pub fn policies_name(params: &Parameters) -> String {
+    if params.policies == default_policies() {
+        return "".to_string();
+    }
+    let mut hasher = DefaultHasher::new();
+    serde_json::to_string(&params.policies)
+        .unwrap()
+        .hash(&mut hasher);
+    format!(",policies={:08x}", hasher.finish() as u32)
+}
+
+// a policy's field from the command line, like policies.decelerate.ego=false
+pub fn set_policy_parameter(params: &mut Parameters, name: &str, val: &str) {
+    let (policy_name, field) = name
+        .strip_prefix("policies.")
+        .and_then(|rest| rest.split_once('.'))
+        .unwrap_or_else(|| panic!("{} should be like policies.<name>.<field>", name));
+    let decl = params
+        .policies
+        .iter_mut()
+        .find(|d| d.name == policy_name)
+        .unwrap_or_else(|| panic!("No policy named {}", policy_name));
+    match field {
+        "kind" => decl.kind = val.to_owned(),
+        "lanes" => decl.lanes = val.to_owned(),
+        "longitudinal" => decl.longitudinal = val.to_owned(),
+        "wait_for_clear" => decl.wait_for_clear = val.parse().unwrap(),
+        "abort" => decl.abort = val.parse().unwrap(),
+        "lane_change_time" => decl.lane_change_time = Some(val.parse().unwrap()),
+        "offset" => decl.offset = val.parse().unwrap(),
+        "gap" => decl.gap = val.parse().unwrap(),
+        "ego" => decl.ego = val.parse().unwrap(),
+        "obstacle" => decl.obstacle = val.parse().unwrap(),
+        "belief" => decl.belief = val.parse().unwrap(),
+        "novel" => decl.novel = val.parse().unwrap(),
+        _ => panic!("{} is not a policy field", field),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::side_policies::SidePolicyTrait;
+
+    #[test]
+    fn test_policy_roles() {
+        let mut params = Parameters::new().unwrap();
+        params.n_lanes = 3;
+        params.mobil.fraction = 0.5;
+        // every lane's maintain and accelerate, then deceleration, then MOBIL for the obstacles
+        assert_eq!(make_policies(&params, PolicyRole::Ego).len(), 7);
+        let obstacle = make_policies(&params, PolicyRole::Obstacle);
+        assert_eq!(obstacle.len(), 8);
+        assert_eq!(make_policies(&params, PolicyRole::Belief).len(), 14);
+        assert!(matches!(obstacle[7], SidePolicy::MobilPolicy(_)));
+        // the aborting lane changes into each lane and the two nudges, for mismatch
+        assert_eq!(make_policies(&params, PolicyRole::Novel).len(), 2 * 3 + 2);
+        let accelerate = find_lane_change(&obstacle, Some(1), LongitudinalPolicy::Accelerate);
+        assert_eq!(accelerate.unwrap().policy_id(), 3);
+
+        // the obstacle cars giving up on lane changes into a closing gap
+        set_policy_parameter(&mut params, "policies.maintain_waiting.abort", "true");
+        let obstacle = make_policies(&params, PolicyRole::Obstacle);
+        match find_lane_change(&obstacle, Some(1), LongitudinalPolicy::Maintain) {
+            Some(SidePolicy::LaneChangePolicy(p)) => assert!(p.abort_if_closed()),
+            p => panic!("{:?} should be a lane change", p),
+        }
+        set_policy_parameter(&mut params, "policies.maintain_waiting.abort", "false");
+
+        set_policy_parameter(&mut params, "policies.decelerate.ego", "false");
+        set_policy_parameter(&mut params, "policies.accelerate.lane_change_time", "4");
+        let ego = make_policies(&params, PolicyRole::Ego);
+        assert_eq!(ego.len(), 6);
+        assert!(find_lane_change(&ego, None, LongitudinalPolicy::Decelerate).is_none());
+        assert!(policies_name(&params).starts_with(",policies="));
+
+        // the nudges and gap alignments only when turned on
+        set_policy_parameter(&mut params, "policies.nudge_left.ego", "true");
+        set_policy_parameter(&mut params, "policies.align_ahead.ego", "true");
+        set_policy_parameter(&mut params, "policies.align_ahead.gap", "2");
+        let ego = make_policies(&params, PolicyRole::Ego);
+        assert_eq!(ego.len(), 3 * 3 + 1);
+        match &ego[2] {
+            SidePolicy::GapAlignmentPolicy(p) => assert_eq!((p.target_lane_i(), p.gap()), (0, 2)),
+            p => panic!("{:?} should be a gap alignment", p),
+        }
+        assert!(matches!(ego[9], SidePolicy::NudgePolicy(_)));
+    }
+
+    #[test]
+    fn test_shared_policies() {
+        let mut params = Parameters::new().unwrap();
+        let ego = shared_policies(&params, PolicyRole::Ego);
+        assert!(Rc::ptr_eq(&ego, &shared_policies(&params, PolicyRole::Ego)));
+        assert_eq!(&ego[..], &make_policies(&params, PolicyRole::Ego)[..]);
+        assert!(!Rc::ptr_eq(
+            &ego,
+            &shared_policies(&params, PolicyRole::Belief)
+        ));
+
+        // and rebuilt for different parameters
+        params.n_lanes += 1;
+        let more_lanes = shared_policies(&params, PolicyRole::Ego);
+        assert!(more_lanes.len() > ego.len());
+        set_policy_parameter(&mut params, "policies.decelerate.ego", "false");
+        assert_eq!(
+            shared_policies(&params, PolicyRole::Ego).len(),
+            more_lanes.len() - 1
+        );
+    }
+}
@@ -0,0 +1,148 @@
+use std::{cell::Cell, time::Instant};
+
+use crate::arg_parameters::Parameters;
+
+// Wall-clock seconds that planning spends in each of its subsystems, with
+// params.profile_subsystems. Counted per thread like the rollouts, so a run's own rollouts
+// aren't mixed up with another scenario's on the same rayon worker.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Subsystem {
+    // take_update_steps, which the collision checks and policy evaluation below are part of
+    ForwardSims,
+    CollisionChecks,
+    // the side policies choosing their lanes, speeds and trajectories in forward sims
+    PolicyEvaluation,
+    BeliefSampling,
+}
+
+const N_SUBSYSTEMS: usize = 4;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Profile {
+    seconds: [f64; N_SUBSYSTEMS],
+}
+
+impl Profile {
+    pub fn seconds(&self, subsystem: Subsystem) -> f64 {
+        self.seconds[subsystem as usize]
+    }
+
+    pub fn add(&mut self, other: &Profile) {
+        for (s, o) in self.seconds.iter_mut().zip(other.seconds.iter()) {
+            *s += o;
+        }
+    }
+}
+
+thread_local! {
+    static PROFILE: Cell<Profile> = const {
+        Cell::new(Profile {
+            seconds: [0.0; N_SUBSYSTEMS],
+        })
+    };
+}
+
+// The start of a timed section, or None when not profiling
+pub fn start(params: &Parameters) -> Option<Instant> {
+    if params.profile_subsystems {
+        Some(Instant::now())
+    } else {
+        None
+    }
+}
+
+pub fn stop(subsystem: Subsystem, start: Option<Instant>) {
+    if let Some(start) = start {
+        let seconds = start.elapsed().as_secs_f64();
+        PROFILE.with(|p| {
+            let mut profile = p.get();
+            profile.seconds[subsystem as usize] += seconds;
+            p.set(profile);
+        });
+    }
+}
+
+// The time profiled on this thread since the last call
+pub fn take_profile() -> Profile {
+    PROFILE.with(|p| p.take())
+}
+
+// for the time that other threads spent for this one
+pub fn add_profile(profile: &Profile) {
+    PROFILE.with(|p| {
+        let mut total = p.get();
+        total.add(profile);
+        p.set(total);
+    });
+}
+
+// A run's profile over all its planning calls
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RunProfile {
+    pub planning: f64,
+    pub subsystems: Profile,
+}
+
+impl RunProfile {
+    pub fn add_planning_call(&mut self, planning: f64, subsystems: &Profile) {
+        self.planning += planning;
+        self.subsystems.add(subsystems);
+    }
+
+    pub fn seconds(&self, subsystem: Subsystem) -> f64 {
+        self.subsystems.seconds(subsystem)
+    }
+
+    // Planning outside of the forward sims and belief sampling: the planners' trees, cost
+    // sorting and so on. With parallel_rollouts the summed forward sims can outrun the wall clock.
+    pub fn tree_bookkeeping(&self) -> f64 {
+        (self.planning
+            - self.seconds(Subsystem::ForwardSims)
+            - self.seconds(Subsystem::BeliefSampling))
+        .max(0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{prelude::StdRng, SeedableRng};
+
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::new_state;
+
+    #[test]
+    fn test_profile_planning() {
+        let mut params = Parameters::new().unwrap();
+        params.method = "mpdm".to_owned();
+        params.n_cars = 10;
+        let state = new_state(Arc::new(params.clone())).unwrap();
+
+        let profile_plan = |profile_subsystems: bool| {
+            let mut road = state.road.clone();
+            let mut params = params.clone();
+            params.profile_subsystems = profile_subsystems;
+            road.params = Arc::new(params);
+            take_profile();
+            crate::choose_policy(&road.params, &road, &mut StdRng::seed_from_u64(0)).unwrap();
+            take_profile()
+        };
+
+        assert_eq!(profile_plan(false), Profile::default());
+        let profile = profile_plan(true);
+        for subsystem in [
+            Subsystem::CollisionChecks,
+            Subsystem::PolicyEvaluation,
+            Subsystem::BeliefSampling,
+        ] {
+            assert!(profile.seconds(subsystem) > 0.0, "{:?}", subsystem);
+        }
+        // the collision checks and policy evaluation are timed inside the forward sims
+        assert!(
+            profile.seconds(Subsystem::ForwardSims)
+                > profile.seconds(Subsystem::CollisionChecks)
+                    + profile.seconds(Subsystem::PolicyEvaluation)
+        );
+    }
+}
@@ -1,6 +1,7 @@
 use nalgebra::point;
 use parry2d_f64::{math::Isometry, na::Point2, shape::Ball};
 use rvx::{Rvx, RvxColor};
+use serde::{Deserialize, Serialize};
 
 use crate::{car::PRIUS_LENGTH, road::LANE_WIDTH, side_control::SideControlTrait, Road};
 use itertools::Itertools;
@@ -31,9 +32,11 @@ impl PurePursuitPolicyDebug {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct PurePursuitPolicy {
     ahead_time: f64,
+    // only for drawing
+    #[serde(skip)]
     debug_info: Option<Box<PurePursuitPolicyDebug>>,
 }
 
@@ -277,8 +280,10 @@ impl SideControlTrait for PurePursuitPolicy {
             target_ahead_dist * 2.0,
         );
         if contact.is_none() {
-            eprintln_f!("{car_i=}, trajectory: {:.2?}", trajectory);
-            eprintln_f!("{car_ref_x=:.2}, {car_ref_y=:.2}, {target_ahead_dist=:.2}");
+            error_f!(
+                "No pure pursuit target for {car_i=}, {car_ref_x=:.2}, {car_ref_y=:.2}, {target_ahead_dist=:.2}, trajectory: {:.2?}",
+                trajectory
+            );
         }
 
         let contact = contact.unwrap();
@@ -0,0 +1,270 @@
+use crate::{
+    car::Car,
+    occlusion,
+    pedestrian::{CROSSWALK_WIDTH, PEDESTRIAN_RADIUS},
+    road::{Road, TraceLine, LANE_WIDTH, ROAD_DASH_DIST, ROAD_DASH_LENGTH},
+    traffic_light,
+};
+
+// Drawing the road without the rvx viewer, for videos and SVG snapshots
+
+pub const WIDTH: usize = 1280;
+pub const HEIGHT: usize = 360;
+// m of road across the width of a picture
+const VIEW_LENGTH: f64 = 120.0;
+pub const SCALE: f64 = WIDTH as f64 / VIEW_LENGTH;
+// length of the pieces the road is drawn in, so it follows curves
+const PIECE_LENGTH: f64 = 2.0;
+
+pub type Rgb = [u8; 3];
+
+pub const BACKGROUND: Rgb = [20, 20, 20];
+pub const ROAD_GRAY: Rgb = [128, 128, 128];
+pub const DARK_GRAY: Rgb = [64, 64, 64];
+pub const WHITE: Rgb = [255, 255, 255];
+pub const BLACK: Rgb = [0, 0, 0];
+pub const GREEN: Rgb = [0, 200, 0];
+pub const YELLOW: Rgb = [240, 220, 0];
+pub const ORANGE: Rgb = [255, 140, 0];
+pub const RED: Rgb = [220, 0, 0];
+pub const BLUE: Rgb = [40, 80, 255];
+pub const PINK: Rgb = [255, 150, 200];
+
+// The world around a point, with the road running left to right
+#[derive(Clone, Copy, Debug)]
+pub struct View {
+    center: (f64, f64),
+    heading: f64,
+}
+
+impl View {
+    // centered across the road at the ego
+    pub fn around_ego(road: &Road) -> Self {
+        let (low_y, high_y) = road_edges(road);
+        let ego_s = road.cars[0].x();
+        Self {
+            center: road.geometry.to_cartesian(ego_s, (low_y + high_y) / 2.0),
+            heading: road.geometry.heading(ego_s, 0.0),
+        }
+    }
+
+    pub fn to_pixel(&self, x: f64, y: f64) -> (f64, f64) {
+        let (dx, dy) = (x - self.center.0, y - self.center.1);
+        let (sin, cos) = self.heading.sin_cos();
+        let u = cos * dx + sin * dy;
+        let v = -sin * dx + cos * dy;
+        (
+            WIDTH as f64 / 2.0 + u * SCALE,
+            HEIGHT as f64 / 2.0 - v * SCALE,
+        )
+    }
+
+    // whether any of the box around the pixel points is in the picture
+    pub fn shows(points: &[(f64, f64)]) -> bool {
+        points.iter().any(|p| p.0 >= 0.0)
+            && points.iter().any(|p| p.1 >= 0.0)
+            && points.iter().any(|p| p.0 < WIDTH as f64)
+            && points.iter().any(|p| p.1 < HEIGHT as f64)
+    }
+}
+
+// What the road gets drawn onto, all in world coordinates (m)
+pub trait Painter {
+    // the convex polygon of the corners, in order around it
+    fn fill_polygon(&mut self, corners: &[(f64, f64)], color: Rgb, alpha: f64);
+    fn polyline(&mut self, points: &[(f64, f64)], width: f64, color: Rgb, alpha: f64);
+    // size is the height of the letters
+    fn text(&mut self, at: (f64, f64), text: &str, size: f64, color: Rgb);
+
+    // a length by width rectangle around center, with length along theta
+    fn fill_rect(
+        &mut self,
+        center: (f64, f64),
+        length: f64,
+        width: f64,
+        theta: f64,
+        color: Rgb,
+        alpha: f64,
+    ) {
+        let (sin, cos) = theta.sin_cos();
+        let (hl, hw) = (length / 2.0, width / 2.0);
+        let corners = [(hl, hw), (-hl, hw), (-hl, -hw), (hl, -hw)]
+            .iter()
+            .map(|&(a, b)| (center.0 + a * cos - b * sin, center.1 + a * sin + b * cos))
+            .collect::<Vec<_>>();
+        self.fill_polygon(&corners, color, alpha);
+    }
+
+    fn fill_circle(&mut self, center: (f64, f64), radius: f64, color: Rgb) {
+        let corners = (0..16)
+            .map(|i| {
+                let angle = i as f64 * std::f64::consts::PI / 8.0;
+                (
+                    center.0 + radius * angle.cos(),
+                    center.1 + radius * angle.sin(),
+                )
+            })
+            .collect::<Vec<_>>();
+        self.fill_polygon(&corners, color, 1.0);
+    }
+
+    fn draw_car(&mut self, car: &Car, color: Rgb) {
+        let (sin, cos) = car.theta().sin_cos();
+        let center = (
+            car.x() - car.length / 2.0 * cos,
+            car.y() - car.length / 2.0 * sin,
+        );
+        self.fill_rect(center, car.length, car.width, car.theta(), color, 0.8);
+        // front wheel, to show the steering
+        self.fill_rect(
+            (car.x(), car.y()),
+            1.0,
+            0.5,
+            car.theta() + car.steer,
+            BLACK,
+            0.9,
+        );
+    }
+}
+
+// y of the road's edges, as in Road::draw()
+pub fn road_edges(road: &Road) -> (f64, f64) {
+    (-LANE_WIDTH, (road.params.n_lanes - 1) as f64 * LANE_WIDTH)
+}
+
+// Draws the road around the ego much like Road::draw() does in the viewer,
+// with the ego's planned traces on top
+pub fn draw_scene(road: &Road, painter: &mut impl Painter, traces: &[TraceLine]) {
+    let params = &road.params;
+    let geometry = &road.geometry;
+    let (low_y, high_y) = road_edges(road);
+    let mid_y = (low_y + high_y) / 2.0;
+    let ego_s = road.cars[0].x();
+
+    let low_s = ((ego_s - VIEW_LENGTH) / PIECE_LENGTH).floor() * PIECE_LENGTH;
+    let n_pieces = (2.0 * VIEW_LENGTH / PIECE_LENGTH) as usize + 1;
+    for piece_i in 0..n_pieces {
+        let s = low_s + piece_i as f64 * PIECE_LENGTH;
+        // a little overlap so no seams show between pieces
+        let next_s = s + PIECE_LENGTH * 1.05;
+        let strip = |low: f64, high: f64| {
+            [
+                geometry.to_cartesian(s, low),
+                geometry.to_cartesian(next_s, low),
+                geometry.to_cartesian(next_s, high),
+                geometry.to_cartesian(s, high),
+            ]
+        };
+        painter.fill_polygon(&strip(low_y, high_y), ROAD_GRAY, 1.0);
+
+        let ramp_lane = params.merge.ramp_lane;
+        if ramp_lane >= 0 && !road.lane_exists(ramp_lane, s + PIECE_LENGTH / 2.0) {
+            let lane_y = Road::get_lane_y(ramp_lane);
+            let lane = strip(lane_y - LANE_WIDTH / 2.0, lane_y + LANE_WIDTH / 2.0);
+            painter.fill_polygon(&lane, DARK_GRAY, 1.0);
+        }
+
+        for &edge_y in [low_y, high_y].iter() {
+            painter.fill_polygon(&strip(edge_y - 0.1, edge_y + 0.1), WHITE, 1.0);
+        }
+    }
+
+    // the dashes in the middle
+    let dash_interval = ROAD_DASH_LENGTH + ROAD_DASH_DIST;
+    let dash_offset = (ego_s / dash_interval).round() * dash_interval;
+    let n_dashes = (VIEW_LENGTH / dash_interval) as i32 + 1;
+    for lane_i in 1..params.n_lanes {
+        let dash_y = (lane_i - 1) as f64 * LANE_WIDTH;
+        for dash_i in -n_dashes..=n_dashes {
+            let s = dash_i as f64 * dash_interval + dash_offset;
+            painter.fill_rect(
+                geometry.to_cartesian(s, dash_y),
+                ROAD_DASH_LENGTH,
+                0.2,
+                geometry.heading(s, 0.0),
+                WHITE,
+                1.0,
+            );
+        }
+    }
+
+    if params.signals.spacing > 0.0 {
+        traffic_light::paint_intersections(road, painter, low_y, high_y);
+    }
+    for s in road.crosswalks_between(ego_s - VIEW_LENGTH, ego_s + VIEW_LENGTH) {
+        painter.fill_rect(
+            geometry.to_cartesian(s, mid_y),
+            CROSSWALK_WIDTH,
+            high_y - low_y,
+            geometry.heading(s, 0.0),
+            WHITE,
+            0.3,
+        );
+    }
+    for pedestrian in road.pedestrians.iter() {
+        let color = if pedestrian.hit { RED } else { PINK };
+        let center = geometry.to_cartesian(pedestrian.x, pedestrian.y);
+        painter.fill_circle(center, PEDESTRIAN_RADIUS, color);
+    }
+
+    for (i, car) in road.cars.iter().enumerate() {
+        let color = if i == 0 && car.crashed {
+            ORANGE
+        } else if i == 0 {
+            GREEN
+        } else if car.crashed {
+            RED
+        } else if car.vel == 0.0 {
+            WHITE
+        } else {
+            BLUE
+        };
+        painter.draw_car(&road.world_car(car), color);
+    }
+    if params.occlusion.enabled {
+        let hidden = occlusion::occluded_cars(road);
+        for (car, _) in road.cars.iter().zip(hidden).filter(|(_, hidden)| *hidden) {
+            let car = road.world_car(car);
+            let center = car.pose().translation.vector;
+            painter.fill_rect(
+                (center.x, center.y),
+                car.length,
+                car.width,
+                car.theta(),
+                BLACK,
+                0.6,
+            );
+        }
+    }
+
+    for trace in traces.iter() {
+        let color = if trace.crashed {
+            RED
+        } else if trace.not_safe {
+            PINK
+        } else {
+            GREEN
+        };
+        let width = 0.5 / (1 << trace.depth_level.min(3)) as f64;
+        let points = trace
+            .points
+            .chunks(2)
+            .map(|p| (p[0], p[1]))
+            .collect::<Vec<_>>();
+        painter.polyline(&points, width, color, 0.6);
+    }
+
+    if params.belief_overlay {
+        if let Some(belief) = road.belief.as_ref() {
+            belief.paint(road, painter);
+        }
+    }
+
+    let (label_x, label_y) = geometry.to_cartesian(ego_s - VIEW_LENGTH / 2.0 + 2.0, high_y + 5.0);
+    painter.text(
+        (label_x, label_y),
+        &format!("{} (t = {:.2} s)", road.timesteps, road.t),
+        1.5,
+        WHITE,
+    );
+}
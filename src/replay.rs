@@ -0,0 +1,391 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    convert::TryInto,
+    hash::{Hash, Hasher},
+    io::BufRead,
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, Receiver},
+    sync::Arc,
+    time::Duration,
+};
+
+use rvx::Rvx;
+
+use crate::{
+    arg_parameters::Parameters,
+    candidates::{candidates_overlay, describe, Candidate},
+    car::Car,
+    rate_timer::RateTimer,
+    road::Road,
+};
+
+const MAGIC: &[u8; 4] = b"SDRP";
+const VERSION: u8 = 2;
+
+// Everything needed to look at a run again without re-running it: the fully resolved
+// parameters (with the seeds), then for every physics timestep the ego's operating policy,
+// whether it replanned and if so the planner's candidate policies, and the state of every car.
+// Little-endian: "SDRP", version u8, params JSON length u32, params JSON,
+// then per frame: t f64, ego policy u32, replanned u8,
+// if replanned n_candidates u32 and per candidate: policy u32, cost f32, chosen u8,
+// then n_cars u32, and per car: x, y, theta, vel, steer, preferred_vel as f32, crashed u8, policy u32.
+// Version 1 was the same without the candidates.
+pub struct ReplayRecorder {
+    bytes: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReplayCar {
+    pub x: f32,
+    pub y: f32,
+    pub theta: f32,
+    pub vel: f32,
+    pub steer: f32,
+    pub preferred_vel: f32,
+    pub crashed: bool,
+    pub policy_id: u32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReplayFrame {
+    pub t: f64,
+    pub ego_policy_id: u32,
+    pub replanned: bool,
+    // empty unless it replanned
+    pub candidates: Vec<Candidate>,
+    pub cars: Vec<ReplayCar>,
+}
+
+// One file per scenario, so running the same scenario again replaces its replay
+pub fn replay_path(params: &Parameters) -> PathBuf {
+    run_file_path(&params.replays_dir, params, "replay")
+}
+
+// and likewise for the other files written for each run
+pub fn run_file_path(dir: &str, params: &Parameters, extension: &str) -> PathBuf {
+    let mut hasher = DefaultHasher::new();
+    params.scenario_name.hash(&mut hasher);
+    Path::new(dir).join(format!(
+        "{}_seed{}_{:016x}.{}",
+        params.method,
+        params.rng_seed,
+        hasher.finish(),
+        extension
+    ))
+}
+
+impl ReplayRecorder {
+    pub fn new(params: &Parameters) -> Self {
+        let params_json = serde_json::to_vec(params).unwrap();
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(VERSION);
+        bytes.extend_from_slice(&(params_json.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&params_json);
+        Self { bytes }
+    }
+
+    pub fn record_frame(&mut self, road: &Road, replanned: bool, candidates: &[Candidate]) {
+        let bytes = &mut self.bytes;
+        bytes.extend_from_slice(&road.t.to_le_bytes());
+        bytes.extend_from_slice(&road.cars[0].operating_policy_id().to_le_bytes());
+        bytes.push(replanned as u8);
+        if replanned {
+            bytes.extend_from_slice(&(candidates.len() as u32).to_le_bytes());
+            for candidate in candidates.iter() {
+                bytes.extend_from_slice(&candidate.policy_id.to_le_bytes());
+                bytes.extend_from_slice(&(candidate.cost as f32).to_le_bytes());
+                bytes.push(candidate.chosen as u8);
+            }
+        }
+        bytes.extend_from_slice(&(road.cars.len() as u32).to_le_bytes());
+        for car in road.cars.iter() {
+            for v in [
+                car.x(),
+                car.y(),
+                car.theta(),
+                car.vel,
+                car.steer,
+                car.preferred_vel,
+            ]
+            .iter()
+            {
+                bytes.extend_from_slice(&(*v as f32).to_le_bytes());
+            }
+            bytes.push(car.crashed as u8);
+            bytes.extend_from_slice(&car.operating_policy_id().to_le_bytes());
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> std::io::Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, &self.bytes)
+    }
+}
+
+pub fn read_replay(path: &Path) -> (Parameters, Vec<ReplayFrame>) {
+    let bytes =
+        std::fs::read(path).unwrap_or_else(|e| panic!("Could not read {}: {}", path.display(), e));
+    assert!(
+        bytes.len() >= 9 && &bytes[0..4] == MAGIC && (1..=VERSION).contains(&bytes[4]),
+        "{} is not a version 1 to {} replay file",
+        path.display(),
+        VERSION
+    );
+    let version = bytes[4];
+
+    let mut pos = 5;
+    let mut take = |n: usize| {
+        let slice = &bytes[pos..pos + n];
+        pos += n;
+        slice
+    };
+    let params_len = u32::from_le_bytes(take(4).try_into().unwrap()) as usize;
+    let params: Parameters = serde_json::from_slice(take(params_len))
+        .unwrap_or_else(|e| panic!("Bad parameters in {}: {}", path.display(), e));
+
+    let mut frames = Vec::new();
+    while pos < bytes.len() {
+        let mut take = |n: usize| {
+            let slice = &bytes[pos..pos + n];
+            pos += n;
+            slice
+        };
+        let t = f64::from_le_bytes(take(8).try_into().unwrap());
+        let ego_policy_id = u32::from_le_bytes(take(4).try_into().unwrap());
+        let replanned = take(1)[0] != 0;
+        let mut candidates = Vec::new();
+        if replanned && version >= 2 {
+            let n_candidates = u32::from_le_bytes(take(4).try_into().unwrap());
+            for _ in 0..n_candidates {
+                candidates.push(Candidate {
+                    policy_id: u32::from_le_bytes(take(4).try_into().unwrap()),
+                    cost: f32::from_le_bytes(take(4).try_into().unwrap()) as f64,
+                    chosen: take(1)[0] != 0,
+                });
+            }
+        }
+        let n_cars = u32::from_le_bytes(take(4).try_into().unwrap());
+        let cars = (0..n_cars)
+            .map(|_| {
+                let mut f = || f32::from_le_bytes(take(4).try_into().unwrap());
+                ReplayCar {
+                    x: f(),
+                    y: f(),
+                    theta: f(),
+                    vel: f(),
+                    steer: f(),
+                    preferred_vel: f(),
+                    crashed: take(1)[0] != 0,
+                    policy_id: u32::from_le_bytes(take(4).try_into().unwrap()),
+                }
+            })
+            .collect();
+        frames.push(ReplayFrame {
+            t,
+            ego_policy_id,
+            replanned,
+            candidates,
+            cars,
+        });
+    }
+
+    (params, frames)
+}
+
+fn frame_road(params: &Arc<Parameters>, frame: &ReplayFrame, timesteps: usize) -> Road {
+    let mut road = Road::new(params.clone());
+    road.t = frame.t;
+    road.timesteps = timesteps;
+    road.cars = frame
+        .cars
+        .iter()
+        .enumerate()
+        .map(|(car_i, c)| {
+            let mut car = Car::new(params, car_i, 0);
+            car.set_x(c.x as f64);
+            car.set_y(c.y as f64);
+            car.set_theta(c.theta as f64);
+            car.vel = c.vel as f64;
+            car.steer = c.steer as f64;
+            car.preferred_vel = c.preferred_vel as f64;
+            car.crashed = c.crashed;
+            car
+        })
+        .collect();
+    road
+}
+
+// Commands typed into the terminal, one per line
+pub fn spawn_stdin_commands() -> Receiver<String> {
+    let (tx, rx) = channel();
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lock().lines() {
+            match line {
+                Ok(line) => {
+                    if tx.send(line.trim().to_owned()).is_err() {
+                        break;
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+    });
+    rx
+}
+
+// Shows a recorded run in the rvx viewer, at the run's graphics_speedup.
+// Enter pauses and resumes, n and b step forward and back, g <step> jumps, c toggles the
+// overlay of the latest replan's candidate policies, and q quits.
+pub fn play_replay(path: &str) {
+    let (params, frames) = read_replay(Path::new(path));
+    assert!(!frames.is_empty(), "{} has no frames", path);
+    eprintln!(
+        "Replaying {} steps of {}",
+        frames.len(),
+        params.scenario_name.as_deref().unwrap_or("")
+    );
+    eprintln!(
+        "Enter: pause/resume, n: step, b: step back, g <step>: go to step, \
+         c: candidate policies overlay, q: quit"
+    );
+    let params = Arc::new(params);
+
+    let mut r = Rvx::new("Self-Driving Replay", [0, 0, 0, 0], 8000);
+    std::thread::sleep(Duration::from_millis(500));
+    r.set_user_zoom(None);
+
+    let commands = spawn_stdin_commands();
+    let mut rate = RateTimer::new(Duration::from_millis(
+        (params.physics_dt * 1000.0 / params.graphics_speedup) as u64,
+    ));
+    let mut step = 0;
+    let mut paused = false;
+    let mut redraw = true;
+    let mut show_candidates = params.candidates_overlay;
+    // the candidates of the latest replan at or before a step
+    let latest_candidates = |step: usize| {
+        frames[..=step]
+            .iter()
+            .rev()
+            .find(|f| f.replanned)
+            .map_or(&[][..], |f| &f.candidates[..])
+    };
+    loop {
+        if let Ok(command) = commands.try_recv() {
+            let mut words = command.split_whitespace();
+            match words.next() {
+                None => paused = !paused,
+                Some("q") => break,
+                Some("n") => {
+                    paused = true;
+                    step = (step + 1).min(frames.len() - 1);
+                }
+                Some("b") => {
+                    paused = true;
+                    step = step.saturating_sub(1);
+                }
+                Some("g") => match words.next().and_then(|s| s.parse::<usize>().ok()) {
+                    Some(to) => {
+                        paused = true;
+                        step = to.min(frames.len() - 1);
+                    }
+                    None => eprintln!("Usage: g <step>"),
+                },
+                Some("c") => show_candidates = !show_candidates,
+                Some(other) => eprintln!("Unknown command {}", other),
+            }
+            redraw = true;
+            if paused {
+                let frame = &frames[step];
+                eprintln!(
+                    "step {}, t = {:.2}, ego policy {}{}",
+                    step,
+                    frame.t,
+                    frame.ego_policy_id,
+                    if frame.replanned { " (replanned)" } else { "" }
+                );
+                let road = frame_road(&params, frame, step);
+                for line in describe(&road, latest_candidates(step)) {
+                    eprintln!("  {}", line);
+                }
+            }
+        }
+
+        if redraw {
+            r.clear();
+            let road = frame_road(&params, &frames[step], step);
+            road.draw(&mut r);
+            if show_candidates {
+                r.draw_all(candidates_overlay(&road, latest_candidates(step)));
+            }
+            r.set_global_rot(-std::f64::consts::PI / 2.0);
+            r.commit_changes();
+            redraw = false;
+        }
+
+        if paused {
+            std::thread::sleep(Duration::from_millis(20));
+        } else {
+            rate.wait_until_ready();
+            if step + 1 < frames.len() {
+                step += 1;
+                redraw = true;
+            } else {
+                paused = true;
+                eprintln!("End of the replay at step {}", step);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replay_round_trip() {
+        let mut params = Parameters::new().unwrap();
+        params.rng_seed = 12;
+        params.scenario_name = Some(",method=fixed,rng_seed=12,".to_owned());
+        let mut road = Road::new(Arc::new(params.clone()));
+        road.cars.push(Car::new(&params, 1, 1));
+        road.cars[1].set_x(20.0);
+        road.cars[1].vel = 3.0;
+
+        let candidates = [
+            Candidate {
+                policy_id: 3,
+                cost: 1.5,
+                chosen: true,
+            },
+            Candidate {
+                policy_id: 0,
+                cost: 2.25,
+                chosen: false,
+            },
+        ];
+        let mut recorder = ReplayRecorder::new(&params);
+        recorder.record_frame(&road, true, &candidates);
+        road.t = 0.5;
+        road.cars[1].crashed = true;
+        recorder.record_frame(&road, false, &candidates);
+
+        let path = std::env::temp_dir().join(format!("replay_test_{}.replay", std::process::id()));
+        recorder.write(&path).unwrap();
+        let (read_params, frames) = read_replay(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(read_params, params);
+        assert_eq!(frames.len(), 2);
+        assert!(frames[0].replanned && !frames[1].replanned);
+        assert_eq!(frames[0].candidates, candidates);
+        assert!(frames[1].candidates.is_empty());
+        assert_eq!(frames[1].t, 0.5);
+        assert_eq!(frames[1].cars[1].x, 20.0);
+        assert_eq!(frames[1].cars[1].vel, 3.0);
+        assert!(!frames[0].cars[1].crashed && frames[1].cars[1].crashed);
+    }
+}
@@ -1,3 +1,8 @@
+use crate::{
+    profiling::{RunProfile, Subsystem},
+    safety_metrics::SafetyMetrics,
+};
+
 #[derive(Default)]
 pub struct Reward {
     pub crashed: bool,
@@ -10,9 +15,54 @@ pub struct Reward {
     pub below997_planning_time: Option<f64>,
     pub max_planning_time: Option<f64>,
     pub stddev_planning_time: Option<f64>,
+    // seconds of planning per second driven, which compares across replan intervals
+    pub planning_load: Option<f64>,
+    // cars (ego or not) that crashed on the true road
+    pub crash_count: u32,
+    // time-to-collision, headway and post-encroachment time of the ego
+    pub safety: SafetyMetrics,
+    // changes of the ego's operating policy
+    pub policy_switches: u32,
+    // forward simulations run by the planner, see road::take_rollout_count()
+    pub rollouts: u64,
+    // heap allocations while planning, when the binary counts them, see alloc_counter.rs
+    pub planning_allocations: u64,
+    // where the planning time went, with params.profile_subsystems
+    pub profile: RunProfile,
+    // times the ego crossed a stop line at a red light
+    pub red_light_violations: u32,
+    // times the safety filter started braking for the ego
+    pub emergency_brakes: u32,
+    // seconds the ego spent in an RSS dangerous situation
+    pub rss_violation_t: f64,
+    // the ego's impact speed if it crashed, see Road::impact_speed()
+    pub impact_speed: Option<f64>,
+    // obstacle car steps the belief had a lane for, and had the wrong one, see Belief::lane_errors
+    pub belief_lane_checks: u32,
+    pub belief_lane_errors: u32,
+    // the ego's squared offset from its lane's center integrated over the time it kept its lane,
+    // and that time, see Road::ego_lane_offset()
+    pub lane_offset_sq_t: f64,
+    pub lane_keeping_t: f64,
 }
 
 impl Reward {
+    // the fraction of the obstacle car steps where the belief had them in the wrong lane
+    pub fn belief_lane_error(&self) -> f64 {
+        if self.belief_lane_checks == 0 {
+            return 0.0;
+        }
+        self.belief_lane_errors as f64 / self.belief_lane_checks as f64
+    }
+
+    // the RMS of the ego's offset from its lane's center while it kept its lane
+    pub fn lane_offset_rms(&self) -> f64 {
+        if self.lane_keeping_t <= 0.0 {
+            return 0.0;
+        }
+        (self.lane_offset_sq_t / self.lane_keeping_t).sqrt()
+    }
+
     pub fn calculate_timestep_metrics(&mut self) {
         self.planning_times
             .sort_by(|a, b| a.partial_cmp(b).unwrap());
@@ -22,8 +72,12 @@ impl Reward {
         self.below997_planning_time = Some(self.planning_times[n * 997 / 1000]);
         self.max_planning_time = Some(self.planning_times[n - 1]);
 
-        let mean = self.planning_times.iter().sum::<f64>() / n as f64;
+        let total = self.planning_times.iter().sum::<f64>();
+        let mean = total / n as f64;
         self.mean_planning_time = Some(mean);
+        if self.end_t > 0.0 {
+            self.planning_load = Some(total / self.end_t);
+        }
 
         // standard deviation of the mean or "standard error"
         let stddev = (self
@@ -43,13 +97,27 @@ impl std::fmt::Display for Reward {
         let s = self;
         write_f!(
             f,
-            "{} {s.end_t:5.2} {s.dist_travelled:5.2} {s.avg_vel:5.2} {:7.5} {:7.5} {:7.5} {:7.5} {:8.6}",
+            "{} {s.end_t:5.2} {s.dist_travelled:5.2} {s.avg_vel:5.2} {:7.5} {:7.5} {:7.5} {:7.5} {:8.6} {s.crash_count} {:.3} {s.policy_switches} {s.rollouts} {s.red_light_violations} {s.emergency_brakes} {s.rss_violation_t:.2} {:.3} {:.3} {:.3} {:.3} {:.3} {:.2} {s.planning_allocations} {:.4} {:.4} {:.4} {:.4} {:.4} {:.5} {:.4}",
             if s.crashed { 1.0 } else { 0.0 },
             s.mean_planning_time.unwrap(),
             s.below95_planning_time.unwrap(),
             s.below997_planning_time.unwrap(),
             s.max_planning_time.unwrap(),
-            s.stddev_planning_time.unwrap()
+            s.stddev_planning_time.unwrap(),
+            s.safety.ttc.min.unwrap_or(f64::INFINITY),
+            s.safety.ttc.mean().unwrap_or(f64::INFINITY),
+            s.safety.headway.min.unwrap_or(f64::INFINITY),
+            s.safety.headway.mean().unwrap_or(f64::INFINITY),
+            s.safety.pet.min.unwrap_or(f64::INFINITY),
+            s.safety.pet.mean().unwrap_or(f64::INFINITY),
+            s.impact_speed.unwrap_or(0.0),
+            s.profile.seconds(Subsystem::ForwardSims),
+            s.profile.seconds(Subsystem::CollisionChecks),
+            s.profile.seconds(Subsystem::PolicyEvaluation),
+            s.profile.seconds(Subsystem::BeliefSampling),
+            s.profile.tree_bookkeeping(),
+            s.planning_load.unwrap_or(0.0),
+            s.lane_offset_rms()
         )
     }
 }
@@ -73,6 +141,39 @@ impl std::fmt::Debug for Reward {
         if let Some(t) = self.stddev_planning_time {
             write_f!(f, ", stddev: {:.3}", t * 1000.0)?;
         }
+        if let Some(load) = self.planning_load {
+            write_f!(f, ", planning load: {:.4}", load)?;
+        }
+        write_f!(
+            f,
+            ", crashes: {s.crash_count}, switches: {s.policy_switches}, rollouts: {s.rollouts}, planning allocations: {s.planning_allocations}, red lights: {s.red_light_violations}, emergency brakes: {s.emergency_brakes}, rss violations: {s.rss_violation_t:.2}s"
+        )?;
+        write_f!(f, ", lane offset rms: {:.3}", s.lane_offset_rms())?;
+        if let Some(impact_speed) = self.impact_speed {
+            write_f!(f, ", impact speed: {impact_speed:.2}")?;
+        }
+        let profile = &self.profile;
+        if profile.planning > 0.0 {
+            write_f!(
+                f,
+                ", forward sims: {:.3}s, collision checks: {:.3}s, policy evaluation: {:.3}s, belief sampling: {:.3}s, tree bookkeeping: {:.3}s",
+                profile.seconds(Subsystem::ForwardSims),
+                profile.seconds(Subsystem::CollisionChecks),
+                profile.seconds(Subsystem::PolicyEvaluation),
+                profile.seconds(Subsystem::BeliefSampling),
+                profile.tree_bookkeeping()
+            )?;
+        }
+        let safety = &self.safety;
+        for (name, stats) in [
+            ("ttc", safety.ttc),
+            ("headway", safety.headway),
+            ("pet", safety.pet),
+        ] {
+            if let (Some(min), Some(mean)) = (stats.min, stats.mean()) {
+                write_f!(f, ", min {name}: {min:.2}, mean {name}: {mean:.2}")?;
+            }
+        }
         Ok(())
     }
 }
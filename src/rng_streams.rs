@@ -0,0 +1,129 @@
+use rand::{prelude::StdRng, Rng, RngCore, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+// What a stream's draws are for, so the same rng forks differently for each
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stream {
+    Spawn = 1,
+    Respawn = 2,
+    Pedestrians = 3,
+    PolicyChange = 4,
+    Sensor = 5,
+    Planning = 6,
+    Belief = 7,
+    ProcessNoise = 8,
+}
+
+// https://prng.di.unimi.it/splitmix64.c
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+// With rng_streams, a stream of its own for the draws about keys (like a timestep and a car),
+// from where rng is without advancing it. So with rng left at its seed, one more car or one
+// more rollout doesn't shift the draws for any other, which keeps paired runs paired.
+pub fn fork(rng: &StdRng, stream: Stream, keys: &[u64]) -> StdRng {
+    let mut state = rng.clone().gen::<u64>();
+    for key in std::iter::once(stream as u64).chain(keys.iter().copied()) {
+        state = splitmix64(state ^ splitmix64(key));
+    }
+    StdRng::seed_from_u64(state)
+}
+
+// A stream of draws that's plain data, splitmix64 of a counter, for a road to carry through
+// its clones (and serialization) without an rng of its own
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CounterStream(u64);
+
+impl CounterStream {
+    pub fn new(seed: u64, stream: Stream) -> Self {
+        Self(splitmix64(seed ^ splitmix64(stream as u64)))
+    }
+
+    // a stream of its own from where this one is, without advancing it
+    pub fn fork(&self) -> Self {
+        Self(splitmix64(!self.0))
+    }
+
+    // like fork, but a different stream for each key
+    pub fn fork_with(&self, key: u64) -> Self {
+        Self(splitmix64(!self.0 ^ splitmix64(key)))
+    }
+}
+
+// so gaussian() and the rest of Rng work on it like on the other rngs
+impl RngCore for CounterStream {
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(1);
+        splitmix64(self.0)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+// a normal sample with mean 0, by the Box-Muller transform
+pub fn gaussian(rng: &mut impl Rng, std: f64) -> f64 {
+    if std <= 0.0 {
+        return 0.0;
+    }
+    let u1: f64 = 1.0 - rng.gen::<f64>();
+    let u2: f64 = rng.gen();
+    std * (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fork() {
+        let rng = StdRng::seed_from_u64(7);
+        let draw = |stream, keys: &[u64]| fork(&rng, stream, keys).gen::<u64>();
+        assert_eq!(draw(Stream::Spawn, &[3]), draw(Stream::Spawn, &[3]));
+        assert_ne!(draw(Stream::Spawn, &[3]), draw(Stream::Spawn, &[4]));
+        assert_ne!(draw(Stream::Spawn, &[3]), draw(Stream::Respawn, &[3]));
+        assert_ne!(draw(Stream::Sensor, &[0, 1]), draw(Stream::Sensor, &[1, 0]));
+        // and forking leaves rng where it was
+        assert_eq!(
+            rng.clone().gen::<u64>(),
+            StdRng::seed_from_u64(7).gen::<u64>()
+        );
+    }
+
+    #[test]
+    fn test_counter_stream() {
+        let mut stream = CounterStream::new(7, Stream::ProcessNoise);
+        let mut fork = stream.fork();
+        let mut copy = stream;
+        assert_eq!(gaussian(&mut stream, 1.0), gaussian(&mut copy, 1.0));
+        assert_ne!(gaussian(&mut stream, 1.0), gaussian(&mut fork, 1.0));
+        assert_ne!(stream.fork_with(1), stream.fork_with(2));
+        assert_ne!(stream.fork_with(1), stream.fork());
+
+        let n = 10000;
+        let draws = (0..n)
+            .map(|_| gaussian(&mut stream, 2.0))
+            .collect::<Vec<_>>();
+        let mean = draws.iter().sum::<f64>() / n as f64;
+        let var = draws.iter().map(|d| (d - mean).powi(2)).sum::<f64>() / n as f64;
+        assert!(mean.abs() < 0.1);
+        assert!((var.sqrt() - 2.0).abs() < 0.1);
+    }
+}
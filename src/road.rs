@@ -1,27 +1,50 @@
-use std::{f64::consts::PI, rc::Rc, u32};
+use std::{
+    cell::{Cell, RefCell},
+    f64::consts::PI,
+    path::Path,
+    sync::Arc,
+    time::Instant,
+    u32,
+};
 
-use itertools::Itertools;
-use nalgebra::{vector, Point2, Point3};
+use itertools::{Either, Itertools};
+use nalgebra::{vector, Point3};
 use parry2d_f64::{
     bounding_volume::AABB,
     math::Isometry,
     na::point,
     query::{self, ClosestPoints},
-    shape::Shape,
 };
 use rand::{prelude::StdRng, Rng};
 use rvx::{Rvx, RvxColor};
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    arg_parameters::Parameters, belief::Belief, car::SpatialCar, cost::Cost,
-    mpdm::make_obstacle_vehicle_policy_belief_states, side_control::SideControlTrait,
-    side_policies::SidePolicy,
+    arg_parameters::Parameters,
+    belief::{Belief, BeliefSampler},
+    car::SpatialCar,
+    cost::Cost,
+    cost_spec::CostMeasure,
+    mpdm::make_obstacle_vehicle_policy_belief_states,
+    occlusion,
+    pedestrian::{Pedestrian, CROSSWALK_WIDTH, PEDESTRIAN_RADIUS},
+    profiling::{self, Subsystem},
+    rng_streams::{self, CounterStream, Stream},
+    road_arena,
+    road_geometry::RoadGeometry,
+    rss,
+    sensor::{self, sensor_enabled},
+    side_control::SideControlTrait,
+    side_policies::{SidePolicy, Trajectory},
+    sim_error::SimError,
+    traffic_light::{draw_intersections, ran_red_light},
+    x_prefilter,
 };
 use crate::{car::PRIUS_MAX_STEER, forward_control::ForwardControlTrait};
 
 use crate::side_policies::SidePolicyTrait;
 
-use crate::car::{Car, BREAKING_ACCEL};
+use crate::car::{Car, ProcessNoise, BREAKING_ACCEL, PRIUS_LENGTH};
 
 pub const LANE_WIDTH: f64 = 3.7;
 pub const ROAD_DASH_LENGTH: f64 = 3.0;
@@ -30,24 +53,163 @@ pub const ROAD_LENGTH: f64 = 400.0;
 
 pub const SIDE_MARGIN: f64 = 0.0;
 
-#[derive(Clone)]
+thread_local! {
+    // calls to take_update_steps on this thread, i.e. forward simulations over one horizon or layer
+    static ROLLOUTS: Cell<u64> = Cell::new(0);
+    // the ego's traces from make_traces, while collecting them for drawing outside of rvx
+    static TRACE_LINES: RefCell<Option<Vec<TraceLine>>> = RefCell::new(None);
+}
+
+// Rollouts run on this thread since the last call
+pub fn take_rollout_count() -> u64 {
+    ROLLOUTS.with(|c| c.replace(0))
+}
+
+// for the rollouts that other threads ran for this one
+pub fn add_rollout_count(rollouts: u64) {
+    ROLLOUTS.with(|c| c.set(c.get() + rollouts));
+}
+
+// An ego trace as flattened x, y points, with how make_traces would color it
+#[derive(Clone, Debug)]
+pub struct TraceLine {
+    pub points: Vec<f64>,
+    pub depth_level: u32,
+    pub crashed: bool,
+    pub not_safe: bool,
+}
+
+pub fn collect_trace_lines(collect: bool) {
+    TRACE_LINES.with(|t| *t.borrow_mut() = if collect { Some(Vec::new()) } else { None });
+}
+
+// The ego traces made on this thread since the last call
+pub fn take_trace_lines() -> Vec<TraceLine> {
+    TRACE_LINES.with(|t| {
+        t.borrow_mut()
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    })
+}
+
+pub fn collecting_trace_lines() -> bool {
+    TRACE_LINES.with(|t| t.borrow().is_some())
+}
+
+// for the traces that other threads made for this one
+pub fn add_trace_lines(lines: Vec<TraceLine>) {
+    TRACE_LINES.with(|t| {
+        if let Some(collected) = t.borrow_mut().as_mut() {
+            collected.extend(lines);
+        }
+    })
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct Road {
-    pub params: Rc<Parameters>,
+    pub params: Arc<Parameters>,
+    pub geometry: Arc<RoadGeometry>,
     pub t: f64,           // current time in seconds
     pub timesteps: usize, // current time in timesteps (related by DT)
     pub cars: Vec<Car>,
-    pub cars_spatial: Vec<SpatialCar>, // This is a copy for spatial queries, updated ONLY at the end of road.update()
-    pub belief: Option<Rc<Belief>>,
+    // The cars sorted by x for the proximity queries, updated ONLY once road.update() has moved
+    // them (and after respawns), so the policies all see the cars from the same time
+    pub cars_spatial: Vec<SpatialCar>,
+    // the farthest any car's bounding box reaches along x from its center, as of cars_spatial
+    pub spatial_reach: f64,
+    // the cars' x by car_i as of cars_spatial, side by side for the x_prefilter
+    #[serde(default)]
+    pub spatial_xs: Vec<f64>,
+    pub pedestrians: Vec<Pedestrian>,
+    pub belief: Option<Arc<Belief>>,
     pub last_ego: Car,
     pub switched_ego_policy: bool,
+    // whether the safety filter is braking for the ego this step
+    pub emergency_braking: bool,
+    // whether the ego is in an RSS dangerous situation, see rss::ego_violation()
+    pub rss_violation: bool,
+    // the time and lane of the ego when it passed the goal station, see goal_cost()
+    pub goal_reached: Option<(f64, i32)>,
+    // the ego's impact speed when it crashed, see impact_speed()
+    pub ego_impact_speed: Option<f64>,
     pub cost: Cost,
     pub car_traces: Option<Vec<Vec<(Point3<f64>, u32)>>>,
     pub last_reset_cost: Cost,
-    pub trajectory_buffer: Vec<Point2<f64>>,
+    // when cost started accruing, for the average objective_mode
+    pub cost_start_t: f64,
+    // scratch space for the crash check's pairs of cars, kept to reuse its allocation
+    #[serde(skip)]
+    pub crash_pairs: Vec<(usize, usize)>,
     pub debug: bool,
     pub is_truth: bool,
     pub sample_id: Option<usize>,
     pub particle: Option<Particle>,
+    // where the draws for process noise are, forked off for the planners' forward sims
+    #[serde(default)]
+    pub process_noise: CounterStream,
+}
+
+impl Clone for Road {
+    fn clone(&self) -> Self {
+        Self {
+            params: self.params.clone(),
+            geometry: self.geometry.clone(),
+            t: self.t,
+            timesteps: self.timesteps,
+            cars: self.cars.clone(),
+            cars_spatial: self.cars_spatial.clone(),
+            spatial_reach: self.spatial_reach,
+            spatial_xs: self.spatial_xs.clone(),
+            pedestrians: self.pedestrians.clone(),
+            belief: self.belief.clone(),
+            last_ego: self.last_ego.clone(),
+            switched_ego_policy: self.switched_ego_policy,
+            emergency_braking: self.emergency_braking,
+            rss_violation: self.rss_violation,
+            goal_reached: self.goal_reached,
+            ego_impact_speed: self.ego_impact_speed,
+            cost: self.cost,
+            car_traces: self.car_traces.clone(),
+            last_reset_cost: self.last_reset_cost,
+            cost_start_t: self.cost_start_t,
+            crash_pairs: Vec::new(),
+            debug: self.debug,
+            is_truth: self.is_truth,
+            sample_id: self.sample_id,
+            particle: self.particle.clone(),
+            process_noise: self.process_noise,
+        }
+    }
+
+    // Reuses the existing vectors' allocations, which is what makes recycled roads cheap to reuse
+    fn clone_from(&mut self, source: &Self) {
+        self.params.clone_from(&source.params);
+        self.geometry.clone_from(&source.geometry);
+        self.t = source.t;
+        self.timesteps = source.timesteps;
+        self.cars.clone_from(&source.cars);
+        self.cars_spatial.clone_from(&source.cars_spatial);
+        self.spatial_reach = source.spatial_reach;
+        self.spatial_xs.clone_from(&source.spatial_xs);
+        self.pedestrians.clone_from(&source.pedestrians);
+        self.belief.clone_from(&source.belief);
+        self.last_ego.clone_from(&source.last_ego);
+        self.switched_ego_policy = source.switched_ego_policy;
+        self.emergency_braking = source.emergency_braking;
+        self.rss_violation = source.rss_violation;
+        self.goal_reached = source.goal_reached;
+        self.ego_impact_speed = source.ego_impact_speed;
+        self.cost = source.cost;
+        self.car_traces.clone_from(&source.car_traces);
+        self.last_reset_cost = source.last_reset_cost;
+        self.cost_start_t = source.cost_start_t;
+        self.debug = source.debug;
+        self.is_truth = source.is_truth;
+        self.sample_id = source.sample_id;
+        self.particle.clone_from(&source.particle);
+        self.process_noise = source.process_noise;
+    }
 }
 
 fn range_dist(low_a: f64, high_a: f64, low_b: f64, high_b: f64) -> f64 {
@@ -62,35 +224,67 @@ fn range_dist(low_a: f64, high_a: f64, low_b: f64, high_b: f64) -> f64 {
     // sep
 }
 
-fn logistic(x: f64) -> f64 {
+// how far a car's bounding box reaches along x from its x, which is the front of the car
+fn x_reach(car: &Car) -> f64 {
+    let aabb = car.aabb();
+    (car.x() - aabb.mins[0]).max(aabb.maxs[0] - car.x())
+}
+
+// an ego slower than this (m/s) is treated as this fast for when it reaches the goal
+const MIN_GOAL_VEL: f64 = 1.0;
+// m from a car that picking a point still finds it
+const PICK_DIST: f64 = 2.0;
+
+pub fn logistic(x: f64) -> f64 {
     1.0 / (1.0 + (-x).exp())
 }
 
-fn change_range(x: f64, a_low: f64, a_high: f64, b_low: f64, b_high: f64) -> f64 {
+pub fn change_range(x: f64, a_low: f64, a_high: f64, b_low: f64, b_high: f64) -> f64 {
     b_low + (b_high - b_low) * (x - a_low) / (a_high - a_low)
 }
 
+// The actuators respond to a command with a first-order lag of time_constant,
+// or right away with no time constant
+fn first_order_lag(current: f64, command: f64, time_constant: f64, dt: f64) -> f64 {
+    if time_constant <= 0.0 {
+        command
+    } else {
+        current + (command - current) * (dt / time_constant).min(1.0)
+    }
+}
+
 impl Road {
-    pub fn new(params: Rc<Parameters>) -> Self {
+    pub fn new(params: Arc<Parameters>) -> Self {
         let ego_car = Car::new(&params, 0, 0);
+        let process_noise = CounterStream::new(params.rng_seed, Stream::ProcessNoise);
 
         Self {
             t: 0.0,
             timesteps: 0,
             last_ego: ego_car.clone(),
             cars_spatial: vec![SpatialCar::from(&ego_car)].into_iter().collect(),
+            spatial_reach: x_reach(&ego_car),
+            spatial_xs: vec![ego_car.x()],
             cars: vec![ego_car],
+            pedestrians: Vec::new(),
             belief: None,
             switched_ego_policy: false,
+            emergency_braking: false,
+            rss_violation: false,
+            goal_reached: None,
+            ego_impact_speed: None,
             cost: Cost::new(1.0, 1.0),
             debug: !params.run_fast,
             car_traces: Some(Vec::new()),
             last_reset_cost: Cost::new(1.0, 1.0),
-            trajectory_buffer: Vec::new(),
+            cost_start_t: 0.0,
+            crash_pairs: Vec::new(),
+            geometry: Arc::new(RoadGeometry::parse(&params.road_geometry)),
             params,
             is_truth: true,
             sample_id: None,
             particle: None,
+            process_noise,
         }
     }
 
@@ -107,68 +301,149 @@ impl Road {
     //     }
     // }
 
-    pub fn add_random_car(&mut self, rng: &mut StdRng) {
-        for _ in 0..100 {
+    pub fn add_random_car(&mut self, rng: &mut StdRng) -> Result<(), SimError> {
+        let mut car_rng;
+        let rng = if self.params.rng_streams {
+            car_rng = rng_streams::fork(rng, Stream::Spawn, &[self.cars.len() as u64]);
+            &mut car_rng
+        } else {
+            rng
+        };
+        let tries = 100;
+        for _ in 0..tries {
             let mut car = Car::random_new(&self.params, self.cars.len(), rng);
             car.vel = 0.0;
-            if self.collides_any_car(&car) {
+            if self.collides_any_car(&car) || !self.lane_exists(car.current_lane(), car.x()) {
                 continue;
             }
             self.cars.push(car);
-            return;
+            return Ok(());
         }
-        panic!("Could not place a car without it colliding... too many cars or bad collision detection?");
+        Err(SimError::CarPlacement {
+            car_i: self.cars.len(),
+            tries,
+        })
+    }
+
+    // The whole road at this timestep as JSON, for load_snapshot to carry on from exactly here.
+    // Its parameters, geometry and belief are saved by value, so a loaded road doesn't share them.
+    pub fn save_snapshot(&self, path: &Path) -> std::io::Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, serde_json::to_string(self)?)
+    }
+
+    pub fn load_snapshot(path: &str) -> Result<Self, SimError> {
+        let load_error = |message: String| SimError::Load {
+            path: path.to_owned(),
+            message,
+        };
+        let contents = std::fs::read_to_string(path).map_err(|e| load_error(e.to_string()))?;
+        serde_json::from_str(&contents).map_err(|e| load_error(e.to_string()))
     }
 
     pub fn init_belief(&mut self) {
         let n_policies = make_obstacle_vehicle_policy_belief_states(&self.params).len();
-        self.belief = Some(Rc::new(Belief::uniform(self.cars.len(), n_policies)));
+        self.belief = Some(Arc::new(Belief::uniform(self.cars.len(), n_policies)));
     }
 
-    pub fn update_belief(&mut self) {
-        let mut belief_rc = self.belief.take().unwrap();
-        let belief = Rc::get_mut(&mut belief_rc).expect("update_belief should only be called when it has exclusive access to the top-level road");
-        belief.update(self);
+    // from what the ego's sensor sees, when it has one, and otherwise the truth
+    pub fn update_belief(&mut self, sensor_rng: &mut StdRng) -> Result<(), SimError> {
+        let mut belief_rc = self.belief.take().ok_or(SimError::NoBelief)?;
+        let belief = match Arc::get_mut(&mut belief_rc) {
+            Some(belief) => belief,
+            None => {
+                self.belief = Some(belief_rc);
+                return Err(SimError::SharedBelief);
+            }
+        };
+        if sensor_enabled(&self.params) {
+            let observation = sensor::observe(self, sensor_rng);
+            belief.update(&observation.road, Some(&observation.visible));
+        } else {
+            belief.update(self, None);
+        }
 
         if self.super_debug() && self.params.obstacle_car_debug {
             if let Some(debug_car_i) = self.params.debug_car_i {
                 let s = &self;
                 let bel = belief.get_all(debug_car_i);
-                eprintln_f!("{s.timesteps}: belief about {s.params.debug_car_i:?}: {bel:.2?}")
+                debug_f!("{s.timesteps}: belief about {s.params.debug_car_i:?}: {bel:.2?}")
             }
         }
 
         self.belief = Some(belief_rc);
+        Ok(())
+    }
+
+    // Approximate heap memory owned by this road, not counting the shared params and belief
+    pub fn heap_bytes(&self) -> usize {
+        use std::mem::size_of;
+        let traces_bytes = self.car_traces.as_ref().map_or(0, |traces| {
+            traces.capacity() * size_of::<Vec<(Point3<f64>, u32)>>()
+                + traces
+                    .iter()
+                    .map(|t| t.capacity() * size_of::<(Point3<f64>, u32)>())
+                    .sum::<usize>()
+        });
+        self.cars.capacity() * size_of::<Car>()
+            + self.cars_spatial.capacity() * size_of::<SpatialCar>()
+            + self.spatial_xs.capacity() * size_of::<f64>()
+            + self.pedestrians.capacity() * size_of::<Pedestrian>()
+            + self.crash_pairs.capacity() * size_of::<(usize, usize)>()
+            + traces_bytes
     }
 
     pub fn clone_without_cars(&self) -> Self {
         Self {
             params: self.params.clone(),
+            geometry: self.geometry.clone(),
             t: self.t,
             timesteps: self.timesteps,
             cars: Vec::new(),
             cars_spatial: Vec::new(),
+            spatial_reach: 0.0,
+            spatial_xs: Vec::new(),
+            pedestrians: self.pedestrians.clone(),
             belief: self.belief.clone(),
             last_ego: self.last_ego.clone(),
             switched_ego_policy: false,
+            emergency_braking: false,
+            rss_violation: false,
+            goal_reached: self.goal_reached,
+            ego_impact_speed: self.ego_impact_speed,
             cost: self.cost,
             car_traces: None,
             last_reset_cost: self.last_reset_cost,
-            trajectory_buffer: Vec::new(),
+            cost_start_t: self.cost_start_t,
+            crash_pairs: Vec::new(),
             debug: self.debug,
             is_truth: false,
             sample_id: self.sample_id,
             particle: None,
+            process_noise: self.process_noise.fork(),
         }
     }
 
     pub fn sim_estimate(&self) -> Self {
-        let mut road = self.clone_without_cars();
-        road.cars = self.cars.iter().map(|c| c.sim_estimate()).collect();
-        // preserve the ego-car
-        road.cars[0] = self.cars[0].clone();
+        // same as clone_without_cars, but reusing a recycled road's vectors when there is one
+        let mut road = road_arena::clone_road(self);
+        road.cars_spatial.clear();
+        road.switched_ego_policy = false;
+        road.emergency_braking = false;
+        road.rss_violation = false;
+        road.car_traces = None;
+        road.is_truth = false;
+        road.particle = None;
+        // so the forward sims don't know the noise the truth has coming
+        road.process_noise = self.process_noise.fork();
+        // preserve the ego-car, the others are estimates
+        for car in road.cars.iter_mut().skip(1) {
+            car.make_sim_estimate();
+        }
         road.debug = false;
-        road.cost = Cost::new(self.params.cost.discount_factor, 1.0);
+        road.reset_rollout_cost();
         road
     }
 
@@ -181,48 +456,149 @@ impl Road {
             road.cars[keep_car_i] = self.cars[keep_car_i].clone();
         }
         road.debug = false;
-        road.cost = Cost::new(self.params.cost.discount_factor, 1.0);
+        road.reset_rollout_cost();
         road
     }
 
     pub fn sample_belief(&self, rng: &mut StdRng) -> Self {
-        let belief = self.belief.clone().unwrap();
+        let start = profiling::start(&self.params);
+        let sampler = self.belief.as_ref().unwrap().sampler();
         let policies = make_obstacle_vehicle_policy_belief_states(&self.params);
 
         let mut road = self.sim_estimate();
+        road.sample_policies(&sampler, &policies, rng);
+
+        profiling::stop(Subsystem::BeliefSampling, start);
+        road
+    }
+
+    // n samples of the belief, the same as from sample_belief with the rng forked for each
+    // sample with rng_streams, but building the belief's distributions and the sim estimate
+    // just once, and only copying the sampled policies into each copy of it
+    pub fn sample_beliefs(&self, rng: &mut StdRng, n: usize) -> Vec<Self> {
+        let start = profiling::start(&self.params);
+        let sampler = self.belief.as_ref().unwrap().sampler();
+        let policies = make_obstacle_vehicle_policy_belief_states(&self.params);
+
+        let mut template = Some(self.sim_estimate());
+        let roads = (0..n)
+            .map(|i| {
+                let mut road = if i + 1 < n {
+                    road_arena::clone_road(template.as_ref().unwrap())
+                } else {
+                    template.take().unwrap()
+                };
+                if self.params.rng_streams {
+                    let mut sample_rng = rng_streams::fork(rng, Stream::Belief, &[i as u64]);
+                    road.sample_policies(&sampler, &policies, &mut sample_rng);
+                } else {
+                    road.sample_policies(&sampler, &policies, rng);
+                }
+                road
+            })
+            .collect();
+        // with n = 0
+        if let Some(template) = template {
+            road_arena::recycle_road(template);
+        }
 
-        let sample = belief.sample(rng);
+        profiling::stop(Subsystem::BeliefSampling, start);
+        roads
+    }
 
-        // sample policies from the belief state
-        for (car_i, car) in road.cars.iter_mut().enumerate().skip(1) {
-            car.side_policy = Some(policies[sample[car_i]].clone());
+    // sample the obstacle cars' policies from the belief state
+    fn sample_policies(
+        &mut self,
+        sampler: &BeliefSampler,
+        policies: &[SidePolicy],
+        rng: &mut StdRng,
+    ) {
+        // each sample's rollouts get noise of their own, keyed on (but not advancing) its rng
+        self.process_noise = self.process_noise.fork_with(rng.clone().gen());
+
+        // the ego gets a sample too, so the draws stay the same as over the whole belief
+        for car_i in 0..sampler.n_cars() {
+            let policy_i = if self.params.rng_streams {
+                let mut car_rng = rng_streams::fork(rng, Stream::Belief, &[car_i as u64]);
+                sampler.sample_car(car_i, &mut car_rng)
+            } else {
+                sampler.sample_car(car_i, rng)
+            };
+            if car_i == 0 {
+                continue;
+            }
+            // reusing the estimate's policy allocations
+            self.cars[car_i].side_policy.clone_from(&policies[policy_i]);
         }
 
-        road
+        // the ego only knows where the cars it can't see are from before they were hidden,
+        // which this doesn't model, but it can allow for ones it never saw
+        if self.params.occlusion.phantoms > 0 {
+            occlusion::add_phantom_cars(self);
+        }
     }
 
     pub fn ego_policy(&self) -> &SidePolicy {
-        self.cars[0].side_policy.as_ref().unwrap()
+        &self.cars[0].side_policy
     }
 
-    pub fn set_ego_policy(&mut self, policy: SidePolicy) {
-        self.cars[0].side_policy = Some(policy);
+    // Cloned into the ego's current policy, which keeps its boxes when it's the same kind
+    pub fn set_ego_policy(&mut self, policy: &SidePolicy) {
+        self.cars[0].side_policy.clone_from(policy);
         self.switched_ego_policy = true;
     }
 
-    pub fn set_ego_policy_not_switched(&mut self, policy: SidePolicy) {
-        self.cars[0].side_policy = Some(policy);
+    pub fn set_ego_policy_not_switched(&mut self, policy: &SidePolicy) {
+        self.cars[0].side_policy.clone_from(policy);
     }
 
     pub fn take_update_steps(&mut self, t: f64, dt: f64) {
+        self.take_update_steps_with(t, dt, |_, _| {});
+    }
+
+    // take_update_steps, also summing car_i's own discounted cost over the rollout:
+    // its builtin running costs (see car_running_cost) and crash.base_weight when it crashes
+    pub fn take_update_steps_car_cost(&mut self, t: f64, dt: f64, car_i: usize) -> f64 {
+        let mut cost = 0.0;
+        let mut discount = 1.0;
+        let mut crashed = self.cars[car_i].crashed;
+        self.take_update_steps_with(t, dt, |road, dt| {
+            if road.cars[car_i].crashed {
+                if !crashed {
+                    cost += road.params.crash.base_weight * discount;
+                    crashed = true;
+                }
+            } else {
+                cost += road.car_running_cost(car_i, dt) * discount;
+            }
+            discount *= road.cost.discount_factor.powf(dt);
+        });
+        cost
+    }
+
+    fn take_update_steps_with(&mut self, t: f64, dt: f64, mut after_step: impl FnMut(&Self, f64)) {
+        ROLLOUTS.with(|c| c.set(c.get() + 1));
+        let start = self.profile_start();
         // For example, w/ t = 1.0, dt = 0.4 we get steps [0.2, 0.4, 0.4]
         let n_full_steps = (t / dt).floor() as i32;
         let remaining = t - dt * n_full_steps as f64;
         if remaining > 1e-6 {
             self.update(remaining);
+            after_step(self, remaining);
         }
         for _ in 0..n_full_steps {
             self.update(dt);
+            after_step(self, dt);
+        }
+        profiling::stop(Subsystem::ForwardSims, start);
+    }
+
+    // the start of a timed section of a forward sim, see profiling.rs
+    fn profile_start(&self) -> Option<Instant> {
+        if self.is_truth {
+            None
+        } else {
+            profiling::start(&self.params)
         }
     }
 
@@ -240,7 +616,12 @@ impl Road {
         high_x: f64,
     ) -> bool {
         assert!(low_x < high_x);
-        for c in self.cars.iter() {
+        if self.lane_closed_between(lane_i, low_x, high_x) {
+            return false;
+        }
+        // spatial_reach covers the cars' half lengths
+        let (mid_x, half_len) = ((low_x + high_x) * 0.5, (high_x - low_x) * 0.5);
+        for c in self.cars_near_x(mid_x, half_len + self.spatial_reach) {
             if c.car_i == skip_car_i {
                 continue;
             }
@@ -276,25 +657,29 @@ impl Road {
         let car_a = &self.cars[car_i1];
         let car_b = &self.cars[car_i2];
 
-        if (car_a.x() - car_b.x()).abs() > (car_a.length + car_b.length) / 2.0 {
+        // x() is the front, so with cars of different lengths only their extents can rule it out
+        let (aabb_a, aabb_b) = (car_a.aabb(), car_b.aabb());
+        if aabb_a.mins[0] > aabb_b.maxs[0] || aabb_b.mins[0] > aabb_a.maxs[0] {
             return false;
         }
 
-        parry2d_f64::query::intersection_test(
-            &car_a.pose(),
-            &car_a.shape(),
-            &car_b.pose(),
-            &car_b.shape(),
-        )
-        .unwrap()
+        if self.params.f32_rollout_math && !self.is_truth {
+            car_a.intersects_f32(car_b)
+        } else {
+            car_a.intersects(car_b)
+        }
     }
 
+    // A scan of all the cars, since placing cars changes them between updates of cars_spatial,
+    // but only the cars that overlap along x get the exact test
     pub fn collides_any_car(&self, car: &Car) -> bool {
-        let pose = car.pose();
-        let shape = car.shape();
+        let aabb = car.aabb();
         for c in self.cars.iter() {
-            if parry2d_f64::query::intersection_test(&pose, &shape, &c.pose(), &c.shape()).unwrap()
-            {
+            let other_aabb = c.aabb();
+            if other_aabb.mins[0] > aabb.maxs[0] || other_aabb.maxs[0] < aabb.mins[0] {
+                continue;
+            }
+            if car.intersects(c) {
                 return true;
             }
         }
@@ -329,9 +714,12 @@ impl Road {
 
         // for (i, c) in self.cars.iter().enumerate() {
         let start_spacial_x = car.spatial_x();
-        for spatial_car in &self.cars_spatial {
-            if spatial_car.x < start_spacial_x {
-                continue;
+        let start = self.cars_spatial.partition_point(|s| s.x < start_spacial_x);
+        for spatial_car in &self.cars_spatial[start..] {
+            // the rest start even farther ahead than the closest so far
+            let lowest_min_x = spatial_car.x.saturating_sub(1) as f64 / 1000.0 - self.spatial_reach;
+            if lowest_min_x - aabb.maxs[0] >= min_dist {
+                break;
             }
 
             let i = spatial_car.car_i as usize;
@@ -361,12 +749,12 @@ impl Road {
 
                 if self.params.separation_debug {
                     if self.super_debug() && car.is_ego() {
-                        eprintln_f!("ego from {i} {side_sep=:.2}, {dist=:.2}");
+                        trace_f!("ego from {i} {side_sep=:.2}, {dist=:.2}");
                     } else if self.super_debug()
                         && c.is_ego()
                         && self.params.debug_car_i == Some(car.car_i)
                     {
-                        eprintln_f!("{car.car_i} from ego {side_sep=:.2}, {dist=:.2}");
+                        trace_f!("{car.car_i} from ego {side_sep=:.2}, {dist=:.2}");
                     }
                 }
             }
@@ -375,7 +763,19 @@ impl Road {
         Some((min_dist, min_car_i?))
     }
 
-    fn min_unsafe_dist(&self, car_i: usize) -> Option<f64> {
+    // Time until the ego reaches the car ahead in its lane at their current velocities,
+    // or None if it isn't closing in on one
+    pub fn ego_time_to_collision(&self) -> Option<f64> {
+        let ego = &self.cars[0];
+        let (dist, car_i) = self.dist_clear_ahead_in_lane(0, ego.current_lane())?;
+        let closing_vel = ego.vel - self.cars[car_i].vel;
+        if closing_vel <= 0.0 {
+            return None;
+        }
+        Some(dist.max(0.0) / closing_vel)
+    }
+
+    pub fn min_unsafe_dist(&self, car_i: usize) -> Option<f64> {
         let safety_margin_high = self.params.cost.safety_margin_high;
 
         let car = &self.cars[car_i];
@@ -385,8 +785,11 @@ impl Road {
 
         let pose = car.pose();
         let shape = car.shape();
-        let aabb = shape.compute_aabb(&pose);
-        for (i, c) in self.cars.iter().enumerate() {
+        let aabb = car.aabb();
+        let range = self.spatial_range(car.x() - dist_thresh, car.x() + dist_thresh);
+        for spatial_car in &self.cars_spatial[range] {
+            let i = spatial_car.car_i as usize;
+            let c = &self.cars[i];
             if i == car_i {
                 continue;
             }
@@ -394,7 +797,7 @@ impl Road {
                 continue;
             }
 
-            let other_aabb = c.shape().compute_aabb(&c.pose());
+            let other_aabb = c.aabb();
             let side_sep = range_dist(
                 aabb.mins[1],
                 aabb.maxs[1],
@@ -443,8 +846,24 @@ impl Road {
         min_dist
     }
 
+    // distance from car_i to the closest crossing pedestrian within the pedestrians' safety_margin
+    pub fn min_pedestrian_dist(&self, car_i: usize) -> Option<f64> {
+        let margin = self.params.pedestrians.safety_margin;
+        let car = &self.cars[car_i];
+        self.pedestrians
+            .iter()
+            .filter(|p| {
+                p.is_crossing()
+                    && p.x > car.x() - car.length - PEDESTRIAN_RADIUS - margin
+                    && p.x < car.x() + PEDESTRIAN_RADIUS + margin
+            })
+            .map(|p| query::distance(&car.pose(), &car.shape(), &p.pose(), &p.shape()).unwrap())
+            .filter(|&dist| dist < margin)
+            .min_by(|a, b| a.partial_cmp(b).unwrap())
+    }
+
     fn update_inner(&mut self, dt: f64) {
-        let mut trajectory = std::mem::take(&mut self.trajectory_buffer);
+        let mut trajectory = Trajectory::default();
 
         for car_i in 0..self.cars.len() {
             if self.cars[car_i].crashed {
@@ -452,46 +871,98 @@ impl Road {
             }
             // policy
             {
-                let mut policy = self.cars[car_i].side_policy.take().unwrap();
+                let start = self.profile_start();
+                // swapped out for the zero-sized open loop policy while it borrows the road
+                let mut policy = std::mem::take(&mut self.cars[car_i].side_policy);
                 policy.precheck(self, dt);
                 self.cars[car_i].target_lane_i = policy.choose_target_lane(self, car_i);
                 self.cars[car_i].target_follow_time = policy.choose_follow_time(self, car_i);
                 self.cars[car_i].target_vel = policy.choose_vel(self, car_i);
                 policy.choose_trajectory(self, car_i, &mut trajectory);
-                self.cars[car_i].side_policy = Some(policy);
+                self.cars[car_i].side_policy = policy;
+                profiling::stop(Subsystem::PolicyEvaluation, start);
             }
 
             // forward control
             {
-                let mut control = self.cars[car_i].forward_control.take().unwrap();
+                let mut control = std::mem::take(&mut self.cars[car_i].forward_control);
                 let mut accel = control.choose_accel(self, car_i);
+                // the safety filter on the true road brakes as hard as it can, whatever
+                // the policy, when the ego is about to run into the car ahead
+                if car_i == 0 && self.is_truth && self.params.safety_filter.enabled {
+                    let ttc_threshold = self.params.safety_filter.ttc_threshold;
+                    self.emergency_braking = self
+                        .ego_time_to_collision()
+                        .map_or(false, |ttc| ttc < ttc_threshold);
+                    if self.emergency_braking {
+                        accel = -BREAKING_ACCEL;
+                    }
+                }
 
+                let actuators = &self.params.actuators;
                 let car = &mut self.cars[car_i];
                 accel = accel.max(-BREAKING_ACCEL).min(car.preferred_vel);
-                car.vel = (car.vel + accel * dt).max(0.0).min(car.preferred_vel);
-                self.cars[car_i].forward_control = Some(control);
+                car.accel = first_order_lag(car.accel, accel, actuators.accel_time_constant, dt);
+                car.vel = (car.vel + car.accel * dt).max(0.0).min(car.preferred_vel);
+                self.cars[car_i].forward_control = control;
             }
 
             // side control
             {
-                let mut control = self.cars[car_i].side_control.take().unwrap();
-                let target_steer = control.choose_steer(self, car_i, &trajectory);
+                let mut control = std::mem::take(&mut self.cars[car_i].side_control);
+                // the controllers work in the road's frame, so they don't see its curve
+                let target_steer = control.choose_steer(self, car_i, &trajectory)
+                    + self.geometry.curvature(self.cars[car_i].x()) * self.cars[car_i].length;
 
+                let actuators = &self.params.actuators;
                 let car = &mut self.cars[car_i];
-                car.steer = target_steer.max(-PRIUS_MAX_STEER).min(PRIUS_MAX_STEER);
-                self.cars[car_i].side_control = Some(control);
+                let target_steer = target_steer.max(-PRIUS_MAX_STEER).min(PRIUS_MAX_STEER);
+                let mut steer =
+                    first_order_lag(car.steer, target_steer, actuators.steer_time_constant, dt);
+                if actuators.max_steer_rate > 0.0 {
+                    let max_change = actuators.max_steer_rate * dt;
+                    steer = steer
+                        .max(car.steer - max_change)
+                        .min(car.steer + max_change);
+                }
+                car.steer = steer;
+                self.cars[car_i].side_control = control;
             }
         }
 
+        let geometry = &self.geometry;
+        let dynamics = &self.params.ego_dynamics;
+        let f32_sim = self.params.f32_rollout_math && !self.is_truth;
+        let pn = &self.params.process_noise;
+        let noisy = if self.is_truth { pn.truth } else { pn.rollouts };
         for car in self.cars.iter_mut() {
-            if !car.crashed {
-                car.update(dt);
+            if car.crashed {
+                continue;
+            }
+            let noise = if noisy {
+                ProcessNoise {
+                    accel: rng_streams::gaussian(&mut self.process_noise, pn.accel_std),
+                    steer: rng_streams::gaussian(&mut self.process_noise, pn.steer_std),
+                }
+            } else {
+                ProcessNoise::default()
+            };
+            if f32_sim {
+                car.update_f32(dt, geometry.curvature(car.x()), dynamics, noise);
+            } else {
+                car.update(dt, geometry.curvature(car.x()), dynamics, noise);
             }
         }
 
+        let t = self.t + dt;
+        for pedestrian in self.pedestrians.iter_mut() {
+            pedestrian.update(&self.params, &self.cars, t, dt);
+        }
+        self.check_pedestrian_crashes();
+
         if self.params.ego_state_debug && self.super_debug() {
             let ego = &self.cars[0];
-            eprintln!(
+            tracing::debug!(
                 "{}: ego x: {:.2}, y: {:.2}, vel: {:.10}",
                 self.timesteps,
                 ego.x(),
@@ -500,17 +971,26 @@ impl Road {
             );
         }
 
+        // forward simulations stop recording traces once the planning call has used its memory budget
+        if !self.is_truth && self.car_traces.is_some() {
+            let bytes = self.cars.len() * std::mem::size_of::<(Point3<f64>, u32)>();
+            if !road_arena::reserve_trace_bytes(&self.params, bytes) {
+                self.car_traces = None;
+            }
+        }
+
         if let Some(traces) = self.car_traces.as_mut() {
             traces.resize(self.cars.len(), Vec::new());
 
             for (car_i, car) in self.cars.iter_mut().enumerate() {
                 if !car.crashed {
-                    let policy_id = car.side_policy.as_ref().unwrap().policy_id();
+                    let policy_id = car.side_policy.policy_id();
                     traces[car_i].push((point!(car.x(), car.y(), car.theta()), policy_id));
                 }
             }
         }
 
+        let start = self.profile_start();
         if self.params.only_crashes_with_ego {
             let i1 = 0;
             for i2 in 1..self.cars.len() {
@@ -523,11 +1003,12 @@ impl Road {
                 }
                 if self.collides_between(i1, i2) {
                     if self.super_debug() {
-                        eprintln!();
-                        eprintln!("{}: CRASH between:", self.timesteps);
-                        eprintln!("{:.2?}", self.cars[i1]);
-                        eprintln!("{:.2?}", self.cars[i2]);
-                        eprintln!();
+                        tracing::warn!(
+                            "{}: CRASH between:\n{:.2?}\n{:.2?}",
+                            self.timesteps,
+                            self.cars[i1],
+                            self.cars[i2]
+                        );
                     }
 
                     if self.is_truth || !self.params.only_ego_crashes_in_forward_sims || i1 == 0 {
@@ -536,20 +1017,38 @@ impl Road {
                     if self.is_truth || !self.params.only_ego_crashes_in_forward_sims || i2 == 0 {
                         self.cars[i2].crashed = true;
                     }
+                    self.record_ego_impact(i1, i2);
                 }
             }
         } else {
-            for (i1, i2) in (0..self.cars.len()).tuple_combinations() {
+            // sweep and prune: only the cars whose bounding boxes might overlap along x,
+            // checked in the same order as all the pairs would be
+            self.update_cars_spatial();
+            let window = ((2.0 * self.spatial_reach * 1000.0) as i32).saturating_add(2);
+            let mut pairs = std::mem::take(&mut self.crash_pairs);
+            pairs.clear();
+            for (k, a) in self.cars_spatial.iter().enumerate() {
+                for b in self.cars_spatial[k + 1..].iter() {
+                    if b.x - a.x > window {
+                        break;
+                    }
+                    let (i1, i2) = (a.car_i.min(b.car_i), a.car_i.max(b.car_i));
+                    pairs.push((i1 as usize, i2 as usize));
+                }
+            }
+            pairs.sort_unstable();
+            for &(i1, i2) in pairs.iter() {
                 if self.cars[i1].crashed && self.cars[i2].crashed {
                     continue;
                 }
                 if self.collides_between(i1, i2) {
                     if self.super_debug() {
-                        eprintln!();
-                        eprintln!("{}: CRASH between:", self.timesteps);
-                        eprintln!("{:.2?}", self.cars[i1]);
-                        eprintln!("{:.2?}", self.cars[i2]);
-                        eprintln!();
+                        tracing::warn!(
+                            "{}: CRASH between:\n{:.2?}\n{:.2?}",
+                            self.timesteps,
+                            self.cars[i1],
+                            self.cars[i2]
+                        );
                     }
 
                     if self.is_truth || !self.params.only_ego_crashes_in_forward_sims || i1 == 0 {
@@ -558,18 +1057,130 @@ impl Road {
                     if self.is_truth || !self.params.only_ego_crashes_in_forward_sims || i2 == 0 {
                         self.cars[i2].crashed = true;
                     }
+                    self.record_ego_impact(i1, i2);
+                }
+            }
+            self.crash_pairs = pairs;
+        }
+        profiling::stop(Subsystem::CollisionChecks, start);
+    }
+
+    // The closing speed of the two cars along the normal of their contact,
+    // so a sideswipe is gentler than running into the same car head on
+    pub fn impact_speed(&self, car_i1: usize, car_i2: usize) -> f64 {
+        let car_a = &self.cars[car_i1];
+        let car_b = &self.cars[car_i2];
+        let vel = |c: &Car| vector!(c.vel * c.theta().cos(), c.vel * c.theta().sin());
+        let relative_vel = vel(car_a) - vel(car_b);
+        let normal = query::contact(
+            &car_a.pose(),
+            &car_a.shape(),
+            &car_b.pose(),
+            &car_b.shape(),
+            0.0,
+        )
+        .unwrap()
+        .map_or_else(
+            || (car_b.pose().translation.vector - car_a.pose().translation.vector).normalize(),
+            |contact| contact.normal1.into_inner(),
+        );
+        relative_vel.dot(&normal).max(0.0)
+    }
+
+    fn record_ego_impact(&mut self, car_i1: usize, car_i2: usize) {
+        if car_i1 == 0 && self.cars[0].crashed && self.ego_impact_speed.is_none() {
+            self.ego_impact_speed = Some(self.impact_speed(car_i1, car_i2));
+        }
+    }
+
+    fn check_pedestrian_crashes(&mut self) {
+        for car_i in 0..self.cars.len() {
+            if self.cars[car_i].crashed
+                || !self.is_truth && self.params.only_ego_crashes_in_forward_sims && car_i != 0
+            {
+                continue;
+            }
+            let car = &self.cars[car_i];
+            for pedestrian in self.pedestrians.iter_mut() {
+                if pedestrian.x < car.x() - car.length - PEDESTRIAN_RADIUS
+                    || pedestrian.x > car.x() + PEDESTRIAN_RADIUS
+                {
+                    continue;
+                }
+                if query::intersection_test(
+                    &car.pose(),
+                    &car.shape(),
+                    &pedestrian.pose(),
+                    &pedestrian.shape(),
+                )
+                .unwrap()
+                {
+                    if self.debug {
+                        tracing::warn!(
+                            "{}: CRASH between car {} and a pedestrian at x = {:.2}, y = {:.2}",
+                            self.timesteps,
+                            car_i,
+                            pedestrian.x,
+                            pedestrian.y
+                        );
+                    }
+                    pedestrian.hit = true;
+                    self.cars[car_i].crashed = true;
+                    if car_i == 0 && self.ego_impact_speed.is_none() {
+                        self.ego_impact_speed = Some(self.cars[0].vel);
+                    }
+                    break;
+                }
+            }
+        }
+    }
+
+    // Sorts the cars by x into cars_spatial, for the proximity queries. With the same cars as
+    // before, which have only moved a little since, it updates their x in place and the
+    // insertion sort only has a few swaps to make, so it's close to linear.
+    pub fn update_cars_spatial(&mut self) {
+        if self.cars_spatial.len() == self.cars.len() {
+            for spatial_car in self.cars_spatial.iter_mut() {
+                spatial_car.x = self.cars[spatial_car.car_i as usize].spatial_x();
+            }
+            for i in 1..self.cars_spatial.len() {
+                let mut j = i;
+                while j > 0 && self.cars_spatial[j - 1].x > self.cars_spatial[j].x {
+                    self.cars_spatial.swap(j - 1, j);
+                    j -= 1;
                 }
             }
+        } else {
+            self.cars_spatial.clear();
+            self.cars_spatial
+                .extend(self.cars.iter().map(SpatialCar::from));
+            self.cars_spatial.sort_unstable_by(|a, b| a.x.cmp(&b.x));
         }
+        self.spatial_reach = self.cars.iter().map(x_reach).fold(0.0, f64::max);
+        self.spatial_xs.clear();
+        self.spatial_xs.extend(self.cars.iter().map(|c| c.x()));
+    }
 
-        self.trajectory_buffer = trajectory;
+    // The cars that might be within reach of x, by the x_prefilter over the cars as of
+    // cars_spatial, or all of them if cars have come or gone since
+    pub fn cars_near_x(&self, x: f64, reach: f64) -> impl Iterator<Item = &Car> {
+        if self.spatial_xs.len() == self.cars.len() {
+            Either::Left(
+                x_prefilter::within_reach(&self.spatial_xs, x, reach).map(move |i| &self.cars[i]),
+            )
+        } else {
+            Either::Right(self.cars.iter())
+        }
     }
 
-    fn update_cars_spatial(&mut self) {
-        self.cars_spatial.clear();
-        self.cars_spatial
-            .extend(self.cars.iter().map(SpatialCar::from));
-        self.cars_spatial.sort_unstable_by(|a, b| a.x.cmp(&b.x));
+    // Where the cars of cars_spatial that might reach low_x start, and end for high_x,
+    // with a millimeter to spare for its rounding
+    fn spatial_range(&self, low_x: f64, high_x: f64) -> std::ops::Range<usize> {
+        let low = (((low_x - self.spatial_reach) * 1000.0) as i32).saturating_sub(1);
+        let high = (((high_x + self.spatial_reach) * 1000.0) as i32).saturating_add(1);
+        let start = self.cars_spatial.partition_point(|s| s.x < low);
+        let end = self.cars_spatial.partition_point(|s| s.x <= high);
+        start..end.max(start)
     }
 
     pub fn update(&mut self, dt: f64) {
@@ -585,12 +1196,105 @@ impl Road {
         self.t += dt;
         self.timesteps += 1;
 
-        self.update_cost(dt);
-
+        // before the cost, which measures the distances between the cars where they are now
         self.update_cars_spatial();
+
+        self.update_cost(dt);
     }
 
     fn update_cost(&mut self, dt: f64) {
+        // forward sims only need it when it costs something
+        let rss = &self.params.rss;
+        let rss_costs = match &self.params.cost_spec {
+            Some(spec) => spec.uses(CostMeasure::RssViolation),
+            None => rss.weight > 0.0,
+        };
+        if rss.enabled && (self.is_truth || rss_costs) {
+            self.rss_violation = rss::ego_violation(self);
+        }
+
+        // a crash's severity in place of any running costs afterward
+        let crash = &self.params.crash;
+        if crash.severity && self.cars[0].crashed {
+            if !self.last_ego.crashed {
+                let impact_speed = self.ego_impact_speed.unwrap_or(0.0);
+                self.cost.safety +=
+                    (crash.base_weight + crash.speed_weight * impact_speed) * self.cost.discount;
+            }
+            self.last_ego.clone_from(&self.cars[0]);
+            self.cost.update_discount(dt);
+            return;
+        }
+
+        let params = self.params.clone();
+        match &params.cost_spec {
+            Some(spec) => spec.add_costs(self, dt),
+            None => self.add_builtin_costs(dt),
+        }
+
+        let car = &self.cars[0];
+        let goal = &self.params.goal;
+        if goal.enabled
+            && self.goal_reached.is_none()
+            && self.last_ego.x() < goal.x
+            && car.x() >= goal.x
+        {
+            self.goal_reached = Some((self.t, car.current_lane()));
+        }
+
+        let policy_id = car.operating_policy_id();
+        let last_policy_id = self.last_ego.operating_policy_id();
+        if policy_id != last_policy_id {
+            if self.debug && self.params.ego_policy_change_debug {
+                debug_f!(
+                    "{}: policy change from {last_policy_id} to {policy_id}",
+                    self.timesteps
+                );
+                tracing::debug!("New policy: {:?}", self.ego_policy().operating_policy());
+            }
+        } else if self.debug && self.params.ego_policy_change_debug && self.switched_ego_policy {
+            let policy_id = car.full_policy_id();
+            let last_policy_id = self.last_ego.full_policy_id();
+            debug_f!(
+                "{}: full policy has changed from {last_policy_id} to {policy_id}",
+                self.timesteps
+            );
+        }
+
+        if self.switched_ego_policy {
+            self.switched_ego_policy = false;
+        }
+
+        self.last_ego.clone_from(&self.cars[0]);
+        self.cost.update_discount(dt);
+    }
+
+    // Any car's efficiency and safety costs for one step, with the ego's weights and margins,
+    // undiscounted
+    fn car_running_cost(&self, car_i: usize, dt: f64) -> f64 {
+        let cparams = &self.params.cost;
+        let car = &self.cars[car_i];
+
+        let mut cost = cparams.efficiency_weight
+            * cparams.efficiency_speed_cost
+            * (car.preferred_vel - car.vel).abs()
+            * dt;
+        if let Some(min_dist) = self.min_unsafe_dist(car_i) {
+            cost += cparams.safety_weight
+                * logistic(change_range(
+                    min_dist,
+                    cparams.safety_margin_low,
+                    cparams.safety_margin_high,
+                    cparams.logistic_map_low,
+                    cparams.logistic_map_high,
+                ))
+                * dt;
+        }
+        cost
+    }
+
+    // the cost function of the cost parameters, when there is no cost_file
+    fn add_builtin_costs(&mut self, dt: f64) {
         let cparams = &self.params.cost;
         let car = &self.cars[0];
 
@@ -600,6 +1304,15 @@ impl Road {
             * dt
             * self.cost.discount;
 
+        if self.emergency_braking {
+            self.cost.safety +=
+                self.params.safety_filter.activation_weight * dt * self.cost.discount;
+        }
+
+        if self.rss_violation {
+            self.cost.safety += self.params.rss.weight * dt * self.cost.discount;
+        }
+
         let min_dist = self.min_unsafe_dist(0);
         if let Some(min_dist) = min_dist {
             // When safety_margin_low = 0, this reduces to the simple equation shown in the paper
@@ -616,92 +1329,234 @@ impl Road {
                 ));
             self.cost.safety += penalty * dt * self.cost.discount;
             if self.debug && penalty > 10.0 {
-                eprintln!(
+                tracing::debug!(
                     "{}: safety distance: {:.2} -> penalty {:.2}",
-                    self.timesteps, min_dist, penalty
+                    self.timesteps,
+                    min_dist,
+                    penalty
                 );
             }
         }
 
-        let policy_id = car.operating_policy_id();
-        let last_policy_id = self.last_ego.operating_policy_id();
-        if policy_id != last_policy_id {
-            if self.debug && self.params.ego_policy_change_debug {
-                eprintln_f!(
-                    "{}: policy change from {last_policy_id} to {policy_id}",
-                    self.timesteps
+        if let Some(min_dist) = self.min_pedestrian_dist(0) {
+            // the same logistic, over the pedestrians' own margin
+            let pparams = &self.params.pedestrians;
+            let penalty = pparams.safety_weight
+                * logistic(change_range(
+                    min_dist,
+                    0.0,
+                    pparams.safety_margin,
+                    cparams.logistic_map_low,
+                    cparams.logistic_map_high,
+                ));
+            self.cost.safety += penalty * dt * self.cost.discount;
+            if self.debug && penalty > 10.0 {
+                tracing::debug!(
+                    "{}: pedestrian distance: {:.2} -> penalty {:.2}",
+                    self.timesteps,
+                    min_dist,
+                    penalty
                 );
-                eprintln!("New policy: {:?}", self.ego_policy().operating_policy());
             }
-        } else if self.debug && self.params.ego_policy_change_debug && self.switched_ego_policy {
-            let policy_id = car.full_policy_id();
-            let last_policy_id = self.last_ego.full_policy_id();
-            eprintln_f!(
-                "{}: full policy has changed from {last_policy_id} to {policy_id}",
-                self.timesteps
-            );
         }
 
-        if self.switched_ego_policy {
-            self.switched_ego_policy = false;
+        if self.ego_ran_red_light() {
+            self.cost.safety += self.params.signals.violation_weight * self.cost.discount;
+            if self.debug {
+                tracing::debug!("{}: ego ran a red light", self.timesteps);
+            }
         }
 
-        let accel = (car.vel - self.last_ego.vel) / dt;
+        let accel = self.ego_accel(dt);
         self.cost.accel += cparams.accel_weight * accel.powi(2) * dt * self.cost.discount;
 
-        let theta_accel = (car.theta() - self.last_ego.theta()) / dt;
+        let theta_accel = self.ego_course_rate(dt);
         self.cost.steer += cparams.steer_weight * theta_accel.powi(2) * dt * self.cost.discount;
 
-        self.last_ego = self.cars[0].clone();
-        self.cost.update_discount(dt);
+        let jerk = self.ego_jerk(dt);
+        self.cost.jerk += cparams.jerk_weight * jerk.powi(2) * dt * self.cost.discount;
+        let lat_accel = self.ego_lat_accel(dt);
+        self.cost.lat_accel +=
+            cparams.lat_accel_weight * lat_accel.powi(2) * dt * self.cost.discount;
+
+        if let Some(offset) = self.ego_lane_offset() {
+            self.cost.lane_offset +=
+                cparams.lane_offset_weight * offset.powi(2) * dt * self.cost.discount;
+        }
     }
 
-    pub fn draw(&self, r: &mut Rvx) {
-        // draw a 'road'
-        r.draw(
-            Rvx::square()
-                .scale_xy(&[ROAD_LENGTH, LANE_WIDTH * 2.0])
-                .color(RvxColor::GRAY),
-        );
-        r.draw(
-            Rvx::square()
-                .scale_xy(&[ROAD_LENGTH, 0.2])
-                .translate(&[0.0, -LANE_WIDTH])
-                .color(RvxColor::WHITE),
-        );
-        r.draw(
-            Rvx::square()
-                .scale_xy(&[ROAD_LENGTH, 0.2])
-                .translate(&[0.0, LANE_WIDTH])
-                .color(RvxColor::WHITE),
-        );
+    // The goal's terminal cost for where the ego is now: per lane it is off from the goal lane,
+    // or was when it passed the station, and per second past the goal time it got there,
+    // or will at its current speed
+    pub fn goal_cost(&self) -> f64 {
+        let goal = &self.params.goal;
+        if !goal.enabled {
+            return 0.0;
+        }
+        let ego = &self.cars[0];
+        let (arrival_t, lane_i) = self.goal_reached.unwrap_or_else(|| {
+            let remaining = (goal.x - ego.x()).max(0.0);
+            (
+                self.t + remaining / ego.vel.max(MIN_GOAL_VEL),
+                ego.current_lane(),
+            )
+        });
+        goal.lane_weight * (lane_i - goal.lane).abs() as f64
+            + goal.late_weight * (arrival_t - goal.time).max(0.0)
+    }
 
-        if !self.params.graphics_for_paper {
+    // the cost with the terminal cost for ending here, per second for the average objective_mode
+    pub fn final_cost(&self) -> Cost {
+        let mut cost = self.cost;
+        cost.efficiency += self.goal_cost() * cost.discount;
+        let elapsed_t = self.t - self.cost_start_t;
+        if self.params.objective_mode == "average" && elapsed_t > 0.0 {
+            cost /= elapsed_t;
+        }
+        cost
+    }
+
+    // a forward simulation's cost starts from zero now, discounted only for discounted objectives
+    fn reset_rollout_cost(&mut self) {
+        let discount_factor = match self.params.objective_mode.as_str() {
+            "discounted" => self.params.cost.discount_factor,
+            _ => 1.0,
+        };
+        self.cost = Cost::new(discount_factor, 1.0);
+        self.cost_start_t = self.t;
+    }
+
+    pub fn ego_ran_red_light(&self) -> bool {
+        ran_red_light(&self.params, self.last_ego.x(), self.cars[0].x(), self.t)
+    }
+
+    // the ego's change in speed over the last step
+    pub fn ego_accel(&self, dt: f64) -> f64 {
+        (self.cars[0].vel - self.last_ego.vel) / dt
+    }
+
+    // how fast the ego's course turns; with tire slip that isn't the same as its heading
+    pub fn ego_course_rate(&self, dt: f64) -> f64 {
+        let car = &self.cars[0];
+        if car.uses_dynamic_model(&self.params.ego_dynamics) {
+            car.lat_accel / car.vel
+        } else {
+            (car.theta() - self.last_ego.theta()) / dt
+        }
+    }
+
+    // from the change in the acceleration the car actually makes
+    pub fn ego_jerk(&self, dt: f64) -> f64 {
+        (self.cars[0].accel - self.last_ego.accel) / dt
+    }
+
+    // the centripetal acceleration of the ego's course
+    pub fn ego_lat_accel(&self, dt: f64) -> f64 {
+        let car = &self.cars[0];
+        if car.uses_dynamic_model(&self.params.ego_dynamics) {
+            car.lat_accel
+        } else {
+            car.vel * self.ego_course_rate(dt)
+        }
+    }
+
+    // how far the ego is off its lane's center, while it keeps its lane rather than changing
+    pub fn ego_lane_offset(&self) -> Option<f64> {
+        let car = &self.cars[0];
+        let lane_i = car.current_lane();
+        if car.target_lane_i != lane_i {
+            return None;
+        }
+        Some(car.y() - Road::get_lane_y(lane_i))
+    }
+
+    pub fn draw(&self, r: &mut Rvx) {
+        // lane 0 is centered at -LANE_WIDTH / 2, and the others are above it
+        let n_lanes = self.params.n_lanes;
+        let low_y = -LANE_WIDTH;
+        let high_y = (n_lanes - 1) as f64 * LANE_WIDTH;
+
+        if self.geometry.is_straight() {
+            // draw a 'road'
             r.draw(
-                Rvx::text(&format!("{}", self.timesteps), "Arial", 150.0)
-                    .rot(-PI / 2.0)
-                    .translate(&[0.0, 5.0 * LANE_WIDTH])
-                    .color(RvxColor::WHITE),
+                Rvx::square()
+                    .scale_xy(&[ROAD_LENGTH, high_y - low_y])
+                    .translate(&[0.0, (low_y + high_y) / 2.0])
+                    .color(RvxColor::GRAY),
             );
+            for &edge_y in [low_y, high_y].iter() {
+                r.draw(
+                    Rvx::square()
+                        .scale_xy(&[ROAD_LENGTH, 0.2])
+                        .translate(&[0.0, edge_y])
+                        .color(RvxColor::WHITE),
+                );
+            }
+
+            if !self.params.graphics_for_paper {
+                r.draw(
+                    Rvx::text(&format!("{}", self.timesteps), "Arial", 150.0)
+                        .rot(-PI / 2.0)
+                        .translate(&[0.0, high_y + 4.0 * LANE_WIDTH])
+                        .color(RvxColor::WHITE),
+                );
+            }
+
+            // adjust for ego car
+            r.set_translate_modifier(-self.cars[0].x(), 0.0);
+
+            // draw the dashes in the middle
+            let dash_interval = ROAD_DASH_LENGTH + ROAD_DASH_DIST;
+            let dash_offset = (self.cars[0].x() / dash_interval).round() * dash_interval;
+            for lane_i in 1..n_lanes {
+                let dash_y = (lane_i - 1) as f64 * LANE_WIDTH;
+                for dash_i in -15..=15 {
+                    r.draw(
+                        Rvx::square()
+                            .scale_xy(&[ROAD_DASH_LENGTH, 0.2])
+                            .translate(&[dash_i as f64 * dash_interval + dash_offset, dash_y])
+                            .color(RvxColor::WHITE),
+                    );
+                }
+            }
+        } else {
+            self.draw_curved_road(r, low_y, high_y);
+        }
+        if self.params.merge.ramp_lane >= 0 {
+            self.draw_missing_ramp(r);
         }
 
-        // adjust for ego car
-        r.set_translate_modifier(-self.cars[0].x(), 0.0);
+        if self.params.closure.lane >= 0 {
+            self.draw_cones(r);
+        }
+        if self.params.signals.spacing > 0.0 {
+            draw_intersections(self, r, low_y, high_y);
+        }
 
-        // draw the dashes in the middle
-        let dash_interval = ROAD_DASH_LENGTH + ROAD_DASH_DIST;
-        let dash_offset = (self.cars[0].x() / dash_interval).round() * dash_interval;
-        for dash_i in -15..=15 {
+        let ego_x = self.cars[0].x();
+        for s in self.crosswalks_between(ego_x - ROAD_LENGTH / 2.0, ego_x + ROAD_LENGTH / 2.0) {
+            let (x, y) = self.geometry.to_cartesian(s, (low_y + high_y) / 2.0);
             r.draw(
                 Rvx::square()
-                    .scale_xy(&[ROAD_DASH_LENGTH, 0.2])
-                    .translate(&[dash_i as f64 * dash_interval + dash_offset, 0.0])
-                    .color(RvxColor::WHITE),
+                    .scale_xy(&[CROSSWALK_WIDTH, high_y - low_y])
+                    .rot(self.geometry.heading(s, 0.0))
+                    .translate(&[x, y])
+                    .color(RvxColor::WHITE.set_a(0.3)),
             );
         }
+        for pedestrian in self.pedestrians.iter() {
+            pedestrian.draw(&self.geometry, r);
+        }
 
         // draw the cars
         for (i, car) in self.cars.iter().enumerate() {
+            let world_car;
+            let car = if self.geometry.is_straight() {
+                car
+            } else {
+                world_car = self.world_car(car);
+                &world_car
+            };
             if i == 0 && car.crashed {
                 car.draw(&self.params, r, RvxColor::ORANGE.set_a(0.6));
             } else if i == 0 {
@@ -714,10 +1569,211 @@ impl Road {
                 car.draw(&self.params, r, RvxColor::BLUE.set_a(0.6));
             }
         }
+        let to_world = |car: &Car| {
+            if self.geometry.is_straight() {
+                car.clone()
+            } else {
+                self.world_car(car)
+            }
+        };
+        if self.params.occlusion.enabled {
+            occlusion::draw_occluded(self, r, to_world);
+        }
+        if self.params.belief_overlay {
+            if let Some(belief) = self.belief.as_ref() {
+                belief.draw(self, r, to_world);
+            }
+        }
+    }
+
+    // covers the ramp lane where it doesn't exist
+    fn draw_missing_ramp(&self, r: &mut Rvx) {
+        let lane_i = self.params.merge.ramp_lane;
+        let piece_length = 4.0;
+        let start_x =
+            ((self.cars[0].x() - ROAD_LENGTH / 2.0) / piece_length).round() * piece_length;
+        for piece_i in 0..(ROAD_LENGTH / piece_length) as usize {
+            let s = start_x + (piece_i as f64 + 0.5) * piece_length;
+            if self.lane_exists(lane_i, s) {
+                continue;
+            }
+            let (x, y) = self.geometry.to_cartesian(s, Road::get_lane_y(lane_i));
+            r.draw(
+                Rvx::square()
+                    .scale_xy(&[piece_length * 1.1, LANE_WIDTH])
+                    .rot(self.geometry.heading(s, 0.0))
+                    .translate(&[x, y])
+                    .color(RvxColor::DARK_GRAY),
+            );
+        }
+    }
+
+    // cones across the closed lane, tapering in from its far edge
+    fn draw_cones(&self, r: &mut Rvx) {
+        let closure = &self.params.closure;
+        let lane_i = closure.lane;
+        let open_side = if lane_i + 1 < self.params.n_lanes {
+            1.0
+        } else {
+            -1.0
+        };
+        let lane_y = Road::get_lane_y(lane_i);
+        let cone_spacing = 3.0;
+        let taper_length = 15.0;
+        let start_x =
+            ((self.cars[0].x() - ROAD_LENGTH / 2.0) / cone_spacing).round() * cone_spacing;
+        for cone_i in 0..(ROAD_LENGTH / cone_spacing) as usize {
+            let s = start_x + cone_i as f64 * cone_spacing;
+            if self.lane_exists(lane_i, s) {
+                continue;
+            }
+            // how far into the closure s is
+            let into = (1..)
+                .map(|i| i as f64 * cone_spacing)
+                .take_while(|&back| back <= taper_length)
+                .filter(|&back| !self.lane_exists(lane_i, s - back))
+                .count() as f64
+                * cone_spacing;
+            let side = (into / taper_length).min(1.0) - 0.5;
+            let (x, y) = self
+                .geometry
+                .to_cartesian(s, lane_y + open_side * side * LANE_WIDTH * 0.9);
+            r.draw(
+                Rvx::circle()
+                    .scale(0.3)
+                    .translate(&[x, y])
+                    .color(RvxColor::ORANGE),
+            );
+        }
+    }
+
+    // the car moved from the road's frame to the world frame, for drawing it
+    pub fn world_car(&self, car: &Car) -> Car {
+        let mut world_car = car.clone();
+        let (x, y) = self.geometry.to_cartesian(car.x(), car.y());
+        world_car.set_x(x);
+        world_car.set_y(y);
+        world_car.set_theta(self.geometry.heading(car.x(), car.theta()));
+        world_car
+    }
+
+    // the car under the point (x, y), or else the closest one within PICK_DIST of it
+    pub fn pick_car(&self, x: f64, y: f64) -> Option<usize> {
+        self.cars
+            .iter()
+            .enumerate()
+            .map(|(car_i, car)| {
+                // from the point to the car's (unrotated) box
+                let dx = (x - car.x()).max(car.x() - car.length - x).max(0.0);
+                let dy = ((y - car.y()).abs() - car.width / 2.0).max(0.0);
+                (dx.hypot(dy), car_i)
+            })
+            .filter(|(dist, _)| *dist <= PICK_DIST)
+            .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap())
+            .map(|(_, car_i)| car_i)
+    }
+
+    // all about a car, with its policy and the belief over it, for inspecting it
+    pub fn describe_car(&self, car_i: usize) -> String {
+        let car = &self.cars[car_i];
+        let mut description = format!(
+            "car {} at step {} (t = {:.2}): x: {:.2}, y: {:.2}, theta: {:.3}, vel: {:.2}, \
+             accel: {:.2}, steer: {:.3}, lane: {}, target lane: {}, target vel: {:.2}, \
+             preferred vel: {:.2}, follow time: {:.2}, crashed: {}\n  policy: {:?}",
+            car_i,
+            self.timesteps,
+            self.t,
+            car.x(),
+            car.y(),
+            car.theta(),
+            car.vel,
+            car.accel,
+            car.steer,
+            car.current_lane(),
+            car.target_lane_i,
+            car.target_vel,
+            car.preferred_vel,
+            car.target_follow_time,
+            car.crashed,
+            car.side_policy,
+        );
+        if let Some(traits) = car.traits.as_ref() {
+            description += &format!("\n  traits: {:?}", traits);
+        }
+        if car_i > 0 {
+            if let Some(belief) = self.belief.as_ref() {
+                description += &format!(
+                    "\n  belief: {:.2?}, most likely {}, entropy {:.2} bits",
+                    belief.get_all(car_i),
+                    belief.get_most_likely(car_i),
+                    belief.entropy(car_i)
+                );
+                if self.params.intent.enabled {
+                    description += &format!(", intent: {:.2?}", belief.get_intent(car_i));
+                }
+            }
+        }
+        description
+    }
+
+    // the road around the ego as short pieces along the centerline
+    fn draw_curved_road(&self, r: &mut Rvx, low_y: f64, high_y: f64) {
+        let ego_s = self.cars[0].x();
+        let (ego_x, ego_y) = self.geometry.to_cartesian(ego_s, self.cars[0].y());
+
+        if !self.params.graphics_for_paper {
+            r.draw(
+                Rvx::text(&format!("{}", self.timesteps), "Arial", 150.0)
+                    .rot(-PI / 2.0)
+                    .translate(&[0.0, high_y + 4.0 * LANE_WIDTH])
+                    .color(RvxColor::WHITE),
+            );
+        }
+
+        r.set_translate_modifier(-ego_x, -ego_y);
+
+        let piece = |r: &mut Rvx, s: f64, y: f64, length: f64, width: f64, color: RvxColor| {
+            let (x, y) = self.geometry.to_cartesian(s, y);
+            r.draw(
+                Rvx::square()
+                    .scale_xy(&[length, width])
+                    .rot(self.geometry.heading(s, 0.0))
+                    .translate(&[x, y])
+                    .color(color),
+            );
+        };
+
+        let piece_length = 4.0;
+        let start_s = ((ego_s - ROAD_LENGTH / 2.0) / piece_length).round() * piece_length;
+        for piece_i in 0..(ROAD_LENGTH / piece_length) as usize {
+            let s = start_s + piece_i as f64 * piece_length;
+            // a little longer, to cover the gaps on the outside of the curves
+            piece(
+                r,
+                s,
+                (low_y + high_y) / 2.0,
+                piece_length * 1.1,
+                high_y - low_y,
+                RvxColor::GRAY,
+            );
+            for &edge_y in [low_y, high_y].iter() {
+                piece(r, s, edge_y, piece_length * 1.1, 0.2, RvxColor::WHITE);
+            }
+        }
+
+        let dash_interval = ROAD_DASH_LENGTH + ROAD_DASH_DIST;
+        let dash_offset = (ego_s / dash_interval).round() * dash_interval;
+        for lane_i in 1..self.params.n_lanes {
+            let dash_y = (lane_i - 1) as f64 * LANE_WIDTH;
+            for dash_i in -15..=15 {
+                let s = dash_i as f64 * dash_interval + dash_offset;
+                piece(r, s, dash_y, ROAD_DASH_LENGTH, 0.2, RvxColor::WHITE);
+            }
+        }
     }
 
     pub fn reset_car_traces(&mut self) {
-        if self.params.run_fast {
+        if self.params.run_fast && self.params.svg.dir.is_empty() {
             self.car_traces = None;
         } else {
             self.car_traces = Some(Vec::new());
@@ -729,6 +1785,36 @@ impl Road {
         self.car_traces = None;
     }
 
+    // the flattened x, y drawing coordinates of a trace
+    fn trace_points(&self, trace: &[(Point3<f64>, u32)]) -> Vec<f64> {
+        // sparsify points that are _really_ close together
+        let mut points_2d = trace.iter().map(|(p, _)| p).copied().collect_vec();
+        let mut p_i = 0;
+        while p_i + 1 < points_2d.len() {
+            if (points_2d[p_i] - points_2d[p_i + 1]).magnitude_squared() < 0.1f64.powi(2) {
+                points_2d.remove(p_i + 1);
+                continue;
+            }
+            p_i += 1;
+        }
+
+        points_2d
+            .iter()
+            .flat_map(|p| {
+                let (x, y) = self.geometry.to_cartesian(p.x, p.y);
+                vec![x, y]
+            })
+            .collect_vec()
+    }
+
+    // where the ego has gone since the traces were last reset, or nothing without traces
+    pub fn ego_trace_points(&self) -> Vec<f64> {
+        match self.car_traces.as_ref().and_then(|traces| traces.first()) {
+            Some(trace) => self.trace_points(trace),
+            None => Vec::new(),
+        }
+    }
+
     pub fn make_traces(&self, depth_level: u32, include_obstacle_cars: bool) -> Vec<rvx::Shape> {
         let mut shapes = Vec::new();
 
@@ -746,23 +1832,21 @@ impl Road {
                 continue;
             }
 
-            // sparsify points that are _really_ close together
-            let mut points_2d = trace.iter().map(|(p, _)| p).copied().collect_vec();
-            let mut p_i = 0;
-            while p_i + 1 < points_2d.len() {
-                if (points_2d[p_i] - points_2d[p_i + 1]).magnitude_squared() < 0.1f64.powi(2) {
-                    points_2d.remove(p_i + 1);
-                    continue;
-                }
-                p_i += 1;
+            let points = self.trace_points(trace);
+
+            if car_i == 0 {
+                TRACE_LINES.with(|t| {
+                    if let Some(lines) = t.borrow_mut().as_mut() {
+                        lines.push(TraceLine {
+                            points: points.clone(),
+                            depth_level,
+                            crashed: self.cars[0].crashed,
+                            not_safe: self.cost.safety > self.last_reset_cost.safety + 20.0,
+                        });
+                    }
+                });
             }
 
-            let points = points_2d
-                .iter()
-                .flat_map(|p| &p.coords.as_slice()[0..2])
-                .copied()
-                .collect_vec();
-
             if car_i == 0 && self.params.ego_traces_debug {
                 // eprintln!("Points in trace: {}", trace.len());
 
@@ -845,6 +1929,101 @@ impl Road {
         shapes
     }
 
+    // the ramp's distance ahead of x to its end, while x is on the ramp
+    fn ramp_end_ahead(&self, x: f64) -> Option<f64> {
+        let merge = &self.params.merge;
+        let x = if merge.period > 0.0 {
+            merge.start + (x - merge.start).rem_euclid(merge.period)
+        } else {
+            x
+        };
+        if x >= merge.start && x < merge.end {
+            Some(merge.end - x)
+        } else {
+            None
+        }
+    }
+
+    // the distance ahead of x to the start of the next lane closure, or 0 inside one
+    fn closure_ahead(&self, x: f64) -> Option<f64> {
+        let closure = &self.params.closure;
+        if closure.lane < 0 {
+            return None;
+        }
+        if closure.period > 0.0 {
+            let x = closure.start + (x - closure.start).rem_euclid(closure.period);
+            if x < closure.end {
+                Some(0.0)
+            } else {
+                Some(closure.start + closure.period - x)
+            }
+        } else if x < closure.start {
+            Some(closure.start - x)
+        } else if x < closure.end {
+            Some(0.0)
+        } else {
+            None
+        }
+    }
+
+    // whether any of lane_i from low_x to high_x is closed
+    pub fn lane_closed_between(&self, lane_i: i32, low_x: f64, high_x: f64) -> bool {
+        lane_i == self.params.closure.lane
+            && self
+                .closure_ahead(low_x)
+                .map_or(false, |dist| dist <= high_x - low_x)
+    }
+
+    pub fn lane_exists(&self, lane_i: i32, x: f64) -> bool {
+        if lane_i < 0 || lane_i >= self.params.n_lanes {
+            return false;
+        }
+        if lane_i == self.params.closure.lane && self.closure_ahead(x) == Some(0.0) {
+            return false;
+        }
+        lane_i != self.params.merge.ramp_lane || self.ramp_end_ahead(x).is_some()
+    }
+
+    // how far ahead of x the lane ends, if it is the ramp, or is closed ahead
+    pub fn lane_end_ahead(&self, lane_i: i32, x: f64) -> Option<f64> {
+        let ramp_end = if lane_i == self.params.merge.ramp_lane {
+            self.ramp_end_ahead(x)
+        } else {
+            None
+        };
+        // a car already inside the closure drives on while it merges out
+        let closure_start = if lane_i == self.params.closure.lane {
+            self.closure_ahead(x).filter(|&dist| dist > 0.0)
+        } else {
+            None
+        };
+        match (ramp_end, closure_start) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    // The lane car_i can actually head for when it wants lane_i: off the ramp or a closed lane
+    // when it is gone or ends before the car could finish changing lanes (a forced merge)
+    pub fn usable_lane(&self, car_i: usize, lane_i: i32) -> i32 {
+        if lane_i != self.params.merge.ramp_lane && lane_i != self.params.closure.lane {
+            return lane_i;
+        }
+        let car = &self.cars[car_i];
+        let merge_dist = self.params.lane_change_time * car.vel + 2.0 * car.length;
+        let usable = self.lane_exists(lane_i, car.x())
+            && self
+                .lane_end_ahead(lane_i, car.x())
+                .map_or(true, |end_dist| end_dist > merge_dist);
+        if usable {
+            lane_i
+        } else if lane_i + 1 < self.params.n_lanes {
+            lane_i + 1
+        } else {
+            lane_i - 1
+        }
+    }
+
     pub fn get_lane_y(lane_i: i32) -> f64 {
         (lane_i as f64 - 0.5) * LANE_WIDTH
     }
@@ -862,33 +2041,211 @@ impl Road {
         for car_i in 1..self.cars.len() {
             let car_x = self.cars[car_i].x();
             if car_x < ego_x - remove_behind_beyond || car_x > ego_x + remove_ahead_beyond {
+                let mut car_rng;
+                let rng = if self.params.rng_streams {
+                    let keys = [self.timesteps as u64, car_i as u64];
+                    car_rng = rng_streams::fork(rng, Stream::Respawn, &keys);
+                    &mut car_rng
+                } else {
+                    &mut *rng
+                };
                 loop {
                     let mut new_car = Car::random_new(&self.params, car_i, rng);
                     let new_dx = rng.gen_range(place_ahead_beyond..remove_ahead_beyond);
                     new_car.set_x(ego_x + new_dx);
 
-                    if !self.collides_any_car(&new_car) {
+                    if !self.collides_any_car(&new_car)
+                        && self.lane_exists(new_car.current_lane(), new_car.x())
+                    {
                         self.cars[car_i] = new_car;
                         break;
                     }
                 }
             }
         }
+        self.update_cars_spatial();
+    }
+
+    // stations of the crosswalks from low_x to high_x
+    pub fn crosswalks_between(&self, low_x: f64, high_x: f64) -> Vec<f64> {
+        let pparams = &self.params.pedestrians;
+        if pparams.crosswalk_spacing <= 0.0 {
+            return Vec::new();
+        }
+        let first_k = ((low_x - pparams.first_crosswalk) / pparams.crosswalk_spacing).ceil() as i64;
+        (first_k..)
+            .map(|k| pparams.first_crosswalk + k as f64 * pparams.crosswalk_spacing)
+            .take_while(|&x| x <= high_x)
+            .collect()
+    }
+
+    // Keeps per_crosswalk pedestrians at each crosswalk within the spawn horizons around the ego,
+    // each starting on a random curb, after a random part of their wait
+    pub fn respawn_pedestrians(&mut self, rng: &mut StdRng) {
+        let pparams = self.params.pedestrians.clone();
+        if pparams.crosswalk_spacing <= 0.0 {
+            return;
+        }
+        let ego_x = self.cars[0].x();
+        let low_x = ego_x - self.params.spawn.remove_behind_beyond;
+        let high_x = ego_x + self.params.spawn.remove_ahead_beyond;
+        self.pedestrians.retain(|p| p.x >= low_x && p.x <= high_x);
+
+        for crosswalk_x in self.crosswalks_between(low_x, high_x) {
+            if self
+                .pedestrians
+                .iter()
+                .any(|p| (p.x - crosswalk_x).abs() < CROSSWALK_WIDTH / 2.0)
+            {
+                continue;
+            }
+            let mut crosswalk_rng;
+            let rng = if self.params.rng_streams {
+                let keys = [crosswalk_x.to_bits()];
+                crosswalk_rng = rng_streams::fork(rng, Stream::Pedestrians, &keys);
+                &mut crosswalk_rng
+            } else {
+                &mut *rng
+            };
+            // side by side across the crosswalk's width
+            let n = pparams.per_crosswalk;
+            for i in 0..n {
+                let x =
+                    crosswalk_x + (i as f64 + 0.5 - n as f64 / 2.0) * CROSSWALK_WIDTH / n as f64;
+                let until = self.t + rng.gen::<f64>() * pparams.wait_time;
+                let pedestrian = Pedestrian::new(&self.params, x, rng.gen_bool(0.5), until);
+                self.pedestrians.push(pedestrian);
+            }
+        }
+    }
+
+    // cars per meter in each lane for the open boundary's flow at its mean speed
+    fn open_boundary_density(&self) -> f64 {
+        let spawn = &self.params.spawn;
+        spawn.flow / ((spawn.speed_low + spawn.speed_high) / 2.0)
+    }
+
+    fn open_boundary_car(&self, lane_i: i32, x: f64, vel: f64, rng: &mut StdRng) -> Car {
+        let mut car = Car::random_in_lane(&self.params, self.cars.len(), lane_i, rng);
+        car.set_x(x);
+        car.vel = vel;
+        car.preferred_vel = vel + car.traits.map_or(0.0, |t| t.speed_offset).max(-vel);
+        car
+    }
+
+    // an arriving car, as car_i self.cars.len(), unless it has no room there
+    pub fn push_car(&mut self, car: Car) -> bool {
+        if self.collides_any_car(&car) || !self.lane_exists(car.current_lane(), car.x()) {
+            return false;
+        }
+        self.cars.push(car);
+        if let Some(belief) = self.belief.as_mut() {
+            Arc::get_mut(belief)
+                .expect("cars should only arrive on the top-level road")
+                .add_car();
+        }
+        true
+    }
+
+    // a leaving car, with the last car taking its place
+    pub fn swap_remove_car(&mut self, car_i: usize) {
+        self.cars.swap_remove(car_i);
+        if car_i < self.cars.len() {
+            self.cars[car_i].car_i = car_i;
+        }
+        if let Some(belief) = self.belief.as_mut() {
+            Arc::get_mut(belief)
+                .expect("cars should only leave the top-level road")
+                .swap_remove_car(car_i);
+        }
+        if let Some(traces) = self.car_traces.as_mut() {
+            if car_i < traces.len() {
+                traces.swap_remove(car_i);
+            }
+        }
+    }
+
+    // The starting traffic for open_boundary: cars spread between the horizons at the
+    // stationary density, so there's no wave of arrivals at the start
+    pub fn populate_open_boundary(&mut self, rng: &mut StdRng) {
+        let spawn = self.params.spawn.clone();
+        let ego_x = self.cars[0].x();
+        let density = self.open_boundary_density();
+        for lane_i in 0..self.params.n_lanes {
+            let mut lane_rng;
+            let rng = if self.params.rng_streams {
+                lane_rng = rng_streams::fork(rng, Stream::Spawn, &[lane_i as u64]);
+                &mut lane_rng
+            } else {
+                &mut *rng
+            };
+            let mut x = ego_x - spawn.remove_behind_beyond;
+            loop {
+                x += -(1.0 - rng.gen::<f64>()).ln() / density;
+                if x > ego_x + spawn.remove_ahead_beyond {
+                    break;
+                }
+                let vel = rng.gen_range(spawn.speed_low..spawn.speed_high);
+                let car = self.open_boundary_car(lane_i, x, vel, rng);
+                self.push_car(car);
+            }
+        }
+    }
+
+    // For open_boundary, instead of respawn_obstacle_cars: cars beyond the horizons leave,
+    // and new cars arrive at them as the ego overtakes slower cars or faster cars catch up.
+    // With a uniform density of cars at uniform speeds, the arrivals of speed v at a horizon
+    // come at the density times |v - ego vel|, which keeps the density stationary.
+    pub fn open_boundary_traffic(&mut self, rng: &mut StdRng, dt: f64) {
+        let spawn = self.params.spawn.clone();
+        let ego_x = self.cars[0].x();
+        let ego_vel = self.cars[0].vel;
+
+        for car_i in (1..self.cars.len()).rev() {
+            let car_x = self.cars[car_i].x();
+            if car_x < ego_x - spawn.remove_behind_beyond
+                || car_x > ego_x + spawn.remove_ahead_beyond
+            {
+                self.swap_remove_car(car_i);
+            }
+        }
+
+        let density = self.open_boundary_density();
+        for lane_i in 0..self.params.n_lanes {
+            let mut lane_rng;
+            let rng = if self.params.rng_streams {
+                let keys = [self.timesteps as u64, lane_i as u64];
+                lane_rng = rng_streams::fork(rng, Stream::Respawn, &keys);
+                &mut lane_rng
+            } else {
+                &mut *rng
+            };
+            let vel = rng.gen_range(spawn.speed_low..spawn.speed_high);
+            let arrival_prob = density * (vel - ego_vel).abs() * dt;
+            if !rng.gen_bool(arrival_prob.min(1.0)) {
+                continue;
+            }
+            // just inside the horizon it arrives at, so it isn't removed right away
+            let x = if vel < ego_vel {
+                ego_x + spawn.remove_ahead_beyond - PRIUS_LENGTH
+            } else {
+                ego_x - spawn.remove_behind_beyond + PRIUS_LENGTH
+            };
+            let car = self.open_boundary_car(lane_i, x, vel, rng);
+            self.push_car(car);
+        }
+        self.update_cars_spatial();
     }
 
     pub fn save_particle(&mut self) {
         self.particle = Some(Particle {
             id: self.sample_id.unwrap(),
-            policies: self
-                .cars
-                .iter()
-                .map(|c| c.side_policy.clone().unwrap())
-                .collect(),
+            policies: self.cars.iter().map(|c| c.side_policy.clone()).collect(),
         });
     }
 }
 
-#[derive(Clone, PartialOrd)]
+#[derive(Clone, PartialOrd, Serialize, Deserialize)]
 pub struct Particle {
     pub id: usize,
     pub policies: Vec<SidePolicy>,
@@ -909,6 +2266,7 @@ impl PartialEq for Particle {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::mpdm::make_obstacle_vehicle_policy_choices;
     use approx::assert_abs_diff_eq;
 
     #[test]
@@ -954,4 +2312,531 @@ mod tests {
             epsilon = 1e-6
         );
     }
+
+    #[test]
+    fn test_more_lanes() {
+        let mut params = Parameters::new().unwrap();
+        params.n_lanes = 4;
+        for lane_i in 0..params.n_lanes {
+            assert_eq!(Road::get_lane_i(Road::get_lane_y(lane_i)), lane_i);
+        }
+
+        // maintain and accelerate in each lane, then decelerate
+        assert_eq!(make_obstacle_vehicle_policy_choices(&params).len(), 9);
+        let car = Car::new(&params, 1, 3);
+        assert_eq!(car.current_lane(), 3);
+        assert_eq!(car.side_policy.policy_id(), 7);
+    }
+
+    #[test]
+    fn test_process_noise() {
+        let run = |truth: bool, rollouts: bool| {
+            let mut params = Parameters::new().unwrap();
+            params.process_noise.accel_std = 0.5;
+            params.process_noise.steer_std = 0.01;
+            params.process_noise.truth = truth;
+            params.process_noise.rollouts = rollouts;
+            let mut road = Road::new(Arc::new(params));
+            road.cars[0].vel = 10.0;
+            road.take_update_steps(2.0, road.params.physics_dt);
+            let mut rollout = road.sim_estimate();
+            rollout.take_update_steps(2.0, road.params.physics_dt);
+            (road.cars[0].x(), rollout.cars[0].x())
+        };
+        let (quiet_x, quiet_rollout_x) = run(false, false);
+        let (noisy_x, _) = run(true, false);
+        let (rollouts_noisy_x, noisy_rollout_x) = run(false, true);
+        assert_ne!(quiet_x, noisy_x);
+        // noise only in the rollouts leaves the truth alone
+        assert_eq!(quiet_x, rollouts_noisy_x);
+        assert_ne!(quiet_rollout_x, noisy_rollout_x);
+        // and the same seed gives the same noise
+        assert_eq!(run(true, true), run(true, true));
+    }
+
+    #[test]
+    fn test_samples_get_their_own_process_noise() {
+        use rand::SeedableRng;
+
+        for rng_streams in [false, true] {
+            let mut params = Parameters::new().unwrap();
+            params.process_noise.accel_std = 0.5;
+            params.process_noise.rollouts = true;
+            params.rng_streams = rng_streams;
+            let mut road = Road::new(Arc::new(params));
+            road.cars[0].vel = 10.0;
+            road.init_belief();
+            let mut rng = StdRng::seed_from_u64(0);
+            let xs = road
+                .sample_beliefs(&mut rng, 2)
+                .into_iter()
+                .map(|mut sample| {
+                    sample.take_update_steps(2.0, road.params.physics_dt);
+                    sample.cars[0].x()
+                })
+                .collect_vec();
+            assert_ne!(xs[0], xs[1]);
+        }
+    }
+
+    #[test]
+    fn test_actuator_lag() {
+        assert_eq!(first_order_lag(0.0, 2.0, 0.0, 0.01), 2.0);
+        assert_abs_diff_eq!(first_order_lag(0.0, 2.0, 0.5, 0.01), 0.04, epsilon = 1e-12);
+
+        // the ego starting a lane change can only turn its wheels so fast
+        let mut params = Parameters::new().unwrap();
+        params.actuators.max_steer_rate = 0.5;
+        let mut road = Road::new(Arc::new(params.clone()));
+        road.cars[0].vel = 10.0;
+        road.set_ego_policy(&make_obstacle_vehicle_policy_choices(&params)[2]);
+        for _ in 0..10 {
+            road.update(0.01);
+            assert!(road.cars[0].steer.abs() <= 0.5 * road.t + 1e-9);
+        }
+        assert!(road.cars[0].steer > 0.0);
+    }
+
+    #[test]
+    fn test_truck_collision() {
+        use crate::car::TRUCK_LENGTH;
+
+        let params = Parameters::new().unwrap();
+        let mut road = Road::new(Arc::new(params.clone()));
+        road.cars[0].make_truck();
+        // a car with its front 10 m behind the truck's front, so alongside the truck's back half
+        let mut car = Car::new(&params, 1, road.cars[0].current_lane());
+        car.set_x(road.cars[0].x() - 10.0);
+        road.cars.push(car);
+        assert!(road.collides_between(0, 1));
+        assert!(road.collides_between(1, 0));
+
+        // but not once it is behind the truck
+        let x = road.cars[0].x() - TRUCK_LENGTH - 0.5;
+        road.cars[1].set_x(x);
+        assert!(!road.collides_between(0, 1));
+    }
+
+    #[test]
+    fn test_safety_filter() {
+        let mut params = Parameters::new().unwrap();
+        params.safety_filter.enabled = true;
+        let mut road = Road::new(Arc::new(params.clone()));
+        road.cars[0].vel = 10.0;
+        let mut stopped = Car::new(&params, 1, 0);
+        stopped.set_x(30.0);
+        stopped.vel = 0.0;
+        road.cars.push(stopped);
+        road.update_cars_spatial();
+
+        // far enough off to leave it to the policy
+        road.update(0.01);
+        assert!(!road.emergency_braking);
+
+        // but not once it's this close
+        let x = road.cars[0].x() + road.cars[1].length + 10.0;
+        road.cars[1].set_x(x);
+        road.update_cars_spatial();
+        let safety = road.cost.safety;
+        road.update(0.01);
+        assert!(road.emergency_braking);
+        assert_eq!(road.cars[0].accel, -BREAKING_ACCEL);
+        assert!(road.cost.safety > safety);
+    }
+
+    #[test]
+    fn test_crash_severity() {
+        let mut params = Parameters::new().unwrap();
+        params.crash.severity = true;
+        let crash = params.crash.clone();
+        let mut road = Road::new(Arc::new(params.clone()));
+        road.is_truth = true;
+        road.cars[0].vel = 10.0;
+        let mut stopped = Car::new(&params, 1, 0);
+        stopped.vel = 0.0;
+        let x = road.cars[0].x() + stopped.length - 0.5;
+        stopped.set_x(x);
+        road.cars.push(stopped);
+        road.update_cars_spatial();
+
+        // running into the back of it is the full closing speed
+        assert!((road.impact_speed(0, 1) - 10.0).abs() < 1e-6);
+
+        // but brushing alongside a car going the same speed is nothing
+        let mut alongside = Car::new(&params, 2, 0);
+        alongside.vel = 10.0;
+        alongside.set_x(road.cars[0].x());
+        alongside.set_y(road.cars[0].y() + road.cars[0].width - 0.1);
+        road.cars.push(alongside);
+        assert!(road.impact_speed(0, 2).abs() < 1e-6);
+        road.cars.pop();
+
+        // the crash costs its severity once, and nothing more while it sits there
+        road.update(0.01);
+        assert!(road.cars[0].crashed);
+        let impact_speed = road.ego_impact_speed.unwrap();
+        assert!(impact_speed > 9.0);
+        let expected = crash.base_weight + crash.speed_weight * impact_speed;
+        assert!((road.cost.safety - expected).abs() < 1e-6);
+        let cost = road.cost;
+        road.update(0.01);
+        assert_eq!(road.cost.total(), cost.total());
+    }
+
+    #[test]
+    fn test_spatial_queries() {
+        use rand::SeedableRng;
+
+        let mut params = Parameters::new().unwrap();
+        params.n_cars = 40;
+        params.only_crashes_with_ego = false;
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut road = Road::new(Arc::new(params.clone()));
+        for _ in 0..params.n_cars {
+            road.add_random_car(&mut rng).unwrap();
+        }
+        road.cars[7].make_truck();
+        road.init_belief();
+        road.update_cars_spatial();
+        for step in 0..300 {
+            road.update_belief(&mut rng).unwrap();
+            road.update(params.physics_dt);
+            if step % 50 != 0 {
+                continue;
+            }
+            // the same as with nothing pruned
+            let mut unpruned = road.clone();
+            unpruned.spatial_reach = f64::INFINITY;
+            for car_i in 0..road.cars.len() {
+                assert_eq!(road.min_unsafe_dist(car_i), unpruned.min_unsafe_dist(car_i));
+                for lane_i in 0..params.n_lanes {
+                    assert_eq!(
+                        road.dist_clear_ahead_in_lane(car_i, lane_i),
+                        unpruned.dist_clear_ahead_in_lane(car_i, lane_i)
+                    );
+                }
+            }
+            let mut sorted = road.cars_spatial.clone();
+            sorted.sort_by_key(|s| s.x);
+            assert!(road
+                .cars_spatial
+                .iter()
+                .zip(sorted)
+                .all(|(a, b)| a.x == b.x));
+        }
+    }
+
+    #[test]
+    fn test_forward_sim_steps_dont_allocate() {
+        use crate::alloc_counter::take_allocation_count;
+        use rand::SeedableRng;
+
+        let mut params = Parameters::new().unwrap();
+        params.n_cars = 20;
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut road = Road::new(Arc::new(params.clone()));
+        for _ in 0..params.n_cars {
+            road.add_random_car(&mut rng).unwrap();
+        }
+        road.init_belief();
+        let mut road = road.sample_belief(&mut rng);
+        road.update(params.physics_dt);
+
+        take_allocation_count();
+        for _ in 0..100 {
+            road.update(params.physics_dt);
+        }
+        assert_eq!(take_allocation_count(), 0);
+    }
+
+    #[test]
+    fn test_ego_policy_switches_dont_allocate() {
+        use crate::{
+            alloc_counter::take_allocation_count, delayed_policy::DelayedPolicy,
+            mpdm::make_policy_choices,
+        };
+        use rand::SeedableRng;
+
+        let mut params = Parameters::new().unwrap();
+        params.n_cars = 20;
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut road = Road::new(Arc::new(params.clone()));
+        for _ in 0..params.n_cars {
+            road.add_random_car(&mut rng).unwrap();
+        }
+        road.init_belief();
+        let mut road = road.sample_belief(&mut rng);
+
+        // the ego switching between the delayed policies of a search, as eudm's rollouts do
+        let choices = make_policy_choices(&params);
+        let delayed = (0..choices.len())
+            .map(|i| {
+                SidePolicy::DelayedPolicy(DelayedPolicy::new(
+                    choices[i].clone(),
+                    choices[(i + 1) % choices.len()].clone(),
+                    0.5,
+                ))
+            })
+            .collect_vec();
+        road.set_ego_policy(&delayed[0]);
+        road.update(params.physics_dt);
+
+        take_allocation_count();
+        for policy in delayed.iter().cycle().take(50) {
+            road.set_ego_policy(policy);
+            for _ in 0..10 {
+                road.update(params.physics_dt);
+            }
+        }
+        assert_eq!(take_allocation_count(), 0);
+    }
+
+    #[test]
+    fn test_snapshot() {
+        use rand::SeedableRng;
+
+        let params = Parameters::new().unwrap();
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut road = Road::new(Arc::new(params.clone()));
+        for _ in 0..params.n_cars {
+            road.add_random_car(&mut rng).unwrap();
+        }
+        road.init_belief();
+        road.update_cars_spatial();
+        for _ in 0..100 {
+            road.update_belief(&mut rng).unwrap();
+            road.update(params.physics_dt);
+        }
+
+        let path = std::env::temp_dir().join(format!("snapshot_test_{}.json", std::process::id()));
+        road.save_snapshot(&path).unwrap();
+        let mut loaded = Road::load_snapshot(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(*loaded.params, params);
+
+        // and both go on exactly the same from there
+        for _ in 0..200 {
+            road.update_belief(&mut rng.clone()).unwrap();
+            loaded.update_belief(&mut rng).unwrap();
+            road.update(params.physics_dt);
+            loaded.update(params.physics_dt);
+        }
+        assert!(road.cost == loaded.cost);
+        for (car, loaded_car) in road.cars.iter().zip(loaded.cars.iter()) {
+            assert_eq!(
+                (car.x(), car.y(), car.vel),
+                (loaded_car.x(), loaded_car.y(), loaded_car.vel)
+            );
+            assert_eq!(car.side_policy, loaded_car.side_policy);
+        }
+    }
+
+    #[test]
+    fn test_pick_car() {
+        let params = Parameters::new().unwrap();
+        let mut road = Road::new(Arc::new(params.clone()));
+        let mut car = Car::new(&params, 1, 1);
+        car.set_x(road.cars[0].x() + 30.0);
+        road.cars.push(car);
+        road.init_belief();
+
+        let lane_y = Road::get_lane_y(1);
+        let x = road.cars[1].x() - 1.0;
+        assert_eq!(road.pick_car(x, lane_y), Some(1));
+        // near enough just ahead of it, but not off in the next lane over
+        assert_eq!(road.pick_car(x + 2.5, lane_y), Some(1));
+        assert_eq!(road.pick_car(x, Road::get_lane_y(0)), None);
+
+        let description = road.describe_car(1);
+        assert!(description.starts_with("car 1 at step 0"));
+        assert!(description.contains("belief"));
+    }
+
+    #[test]
+    fn test_objective_mode() {
+        let mut params = Parameters::new().unwrap();
+        let discounted = Road::new(Arc::new(params.clone())).sim_estimate();
+        assert_eq!(discounted.cost.discount_factor, params.cost.discount_factor);
+
+        // the same cost over 2 s is half as much per second
+        params.objective_mode = "average".to_string();
+        let mut road = Road::new(Arc::new(params)).sim_estimate();
+        assert_eq!(road.cost.discount_factor, 1.0);
+        road.cost.efficiency = 10.0;
+        road.t += 2.0;
+        assert_eq!(road.final_cost().efficiency, 5.0);
+    }
+
+    #[test]
+    fn test_goal_cost() {
+        let mut params = Parameters::new().unwrap();
+        params.goal.enabled = true;
+        params.goal.x = 50.0;
+        params.goal.lane = 1;
+        params.goal.time = 10.0;
+        let lane_weight = params.goal.lane_weight;
+        let late_weight = params.goal.late_weight;
+        let mut road = Road::new(Arc::new(params));
+        road.cars[0].set_x(0.0);
+        road.cars[0].set_y(Road::get_lane_y(0));
+
+        // on time, but in the wrong lane
+        road.cars[0].vel = 10.0;
+        assert_eq!(road.goal_cost(), lane_weight);
+
+        // and too slow to make it, 50 s away
+        road.cars[0].vel = 1.0;
+        assert!((road.goal_cost() - lane_weight - 40.0 * late_weight).abs() < 1e-9);
+
+        // once it passes in the goal lane, it's done whatever it does after
+        road.last_ego = road.cars[0].clone();
+        road.cars[0].set_x(51.0);
+        road.cars[0].set_y(Road::get_lane_y(1));
+        road.t = 12.0;
+        road.update_cost(0.1);
+        assert_eq!(road.goal_reached, Some((12.0, 1)));
+        road.cars[0].set_y(Road::get_lane_y(0));
+        assert!((road.goal_cost() - 2.0 * late_weight).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_comfort_cost() {
+        let mut params = Parameters::new().unwrap();
+        params.cost.jerk_weight = 1.0;
+        params.cost.lat_accel_weight = 1.0;
+        let mut road = Road::new(Arc::new(params));
+        road.cars[0].vel = 10.0;
+        road.last_ego = road.cars[0].clone();
+
+        // going from no acceleration to 2 m/s^2 in 0.1 s, in a straight line
+        road.cars[0].accel = 2.0;
+        road.update_cost(0.1);
+        assert!((road.cost.jerk - 20.0f64.powi(2) * 0.1).abs() < 1e-9);
+        assert_eq!(road.cost.lat_accel, 0.0);
+
+        // then turning at 0.1 rad/s
+        let theta = road.cars[0].theta() + 0.01;
+        road.cars[0].set_theta(theta);
+        let discount = road.cost.discount;
+        road.update_cost(0.1);
+        assert!((road.cost.lat_accel - 1.0f64.powi(2) * 0.1 * discount).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_lane_offset_cost() {
+        let mut params = Parameters::new().unwrap();
+        params.cost.lane_offset_weight = 2.0;
+        let mut road = Road::new(Arc::new(params));
+        road.last_ego = road.cars[0].clone();
+
+        // 0.5 m off the center of its lane
+        road.cars[0].set_y(Road::get_lane_y(1) + 0.5);
+        road.cars[0].target_lane_i = 1;
+        assert_eq!(road.ego_lane_offset(), Some(0.5));
+        road.update_cost(0.1);
+        assert!((road.cost.lane_offset - 2.0 * 0.5f64.powi(2) * 0.1).abs() < 1e-9);
+
+        // but not while changing lanes
+        road.cars[0].target_lane_i = 0;
+        assert_eq!(road.ego_lane_offset(), None);
+        let lane_offset = road.cost.lane_offset;
+        road.update_cost(0.1);
+        assert_eq!(road.cost.lane_offset, lane_offset);
+    }
+
+    #[test]
+    fn test_on_ramp() {
+        let mut params = Parameters::new().unwrap();
+        params.merge.ramp_lane = 0;
+        params.merge.start = 0.0;
+        params.merge.end = 100.0;
+        params.merge.period = 300.0;
+        let mut road = Road::new(Arc::new(params));
+
+        assert!(road.lane_exists(0, 50.0) && road.lane_exists(0, 350.0));
+        assert!(!road.lane_exists(0, 150.0) && !road.lane_exists(0, -10.0));
+        assert!(road.lane_exists(1, 150.0) && !road.lane_exists(2, 150.0));
+        assert_eq!(road.lane_end_ahead(0, 330.0), Some(70.0));
+        assert_eq!(road.lane_end_ahead(1, 30.0), None);
+
+        // the ego on the ramp has to merge out once it's close to the end
+        road.cars[0].vel = 10.0;
+        road.cars[0].set_x(10.0);
+        assert_eq!(road.usable_lane(0, 0), 0);
+        road.cars[0].set_x(90.0);
+        assert_eq!(road.usable_lane(0, 0), 1);
+        assert_eq!(road.usable_lane(0, 1), 1);
+    }
+
+    #[test]
+    fn test_lane_closure() {
+        let mut params = Parameters::new().unwrap();
+        params.closure.lane = 1;
+        params.closure.start = 100.0;
+        params.closure.end = 160.0;
+        let mut road = Road::new(Arc::new(params));
+
+        assert!(road.lane_exists(1, 90.0) && !road.lane_exists(1, 120.0));
+        assert!(road.lane_exists(1, 170.0) && road.lane_exists(0, 120.0));
+        assert_eq!(road.lane_end_ahead(1, 40.0), Some(60.0));
+        assert_eq!(road.lane_end_ahead(1, 120.0), None);
+        assert_eq!(road.lane_end_ahead(0, 40.0), None);
+        assert!(!road.lane_definitely_clear_between(0, 1, 95.0, 105.0));
+        assert!(road.lane_definitely_clear_between(0, 1, 165.0, 175.0));
+
+        // nobody heads for the closed lane close to the closure
+        road.cars[0].vel = 10.0;
+        road.cars[0].set_x(0.0);
+        assert_eq!(road.usable_lane(0, 1), 1);
+        road.cars[0].set_x(80.0);
+        assert_eq!(road.usable_lane(0, 1), 0);
+        road.cars[0].set_x(130.0);
+        assert_eq!(road.usable_lane(0, 1), 0);
+    }
+
+    #[test]
+    fn test_open_boundary_density() {
+        use rand::SeedableRng;
+
+        let mut params = Parameters::new().unwrap();
+        params.spawn.open_boundary = true;
+        let spawn = params.spawn.clone();
+        let mut road = Road::new(Arc::new(params));
+        let mut rng = StdRng::seed_from_u64(1);
+        road.populate_open_boundary(&mut rng);
+        road.init_belief();
+        let expected = road.open_boundary_density()
+            * (spawn.remove_ahead_beyond + spawn.remove_behind_beyond)
+            * 2.0;
+
+        // everyone just drives straight at their own speed
+        let dt = 0.05;
+        road.cars[0].vel = 11.0;
+        let mut total_cars = 0;
+        let n_steps = 20000;
+        for _ in 0..n_steps {
+            for car in road.cars.iter_mut() {
+                car.set_x(car.x() + car.vel * dt);
+            }
+            road.open_boundary_traffic(&mut rng, dt);
+            road.timesteps += 1;
+            assert!(road.cars.iter().enumerate().all(|(i, c)| c.car_i == i));
+            total_cars += road.cars.len() - 1;
+        }
+        let mean_cars = total_cars as f64 / n_steps as f64;
+        assert!(
+            (mean_cars - expected).abs() < 0.25 * expected,
+            "{} vs {}",
+            mean_cars,
+            expected
+        );
+        assert_eq!(
+            road.belief
+                .as_ref()
+                .unwrap()
+                .get_all(road.cars.len() - 1)
+                .len(),
+            9
+        );
+    }
 }
@@ -0,0 +1,140 @@
+use std::cell::{Cell, RefCell};
+
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+
+use crate::{
+    alloc_counter,
+    arg_parameters::Parameters,
+    candidates, profiling,
+    road::{self, Road},
+};
+
+// Per-thread scratch memory for the planners. Each rayon worker runs one scenario at a time,
+// so roads recycled here are reused by its later rollouts and scenarios
+// instead of reallocating their car vectors for every forward simulation.
+// params.memory_budget_mb (per worker, 0 for unbounded) caps both the recycled roads kept here
+// and the car trace points that forward simulations record during one planning call.
+
+thread_local! {
+    // (heap bytes, road)
+    static RECYCLED_ROADS: RefCell<Vec<(usize, Road)>> = RefCell::new(Vec::new());
+    static RECYCLED_BYTES: Cell<usize> = Cell::new(0);
+    static TRACE_BYTES: Cell<usize> = Cell::new(0);
+}
+
+fn budget_bytes(params: &Parameters) -> Option<usize> {
+    if params.memory_budget_mb == 0 {
+        None
+    } else {
+        Some(params.memory_budget_mb * 1024 * 1024)
+    }
+}
+
+// A copy of road, made in the allocations of a recycled road if there is one
+pub fn clone_road(road: &Road) -> Road {
+    match RECYCLED_ROADS.with(|recycled| recycled.borrow_mut().pop()) {
+        Some((bytes, mut recycled)) => {
+            RECYCLED_BYTES.with(|b| b.set(b.get() - bytes));
+            recycled.clone_from(road);
+            recycled
+        }
+        None => road.clone(),
+    }
+}
+
+// Keeps the road's allocations for later clone_road calls, while they fit in the budget
+pub fn recycle_road(mut road: Road) {
+    // the truth road's belief has to stay exclusive for update_belief
+    road.belief = None;
+    let bytes = road.heap_bytes();
+    let total = RECYCLED_BYTES.with(|b| b.get()) + bytes;
+    if budget_bytes(&road.params).map_or(false, |budget| total > budget) {
+        return;
+    }
+    RECYCLED_BYTES.with(|b| b.set(total));
+    RECYCLED_ROADS.with(|recycled| recycled.borrow_mut().push((bytes, road)));
+}
+
+// Starts a new planning call's car trace budget
+pub fn begin_planning() {
+    TRACE_BYTES.with(|b| b.set(0));
+}
+
+// Accounts for recording bytes more of car traces,
+// returning false once this planning call's traces would go over the budget
+pub fn reserve_trace_bytes(params: &Parameters, bytes: usize) -> bool {
+    TRACE_BYTES.with(|b| {
+        let total = b.get() + bytes;
+        if budget_bytes(params).map_or(false, |budget| total > budget) {
+            return false;
+        }
+        b.set(total);
+        true
+    })
+}
+
+// f for each of items, in order, and with params.parallel_rollouts spread over the rayon pool.
+// The forward sims only depend on their roads, so this chooses the same either way.
+// That's only outside rayon's workers: one running a sweep's scenario that waited on the tasks
+// could pick up another of the scenarios meanwhile, mixing up its thread-locals.
+// So only these tasks run on the pool's threads, and each hands back what it counted there
+// with its result: its rollouts, allocations, profiled time, trace lines and candidates.
+// Each task recycles into the arena of the thread it runs on,
+// and may record an equal share of what's left of the planning call's car trace budget.
+pub fn map_rollouts<T, R, F>(params: &Parameters, items: &[T], f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    if !params.parallel_rollouts || rayon::current_thread_index().is_some() {
+        return items.iter().map(f).collect();
+    }
+    let start_bytes = TRACE_BYTES.with(|b| b.get());
+    // where each task's traces start counting, so that its share takes it to the budget
+    let task_bytes = budget_bytes(params).map_or(0, |budget| {
+        let share = budget.saturating_sub(start_bytes) / items.len().max(1);
+        budget - share
+    });
+    let collect_lines = road::collecting_trace_lines();
+    let results = items
+        .par_iter()
+        .map(|item| {
+            TRACE_BYTES.with(|b| b.set(task_bytes));
+            road::collect_trace_lines(collect_lines);
+            let result = f(item);
+            let counts = Counts {
+                recorded: TRACE_BYTES.with(|b| b.get()) - task_bytes,
+                rollouts: road::take_rollout_count(),
+                allocations: alloc_counter::take_allocation_count(),
+                profile: profiling::take_profile(),
+                lines: road::take_trace_lines(),
+                candidates: candidates::take_candidates(),
+            };
+            (result, counts)
+        })
+        .collect::<Vec<_>>();
+    let recorded = results.iter().map(|(_, c)| c.recorded).sum::<usize>();
+    TRACE_BYTES.with(|b| b.set(start_bytes + recorded));
+    road::add_rollout_count(results.iter().map(|(_, c)| c.rollouts).sum());
+    alloc_counter::add_allocation_count(results.iter().map(|(_, c)| c.allocations).sum());
+    results
+        .into_iter()
+        .map(|(result, counts)| {
+            profiling::add_profile(&counts.profile);
+            road::add_trace_lines(counts.lines);
+            candidates::add_candidates(&counts.candidates);
+            result
+        })
+        .collect()
+}
+
+// what a map_rollouts task counted on its thread, for the calling thread
+struct Counts {
+    recorded: usize,
+    rollouts: u64,
+    allocations: u64,
+    profile: profiling::Profile,
+    lines: Vec<road::TraceLine>,
+    candidates: Vec<candidates::Candidate>,
+}
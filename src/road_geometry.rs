@@ -0,0 +1,310 @@
+use std::f64::consts::PI;
+
+use serde::{Deserialize, Serialize};
+
+// A piece of the road's centerline, with its curvature changing linearly over its length:
+// straight (zero curvature), a constant-curvature arc, or a clothoid between two curvatures.
+// Positive curvature turns left.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+struct Segment {
+    length: f64,
+    start_curvature: f64,
+    end_curvature: f64,
+}
+
+impl Segment {
+    fn curvature(&self, s: f64) -> f64 {
+        self.start_curvature + (self.end_curvature - self.start_curvature) * s / self.length
+    }
+
+    // change in heading from the start of the segment
+    fn heading(&self, s: f64) -> f64 {
+        self.start_curvature * s
+            + (self.end_curvature - self.start_curvature) * s * s / (2.0 * self.length)
+    }
+
+    // centerline pose at s, relative to the segment's start
+    fn local_pose(&self, s: f64) -> Pose {
+        if self.start_curvature == self.end_curvature {
+            let k = self.start_curvature;
+            if k == 0.0 {
+                return Pose {
+                    x: s,
+                    y: 0.0,
+                    heading: 0.0,
+                };
+            }
+            return Pose {
+                x: (k * s).sin() / k,
+                y: (1.0 - (k * s).cos()) / k,
+                heading: k * s,
+            };
+        }
+
+        // Simpson's rule, with steps of at most a meter
+        let n = ((s.abs() / 2.0).ceil() as usize).max(1) * 2;
+        let h = s / n as f64;
+        let (mut x, mut y) = (0.0, 0.0);
+        for i in 0..=n {
+            let weight = if i == 0 || i == n {
+                1.0
+            } else if i % 2 == 1 {
+                4.0
+            } else {
+                2.0
+            };
+            let heading = self.heading(i as f64 * h);
+            x += weight * heading.cos();
+            y += weight * heading.sin();
+        }
+        Pose {
+            x: x * h / 3.0,
+            y: y * h / 3.0,
+            heading: self.heading(s),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Pose {
+    pub x: f64,
+    pub y: f64,
+    pub heading: f64,
+}
+
+impl Pose {
+    const ORIGIN: Pose = Pose {
+        x: 0.0,
+        y: 0.0,
+        heading: 0.0,
+    };
+
+    // other, given relative to self
+    fn then(&self, other: &Pose) -> Pose {
+        let (sin, cos) = self.heading.sin_cos();
+        Pose {
+            x: self.x + cos * other.x - sin * other.y,
+            y: self.y + sin * other.x + cos * other.y,
+            heading: self.heading + other.heading,
+        }
+    }
+
+    fn inverse(&self) -> Pose {
+        let (sin, cos) = self.heading.sin_cos();
+        Pose {
+            x: -cos * self.x - sin * self.y,
+            y: sin * self.x - cos * self.y,
+            heading: -self.heading,
+        }
+    }
+}
+
+// The road's centerline, for running on curved roads in the road's Frenet frame:
+// a car's x is its station s along the centerline, its y is the offset d to the left
+// of the centerline (so lanes keep their y), and its theta is relative to the centerline's heading.
+// Gaps and collisions are still checked in the road's frame, which is close while
+// the curvature is small next to 1 / car length.
+// The segments repeat forever in both directions, and no segments is a straight road.
+//
+// Parsed from road_geometry, as comma-separated segments:
+// straight:<length>, arc:<length>:<curvature>, or clothoid:<length>:<start curvature>:<end curvature>
+// like "straight:100,clothoid:50:0:0.01,arc:100:0.01,clothoid:50:0.01:0"
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RoadGeometry {
+    segments: Vec<Segment>,
+    // station and pose at the start of each segment
+    starts: Vec<(f64, Pose)>,
+    period: f64,
+    period_end: Pose,
+}
+
+impl RoadGeometry {
+    pub fn straight() -> Self {
+        Self {
+            segments: Vec::new(),
+            starts: Vec::new(),
+            period: 0.0,
+            period_end: Pose::ORIGIN,
+        }
+    }
+
+    pub fn parse(spec: &str) -> Self {
+        if spec.trim().is_empty() || spec.trim() == "straight" {
+            return Self::straight();
+        }
+
+        let segments = spec
+            .split(',')
+            .map(|part| {
+                let fields = part.trim().split(':').collect::<Vec<_>>();
+                let num = |i: usize| -> f64 {
+                    fields
+                        .get(i)
+                        .and_then(|f| f.parse().ok())
+                        .unwrap_or_else(|| panic!("Bad road_geometry segment '{}'", part))
+                };
+                let (length, start_curvature, end_curvature) = match (fields[0], fields.len()) {
+                    ("straight", 2) => (num(1), 0.0, 0.0),
+                    ("arc", 3) => (num(1), num(2), num(2)),
+                    ("clothoid", 4) => (num(1), num(2), num(3)),
+                    _ => panic!(
+                        "Bad road_geometry segment '{}', expected straight:<length>, arc:<length>:<curvature>, or clothoid:<length>:<curvature>:<curvature>",
+                        part
+                    ),
+                };
+                assert!(length > 0.0, "road_geometry segment '{}' needs a positive length", part);
+                Segment {
+                    length,
+                    start_curvature,
+                    end_curvature,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut starts = Vec::new();
+        let mut station = 0.0;
+        let mut pose = Pose::ORIGIN;
+        for segment in segments.iter() {
+            starts.push((station, pose));
+            station += segment.length;
+            pose = pose.then(&segment.local_pose(segment.length));
+        }
+
+        Self {
+            segments,
+            starts,
+            period: station,
+            period_end: pose,
+        }
+    }
+
+    pub fn is_straight(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    // the segment containing s (within one period) and s relative to its start
+    fn locate(&self, s: f64) -> (i64, usize, f64) {
+        let repeats = (s / self.period).floor();
+        let s = s - repeats * self.period;
+        let i = match self
+            .starts
+            .binary_search_by(|(start, _)| start.partial_cmp(&s).unwrap())
+        {
+            Ok(i) => i,
+            Err(i) => i - 1,
+        };
+        (repeats as i64, i, s - self.starts[i].0)
+    }
+
+    pub fn curvature(&self, s: f64) -> f64 {
+        if self.is_straight() {
+            return 0.0;
+        }
+        let (_, i, local_s) = self.locate(s);
+        self.segments[i].curvature(local_s)
+    }
+
+    pub fn centerline(&self, s: f64) -> Pose {
+        if self.is_straight() {
+            return Pose {
+                x: s,
+                y: 0.0,
+                heading: 0.0,
+            };
+        }
+
+        let (repeats, i, local_s) = self.locate(s);
+        let step = if repeats >= 0 {
+            self.period_end
+        } else {
+            self.period_end.inverse()
+        };
+        let mut pose = Pose::ORIGIN;
+        for _ in 0..repeats.abs() {
+            pose = pose.then(&step);
+        }
+        pose.then(&self.starts[i].1)
+            .then(&self.segments[i].local_pose(local_s))
+    }
+
+    // world position of station s, offset d to the left of the centerline
+    pub fn to_cartesian(&self, s: f64, d: f64) -> (f64, f64) {
+        let pose = self.centerline(s);
+        let (sin, cos) = pose.heading.sin_cos();
+        (pose.x - sin * d, pose.y + cos * d)
+    }
+
+    // station and offset of the world position (x, y), found from near s_hint
+    pub fn to_frenet(&self, x: f64, y: f64, s_hint: f64) -> (f64, f64) {
+        let mut s = s_hint;
+        for _ in 0..50 {
+            let pose = self.centerline(s);
+            let (sin, cos) = pose.heading.sin_cos();
+            let (dx, dy) = (x - pose.x, y - pose.y);
+            let along = cos * dx + sin * dy;
+            let d = -sin * dx + cos * dy;
+            let ds = along / (1.0 - self.curvature(s) * d).max(0.1);
+            s += ds;
+            if ds.abs() < 1e-9 {
+                break;
+            }
+        }
+        let pose = self.centerline(s);
+        let d = -pose.heading.sin() * (x - pose.x) + pose.heading.cos() * (y - pose.y);
+        (s, d)
+    }
+
+    // world heading of a car with heading theta relative to the road at station s
+    pub fn heading(&self, s: f64, theta: f64) -> f64 {
+        let heading = self.centerline(s).heading + theta;
+        (heading + PI).rem_euclid(2.0 * PI) - PI
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+
+    #[test]
+    fn test_road_geometry() {
+        let straight = RoadGeometry::parse("");
+        assert_eq!(straight.to_cartesian(12.5, -1.0), (12.5, -1.0));
+        assert_eq!(straight.curvature(-40.0), 0.0);
+
+        // a quarter circle of radius 100, then straight up
+        let r = 100.0;
+        let geometry =
+            RoadGeometry::parse(&format!("arc:{}:{},straight:50", PI / 2.0 * r, 1.0 / r));
+        let end = geometry.centerline(PI / 2.0 * r);
+        assert_abs_diff_eq!(end.x, r, epsilon = 1e-9);
+        assert_abs_diff_eq!(end.y, r, epsilon = 1e-9);
+        assert_abs_diff_eq!(end.heading, PI / 2.0, epsilon = 1e-9);
+        let (x, y) = geometry.to_cartesian(PI / 2.0 * r + 20.0, 2.0);
+        assert_abs_diff_eq!(x, r - 2.0, epsilon = 1e-9);
+        assert_abs_diff_eq!(y, r + 20.0, epsilon = 1e-9);
+
+        // a clothoid into the arc and back out ends up at the same pose from either side
+        let geometry =
+            RoadGeometry::parse("straight:30,clothoid:40:0:0.02,arc:30:0.02,clothoid:40:0.02:0");
+        for &(s, d) in [
+            (10.0, 1.5),
+            (55.0, -1.8),
+            (90.0, 0.3),
+            (135.0, 1.0),
+            (400.0, -1.0),
+            (-60.0, 1.7),
+        ]
+        .iter()
+        {
+            let (x, y) = geometry.to_cartesian(s, d);
+            let pose = geometry.centerline(s);
+            assert_abs_diff_eq!((x - pose.x).hypot(y - pose.y), d.abs(), epsilon = 1e-9);
+            let (s2, d2) = geometry.to_frenet(x, y, s + 3.0);
+            assert_abs_diff_eq!(s2, s, epsilon = 1e-6);
+            assert_abs_diff_eq!(d2, d, epsilon = 1e-6);
+        }
+        assert_abs_diff_eq!(geometry.curvature(50.0), 0.01, epsilon = 1e-12);
+        assert_abs_diff_eq!(geometry.curvature(140.0 + 80.0), 0.02, epsilon = 1e-12);
+    }
+}
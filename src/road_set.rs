@@ -1,6 +1,10 @@
 use rand::prelude::StdRng;
 
-use crate::{cost::Cost, road::Road, side_policies::SidePolicy};
+use crate::{
+    cost::Cost, forward_control::ForwardControl, idm_control::IdmControl,
+    mobil_policy::MobilPolicy, mpdm::make_obstacle_vehicle_policy_belief_states, road::Road,
+    road_arena, side_policies::SidePolicy,
+};
 
 #[derive(Clone)]
 pub struct RoadSet {
@@ -25,12 +29,54 @@ impl RoadSet {
             };
         }
 
-        let mut roads = Vec::with_capacity(n);
-        for _ in 0..n {
-            roads.push(road.sample_belief(rng));
+        Self::new(road.sample_beliefs(rng, n))
+    }
+
+    // How the obstacle cars act in the rollouts, by the planner's prediction parameter:
+    // "belief" leaves them the policies sampled from the belief, "interactive" has them all
+    // react to the ego and each other by IDM and MOBIL instead, whatever they're believed
+    // to be doing, and "open_loop" holds them to their current velocities
+    pub fn set_prediction(&mut self, prediction: &str) {
+        match prediction {
+            "belief" => (),
+            "interactive" => {
+                // with an id past the belief's, so it's never mistaken for one of them
+                let params = &self.roads[0].params;
+                let policy_id = make_obstacle_vehicle_policy_belief_states(params).len() as u32;
+                let mobil = SidePolicy::MobilPolicy(MobilPolicy::new(policy_id));
+                for road in self.roads.iter_mut() {
+                    for car in road.cars.iter_mut().skip(1) {
+                        car.side_policy.clone_from(&mobil);
+                        car.forward_control = ForwardControl::IdmControl(IdmControl::new());
+                    }
+                }
+            }
+            "open_loop" => {
+                for road in self.roads.iter_mut() {
+                    for car in road.cars.iter_mut().skip(1) {
+                        *car = car.open_loop_estimate();
+                    }
+                }
+            }
+            _ => panic!(
+                "Unknown prediction '{}', expected belief, interactive, or open_loop",
+                prediction
+            ),
         }
+    }
 
-        Self::new(roads)
+    // Like clone(), but reusing the allocations of roads recycled on this thread
+    pub fn arena_clone(&self) -> Self {
+        Self {
+            roads: self.roads.iter().map(road_arena::clone_road).collect(),
+        }
+    }
+
+    // Hands the roads back to this thread's arena for later arena_clone() calls
+    pub fn recycle(self) {
+        for road in self.roads {
+            road_arena::recycle_road(road);
+        }
     }
 
     pub fn ego_policy(&self) -> &SidePolicy {
@@ -39,13 +85,13 @@ impl RoadSet {
 
     pub fn set_ego_policy(&mut self, policy: &SidePolicy) {
         for road in self.roads.iter_mut() {
-            road.set_ego_policy(policy.clone());
+            road.set_ego_policy(policy);
         }
     }
 
     pub fn set_ego_policy_not_switched(&mut self, policy: &SidePolicy) {
         for road in self.roads.iter_mut() {
-            road.set_ego_policy_not_switched(policy.clone());
+            road.set_ego_policy_not_switched(policy);
         }
     }
 
@@ -84,15 +130,146 @@ impl RoadSet {
     }
 
     pub fn cost(&self) -> Cost {
-        self.roads.iter().map(|r| r.cost).sum::<Cost>() / self.roads.len() as f64
+        Cost::aggregate(
+            self.roads.iter().map(|r| r.final_cost()),
+            &self.roads[0].params.risk,
+        )
+    }
+
+    pub fn roads(&self) -> &[Road] {
+        &self.roads
     }
 
-    #[allow(unused)]
     pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Road> {
         self.roads.iter_mut()
     }
 
+    // Splits the roads into sets by key, in the order each key first shows up
+    pub fn partition<K: PartialEq>(self, key: impl Fn(&Road) -> K) -> Vec<(K, RoadSet)> {
+        let mut sets: Vec<(K, RoadSet)> = Vec::new();
+        for road in self.roads {
+            let k = key(&road);
+            match sets.iter_mut().find(|(set_k, _)| *set_k == k) {
+                Some((_, set)) => set.roads.push(road),
+                None => sets.push((k, Self { roads: vec![road] })),
+            }
+        }
+        sets
+    }
+
+    // The reverse of partition, keeping the roads' sample ids
+    pub fn merge(sets: Vec<RoadSet>) -> Self {
+        Self {
+            roads: sets.into_iter().flat_map(|set| set.roads).collect(),
+        }
+    }
+
     pub fn pop(&mut self) -> Road {
         self.roads.remove(0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        arg_parameters::Parameters,
+        car::Car,
+        rng_streams::{self, Stream},
+        side_policies::SidePolicyTrait,
+    };
+
+    #[test]
+    fn test_prediction() {
+        let params = Parameters::new().unwrap();
+        let mut road = Road::new(Arc::new(params.clone()));
+        road.cars[0].vel = 5.0;
+        // coming up fast right behind the ego
+        let mut car = Car::new(&params, 1, 0);
+        car.set_x(-15.0);
+        car.vel = 15.0;
+        road.cars.push(car);
+        road.update_cars_spatial();
+
+        // only a car that reacts to the ego avoids running into it
+        for &(prediction, crashes) in [("open_loop", true), ("interactive", false)].iter() {
+            let mut roads = RoadSet::new(vec![road.sim_estimate()]);
+            roads.set_prediction(prediction);
+            let mut road = roads.roads.pop().unwrap();
+            road.take_update_steps(4.0, 0.1);
+            assert_eq!(road.cars[0].crashed, crashes, "{}", prediction);
+        }
+    }
+
+    #[test]
+    fn test_samples_with_rng_streams() {
+        use rand::SeedableRng;
+
+        let mut params = Parameters::new().unwrap();
+        params.rng_streams = true;
+        let mut road = Road::new(Arc::new(params.clone()));
+        for car_i in 1..4 {
+            let mut car = Car::new(&params, car_i, 1);
+            car.set_x(car_i as f64 * 20.0);
+            road.cars.push(car);
+        }
+        let mut more_cars = road.clone();
+        let mut car = Car::new(&params, 4, 0);
+        car.set_x(-30.0);
+        assert!(more_cars.push_car(car));
+        road.init_belief();
+        more_cars.init_belief();
+
+        // one more car leaves every other car's policies alone, in every sample
+        let rng = StdRng::seed_from_u64(3);
+        let samples = RoadSet::new_samples(&road, &mut rng.clone(), 8);
+        let more_samples = RoadSet::new_samples(&more_cars, &mut rng.clone(), 8);
+        for (a, b) in samples.roads.iter().zip(more_samples.roads.iter()) {
+            for car_i in 1..4 {
+                assert_eq!(a.cars[car_i].side_policy, b.cars[car_i].side_policy);
+            }
+        }
+        let ids = samples
+            .roads
+            .iter()
+            .map(|r| r.cars[1].side_policy.policy_id())
+            .collect::<Vec<_>>();
+        assert!(ids.iter().any(|&id| id != ids[0]));
+    }
+
+    #[test]
+    fn test_batch_samples_match_single_samples() {
+        use rand::{Rng, SeedableRng};
+
+        for rng_streams in [false, true] {
+            let mut params = Parameters::new().unwrap();
+            params.rng_streams = rng_streams;
+            let mut road = Road::new(Arc::new(params.clone()));
+            let mut rng = StdRng::seed_from_u64(0);
+            for _ in 0..10 {
+                road.add_random_car(&mut rng).unwrap();
+            }
+            road.init_belief();
+
+            let (mut batch_rng, mut single_rng) = (rng.clone(), rng.clone());
+            let batch = road.sample_beliefs(&mut batch_rng, 8);
+            let single = (0..8).map(|i| {
+                if rng_streams {
+                    let mut sample_rng = rng_streams::fork(&single_rng, Stream::Belief, &[i]);
+                    road.sample_belief(&mut sample_rng)
+                } else {
+                    road.sample_belief(&mut single_rng)
+                }
+            });
+            for (a, b) in batch.iter().zip(single) {
+                for (car_a, car_b) in a.cars.iter().zip(b.cars.iter()) {
+                    assert_eq!(car_a.side_policy, car_b.side_policy);
+                    assert_eq!(car_a.x(), car_b.x());
+                }
+            }
+            assert_eq!(batch_rng.gen::<u64>(), single_rng.gen::<u64>());
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use crate::{arg_parameters::RssParameters, car::Car, road::Road};
+
+// beyond this far apart, cars are never close enough for the ego's RSS check
+const RSS_CHECK_DIST: f64 = 150.0;
+
+// The minimum safe distance from Responsibility-Sensitive Safety (Shalev-Shwartz et al. 2017)
+// for a rear car at rear_vel behind a front car at front_vel: the rear car could accelerate at
+// max_accel through the response time and only then brake at min_brake,
+// while the front car brakes at max_brake
+pub fn longitudinal_safe_dist(rss: &RssParameters, rear_vel: f64, front_vel: f64) -> f64 {
+    let rho = rss.response_time;
+    let rear_vel_after = rear_vel + rho * rss.max_accel;
+    (rear_vel * rho
+        + 0.5 * rss.max_accel * rho.powi(2)
+        + rear_vel_after.powi(2) / (2.0 * rss.min_brake)
+        - front_vel.powi(2) / (2.0 * rss.max_brake))
+        .max(0.0)
+}
+
+// And the lateral one, with both lateral velocities positive toward the other car:
+// each could move toward the other at lat_max_accel through the response time, before
+// braking laterally at lat_min_brake, and they still need lat_margin between them
+pub fn lateral_safe_dist(rss: &RssParameters, vel_a: f64, vel_b: f64) -> f64 {
+    let rho = rss.response_time;
+    let travel = |vel: f64| {
+        let vel_after = vel + rho * rss.lat_max_accel;
+        // a car moving away that just keeps doing so still counts for what it covers
+        let dist = (vel + vel_after) / 2.0 * rho;
+        if vel_after > 0.0 {
+            dist + vel_after.powi(2) / (2.0 * rss.lat_min_brake)
+        } else {
+            dist
+        }
+    };
+    rss.lat_margin + (travel(vel_a) + travel(vel_b)).max(0.0)
+}
+
+fn lat_vel(car: &Car) -> f64 {
+    car.vel * car.theta().sin() + car.lat_vel * car.theta().cos()
+}
+
+// whether the ego is in an RSS dangerous situation with some other car:
+// closer than the safe distances both along the road and across it
+pub fn ego_violation(road: &Road) -> bool {
+    let rss = &road.params.rss;
+    let ego = &road.cars[0];
+    road.cars_near_x(ego.x(), RSS_CHECK_DIST).any(|car| {
+        if car.is_ego() || (car.x() - ego.x()).abs() > RSS_CHECK_DIST {
+            return false;
+        }
+
+        // along the road, with the x of each car being its front
+        let long_unsafe = if ego.x() <= car.x() - car.length {
+            let gap = car.x() - car.length - ego.x();
+            gap < longitudinal_safe_dist(rss, ego.vel, car.vel)
+        } else if car.x() <= ego.x() - ego.length {
+            let gap = ego.x() - ego.length - car.x();
+            gap < longitudinal_safe_dist(rss, car.vel, ego.vel)
+        } else {
+            true
+        };
+        if !long_unsafe {
+            return false;
+        }
+
+        // and across it
+        let lat_gap = (car.y() - ego.y()).abs() - (car.width + ego.width) / 2.0;
+        if lat_gap <= 0.0 {
+            return true;
+        }
+        let (ego_toward, car_toward) = if car.y() > ego.y() {
+            (lat_vel(ego), -lat_vel(car))
+        } else {
+            (-lat_vel(ego), lat_vel(car))
+        };
+        lat_gap < lateral_safe_dist(rss, ego_toward, car_toward)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::arg_parameters::Parameters;
+
+    #[test]
+    fn test_rss() {
+        let params = Parameters::new().unwrap();
+        let rss = &params.rss;
+        // more room to follow a faster car, or at all than behind a stopped one
+        assert!(longitudinal_safe_dist(rss, 10.0, 10.0) > 0.0);
+        assert!(longitudinal_safe_dist(rss, 10.0, 10.0) < longitudinal_safe_dist(rss, 10.0, 0.0));
+        assert_eq!(longitudinal_safe_dist(rss, 0.0, 30.0), 0.0);
+        assert!(lateral_safe_dist(rss, 0.0, 0.0) >= rss.lat_margin);
+
+        let mut road = Road::new(Arc::new(params.clone()));
+        road.cars[0].vel = 15.0;
+        let mut ahead = Car::new(&params, 1, 0);
+        ahead.vel = 15.0;
+        ahead.set_x(ahead.length + 100.0);
+        road.cars.push(ahead);
+        assert!(!ego_violation(&road));
+
+        // tailgating it
+        let x = road.cars[1].length + 10.0;
+        road.cars[1].set_x(x);
+        assert!(ego_violation(&road));
+
+        // but it's fine to be alongside a car keeping to the next lane
+        road.cars[1].set_x(0.0);
+        road.cars[1].set_y(Road::get_lane_y(1));
+        assert!(!ego_violation(&road));
+    }
+}
@@ -0,0 +1,139 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::PathBuf,
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::json;
+
+use crate::{arg_parameters::Parameters, candidates::Candidate, cost::Cost, reward::Reward};
+
+fn git_hash() -> Option<String> {
+    let output = Command::new("git")
+        .args(&["rev-parse", "HEAD"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+// Everything needed to reconstruct a single run, in its own directory under params.runs_dir:
+// parameters.json (the fully resolved parameters), metadata.json (seed, git hash, final metrics),
+// timesteps.csv (the ego cost accrued during each physics timestep, by component,
+// and the mean entropy of the belief over the obstacle cars after it),
+// and candidates.csv (each replan's candidate policies, their estimated costs and the chosen one).
+pub fn write_run_artifacts(
+    params: &Parameters,
+    cost_history: &[Cost],
+    belief_entropy_history: &[f64],
+    candidate_history: &[(u32, Candidate)],
+    cost: &Cost,
+    reward: &Reward,
+) -> std::io::Result<PathBuf> {
+    let unix_time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let dir = PathBuf::from(&params.runs_dir).join(format!(
+        "{}_seed{}_{}",
+        params.method, params.rng_seed, unix_time
+    ));
+    std::fs::create_dir_all(&dir)?;
+
+    serde_json::to_writer_pretty(File::create(dir.join("parameters.json"))?, params)?;
+
+    let normalized = cost.normalize();
+    let mean_belief_entropy = if belief_entropy_history.is_empty() {
+        0.0
+    } else {
+        belief_entropy_history.iter().sum::<f64>() / belief_entropy_history.len() as f64
+    };
+    let metadata = json!({
+        "rng_seed": params.rng_seed,
+        "git_hash": git_hash(),
+        "scenario_name": params.scenario_name,
+        "cost": {
+            "efficiency": normalized.efficiency,
+            "safety": normalized.safety,
+            "accel": normalized.accel,
+            "steer": normalized.steer,
+            "jerk": normalized.jerk,
+            "lat_accel": normalized.lat_accel,
+            "lane_offset": normalized.lane_offset,
+            "total": cost.total(),
+        },
+        "reward": {
+            "crashed": reward.crashed,
+            "end_t": reward.end_t,
+            "dist_travelled": reward.dist_travelled,
+            "avg_vel": reward.avg_vel,
+            "mean_planning_time": reward.mean_planning_time,
+            "below95_planning_time": reward.below95_planning_time,
+            "below997_planning_time": reward.below997_planning_time,
+            "max_planning_time": reward.max_planning_time,
+            "stddev_planning_time": reward.stddev_planning_time,
+            "crash_count": reward.crash_count,
+            "min_ttc": reward.safety.ttc.min,
+            "mean_ttc": reward.safety.ttc.mean(),
+            "min_headway": reward.safety.headway.min,
+            "mean_headway": reward.safety.headway.mean(),
+            "min_pet": reward.safety.pet.min,
+            "mean_pet": reward.safety.pet.mean(),
+            "policy_switches": reward.policy_switches,
+            "rollouts": reward.rollouts,
+            "red_light_violations": reward.red_light_violations,
+            "emergency_brakes": reward.emergency_brakes,
+            "rss_violation_t": reward.rss_violation_t,
+            "impact_speed": reward.impact_speed,
+            "lane_offset_rms": reward.lane_offset_rms(),
+        },
+        "mean_belief_entropy": mean_belief_entropy,
+    });
+    serde_json::to_writer_pretty(File::create(dir.join("metadata.json"))?, &metadata)?;
+
+    let mut timesteps = BufWriter::new(File::create(dir.join("timesteps.csv"))?);
+    writeln!(
+        timesteps,
+        "step,t,efficiency,safety,accel,steer,jerk,lat_accel,lane_offset,total,belief_entropy"
+    )?;
+    let mut last = Cost::ZERO;
+    for (step, (cumulative, entropy)) in cost_history.iter().zip(belief_entropy_history).enumerate()
+    {
+        let delta = *cumulative - last;
+        writeln!(
+            timesteps,
+            "{},{},{},{},{},{},{},{},{},{},{}",
+            step,
+            (step + 1) as f64 * params.physics_dt,
+            delta.efficiency,
+            delta.safety,
+            delta.accel,
+            delta.steer,
+            delta.jerk,
+            delta.lat_accel,
+            delta.lane_offset,
+            delta.total(),
+            entropy
+        )?;
+        last = *cumulative;
+    }
+
+    let mut candidates = BufWriter::new(File::create(dir.join("candidates.csv"))?);
+    writeln!(candidates, "step,t,policy_id,cost,chosen")?;
+    for (step, candidate) in candidate_history.iter() {
+        writeln!(
+            candidates,
+            "{},{},{},{},{}",
+            step,
+            *step as f64 * params.physics_dt,
+            candidate.policy_id,
+            candidate.cost,
+            candidate.chosen as u8
+        )?;
+    }
+
+    Ok(dir)
+}
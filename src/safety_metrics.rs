@@ -0,0 +1,144 @@
+use crate::road::Road;
+
+// leaders further ahead than this (m) aren't being followed, for headway and PET
+const MAX_FOLLOW_DIST: f64 = 100.0;
+// below this ego speed (m/s), headway says nothing useful
+const MIN_HEADWAY_VEL: f64 = 0.5;
+
+// The minimum and mean of a measure over the steps it applied on
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MeasureStats {
+    pub min: Option<f64>,
+    sum: f64,
+    n: u32,
+}
+
+impl MeasureStats {
+    pub fn add(&mut self, val: f64) {
+        self.min = Some(self.min.map_or(val, |m| m.min(val)));
+        self.sum += val;
+        self.n += 1;
+    }
+
+    pub fn mean(&self) -> Option<f64> {
+        if self.n == 0 {
+            None
+        } else {
+            Some(self.sum / self.n as f64)
+        }
+    }
+}
+
+// Surrogate safety measures of the ego over a run, as traffic engineering uses them:
+// time-to-collision with the car ahead in its lane while closing in on it,
+// time headway (the gap over the ego's speed) to that car,
+// and post-encroachment time (PET) whenever a lane change makes the ego and another car
+// newly leader and follower, being how long the follower takes to reach where the leader's rear was
+#[derive(Clone, Debug, Default)]
+pub struct SafetyMetrics {
+    pub ttc: MeasureStats,
+    pub headway: MeasureStats,
+    pub pet: MeasureStats,
+    lead_car_i: Option<usize>,
+    follow_car_i: Option<usize>,
+    last_lanes: Vec<i32>,
+}
+
+// the closest car behind the ego in its lane, with the gap from its front to the ego's rear
+fn follower(road: &Road) -> Option<(f64, usize)> {
+    let ego = &road.cars[0];
+    let lane_i = ego.current_lane();
+    road.cars
+        .iter()
+        .enumerate()
+        .skip(1)
+        .filter(|(_, c)| c.current_lane() == lane_i && c.x() < ego.x())
+        .map(|(i, c)| (ego.x() - ego.length - c.x(), i))
+        .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap())
+}
+
+impl SafetyMetrics {
+    // after each step of the true road
+    pub fn update(&mut self, road: &Road) {
+        let ego = &road.cars[0];
+        let ego_lane = ego.current_lane();
+
+        if let Some(ttc) = road.ego_time_to_collision() {
+            self.ttc.add(ttc);
+        }
+
+        let lead = road
+            .dist_clear_ahead_in_lane(0, ego_lane)
+            .filter(|(dist, _)| *dist < MAX_FOLLOW_DIST);
+        if let Some((dist, _)) = lead {
+            if ego.vel > MIN_HEADWAY_VEL {
+                self.headway.add(dist.max(0.0) / ego.vel);
+            }
+        }
+        let follow = follower(road).filter(|(dist, _)| *dist < MAX_FOLLOW_DIST);
+
+        // a new pair only counts as an encroachment if one of them just came into the lane
+        let last_lanes = &self.last_lanes;
+        let changed_lane = |car_i: usize| {
+            last_lanes
+                .get(car_i)
+                .map_or(false, |&lane_i| lane_i != road.cars[car_i].current_lane())
+        };
+        let encroached = |car_i: usize| changed_lane(0) || changed_lane(car_i);
+        if let Some((dist, car_i)) = lead {
+            if self.lead_car_i != Some(car_i) && encroached(car_i) && ego.vel > 0.0 {
+                self.pet.add(dist.max(0.0) / ego.vel);
+            }
+        }
+        if let Some((dist, car_i)) = follow {
+            let vel = road.cars[car_i].vel;
+            if self.follow_car_i != Some(car_i) && encroached(car_i) && vel > 0.0 {
+                self.pet.add(dist.max(0.0) / vel);
+            }
+        }
+
+        self.lead_car_i = lead.map(|(_, car_i)| car_i);
+        self.follow_car_i = follow.map(|(_, car_i)| car_i);
+        self.last_lanes = road.cars.iter().map(|c| c.current_lane()).collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{arg_parameters::Parameters, car::Car};
+
+    #[test]
+    fn test_safety_metrics() {
+        let params = Parameters::new().unwrap();
+        let mut road = Road::new(Arc::new(params.clone()));
+        road.cars[0].vel = 10.0;
+        let mut lead = Car::new(&params, 1, 1);
+        lead.vel = 5.0;
+        lead.set_x(lead.length + 20.0);
+        road.cars.push(lead);
+        road.update_cars_spatial();
+
+        // nothing ahead of the ego while the car is in the next lane
+        let mut metrics = SafetyMetrics::default();
+        metrics.update(&road);
+        assert!(metrics.ttc.min.is_none());
+        assert!(metrics.headway.min.is_none());
+
+        // and then it cuts in
+        road.cars[1].set_y(Road::get_lane_y(0));
+        road.update_cars_spatial();
+        metrics.update(&road);
+        let (dist, _) = road.dist_clear_ahead_in_lane(0, 0).unwrap();
+        assert!((metrics.ttc.min.unwrap() - dist / 5.0).abs() < 1e-9);
+        assert!((metrics.headway.min.unwrap() - dist / 10.0).abs() < 1e-9);
+        assert!((metrics.pet.min.unwrap() - dist / 10.0).abs() < 1e-9);
+
+        // but following it afterward isn't another encroachment
+        metrics.update(&road);
+        assert_eq!(metrics.pet.n, 1);
+        assert_eq!(metrics.ttc.n, 2);
+    }
+}
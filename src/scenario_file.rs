@@ -0,0 +1,295 @@
+use rand::prelude::StdRng;
+use std::{io, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    arg_parameters::Parameters,
+    car::{Car, SpatialCar, TRUCK_LENGTH},
+    lane_change_policy::LongitudinalPolicy,
+    mpdm::make_obstacle_vehicle_policy_choices,
+    policy_registry::find_lane_change,
+    road::Road,
+    scripted_events::EventSpec,
+    side_policies::SidePolicy,
+    sim_error::SimError,
+};
+
+// A specific traffic configuration, loaded with scenario_file (or --scenario) file.yaml.
+// The listed cars are placed first, then random cars fill in up to n_cars,
+// and cars that fall out of range are respawned randomly as usual.
+//
+// spawn_seed: 7          # seeds the random cars, instead of rng_seed
+// ego: { lane: 0, x: 0.0, vel: 11.0 }
+// cars:
+//   - { lane: 1, x: 15.0, vel: 9.0, policy: maintain, target_lane: 0 }
+// obstacles:
+//   - { lane: 0, x: 120.0 }   # a stopped car
+//   - { lane: 1, x: 200.0, truck: true }   # or truck, which hides more behind it
+// events:
+//   - { car: 1, t: 3.0, action: change_lanes, lane: 0 }   # see scripted_events.rs
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScenarioFile {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spawn_seed: Option<u64>,
+    #[serde(default = "default_true")]
+    pub fill_random_cars: bool,
+    pub ego: Option<CarSpec>,
+    #[serde(default)]
+    pub cars: Vec<CarSpec>,
+    #[serde(default)]
+    pub obstacles: Vec<ObstacleSpec>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub events: Vec<EventSpec>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PolicySpec {
+    Maintain,
+    Accelerate,
+    Decelerate,
+}
+
+impl Default for PolicySpec {
+    fn default() -> Self {
+        Self::Maintain
+    }
+}
+
+// Unset velocities and driver preferences get the same defaults as Car::new
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CarSpec {
+    pub lane: i32,
+    pub x: f64,
+    pub vel: Option<f64>,
+    pub preferred_vel: Option<f64>,
+    pub preferred_accel: Option<f64>,
+    pub follow_time: Option<f64>,
+    #[serde(default)]
+    pub policy: PolicySpec,
+    // defaults to the car's lane
+    pub target_lane: Option<i32>,
+    #[serde(default)]
+    pub truck: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ObstacleSpec {
+    pub lane: i32,
+    pub x: f64,
+    #[serde(default)]
+    pub truck: bool,
+}
+
+impl ScenarioFile {
+    pub fn load(path: &str) -> Result<Self, SimError> {
+        let load_error = |message: String| SimError::Load {
+            path: path.to_owned(),
+            message,
+        };
+        let contents = std::fs::read_to_string(path).map_err(|e| load_error(e.to_string()))?;
+        serde_yaml::from_str(&contents).map_err(|e| load_error(e.to_string()))
+    }
+
+    // Places the ego and the listed cars and obstacles on a new road,
+    // then fills in random cars with rng if fill_random_cars
+    pub fn populate_road(&self, road: &mut Road, rng: &mut StdRng) -> Result<(), SimError> {
+        let params = road.params.clone();
+        if let Some(ego) = &self.ego {
+            road.cars[0] = make_car(&params, 0, ego);
+            road.last_ego = road.cars[0].clone();
+            road.cars_spatial[0] = SpatialCar::from(&road.cars[0]);
+        }
+
+        let obstacles = self
+            .obstacles
+            .iter()
+            .map(|o| CarSpec {
+                lane: o.lane,
+                x: o.x,
+                vel: Some(0.0),
+                preferred_vel: Some(0.0),
+                preferred_accel: None,
+                follow_time: None,
+                policy: PolicySpec::Decelerate,
+                target_lane: None,
+                truck: o.truck,
+            })
+            .collect::<Vec<_>>();
+        for spec in self.cars.iter().chain(obstacles.iter()) {
+            push_car(road, make_car(&params, road.cars.len(), spec))?;
+        }
+
+        if self.fill_random_cars {
+            while road.cars.len() < params.n_cars + 1 {
+                road.add_random_car(rng)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl CarSpec {
+    // The car as it is, for a scenario that starts partway through a run. Only lane change
+    // policies carry over, so MOBIL and the rest maintain toward the car's target lane.
+    pub fn from_car(car: &Car) -> Self {
+        let (policy, target_lane) = match &car.side_policy {
+            SidePolicy::LaneChangePolicy(p) => match p.long_policy() {
+                LongitudinalPolicy::Decelerate => (PolicySpec::Decelerate, None),
+                LongitudinalPolicy::Maintain => (PolicySpec::Maintain, p.target_lane_i()),
+                LongitudinalPolicy::Accelerate => (PolicySpec::Accelerate, p.target_lane_i()),
+            },
+            _ => (PolicySpec::Maintain, Some(car.target_lane_i)),
+        };
+        Self {
+            lane: car.current_lane(),
+            x: car.x(),
+            vel: Some(car.vel),
+            preferred_vel: Some(car.preferred_vel),
+            preferred_accel: Some(car.preferred_accel),
+            follow_time: Some(car.preferred_follow_time),
+            policy,
+            target_lane,
+            truck: car.length == TRUCK_LENGTH,
+        }
+    }
+}
+
+impl ScenarioFile {
+    // The road's cars as they are, with stopped cars as obstacles and no random cars,
+    // to start over from there. Pedestrians and the cars' lateral motion don't carry over.
+    pub fn from_road(road: &Road) -> Self {
+        let mut cars = Vec::new();
+        let mut obstacles = Vec::new();
+        for car in road.cars.iter().skip(1) {
+            if car.vel == 0.0 && car.preferred_vel == 0.0 {
+                obstacles.push(ObstacleSpec {
+                    lane: car.current_lane(),
+                    x: car.x(),
+                    truck: car.length == TRUCK_LENGTH,
+                });
+            } else {
+                cars.push(CarSpec::from_car(car));
+            }
+        }
+        Self {
+            spawn_seed: None,
+            fill_random_cars: false,
+            ego: Some(CarSpec::from_car(&road.cars[0])),
+            cars,
+            obstacles,
+            events: Vec::new(),
+        }
+    }
+
+    pub fn n_cars(&self) -> usize {
+        self.cars.len() + self.obstacles.len()
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let contents = serde_yaml::to_string(self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        std::fs::write(path, contents)
+    }
+}
+
+fn push_car(road: &mut Road, car: Car) -> Result<(), SimError> {
+    if road.collides_any_car(&car) {
+        return Err(SimError::CarOverlap {
+            car_i: car.car_i,
+            x: car.x(),
+        });
+    }
+    road.cars.push(car);
+    Ok(())
+}
+
+fn make_car(params: &Parameters, car_i: usize, spec: &CarSpec) -> Car {
+    assert!(
+        (0..params.n_lanes).contains(&spec.lane),
+        "Scenario file lane {} is not one of the {} lanes",
+        spec.lane,
+        params.n_lanes
+    );
+    let mut car = Car::new(params, car_i, spec.lane);
+    if spec.truck {
+        car.make_truck();
+    }
+    car.set_x(spec.x);
+    if let Some(preferred_vel) = spec.preferred_vel {
+        car.preferred_vel = preferred_vel;
+        car.target_vel = preferred_vel;
+    }
+    car.vel = spec.vel.unwrap_or(car.preferred_vel);
+    if let Some(preferred_accel) = spec.preferred_accel {
+        car.preferred_accel = preferred_accel;
+    }
+    if let Some(follow_time) = spec.follow_time {
+        car.preferred_follow_time = follow_time;
+        car.target_follow_time = follow_time;
+    }
+
+    let target_lane = spec.target_lane.unwrap_or(spec.lane);
+    assert!(
+        (0..params.n_lanes).contains(&target_lane),
+        "Scenario file target_lane {} is not one of the {} lanes",
+        target_lane,
+        params.n_lanes
+    );
+    let (lane_i, long_policy) = match spec.policy {
+        PolicySpec::Decelerate => (None, LongitudinalPolicy::Decelerate),
+        PolicySpec::Maintain => (Some(target_lane), LongitudinalPolicy::Maintain),
+        PolicySpec::Accelerate => (Some(target_lane), LongitudinalPolicy::Accelerate),
+    };
+    let policies = make_obstacle_vehicle_policy_choices(params);
+    let policy = find_lane_change(&policies, lane_i, long_policy).unwrap_or_else(|| {
+        panic!(
+            "Scenario file policy {:?} isn't one of the obstacle cars' policies",
+            spec.policy
+        )
+    });
+    car.side_policy = policy.clone();
+    car
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scripted_events::EventAction;
+
+    #[test]
+    fn test_parse_scenario_file() {
+        let file: ScenarioFile = serde_yaml::from_str(
+            "spawn_seed: 7
+ego: { lane: 0, x: 0.0, vel: 11.0 }
+cars:
+  - { lane: 1, x: 15.0, policy: accelerate, target_lane: 0 }
+obstacles:
+  - { lane: 0, x: 120.0 }
+events:
+  - { car: 1, x: 100.0, action: hard_brake, decel: 4.0 }
+",
+        )
+        .unwrap();
+        assert_eq!(file.spawn_seed, Some(7));
+        assert!(file.fill_random_cars);
+        assert_eq!(file.ego.unwrap().vel, Some(11.0));
+        assert_eq!(file.cars[0].policy, PolicySpec::Accelerate);
+        assert_eq!(file.cars[0].target_lane, Some(0));
+        assert_eq!(file.obstacles[0].x, 120.0);
+        assert_eq!(file.events[0].action, EventAction::HardBrake);
+        assert_eq!(file.events[0].decel, Some(4.0));
+
+        assert!(
+            serde_yaml::from_str::<ScenarioFile>("cars: [{ lane: 0, x: 0, speed: 3 }]").is_err()
+        );
+    }
+}
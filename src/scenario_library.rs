@@ -0,0 +1,133 @@
+use rand::{prelude::StdRng, Rng};
+
+use crate::{
+    car::{FOLLOW_TIME_HIGH, PREFERRED_ACCEL_LOW, SPEED_DEFAULT, SPEED_HIGH, SPEED_LOW},
+    scenario_file::{CarSpec, ObstacleSpec, PolicySpec, ScenarioFile},
+};
+
+pub const SCENARIO_NAMES: &[&str] = &[
+    "hard_brake",
+    "cut_in",
+    "stalled_lead",
+    "slow_convoy",
+    "merge",
+];
+
+fn car(lane: i32, x: f64, vel: f64) -> CarSpec {
+    CarSpec {
+        lane,
+        x,
+        vel: Some(vel),
+        preferred_vel: Some(vel),
+        preferred_accel: None,
+        follow_time: None,
+        policy: PolicySpec::Maintain,
+        target_lane: None,
+        truck: false,
+    }
+}
+
+// Canonical highway situations, selected with named_scenario <name>.
+// Each has a fixed structure around the ego (in lane 0 at x = 0, at SPEED_DEFAULT),
+// with the gaps and speeds drawn from rng so that a block of rng_seeds covers
+// the variations of the same situation. No random traffic is added, so that nothing
+// gets in between the ego and the cars that make the situation, though any car falling
+// out of range is still respawned as random traffic.
+pub fn named_scenario(name: &str, rng: &mut StdRng) -> ScenarioFile {
+    let ego = car(0, 0.0, SPEED_DEFAULT);
+    let mut cars = Vec::new();
+    let mut obstacles = Vec::new();
+
+    match name {
+        // the lead car in the ego's lane brakes hard right away
+        "hard_brake" => {
+            let mut lead = car(0, rng.gen_range(20.0..35.0), SPEED_DEFAULT);
+            lead.policy = PolicySpec::Decelerate;
+            cars.push(lead);
+        }
+        // a faster car in the other lane passes the ego and changes into its lane
+        "cut_in" => {
+            let mut cutter = car(
+                1,
+                rng.gen_range(-15.0..-5.0),
+                rng.gen_range(SPEED_DEFAULT..SPEED_HIGH),
+            );
+            cutter.target_lane = Some(0);
+            cars.push(cutter);
+            cars.push(car(0, rng.gen_range(40.0..70.0), SPEED_DEFAULT));
+        }
+        // a stopped car blocks the ego's lane, with traffic in the other lane
+        "stalled_lead" => {
+            obstacles.push(ObstacleSpec {
+                lane: 0,
+                x: rng.gen_range(60.0..120.0),
+                truck: false,
+            });
+            let mut x = rng.gen_range(-30.0..0.0);
+            for _ in 0..3 {
+                cars.push(car(1, x, rng.gen_range(SPEED_LOW..SPEED_DEFAULT)));
+                x += rng.gen_range(20.0..40.0);
+            }
+        }
+        // slow trucks, closely spaced in the ego's lane
+        "slow_convoy" => {
+            let vel = rng.gen_range(SPEED_LOW * 0.7..SPEED_LOW);
+            let mut x = rng.gen_range(30.0..50.0);
+            for _ in 0..rng.gen_range(3..=5) {
+                let mut truck = car(0, x, vel);
+                truck.preferred_accel = Some(PREFERRED_ACCEL_LOW);
+                truck.follow_time = Some(FOLLOW_TIME_HIGH);
+                cars.push(truck);
+                x += rng.gen_range(12.0..18.0);
+            }
+        }
+        // a car level with the ego in the other lane, at about its speed, merging into its lane
+        "merge" => {
+            let mut merger = car(
+                1,
+                rng.gen_range(-3.0..3.0),
+                SPEED_DEFAULT + rng.gen_range(-1.0..1.0),
+            );
+            merger.target_lane = Some(0);
+            cars.push(merger);
+            cars.push(car(0, rng.gen_range(30.0..60.0), SPEED_DEFAULT));
+        }
+        _ => panic!(
+            "Unknown named_scenario {}, expected one of {:?}",
+            name, SCENARIO_NAMES
+        ),
+    }
+
+    ScenarioFile {
+        spawn_seed: None,
+        fill_random_cars: false,
+        ego: Some(ego),
+        cars,
+        obstacles,
+        events: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+
+    #[test]
+    fn test_named_scenarios_vary_with_seed() {
+        for name in SCENARIO_NAMES.iter() {
+            let a = named_scenario(name, &mut StdRng::seed_from_u64(0));
+            let b = named_scenario(name, &mut StdRng::seed_from_u64(1));
+            assert!(!a.cars.is_empty() || !a.obstacles.is_empty());
+            let xs = |s: &ScenarioFile| {
+                s.cars
+                    .iter()
+                    .map(|c| c.x)
+                    .chain(s.obstacles.iter().map(|o| o.x))
+                    .collect::<Vec<_>>()
+            };
+            assert_ne!(xs(&a), xs(&b), "{}", name);
+        }
+    }
+}
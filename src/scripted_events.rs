@@ -0,0 +1,243 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    car::BREAKING_ACCEL,
+    forward_control::{ForwardControl, ForwardControlTrait},
+    lane_change_policy::{LaneChangePolicy, LongitudinalPolicy},
+    mpdm::make_obstacle_vehicle_policy_choices,
+    policy_registry::find_lane_change,
+    road::Road,
+    side_policies::{SidePolicy, SidePolicyTrait},
+};
+
+// how hard a stalled car slows, m/s^2, without braking
+const STALL_DECEL: f64 = 1.5;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventAction {
+    HardBrake,
+    ChangeLanes,
+    Stall,
+}
+
+// Something a scenario file makes a car do (see scenario_file.rs), triggered by exactly one of
+// t, x, or ego_within. The car is its index on the road: 1 for the first of the cars,
+// counting on through the obstacles.
+//
+// events:
+//   - { car: 2, t: 5.0, action: hard_brake }   # brakes as hard as it can, to a stop
+//   - { car: 2, t: 5.0, action: hard_brake, decel: 4.0, vel: 5.0 }   # or down to 5 m/s
+//   - { car: 1, ego_within: 20.0, action: change_lanes, lane: 0 }
+//   - { car: 3, x: 300.0, action: stall }   # coasts to a stop and stays there
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct EventSpec {
+    pub car: usize,
+    // once the time reaches t
+    pub t: Option<f64>,
+    // once the car reaches x
+    pub x: Option<f64>,
+    // once the ego is within this distance along the road of the car
+    pub ego_within: Option<f64>,
+    pub action: EventAction,
+    // the lane to change into
+    pub lane: Option<i32>,
+    // how hard to slow, m/s^2, or BREAKING_ACCEL for hard_brake and STALL_DECEL for stall
+    pub decel: Option<f64>,
+    // the speed to slow to, and then hold
+    pub vel: Option<f64>,
+}
+
+impl EventSpec {
+    fn triggered(&self, road: &Road) -> bool {
+        let car = &road.cars[self.car];
+        self.t.map_or(false, |t| road.t >= t)
+            || self.x.map_or(false, |x| car.x() >= x)
+            || self
+                .ego_within
+                .map_or(false, |dist| (car.x() - road.cars[0].x()).abs() <= dist)
+    }
+
+    fn apply(&self, road: &mut Road) {
+        let params = road.params.clone();
+        let car = &mut road.cars[self.car];
+        match self.action {
+            EventAction::HardBrake | EventAction::Stall => {
+                let decel = self.decel.unwrap_or(if self.action == EventAction::Stall {
+                    STALL_DECEL
+                } else {
+                    BREAKING_ACCEL
+                });
+                let control = std::mem::take(&mut car.forward_control);
+                car.forward_control = ForwardControl::ScriptedBraking(ScriptedBraking {
+                    decel,
+                    vel: self.vel.unwrap_or(0.0),
+                    control: Box::new(control),
+                });
+            }
+            EventAction::ChangeLanes => {
+                let lane_i = self.lane.unwrap();
+                // right away, without waiting for a clear lane
+                let policies = make_obstacle_vehicle_policy_choices(&params);
+                let policy_id =
+                    find_lane_change(&policies, Some(lane_i), LongitudinalPolicy::Maintain)
+                        .map_or(0, |p| p.policy_id());
+                car.side_policy = SidePolicy::LaneChangePolicy(LaneChangePolicy::new(
+                    policy_id,
+                    Some(lane_i),
+                    params.lane_change_time,
+                    false,
+                    LongitudinalPolicy::Maintain,
+                ));
+            }
+        }
+    }
+}
+
+// A scenario's events, each happening once, the first step it's triggered
+pub struct ScriptedEvents {
+    events: Vec<EventSpec>,
+    fired: Vec<bool>,
+}
+
+impl ScriptedEvents {
+    pub fn new(events: Vec<EventSpec>, road: &Road) -> Self {
+        for event in events.iter() {
+            assert!(
+                (1..road.cars.len()).contains(&event.car),
+                "Scenario event car {} is not one of the road's {} other cars",
+                event.car,
+                road.cars.len() - 1
+            );
+            let n_triggers = [event.t, event.x, event.ego_within]
+                .iter()
+                .filter(|t| t.is_some())
+                .count();
+            assert_eq!(
+                n_triggers, 1,
+                "Scenario event for car {} needs exactly one of t, x, or ego_within",
+                event.car
+            );
+            if event.action == EventAction::ChangeLanes {
+                let lane = event
+                    .lane
+                    .unwrap_or_else(|| panic!("Scenario event for car {} needs a lane", event.car));
+                assert!(
+                    (0..road.params.n_lanes).contains(&lane),
+                    "Scenario event lane {} is not one of the {} lanes",
+                    lane,
+                    road.params.n_lanes
+                );
+            }
+        }
+        let fired = vec![false; events.len()];
+        Self { events, fired }
+    }
+
+    // applies the events triggered now, returning them
+    pub fn update(&mut self, road: &mut Road) -> Vec<EventSpec> {
+        let mut applied = Vec::new();
+        for (event, fired) in self.events.iter().zip(self.fired.iter_mut()) {
+            if *fired || road.cars[event.car].crashed || !event.triggered(road) {
+                continue;
+            }
+            event.apply(road);
+            *fired = true;
+            applied.push(event.clone());
+        }
+        applied
+    }
+
+    // whether an event has taken over car_i, so nothing else should change its policy
+    pub fn is_scripted(&self, car_i: usize) -> bool {
+        self.events
+            .iter()
+            .zip(self.fired.iter())
+            .any(|(event, &fired)| fired && event.car == car_i)
+    }
+}
+
+// Slows at decel down to vel and then holds it, while still braking for whatever's ahead
+// as the car's own forward control would
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ScriptedBraking {
+    decel: f64,
+    vel: f64,
+    control: Box<ForwardControl>,
+}
+
+impl ScriptedBraking {
+    // the car's own forward control, from before the event
+    pub fn control(&self) -> &ForwardControl {
+        &self.control
+    }
+}
+
+impl ForwardControlTrait for ScriptedBraking {
+    fn choose_accel(&mut self, road: &Road, car_i: usize) -> f64 {
+        let accel = self.control.choose_accel(road, car_i);
+        if road.cars[car_i].vel > self.vel {
+            accel.min(-self.decel)
+        } else {
+            accel.min(0.0)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{arg_parameters::Parameters, car::Car};
+
+    #[test]
+    fn test_scripted_events() {
+        let params = Arc::new(Parameters::new().unwrap());
+        let mut road = Road::new(params.clone());
+        road.cars[0].vel = 10.0;
+        for (car_i, lane_i, x) in [(1, 0, 30.0), (2, 1, -15.0)] {
+            let mut car = Car::new(&params, car_i, lane_i);
+            car.set_x(x);
+            car.vel = 10.0;
+            road.cars.push(car);
+        }
+        road.update_cars_spatial();
+
+        let events: Vec<EventSpec> = serde_yaml::from_str(
+            "- { car: 1, t: 1.0, action: hard_brake }
+- { car: 2, ego_within: 20.0, action: change_lanes, lane: 0 }
+",
+        )
+        .unwrap();
+        let mut events = ScriptedEvents::new(events, &road);
+        let mut braked_t = None;
+        while road.t < 4.0 {
+            for event in events.update(&mut road) {
+                if event.action == EventAction::HardBrake {
+                    braked_t = Some(road.t);
+                }
+            }
+            road.update(params.physics_dt);
+        }
+        assert!((braked_t.unwrap() - 1.0).abs() < params.physics_dt);
+        assert!(events.is_scripted(1) && !events.is_scripted(0));
+        // stopped by now, at BREAKING_ACCEL from 10 m/s, after the actuator lag
+        assert_eq!(road.cars[1].vel, 0.0);
+        // and the other car was already close enough behind the ego
+        assert!(road.cars.iter().all(|c| !c.crashed));
+        assert!(events.is_scripted(2));
+        assert_eq!(road.cars[2].target_lane_i, 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_event_needs_trigger() {
+        let params = Arc::new(Parameters::new().unwrap());
+        let mut road = Road::new(params.clone());
+        road.cars.push(Car::new(&params, 1, 0));
+        let events = serde_yaml::from_str("[{ car: 1, action: stall }]").unwrap();
+        ScriptedEvents::new(events, &road);
+    }
+}
@@ -0,0 +1,101 @@
+use rand::prelude::StdRng;
+
+use crate::{
+    arg_parameters::Parameters,
+    occlusion::occluded_cars,
+    rng_streams::{self, gaussian, Stream},
+    road::Road,
+};
+
+// What the ego's sensor sees of the road: the other cars within its range (and not hidden
+// behind others, with occlusion), with their positions and velocities off by gaussian noise.
+// The belief update only gets to use this.
+pub struct Observation {
+    pub road: Road,
+    // by car_i, whether the car was seen at all (the ego always is)
+    pub visible: Vec<bool>,
+}
+
+pub fn sensor_enabled(params: &Parameters) -> bool {
+    let sensor = &params.sensor;
+    sensor.position_std > 0.0
+        || sensor.vel_std > 0.0
+        || sensor.range > 0.0
+        || params.occlusion.enabled
+}
+
+pub fn observe(road: &Road, rng: &mut StdRng) -> Observation {
+    let sensor = &road.params.sensor;
+    let mut observed = road.clone();
+    let ego = &road.cars[0];
+    let (ego_x, ego_y) = (ego.x(), ego.y());
+
+    let mut visible = if road.params.occlusion.enabled {
+        occluded_cars(road).iter().map(|hidden| !hidden).collect()
+    } else {
+        vec![true; road.cars.len()]
+    };
+    for (car_i, car) in observed.cars.iter_mut().enumerate().skip(1) {
+        if !visible[car_i]
+            || sensor.range > 0.0 && (car.x() - ego_x).hypot(car.y() - ego_y) > sensor.range
+        {
+            visible[car_i] = false;
+            continue;
+        }
+        let mut car_rng;
+        let rng = if road.params.rng_streams {
+            let keys = [road.timesteps as u64, car_i as u64];
+            car_rng = rng_streams::fork(rng, Stream::Sensor, &keys);
+            &mut car_rng
+        } else {
+            &mut *rng
+        };
+        car.set_x(car.x() + gaussian(rng, sensor.position_std));
+        car.set_y(car.y() + gaussian(rng, sensor.position_std));
+        car.vel = (car.vel + gaussian(rng, sensor.vel_std)).max(0.0);
+    }
+    observed.update_cars_spatial();
+
+    Observation {
+        road: observed,
+        visible,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::car::Car;
+
+    #[test]
+    fn test_observe() {
+        let mut params = Parameters::new().unwrap();
+        params.sensor.range = 50.0;
+        params.sensor.position_std = 0.5;
+        let mut road = Road::new(Arc::new(params));
+        let mut near = Car::new(&road.params, 1, 1);
+        near.set_x(30.0);
+        road.cars.push(near);
+        let mut far = Car::new(&road.params, 2, 1);
+        far.set_x(-80.0);
+        road.cars.push(far);
+        road.update_cars_spatial();
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut total_dx = 0.0;
+        let n = 1000;
+        for _ in 0..n {
+            let obs = observe(&road, &mut rng);
+            road.timesteps += 1;
+            assert_eq!(obs.visible, vec![true, true, false]);
+            assert_eq!(obs.road.cars[0].x(), road.cars[0].x());
+            total_dx += (obs.road.cars[1].x() - 30.0).powi(2);
+        }
+        let std = (total_dx / n as f64).sqrt();
+        assert!((std - 0.5).abs() < 0.05, "{}", std);
+    }
+}
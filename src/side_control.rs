@@ -1,18 +1,43 @@
 use parry2d_f64::na::Point2;
 use rvx::Rvx;
+use serde::{Deserialize, Serialize};
 
+use crate::arg_parameters::Parameters;
 use crate::Road;
 
 use crate::open_loop_policy::OpenLoopSideControl;
 use crate::pure_pursuit::PurePursuitPolicy;
+use crate::stanley::StanleyControl;
 
 #[enum_dispatch]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum SideControl {
     PurePursuitPolicy,
+    StanleyControl,
     OpenLoopSideControl,
 }
 
+// by the names used for ego_side_control and obstacle_side_control
+pub fn make_side_control(params: &Parameters, name: &str) -> SideControl {
+    match name {
+        "pure_pursuit" => {
+            SideControl::PurePursuitPolicy(PurePursuitPolicy::new(params.pure_pursuit.ahead_time))
+        }
+        "stanley" => SideControl::StanleyControl(StanleyControl::new()),
+        _ => panic!(
+            "Unknown side control '{}', expected pure_pursuit or stanley",
+            name
+        ),
+    }
+}
+
+// what a car holds while its own control is out choosing its steer
+impl Default for SideControl {
+    fn default() -> Self {
+        Self::OpenLoopSideControl(OpenLoopSideControl)
+    }
+}
+
 #[enum_dispatch(SideControl)]
 pub trait SideControlTrait {
     fn choose_steer(&mut self, road: &Road, car_i: usize, trajectory: &[Point2<f64>]) -> f64;
@@ -1,16 +1,80 @@
 use parry2d_f64::na::Point2;
+use serde::{Deserialize, Serialize};
 
+use crate::contingency_policy::ContingencyPolicy;
 use crate::delayed_policy::DelayedPolicy;
+use crate::gap_alignment_policy::GapAlignmentPolicy;
 use crate::lane_change_policy::LaneChangePolicy;
+use crate::mobil_policy::MobilPolicy;
+use crate::nudge_policy::NudgePolicy;
 use crate::open_loop_policy::OpenLoopPolicy;
 use crate::Road;
 
+pub const MAX_TRAJECTORY_POINTS: usize = 3;
+
+// The points a car's side control steers it through, in order, in a fixed-size array
+// so that choosing one on every step of every forward sim doesn't allocate
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Trajectory {
+    points: [Point2<f64>; MAX_TRAJECTORY_POINTS],
+    len: usize,
+}
+
+impl Trajectory {
+    pub fn set(&mut self, points: &[Point2<f64>]) {
+        self.points[..points.len()].copy_from_slice(points);
+        self.len = points.len();
+    }
+}
+
+impl std::ops::Deref for Trajectory {
+    type Target = [Point2<f64>];
+
+    fn deref(&self) -> &Self::Target {
+        &self.points[..self.len]
+    }
+}
+
 #[enum_dispatch]
-#[derive(Debug, Clone, PartialEq, PartialOrd)]
+#[derive(Debug, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub enum SidePolicy {
     LaneChangePolicy,
     DelayedPolicy,
     OpenLoopPolicy,
+    MobilPolicy,
+    ContingencyPolicy,
+    NudgePolicy,
+    GapAlignmentPolicy,
+}
+
+// Cloning into a policy of the same kind reuses the composite policies' boxes
+impl Clone for SidePolicy {
+    fn clone(&self) -> Self {
+        match self {
+            Self::LaneChangePolicy(p) => Self::LaneChangePolicy(p.clone()),
+            Self::DelayedPolicy(p) => Self::DelayedPolicy(p.clone()),
+            Self::OpenLoopPolicy(p) => Self::OpenLoopPolicy(p.clone()),
+            Self::MobilPolicy(p) => Self::MobilPolicy(p.clone()),
+            Self::ContingencyPolicy(p) => Self::ContingencyPolicy(p.clone()),
+            Self::NudgePolicy(p) => Self::NudgePolicy(p.clone()),
+            Self::GapAlignmentPolicy(p) => Self::GapAlignmentPolicy(p.clone()),
+        }
+    }
+
+    fn clone_from(&mut self, source: &Self) {
+        match (self, source) {
+            (Self::DelayedPolicy(p), Self::DelayedPolicy(s)) => p.clone_from(s),
+            (Self::ContingencyPolicy(p), Self::ContingencyPolicy(s)) => p.clone_from(s),
+            (this, source) => *this = source.clone(),
+        }
+    }
+}
+
+// what a car holds while its own policy is out choosing its next step
+impl Default for SidePolicy {
+    fn default() -> Self {
+        Self::OpenLoopPolicy(OpenLoopPolicy)
+    }
 }
 
 #[enum_dispatch(SidePolicy)]
@@ -29,7 +93,12 @@ pub trait SidePolicyTrait {
         road.cars[car_i].preferred_vel
     }
 
-    fn choose_trajectory(&mut self, road: &Road, car_i: usize, traj: &mut Vec<Point2<f64>>);
+    fn choose_trajectory(&mut self, road: &Road, car_i: usize, traj: &mut Trajectory);
     fn policy_id(&self) -> u32;
     fn operating_policy(&self) -> SidePolicy;
+
+    // whether it only ever acts as its operating policy now, with no switch still to come
+    fn is_settled(&self) -> bool {
+        true
+    }
 }
@@ -0,0 +1,67 @@
+use std::fmt;
+
+// Why a run couldn't be set up or carried on, for the caller to report instead of the
+// whole process (or sweep) going down with a panic
+#[derive(Debug, Clone, PartialEq)]
+pub enum SimError {
+    // a scenario, map or other file the run needs
+    Load { path: String, message: String },
+    // SUMO, for its traffic
+    Connect { address: String, message: String },
+    // parameters that can't go together
+    InvalidParameters(String),
+    // no random place for another car that isn't in a car already,
+    // with too many cars or bad collision detection
+    CarPlacement { car_i: usize, tries: u32 },
+    // a car of a scenario file that overlaps another
+    CarOverlap { car_i: usize, x: f64 },
+    // the road's belief is needed before init_belief
+    NoBelief,
+    // update_belief with the belief shared with other roads, which it needs exclusive access to
+    SharedBelief,
+    UnknownMethod(String),
+    // a run to minimize where the ego doesn't crash
+    NoCrash { max_steps: u32 },
+    // no scenario file from the run's cars crashes like it
+    NotReproduced { crash_step: u32 },
+}
+
+impl fmt::Display for SimError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SimError::Load { path, message } => write!(f, "Could not load {}: {}", path, message),
+            SimError::Connect { address, message } => {
+                write!(f, "Could not connect to SUMO at {}: {}", address, message)
+            }
+            SimError::InvalidParameters(message) => write!(f, "Invalid parameters: {}", message),
+            SimError::CarPlacement { car_i, tries } => write!(
+                f,
+                "Could not place car {} without it colliding after {} tries... too many cars or bad collision detection?",
+                car_i, tries
+            ),
+            SimError::CarOverlap { car_i, x } => write!(
+                f,
+                "Car {} of the scenario file (at x = {:.1}) overlaps another car",
+                car_i, x
+            ),
+            SimError::NoBelief => write!(f, "The road has no belief yet (see init_belief)"),
+            SimError::SharedBelief => write!(
+                f,
+                "update_belief should only be called when it has exclusive access to the top-level road"
+            ),
+            SimError::UnknownMethod(method) => write!(f, "invalid method '{}'", method),
+            SimError::NoCrash { max_steps } => write!(
+                f,
+                "The ego doesn't crash within {} steps, so there's nothing to minimize",
+                max_steps
+            ),
+            SimError::NotReproduced { crash_step } => write!(
+                f,
+                "No scenario file from the run's cars reproduces its crash at step {}",
+                crash_step
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SimError {}
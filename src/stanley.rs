@@ -0,0 +1,96 @@
+use parry2d_f64::na::Point2;
+use serde::{Deserialize, Serialize};
+
+use crate::{side_control::SideControlTrait, Road};
+
+// The Stanley controller (Hoffmann et al. 2007): steer to match the trajectory's heading,
+// plus atan(gain * cross-track error / (soft_vel + vel)) to close the distance to it,
+// measured from the front of the car, with the gains from params.stanley.
+// https://thomasfermi.github.io/Algorithms-for-Automated-Driving/Control/Stanley.html
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StanleyControl;
+
+impl StanleyControl {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+// the heading of the trajectory at its closest point to pt,
+// and pt's distance from the line through it, positive when the trajectory is to pt's left
+fn closest_on_trajectory(trajectory: &[Point2<f64>], pt: Point2<f64>) -> (f64, f64) {
+    let mut best: Option<(f64, f64, f64)> = None;
+    for seg in trajectory.windows(2) {
+        let (a, b) = (seg[0], seg[1]);
+        let ab = b - a;
+        let len2 = ab.norm_squared();
+        if len2 == 0.0 {
+            continue;
+        }
+        let along = ((pt - a).dot(&ab) / len2).max(0.0).min(1.0);
+        let closest = a + ab * along;
+        let dist = (closest - pt).norm();
+        if best.map_or(true, |(d, _, _)| dist < d) {
+            let cross_track = (ab.x * (a.y - pt.y) - ab.y * (a.x - pt.x)) / len2.sqrt();
+            best = Some((dist, ab.y.atan2(ab.x), cross_track));
+        }
+    }
+    let (_, heading, cross_track) = best.expect("Stanley control needs a trajectory");
+    (heading, cross_track)
+}
+
+impl SideControlTrait for StanleyControl {
+    fn choose_steer(&mut self, road: &Road, car_i: usize, trajectory: &[Point2<f64>]) -> f64 {
+        let sparams = &road.params.stanley;
+        let car = &road.cars[car_i];
+        // the policies' trajectories start from where the car is now, which would leave
+        // no cross-track error, so it follows the rest of the trajectory after that
+        let path = if trajectory.len() > 2 {
+            &trajectory[1..]
+        } else {
+            trajectory
+        };
+        let (heading, cross_track) = closest_on_trajectory(path, Point2::new(car.x(), car.y()));
+
+        // wrapped to within pi
+        let dtheta = heading - car.theta();
+        let heading_error = dtheta.sin().atan2(dtheta.cos());
+        heading_error + (sparams.gain * cross_track).atan2(sparams.soft_vel + car.vel)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{arg_parameters::Parameters, road::LANE_WIDTH};
+
+    #[test]
+    fn test_stanley_steers_onto_trajectory() {
+        let mut params = Parameters::new().unwrap();
+        let mut road = Road::new(Arc::new(params.clone()));
+        road.cars[0].vel = 10.0;
+        let (x, y) = (road.cars[0].x(), road.cars[0].y());
+        let mut control = StanleyControl::new();
+        // like the lane change policy's: from the car, over to dy, and then straight on
+        let trajectory = |dy: f64| {
+            [
+                Point2::new(x, y),
+                Point2::new(x + 20.0, y + dy),
+                Point2::new(x + 120.0, y + dy),
+            ]
+        };
+
+        // already in the lane
+        assert_eq!(control.choose_steer(&road, 0, &trajectory(0.0)), 0.0);
+
+        // toward a lane to the left or to the right, and harder with more gain
+        let left_steer = control.choose_steer(&road, 0, &trajectory(LANE_WIDTH));
+        assert!(left_steer > 0.0);
+        assert!(control.choose_steer(&road, 0, &trajectory(-LANE_WIDTH)) < 0.0);
+        params.stanley.gain *= 2.0;
+        road.params = Arc::new(params);
+        assert!(control.choose_steer(&road, 0, &trajectory(LANE_WIDTH)) > left_steer);
+    }
+}
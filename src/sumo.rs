@@ -0,0 +1,492 @@
+use std::{
+    convert::TryInto,
+    f64::consts::PI,
+    io::{Read, Write},
+    net::TcpStream,
+};
+
+use crate::{arg_parameters::Parameters, car::Car, road::Road, sim_error::SimError};
+
+// TraCI command, variable and type ids (see the SUMO docs on the TraCI protocol)
+const CMD_SIMSTEP: u8 = 0x02;
+const CMD_CLOSE: u8 = 0x7f;
+const CMD_GET_VEHICLE_VARIABLE: u8 = 0xa4;
+const RESPONSE_GET_VEHICLE_VARIABLE: u8 = 0xb4;
+const CMD_SET_VEHICLE_VARIABLE: u8 = 0xc4;
+
+const ID_LIST: u8 = 0x00;
+const VAR_SPEED: u8 = 0x40;
+const VAR_POSITION: u8 = 0x42;
+const VAR_ANGLE: u8 = 0x43;
+const VAR_LENGTH: u8 = 0x44;
+const VAR_WIDTH: u8 = 0x4d;
+const ADD_FULL: u8 = 0x85;
+const MOVE_TO_XY: u8 = 0xb4;
+
+const TYPE_POSITION_2D: u8 = 0x01;
+const TYPE_BYTE: u8 = 0x08;
+const TYPE_INTEGER: u8 = 0x09;
+const TYPE_DOUBLE: u8 = 0x0b;
+const TYPE_STRING: u8 = 0x0c;
+const TYPE_STRING_LIST: u8 = 0x0e;
+const TYPE_COMPOUND: u8 = 0x0f;
+
+const RTYPE_OK: u8 = 0x00;
+
+// A TraCI value, as sent in a command
+enum Value<'a> {
+    Byte(u8),
+    Int(i32),
+    Double(f64),
+    Str(&'a str),
+    Compound(Vec<Value<'a>>),
+}
+
+fn put_string(buf: &mut Vec<u8>, s: &str) {
+    buf.extend_from_slice(&(s.len() as i32).to_be_bytes());
+    buf.extend_from_slice(s.as_bytes());
+}
+
+fn put_value(buf: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::Byte(v) => buf.extend_from_slice(&[TYPE_BYTE, *v]),
+        Value::Int(v) => {
+            buf.push(TYPE_INTEGER);
+            buf.extend_from_slice(&v.to_be_bytes());
+        }
+        Value::Double(v) => {
+            buf.push(TYPE_DOUBLE);
+            buf.extend_from_slice(&v.to_be_bytes());
+        }
+        Value::Str(v) => {
+            buf.push(TYPE_STRING);
+            put_string(buf, v);
+        }
+        Value::Compound(items) => {
+            buf.push(TYPE_COMPOUND);
+            buf.extend_from_slice(&(items.len() as i32).to_be_bytes());
+            for item in items.iter() {
+                put_value(buf, item);
+            }
+        }
+    }
+}
+
+// A whole message of one command: its length, then the command's length, id and content
+fn message(command_id: u8, content: &[u8]) -> Vec<u8> {
+    let mut command = Vec::new();
+    if content.len() + 2 <= 255 {
+        command.push((content.len() + 2) as u8);
+    } else {
+        command.push(0);
+        command.extend_from_slice(&((content.len() + 6) as i32).to_be_bytes());
+    }
+    command.push(command_id);
+    command.extend_from_slice(content);
+
+    let mut msg = ((command.len() + 4) as i32).to_be_bytes().to_vec();
+    msg.extend(command);
+    msg
+}
+
+// Reading through a response message
+struct Reader {
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl Reader {
+    fn take(&mut self, n: usize) -> &[u8] {
+        assert!(self.pos + n <= self.buf.len(), "TraCI response ended early");
+        self.pos += n;
+        &self.buf[self.pos - n..self.pos]
+    }
+
+    fn u8(&mut self) -> u8 {
+        self.take(1)[0]
+    }
+
+    fn i32(&mut self) -> i32 {
+        i32::from_be_bytes(self.take(4).try_into().unwrap())
+    }
+
+    fn f64(&mut self) -> f64 {
+        f64::from_be_bytes(self.take(8).try_into().unwrap())
+    }
+
+    fn string(&mut self) -> String {
+        let len = self.i32() as usize;
+        String::from_utf8_lossy(self.take(len)).into_owned()
+    }
+
+    // the length and id at the start of a command
+    fn command_start(&mut self) -> u8 {
+        if self.u8() == 0 {
+            self.i32();
+        }
+        self.u8()
+    }
+
+    fn typed(&mut self, type_id: u8) {
+        let got = self.u8();
+        assert_eq!(
+            got, type_id,
+            "TraCI value of type {:#x}, not {:#x}",
+            got, type_id
+        );
+    }
+}
+
+// A client for a running SUMO, started with --remote-port
+pub struct TraciClient {
+    stream: TcpStream,
+}
+
+impl TraciClient {
+    pub fn connect(host: &str, port: u16) -> Result<Self, SimError> {
+        let stream = TcpStream::connect((host, port)).map_err(|e| SimError::Connect {
+            address: format!("{}:{}", host, port),
+            message: e.to_string(),
+        })?;
+        stream.set_nodelay(true).unwrap();
+        Ok(Self { stream })
+    }
+
+    // sends one command, and gives back the response after its status
+    fn command(&mut self, command_id: u8, content: &[u8]) -> Reader {
+        self.stream
+            .write_all(&message(command_id, content))
+            .expect("Could not send to SUMO");
+        let mut len = [0; 4];
+        self.stream
+            .read_exact(&mut len)
+            .expect("Could not read from SUMO");
+        let mut buf = vec![0; i32::from_be_bytes(len) as usize - 4];
+        self.stream
+            .read_exact(&mut buf)
+            .expect("Could not read from SUMO");
+
+        let mut reader = Reader { buf, pos: 0 };
+        let status_id = reader.command_start();
+        let result = reader.u8();
+        let description = reader.string();
+        assert_eq!(status_id, command_id, "TraCI status for the wrong command");
+        assert!(
+            result == RTYPE_OK,
+            "SUMO failed command {:#x}: {}",
+            command_id,
+            description
+        );
+        reader
+    }
+
+    // SUMO's simulation up until time t, in s
+    pub fn simulation_step(&mut self, t: f64) {
+        // the subscription results that follow are left unread, as there aren't any
+        self.command(CMD_SIMSTEP, &t.to_be_bytes());
+    }
+
+    fn get_vehicle(&mut self, var: u8, id: &str, type_id: u8) -> Reader {
+        let mut content = vec![var];
+        put_string(&mut content, id);
+        let mut reader = self.command(CMD_GET_VEHICLE_VARIABLE, &content);
+        assert_eq!(reader.command_start(), RESPONSE_GET_VEHICLE_VARIABLE);
+        assert_eq!(reader.u8(), var);
+        reader.string();
+        reader.typed(type_id);
+        reader
+    }
+
+    fn get_vehicle_f64(&mut self, var: u8, id: &str) -> f64 {
+        self.get_vehicle(var, id, TYPE_DOUBLE).f64()
+    }
+
+    pub fn vehicle_ids(&mut self) -> Vec<String> {
+        let mut reader = self.get_vehicle(ID_LIST, "", TYPE_STRING_LIST);
+        let n = reader.i32();
+        (0..n).map(|_| reader.string()).collect()
+    }
+
+    pub fn vehicle(&mut self, id: &str) -> SumoVehicle {
+        let mut position = self.get_vehicle(VAR_POSITION, id, TYPE_POSITION_2D);
+        let (x, y) = (position.f64(), position.f64());
+        SumoVehicle {
+            x,
+            y,
+            angle: self.get_vehicle_f64(VAR_ANGLE, id),
+            speed: self.get_vehicle_f64(VAR_SPEED, id),
+            length: self.get_vehicle_f64(VAR_LENGTH, id),
+            width: self.get_vehicle_f64(VAR_WIDTH, id),
+        }
+    }
+
+    fn set_vehicle(&mut self, var: u8, id: &str, value: &Value) {
+        let mut content = vec![var];
+        put_string(&mut content, id);
+        put_value(&mut content, value);
+        self.command(CMD_SET_VEHICLE_VARIABLE, &content);
+    }
+
+    // a new vehicle on route, departing right away
+    pub fn add_vehicle(&mut self, id: &str, route: &str) {
+        let strings = [
+            route,
+            "DEFAULT_VEHTYPE",
+            "now",
+            "first",
+            "base",
+            "0",
+            "current",
+            "max",
+            "current",
+            "",
+            "",
+            "",
+        ];
+        let mut items = strings.iter().map(|s| Value::Str(s)).collect::<Vec<_>>();
+        items.extend([Value::Int(0), Value::Int(0)]);
+        self.set_vehicle(ADD_FULL, id, &Value::Compound(items));
+    }
+
+    // puts a vehicle wherever it is in our simulation, off its route if need be
+    pub fn move_vehicle(&mut self, id: &str, vehicle: &SumoVehicle) {
+        let items = vec![
+            Value::Str(""),
+            Value::Int(-1),
+            Value::Double(vehicle.x),
+            Value::Double(vehicle.y),
+            Value::Double(vehicle.angle),
+            // keepRoute 2: anywhere on the network
+            Value::Byte(2),
+        ];
+        self.set_vehicle(MOVE_TO_XY, id, &Value::Compound(items));
+        self.set_vehicle(VAR_SPEED, id, &Value::Double(vehicle.speed));
+    }
+}
+
+impl Drop for TraciClient {
+    fn drop(&mut self) {
+        // SUMO may already have gone
+        let _ = self.stream.write_all(&message(CMD_CLOSE, &[]));
+    }
+}
+
+// A vehicle as SUMO has it: the middle of its front bumper in network coordinates,
+// its angle in degrees clockwise from north, and its speed in m/s
+#[derive(Clone, Debug, PartialEq)]
+pub struct SumoVehicle {
+    pub x: f64,
+    pub y: f64,
+    pub angle: f64,
+    pub speed: f64,
+    pub length: f64,
+    pub width: f64,
+}
+
+// world heading, counterclockwise from the x axis in radians, from SUMO's angle and back
+fn heading_from_angle(angle: f64) -> f64 {
+    let heading = (90.0 - angle).to_radians();
+    (heading + PI).rem_euclid(2.0 * PI) - PI
+}
+
+fn angle_from_heading(heading: f64) -> f64 {
+    (90.0 - heading.to_degrees()).rem_euclid(360.0)
+}
+
+// Background traffic driven by SUMO: every sync_dt, our ego goes to SUMO, SUMO steps,
+// and its vehicles come back as the obstacle cars, arriving and leaving as they
+// come within range of the ego along the road and go out of it again. Between syncs
+// the obstacle cars move by their own policies, as they do in the planners' rollouts.
+pub struct SumoTraffic {
+    client: TraciClient,
+    // the SUMO id for each of the road's cars
+    ids: Vec<String>,
+}
+
+impl SumoTraffic {
+    pub fn connect(params: &Parameters) -> Result<Self, SimError> {
+        let sumo = &params.sumo;
+        let mut client = TraciClient::connect(&sumo.host, sumo.port)?;
+        if !client.vehicle_ids().contains(&sumo.ego_id) {
+            client.add_vehicle(&sumo.ego_id, &sumo.ego_route);
+        }
+        Ok(Self {
+            client,
+            ids: vec![sumo.ego_id.clone()],
+        })
+    }
+
+    pub fn sync(&mut self, road: &mut Road) {
+        let sumo = road.params.sumo.clone();
+        assert_eq!(
+            self.ids.len(),
+            road.cars.len(),
+            "cars came or went outside of SUMO"
+        );
+
+        let ego = road.world_car(&road.cars[0]);
+        let ego_vehicle = SumoVehicle {
+            x: ego.x() + sumo.origin_x,
+            y: ego.y() + sumo.origin_y,
+            angle: angle_from_heading(ego.theta()),
+            speed: ego.vel,
+            length: ego.length,
+            width: ego.width,
+        };
+        self.client.move_vehicle(&sumo.ego_id, &ego_vehicle);
+        self.client.simulation_step(road.t);
+
+        let ego_s = road.cars[0].x();
+        let mut vehicles = Vec::new();
+        for id in self.client.vehicle_ids() {
+            if id == sumo.ego_id {
+                continue;
+            }
+            let vehicle = self.client.vehicle(&id);
+            let (s, d) = road.geometry.to_frenet(
+                vehicle.x - sumo.origin_x,
+                vehicle.y - sumo.origin_y,
+                ego_s,
+            );
+            if (s - ego_s).abs() <= sumo.range {
+                vehicles.push((id, s, d, vehicle));
+            }
+        }
+
+        // the cars that have gone, from the back so the swaps don't skip any
+        for car_i in (1..road.cars.len()).rev() {
+            if !vehicles.iter().any(|(id, ..)| *id == self.ids[car_i]) {
+                road.swap_remove_car(car_i);
+                self.ids.swap_remove(car_i);
+            }
+        }
+
+        for (id, s, d, vehicle) in vehicles {
+            let theta = heading_from_angle(vehicle.angle) - road.geometry.heading(s, 0.0);
+            let theta = (theta + PI).rem_euclid(2.0 * PI) - PI;
+            match self.ids.iter().position(|car_id| *car_id == id) {
+                Some(car_i) => {
+                    let car = &mut road.cars[car_i];
+                    car.set_x(s);
+                    car.set_y(d);
+                    car.set_theta(theta);
+                    car.vel = vehicle.speed;
+                }
+                None => {
+                    let lane_i = Road::get_lane_i(d);
+                    let mut car = Car::new(&road.params, road.cars.len(), lane_i);
+                    car.set_size(vehicle.length, vehicle.width);
+                    car.set_x(s);
+                    car.set_y(d);
+                    car.set_theta(theta);
+                    car.vel = vehicle.speed;
+                    car.preferred_vel = vehicle.speed;
+                    car.target_vel = vehicle.speed;
+                    // off the lanes, or where one of ours is, it waits until there's room
+                    if road.push_car(car) {
+                        self.ids.push(id);
+                    }
+                }
+            }
+        }
+        road.update_cars_spatial();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, sync::Arc, thread};
+
+    use super::*;
+
+    // a SUMO with one other vehicle after the first step, a 12 m truck 20 m ahead of the ego
+    // in its lane, going 10 m/s
+    fn fake_sumo(listener: TcpListener) {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut vehicle_ids = vec![];
+        loop {
+            let mut len = [0; 4];
+            if stream.read_exact(&mut len).is_err() {
+                return;
+            }
+            let mut buf = vec![0; i32::from_be_bytes(len) as usize - 4];
+            stream.read_exact(&mut buf).unwrap();
+            let mut reader = Reader { buf, pos: 0 };
+            let command_id = reader.command_start();
+
+            let mut response = vec![7, command_id, RTYPE_OK, 0, 0, 0, 0];
+            match command_id {
+                CMD_CLOSE => return,
+                CMD_SIMSTEP => vehicle_ids = vec!["ego", "car"],
+                CMD_SET_VEHICLE_VARIABLE => {
+                    if reader.u8() == ADD_FULL {
+                        assert_eq!(reader.string(), "ego");
+                        vehicle_ids = vec!["ego"];
+                    }
+                }
+                CMD_GET_VEHICLE_VARIABLE => {
+                    let var = reader.u8();
+                    let id = reader.string();
+                    let mut content = vec![var];
+                    put_string(&mut content, &id);
+                    match var {
+                        ID_LIST => {
+                            content.push(TYPE_STRING_LIST);
+                            content.extend_from_slice(&(vehicle_ids.len() as i32).to_be_bytes());
+                            for id in vehicle_ids.iter() {
+                                put_string(&mut content, id);
+                            }
+                        }
+                        VAR_POSITION => {
+                            content.push(TYPE_POSITION_2D);
+                            content.extend_from_slice(&1020.0_f64.to_be_bytes());
+                            content.extend_from_slice(&(500.0 + Road::get_lane_y(0)).to_be_bytes());
+                        }
+                        _ => {
+                            let value = match var {
+                                VAR_ANGLE => 90.0,
+                                VAR_SPEED => 10.0,
+                                VAR_LENGTH => 12.0,
+                                _ => 2.5,
+                            };
+                            put_value(&mut content, &Value::Double(value));
+                        }
+                    }
+                    let command = message(RESPONSE_GET_VEHICLE_VARIABLE, &content);
+                    response.extend_from_slice(&command[4..]);
+                }
+                _ => panic!("unexpected command {:#x}", command_id),
+            }
+            let mut msg = ((response.len() + 4) as i32).to_be_bytes().to_vec();
+            msg.extend(response);
+            stream.write_all(&msg).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_sumo_sync() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || fake_sumo(listener));
+
+        let mut params = Parameters::new().unwrap();
+        params.sumo.port = port;
+        params.sumo.origin_x = 1000.0;
+        params.sumo.origin_y = 500.0;
+        let params = Arc::new(params);
+        let mut road = Road::new(params.clone());
+        road.init_belief();
+
+        let mut traffic = SumoTraffic::connect(&params).unwrap();
+        traffic.sync(&mut road);
+        assert_eq!(road.cars.len(), 2);
+        let car = &road.cars[1];
+        assert_eq!((car.x(), car.vel, car.current_lane()), (20.0, 10.0, 0));
+        assert!(car.theta().abs() < 1e-9);
+        assert_eq!(car.length, 12.0);
+        assert_eq!(traffic.ids, ["ego", "car"]);
+
+        drop(traffic);
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,195 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    fmt::Write as _,
+    hash::{Hash, Hasher},
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    arg_parameters::Parameters,
+    render::{self, Painter, Rgb, View, BACKGROUND, HEIGHT, SCALE, WIDTH},
+    road::{Road, TraceLine},
+};
+
+// A vector picture of the road, in the same pixel space as the video frames
+pub struct SvgFrame {
+    view: View,
+    body: String,
+}
+
+fn rgb(color: Rgb) -> String {
+    format!("rgb({},{},{})", color[0], color[1], color[2])
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+impl SvgFrame {
+    pub fn new(view: View) -> Self {
+        Self {
+            view,
+            body: String::new(),
+        }
+    }
+
+    fn pixel_points(&self, points: &[(f64, f64)]) -> Option<String> {
+        let points = points
+            .iter()
+            .map(|&(x, y)| self.view.to_pixel(x, y))
+            .collect::<Vec<_>>();
+        if !View::shows(&points) {
+            return None;
+        }
+        Some(
+            points
+                .iter()
+                .map(|(u, v)| format!("{:.1},{:.1}", u, v))
+                .collect::<Vec<_>>()
+                .join(" "),
+        )
+    }
+
+    pub fn finish(&self) -> String {
+        format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" \
+             viewBox=\"0 0 {w} {h}\">\n<rect width=\"{w}\" height=\"{h}\" fill=\"{bg}\"/>\n{body}</svg>\n",
+            w = WIDTH,
+            h = HEIGHT,
+            bg = rgb(BACKGROUND),
+            body = self.body
+        )
+    }
+}
+
+impl Painter for SvgFrame {
+    fn fill_polygon(&mut self, corners: &[(f64, f64)], color: Rgb, alpha: f64) {
+        if let Some(points) = self.pixel_points(corners) {
+            writeln!(
+                self.body,
+                "<polygon points=\"{}\" fill=\"{}\" fill-opacity=\"{}\"/>",
+                points,
+                rgb(color),
+                alpha
+            )
+            .unwrap();
+        }
+    }
+
+    fn polyline(&mut self, points: &[(f64, f64)], width: f64, color: Rgb, alpha: f64) {
+        if let Some(points) = self.pixel_points(points) {
+            writeln!(
+                self.body,
+                "<polyline points=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"{:.2}\" \
+                 stroke-opacity=\"{}\" stroke-linejoin=\"round\" stroke-linecap=\"round\"/>",
+                points,
+                rgb(color),
+                width * SCALE,
+                alpha
+            )
+            .unwrap();
+        }
+    }
+
+    fn text(&mut self, at: (f64, f64), text: &str, size: f64, color: Rgb) {
+        let (u, v) = self.view.to_pixel(at.0, at.1);
+        if !View::shows(&[(u, v)]) {
+            return;
+        }
+        writeln!(
+            self.body,
+            "<text x=\"{:.1}\" y=\"{:.1}\" font-family=\"Arial\" font-size=\"{:.1}\" \
+             fill=\"{}\">{}</text>",
+            u,
+            v,
+            size * SCALE,
+            rgb(color),
+            escape(text)
+        )
+        .unwrap();
+    }
+}
+
+pub fn render_svg(road: &Road, traces: &[TraceLine]) -> String {
+    let mut frame = SvgFrame::new(View::around_ego(road));
+    render::draw_scene(road, &mut frame, traces);
+    frame.finish()
+}
+
+// like replays, by the method, seed and a hash of the scenario name, and then the timestep
+pub fn svg_path(params: &Parameters, timesteps: usize) -> PathBuf {
+    let mut hasher = DefaultHasher::new();
+    params.scenario_name.hash(&mut hasher);
+    Path::new(&params.svg.dir).join(format!(
+        "{}_seed{}_{:016x}_step{}.svg",
+        params.method,
+        params.rng_seed,
+        hasher.finish(),
+        timesteps
+    ))
+}
+
+// Writes the snapshots svg parameters ask for, as a run goes
+#[derive(Default)]
+pub struct SvgExporter {
+    ego_crashed: bool,
+}
+
+impl SvgExporter {
+    // after each step, with the ego's traces from the latest planning
+    pub fn after_step(&mut self, road: &Road, traces: &[TraceLine]) -> io::Result<Option<PathBuf>> {
+        let svg = &road.params.svg;
+        let step = road.timesteps;
+        let crashed_now = road.cars[0].crashed && !self.ego_crashed;
+        self.ego_crashed = road.cars[0].crashed;
+
+        let wanted = svg.steps.contains(&step)
+            || svg.every > 0 && step % svg.every == 0
+            || svg.on_crash && crashed_now;
+        if !wanted {
+            return Ok(None);
+        }
+        let path = svg_path(&road.params, step);
+        std::fs::create_dir_all(&svg.dir)?;
+        std::fs::write(&path, render_svg(road, traces))?;
+        Ok(Some(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::car::Car;
+
+    #[test]
+    fn test_render_svg() {
+        let mut params = Parameters::new().unwrap();
+        params.belief_overlay = true;
+        let mut road = Road::new(Arc::new(params.clone()));
+        let mut ahead = Car::new(&params, 1, 0);
+        ahead.set_x(road.cars[0].x() + 20.0);
+        ahead.vel = 10.0;
+        road.cars.push(ahead);
+        road.init_belief();
+
+        let x = road.cars[0].x();
+        let trace = TraceLine {
+            points: vec![x, 0.0, x + 10.0, 0.0, x + 20.0, 1.0],
+            depth_level: 0,
+            crashed: false,
+            not_safe: false,
+        };
+        let svg = render_svg(&road, &[trace]);
+        assert!(svg.starts_with("<svg") && svg.ends_with("</svg>\n"));
+        // the ego in green and the car ahead in blue
+        assert!(svg.contains(&format!("fill=\"{}\"", rgb(render::GREEN))));
+        assert!(svg.contains(&format!("fill=\"{}\"", rgb(render::BLUE))));
+        assert_eq!(svg.matches("<polyline").count(), 1);
+        assert!(svg.contains("H: "));
+    }
+}
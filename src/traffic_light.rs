@@ -0,0 +1,204 @@
+use rvx::{Rvx, RvxColor};
+
+use crate::{
+    arg_parameters::Parameters,
+    car::BREAKING_ACCEL,
+    render::{self, Painter},
+    road::{Road, ROAD_LENGTH},
+};
+
+// how far past its stop line the intersection's cross street reaches
+pub const INTERSECTION_LENGTH: f64 = 12.0;
+// the hardest a car brakes to stop for a yellow light, rather than going through
+const YELLOW_STOP_DECEL: f64 = 0.5 * BREAKING_ACCEL;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SignalPhase {
+    Green,
+    Yellow,
+    Red,
+}
+
+// stop line station of signalized intersection k
+fn stop_line_x(params: &Parameters, k: i64) -> f64 {
+    params.signals.first + k as f64 * params.signals.spacing
+}
+
+// Each intersection cycles through green, yellow, and red,
+// phase_offset later than the intersection before it
+fn phase_of(params: &Parameters, k: i64, t: f64) -> SignalPhase {
+    let signals = &params.signals;
+    let cycle = signals.green_time + signals.yellow_time + signals.red_time;
+    let cycle_t = (t - k as f64 * signals.phase_offset).rem_euclid(cycle);
+    if cycle_t < signals.green_time {
+        SignalPhase::Green
+    } else if cycle_t < signals.green_time + signals.yellow_time {
+        SignalPhase::Yellow
+    } else {
+        SignalPhase::Red
+    }
+}
+
+// the stop lines from low_x to high_x, with their phases at time t
+pub fn stop_lines_between(
+    params: &Parameters,
+    low_x: f64,
+    high_x: f64,
+    t: f64,
+) -> Vec<(f64, SignalPhase)> {
+    if params.signals.spacing <= 0.0 {
+        return Vec::new();
+    }
+    let first_k = ((low_x - params.signals.first) / params.signals.spacing).ceil() as i64;
+    (first_k..)
+        .map(|k| (stop_line_x(params, k), phase_of(params, k, t)))
+        .take_while(|&(x, _)| x <= high_x)
+        .collect()
+}
+
+// Distance from car_i's front to the next stop line, if it should stop there:
+// at a red light, or a yellow one that it can still stop for without braking hard
+pub fn stop_line_ahead(road: &Road, car_i: usize) -> Option<f64> {
+    let params = &road.params;
+    if params.signals.spacing <= 0.0 {
+        return None;
+    }
+    let car = &road.cars[car_i];
+    let k = ((car.x() - params.signals.first) / params.signals.spacing).floor() as i64 + 1;
+    let dist = stop_line_x(params, k) - car.x();
+    match phase_of(params, k, road.t) {
+        SignalPhase::Green => None,
+        SignalPhase::Yellow if car.vel.powi(2) / (2.0 * YELLOW_STOP_DECEL) > dist => None,
+        SignalPhase::Yellow | SignalPhase::Red => Some(dist),
+    }
+}
+
+// whether a car's front going from last_x to x crossed a stop line at a red light
+pub fn ran_red_light(params: &Parameters, last_x: f64, x: f64, t: f64) -> bool {
+    stop_lines_between(params, last_x, x, t)
+        .iter()
+        .any(|&(line_x, phase)| line_x > last_x && phase == SignalPhase::Red)
+}
+
+// the cross street and the stop lines, colored by their lights
+pub fn draw_intersections(road: &Road, r: &mut Rvx, low_y: f64, high_y: f64) {
+    let ego_x = road.cars[0].x();
+    let geometry = &road.geometry;
+    for (x, phase) in stop_lines_between(
+        &road.params,
+        ego_x - ROAD_LENGTH / 2.0,
+        ego_x + ROAD_LENGTH / 2.0,
+        road.t,
+    ) {
+        let heading = geometry.heading(x, 0.0);
+        let cross_s = x + INTERSECTION_LENGTH / 2.0;
+        let (cross_x, cross_y) = geometry.to_cartesian(cross_s, (low_y + high_y) / 2.0);
+        r.draw(
+            Rvx::square()
+                .scale_xy(&[INTERSECTION_LENGTH, 3.0 * (high_y - low_y)])
+                .rot(geometry.heading(cross_s, 0.0))
+                .translate(&[cross_x, cross_y])
+                .color(RvxColor::GRAY),
+        );
+
+        let color = match phase {
+            SignalPhase::Green => RvxColor::GREEN,
+            SignalPhase::Yellow => RvxColor::YELLOW,
+            SignalPhase::Red => RvxColor::RED,
+        };
+        let (line_x, line_y) = geometry.to_cartesian(x, (low_y + high_y) / 2.0);
+        r.draw(
+            Rvx::square()
+                .scale_xy(&[0.4, high_y - low_y])
+                .rot(heading)
+                .translate(&[line_x, line_y])
+                .color(color),
+        );
+    }
+}
+
+// the same for drawing outside of rvx
+pub fn paint_intersections(road: &Road, painter: &mut impl Painter, low_y: f64, high_y: f64) {
+    let ego_x = road.cars[0].x();
+    let geometry = &road.geometry;
+    for (x, phase) in stop_lines_between(
+        &road.params,
+        ego_x - ROAD_LENGTH / 2.0,
+        ego_x + ROAD_LENGTH / 2.0,
+        road.t,
+    ) {
+        let cross_s = x + INTERSECTION_LENGTH / 2.0;
+        painter.fill_rect(
+            geometry.to_cartesian(cross_s, (low_y + high_y) / 2.0),
+            INTERSECTION_LENGTH,
+            3.0 * (high_y - low_y),
+            geometry.heading(cross_s, 0.0),
+            render::ROAD_GRAY,
+            1.0,
+        );
+
+        let color = match phase {
+            SignalPhase::Green => render::GREEN,
+            SignalPhase::Yellow => render::YELLOW,
+            SignalPhase::Red => render::RED,
+        };
+        painter.fill_rect(
+            geometry.to_cartesian(x, (low_y + high_y) / 2.0),
+            0.4,
+            high_y - low_y,
+            geometry.heading(x, 0.0),
+            color,
+            1.0,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[test]
+    fn test_signal_phases() {
+        let mut params = Parameters::new().unwrap();
+        params.signals.spacing = 200.0;
+        params.signals.first = 100.0;
+        params.signals.green_time = 10.0;
+        params.signals.yellow_time = 3.0;
+        params.signals.red_time = 7.0;
+        params.signals.phase_offset = 5.0;
+
+        assert_eq!(phase_of(&params, 0, 9.0), SignalPhase::Green);
+        assert_eq!(phase_of(&params, 0, 11.0), SignalPhase::Yellow);
+        assert_eq!(phase_of(&params, 0, 15.0), SignalPhase::Red);
+        assert_eq!(phase_of(&params, 0, 21.0), SignalPhase::Green);
+        assert_eq!(phase_of(&params, 1, 15.0), SignalPhase::Yellow);
+        assert_eq!(phase_of(&params, -1, 6.0), SignalPhase::Yellow);
+        assert_eq!(
+            stop_lines_between(&params, -150.0, 350.0, 0.0)
+                .iter()
+                .map(|&(x, _)| x)
+                .collect::<Vec<_>>(),
+            vec![-100.0, 100.0, 300.0]
+        );
+
+        assert!(ran_red_light(&params, 99.0, 100.5, 15.0));
+        assert!(!ran_red_light(&params, 99.0, 100.5, 9.0));
+        assert!(!ran_red_light(&params, 100.0, 100.5, 15.0));
+
+        // stops for the red, and for the yellow only while it's far enough away
+        let mut road = Road::new(Arc::new(params));
+        road.cars[0].set_x(60.0);
+        road.cars[0].vel = 10.0;
+        road.t = 15.0;
+        assert_eq!(stop_line_ahead(&road, 0), Some(40.0));
+        road.t = 11.0;
+        assert_eq!(stop_line_ahead(&road, 0), Some(40.0));
+        road.cars[0].set_x(90.0);
+        assert_eq!(stop_line_ahead(&road, 0), None);
+        road.t = 0.0;
+        road.cars[0].set_x(60.0);
+        assert_eq!(stop_line_ahead(&road, 0), None);
+    }
+}
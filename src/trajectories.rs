@@ -0,0 +1,113 @@
+use std::{fmt::Write, path::Path};
+
+use serde_json::json;
+
+use crate::{arg_parameters::Parameters, road::Road};
+
+const CSV_HEADER: &str = "t,car,x,y,theta,vel,steer,policy_id,crashed\n";
+
+// Every car's state at every physics timestep, for analysis outside of the simulator,
+// in trajectories.format: csv, with CSV_HEADER, or jsonl, with an object per line.
+// x, y and theta are in the world, so they follow the road where it curves, and car is
+// the car's index at that timestep, with the ego as 0.
+pub struct TrajectoryRecorder {
+    jsonl: bool,
+    text: String,
+}
+
+impl TrajectoryRecorder {
+    pub fn new(params: &Parameters) -> Self {
+        let jsonl = match params.trajectories.format.as_str() {
+            "csv" => false,
+            "jsonl" => true,
+            _ => panic!("Unknown trajectories.format {}", params.trajectories.format),
+        };
+        let text = if jsonl {
+            String::new()
+        } else {
+            CSV_HEADER.to_owned()
+        };
+        Self { jsonl, text }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        if self.jsonl {
+            "jsonl"
+        } else {
+            "csv"
+        }
+    }
+
+    pub fn record_frame(&mut self, road: &Road) {
+        for (car_i, car) in road.cars.iter().enumerate() {
+            let world_car = road.world_car(car);
+            if self.jsonl {
+                let row = json!({
+                    "t": road.t,
+                    "car": car_i,
+                    "x": world_car.x(),
+                    "y": world_car.y(),
+                    "theta": world_car.theta(),
+                    "vel": car.vel,
+                    "steer": car.steer,
+                    "policy_id": car.operating_policy_id(),
+                    "crashed": car.crashed,
+                });
+                writeln!(self.text, "{}", row).unwrap();
+            } else {
+                writeln!(
+                    self.text,
+                    "{},{},{},{},{},{},{},{},{}",
+                    road.t,
+                    car_i,
+                    world_car.x(),
+                    world_car.y(),
+                    world_car.theta(),
+                    car.vel,
+                    car.steer,
+                    car.operating_policy_id(),
+                    car.crashed as u8
+                )
+                .unwrap();
+            }
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> std::io::Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, &self.text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[test]
+    fn test_trajectory_formats() {
+        let mut params = Parameters::new().unwrap();
+        let road = Road::new(Arc::new(params.clone()));
+        let ego = &road.cars[0];
+
+        let mut csv = TrajectoryRecorder::new(&params);
+        csv.record_frame(&road);
+        let lines = csv.text.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        let fields = lines[1].split(',').collect::<Vec<_>>();
+        assert_eq!(fields.len(), CSV_HEADER.split(',').count());
+        assert_eq!(fields[3].parse::<f64>().unwrap(), ego.y());
+
+        params.trajectories.format = "jsonl".to_owned();
+        let mut jsonl = TrajectoryRecorder::new(&params);
+        jsonl.record_frame(&road);
+        assert_eq!(jsonl.extension(), "jsonl");
+        let row: serde_json::Value = serde_json::from_str(jsonl.text.trim()).unwrap();
+        assert_eq!(row["car"], 0);
+        assert_eq!(row["policy_id"], ego.operating_policy_id());
+        assert_eq!(row["crashed"], false);
+    }
+}
@@ -0,0 +1,216 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+};
+
+use crate::{
+    arg_parameters::Parameters,
+    render::{self, Painter, Rgb, View, BACKGROUND, HEIGHT, WIDTH},
+    road::Road,
+};
+
+// frames per second of video, which plays back in real time
+const FPS: f64 = 25.0;
+
+// An RGB image of the road
+pub struct Frame {
+    pub pixels: Vec<u8>,
+    view: View,
+}
+
+impl Frame {
+    pub fn new(view: View) -> Self {
+        let mut pixels = Vec::with_capacity(WIDTH * HEIGHT * 3);
+        for _ in 0..WIDTH * HEIGHT {
+            pixels.extend_from_slice(&BACKGROUND);
+        }
+        Self { pixels, view }
+    }
+
+    fn blend(&mut self, col: usize, row: usize, color: Rgb, alpha: f64) {
+        let i = (row * WIDTH + col) * 3;
+        for (p, &c) in self.pixels[i..i + 3].iter_mut().zip(color.iter()) {
+            *p = (*p as f64 * (1.0 - alpha) + c as f64 * alpha).round() as u8;
+        }
+    }
+}
+
+impl Painter for Frame {
+    fn fill_polygon(&mut self, corners: &[(f64, f64)], color: Rgb, alpha: f64) {
+        let points = corners
+            .iter()
+            .map(|&(x, y)| self.view.to_pixel(x, y))
+            .collect::<Vec<_>>();
+        if !View::shows(&points) {
+            return;
+        }
+        let low_u = points.iter().map(|p| p.0).fold(f64::MAX, f64::min);
+        let high_u = points.iter().map(|p| p.0).fold(f64::MIN, f64::max);
+        let low_v = points.iter().map(|p| p.1).fold(f64::MAX, f64::min);
+        let high_v = points.iter().map(|p| p.1).fold(f64::MIN, f64::max);
+
+        let low_col = low_u.max(0.0) as usize;
+        let high_col = (high_u.ceil() as usize).min(WIDTH - 1);
+        let low_row = low_v.max(0.0) as usize;
+        let high_row = (high_v.ceil() as usize).min(HEIGHT - 1);
+        for row in low_row..=high_row {
+            for col in low_col..=high_col {
+                let (u, v) = (col as f64 + 0.5, row as f64 + 0.5);
+                let mut has_pos = false;
+                let mut has_neg = false;
+                for (a, b) in points.iter().zip(points.iter().cycle().skip(1)) {
+                    let cross = (b.0 - a.0) * (v - a.1) - (b.1 - a.1) * (u - a.0);
+                    has_pos |= cross > 0.0;
+                    has_neg |= cross < 0.0;
+                }
+                if !(has_pos && has_neg) {
+                    self.blend(col, row, color, alpha);
+                }
+            }
+        }
+    }
+
+    // as a rectangle for each segment
+    fn polyline(&mut self, points: &[(f64, f64)], width: f64, color: Rgb, alpha: f64) {
+        for (a, b) in points.iter().zip(points.iter().skip(1)) {
+            let center = ((a.0 + b.0) / 2.0, (a.1 + b.1) / 2.0);
+            let length = ((b.0 - a.0).powi(2) + (b.1 - a.1).powi(2)).sqrt();
+            let theta = (b.1 - a.1).atan2(b.0 - a.0);
+            self.fill_rect(center, length, width, theta, color, alpha);
+        }
+    }
+
+    // there's no font to draw with here, and the video has the time anyway
+    fn text(&mut self, _at: (f64, f64), _text: &str, _size: f64, _color: Rgb) {}
+}
+
+pub fn render_frame(road: &Road) -> Frame {
+    let mut frame = Frame::new(View::around_ego(road));
+    render::draw_scene(road, &mut frame, &[]);
+    frame
+}
+
+// where a run's video goes: the record path itself for a single run,
+// and otherwise that with the method, seed and a hash of the scenario name in it, like replays
+pub fn video_path(params: &Parameters) -> PathBuf {
+    let path = Path::new(&params.record);
+    if params.is_single_run {
+        return path.to_owned();
+    }
+    let mut hasher = DefaultHasher::new();
+    params.scenario_name.hash(&mut hasher);
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("video");
+    let extension = path.extension().and_then(|s| s.to_str()).unwrap_or("mp4");
+    path.with_file_name(format!(
+        "{}_{}_seed{}_{:016x}.{}",
+        stem,
+        params.method,
+        params.rng_seed,
+        hasher.finish(),
+        extension
+    ))
+}
+
+// Renders a run offscreen and pipes the frames to ffmpeg, which encodes them
+// by the extension of the record path (.mp4, .webm, ...)
+pub struct VideoRecorder {
+    ffmpeg: Child,
+    path: PathBuf,
+    frame_interval: usize,
+}
+
+impl VideoRecorder {
+    pub fn new(params: &Parameters) -> Self {
+        let path = video_path(params);
+        let ffmpeg = Command::new("ffmpeg")
+            .args(&[
+                "-y",
+                "-loglevel",
+                "error",
+                "-f",
+                "rawvideo",
+                "-pix_fmt",
+                "rgb24",
+            ])
+            .args(&[
+                "-s",
+                &format!("{}x{}", WIDTH, HEIGHT),
+                "-r",
+                &FPS.to_string(),
+            ])
+            .args(&["-i", "-", "-pix_fmt", "yuv420p"])
+            .arg(&path)
+            .stdin(Stdio::piped())
+            .spawn()
+            .unwrap_or_else(|e| {
+                panic!("Could not start ffmpeg to record {}: {}", path.display(), e)
+            });
+        Self {
+            ffmpeg,
+            path,
+            frame_interval: ((1.0 / (FPS * params.physics_dt)).round() as usize).max(1),
+        }
+    }
+
+    pub fn record_frame(&mut self, road: &Road) {
+        if road.timesteps % self.frame_interval != 0 {
+            return;
+        }
+        let frame = render_frame(road);
+        let stdin = self.ffmpeg.stdin.as_mut().unwrap();
+        stdin
+            .write_all(&frame.pixels)
+            .unwrap_or_else(|e| panic!("Could not write to ffmpeg: {}", e));
+    }
+
+    // closes the stream so ffmpeg finishes the file
+    pub fn finish(mut self) -> io::Result<PathBuf> {
+        drop(self.ffmpeg.stdin.take());
+        let status = self.ffmpeg.wait()?;
+        if !status.success() {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("ffmpeg exited with {}", status),
+            ));
+        }
+        Ok(self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::car::Car;
+
+    #[test]
+    fn test_render_frame() {
+        let params = Parameters::new().unwrap();
+        let mut road = Road::new(Arc::new(params.clone()));
+        let mut ahead = Car::new(&params, 1, 0);
+        ahead.set_x(road.cars[0].x() + 20.0);
+        ahead.vel = 10.0;
+        road.cars.push(ahead);
+
+        let frame = render_frame(&road);
+        assert_eq!(frame.pixels.len(), WIDTH * HEIGHT * 3);
+        let view = View::around_ego(&road);
+        let pixel = |(u, v): (f64, f64)| {
+            let i = (v as usize * WIDTH + u as usize) * 3;
+            [frame.pixels[i], frame.pixels[i + 1], frame.pixels[i + 2]]
+        };
+        let at = |car: &Car| pixel(view.to_pixel(car.x() - car.length / 2.0, car.y()));
+        // the ego is green and the car ahead blue, to the right of it
+        let ego_color = at(&road.cars[0]);
+        assert!(ego_color[1] > ego_color[0] && ego_color[1] > ego_color[2]);
+        let ahead_color = at(&road.cars[1]);
+        assert!(ahead_color[2] > ahead_color[0] && ahead_color[2] > ahead_color[1]);
+        assert!(view.to_pixel(road.cars[1].x(), 0.0).0 > view.to_pixel(road.cars[0].x(), 0.0).0);
+        // and off the road is background
+        assert_eq!(pixel((0.0, 0.0)), BACKGROUND);
+    }
+}
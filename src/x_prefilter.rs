@@ -0,0 +1,83 @@
+use std::convert::TryFrom;
+
+// The filter compares this many x at a time, in fixed-size chunks that the compiler vectorizes
+const LANES: usize = 4;
+
+// The indices of xs within reach of x, |xs[i] - x| <= reach, in order. A cheap first pass for
+// the proximity checks, over x stored side by side instead of spread through the cars.
+pub fn within_reach(xs: &[f64], x: f64, reach: f64) -> WithinReach<'_> {
+    WithinReach {
+        xs,
+        x,
+        reach,
+        next_chunk: 0,
+        base: 0,
+        mask: 0,
+    }
+}
+
+pub struct WithinReach<'a> {
+    xs: &'a [f64],
+    x: f64,
+    reach: f64,
+    next_chunk: usize,
+    // where the chunk of mask starts, with a bit set for each of its x within reach
+    base: usize,
+    mask: u32,
+}
+
+impl Iterator for WithinReach<'_> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        while self.mask == 0 {
+            if self.next_chunk >= self.xs.len() {
+                return None;
+            }
+            self.base = self.next_chunk;
+            self.next_chunk = (self.base + LANES).min(self.xs.len());
+            self.mask = chunk_mask(&self.xs[self.base..self.next_chunk], self.x, self.reach);
+        }
+        let lane = self.mask.trailing_zeros() as usize;
+        self.mask &= self.mask - 1;
+        Some(self.base + lane)
+    }
+}
+
+fn chunk_mask(xs: &[f64], x: f64, reach: f64) -> u32 {
+    match <&[f64; LANES]>::try_from(xs) {
+        Ok(chunk) => {
+            let mut within = [false; LANES];
+            for (w, xi) in within.iter_mut().zip(chunk.iter()) {
+                *w = (xi - x).abs() <= reach;
+            }
+            within
+                .iter()
+                .enumerate()
+                .fold(0, |mask, (i, &w)| mask | (w as u32) << i)
+        }
+        // the last few
+        Err(_) => xs
+            .iter()
+            .enumerate()
+            .filter(|(_, xi)| (*xi - x).abs() <= reach)
+            .fold(0, |mask, (i, _)| mask | 1 << i),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_within_reach() {
+        let xs = (0..11).map(|i| i as f64 * 10.0).collect::<Vec<_>>();
+        for &(x, reach) in [(50.0, 15.0), (0.0, 0.0), (-50.0, 5.0), (95.0, 1000.0)].iter() {
+            let expected = (0..xs.len())
+                .filter(|&i| (xs[i] - x).abs() <= reach)
+                .collect::<Vec<_>>();
+            assert_eq!(within_reach(&xs, x, reach).collect::<Vec<_>>(), expected);
+        }
+        assert_eq!(within_reach(&[], 0.0, 1.0).next(), None);
+    }
+}